@@ -0,0 +1,378 @@
+//! Annotation import: custom names, tags, and notes per title, as exported by external
+//! library managers.
+//!
+//! CSV needs a header row naming its columns: `title_id` (or `titleid`, `id`) and any of
+//! `name`, `tags`, `notes`; other columns are ignored. Commas, semicolons, and tabs are
+//! recognized as the delimiter. JSON is either an object keyed by title ID or an array
+//! of objects with a `title_id` field. Tags may be a list or one string; in a string they
+//! are separated by `,`, `;`, or `|`. Rows that can't be used are reported by number and
+//! skipped; only a document that can't be read at all is an error.
+
+use std::collections::{BTreeSet, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::artwork::normalize_title_id;
+
+const TAG_SEPARATORS: [char; 3] = [',', ';', '|'];
+
+#[derive(Debug, Error)]
+pub enum AnnotationError {
+    #[error("the CSV has no header row")]
+    MissingHeader,
+    #[error("the CSV has no title_id column")]
+    MissingTitleIdColumn,
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationFormat {
+    Csv,
+    Json,
+}
+
+impl AnnotationFormat {
+    /// Format named by a `Content-Type`, or else guessed from the body.
+    pub fn detect(content_type: Option<&str>, body: &str) -> Self {
+        match content_type.map(str::to_ascii_lowercase) {
+            Some(mime) if mime.contains("csv") => Self::Csv,
+            Some(mime) if mime.contains("json") => Self::Json,
+            _ if body.trim_start().starts_with(['{', '[']) => Self::Json,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// What one row sets for a title. Unset fields leave the current value alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub title_id: String,
+    pub name: Option<String>,
+    pub tags: BTreeSet<String>,
+    pub notes: Option<String>,
+}
+
+/// A row that was skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowError {
+    /// 1-based; in CSV the header is row 1.
+    pub row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_id: Option<String>,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedAnnotations {
+    pub rows: usize,
+    pub annotations: Vec<Annotation>,
+    pub errors: Vec<RowError>,
+}
+
+impl ParsedAnnotations {
+    /// Validate one row and keep it, or record why not.
+    fn push(&mut self, row: usize, fields: RawAnnotation) {
+        self.rows += 1;
+        let raw_id = fields.title_id.unwrap_or_default();
+        let mut error = |error: &str| {
+            self.errors.push(RowError {
+                row,
+                title_id: Some(raw_id.trim().to_string()).filter(|id| !id.is_empty()),
+                error: error.to_string(),
+            });
+        };
+        let Some(title_id) = normalize_title_id(raw_id.trim()) else {
+            error("invalid title id");
+            return;
+        };
+        let annotation = Annotation {
+            title_id,
+            name: non_blank(fields.name),
+            tags: fields.tags,
+            notes: non_blank(fields.notes),
+        };
+        if annotation.name.is_none() && annotation.tags.is_empty() && annotation.notes.is_none() {
+            error("no name, tags, or notes");
+        } else if self
+            .annotations
+            .iter()
+            .any(|known| known.title_id == annotation.title_id)
+        {
+            error("duplicate title id; the first row was used");
+        } else {
+            self.annotations.push(annotation);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RawAnnotation {
+    title_id: Option<String>,
+    name: Option<String>,
+    tags: BTreeSet<String>,
+    notes: Option<String>,
+}
+
+/// Parse an annotation document.
+pub fn parse(body: &str, format: AnnotationFormat) -> Result<ParsedAnnotations, AnnotationError> {
+    let body = body.strip_prefix('\u{feff}').unwrap_or(body);
+    match format {
+        AnnotationFormat::Csv => parse_csv(body),
+        AnnotationFormat::Json => parse_json(body),
+    }
+}
+
+fn parse_csv(body: &str) -> Result<ParsedAnnotations, AnnotationError> {
+    let mut records = csv_records(body, detect_delimiter(body));
+    let (_, header) = records.next().ok_or(AnnotationError::MissingHeader)?;
+    let column = |names: &[&str]| {
+        header.iter().position(|field| {
+            let field = field.trim().to_ascii_lowercase().replace([' ', '-'], "_");
+            names.contains(&field.as_str())
+        })
+    };
+    let title_id =
+        column(&["title_id", "titleid", "id"]).ok_or(AnnotationError::MissingTitleIdColumn)?;
+    let name = column(&["name", "custom_name", "display_name"]);
+    let tags = column(&["tags", "tag"]);
+    let notes = column(&["notes", "note"]);
+
+    let mut parsed = ParsedAnnotations::default();
+    for (row, record) in records {
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let field = |index: Option<usize>| index.and_then(|index| record.get(index)).cloned();
+        parsed.push(
+            row,
+            RawAnnotation {
+                title_id: field(Some(title_id)),
+                name: field(name),
+                tags: field(tags)
+                    .map(|tags| split_tags(&tags))
+                    .unwrap_or_default(),
+                notes: field(notes),
+            },
+        );
+    }
+    Ok(parsed)
+}
+
+/// `,` unless the header line has more `;` or tabs.
+fn detect_delimiter(body: &str) -> char {
+    let header = body.lines().next().unwrap_or_default();
+    [',', ';', '\t']
+        .into_iter()
+        .max_by_key(|delimiter| (header.matches(*delimiter).count(), *delimiter == ','))
+        .unwrap_or(',')
+}
+
+/// RFC 4180 records with their 1-based row number: quoted fields may contain the
+/// delimiter, `""`, and line breaks.
+fn csv_records(body: &str, delimiter: char) -> impl Iterator<Item = (usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = body.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(ch),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ if ch == delimiter => record.push(std::mem::take(&mut field)),
+            _ => field.push(ch),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| (index + 1, record))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonTags {
+    List(Vec<String>),
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonAnnotation {
+    #[serde(default, alias = "titleId", alias = "id")]
+    title_id: Option<String>,
+    #[serde(default, alias = "custom_name", alias = "customName")]
+    name: Option<String>,
+    #[serde(default, alias = "tag")]
+    tags: Option<JsonTags>,
+    #[serde(default, alias = "note")]
+    notes: Option<String>,
+}
+
+fn parse_json(body: &str) -> Result<ParsedAnnotations, AnnotationError> {
+    let entries: Vec<(Option<String>, Value)> = match serde_json::from_str(body)? {
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(key, value)| (Some(key), value))
+            .collect(),
+        Value::Array(values) => values.into_iter().map(|value| (None, value)).collect(),
+        _ => {
+            return Err(AnnotationError::Json(serde::de::Error::custom(
+                "expected an object keyed by title id or an array",
+            )))
+        }
+    };
+
+    let mut parsed = ParsedAnnotations::default();
+    for (index, (key, value)) in entries.into_iter().enumerate() {
+        let row = index + 1;
+        let entry = match serde_json::from_value::<JsonAnnotation>(value) {
+            Ok(entry) => entry,
+            Err(err) => {
+                parsed.rows += 1;
+                parsed.errors.push(RowError {
+                    row,
+                    title_id: key,
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let tags = match entry.tags {
+            Some(JsonTags::List(tags)) => tags.iter().flat_map(|tag| split_tags(tag)).collect(),
+            Some(JsonTags::Text(tags)) => split_tags(&tags),
+            None => BTreeSet::new(),
+        };
+        parsed.push(
+            row,
+            RawAnnotation {
+                title_id: key.or(entry.title_id),
+                name: entry.name,
+                tags,
+                notes: entry.notes,
+            },
+        );
+    }
+    Ok(parsed)
+}
+
+fn split_tags(raw: &str) -> BTreeSet<String> {
+    raw.split(TAG_SEPARATORS)
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn non_blank(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Title IDs of `annotations` not in `known`, e.g. titles not in the library yet.
+pub fn unknown_titles(annotations: &[Annotation], known: &HashSet<&str>) -> Vec<String> {
+    annotations
+        .iter()
+        .filter(|annotation| !known.contains(annotation.title_id.as_str()))
+        .map(|annotation| annotation.title_id.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{parse, AnnotationFormat};
+
+    #[test]
+    fn csv_rows_are_validated_and_quoted_fields_kept() -> Result<()> {
+        let csv = "\u{feff}Title ID,Name,Tags,Notes,Rating\r\n\
+            0100abcd12340000,\"Zelda, BotW\",rpg; open world,\"line one\nline two\",5\r\n\
+            not-an-id,Broken,,,\r\n\
+            ,,,,\r\n\
+            0100FFFF00000000,,,,3\r\n\
+            0100ABCD12340000,Again,,,\r\n";
+        let parsed = parse(csv, AnnotationFormat::Csv)?;
+        assert_eq!(parsed.rows, 4);
+        assert_eq!(parsed.annotations.len(), 1);
+        let zelda = &parsed.annotations[0];
+        assert_eq!(zelda.title_id, "0100ABCD12340000");
+        assert_eq!(zelda.name.as_deref(), Some("Zelda, BotW"));
+        assert_eq!(
+            zelda.tags.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["open world", "rpg"]
+        );
+        assert_eq!(zelda.notes.as_deref(), Some("line one\nline two"));
+
+        let errors: Vec<_> = parsed
+            .errors
+            .iter()
+            .map(|error| (error.row, error.error.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (3, "invalid title id"),
+                (5, "no name, tags, or notes"),
+                (6, "duplicate title id; the first row was used"),
+            ]
+        );
+
+        let semicolons = parse("id;name\n0100ABCD12340000;Zelda\n", AnnotationFormat::Csv)?;
+        assert_eq!(semicolons.annotations[0].name.as_deref(), Some("Zelda"));
+        assert!(parse("name,tags\nZelda,rpg\n", AnnotationFormat::Csv).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn json_accepts_objects_keyed_by_title_id_and_arrays() -> Result<()> {
+        let keyed = parse(
+            r#"{"0100ABCD12340000": {"name": "Zelda", "tags": ["rpg", "co-op|local"]},
+                "0100FFFF00000000": {"tags": 5}}"#,
+            AnnotationFormat::Json,
+        )?;
+        assert_eq!(keyed.annotations.len(), 1);
+        assert_eq!(keyed.annotations[0].tags.len(), 3);
+        assert_eq!(keyed.errors.len(), 1);
+        assert_eq!(
+            keyed.errors[0].title_id.as_deref(),
+            Some("0100FFFF00000000")
+        );
+
+        let list = parse(
+            r#"[{"titleId": "0100abcd12340000", "notes": "Backup of cart", "tags": "rpg, jrpg"}]"#,
+            AnnotationFormat::Json,
+        )?;
+        assert_eq!(list.annotations[0].title_id, "0100ABCD12340000");
+        assert_eq!(list.annotations[0].tags.len(), 2);
+
+        assert!(parse("\"nope\"", AnnotationFormat::Json).is_err());
+        assert_eq!(
+            AnnotationFormat::detect(None, "  [{}]"),
+            AnnotationFormat::Json
+        );
+        assert_eq!(
+            AnnotationFormat::detect(Some("text/csv"), "[{}]"),
+            AnnotationFormat::Csv
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Announcements: short messages from the admin to shop users, such as a maintenance
+//! window or new arrivals.
+//!
+//! The newest active announcement is shown as the shop index's `success` message, which
+//! Tinfoil displays when the shop loads; every active one is listed at
+//! `/api/announcements`. An announcement may expire at a set time; expired ones are
+//! dropped on the next change. Persisted to `<data_dir>/announcements.json`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::warn;
+use utoipa::ToSchema;
+
+const ANNOUNCEMENTS_FILE: &str = "announcements.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Announcement {
+    pub id: u64,
+    pub message: String,
+    /// Unix seconds.
+    pub created_at: u64,
+    /// Unix seconds; shown indefinitely when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Announcement {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.expires_at.map_or(true, |at| at > now)
+    }
+}
+
+/// What the admin sends to create or edit an announcement.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnnouncementDraft {
+    pub message: String,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnouncementStore {
+    /// Oldest first.
+    inner: Arc<RwLock<Vec<Announcement>>>,
+    store_path: PathBuf,
+}
+
+impl AnnouncementStore {
+    /// Load announcements from `data_dir`, starting empty if the file is missing or
+    /// invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(ANNOUNCEMENTS_FILE);
+        let announcements = match std::fs::read_to_string(&store_path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+                warn!(path = %store_path.display(), error = %err, "ignoring invalid announcements file");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            inner: Arc::new(RwLock::new(announcements)),
+            store_path,
+        }
+    }
+
+    /// Announcements that haven't expired at `now`, newest first.
+    pub async fn active(&self, now: u64) -> Vec<Announcement> {
+        let announcements = self.inner.read().await;
+        announcements
+            .iter()
+            .rev()
+            .filter(|announcement| announcement.is_active(now))
+            .cloned()
+            .collect()
+    }
+
+    /// The newest announcement that hasn't expired at `now`.
+    pub async fn latest(&self, now: u64) -> Option<Announcement> {
+        let announcements = self.inner.read().await;
+        announcements
+            .iter()
+            .rev()
+            .find(|announcement| announcement.is_active(now))
+            .cloned()
+    }
+
+    pub async fn create(
+        &self,
+        draft: AnnouncementDraft,
+        now: u64,
+    ) -> std::io::Result<Announcement> {
+        let mut announcements = self.inner.write().await;
+        prune(&mut announcements, now);
+        let announcement = Announcement {
+            id: announcements
+                .iter()
+                .map(|known| known.id)
+                .max()
+                .unwrap_or(0)
+                + 1,
+            message: draft.message,
+            created_at: now,
+            expires_at: draft.expires_at,
+        };
+        announcements.push(announcement.clone());
+        self.save(&announcements).await?;
+        Ok(announcement)
+    }
+
+    /// Replace the message and expiry of announcement `id`. Returns `None` if there is
+    /// no such active announcement.
+    pub async fn update(
+        &self,
+        id: u64,
+        draft: AnnouncementDraft,
+        now: u64,
+    ) -> std::io::Result<Option<Announcement>> {
+        let mut announcements = self.inner.write().await;
+        let pruned = prune(&mut announcements, now);
+        let Some(announcement) = announcements.iter_mut().find(|known| known.id == id) else {
+            if pruned {
+                self.save(&announcements).await?;
+            }
+            return Ok(None);
+        };
+        announcement.message = draft.message;
+        announcement.expires_at = draft.expires_at;
+        let updated = announcement.clone();
+        self.save(&announcements).await?;
+        Ok(Some(updated))
+    }
+
+    /// Delete announcement `id`. Returns whether it existed.
+    pub async fn remove(&self, id: u64) -> std::io::Result<bool> {
+        let mut announcements = self.inner.write().await;
+        let before = announcements.len();
+        announcements.retain(|announcement| announcement.id != id);
+        if announcements.len() == before {
+            return Ok(false);
+        }
+        self.save(&announcements).await.map(|()| true)
+    }
+
+    async fn save(&self, announcements: &[Announcement]) -> std::io::Result<()> {
+        let raw = serde_json::to_string_pretty(announcements).map_err(std::io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp = self.store_path.with_extension("json.tmp");
+        tokio::fs::write(&temp, raw).await?;
+        tokio::fs::rename(&temp, &self.store_path).await
+    }
+}
+
+/// Drop announcements expired at `now`. Returns whether any were.
+fn prune(announcements: &mut Vec<Announcement>, now: u64) -> bool {
+    let before = announcements.len();
+    announcements.retain(|announcement| announcement.is_active(now));
+    announcements.len() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{AnnouncementDraft, AnnouncementStore};
+
+    fn draft(message: &str, expires_at: Option<u64>) -> AnnouncementDraft {
+        AnnouncementDraft {
+            message: String::from(message),
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn newest_active_announcement_wins_and_expired_ones_drop() -> Result<()> {
+        let dir = tempdir()?;
+        let store = AnnouncementStore::load(dir.path());
+        let first = store.create(draft("Welcome", None), 100).await?;
+        let maintenance = store
+            .create(draft("Down Sunday 2-4am", Some(500)), 200)
+            .await?;
+        assert_eq!(store.latest(300).await, Some(maintenance.clone()));
+        assert_eq!(store.latest(500).await, Some(first.clone()));
+        assert_eq!(store.active(300).await.len(), 2);
+
+        // Expired announcements are pruned on the next change and can't be edited.
+        assert!(store
+            .update(maintenance.id, draft("Extended", None), 600)
+            .await?
+            .is_none());
+        let reloaded = AnnouncementStore::load(dir.path());
+        assert_eq!(reloaded.active(0).await, vec![first.clone()]);
+
+        let third = reloaded.create(draft("New games!", None), 700).await?;
+        assert_eq!(third.id, 2);
+        assert!(reloaded.remove(third.id).await?);
+        assert!(!reloaded.remove(third.id).await?);
+        assert_eq!(reloaded.latest(800).await, Some(first));
+        Ok(())
+    }
+}
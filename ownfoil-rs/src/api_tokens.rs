@@ -0,0 +1,201 @@
+//! Long-lived API tokens for scripts and monitors, sent as `Authorization: Bearer <token>`.
+//!
+//! Admins issue tokens per user through `/api/users/{username}/tokens`. A request with a
+//! token acts as its user, with their role and content filters, so a script needs no
+//! password and each token can be revoked on its own. Only a SHA-256 digest of each token
+//! is persisted, to `<data_dir>/api_tokens.json`; the token itself is shown once, when
+//! issued. Tokens stop working while their user is disabled or missing from the auth file.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+const TOKENS_FILE: &str = "api_tokens.json";
+/// Starts every token, so they are easy to spot in scripts and secret scanners.
+const TOKEN_PREFIX: &str = "ofrs_";
+/// Random bytes in a token.
+const TOKEN_BYTES: usize = 32;
+
+/// A token as listed: everything but the token itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: u64,
+    pub username: String,
+    /// What the token is for, e.g. `backup script`.
+    pub name: String,
+    /// Unix seconds.
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// Hex SHA-256 of the token.
+    digest: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiTokenStore {
+    /// Read on every request with a token, so behind a lock that doesn't need `.await`.
+    inner: Arc<RwLock<Vec<StoredToken>>>,
+    /// Held from a change until it is saved, so saves land in order.
+    saving: Arc<Mutex<()>>,
+    store_path: PathBuf,
+}
+
+impl ApiTokenStore {
+    /// Load tokens from `data_dir`, starting empty if the file is missing or invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(TOKENS_FILE);
+        let tokens = match std::fs::read_to_string(&store_path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+                warn!(path = %store_path.display(), error = %err, "ignoring invalid API tokens file");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            inner: Arc::new(RwLock::new(tokens)),
+            saving: Arc::default(),
+            store_path,
+        }
+    }
+
+    /// The user `token` was issued to, if it hasn't been revoked.
+    pub fn user_for(&self, token: &str) -> Option<String> {
+        let digest = digest(token);
+        let tokens = self.inner.read().unwrap_or_else(|p| p.into_inner());
+        tokens
+            .iter()
+            .find(|known| bool::from(known.digest.as_bytes().ct_eq(digest.as_bytes())))
+            .map(|known| known.token.username.clone())
+    }
+
+    /// Tokens of `username`, oldest first.
+    pub fn list(&self, username: &str) -> Vec<ApiToken> {
+        let tokens = self.inner.read().unwrap_or_else(|p| p.into_inner());
+        tokens
+            .iter()
+            .filter(|known| known.token.username == username)
+            .map(|known| known.token.clone())
+            .collect()
+    }
+
+    /// Issue a new token for `username`. Returns it with the token itself, which isn't
+    /// kept.
+    pub async fn issue(
+        &self,
+        username: &str,
+        name: &str,
+        now: u64,
+    ) -> std::io::Result<(ApiToken, String)> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| std::io::Error::other("no random source"))?;
+        let secret = format!("{TOKEN_PREFIX}{}", hex(&bytes));
+
+        let _saving = self.saving.lock().await;
+        let (token, snapshot) = {
+            let mut tokens = self.inner.write().unwrap_or_else(|p| p.into_inner());
+            let token = ApiToken {
+                id: tokens.iter().map(|known| known.token.id).max().unwrap_or(0) + 1,
+                username: username.to_string(),
+                name: name.to_string(),
+                created_at: now,
+            };
+            tokens.push(StoredToken {
+                token: token.clone(),
+                digest: digest(&secret),
+            });
+            (token, tokens.clone())
+        };
+        self.save(&snapshot).await?;
+        Ok((token, secret))
+    }
+
+    /// Revoke token `id` of `username`. Returns whether it existed.
+    pub async fn revoke(&self, username: &str, id: u64) -> std::io::Result<bool> {
+        self.remove(|known| known.username == username && known.id == id)
+            .await
+            .map(|removed| removed > 0)
+    }
+
+    /// Revoke every token of `username`. Returns how many there were.
+    pub async fn revoke_user(&self, username: &str) -> std::io::Result<usize> {
+        self.remove(|known| known.username == username).await
+    }
+
+    async fn remove(&self, revoked: impl Fn(&ApiToken) -> bool) -> std::io::Result<usize> {
+        let _saving = self.saving.lock().await;
+        let (removed, snapshot) = {
+            let mut tokens = self.inner.write().unwrap_or_else(|p| p.into_inner());
+            let before = tokens.len();
+            tokens.retain(|known| !revoked(&known.token));
+            (before - tokens.len(), tokens.clone())
+        };
+        if removed > 0 {
+            self.save(&snapshot).await?;
+        }
+        Ok(removed)
+    }
+
+    async fn save(&self, tokens: &[StoredToken]) -> std::io::Result<()> {
+        let raw = serde_json::to_string_pretty(tokens).map_err(std::io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp = self.store_path.with_extension("json.tmp");
+        tokio::fs::write(&temp, raw).await?;
+        tokio::fs::rename(&temp, &self.store_path).await
+    }
+}
+
+fn digest(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::ApiTokenStore;
+
+    #[tokio::test]
+    async fn tokens_are_kept_as_digests_and_revoked_one_at_a_time() -> Result<()> {
+        let dir = tempdir()?;
+        let store = ApiTokenStore::load(dir.path());
+        let (backup, backup_secret) = store.issue("alice", "backup", 10).await?;
+        let (monitor, monitor_secret) = store.issue("alice", "monitor", 20).await?;
+        assert!(backup_secret.starts_with("ofrs_"));
+        assert_ne!(backup.id, monitor.id);
+
+        let raw = std::fs::read_to_string(dir.path().join("api_tokens.json"))?;
+        assert!(!raw.contains(&backup_secret[5..]));
+
+        let reloaded = ApiTokenStore::load(dir.path());
+        assert_eq!(reloaded.list("alice"), vec![backup.clone(), monitor]);
+        assert_eq!(reloaded.user_for(&backup_secret).as_deref(), Some("alice"));
+        assert!(reloaded.revoke("alice", backup.id).await?);
+        assert!(!reloaded.revoke("alice", backup.id).await?);
+        assert_eq!(reloaded.user_for(&backup_secret), None);
+        assert_eq!(reloaded.user_for(&monitor_secret).as_deref(), Some("alice"));
+
+        assert_eq!(reloaded.revoke_user("alice").await?, 1);
+        assert_eq!(reloaded.user_for(&monitor_secret), None);
+        assert!(reloaded.list("alice").is_empty());
+        Ok(())
+    }
+}
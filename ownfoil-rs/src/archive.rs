@@ -0,0 +1,236 @@
+//! Zip archives holding a single title (`Game.zip` containing `Game.nsp`).
+//!
+//! With `[scan] archives = true` the scanner indexes such archives under the inner file's
+//! name and size, and downloads stream the inner file. Entries stored without compression
+//! are served straight from the archive, so range requests work as for plain files;
+//! compressed entries are decompressed on the fly and always sent whole.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use bytes::Bytes;
+use futures_util::stream::{self, Stream};
+use tokio::sync::mpsc;
+use zip::CompressionMethod;
+
+use crate::scanner::is_supported_content;
+
+const STREAM_CHUNK: usize = 256 * 1024;
+
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// The single supported content file inside an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// File name of the entry, without its directory inside the archive.
+    pub name: String,
+    /// Uncompressed size.
+    pub size: u64,
+    /// Offset of the entry's bytes in the archive when stored uncompressed.
+    pub stored_at: Option<u64>,
+    index: usize,
+}
+
+impl ArchiveEntry {
+    /// Read the archive at `path` and return its content entry, if it holds exactly one
+    /// unencrypted `.nsp`/`.xci`/`.nsz`/`.xcz` file.
+    pub fn find(path: &Path) -> io::Result<Option<Self>> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+        let mut found = None;
+        for index in 0..archive.len() {
+            let entry = archive.by_index_raw(index).map_err(io::Error::other)?;
+            let Some(name) = entry
+                .enclosed_name()
+                .and_then(|inner| inner.file_name()?.to_str().map(String::from))
+            else {
+                continue;
+            };
+            if entry.is_dir() || !is_supported_content(Path::new(&name)) {
+                continue;
+            }
+            if found.is_some() || entry.encrypted() {
+                return Ok(None);
+            }
+            found = Some(Self {
+                name,
+                size: entry.size(),
+                stored_at: (entry.compression() == CompressionMethod::Stored)
+                    .then(|| entry.data_start()),
+                index,
+            });
+        }
+        Ok(found)
+    }
+
+    /// Blocking reader over a stored entry's bytes, or `None` for compressed entries.
+    pub fn stored_reader(&self, path: &Path) -> io::Result<Option<EntryReader>> {
+        let Some(start) = self.stored_at else {
+            return Ok(None);
+        };
+        Ok(Some(EntryReader {
+            file: File::open(path)?,
+            start,
+            len: self.size,
+            position: 0,
+        }))
+    }
+
+    /// Stream the decompressed entry from a blocking task.
+    pub fn stream_decompressed(
+        &self,
+        path: &Path,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+        let path = path.to_path_buf();
+        let index = self.index;
+        tokio::task::spawn_blocking(move || {
+            let result = (|| {
+                let mut archive =
+                    zip::ZipArchive::new(File::open(&path)?).map_err(io::Error::other)?;
+                let mut entry = archive.by_index(index).map_err(io::Error::other)?;
+                let mut buf = vec![0u8; STREAM_CHUNK];
+                loop {
+                    let read = entry.read(&mut buf)?;
+                    if read == 0 {
+                        return Ok(());
+                    }
+                    if tx
+                        .blocking_send(Ok(Bytes::copy_from_slice(&buf[..read])))
+                        .is_err()
+                    {
+                        // Client went away.
+                        return Ok(());
+                    }
+                }
+            })();
+            if let Err(err) = result {
+                let _ = tx.blocking_send(Err(err));
+            }
+        });
+        stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        })
+    }
+}
+
+/// Reads a stored entry's bytes in place inside the archive.
+#[derive(Debug)]
+pub struct EntryReader {
+    file: File,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        if remaining == 0 {
+            return Ok(0);
+        }
+        let want = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        self.file
+            .seek(SeekFrom::Start(self.start + self.position))?;
+        let read = self.file.read(&mut buf[..want])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for EntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of entry")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    use anyhow::Result;
+    use futures_util::TryStreamExt;
+    use tempfile::tempdir;
+    use zip::write::SimpleFileOptions;
+    use zip::CompressionMethod;
+
+    use super::ArchiveEntry;
+
+    /// Write a zip at `path` with `files`, each stored or deflated.
+    pub(crate) fn write_zip(
+        path: &Path,
+        files: &[(&str, &[u8])],
+        method: CompressionMethod,
+    ) -> Result<()> {
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(path)?);
+        for (name, data) in files {
+            writer.start_file(
+                *name,
+                SimpleFileOptions::default().compression_method(method),
+            )?;
+            writer.write_all(data)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stored_and_deflated_entries_are_readable() -> Result<()> {
+        let dir = tempdir()?;
+        let stored = dir.path().join("stored.zip");
+        write_zip(
+            &stored,
+            &[("readme.txt", b"hi"), ("Games/Game.nsp", b"0123456789")],
+            CompressionMethod::Stored,
+        )?;
+        let entry = ArchiveEntry::find(&stored)?.ok_or_else(|| anyhow::anyhow!("no entry"))?;
+        assert_eq!(entry.name, "Game.nsp");
+        assert_eq!(entry.size, 10);
+        let mut content = String::new();
+        entry
+            .stored_reader(&stored)?
+            .ok_or_else(|| anyhow::anyhow!("not stored"))?
+            .read_to_string(&mut content)?;
+        assert_eq!(content, "0123456789");
+
+        let deflated = dir.path().join("deflated.zip");
+        write_zip(
+            &deflated,
+            &[("Game.xci", b"abcabcabcabc")],
+            CompressionMethod::Deflated,
+        )?;
+        let entry = ArchiveEntry::find(&deflated)?.ok_or_else(|| anyhow::anyhow!("no entry"))?;
+        assert_eq!(entry.stored_at, None);
+        let chunks: Vec<_> = entry.stream_decompressed(&deflated).try_collect().await?;
+        assert_eq!(chunks.concat(), b"abcabcabcabc");
+        Ok(())
+    }
+
+    #[test]
+    fn archives_with_several_titles_are_skipped() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("bundle.zip");
+        write_zip(
+            &path,
+            &[("a.nsp", b"a"), ("b.nsp", b"b")],
+            CompressionMethod::Stored,
+        )?;
+        assert_eq!(ArchiveEntry::find(&path)?, None);
+        Ok(())
+    }
+}
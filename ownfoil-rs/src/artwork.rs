@@ -0,0 +1,471 @@
+//! Artwork overrides and fallbacks for shop icons and banners.
+//!
+//! Images in `<data_dir>/artwork` (`<TITLE_ID>.png`, `<TITLE_ID>.banner.png`) override
+//! TitleDB, so wrong or missing art can be fixed by hand. For titles TitleDB doesn't know
+//! (homebrew, obscure releases), icons fall back to a configured local folder, then a
+//! URL template. Remote images are cached under `<data_dir>/artwork_cache` (see
+//! [`IconCache`]) and refetched once they are a day old; misses are remembered for a while
+//! so the provider isn't queried on every shop refresh.
+//!
+//! SVG art (and the built-in placeholder) can be rendered to PNG for clients that can't
+//! display SVG; renders are kept in memory per image and size.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use tracing::{debug, warn};
+
+use crate::config::ArtworkConfig;
+use crate::icon_cache::{IconCache, DEFAULT_MAX_BYTES};
+
+const OVERRIDE_DIR: &str = "artwork";
+const CACHE_DIR: &str = "artwork_cache";
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "svg"];
+/// How long a cached remote image is used before it is fetched again.
+pub const CACHE_TTL: Duration = Duration::from_secs(86400);
+/// How long a failed lookup is remembered before the provider is asked again.
+const MISS_TTL: Duration = Duration::from_secs(3600);
+/// Rendered PNGs kept before the cache is cleared; bounds memory for odd `size` values.
+const MAX_RASTERIZED: usize = 512;
+
+/// Image bytes with their MIME type.
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub bytes: Bytes,
+    pub content_type: String,
+    /// When the image last changed, if known.
+    pub modified: Option<SystemTime>,
+    /// When a remote image was copied into the artwork cache; `None` for local images.
+    pub fetched: Option<SystemTime>,
+}
+
+impl Artwork {
+    /// How long ago a remote image was fetched.
+    pub fn age(&self) -> Option<Duration> {
+        self.fetched
+            .map(|fetched| fetched.elapsed().unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArtworkProvider {
+    inner: Arc<ArtworkProviderInner>,
+}
+
+#[derive(Debug)]
+struct ArtworkProviderInner {
+    url_template: Option<String>,
+    local_dir: Option<PathBuf>,
+    override_dir: PathBuf,
+    cache: IconCache,
+    client: reqwest::Client,
+    misses: DashMap<String, Instant>,
+    rasterized: DashMap<(blake3::Hash, u32), Bytes>,
+}
+
+impl ArtworkProvider {
+    pub fn new(config: ArtworkConfig, data_dir: &Path) -> Self {
+        Self {
+            inner: Arc::new(ArtworkProviderInner {
+                url_template: config.fallback_url,
+                local_dir: config.fallback_dir,
+                override_dir: data_dir.join(OVERRIDE_DIR),
+                cache: IconCache::open(
+                    data_dir.join(CACHE_DIR),
+                    config
+                        .cache_max_mb
+                        .map_or(DEFAULT_MAX_BYTES, |mb| mb.saturating_mul(1 << 20)),
+                ),
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .user_agent("ownfoil-rs/1.0 (artwork fetcher)")
+                    .build()
+                    .unwrap_or_default(),
+                misses: DashMap::new(),
+                rasterized: DashMap::new(),
+            }),
+        }
+    }
+
+    /// User-provided icon that takes precedence over TitleDB.
+    pub async fn icon_override(&self, title_id: &str) -> Option<Artwork> {
+        read_image(&self.inner.override_dir, &normalize_title_id(title_id)?).await
+    }
+
+    /// User-provided banner that takes precedence over TitleDB.
+    pub async fn banner_override(&self, title_id: &str) -> Option<Artwork> {
+        let title_id = normalize_title_id(title_id)?;
+        read_image(&self.inner.override_dir, &format!("{title_id}.banner")).await
+    }
+
+    /// Whether an icon override exists, so shop payloads link to the local icon endpoint.
+    pub async fn has_icon_override(&self, title_id: &str) -> bool {
+        match normalize_title_id(title_id) {
+            Some(title_id) => find_image(&self.inner.override_dir, &title_id)
+                .await
+                .is_some(),
+            None => false,
+        }
+    }
+
+    /// Store `artwork` as the icon override for `title_id`, replacing any existing one.
+    /// Fails with `InvalidInput` for an invalid title ID or an unsupported image type.
+    pub async fn set_icon_override(
+        &self,
+        title_id: &str,
+        artwork: &Artwork,
+    ) -> std::io::Result<()> {
+        let (Some(title_id), Some(extension)) = (
+            normalize_title_id(title_id),
+            extension_for(&artwork.content_type),
+        ) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "expected a title ID and a PNG, JPEG, WebP, or SVG image",
+            ));
+        };
+        self.remove_icon_override(&title_id).await?;
+        tokio::fs::create_dir_all(&self.inner.override_dir).await?;
+        let path = self
+            .inner
+            .override_dir
+            .join(format!("{title_id}.{extension}"));
+        tokio::fs::write(path, &artwork.bytes).await
+    }
+
+    /// Delete the icon override for `title_id`. Returns whether one existed.
+    pub async fn remove_icon_override(&self, title_id: &str) -> std::io::Result<bool> {
+        let Some(title_id) = normalize_title_id(title_id) else {
+            return Ok(false);
+        };
+        let mut removed = false;
+        while let Some(path) = find_image(&self.inner.override_dir, &title_id).await {
+            tokio::fs::remove_file(path).await?;
+            removed = true;
+        }
+        Ok(removed)
+    }
+
+    /// Drop the cached fallback icon and remembered miss for `title_id`, then look it up
+    /// again. Returns whether an icon is available now.
+    pub async fn refresh_icon(&self, title_id: &str) -> bool {
+        let Some(title_id) = normalize_title_id(title_id) else {
+            return false;
+        };
+        self.inner.misses.remove(&title_id);
+        if let Some(template) = &self.inner.url_template {
+            let url = template.replace("{title_id}", &title_id);
+            self.inner.cache.remove(&url).await;
+        }
+        self.has_icon_override(&title_id).await || self.icon(&title_id).await.is_some()
+    }
+
+    /// Fallback icon for `title_id`, or `None` if no provider has one.
+    pub async fn icon(&self, title_id: &str) -> Option<Artwork> {
+        let title_id = normalize_title_id(title_id)?;
+        if let Some(dir) = &self.inner.local_dir {
+            if let Some(artwork) = read_image(dir, &title_id).await {
+                return Some(artwork);
+            }
+        }
+        let template = self.inner.url_template.as_deref()?;
+        let url = template.replace("{title_id}", &title_id);
+        let cached = self.inner.cache.get(&url).await;
+        if let Some(artwork) = cached
+            .as_ref()
+            .filter(|artwork| artwork.age().is_some_and(|age| age < CACHE_TTL))
+        {
+            return Some(artwork.clone());
+        }
+        if self
+            .inner
+            .misses
+            .get(&title_id)
+            .is_some_and(|at| at.elapsed() < MISS_TTL)
+        {
+            return cached;
+        }
+
+        match self.fetch(&url).await {
+            Some(artwork) => {
+                self.inner.cache.insert(&url, &artwork).await;
+                Some(artwork)
+            }
+            None => {
+                // A stale copy beats no icon while the provider is unreachable.
+                self.inner.misses.insert(title_id, Instant::now());
+                cached
+            }
+        }
+    }
+
+    /// Copy of the image at `url` fetched less than [`CACHE_TTL`] ago, such as TitleDB art
+    /// stored by [`prefetch`](Self::prefetch).
+    pub async fn cached(&self, url: &str) -> Option<Artwork> {
+        self.inner
+            .cache
+            .get(url)
+            .await
+            .filter(|artwork| artwork.age().is_some_and(|age| age < CACHE_TTL))
+    }
+
+    /// Fetch the image at `url` into the artwork cache unless a fresh copy is there
+    /// already. Returns whether one is cached now.
+    pub async fn prefetch(&self, url: &str) -> bool {
+        if self.cached(url).await.is_some() {
+            return true;
+        }
+        match self.fetch(url).await {
+            Some(artwork) => {
+                self.inner.cache.insert(url, &artwork).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Disk cache of fetched fallback icons.
+    pub fn icon_cache(&self) -> &IconCache {
+        &self.inner.cache
+    }
+
+    /// PNG rendering of `svg` fitted into a `size`x`size` square, or `None` if it doesn't
+    /// parse as SVG.
+    pub async fn svg_to_png(&self, svg: &Bytes, size: u32) -> Option<Bytes> {
+        let key = (blake3::hash(svg), size);
+        if let Some(png) = self.inner.rasterized.get(&key) {
+            return Some(png.clone());
+        }
+        let source = svg.clone();
+        let png = tokio::task::spawn_blocking(move || render_png(&source, size))
+            .await
+            .ok()
+            .flatten()
+            .map(Bytes::from)?;
+        if self.inner.rasterized.len() >= MAX_RASTERIZED {
+            self.inner.rasterized.clear();
+        }
+        self.inner.rasterized.insert(key, png.clone());
+        Some(png)
+    }
+
+    async fn fetch(&self, url: &str) -> Option<Artwork> {
+        let response = match self.inner.client.get(url).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(url = %url, error = %err, "artwork fallback request failed");
+                return None;
+            }
+        };
+        if !response.status().is_success() {
+            debug!(url = %url, status = %response.status(), "artwork fallback miss");
+            return None;
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("image/"))
+            .map(str::to_string)
+            .or_else(|| {
+                mime_guess::from_path(url)
+                    .first()
+                    .filter(|mime| mime.type_() == "image")
+                    .map(|mime| mime.to_string())
+            })?;
+        let bytes = response.bytes().await.ok()?;
+        let now = SystemTime::now();
+        Some(Artwork {
+            bytes,
+            content_type,
+            modified: Some(now),
+            fetched: Some(now),
+        })
+    }
+}
+
+/// Read `<dir>/<stem>.<ext>` for the first supported image extension present.
+async fn read_image(dir: &Path, stem: &str) -> Option<Artwork> {
+    let path = find_image(dir, stem).await?;
+    let bytes = tokio::fs::read(&path).await.ok()?;
+    let modified = tokio::fs::metadata(&path)
+        .await
+        .and_then(|meta| meta.modified())
+        .ok();
+    Some(Artwork {
+        bytes: Bytes::from(bytes),
+        content_type: mime_guess::from_path(&path)
+            .first_or_octet_stream()
+            .to_string(),
+        modified,
+        fetched: None,
+    })
+}
+
+async fn find_image(dir: &Path, stem: &str) -> Option<PathBuf> {
+    for extension in IMAGE_EXTENSIONS {
+        let path = dir.join(format!("{stem}.{extension}"));
+        if tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|meta| meta.is_file())
+        {
+            return Some(path);
+        }
+    }
+    None
+}
+
+pub fn is_svg(content_type: &str) -> bool {
+    content_type.split(';').next().map(str::trim) == Some("image/svg+xml")
+}
+
+fn render_png(svg: &[u8], size: u32) -> Option<Vec<u8>> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree = usvg::Tree::from_data(svg, &usvg::Options::default()).ok()?;
+    let mut pixmap = tiny_skia::Pixmap::new(size, size)?;
+    let (width, height) = (tree.size().width(), tree.size().height());
+    let scale = (size as f32 / width).min(size as f32 / height);
+    let transform = tiny_skia::Transform::from_translate(
+        (size as f32 - width * scale) / 2.0,
+        (size as f32 - height * scale) / 2.0,
+    )
+    .pre_scale(scale, scale);
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    pixmap.encode_png().ok()
+}
+
+/// Uppercase a 16-char hex title ID; rejects anything else so it is safe in paths and URLs.
+pub fn normalize_title_id(raw: &str) -> Option<String> {
+    (raw.len() == 16 && raw.chars().all(|ch| ch.is_ascii_hexdigit()))
+        .then(|| raw.to_ascii_uppercase())
+}
+
+pub(crate) fn extension_for(content_type: &str) -> Option<&'static str> {
+    match content_type.split(';').next().map(str::trim) {
+        Some("image/png") => Some("png"),
+        Some("image/jpeg") => Some("jpg"),
+        Some("image/webp") => Some("webp"),
+        Some("image/svg+xml") => Some("svg"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use axum::http::header::CONTENT_TYPE;
+    use axum::routing::get;
+    use axum::Router;
+    use tempfile::tempdir;
+
+    use super::{normalize_title_id, ArtworkProvider, CACHE_TTL};
+    use crate::config::ArtworkConfig;
+
+    #[tokio::test]
+    async fn local_folder_is_keyed_by_title_id() -> Result<()> {
+        let art = tempdir()?;
+        let data = tempdir()?;
+        std::fs::write(art.path().join("0100ABCD12340000.jpg"), b"jpeg")?;
+        let provider = ArtworkProvider::new(
+            ArtworkConfig {
+                fallback_url: None,
+                fallback_dir: Some(art.path().to_path_buf()),
+                ..ArtworkConfig::default()
+            },
+            data.path(),
+        );
+
+        let icon = provider.icon("0100abcd12340000").await;
+        assert_eq!(
+            icon.as_ref().map(|a| a.content_type.as_str()),
+            Some("image/jpeg")
+        );
+        assert!(provider.icon("0100ABCD12341000").await.is_none());
+        assert!(provider.icon("../etc/passwd").await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remote_artwork_is_cached_on_disk() -> Result<()> {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        let app = Router::new().route(
+            "/icons/{id}",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { ([(CONTENT_TYPE, "image/png")], "png-bytes") }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let data = tempdir()?;
+        let provider = ArtworkProvider::new(
+            ArtworkConfig {
+                fallback_url: Some(format!("http://{addr}/icons/{{title_id}}.png")),
+                fallback_dir: None,
+                ..ArtworkConfig::default()
+            },
+            data.path(),
+        );
+        let icon = provider.icon("0100ABCD12340000").await;
+        assert_eq!(icon.map(|a| a.bytes.to_vec()), Some(b"png-bytes".to_vec()));
+        assert_eq!(provider.icon_cache().stats().entries, 1);
+
+        let cached = provider.icon("0100ABCD12340000").await;
+        assert!(cached.and_then(|a| a.age()).is_some());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // Once older than a day, the copy is refreshed from the provider.
+        let path = std::fs::read_dir(data.path().join("artwork_cache"))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("icon not cached"))??
+            .path();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(std::time::SystemTime::now() - 2 * CACHE_TTL)?;
+        assert!(provider.icon("0100ABCD12340000").await.is_some());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn overrides_live_in_data_dir() -> Result<()> {
+        let data = tempdir()?;
+        let overrides = data.path().join("artwork");
+        std::fs::create_dir(&overrides)?;
+        std::fs::write(overrides.join("0100ABCD12340000.png"), b"icon")?;
+        std::fs::write(overrides.join("0100ABCD12340000.banner.jpg"), b"banner")?;
+        let provider = ArtworkProvider::new(ArtworkConfig::default(), data.path());
+
+        assert!(provider.has_icon_override("0100abcd12340000").await);
+        let icon = provider.icon_override("0100ABCD12340000").await;
+        assert_eq!(icon.map(|a| a.bytes.to_vec()), Some(b"icon".to_vec()));
+        let banner = provider.banner_override("0100ABCD12340000").await;
+        assert_eq!(
+            banner.map(|a| a.content_type),
+            Some(String::from("image/jpeg"))
+        );
+        assert!(!provider.has_icon_override("0100ABCD12341000").await);
+        Ok(())
+    }
+
+    #[test]
+    fn title_ids_are_validated() {
+        assert_eq!(
+            normalize_title_id("0100abcd12340000").as_deref(),
+            Some("0100ABCD12340000")
+        );
+        assert_eq!(normalize_title_id("0100ABCD1234000"), None);
+        assert_eq!(normalize_title_id("0100ABCD1234000/"), None);
+    }
+}
@@ -24,35 +24,155 @@
 //! Both can be combined; the single `username`/`password` pair is merged with `[[users]]`.
 //! Duplicate usernames are deduplicated (last wins). Empty usernames or passwords are skipped.
 //!
+//! **Hashed passwords:** any entry may give `password_hash` instead of `password`, an Argon2
+//! (`$argon2id$...`) or bcrypt (`$2b$...`) hash as printed by `ownfoil-rs hash-password`.
+//! When both are set the hash is used. A hash that doesn't parse fails the whole file.
+//! Hashes are slow to check on purpose, so a password that matched is remembered in memory,
+//! keyed by the hash, until the file is reloaded.
+//!
+//! **Roles and content:** a `[[users]]` entry may also set `role` (`admin`, the default;
+//! `download`, the shop without the admin pages and API; or `view_only`, the shop without
+//! downloads), `kinds` (content kinds the user sees, like `["base", "update"]`), and
+//! `titles` (base title IDs the user sees, with their updates and DLC). Empty lists show
+//! everything. The flat `username`/`password` pair is always an admin.
+//!
+//! **Disabled users:** `disabled = true` keeps a `[[users]]` entry in the file but turns
+//! away its logins, sessions, and shop URL.
+//!
 //! **Security:** Use `chmod 600` on the auth file. The server warns if it is world-readable (Unix).
-
-use std::collections::BTreeMap;
-use std::path::Path;
-
-use serde::Deserialize;
+//!
+//! The file is watched while the server runs; edits are picked up without a restart. A file
+//! that fails to parse or has no valid credentials is ignored and the previous users stay.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use notify::{Event, RecursiveMode, Watcher};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
 use subtle::ConstantTimeEq;
 use thiserror::Error;
-use tracing::warn;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::artwork::normalize_title_id;
+use crate::catalog::{classify_title_id, derive_base_title_id, ContentFile, ContentKind};
+
+/// Quiet period after the last change to the auth file before it is reloaded.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Clone)]
 pub struct AuthSettings {
-    users: BTreeMap<String, String>,
+    users: BTreeMap<String, Credential>,
+    access: BTreeMap<String, UserAccess>,
+    /// Per user, the digest of the last password that matched their hash.
+    verified: Arc<Mutex<HashMap<String, blake3::Hash>>>,
+}
+
+/// How a user's password is stored in the auth file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Credential {
+    Plain(String),
+    /// Argon2 or bcrypt hash in its usual `$...$` form.
+    Hash(String),
+}
+
+/// What a user may do besides browsing the shop.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Download, and use the admin pages and API.
+    #[default]
+    Admin,
+    Download,
+    ViewOnly,
+}
+
+/// A user's role and the part of the library they see. The default, for users without
+/// restrictions and for requests when auth is off, allows everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserAccess {
+    pub role: Role,
+    /// Content kinds listed and served; all when empty.
+    pub kinds: Vec<ContentKind>,
+    /// Uppercase base title IDs listed and served, with their updates and DLC; all when
+    /// empty.
+    pub titles: BTreeSet<String>,
+}
+
+impl UserAccess {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    pub fn can_download(&self) -> bool {
+        self.role != Role::ViewOnly
+    }
+
+    /// Whether the user sees the whole library.
+    pub fn sees_everything(&self) -> bool {
+        self.kinds.is_empty() && self.titles.is_empty()
+    }
+
+    pub fn allows(&self, file: &ContentFile) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&file.kind))
+            && (self.titles.is_empty()
+                || file
+                    .title_id
+                    .as_deref()
+                    .is_some_and(|title_id| self.allows_title_id(title_id)))
+    }
+
+    /// Whether `title_id`, or the base title it belongs to, is on the user's title list.
+    pub fn allows_title_id(&self, title_id: &str) -> bool {
+        if self.titles.is_empty() {
+            return true;
+        }
+        let Some(title_id) = normalize_title_id(title_id) else {
+            return false;
+        };
+        let kind = classify_title_id(Some(&title_id));
+        self.titles.contains(&title_id)
+            || derive_base_title_id(kind, Some(&title_id))
+                .is_some_and(|base| self.titles.contains(&base))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HashAlgorithm {
+    Argon2,
+    Bcrypt,
 }
 
 impl AuthSettings {
+    /// Disabled users, and users with an empty name or password or a hash that doesn't
+    /// parse, are skipped.
     pub fn from_users(users: Vec<AuthUser>) -> Self {
         let mut mapped = BTreeMap::new();
+        let mut access = BTreeMap::new();
 
         for user in users {
-            let username = user.username.trim().to_string();
-            let password = user.password.trim().to_string();
-            if username.is_empty() || password.is_empty() {
+            if user.disabled {
                 continue;
             }
-            mapped.insert(username, password);
+            let Some(credential) = user.credential() else {
+                continue;
+            };
+            let username = user.username.trim().to_string();
+            access.insert(username.clone(), user.access);
+            mapped.insert(username, credential);
         }
 
-        Self { users: mapped }
+        Self {
+            users: mapped,
+            access,
+            verified: Arc::default(),
+        }
     }
 
     pub fn is_enabled(&self) -> bool {
@@ -64,22 +184,257 @@ impl AuthSettings {
     }
 
     pub fn is_authorized(&self, username: &str, password: &str) -> bool {
-        self.users.get(username).is_some_and(|known_password| {
-            let a = password.as_bytes();
-            let b = known_password.as_bytes();
-            a.ct_eq(b).into()
-        })
+        match self.users.get(username) {
+            Some(Credential::Plain(known_password)) => {
+                let a = password.as_bytes();
+                let b = known_password.as_bytes();
+                a.ct_eq(b).into()
+            }
+            Some(Credential::Hash(hash)) => self.verify_hash(username, password, hash),
+            None => false,
+        }
+    }
+
+    fn verify_hash(&self, username: &str, password: &str, hash: &str) -> bool {
+        // Keyed by the hash, so the digest is salted like it and changes with it.
+        let digest = blake3::Hasher::new()
+            .update(hash.as_bytes())
+            .update(password.as_bytes())
+            .finalize();
+        let mut verified = self.verified.lock().unwrap_or_else(|p| p.into_inner());
+        // `blake3::Hash` compares in constant time.
+        if verified.get(username) == Some(&digest) {
+            return true;
+        }
+        drop(verified);
+        if !verify_password_hash(password, hash) {
+            return false;
+        }
+        verified = self.verified.lock().unwrap_or_else(|p| p.into_inner());
+        verified.insert(username.to_string(), digest);
+        true
+    }
+
+    pub fn has_user(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    pub fn access(&self, username: &str) -> Option<&UserAccess> {
+        self.access.get(username)
+    }
+
+    pub fn usernames(&self) -> impl Iterator<Item = &str> {
+        self.users.keys().map(String::as_str)
     }
 
-    fn into_users(self) -> BTreeMap<String, String> {
+    fn into_users(mut self) -> Vec<AuthUser> {
         self.users
+            .into_iter()
+            .map(|(username, credential)| {
+                let access = self.access.remove(&username).unwrap_or_default();
+                let (password, password_hash) = match credential {
+                    Credential::Plain(password) => (password, None),
+                    Credential::Hash(hash) => (String::new(), Some(hash)),
+                };
+                AuthUser {
+                    username,
+                    password,
+                    password_hash,
+                    access,
+                    disabled: false,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether some user may use the admin pages and API.
+    pub fn has_admin(&self) -> bool {
+        self.access.values().any(UserAccess::is_admin)
+    }
+
+    /// Usernames added, removed, or with a new password or access in `next`. Never
+    /// includes passwords.
+    pub fn diff(&self, next: &AuthSettings) -> AuthDiff {
+        AuthDiff {
+            added: next
+                .users
+                .keys()
+                .filter(|name| !self.users.contains_key(*name))
+                .cloned()
+                .collect(),
+            removed: self
+                .users
+                .keys()
+                .filter(|name| !next.users.contains_key(*name))
+                .cloned()
+                .collect(),
+            password_changed: self
+                .users
+                .iter()
+                .filter(|(name, password)| {
+                    next.users
+                        .get(*name)
+                        .is_some_and(|other| other != *password)
+                })
+                .map(|(name, _)| name.clone())
+                .collect(),
+            access_changed: self
+                .access
+                .iter()
+                .filter(|(name, access)| {
+                    next.access.get(*name).is_some_and(|other| other != *access)
+                })
+                .map(|(name, _)| name.clone())
+                .collect(),
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AuthDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub password_changed: Vec<String>,
+    pub access_changed: Vec<String>,
+}
+
+impl AuthDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.password_changed.is_empty()
+            && self.access_changed.is_empty()
+    }
+}
+
+/// Current credentials, swapped as a whole when the auth file is reloaded. Readers get an
+/// `Arc` snapshot, so a request never sees a half-updated user list.
+#[derive(Debug, Clone)]
+pub struct SharedAuth {
+    current: Arc<RwLock<Arc<AuthSettings>>>,
+    /// The auth file, when credentials come from one; `/api/users` edits it.
+    file: Option<Arc<Path>>,
+    /// Held while the auth file is rewritten, so concurrent edits don't lose each other.
+    pub(crate) edits: Arc<Mutex<()>>,
+}
+
+impl SharedAuth {
+    pub fn new(settings: AuthSettings) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(settings))),
+            file: None,
+            edits: Arc::default(),
+        }
+    }
+
+    /// Remember `path` as the file the users came from.
+    pub fn with_file(mut self, path: &Path) -> Self {
+        self.file = Some(Arc::from(path));
+        self
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn load(&self) -> Arc<AuthSettings> {
+        Arc::clone(&self.current.read().unwrap_or_else(|p| p.into_inner()))
+    }
+
+    pub fn store(&self, settings: AuthSettings) {
+        *self.current.write().unwrap_or_else(|p| p.into_inner()) = Arc::new(settings);
+    }
+
+    /// Re-read `path` and swap in its users. On error the current users are kept.
+    pub fn reload(&self, path: &Path) -> Result<AuthDiff, AuthFileError> {
+        let next = load_auth(Some(path))?;
+        let diff = self.load().diff(&next);
+        if !diff.is_empty() {
+            self.store(next);
+        }
+        Ok(diff)
+    }
+}
+
+/// Watch the auth file and reload it into `auth` whenever it changes.
+///
+/// The parent directory is watched (not the file itself) so editors that save by writing a
+/// new file and renaming it over the old one are still noticed.
+pub fn spawn_auth_watcher(path: PathBuf, auth: SharedAuth) -> Result<(), notify::Error> {
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            let touches_file = event
+                .paths
+                .iter()
+                .any(|changed| changed.file_name().map(|n| n.to_os_string()) == file_name);
+            if touches_file && !event.kind.is_access() {
+                let _ = tx.send(());
+            }
+        }
+        Err(err) => warn!(error = %err, "auth file watcher error"),
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    info!(path = %path.display(), "auth file watcher started");
+
+    tokio::spawn(async move {
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            while let Ok(Some(())) = tokio::time::timeout(RELOAD_DEBOUNCE, rx.recv()).await {}
+            match auth.reload(&path) {
+                Ok(diff) if diff.is_empty() => {}
+                Ok(diff) => info!(
+                    added = ?diff.added,
+                    removed = ?diff.removed,
+                    password_changed = ?diff.password_changed,
+                    access_changed = ?diff.access_changed,
+                    users = auth.load().user_count(),
+                    "auth file reloaded"
+                ),
+                Err(err) => warn!(
+                    error = %err,
+                    "auth file reload failed; keeping previous credentials"
+                ),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct AuthUser {
     pub username: String,
     pub password: String,
+    /// Argon2 or bcrypt hash, checked instead of `password` when set.
+    pub password_hash: Option<String>,
+    pub access: UserAccess,
+    /// Kept in the file, but not allowed in.
+    pub disabled: bool,
+}
+
+impl AuthUser {
+    /// The user's password or hash, trimmed; `None` when the name or password is empty or
+    /// the hash doesn't parse.
+    fn credential(&self) -> Option<Credential> {
+        if self.username.trim().is_empty() {
+            return None;
+        }
+        match &self.password_hash {
+            Some(hash) => {
+                let hash = hash.trim();
+                is_password_hash(hash).then(|| Credential::Hash(hash.to_string()))
+            }
+            None => {
+                let password = self.password.trim();
+                (!password.is_empty()).then(|| Credential::Plain(password.to_string()))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -96,19 +451,130 @@ pub enum AuthFileError {
     },
     #[error("auth file {path} does not define valid credentials")]
     EmptyCredentials { path: String },
+    #[error("password_hash of user {username} in {path} is not an Argon2 or bcrypt hash")]
+    InvalidHash { path: String, username: String },
+    #[error("titles of user {username} in {path} lists {value:?}, which is not a title ID")]
+    InvalidTitleId {
+        path: String,
+        username: String,
+        value: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum HashPasswordError {
+    #[error("failed to read password: {0}")]
+    Read(#[from] io::Error),
+    #[error("password is empty")]
+    Empty,
+    #[error("passwords do not match")]
+    Mismatch,
+    #[error("failed to hash password: {0}")]
+    Hash(String),
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct HashPasswordArgs {
+    #[arg(long, value_enum, default_value = "argon2")]
+    pub algorithm: HashAlgorithm,
+    /// Print a whole `[[users]]` entry for this username.
+    #[arg(long, short = 'u', value_name = "NAME")]
+    pub user: Option<String>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 struct AuthFile {
     username: Option<String>,
     password: Option<String>,
+    password_hash: Option<String>,
     users: Option<Vec<AuthUserEntry>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct AuthUserEntry {
     username: String,
+    #[serde(default)]
     password: String,
+    password_hash: Option<String>,
+    #[serde(default)]
+    role: Role,
+    #[serde(default)]
+    kinds: Vec<ContentKind>,
+    #[serde(default)]
+    titles: Vec<String>,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// Whether `hash` is an Argon2 or bcrypt hash this server can check passwords against.
+fn is_password_hash(hash: &str) -> bool {
+    let argon2 = PasswordHash::new(hash).is_ok_and(|parsed| {
+        matches!(
+            parsed.algorithm.as_str(),
+            "argon2id" | "argon2i" | "argon2d"
+        ) && parsed.hash.is_some()
+    });
+    argon2 || hash.parse::<bcrypt::HashParts>().is_ok()
+}
+
+/// Check `password` against an Argon2 or bcrypt hash; both compare in constant time.
+fn verify_password_hash(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        PasswordHash::new(hash).is_ok_and(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+/// Hash `password` with a fresh salt and the algorithm's default cost.
+pub fn hash_password(
+    password: &str,
+    algorithm: HashAlgorithm,
+) -> Result<String, HashPasswordError> {
+    match algorithm {
+        HashAlgorithm::Argon2 => {
+            let mut salt = [0u8; 16];
+            SystemRandom::new()
+                .fill(&mut salt)
+                .map_err(|_| HashPasswordError::Hash(String::from("no random source")))?;
+            let salt = SaltString::encode_b64(&salt)
+                .map_err(|err| HashPasswordError::Hash(err.to_string()))?;
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|err| HashPasswordError::Hash(err.to_string()))
+        }
+        HashAlgorithm::Bcrypt => bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map_err(|err| HashPasswordError::Hash(err.to_string())),
+    }
+}
+
+/// `ownfoil-rs hash-password`: read a password, prompting twice on a terminal or taking
+/// the first line of stdin otherwise, and return the auth file lines for it.
+pub fn hash_password_entry(args: &HashPasswordArgs) -> Result<String, HashPasswordError> {
+    let password = if io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("Password: ")?;
+        if rpassword::prompt_password("Repeat password: ")? != password {
+            return Err(HashPasswordError::Mismatch);
+        }
+        password
+    } else {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.trim().is_empty() {
+        return Err(HashPasswordError::Empty);
+    }
+    let hash = hash_password(password.trim(), args.algorithm)?;
+    Ok(match &args.user {
+        Some(user) => format!("[[users]]\nusername = {user:?}\npassword_hash = {hash:?}"),
+        None => format!("password_hash = {hash:?}"),
+    })
 }
 
 /// Load auth settings from a file. Returns empty settings if path is None.
@@ -149,37 +615,97 @@ pub fn load_users_from_file(path: Option<&Path>) -> Result<Vec<AuthUser>, AuthFi
         path: path.display().to_string(),
         source,
     })?;
+    enabled_users(path, parse_users(path, &raw)?)
+}
 
-    let parsed: AuthFile = toml::from_str(&raw).map_err(|source| AuthFileError::Parse {
+/// Every user in the auth file at `path`, disabled ones included, one per username (the
+/// last entry wins). Users without valid credentials are left out.
+pub fn read_users(path: &Path) -> Result<Vec<AuthUser>, AuthFileError> {
+    let raw = std::fs::read_to_string(path).map_err(|source| AuthFileError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let mut users = BTreeMap::new();
+    for mut user in parse_users(path, &raw)? {
+        if user.credential().is_none() {
+            continue;
+        }
+        user.username = user.username.trim().to_string();
+        users.insert(user.username.clone(), user);
+    }
+    Ok(users.into_values().collect())
+}
+
+/// The users that can log in, or an error when there are none.
+fn enabled_users(path: &Path, users: Vec<AuthUser>) -> Result<Vec<AuthUser>, AuthFileError> {
+    let settings = AuthSettings::from_users(users);
+    if settings.is_enabled() {
+        Ok(settings.into_users())
+    } else {
+        Err(AuthFileError::EmptyCredentials {
+            path: path.display().to_string(),
+        })
+    }
+}
+
+/// Users listed in `raw`, the contents of the auth file at `path`, as written. Fails on
+/// TOML errors, title IDs that don't parse, and hashes that don't parse.
+pub(crate) fn parse_users(path: &Path, raw: &str) -> Result<Vec<AuthUser>, AuthFileError> {
+    let parsed: AuthFile = toml::from_str(raw).map_err(|source| AuthFileError::Parse {
         path: path.display().to_string(),
         source,
     })?;
 
     let mut users = Vec::new();
 
-    if let (Some(username), Some(password)) = (parsed.username, parsed.password) {
-        users.push(AuthUser { username, password });
+    if let Some(username) = parsed.username {
+        if parsed.password.is_some() || parsed.password_hash.is_some() {
+            users.push(AuthUser {
+                username,
+                password: parsed.password.unwrap_or_default(),
+                password_hash: parsed.password_hash,
+                access: UserAccess::default(),
+                disabled: false,
+            });
+        }
     }
 
-    if let Some(more) = parsed.users {
-        users.extend(more.into_iter().map(|entry| AuthUser {
+    for entry in parsed.users.unwrap_or_default() {
+        let titles = entry
+            .titles
+            .iter()
+            .map(|value| {
+                normalize_title_id(value).ok_or_else(|| AuthFileError::InvalidTitleId {
+                    path: path.display().to_string(),
+                    username: entry.username.clone(),
+                    value: value.clone(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        users.push(AuthUser {
             username: entry.username,
             password: entry.password,
-        }));
+            password_hash: entry.password_hash,
+            access: UserAccess {
+                role: entry.role,
+                kinds: entry.kinds,
+                titles,
+            },
+            disabled: entry.disabled,
+        });
     }
 
-    let settings = AuthSettings::from_users(users);
-    if settings.is_enabled() {
-        Ok(settings
-            .into_users()
-            .into_iter()
-            .map(|(username, password)| AuthUser { username, password })
-            .collect::<Vec<_>>())
-    } else {
-        Err(AuthFileError::EmptyCredentials {
+    if let Some(user) = users.iter().find(|user| {
+        user.password_hash
+            .as_deref()
+            .is_some_and(|hash| !is_password_hash(hash.trim()))
+    }) {
+        return Err(AuthFileError::InvalidHash {
             path: path.display().to_string(),
-        })
+            username: user.username.clone(),
+        });
     }
+    Ok(users)
 }
 
 #[cfg(test)]
@@ -188,7 +714,138 @@ mod tests {
     use anyhow::Result;
     use tempfile::tempdir;
 
-    use super::{load_users_from_file, AuthSettings, AuthUser};
+    use argon2::{Algorithm, Params, Version};
+
+    use super::*;
+
+    /// Argon2 hash at the lowest cost, so tests stay fast in debug builds.
+    fn cheap_argon2(password: &str) -> String {
+        let params = Params::new(8, 1, 1, None).unwrap();
+        let salt = SaltString::encode_b64(b"0123456789abcdef").unwrap();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn hashed_passwords_are_checked_and_remembered() {
+        let bcrypt_hash = bcrypt::hash("pw2", 4).unwrap();
+        let settings = AuthSettings::from_users(vec![
+            AuthUser {
+                username: String::from("alice"),
+                password: String::from("ignored"),
+                password_hash: Some(cheap_argon2("pw1")),
+                ..AuthUser::default()
+            },
+            AuthUser {
+                username: String::from("bob"),
+                password: String::new(),
+                password_hash: Some(bcrypt_hash),
+                ..AuthUser::default()
+            },
+            AuthUser {
+                username: String::from("carol"),
+                password: String::new(),
+                password_hash: Some(String::from("pw3")),
+                ..AuthUser::default()
+            },
+        ]);
+
+        assert_eq!(settings.user_count(), 2);
+        assert!(!settings.is_authorized("alice", "ignored"));
+        assert!(!settings.is_authorized("alice", "pw2"));
+        assert!(settings.is_authorized("alice", "pw1"));
+        assert!(settings.is_authorized("bob", "pw2"));
+        assert!(!settings.is_authorized("bob", "pw1"));
+        assert_eq!(settings.verified.lock().unwrap().len(), 2);
+        // Remembered matches still reject other passwords.
+        assert!(settings.is_authorized("alice", "pw1"));
+        assert!(!settings.is_authorized("alice", "pw1 "));
+    }
+
+    #[test]
+    fn auth_file_reads_password_hashes_and_rejects_bad_ones() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("auth.toml");
+        let hash = cheap_argon2("pw1");
+        std::fs::write(
+            &path,
+            format!("[[users]]\nusername = \"alice\"\npassword_hash = {hash:?}\n"),
+        )?;
+        let users = load_users_from_file(Some(&path))?;
+        assert_eq!(users[0].password_hash.as_deref(), Some(hash.as_str()));
+        assert!(load_auth(Some(&path))?.is_authorized("alice", "pw1"));
+
+        std::fs::write(
+            &path,
+            "username = \"admin\"\npassword = \"pw\"\n[[users]]\nusername = \"alice\"\npassword_hash = \"$argon2id$oops\"\n",
+        )?;
+        let result = load_users_from_file(Some(&path));
+        assert!(
+            matches!(result, Err(AuthFileError::InvalidHash { ref username, .. }) if username == "alice")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn auth_file_reads_roles_and_content_filters() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("auth.toml");
+        std::fs::write(
+            &path,
+            "username = \"admin\"\npassword = \"pw\"\n\n[[users]]\nusername = \"friend\"\npassword = \"pw\"\nrole = \"download\"\nkinds = [\"base\", \"update\"]\ntitles = [\"0100abcd12340000\"]\n",
+        )?;
+        let auth = SharedAuth::new(load_auth(Some(&path))?);
+        let settings = auth.load();
+        assert!(settings.access("admin").is_some_and(UserAccess::is_admin));
+        let friend = settings.access("friend").cloned().unwrap_or_default();
+        assert_eq!(friend.role, Role::Download);
+        assert!(friend.can_download() && !friend.is_admin());
+        // Updates of a listed title count, its DLC and other titles don't.
+        assert!(friend.allows_title_id("0100ABCD12340800"));
+        assert!(!friend.allows_title_id("0100EEEE00000000"));
+        let file = |title_id: &str, kind| ContentFile {
+            root: PathBuf::new(),
+            title_id: Some(String::from(title_id)),
+            version: Some(0),
+            kind,
+            ..ContentFile::fixture("x.nsp", 1)
+        };
+        assert!(friend.allows(&file("0100ABCD12340800", ContentKind::Update)));
+        assert!(!friend.allows(&file("0100ABCD12341001", ContentKind::Dlc)));
+
+        std::fs::write(
+            &path,
+            "username = \"admin\"\npassword = \"pw\"\n\n[[users]]\nusername = \"friend\"\npassword = \"pw\"\nrole = \"view_only\"\n",
+        )?;
+        let diff = auth.reload(&path)?;
+        assert_eq!(diff.access_changed, vec![String::from("friend")]);
+        assert!(!auth
+            .load()
+            .access("friend")
+            .is_some_and(UserAccess::can_download));
+
+        std::fs::write(
+            &path,
+            "[[users]]\nusername = \"friend\"\npassword = \"pw\"\ntitles = [\"zelda\"]\n",
+        )?;
+        assert!(matches!(
+            load_users_from_file(Some(&path)),
+            Err(AuthFileError::InvalidTitleId { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn hash_password_makes_hashes_that_verify() -> Result<()> {
+        let hash = hash_password("hunter2", HashAlgorithm::Argon2)?;
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(is_password_hash(&hash));
+        assert!(verify_password_hash("hunter2", &hash));
+        assert!(!verify_password_hash("hunter3", &hash));
+        Ok(())
+    }
 
     #[test]
     fn auth_settings_merges_duplicate_users() {
@@ -196,14 +853,17 @@ mod tests {
             AuthUser {
                 username: String::from("alice"),
                 password: String::from("pw1"),
+                ..AuthUser::default()
             },
             AuthUser {
                 username: String::from("alice"),
                 password: String::from("pw2"),
+                ..AuthUser::default()
             },
             AuthUser {
                 username: String::from("bob"),
                 password: String::from("pw3"),
+                ..AuthUser::default()
             },
         ]);
 
@@ -257,6 +917,7 @@ mod tests {
         let settings = AuthSettings::from_users(vec![AuthUser {
             username: String::from("user"),
             password: String::from("correct"),
+            ..AuthUser::default()
         }]);
         assert!(!settings.is_authorized("user", "wrong"));
     }
@@ -266,7 +927,32 @@ mod tests {
         let settings = AuthSettings::from_users(vec![AuthUser {
             username: String::from("alice"),
             password: String::from("secret"),
+            ..AuthUser::default()
         }]);
         assert!(!settings.is_authorized("bob", "secret"));
     }
+
+    #[test]
+    fn reload_swaps_users_and_keeps_them_on_bad_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("auth.toml");
+        std::fs::write(&path, "username = \"alice\"\npassword = \"pw1\"\n")?;
+        let auth = SharedAuth::new(super::load_auth(Some(&path))?);
+
+        std::fs::write(
+            &path,
+            "[[users]]\nusername = \"alice\"\npassword = \"pw2\"\n\n[[users]]\nusername = \"bob\"\npassword = \"pw3\"\n",
+        )?;
+        let diff = auth.reload(&path)?;
+        assert_eq!(diff.added, vec![String::from("bob")]);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.password_changed, vec![String::from("alice")]);
+        assert!(auth.load().is_authorized("bob", "pw3"));
+        assert!(!auth.load().is_authorized("alice", "pw1"));
+
+        std::fs::write(&path, "not = [valid")?;
+        assert!(auth.reload(&path).is_err());
+        assert!(auth.load().is_authorized("bob", "pw3"));
+        Ok(())
+    }
 }
@@ -0,0 +1,172 @@
+//! Download bandwidth limits: `[downloads] max_rate` is shared by all downloads, and
+//! `per_client_rate` by the downloads of each client IP, so one console pulling a large
+//! dump can't saturate the uplink.
+//!
+//! Each limit is a token bucket holding up to a second of its rate. A download takes
+//! tokens for every chunk it sends and waits while a bucket it draws from is in debt.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use tokio::time::Instant;
+
+use crate::config::DownloadsConfig;
+
+/// Debt worth waiting for; smaller debts carry over to the next chunk, as timers can't
+/// sleep much shorter.
+const MIN_WAIT: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    global: Option<Arc<Bucket>>,
+    per_client_rate: Option<u64>,
+    /// Buckets of clients with downloads running.
+    clients: Arc<Mutex<HashMap<IpAddr, Arc<Bucket>>>>,
+}
+
+impl Bandwidth {
+    pub fn new(config: &DownloadsConfig) -> Self {
+        Self {
+            global: config.max_rate().map(|rate| Arc::new(Bucket::new(rate))),
+            per_client_rate: config.per_client_rate(),
+            clients: Arc::default(),
+        }
+    }
+
+    /// The limits a download by `client` is held to.
+    pub fn for_client(&self, client: IpAddr) -> Throttle {
+        let mut buckets: Vec<_> = self.global.iter().cloned().collect();
+        if let Some(rate) = self.per_client_rate {
+            let mut clients = self.clients.lock().unwrap_or_else(|p| p.into_inner());
+            // Clients without running downloads start over with a full bucket.
+            clients.retain(|_, bucket| Arc::strong_count(bucket) > 1);
+            let bucket = clients
+                .entry(client)
+                .or_insert_with(|| Arc::new(Bucket::new(rate)));
+            buckets.push(Arc::clone(bucket));
+        }
+        Throttle(buckets)
+    }
+}
+
+/// The buckets one download draws from; unlimited when empty.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle(Vec<Arc<Bucket>>);
+
+impl Throttle {
+    /// `stream`, slowed down to the limits.
+    pub fn apply<S>(self, stream: S) -> BoxStream<'static, io::Result<Bytes>>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        if self.0.is_empty() {
+            return stream.boxed();
+        }
+        let buckets = Arc::new(self.0);
+        stream
+            .then(move |item| {
+                let buckets = Arc::clone(&buckets);
+                async move {
+                    if let Ok(chunk) = &item {
+                        let len = chunk.len() as u64;
+                        let wait = buckets
+                            .iter()
+                            .map(|bucket| bucket.take(len))
+                            .max()
+                            .unwrap_or_default();
+                        if wait >= MIN_WAIT {
+                            tokio::time::sleep(wait).await;
+                        }
+                    }
+                    item
+                }
+            })
+            .boxed()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes per second, and the most the bucket holds.
+    rate: u64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// Negative while in debt.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `bytes` tokens, returning how long until the bucket is out of debt.
+    fn take(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+        let now = Instant::now();
+        let refill = now.duration_since(state.refilled).as_secs_f64() * self.rate as f64;
+        state.tokens = (state.tokens + refill).min(self.rate as f64) - bytes as f64;
+        state.refilled = now;
+        if state.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-state.tokens / self.rate as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures_util::stream::{self, StreamExt};
+
+    use super::Bandwidth;
+    use crate::config::DownloadsConfig;
+
+    #[tokio::test(start_paused = true)]
+    async fn downloads_by_one_client_share_its_rate() {
+        let bandwidth = Bandwidth::new(&DownloadsConfig {
+            max_rate: Some(String::from("4MB/s")),
+            per_client_rate: Some(String::from("1MB/s")),
+            ..DownloadsConfig::default()
+        });
+        let client = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+        let chunks = || stream::iter((0..40).map(|_| Ok(Bytes::from(vec![0; 100_000]))));
+
+        // Two downloads by one client: 8 MB at 1 MB/s, the first megabyte from
+        // the full bucket.
+        let started = tokio::time::Instant::now();
+        let first = bandwidth.for_client(client).apply(chunks());
+        let second = bandwidth.for_client(client).apply(chunks());
+        let (first, second) = tokio::join!(first.count(), second.count());
+        assert_eq!((first, second), (40, 40));
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(6900) && elapsed <= Duration::from_millis(7200),
+            "{elapsed:?}"
+        );
+
+        // Without limits, nothing waits.
+        let started = tokio::time::Instant::now();
+        let unlimited = Bandwidth::new(&DownloadsConfig::default()).for_client(client);
+        assert_eq!(unlimited.apply(chunks()).count().await, 40);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
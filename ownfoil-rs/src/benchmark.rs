@@ -0,0 +1,224 @@
+//! Storage benchmark: measures sequential read throughput of each library root.
+//!
+//! A few of the largest files under a root are read start to finish (up to a byte
+//! budget), the same access pattern as a console installing a title. If the result is
+//! well below the speed clients see from `/api/speedtest`, the storage (often a network
+//! mount) is the bottleneck rather than the server. Each root runs as its own job in the
+//! [`JobManager`]; the result is the job's completion message.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::catalog::ContentFile;
+use crate::jobs::{JobHandle, JobManager};
+use crate::split::ContentReader;
+
+/// Job kind used for benchmark jobs.
+pub const JOB_KIND: &str = "benchmark";
+
+/// Files sampled per root.
+const SAMPLE_FILES: usize = 3;
+/// Bytes read per root, spread over the sampled files.
+const MAX_BYTES_PER_ROOT: u64 = 512 << 20;
+const READ_BUFFER: usize = 1 << 20;
+
+/// A library file to read during a benchmark.
+#[derive(Debug, Clone)]
+pub struct BenchmarkSample {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Bytes read and how long it took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub files: usize,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    pub fn mib_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64().max(1e-6);
+        self.bytes as f64 / (1 << 20) as f64 / seconds
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} MiB/s ({} MiB from {} file{})",
+            self.mib_per_second(),
+            self.bytes >> 20,
+            self.files,
+            if self.files == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// The largest current files under `root`, which give the steadiest sequential reads.
+pub fn pick_samples(files: &[ContentFile], root: &Path) -> Vec<BenchmarkSample> {
+    let mut candidates: Vec<_> = files
+        .iter()
+        .filter(|file| file.root == root && !file.stale && file.size > 0)
+        .collect();
+    candidates.sort_by_key(|file| std::cmp::Reverse(file.size));
+    candidates
+        .into_iter()
+        .take(SAMPLE_FILES)
+        .map(|file| BenchmarkSample {
+            path: file.root.join(&file.relative_path),
+            size: file.size,
+        })
+        .collect()
+}
+
+/// Start one benchmark job per root and return their IDs. Roots are measured one after
+/// another, since they often share a disk and parallel reads would skew each other.
+pub fn spawn_benchmarks(
+    jobs: &JobManager,
+    roots: Vec<(PathBuf, Vec<BenchmarkSample>)>,
+) -> Vec<String> {
+    let queued: Vec<_> = roots
+        .into_iter()
+        .map(|(root, samples)| {
+            let total = samples
+                .iter()
+                .map(|sample| sample.size)
+                .sum::<u64>()
+                .min(MAX_BYTES_PER_ROOT);
+            let job = jobs.start(JOB_KIND, root.display().to_string(), total);
+            (root, samples, job)
+        })
+        .collect();
+    let ids = queued
+        .iter()
+        .map(|(_, _, job)| job.id().to_string())
+        .collect();
+    tokio::spawn(async move {
+        for (root, samples, job) in queued {
+            let progress = job.clone();
+            let measured = tokio::task::spawn_blocking(move || {
+                measure(&samples, MAX_BYTES_PER_ROOT, &progress)
+            })
+            .await
+            .map_err(|err| io::Error::other(err.to_string()))
+            .and_then(|result| result);
+            match measured {
+                Ok(throughput) => {
+                    info!(
+                        root = %root.display(),
+                        bytes = throughput.bytes,
+                        mib_per_second = throughput.mib_per_second(),
+                        "storage benchmark finished"
+                    );
+                    job.complete(Some(throughput.to_string()));
+                }
+                Err(err) => {
+                    warn!(root = %root.display(), error = %err, "storage benchmark failed");
+                    job.fail(err.to_string());
+                }
+            }
+        }
+    });
+    ids
+}
+
+/// Read the samples sequentially, at most `budget` bytes in total, split evenly between
+/// files so one huge file doesn't stand in for the whole root.
+pub fn measure(
+    samples: &[BenchmarkSample],
+    budget: u64,
+    job: &JobHandle,
+) -> io::Result<Throughput> {
+    let mut buf = vec![0u8; READ_BUFFER];
+    let mut bytes = 0u64;
+    let mut files = 0;
+    let started = Instant::now();
+    for (index, sample) in samples.iter().enumerate() {
+        let remaining_files = (samples.len() - index) as u64;
+        let share = (budget - bytes) / remaining_files;
+        let mut reader = ContentReader::open(&sample.path)?;
+        let mut read_here = 0u64;
+        while read_here < share {
+            let want = (share - read_here).min(READ_BUFFER as u64) as usize;
+            let read = reader.read(&mut buf[..want])?;
+            if read == 0 {
+                break;
+            }
+            read_here += read as u64;
+            job.add_progress(read as u64);
+        }
+        bytes += read_here;
+        files += 1;
+    }
+    Ok(Throughput {
+        bytes,
+        files,
+        elapsed: started.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{measure, pick_samples};
+    use crate::catalog::ContentFile;
+    use crate::jobs::JobManager;
+
+    fn file(root: &str, name: &str, size: u64) -> ContentFile {
+        ContentFile {
+            root: PathBuf::from(root),
+            ..ContentFile::fixture(name, size)
+        }
+    }
+
+    #[test]
+    fn samples_the_largest_files_of_the_root() {
+        let files = vec![
+            file("/a", "small.nsp", 10),
+            file("/a", "huge.nsp", 1000),
+            file("/b", "other.nsp", 5000),
+            file("/a", "big.nsp", 500),
+            file("/a", "mid.nsp", 100),
+        ];
+        let samples = pick_samples(&files, &PathBuf::from("/a"));
+        let paths: Vec<_> = samples.iter().map(|s| s.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/a/huge.nsp"),
+                PathBuf::from("/a/big.nsp"),
+                PathBuf::from("/a/mid.nsp")
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_within_the_budget_and_reports_progress() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("a.nsp"), vec![1u8; 3000])?;
+        std::fs::write(dir.path().join("b.nsp"), vec![2u8; 500])?;
+        let library = dir.path().to_string_lossy().into_owned();
+        let files = vec![file(&library, "a.nsp", 3000), file(&library, "b.nsp", 500)];
+        let samples = pick_samples(&files, dir.path());
+
+        let jobs = JobManager::new();
+        let job = jobs.start(super::JOB_KIND, library, 2000);
+        let throughput = measure(&samples, 2000, &job)?;
+        // `a.nsp` gets half the budget; `b.nsp` is shorter than the rest.
+        assert_eq!(throughput.bytes, 1500);
+        assert_eq!(throughput.files, 2);
+        assert_eq!(jobs.list(Some(super::JOB_KIND))[0].done_bytes, 1500);
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Title ID blocklist: titles that are never listed or served.
+//!
+//! Persisted to `<data_dir>/blocklist.json` as a list of uppercase title IDs. Blocking a
+//! base title also blocks its updates and DLC. Blocked files are withheld when the catalog
+//! is built, so every listing, search, and download-by-id skips them, and direct path
+//! downloads are checked against the catalog (see [`crate::catalog::Catalog::is_blocked_path`]).
+//!
+//! Lists can be imported from a URL: any plain-text or JSON document works, since every
+//! 16-digit hex token outside `#` comment lines is taken as a title ID.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::artwork::normalize_title_id;
+use crate::catalog::{classify_title_id, derive_base_title_id, ContentFile};
+
+const BLOCKLIST_FILE: &str = "blocklist.json";
+/// Imported lists larger than this are rejected.
+const MAX_IMPORT_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BlocklistError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("blocklist is larger than {MAX_IMPORT_BYTES} bytes")]
+    TooLarge,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Blocklist(BTreeSet<String>);
+
+impl Blocklist {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn title_ids(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// Whether `title_id`, or the base title it belongs to, is blocked.
+    pub fn blocks_title_id(&self, title_id: &str) -> bool {
+        let Some(title_id) = normalize_title_id(title_id) else {
+            return false;
+        };
+        let kind = classify_title_id(Some(&title_id));
+        self.0.contains(&title_id)
+            || derive_base_title_id(kind, Some(&title_id))
+                .is_some_and(|base| self.0.contains(&base))
+    }
+
+    pub fn blocks(&self, file: &ContentFile) -> bool {
+        !self.is_empty()
+            && file
+                .title_id
+                .as_deref()
+                .is_some_and(|title_id| self.blocks_title_id(title_id))
+    }
+}
+
+/// Every title ID in `text`, uppercased, skipping lines that start with `#`.
+pub fn parse_title_ids(text: &str) -> BTreeSet<String> {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(|ch: char| !ch.is_ascii_alphanumeric()))
+        .filter_map(normalize_title_id)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct BlocklistStore {
+    inner: Arc<RwLock<Blocklist>>,
+    store_path: PathBuf,
+}
+
+impl BlocklistStore {
+    /// Load the blocklist from `data_dir`, starting empty if the file is missing or invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(BLOCKLIST_FILE);
+        let blocklist = match std::fs::read_to_string(&store_path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+                warn!(path = %store_path.display(), error = %err, "ignoring invalid blocklist file");
+                Blocklist::default()
+            }),
+            Err(_) => Blocklist::default(),
+        };
+        Self {
+            inner: Arc::new(RwLock::new(blocklist)),
+            store_path,
+        }
+    }
+
+    pub async fn snapshot(&self) -> Blocklist {
+        self.inner.read().await.clone()
+    }
+
+    /// Add `title_ids` (already normalized). Returns how many were new.
+    pub async fn add(&self, title_ids: BTreeSet<String>) -> std::io::Result<usize> {
+        let mut blocklist = self.inner.write().await;
+        let before = blocklist.len();
+        blocklist.0.extend(title_ids);
+        let added = blocklist.len() - before;
+        if added > 0 {
+            self.save(&blocklist).await?;
+        }
+        Ok(added)
+    }
+
+    /// Replace the whole list with `title_ids` (already normalized).
+    pub async fn replace(&self, title_ids: BTreeSet<String>) -> std::io::Result<()> {
+        let mut blocklist = self.inner.write().await;
+        blocklist.0 = title_ids;
+        self.save(&blocklist).await
+    }
+
+    /// Unblock `title_id`. Returns whether it was on the list.
+    pub async fn remove(&self, title_id: &str) -> std::io::Result<bool> {
+        let mut blocklist = self.inner.write().await;
+        if !blocklist.0.remove(title_id) {
+            return Ok(false);
+        }
+        self.save(&blocklist).await.map(|()| true)
+    }
+
+    async fn save(&self, blocklist: &Blocklist) -> std::io::Result<()> {
+        let raw = serde_json::to_string_pretty(blocklist).map_err(std::io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp = self.store_path.with_extension("json.tmp");
+        tokio::fs::write(&temp, raw).await?;
+        tokio::fs::rename(&temp, &self.store_path).await
+    }
+}
+
+/// Download a list from `url` and extract its title IDs.
+pub async fn fetch_title_ids(url: &str) -> Result<BTreeSet<String>, BlocklistError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .user_agent("ownfoil-rs/1.0 (blocklist import)")
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_IMPORT_BYTES as u64)
+    {
+        return Err(BlocklistError::TooLarge);
+    }
+    let body = response.bytes().await?;
+    if body.len() > MAX_IMPORT_BYTES {
+        return Err(BlocklistError::TooLarge);
+    }
+    Ok(parse_title_ids(&String::from_utf8_lossy(&body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{parse_title_ids, BlocklistStore};
+
+    #[test]
+    fn parses_ids_from_text_and_json_skipping_comments() {
+        let text =
+            "# 0100FFFF00000000 is fine\n0100aaaa00000000\n[\"0100BBBB00000000\", \"nope\"]\n";
+        let ids = parse_title_ids(text);
+        assert_eq!(
+            ids.into_iter().collect::<Vec<_>>(),
+            vec!["0100AAAA00000000", "0100BBBB00000000"]
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_a_base_blocks_its_updates_and_dlc_and_survives_reload() -> Result<()> {
+        let dir = tempdir()?;
+        let store = BlocklistStore::load(dir.path());
+        let added = store
+            .add(BTreeSet::from([String::from("0100AAAA00000000")]))
+            .await?;
+        assert_eq!(added, 1);
+
+        let blocklist = BlocklistStore::load(dir.path()).snapshot().await;
+        assert!(blocklist.blocks_title_id("0100aaaa00000000"));
+        assert!(blocklist.blocks_title_id("0100AAAA00000800"));
+        assert!(blocklist.blocks_title_id("0100AAAA00001001"));
+        assert!(!blocklist.blocks_title_id("0100BBBB00000000"));
+
+        assert!(store.remove("0100AAAA00000000").await?);
+        assert!(!store.remove("0100AAAA00000000").await?);
+        Ok(())
+    }
+}
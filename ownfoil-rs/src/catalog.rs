@@ -1,49 +1,123 @@
 //! Catalog: in-memory index of content files with title/version grouping.
 //!
-//! Parses filenames for 16-char hex title IDs and version numbers. Classifies content
+//! Parses filenames for 16-char hex title IDs and version numbers (with built-in patterns,
+//! or the ones from `[scan]` installed by [`set_filename_rules`]). Classifies content
 //! as Base (suffix `000`), Update (`800`), or DLC (other).
 
-use std::collections::BTreeMap;
-use std::path::PathBuf;
-use std::sync::LazyLock;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{LazyLock, OnceLock};
 
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::blocklist::Blocklist;
+use crate::search::{self, SearchQuery};
+use crate::verify::Verification;
 
 /// Content type derived from title ID suffix.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ContentKind {
     Base,
     Update,
     Dlc,
+    /// Homebrew and forwarders: title IDs outside the official `01…` range.
+    Homebrew,
     Unknown,
 }
 
+/// Which format wins when dedup finds the same title and version more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatPreference {
+    /// Prefer NSZ/XCZ (smaller downloads).
+    #[default]
+    Compressed,
+    /// Prefer NSP/XCI (no decompression on the console).
+    Uncompressed,
+}
+
 /// A single content file (NSP, XCI, etc.) with parsed metadata.
-/// A single content file (NSP, XCI, etc.) with parsed metadata.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ContentFile {
+    /// Library root the file was found under. Not exposed to clients.
+    #[serde(skip)]
+    pub root: PathBuf,
+    #[schema(value_type = String)]
     pub relative_path: PathBuf,
     pub name: String,
     pub size: u64,
+    /// Filesystem modification time (Unix seconds), used to order recently added files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
     pub title_id: Option<String>,
     pub version: Option<u32>,
     pub kind: ContentKind,
+    /// BLAKE3 content hash, once the background hashing pass has covered the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Kept from a previous scan because its location was temporarily unreadable.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// Integrity check result, once the background verification pass has covered the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+}
+
+#[cfg(test)]
+impl ContentFile {
+    /// Test fixture: an unclassified file under `/library`, named after its path. Set
+    /// anything else with struct update syntax.
+    pub fn fixture(relative_path: &str, size: u64) -> Self {
+        Self {
+            root: PathBuf::from("/library"),
+            relative_path: PathBuf::from(relative_path),
+            name: String::from(relative_path),
+            size,
+            modified: None,
+            title_id: None,
+            version: None,
+            kind: ContentKind::Unknown,
+            hash: None,
+            stale: false,
+            verification: None,
+        }
+    }
 }
 
 /// All file versions for a given base title ID.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct TitleVersions {
     pub title_id: String,
     pub files: Vec<ContentFile>,
 }
 
+/// One base title with everything the library holds for it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TitleSummary {
+    pub title_id: String,
+    /// Highest-version base file, when the base game itself is in the library.
+    pub base: Option<ContentFile>,
+    pub latest_update: Option<ContentFile>,
+    /// Number of distinct DLC title IDs.
+    pub dlc_count: usize,
+    pub file_count: usize,
+    pub total_size: u64,
+}
+
 /// Sorted index of content files with lookup by title ID.
 #[derive(Debug, Clone)]
 pub struct Catalog {
     files: Vec<ContentFile>,
     titles: BTreeMap<String, Vec<usize>>,
+    blocklist: Blocklist,
+    /// Path keys of files withheld by the blocklist.
+    blocked: HashSet<String>,
+    /// Change-log generation this build corresponds to (see [`crate::changes`]).
+    generation: u64,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -62,6 +136,72 @@ static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
         .unwrap_or_else(|e| panic!("version regex must be valid: {e}"))
 });
 
+/// Filename rules installed at startup; the built-in patterns apply until then.
+static FILENAME_RULES: OnceLock<FilenameRules> = OnceLock::new();
+
+#[derive(Debug, Error)]
+pub enum FilenameRuleError {
+    #[error("invalid {key}: {source}")]
+    Invalid {
+        key: &'static str,
+        source: regex::Error,
+    },
+    #[error("{key} needs a capture group named `{group}`")]
+    MissingGroup {
+        key: &'static str,
+        group: &'static str,
+    },
+}
+
+/// Patterns that pull a title ID and version out of a file name.
+#[derive(Debug, Clone)]
+pub struct FilenameRules {
+    title: Regex,
+    version: Regex,
+}
+
+impl FilenameRules {
+    /// The built-in patterns, with either replaced by a custom regex. A title pattern
+    /// must capture the 16 hex digits in a group named `title`, and a version pattern the
+    /// number in a group named `version`.
+    pub fn new(
+        title_regex: Option<&str>,
+        version_regex: Option<&str>,
+    ) -> Result<Self, FilenameRuleError> {
+        Ok(Self {
+            title: custom_rule("scan.title_regex", title_regex, "title")?
+                .unwrap_or_else(|| TITLE_RE.clone()),
+            version: custom_rule("scan.version_regex", version_regex, "version")?
+                .unwrap_or_else(|| VERSION_RE.clone()),
+        })
+    }
+
+    pub fn parse(&self, name: &str) -> ParsedFilename {
+        parse_with(&self.title, &self.version, name)
+    }
+}
+
+fn custom_rule(
+    key: &'static str,
+    pattern: Option<&str>,
+    group: &'static str,
+) -> Result<Option<Regex>, FilenameRuleError> {
+    let Some(pattern) = pattern else {
+        return Ok(None);
+    };
+    let regex = Regex::new(pattern).map_err(|source| FilenameRuleError::Invalid { key, source })?;
+    if !regex.capture_names().any(|name| name == Some(group)) {
+        return Err(FilenameRuleError::MissingGroup { key, group });
+    }
+    Ok(Some(regex))
+}
+
+/// Use `rules` for every filename parsed from now on. Only the first call has an effect;
+/// returns whether it was this one.
+pub fn set_filename_rules(rules: FilenameRules) -> bool {
+    FILENAME_RULES.set(rules).is_ok()
+}
+
 impl Catalog {
     /// Build a catalog from scanned files. Sorts by title_id, version, name.
     pub fn from_files(mut files: Vec<ContentFile>) -> Self {
@@ -79,13 +219,68 @@ impl Catalog {
             }
         }
 
-        Self { files, titles }
+        Self {
+            files,
+            titles,
+            blocklist: Blocklist::default(),
+            blocked: HashSet::new(),
+            generation: 0,
+        }
+    }
+
+    /// Build a catalog from scanned files, withholding every file `blocklist` covers.
+    pub fn with_blocklist(files: Vec<ContentFile>, blocklist: Blocklist) -> Self {
+        let (blocked, allowed): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|file| blocklist.blocks(file));
+        let mut catalog = Self::from_files(allowed);
+        catalog.blocked = blocked
+            .iter()
+            .map(|file| path_key(&file.relative_path))
+            .collect();
+        catalog.blocklist = blocklist;
+        catalog
+    }
+
+    pub fn with_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Number of files withheld by the blocklist.
+    pub fn blocked_count(&self) -> usize {
+        self.blocked.len()
+    }
+
+    /// Whether a download by relative path must be refused: the file was withheld by the
+    /// blocklist, or its path names a blocked title ID (for files the catalog never saw).
+    pub fn is_blocked_path(&self, relative_path: &Path) -> bool {
+        if self.blocklist.is_empty() {
+            return false;
+        }
+        self.blocked.contains(&path_key(relative_path))
+            || to_display_title_id(
+                parse_filename_metadata(&relative_path.to_string_lossy()).title_id,
+            )
+            .is_some_and(|title_id| self.blocklist.blocks_title_id(&title_id))
     }
 
     pub fn files(&self) -> &[ContentFile] {
         &self.files
     }
 
+    /// All files with their 1-based file IDs.
+    pub fn indexed(&self) -> Vec<(usize, &ContentFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| (index + 1, file))
+            .collect()
+    }
+
     pub fn files_by_kind(&self, kind: ContentKind) -> Vec<&ContentFile> {
         self.files
             .iter()
@@ -93,21 +288,153 @@ impl Catalog {
             .collect::<Vec<_>>()
     }
 
-    pub fn search(&self, query: &str) -> Vec<&ContentFile> {
-        let q = query.to_ascii_lowercase();
+    /// Files matching `query`, best match first. `names` maps title IDs to TitleDB names
+    /// (see [`crate::titledb::TitleDb::names_for`]).
+    pub fn search(
+        &self,
+        query: &SearchQuery,
+        names: &HashMap<String, String>,
+    ) -> Vec<&ContentFile> {
+        search::rank(&self.files, query, names)
+    }
+
+    /// Every title ID in the catalog, in order.
+    pub fn title_ids(&self) -> impl Iterator<Item = &str> {
+        self.titles.keys().map(String::as_str)
+    }
+
+    /// All files, most recently modified first.
+    pub fn recently_added(&self) -> Vec<&ContentFile> {
+        by_recency(&self.files)
+    }
+
+    /// Find a file by its path relative to its library root. When several roots
+    /// contain the same relative path, the first configured root wins.
+    ///
+    /// Falls back to a case-insensitive match (e.g. a client lowercased the URL) as long
+    /// as it is unambiguous.
+    pub fn find_by_relative_path(&self, relative_path: &Path) -> Option<&ContentFile> {
+        if let Some(file) = self
+            .files
+            .iter()
+            .find(|file| file.relative_path == relative_path)
+        {
+            return Some(file);
+        }
+        let key = path_key(relative_path);
+        let mut matches = self
+            .files
+            .iter()
+            .filter(|file| path_key(&file.relative_path) == key);
+        let first = matches.next()?;
+        matches
+            .all(|file| file.relative_path == first.relative_path)
+            .then_some(first)
+    }
+
+    /// Groups of files (as 1-based file IDs) whose relative paths differ only by letter
+    /// case. Downloads of these are ambiguous on case-insensitive filesystems.
+    pub fn case_collisions(&self) -> Vec<Vec<usize>> {
+        let mut groups = self.group_duplicates(|file| Some(path_key(&file.relative_path)));
+        groups.retain(|ids| {
+            let paths = ids
+                .iter()
+                .filter_map(|id| self.files.get(id - 1))
+                .map(|file| &file.relative_path)
+                .collect::<Vec<_>>();
+            paths.iter().any(|path| *path != paths[0])
+        });
+        groups
+    }
+
+    /// Groups of files (as 1-based file IDs) sharing the same content hash.
+    pub fn duplicates_by_hash(&self) -> Vec<Vec<usize>> {
+        self.group_duplicates(|file| file.hash.clone())
+    }
+
+    /// Groups of files (as 1-based file IDs) sharing the same title ID and version.
+    pub fn duplicates_by_title_version(&self) -> Vec<Vec<usize>> {
+        self.group_duplicates(|file| {
+            file.title_id
+                .as_ref()
+                .map(|title_id| format!("{title_id}:{}", file.version.unwrap_or(0)))
+        })
+    }
+
+    fn group_duplicates<F>(&self, key_fn: F) -> Vec<Vec<usize>>
+    where
+        F: Fn(&ContentFile) -> Option<String>,
+    {
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, file) in self.files.iter().enumerate() {
+            if let Some(key) = key_fn(file) {
+                groups.entry(key).or_default().push(index + 1);
+            }
+        }
+        let mut out = groups
+            .into_values()
+            .filter(|ids| ids.len() > 1)
+            .collect::<Vec<_>>();
+        out.sort();
+        out
+    }
+
+    /// All files (base, updates, DLC) belonging to a base title ID.
+    pub fn files_for_base_title(&self, base_title_id: &str) -> Vec<&ContentFile> {
+        let key = base_title_id.to_ascii_uppercase();
         self.files
             .iter()
             .filter(|file| {
-                file.name.to_ascii_lowercase().contains(&q)
-                    || file
-                        .title_id
-                        .as_ref()
-                        .map(|title| title.to_ascii_lowercase().contains(&q))
-                        .unwrap_or(false)
+                derive_base_title_id(file.kind, file.title_id.as_deref()).as_deref()
+                    == Some(key.as_str())
             })
             .collect::<Vec<_>>()
     }
 
+    /// One record per base title, ordered by title ID. Files without a usable title ID
+    /// are left out.
+    #[cfg(any(test, feature = "metrics"))]
+    pub fn titles(&self) -> Vec<TitleSummary> {
+        self.titles_where(|_| true)
+    }
+
+    /// Like [`Catalog::titles`], counting only the files `keep` accepts.
+    pub fn titles_where(&self, keep: impl Fn(&ContentFile) -> bool) -> Vec<TitleSummary> {
+        let mut grouped: BTreeMap<String, Vec<&ContentFile>> = BTreeMap::new();
+        for file in self.files.iter().filter(|file| keep(file)) {
+            if let Some(base) = derive_base_title_id(file.kind, file.title_id.as_deref()) {
+                grouped.entry(base).or_default().push(file);
+            }
+        }
+
+        grouped
+            .into_iter()
+            .map(|(title_id, files)| {
+                // Files are sorted by version, so the last match is the newest.
+                let newest = |wanted: fn(ContentKind) -> bool| {
+                    files
+                        .iter()
+                        .filter(|file| wanted(file.kind))
+                        .max_by_key(|file| file.version)
+                        .map(|file| (*file).clone())
+                };
+                let dlc = files
+                    .iter()
+                    .filter(|file| file.kind == ContentKind::Dlc)
+                    .filter_map(|file| file.title_id.as_deref())
+                    .collect::<std::collections::BTreeSet<_>>();
+                TitleSummary {
+                    base: newest(|kind| !matches!(kind, ContentKind::Update | ContentKind::Dlc)),
+                    latest_update: newest(|kind| kind == ContentKind::Update),
+                    dlc_count: dlc.len(),
+                    file_count: files.len(),
+                    total_size: files.iter().map(|file| file.size).sum(),
+                    title_id,
+                }
+            })
+            .collect()
+    }
+
     /// Get all versions (base, update, DLC) for a base title ID.
     pub fn versions(&self, title_id: &str) -> Option<TitleVersions> {
         let key = title_id.to_ascii_uppercase();
@@ -123,12 +450,19 @@ impl Catalog {
 }
 
 pub fn parse_filename_metadata(name: &str) -> ParsedFilename {
-    let title_id = TITLE_RE
+    match FILENAME_RULES.get() {
+        Some(rules) => rules.parse(name),
+        None => parse_with(&TITLE_RE, &VERSION_RE, name),
+    }
+}
+
+fn parse_with(title_re: &Regex, version_re: &Regex, name: &str) -> ParsedFilename {
+    let title_id = title_re
         .captures(name)
         .and_then(|c| c.name("title"))
         .and_then(|m| to_upper_hex_chars(m.as_str()));
 
-    let version = VERSION_RE
+    let version = version_re
         .captures(name)
         .and_then(|c| {
             c.name("version")
@@ -146,7 +480,9 @@ pub fn classify_title_id(title_id: Option<&str>) -> ContentKind {
     };
 
     let normalized = title_id.to_ascii_uppercase();
-    if normalized.ends_with("000") {
+    if is_homebrew_title_id(&normalized) {
+        ContentKind::Homebrew
+    } else if normalized.ends_with("000") {
         ContentKind::Base
     } else if normalized.ends_with("800") {
         ContentKind::Update
@@ -155,6 +491,108 @@ pub fn classify_title_id(title_id: Option<&str>) -> ContentKind {
     }
 }
 
+/// Official titles live in `0100000000000000..=01FFFFFFFFFFFFFF`; anything else (e.g. `05…`
+/// forwarders) is homebrew.
+pub fn is_homebrew_title_id(title_id: &str) -> bool {
+    !title_id.starts_with("01")
+}
+
+/// Keep only the best copy of each title ID, preserving order: the highest version, then
+/// an available (non-stale) copy, then the preferred format. Files without a title ID
+/// are all kept. The `usize` is carried through untouched (e.g. a file ID).
+pub fn best_versions(
+    files: Vec<(usize, &ContentFile)>,
+    prefer: FormatPreference,
+) -> Vec<(usize, &ContentFile)> {
+    let mut best: HashMap<&str, usize> = HashMap::new();
+    for (position, (_, file)) in files.iter().enumerate() {
+        let Some(title_id) = file.title_id.as_deref() else {
+            continue;
+        };
+        let current = best.entry(title_id).or_insert(position);
+        if dedup_rank(file, prefer) > dedup_rank(files[*current].1, prefer) {
+            *current = position;
+        }
+    }
+    files
+        .iter()
+        .enumerate()
+        .filter(|(position, (_, file))| {
+            file.title_id
+                .as_deref()
+                .map_or(true, |title_id| best.get(title_id) == Some(position))
+        })
+        .map(|(_, entry)| *entry)
+        .collect()
+}
+
+fn dedup_rank(file: &ContentFile, prefer: FormatPreference) -> (u32, bool, bool) {
+    let compressed = file
+        .relative_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("nsz") || ext.eq_ignore_ascii_case("xcz"));
+    (
+        file.version.unwrap_or(0),
+        !file.stale,
+        compressed == (prefer == FormatPreference::Compressed),
+    )
+}
+
+/// Case-insensitive comparison key for a relative path, with `/` separators on every
+/// platform.
+pub fn path_key(path: &Path) -> String {
+    url_path(path).to_lowercase()
+}
+
+/// Relative path joined with `/`, as used in download URLs and API IDs. Windows paths
+/// otherwise render with `\`.
+pub fn url_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Order files by modification time, newest first. Files without a timestamp go last;
+/// ties keep their catalog order.
+pub fn by_recency<'a>(files: impl IntoIterator<Item = &'a ContentFile>) -> Vec<&'a ContentFile> {
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort_by_key(|file| std::cmp::Reverse(file.modified));
+    files
+}
+
+/// Base title ID a file belongs to: updates map `...800` to `...000`, DLC map to the
+/// preceding base title. Returns `None` for missing or malformed title IDs.
+pub fn derive_base_title_id(kind: ContentKind, title_id: Option<&str>) -> Option<String> {
+    let raw = title_id?;
+    if raw.len() != 16 || !raw.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let normalized = raw.to_ascii_uppercase();
+    match kind {
+        ContentKind::Base | ContentKind::Homebrew | ContentKind::Unknown => Some(normalized),
+        ContentKind::Update => {
+            let mut chars = normalized.chars().collect::<Vec<_>>();
+            let len = chars.len();
+            chars[len - 3] = '0';
+            chars[len - 2] = '0';
+            chars[len - 1] = '0';
+            Some(chars.into_iter().collect::<String>())
+        }
+        ContentKind::Dlc => {
+            let high = &normalized[..13];
+            let high_value = u64::from_str_radix(high, 16).ok()?;
+            let base_high = high_value.checked_sub(1)?;
+            Some(format!("{base_high:013X}000"))
+        }
+    }
+}
+
 pub fn to_display_title_id(raw: Option<[char; 16]>) -> Option<String> {
     raw.map(|chars| chars.into_iter().collect::<String>())
 }
@@ -179,9 +617,12 @@ fn to_upper_hex_chars(raw: &str) -> Option<[char; 16]> {
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
-    use super::{classify_title_id, parse_filename_metadata, Catalog, ContentFile, ContentKind};
+    use super::{
+        best_versions, classify_title_id, parse_filename_metadata, path_key, url_path, Catalog,
+        ContentFile, ContentKind, FilenameRuleError, FilenameRules, FormatPreference,
+    };
 
     #[test]
     fn parse_filename_extracts_title_id_and_version() {
@@ -209,6 +650,30 @@ mod tests {
         assert_eq!(parsed.version, Some(131072));
     }
 
+    #[test]
+    fn custom_filename_rules_replace_the_built_in_patterns() -> anyhow::Result<()> {
+        let rules = FilenameRules::new(None, Some(r"-r(?P<version>\d+)\.\w+$"))?;
+        let parsed = rules.parse("Title - 0100ABCD12340800-r2.nsz");
+        assert_eq!(
+            parsed
+                .title_id
+                .map(|chars| chars.into_iter().collect::<String>()),
+            Some(String::from("0100ABCD12340800"))
+        );
+        assert_eq!(parsed.version, Some(2));
+        assert_eq!(rules.parse("Title [v65536].nsp").version, None);
+
+        assert!(matches!(
+            FilenameRules::new(Some(r"[0-9a-f]{16}"), None),
+            Err(FilenameRuleError::MissingGroup { group: "title", .. })
+        ));
+        assert!(matches!(
+            FilenameRules::new(None, Some("(?P<version>")),
+            Err(FilenameRuleError::Invalid { .. })
+        ));
+        Ok(())
+    }
+
     #[test]
     fn classify_title_id_heuristics() {
         assert_eq!(
@@ -223,6 +688,10 @@ mod tests {
             classify_title_id(Some("0100ABCD12340001")),
             ContentKind::Dlc
         );
+        assert_eq!(
+            classify_title_id(Some("05001234ABCD0001")),
+            ContentKind::Homebrew
+        );
         assert_eq!(classify_title_id(None), ContentKind::Unknown);
     }
 
@@ -230,20 +699,18 @@ mod tests {
     fn catalog_groups_versions_by_title() {
         let files = vec![
             ContentFile {
-                relative_path: PathBuf::from("a/base.nsp"),
                 name: String::from("base.nsp"),
-                size: 1,
                 title_id: Some(String::from("0100ABCD12340000")),
                 version: Some(0),
                 kind: ContentKind::Base,
+                ..ContentFile::fixture("a/base.nsp", 1)
             },
             ContentFile {
-                relative_path: PathBuf::from("a/update.nsp"),
                 name: String::from("update.nsp"),
-                size: 1,
                 title_id: Some(String::from("0100ABCD12340000")),
                 version: Some(65536),
                 kind: ContentKind::Update,
+                ..ContentFile::fixture("a/update.nsp", 1)
             },
         ];
 
@@ -255,4 +722,132 @@ mod tests {
         assert_eq!(versions[0].version, Some(0));
         assert_eq!(versions[1].version, Some(65536));
     }
+
+    #[test]
+    fn duplicates_group_by_hash_and_title_version() {
+        let file = |name: &str, title_id: &str, version: u32, hash: &str| ContentFile {
+            title_id: Some(String::from(title_id)),
+            version: Some(version),
+            kind: ContentKind::Base,
+            hash: Some(String::from(hash)),
+            ..ContentFile::fixture(name, 1)
+        };
+        let catalog = Catalog::from_files(vec![
+            file("a.nsp", "0100ABCD12340000", 0, "h1"),
+            file("b.nsp", "0100ABCD12340000", 0, "h2"),
+            file("c.nsp", "0100ABCD12350000", 0, "h1"),
+        ]);
+
+        assert_eq!(catalog.duplicates_by_hash(), vec![vec![1, 3]]);
+        assert_eq!(catalog.duplicates_by_title_version(), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn best_versions_keep_highest_version_then_preferred_format() {
+        let update = |path: &str, version: u32| ContentFile {
+            title_id: Some(String::from("0100ABCD12340800")),
+            version: Some(version),
+            kind: ContentKind::Update,
+            ..ContentFile::fixture(path, 1)
+        };
+        let catalog = Catalog::from_files(vec![
+            update("v1.nsp", 65536),
+            update("v2.nsp", 131072),
+            update("v2.nsz", 131072),
+            ContentFile::fixture("homebrew.nro", 1),
+        ]);
+        let kept = |prefer| {
+            best_versions(catalog.indexed(), prefer)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kept(FormatPreference::Compressed), vec![1, 4]);
+        assert_eq!(kept(FormatPreference::Uncompressed), vec![1, 3]);
+    }
+
+    #[test]
+    fn lookup_falls_back_to_unambiguous_case_insensitive_match() {
+        let catalog = Catalog::from_files(vec![ContentFile::fixture("Games/Zelda.NSP", 1)]);
+        let found = catalog.find_by_relative_path(Path::new("games/zelda.nsp"));
+        assert_eq!(
+            found.map(|file| file.relative_path.as_path()),
+            Some(Path::new("Games/Zelda.NSP"))
+        );
+
+        let ambiguous = Catalog::from_files(vec![
+            ContentFile::fixture("Games/Zelda.nsp", 1),
+            ContentFile::fixture("games/zelda.nsp", 2),
+        ]);
+        assert!(ambiguous
+            .find_by_relative_path(Path::new("GAMES/ZELDA.NSP"))
+            .is_none());
+        assert_eq!(ambiguous.case_collisions(), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn same_path_in_two_roots_is_not_a_case_collision() {
+        let mut other_root = ContentFile::fixture("game.nsp", 1);
+        other_root.root = PathBuf::from("/other");
+        let catalog = Catalog::from_files(vec![ContentFile::fixture("game.nsp", 1), other_root]);
+        assert!(catalog.case_collisions().is_empty());
+    }
+
+    #[test]
+    fn titles_aggregate_base_latest_update_and_dlc() {
+        let titled = |name: &str, title_id: &str, version: u32, size: u64| ContentFile {
+            title_id: Some(String::from(title_id)),
+            version: Some(version),
+            kind: classify_title_id(Some(title_id)),
+            ..ContentFile::fixture(name, size)
+        };
+        let catalog = Catalog::from_files(vec![
+            titled("base.nsp", "0100ABCD12340000", 0, 100),
+            titled("update1.nsp", "0100ABCD12340800", 65536, 10),
+            titled("update2.nsp", "0100ABCD12340800", 131072, 20),
+            titled("dlc1.nsp", "0100ABCD12341001", 0, 1),
+            titled("dlc1-v1.nsp", "0100ABCD12341001", 65536, 1),
+            titled("dlc2.nsp", "0100ABCD12341002", 0, 1),
+            titled("orphan-update.nsp", "0100FFFF00000800", 65536, 5),
+            ContentFile::fixture("untitled.nsp", 7),
+        ]);
+
+        let titles = catalog.titles();
+        assert_eq!(titles.len(), 2);
+        let game = &titles[0];
+        assert_eq!(game.title_id, "0100ABCD12340000");
+        assert_eq!(
+            game.base.as_ref().map(|file| file.name.as_str()),
+            Some("base.nsp")
+        );
+        assert_eq!(
+            game.latest_update.as_ref().map(|file| file.name.as_str()),
+            Some("update2.nsp")
+        );
+        assert_eq!(game.dlc_count, 2);
+        assert_eq!(game.file_count, 6);
+        assert_eq!(game.total_size, 133);
+
+        let orphan = &titles[1];
+        assert!(orphan.base.is_none());
+        assert_eq!(orphan.latest_update.as_ref().map(|file| file.size), Some(5));
+    }
+
+    #[test]
+    fn path_keys_ignore_case() {
+        assert_eq!(path_key(Path::new("Dir/Game.NSP")), "dir/game.nsp");
+        assert_eq!(url_path(Path::new("Dir/Game.NSP")), "Dir/Game.NSP");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_paths_use_forward_slashes() {
+        assert_eq!(url_path(Path::new(r"Dir\Sub\Game.nsp")), "Dir/Sub/Game.nsp");
+        assert_eq!(path_key(Path::new(r"DIR\Game.NSP")), "dir/game.nsp");
+        assert_eq!(
+            path_key(Path::new(r"Dir\Game.nsp")),
+            path_key(Path::new("dir/GAME.nsp"))
+        );
+    }
 }
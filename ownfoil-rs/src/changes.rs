@@ -0,0 +1,187 @@
+//! Catalog change log: what each catalog rebuild added and removed, for delta polling.
+//!
+//! Every rebuild that changes the set of files (or a file's size or modification time)
+//! bumps the catalog generation and records the difference. Clients hold a cursor
+//! (`<epoch>.<generation>`) and ask for everything since it; the epoch is random per
+//! process, so cursors from before a restart are recognized and answered with a reset.
+//! Only the most recent changes are kept; older cursors also get a reset, after which the
+//! client refetches the full catalog.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+use crate::catalog::ContentFile;
+
+/// Change records beyond this count are pruned, oldest first.
+const MAX_CHANGES: usize = 10_000;
+
+#[derive(Debug, Clone)]
+enum Change {
+    Added(ContentFile),
+    Removed(ContentFile),
+}
+
+impl Change {
+    fn file(&self) -> &ContentFile {
+        match self {
+            Change::Added(file) | Change::Removed(file) => file,
+        }
+    }
+}
+
+/// Net effect of the changes after a cursor.
+#[derive(Debug, Default)]
+pub struct Delta {
+    /// Files that are new or changed since the cursor, in the order they appeared.
+    pub added: Vec<ContentFile>,
+    /// Files that were present at the cursor and are gone or changed since.
+    pub removed: Vec<ContentFile>,
+}
+
+#[derive(Debug)]
+pub struct ChangeLog {
+    epoch: u32,
+    generation: u64,
+    /// Oldest generation whose changes are all still recorded; earlier cursors reset.
+    floor: u64,
+    changes: VecDeque<(u64, Change)>,
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self {
+            epoch: uuid::Uuid::new_v4().as_fields().0,
+            generation: 0,
+            floor: 0,
+            changes: VecDeque::new(),
+        }
+    }
+}
+
+impl ChangeLog {
+    /// Opaque cursor for `generation`.
+    pub fn cursor(&self, generation: u64) -> String {
+        format!("{:08x}.{generation}", self.epoch)
+    }
+
+    /// Record the difference between two catalog builds. Returns the generation of the
+    /// new build, which only moves when something changed.
+    pub fn record(&mut self, before: &[ContentFile], after: &[ContentFile]) -> u64 {
+        let fingerprints = |files: &[ContentFile]| -> HashMap<PathBuf, (u64, Option<u64>)> {
+            files
+                .iter()
+                .map(|file| (key(file), (file.size, file.modified)))
+                .collect()
+        };
+        let old = fingerprints(before);
+        let new = fingerprints(after);
+        let removed = before
+            .iter()
+            .filter(|file| new.get(&key(file)) != Some(&(file.size, file.modified)))
+            .cloned()
+            .map(Change::Removed);
+        let added = after
+            .iter()
+            .filter(|file| old.get(&key(file)) != Some(&(file.size, file.modified)))
+            .cloned()
+            .map(Change::Added);
+        let changes: Vec<_> = removed.chain(added).collect();
+        if changes.is_empty() {
+            return self.generation;
+        }
+
+        self.generation += 1;
+        self.changes
+            .extend(changes.into_iter().map(|change| (self.generation, change)));
+        while self.changes.len() > MAX_CHANGES {
+            if let Some((generation, _)) = self.changes.pop_front() {
+                self.floor = self.floor.max(generation);
+            }
+        }
+        self.generation
+    }
+
+    /// Changes after `cursor`, or `None` when the cursor is unknown, from another run of
+    /// the server, or older than the retained history.
+    pub fn since(&self, cursor: &str) -> Option<Delta> {
+        let (epoch, generation) = cursor.split_once('.')?;
+        let epoch = u32::from_str_radix(epoch, 16).ok()?;
+        let since: u64 = generation.parse().ok()?;
+        if epoch != self.epoch || since < self.floor || since > self.generation {
+            return None;
+        }
+
+        // Per file: whether it existed at the cursor, and its latest state.
+        let mut order = Vec::new();
+        let mut net: HashMap<PathBuf, (Option<ContentFile>, Option<ContentFile>)> = HashMap::new();
+        for (_, change) in self.changes.iter().filter(|(gen, _)| *gen > since) {
+            let entry = net.entry(key(change.file())).or_insert_with(|| {
+                order.push(key(change.file()));
+                (None, None)
+            });
+            match change {
+                Change::Removed(file) => {
+                    if entry.0.is_none() && entry.1.is_none() {
+                        entry.0 = Some(file.clone());
+                    }
+                    entry.1 = None;
+                }
+                Change::Added(file) => entry.1 = Some(file.clone()),
+            }
+        }
+        let mut delta = Delta::default();
+        for path in order {
+            if let Some((original, latest)) = net.remove(&path) {
+                delta.removed.extend(original);
+                delta.added.extend(latest);
+            }
+        }
+        Some(delta)
+    }
+}
+
+fn key(file: &ContentFile) -> PathBuf {
+    file.root.join(&file.relative_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChangeLog;
+    use crate::catalog::ContentFile;
+
+    fn names(files: &[ContentFile]) -> Vec<&str> {
+        files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    #[test]
+    fn deltas_collapse_to_the_net_change_since_the_cursor() {
+        let mut log = ChangeLog::default();
+        let first = vec![
+            ContentFile::fixture("a.nsp", 1),
+            ContentFile::fixture("b.nsp", 1),
+        ];
+        let start = log.record(&[], &first);
+        let cursor = log.cursor(start);
+        assert_eq!(log.record(&first, &first), start);
+
+        let second = vec![
+            ContentFile::fixture("a.nsp", 2),
+            ContentFile::fixture("c.nsp", 1),
+        ];
+        log.record(&first, &second);
+        let third = vec![ContentFile::fixture("a.nsp", 2)];
+        let latest = log.record(&second, &third);
+
+        let delta = log.since(&cursor).unwrap_or_default();
+        // `c.nsp` came and went; `a.nsp` changed, so it is replaced.
+        assert_eq!(names(&delta.removed), vec!["a.nsp", "b.nsp"]);
+        assert_eq!(names(&delta.added), vec!["a.nsp"]);
+        assert_eq!(delta.added[0].size, 2);
+
+        let current = log.since(&log.cursor(latest)).unwrap_or_default();
+        assert!(current.added.is_empty() && current.removed.is_empty());
+        assert!(log.since("00000000.1").is_none());
+        assert!(log.since(&log.cursor(99)).is_none());
+        assert!(log.since("garbage").is_none());
+    }
+}
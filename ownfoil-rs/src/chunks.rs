@@ -0,0 +1,139 @@
+//! Chunk maps: a file cut into fixed-size byte ranges, each with its SHA-256, so a client
+//! on a flaky connection can fetch a large title range by range and check every piece
+//! before moving on.
+//!
+//! A map is computed on its first request, which reads the whole file, and kept in
+//! memory while the file's size and modification time stay the same. Requests for a map
+//! that is still being computed wait for it instead of reading the file again.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures_util::stream::{BoxStream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+pub const DEFAULT_CHUNK_SIZE: u64 = 64 << 20;
+pub const MIN_CHUNK_SIZE: u64 = 1 << 20;
+pub const MAX_CHUNK_SIZE: u64 = 4 << 30;
+
+/// Maps kept at most; more start the cache over.
+const MAX_CACHED: usize = 256;
+
+/// Which file a map is for, and of which version of it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<u64>,
+    pub chunk_size: u64,
+}
+
+type Hashes = Arc<Vec<String>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkMaps {
+    maps: Arc<Mutex<HashMap<ChunkKey, Arc<OnceCell<Hashes>>>>>,
+}
+
+impl ChunkMaps {
+    /// SHA-256 of each `key.chunk_size` bytes of the file, the last chunk possibly
+    /// shorter, hashing `open()`'s stream of the whole file unless the map is cached.
+    pub async fn hashes<F, Fut>(&self, key: ChunkKey, open: F) -> io::Result<Hashes>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = io::Result<BoxStream<'static, io::Result<Bytes>>>>,
+    {
+        let chunk_size = key.chunk_size;
+        let cell = {
+            let mut maps = self.maps.lock().unwrap_or_else(|p| p.into_inner());
+            if maps.len() >= MAX_CACHED && !maps.contains_key(&key) {
+                maps.clear();
+            }
+            Arc::clone(maps.entry(key).or_default())
+        };
+        let hashes = cell
+            .get_or_try_init(|| async move {
+                let stream = open().await?;
+                hash_chunks(stream, chunk_size).await.map(Arc::new)
+            })
+            .await?;
+        Ok(Arc::clone(hashes))
+    }
+}
+
+async fn hash_chunks(
+    mut stream: BoxStream<'static, io::Result<Bytes>>,
+    chunk_size: u64,
+) -> io::Result<Vec<String>> {
+    let mut hashes = Vec::new();
+    let mut hasher = Sha256::new();
+    let mut filled = 0u64;
+    while let Some(bytes) = stream.next().await {
+        let mut bytes = bytes?;
+        while !bytes.is_empty() {
+            let take = (chunk_size - filled).min(bytes.len() as u64) as usize;
+            hasher.update(bytes.split_to(take));
+            filled += take as u64;
+            if filled == chunk_size {
+                hashes.push(hex(&hasher.finalize_reset()));
+                filled = 0;
+            }
+        }
+    }
+    if filled > 0 {
+        hashes.push(hex(&hasher.finalize()));
+    }
+    Ok(hashes)
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_util::stream;
+
+    use super::*;
+
+    fn key(chunk_size: u64) -> ChunkKey {
+        ChunkKey {
+            path: PathBuf::from("game.nsp"),
+            size: 10,
+            modified: None,
+            chunk_size,
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_hash_across_reads_and_are_cached() -> io::Result<()> {
+        let maps = ChunkMaps::default();
+        let opened = AtomicUsize::new(0);
+        let open = || async {
+            opened.fetch_add(1, Ordering::Relaxed);
+            let reads = ["012", "3456", "789"].map(|read| Ok(Bytes::from_static(read.as_bytes())));
+            Ok(stream::iter(reads).boxed())
+        };
+
+        let hashes = maps.hashes(key(4), open).await?;
+        let expected: Vec<_> = ["0123", "4567", "89"]
+            .iter()
+            .map(|chunk| hex(&Sha256::digest(chunk.as_bytes())))
+            .collect();
+        assert_eq!(*hashes, expected);
+        maps.hashes(key(4), open).await?;
+        assert_eq!(opened.load(Ordering::Relaxed), 1);
+
+        // A chunk size that divides the file evenly has no short last chunk.
+        assert_eq!(maps.hashes(key(5), open).await?.len(), 2);
+        assert_eq!(opened.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+}
@@ -3,13 +3,23 @@
 //! Priority: CLI flags > config file > defaults. `data_dir` defaults to `./data`
 //! or `$XDG_DATA_HOME/ownfoil-rs` when set.
 
-use std::net::SocketAddr;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 
 use clap::Parser;
 use serde::Deserialize;
 use thiserror::Error;
 
+use crate::auth::HashPasswordArgs;
+use crate::catalog::{FilenameRuleError, FilenameRules, FormatPreference};
+use crate::export::ExportFormat;
+use crate::network::{BindAddr, IpNetwork};
+use crate::remote::RemoteArgs;
+use crate::selftest::SelftestArgs;
+use crate::serve_files::DEFAULT_READ_BUFFER;
+
 #[derive(Debug, Parser)]
 #[command(
     name = "ownfoil-rs",
@@ -17,8 +27,10 @@ use thiserror::Error;
     about = "Minimal CyberFoil-compatible Tinfoil game server"
 )]
 pub struct Cli {
+    /// Address to listen on, or `unix:/path` for a Unix socket; repeat to listen on
+    /// several.
     #[arg(long, value_name = "ADDR")]
-    pub bind: Option<SocketAddr>,
+    pub bind: Vec<BindAddr>,
 
     #[arg(
         long = "library-folder",
@@ -26,7 +38,7 @@ pub struct Cli {
         visible_alias = "library-root",
         value_name = "DIR"
     )]
-    pub library_root: Option<PathBuf>,
+    pub library_roots: Vec<PathBuf>,
 
     #[arg(long, value_name = "FILE")]
     pub auth_file: Option<PathBuf>,
@@ -34,21 +46,208 @@ pub struct Cli {
     #[arg(long, value_name = "SECONDS")]
     pub scan_interval_seconds: Option<u64>,
 
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub scan_mode: Option<ScanMode>,
+
+    /// Hash library files in the background for duplicate detection.
+    #[arg(long)]
+    pub hash_files: bool,
+
+    /// Secondary folder titles can be replicated to (e.g. an external drive).
+    #[arg(long, value_name = "DIR")]
+    pub mirror_root: Option<PathBuf>,
+
+    /// List only the best version of each title in the shop index.
+    #[arg(long)]
+    pub dedup: bool,
+
+    /// Serve a made-up library instead of scanning library folders. Downloads are zeros;
+    /// the shop is public unless an auth file is given.
+    #[arg(long)]
+    pub demo: bool,
+
     #[arg(long, short = 'c', value_name = "FILE")]
     pub config: Option<PathBuf>,
+
+    /// PEM certificate chain; serves HTTPS together with `--tls-key`.
+    #[arg(long, value_name = "FILE")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert`.
+    #[arg(long, value_name = "FILE")]
+    pub tls_key: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Command {
+    /// Scan the library and print its inventory instead of starting the server.
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        /// Write to this file instead of stdout.
+        #[arg(long, short = 'o', value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Hash a password for a `password_hash` entry in the auth file.
+    HashPassword(HashPasswordArgs),
+    /// Administer a running server over its HTTP API.
+    Remote(RemoteArgs),
+    /// Replay Tinfoil, CyberFoil, and DBI requests against a running server and check
+    /// the answers.
+    Selftest(SelftestArgs),
 }
 
 /// Resolved application configuration after merging CLI, file, and env.
 #[derive(Debug, Clone)]
 pub struct AppConfig {
-    pub bind: SocketAddr,
-    pub library_root: PathBuf,
+    /// Listen addresses, never empty; upgrades hand over the first TCP one.
+    pub binds: Vec<BindAddr>,
+    /// Library roots, highest priority first.
+    pub library_roots: Vec<LibraryRoot>,
     pub auth_file: Option<PathBuf>,
     pub public_shop: bool,
     pub insecure_admin_cookie: bool,
     pub scan_interval_seconds: u64,
+    pub scan_mode: ScanMode,
+    pub scan: ScanConfig,
+    pub hash_files: bool,
+    /// Destination for per-title replication; replication is disabled when unset.
+    pub mirror_root: Option<PathBuf>,
+    /// When set, shop listings keep only the best copy of each title ID.
+    pub dedup: Option<FormatPreference>,
     pub data_dir: PathBuf,
     pub titledb: TitleDbConfig,
+    pub artwork: ArtworkConfig,
+    pub reports: ReportsConfig,
+    pub verify: VerifyConfig,
+    pub server: ServerConfig,
+    pub rate_limit: RateLimitConfig,
+    pub hooks: HooksConfig,
+    pub health: HealthConfig,
+    pub shop: ShopConfig,
+    pub quotas: QuotaConfig,
+    pub idle: IdleConfig,
+    pub ftp: FtpConfig,
+    pub downloads: DownloadsConfig,
+    pub uploads: UploadsConfig,
+    /// Title IDs and relative paths left out of every shop listing.
+    pub hidden: Vec<String>,
+    /// `--demo`: serve [`crate::demo`]'s made-up library; `library_roots` is empty.
+    pub demo: bool,
+}
+
+/// A library folder with its own scan schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryRoot {
+    pub path: PathBuf,
+    pub scan_interval_seconds: u64,
+    /// Higher priority roots are merged first and win on duplicate relative paths.
+    pub priority: i32,
+    /// Storage budget of the root in GiB; unlimited when unset.
+    pub quota_gb: Option<u64>,
+}
+
+/// `[[libraries]]` entry in the config file.
+#[derive(Debug, Clone, Deserialize)]
+struct LibraryRootEntry {
+    path: PathBuf,
+    scan_interval_seconds: Option<u64>,
+    priority: Option<i32>,
+    quota_gb: Option<u64>,
+}
+
+/// How the catalog is kept in sync with the library root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanMode {
+    /// Rescan every `scan_interval_seconds`.
+    #[default]
+    Poll,
+    /// Rescan on filesystem events only.
+    Watch,
+    /// Filesystem events plus the periodic rescan as a safety net.
+    Both,
+}
+
+impl ScanMode {
+    pub fn polls(self) -> bool {
+        matches!(self, ScanMode::Poll | ScanMode::Both)
+    }
+
+    pub fn watches(self) -> bool {
+        matches!(self, ScanMode::Watch | ScanMode::Both)
+    }
+}
+
+/// `[scan]`: how hard a library scan may hit the disk (or NAS), and what it indexes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ScanConfig {
+    /// Directories walked in parallel.
+    #[serde(default = "default_scan_concurrency")]
+    pub concurrency: usize,
+    /// Upper bound on files inspected per second across all workers; unlimited when unset.
+    pub files_per_second: Option<u32>,
+    /// Index `.zip` archives that contain a single title.
+    #[serde(default)]
+    pub archives: bool,
+    /// Files smaller than this many bytes (and empty files) are quarantined as
+    /// probably truncated copies.
+    #[serde(default)]
+    pub min_file_size: u64,
+    /// Descend into symlinked directories and index symlinked files. Each directory is
+    /// walked once, so links back into the tree cannot loop.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Replaces the built-in title ID pattern; must have a `title` capture group.
+    pub title_regex: Option<String>,
+    /// Replaces the built-in version pattern; must have a `version` capture group.
+    pub version_regex: Option<String>,
+    /// Directory inside each root that files deleted through the admin API are moved
+    /// to. It is never scanned.
+    #[serde(default = "default_trash_dir")]
+    pub trash_dir: String,
+    /// File names that exclude the directory holding them, and everything below it, from
+    /// scans.
+    #[serde(default = "default_ignore_markers")]
+    pub ignore_markers: Vec<String>,
+}
+
+impl ScanConfig {
+    /// Filename rules with this config's custom patterns applied.
+    pub fn filename_rules(&self) -> Result<FilenameRules, FilenameRuleError> {
+        FilenameRules::new(self.title_regex.as_deref(), self.version_regex.as_deref())
+    }
+}
+
+fn default_scan_concurrency() -> usize {
+    4
+}
+
+fn default_trash_dir() -> String {
+    String::from(".trash")
+}
+
+fn default_ignore_markers() -> Vec<String> {
+    vec![String::from(".nomedia"), String::from(".ownfoil-ignore")]
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_scan_concurrency(),
+            files_per_second: None,
+            archives: false,
+            min_file_size: 0,
+            follow_symlinks: false,
+            title_regex: None,
+            version_regex: None,
+            trash_dir: default_trash_dir(),
+            ignore_markers: default_ignore_markers(),
+        }
+    }
 }
 
 /// TitleDB settings: region, language, refresh interval, optional URL override.
@@ -79,6 +278,470 @@ impl Default for TitleDbConfig {
     }
 }
 
+/// Fallback artwork for titles TitleDB has no icon for (e.g. homebrew).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ArtworkConfig {
+    /// URL template with a `{title_id}` placeholder, e.g. `https://example.com/icons/{title_id}.png`.
+    pub fallback_url: Option<String>,
+    /// Folder of `<TITLE_ID>.png` / `.jpg` / `.webp` images.
+    pub fallback_dir: Option<PathBuf>,
+    /// Size budget of the fetched icon cache in MiB; 256 when unset.
+    pub cache_max_mb: Option<u64>,
+    /// Artwork fetched in parallel for titles a scan adds; 4 when unset, 0 turns
+    /// pre-warming off.
+    pub prewarm_concurrency: Option<usize>,
+}
+
+/// `[quotas]`: storage budgets per content kind, in GiB; unlimited when unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QuotaConfig {
+    pub base_gb: Option<u64>,
+    pub update_gb: Option<u64>,
+    pub dlc_gb: Option<u64>,
+    pub homebrew_gb: Option<u64>,
+    pub unknown_gb: Option<u64>,
+}
+
+/// `[idle]`: stretch or pause background work while no clients are around.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IdleConfig {
+    /// Time without requests before idle mode, e.g. `30m`; idle mode is off when unset.
+    pub after: Option<String>,
+    /// How many times longer periodic scans wait while idle; 10 when unset.
+    pub scan_multiplier: Option<u32>,
+}
+
+impl IdleConfig {
+    /// `after` as a duration; `None` when unset or invalid.
+    pub fn after(&self) -> Option<std::time::Duration> {
+        self.after
+            .as_deref()
+            .and_then(|after| humantime::parse_duration(after).ok())
+    }
+}
+
+/// `[ftp]`: read-only FTP for DBI and FTP clients; off when `bind` is unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FtpConfig {
+    /// Address for control connections, e.g. `0.0.0.0:2121`.
+    pub bind: Option<SocketAddr>,
+    /// Ports for passive data connections, e.g. `"50000-50100"`; any free port when unset.
+    pub passive_ports: Option<String>,
+    /// Address clients are told to open data connections to, for NAT and containers; the
+    /// one the control connection came in on when unset.
+    #[cfg_attr(not(feature = "ftp"), allow(dead_code))]
+    pub passive_address: Option<IpAddr>,
+}
+
+impl FtpConfig {
+    /// `passive_ports` as a range; `None` when unset or invalid.
+    pub fn passive_ports(&self) -> Option<RangeInclusive<u16>> {
+        let ports = self.passive_ports.as_deref()?.trim();
+        let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+        let first: u16 = first.trim().parse().ok()?;
+        let last: u16 = last.trim().parse().ok()?;
+        (first > 0 && first <= last).then_some(first..=last)
+    }
+}
+
+/// `[downloads]`: bandwidth limits, as rates like `"40MB/s"`, and concurrent downloads,
+/// unlimited when unset; how files are read; and NSZ decompression.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DownloadsConfig {
+    /// Shared by all downloads.
+    pub max_rate: Option<String>,
+    /// Shared by the downloads of each client IP.
+    pub per_client_rate: Option<String>,
+    /// Files each client (user, or IP when anonymous) may download at once; unlimited
+    /// when unset or 0.
+    pub max_concurrent_per_client: Option<usize>,
+    /// KiB read from disk at a time per download; 256 when unset.
+    pub read_buffer_kib: Option<usize>,
+    /// Serve `.nsz` files decompressed to NSP at `/api/get_game/{id}/nsp`. Off by default,
+    /// as every such download keeps a core busy decompressing.
+    #[serde(default)]
+    pub transcode_nsz: bool,
+}
+
+impl DownloadsConfig {
+    /// `max_rate` in bytes per second; `None` when unset or invalid.
+    pub fn max_rate(&self) -> Option<u64> {
+        self.max_rate.as_deref().and_then(parse_rate)
+    }
+
+    /// `per_client_rate` in bytes per second; `None` when unset or invalid.
+    pub fn per_client_rate(&self) -> Option<u64> {
+        self.per_client_rate.as_deref().and_then(parse_rate)
+    }
+
+    /// `read_buffer_kib` in bytes, between 4 KiB and 16 MiB.
+    pub fn read_buffer(&self) -> usize {
+        self.read_buffer_kib
+            .map_or(DEFAULT_READ_BUFFER, |kib| kib.clamp(4, 16 * 1024) * 1024)
+    }
+}
+
+/// `[uploads]`: `PUT /api/upload/{filename}`; off when `dir` is unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UploadsConfig {
+    /// Folder inside the first library root that uploads go to, e.g. `"incoming"`.
+    pub dir: Option<String>,
+}
+
+impl UploadsConfig {
+    /// `dir` as a relative path; `None` when unset or when it would leave the library.
+    pub fn dir(&self) -> Option<PathBuf> {
+        let dir = Path::new(self.dir.as_deref()?.trim());
+        let mut components = dir.components().peekable();
+        (components.peek().is_some()
+            && components.all(|component| matches!(component, std::path::Component::Normal(_))))
+        .then(|| dir.to_path_buf())
+    }
+}
+
+/// Bytes per second of a rate like `"10MB/s"`, `"512 KiB/s"`, or `"1000000"`. Decimal
+/// units are powers of 1000 and binary ones (`KiB`, `MiB`, `GiB`) powers of 1024.
+fn parse_rate(raw: &str) -> Option<u64> {
+    let raw = raw.trim();
+    let raw = raw.strip_suffix("/s").unwrap_or(raw).trim_end();
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let unit = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    let rate = (number * unit).round();
+    (rate >= 1.0).then_some(rate as u64)
+}
+
+/// `[reports]`: periodic library reports written to `<data_dir>/reports`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReportsConfig {
+    /// Reports are disabled when unset.
+    pub schedule: Option<ReportSchedule>,
+    /// URLs each new report is POSTed to as JSON.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+/// `[verify]`: background integrity checks of NSP/XCI dumps.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VerifyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// `prod.keys` file; with it, NCA headers are decrypted and checked as well.
+    pub keys_file: Option<PathBuf>,
+    /// Leave files that fail verification out of the shop.
+    #[serde(default)]
+    pub exclude_bad: bool,
+}
+
+/// `[shop]`: how the shop index is served.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ShopConfig {
+    /// Serve Tinfoil an encrypted index at `/`; other clients still get plain JSON.
+    #[serde(default)]
+    pub encrypt: bool,
+    /// PEM file with Tinfoil's RSA public key, required by `encrypt`.
+    pub public_key_file: Option<PathBuf>,
+    /// Welcome message Tinfoil shows when the shop loads; `"ok"` when unset.
+    pub motd: Option<String>,
+    /// Referrer Tinfoil sends with requests to the shop.
+    pub referrer: Option<String>,
+    /// Extra `Name: value` headers Tinfoil sends with requests to the shop.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Google API key Tinfoil uses for Google Drive locations.
+    pub google_api_key: Option<String>,
+    /// Oldest Tinfoil version allowed to open the shop.
+    pub min_version: Option<f64>,
+    /// Themes (by hash) Tinfoil refuses to open the shop with.
+    #[serde(default)]
+    pub theme_blacklist: Vec<String>,
+    /// The only themes Tinfoil opens the shop with, when set.
+    #[serde(default)]
+    pub theme_whitelist: Vec<String>,
+    /// Message shown when a theme is rejected.
+    pub theme_error: Option<String>,
+    /// PEM client certificate Tinfoil presents to the shop (`clientCertPub`).
+    pub client_cert_file: Option<PathBuf>,
+    /// PEM private key of `client_cert_file` (`clientCertKey`).
+    pub client_key_file: Option<PathBuf>,
+    /// `[shop.views."/"]`: what each kind of client gets from a shop index route.
+    #[serde(default)]
+    pub views: BTreeMap<String, ShopViewRule>,
+}
+
+/// Shop index routes whose view can be picked per client.
+pub const SHOP_VIEW_ROUTES: [&str; 3] = ["/", "/shop", "/api/shop"];
+
+/// A shape of the shop index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShopView {
+    /// Tinfoil's index: encrypted when `encrypt` is set, plain JSON otherwise.
+    Tinfoil,
+    /// The plain JSON index.
+    Json,
+    /// The `/api/shop/sections` document CyberFoil builds its home screen from.
+    Sections,
+    /// A library page for people in a browser.
+    Html,
+}
+
+/// Views of one route per client; unset clients keep the route's default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShopViewRule {
+    pub tinfoil: Option<ShopView>,
+    pub cyberfoil: Option<ShopView>,
+    pub browser: Option<ShopView>,
+    /// Anything else: scripts, download managers, other installers.
+    pub other: Option<ShopView>,
+}
+
+/// `[health]`: when `/health` reports `degraded`. Checks without a threshold are off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct HealthConfig {
+    /// Oldest acceptable scan of any root, in seconds.
+    pub max_scan_age_seconds: Option<u64>,
+    /// Oldest acceptable TitleDB refresh, in seconds; ignored while TitleDB is disabled.
+    pub max_titledb_age_seconds: Option<u64>,
+}
+
+/// `[server]`: connection limits that protect an internet-exposed listener, HTTPS, and
+/// the URL prefix the server is reached under.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ServerConfig {
+    /// Open connections allowed per client IP; further connections are closed at once.
+    #[serde(default = "default_max_connections_per_ip")]
+    pub max_connections_per_ip: usize,
+    /// Time a client gets to send a request's headers before its connection is closed.
+    #[serde(default = "default_header_read_timeout_seconds")]
+    pub header_read_timeout_seconds: u64,
+    /// Open connections allowed in total; further connections are closed at once.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// Time a connection may move no bytes either way before it is closed.
+    #[serde(default = "default_idle_timeout_seconds")]
+    pub idle_timeout_seconds: u64,
+    /// Path a reverse proxy serves the shop under, e.g. `/switch`.
+    pub base_path: Option<String>,
+    /// Origin clients reach the server at, e.g. `https://shop.example.com`; index URLs
+    /// become absolute with it.
+    pub external_url: Option<String>,
+    /// Absolute index URLs from the request's host, honouring `X-Forwarded-Host` and
+    /// `X-Forwarded-Proto`, when there is no `external_url`.
+    #[serde(default)]
+    pub absolute_urls: bool,
+    /// PEM certificate chain; the server speaks HTTPS when set with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// Plain-HTTP address that redirects every request to HTTPS, e.g. `0.0.0.0:80`.
+    pub http_redirect_bind: Option<SocketAddr>,
+    /// Certificates obtained automatically instead of `tls_cert` and `tls_key`.
+    pub acme: Option<AcmeConfig>,
+}
+
+/// `[server.acme]`: HTTPS with certificates from Let's Encrypt or another ACME CA.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AcmeConfig {
+    /// Public domain the certificate is for, e.g. `shop.example.com`.
+    pub domain: String,
+    /// Contact address for the CA's expiry and policy notices.
+    pub email: Option<String>,
+    /// ACME directory of the CA; Let's Encrypt when unset.
+    #[serde(default = "default_acme_directory_url")]
+    pub directory_url: String,
+}
+
+fn default_acme_directory_url() -> String {
+    String::from("https://acme-v02.api.letsencrypt.org/directory")
+}
+
+impl ServerConfig {
+    /// `base_path` without its trailing slash; `None` when unset or `/`.
+    pub fn base_path(&self) -> Option<String> {
+        self.base_path.as_deref().and_then(normalize_base_path)
+    }
+
+    /// `external_url` as `scheme://host[:port]`; `None` when unset or invalid.
+    pub fn external_url(&self) -> Option<String> {
+        let url = reqwest::Url::parse(self.external_url.as_deref()?.trim()).ok()?;
+        let plain = matches!(url.scheme(), "http" | "https")
+            && url.host_str().is_some()
+            && url.path() == "/"
+            && url.query().is_none()
+            && url.username().is_empty();
+        plain.then(|| url.origin().ascii_serialization())
+    }
+}
+
+/// A URL path prefix such as `/switch/` as `/switch`. `None` for `/` and for anything
+/// that isn't a plain absolute path.
+pub fn normalize_base_path(raw: &str) -> Option<String> {
+    let path = raw.trim().trim_end_matches('/');
+    let valid = path.starts_with('/')
+        && !path.contains("//")
+        && path
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"/-._~%".contains(&b));
+    valid.then(|| path.to_string())
+}
+
+/// `[rate_limit]`: per-IP request limits, with downloads limited separately from the
+/// index and JSON API, and clients the limits don't apply to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RateLimitConfig {
+    /// Requests per second each client IP may make to the index, artwork, and API; 0
+    /// turns the limit off.
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: u32,
+    /// Requests a client may make at once before the per-second rate applies.
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    /// Like `requests_per_second`, for downloads, which installers fetch in many ranges.
+    #[serde(default = "default_requests_per_second")]
+    pub download_requests_per_second: u32,
+    #[serde(default = "default_burst")]
+    pub download_burst: u32,
+    /// Client addresses or CIDR ranges (e.g. the LAN) that are never throttled.
+    #[serde(default)]
+    pub exempt_networks: Vec<IpNetwork>,
+    /// Users that are never throttled once authenticated (Basic auth, admin session, or
+    /// shop token).
+    #[serde(default)]
+    pub exempt_users: Vec<String>,
+    /// Networks whose clients are never throttled once authenticated as any user.
+    #[serde(default)]
+    pub exempt_authenticated_networks: Vec<IpNetwork>,
+    /// Reverse proxies whose `X-Forwarded-For` / `X-Real-IP` name the client that the
+    /// exempt networks are matched against. Other clients are matched by their own address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNetwork>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: default_requests_per_second(),
+            burst: default_burst(),
+            download_requests_per_second: default_requests_per_second(),
+            download_burst: default_burst(),
+            exempt_networks: Vec::new(),
+            exempt_users: Vec::new(),
+            exempt_authenticated_networks: Vec::new(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn has_exemptions(&self) -> bool {
+        !self.exempt_networks.is_empty()
+            || !self.exempt_users.is_empty()
+            || !self.exempt_authenticated_networks.is_empty()
+    }
+}
+
+fn default_requests_per_second() -> u32 {
+    20
+}
+
+fn default_burst() -> u32 {
+    50
+}
+
+fn default_max_connections_per_ip() -> usize {
+    32
+}
+
+fn default_header_read_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_max_connections() -> usize {
+    1024
+}
+
+fn default_idle_timeout_seconds() -> u64 {
+    120
+}
+
+/// `[hooks]`: HTTP callbacks that let other systems take part in decisions.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HooksConfig {
+    /// URL POSTed the user, title, and client IP before each download; a 2xx answer
+    /// allows it and a 4xx denies it.
+    pub authorize_download_url: Option<String>,
+    /// How long to wait for the hook.
+    #[serde(default = "default_hook_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Allow downloads when the hook fails or times out, instead of denying them.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+fn default_hook_timeout_seconds() -> u64 {
+    5
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            authorize_download_url: None,
+            timeout_seconds: default_hook_timeout_seconds(),
+            fail_open: false,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_connections_per_ip: default_max_connections_per_ip(),
+            header_read_timeout_seconds: default_header_read_timeout_seconds(),
+            max_connections: default_max_connections(),
+            idle_timeout_seconds: default_idle_timeout_seconds(),
+            base_path: None,
+            external_url: None,
+            absolute_urls: false,
+            tls_cert: None,
+            tls_key: None,
+            http_redirect_bind: None,
+            acme: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportSchedule {
+    Weekly,
+    Monthly,
+}
+
+impl ReportSchedule {
+    /// Length of one report period (a month is treated as 30 days).
+    pub fn period(self) -> std::time::Duration {
+        let days = match self {
+            ReportSchedule::Weekly => 7,
+            ReportSchedule::Monthly => 30,
+        };
+        std::time::Duration::from_secs(days * 24 * 3600)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("failed to read config file {path}: {source}")]
@@ -95,22 +758,100 @@ pub enum ConfigError {
     InvalidEnvBool { key: String, value: String },
     #[error("library root {path} does not exist or is not a directory")]
     LibraryRootInvalid { path: String },
+    #[error("library root {inner} is inside library root {outer}; configure only one of them")]
+    NestedLibraryRoot { inner: String, outer: String },
     #[error("auth file {path} does not exist")]
     AuthFileNotFound { path: String },
     #[error("private shop requires --auth-file or auth_file in config")]
     AuthFileRequired,
+    #[error(transparent)]
+    FilenameRule(#[from] FilenameRuleError),
+    #[error("scan.trash_dir must be a single directory name, got {0:?}")]
+    InvalidTrashDir(String),
+    #[error("shop.encrypt requires shop.public_key_file")]
+    ShopKeyRequired,
+    #[error("shop.headers entries must look like \"Name: value\", got {0:?}")]
+    InvalidShopHeader(String),
+    #[error("shop.client_cert_file and shop.client_key_file must be set together")]
+    ShopClientCertIncomplete,
+    #[error("shop.views only applies to \"/\", \"/shop\", and \"/api/shop\", got {0:?}")]
+    InvalidShopViewRoute(String),
+    #[error("server.base_path must be a URL path like \"/switch\", got {0:?}")]
+    InvalidBasePath(String),
+    #[error("server.external_url must be a scheme and host like \"https://shop.example.com\" (put any path in server.base_path), got {0:?}")]
+    InvalidExternalUrl(String),
+    #[error("idle.after must be a duration like \"30m\", got {0:?}")]
+    InvalidIdleAfter(String),
+    #[error("ftp.passive_ports must be a port range like \"50000-50100\", got {0:?}")]
+    InvalidFtpPassivePorts(String),
+    #[error("downloads.{0} must be a rate like \"10MB/s\", got {1:?}")]
+    InvalidDownloadRate(&'static str, String),
+    #[error("uploads.dir must be a folder inside the library like \"incoming\", got {0:?}")]
+    InvalidUploadsDir(String),
+    #[error("hooks.authorize_download_url must be an http or https URL, got {0:?}")]
+    InvalidHookUrl(String),
+    #[error("server.tls_cert and server.tls_key (--tls-cert, --tls-key) must be set together")]
+    TlsIncomplete,
+    #[error(
+        "server.http_redirect_bind requires server.tls_cert and server.tls_key, or server.acme"
+    )]
+    RedirectWithoutTls,
+    #[error("server.acme can't be combined with server.tls_cert and server.tls_key")]
+    AcmeWithTlsFiles,
+    #[error("server.acme.domain must be a DNS name like \"shop.example.com\", got {0:?}")]
+    InvalidAcmeDomain(String),
 }
 
 #[derive(Debug, Default, Deserialize)]
 struct FileConfig {
-    bind: Option<SocketAddr>,
+    bind: Option<BindList>,
     #[serde(alias = "library_folder")]
     library_root: Option<PathBuf>,
+    #[serde(alias = "library_folders")]
+    library_roots: Option<Vec<PathBuf>>,
+    libraries: Option<Vec<LibraryRootEntry>>,
     auth_file: Option<PathBuf>,
     public_shop: Option<bool>,
     insecure_admin_cookie: Option<bool>,
     scan_interval_seconds: Option<u64>,
+    scan_mode: Option<ScanMode>,
+    scan: Option<ScanConfig>,
+    hash_files: Option<bool>,
+    mirror_root: Option<PathBuf>,
+    dedup: Option<bool>,
+    dedup_prefer: Option<FormatPreference>,
     titledb: Option<TitleDbConfig>,
+    artwork: Option<ArtworkConfig>,
+    reports: Option<ReportsConfig>,
+    verify: Option<VerifyConfig>,
+    server: Option<ServerConfig>,
+    rate_limit: Option<RateLimitConfig>,
+    hooks: Option<HooksConfig>,
+    health: Option<HealthConfig>,
+    shop: Option<ShopConfig>,
+    quotas: Option<QuotaConfig>,
+    idle: Option<IdleConfig>,
+    ftp: Option<FtpConfig>,
+    downloads: Option<DownloadsConfig>,
+    uploads: Option<UploadsConfig>,
+    hidden: Option<Vec<String>>,
+}
+
+/// `bind = "..."` or `bind = ["...", "..."]`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BindList {
+    One(BindAddr),
+    Many(Vec<BindAddr>),
+}
+
+impl From<BindList> for Vec<BindAddr> {
+    fn from(list: BindList) -> Self {
+        match list {
+            BindList::One(bind) => vec![bind],
+            BindList::Many(binds) => binds,
+        }
+    }
 }
 
 impl AppConfig {
@@ -121,16 +862,16 @@ impl AppConfig {
         let env_public_shop = read_public_shop_env()?;
         let env_insecure_admin_cookie = read_insecure_admin_cookie_env()?;
 
-        let bind = cli
-            .bind
-            .or(from_file.bind)
-            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 8465)));
-        let library_root = cli
-            .library_root
-            .or(from_file.library_root)
-            .unwrap_or_else(|| PathBuf::from("./library"));
+        let mut binds = cli.bind;
+        if binds.is_empty() {
+            binds = from_file.bind.map(Vec::from).unwrap_or_default();
+        }
+        if binds.is_empty() {
+            binds.push(BindAddr::Tcp(SocketAddr::from(([0, 0, 0, 0], 8465))));
+        }
         let auth_file = cli.auth_file.or(from_file.auth_file);
-        let public_shop = env_public_shop.or(from_file.public_shop).unwrap_or(false);
+        let public_shop = env_public_shop.or(from_file.public_shop).unwrap_or(false)
+            || (cli.demo && auth_file.is_none());
         let insecure_admin_cookie = env_insecure_admin_cookie
             .or(from_file.insecure_admin_cookie)
             .unwrap_or(false);
@@ -139,6 +880,22 @@ impl AppConfig {
             .or(from_file.scan_interval_seconds)
             .unwrap_or(30)
             .max(1);
+        let scan_mode = cli.scan_mode.or(from_file.scan_mode).unwrap_or_default();
+        let hash_files = cli.hash_files || from_file.hash_files.unwrap_or(false);
+        let mirror_root = cli.mirror_root.or(from_file.mirror_root);
+        let dedup = (cli.dedup || from_file.dedup.unwrap_or(false))
+            .then(|| from_file.dedup_prefer.unwrap_or_default());
+        let library_roots = if cli.demo {
+            Vec::new()
+        } else {
+            resolve_library_roots(
+                cli.library_roots,
+                from_file.library_root,
+                from_file.library_roots.unwrap_or_default(),
+                from_file.libraries.unwrap_or_default(),
+                scan_interval_seconds,
+            )
+        };
 
         let data_dir = config_path
             .and_then(|p| p.parent())
@@ -146,31 +903,161 @@ impl AppConfig {
             .unwrap_or_else(|| PathBuf::from("./data"));
 
         let titledb = from_runtime.or(from_file.titledb).unwrap_or_default();
+        let mut server = from_file.server.unwrap_or_default();
+        server.tls_cert = cli.tls_cert.or(server.tls_cert);
+        server.tls_key = cli.tls_key.or(server.tls_key);
 
         let config = Self {
-            bind,
-            library_root: library_root.clone(),
+            binds,
+            library_roots,
             auth_file,
             public_shop,
             insecure_admin_cookie,
             scan_interval_seconds,
+            scan_mode,
+            scan: from_file.scan.unwrap_or_default(),
+            hash_files,
+            mirror_root,
+            dedup,
             data_dir,
             titledb,
+            artwork: from_file.artwork.unwrap_or_default(),
+            reports: from_file.reports.unwrap_or_default(),
+            verify: from_file.verify.unwrap_or_default(),
+            server,
+            rate_limit: from_file.rate_limit.unwrap_or_default(),
+            hooks: from_file.hooks.unwrap_or_default(),
+            health: from_file.health.unwrap_or_default(),
+            shop: from_file.shop.unwrap_or_default(),
+            quotas: from_file.quotas.unwrap_or_default(),
+            idle: from_file.idle.unwrap_or_default(),
+            ftp: from_file.ftp.unwrap_or_default(),
+            downloads: from_file.downloads.unwrap_or_default(),
+            uploads: from_file.uploads.unwrap_or_default(),
+            hidden: from_file.hidden.unwrap_or_default(),
+            demo: cli.demo,
         };
 
-        validate_config(&config)?;
+        // Subcommands don't serve the shop, so they don't need credentials.
+        validate_config(&config, cli.command.is_none())?;
         Ok(config)
     }
 }
 
-fn validate_config(config: &AppConfig) -> Result<(), ConfigError> {
-    if !config.library_root.exists() || !config.library_root.is_dir() {
-        return Err(ConfigError::LibraryRootInvalid {
-            path: config.library_root.display().to_string(),
+fn validate_config(config: &AppConfig, serving: bool) -> Result<(), ConfigError> {
+    config.scan.filename_rules()?;
+    let mut trash = Path::new(&config.scan.trash_dir).components();
+    if !matches!(
+        (trash.next(), trash.next()),
+        (Some(std::path::Component::Normal(_)), None)
+    ) {
+        return Err(ConfigError::InvalidTrashDir(config.scan.trash_dir.clone()));
+    }
+    if config.shop.encrypt && config.shop.public_key_file.is_none() {
+        return Err(ConfigError::ShopKeyRequired);
+    }
+    if let Some(header) = config.shop.headers.iter().find(|header| {
+        header
+            .split_once(':')
+            .map_or(true, |(name, _)| name.trim().is_empty())
+    }) {
+        return Err(ConfigError::InvalidShopHeader(header.clone()));
+    }
+    if config.shop.client_cert_file.is_some() != config.shop.client_key_file.is_some() {
+        return Err(ConfigError::ShopClientCertIncomplete);
+    }
+    if let Some(route) = config
+        .shop
+        .views
+        .keys()
+        .find(|route| !SHOP_VIEW_ROUTES.contains(&route.as_str()))
+    {
+        return Err(ConfigError::InvalidShopViewRoute(route.clone()));
+    }
+    if let Some(base_path) = &config.server.base_path {
+        let is_root = base_path.trim().trim_end_matches('/').is_empty();
+        if !is_root && normalize_base_path(base_path).is_none() {
+            return Err(ConfigError::InvalidBasePath(base_path.clone()));
+        }
+    }
+    if let Some(url) = &config.server.external_url {
+        if config.server.external_url().is_none() {
+            return Err(ConfigError::InvalidExternalUrl(url.clone()));
+        }
+    }
+    if let Some(after) = &config.idle.after {
+        if config.idle.after().is_none() {
+            return Err(ConfigError::InvalidIdleAfter(after.clone()));
+        }
+    }
+    if let Some(ports) = &config.ftp.passive_ports {
+        if config.ftp.passive_ports().is_none() {
+            return Err(ConfigError::InvalidFtpPassivePorts(ports.clone()));
+        }
+    }
+    for (key, rate) in [
+        ("max_rate", &config.downloads.max_rate),
+        ("per_client_rate", &config.downloads.per_client_rate),
+    ] {
+        if let Some(rate) = rate.as_deref().filter(|rate| parse_rate(rate).is_none()) {
+            return Err(ConfigError::InvalidDownloadRate(key, rate.to_string()));
+        }
+    }
+    if let Some(dir) = &config.uploads.dir {
+        if config.uploads.dir().is_none() {
+            return Err(ConfigError::InvalidUploadsDir(dir.clone()));
+        }
+    }
+    if config.server.tls_cert.is_some() != config.server.tls_key.is_some() {
+        return Err(ConfigError::TlsIncomplete);
+    }
+    if let Some(acme) = &config.server.acme {
+        if config.server.tls_cert.is_some() {
+            return Err(ConfigError::AcmeWithTlsFiles);
+        }
+        let domain = acme.domain.as_str();
+        let valid = domain.contains('.')
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && !label.starts_with('-')
+                    && label
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            });
+        if !valid {
+            return Err(ConfigError::InvalidAcmeDomain(acme.domain.clone()));
+        }
+    }
+    let https = config.server.tls_cert.is_some() || config.server.acme.is_some();
+    if config.server.http_redirect_bind.is_some() && !https {
+        return Err(ConfigError::RedirectWithoutTls);
+    }
+    if let Some(url) = &config.hooks.authorize_download_url {
+        if !reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+            return Err(ConfigError::InvalidHookUrl(url.clone()));
+        }
+    }
+
+    for root in &config.library_roots {
+        if !root.path.exists() || !root.path.is_dir() {
+            return Err(ConfigError::LibraryRootInvalid {
+                path: root.path.display().to_string(),
+            });
+        }
+    }
+    let paths = config
+        .library_roots
+        .iter()
+        .map(|root| root.path.clone())
+        .collect::<Vec<_>>();
+    if let Some((inner, outer)) = nested_root(&paths) {
+        return Err(ConfigError::NestedLibraryRoot {
+            inner: inner.display().to_string(),
+            outer: outer.display().to_string(),
         });
     }
 
-    if !config.public_shop {
+    if serving && !config.public_shop {
         let auth_path = config
             .auth_file
             .as_ref()
@@ -185,6 +1072,102 @@ fn validate_config(config: &AppConfig) -> Result<(), ConfigError> {
     Ok(())
 }
 
+/// Merge CLI roots, flat config roots, and `[[libraries]]` entries into one list.
+///
+/// CLI roots replace config roots but still pick up per-root settings from a matching
+/// `[[libraries]]` entry. Roots without explicit settings use the global scan interval
+/// and priority 0. The result is deduplicated and sorted by priority (stable).
+fn resolve_library_roots(
+    cli_roots: Vec<PathBuf>,
+    file_root: Option<PathBuf>,
+    file_roots: Vec<PathBuf>,
+    libraries: Vec<LibraryRootEntry>,
+    default_interval: u64,
+) -> Vec<LibraryRoot> {
+    let from_entry = |entry: &LibraryRootEntry| LibraryRoot {
+        path: entry.path.clone(),
+        scan_interval_seconds: entry
+            .scan_interval_seconds
+            .unwrap_or(default_interval)
+            .max(1),
+        priority: entry.priority.unwrap_or(0),
+        quota_gb: entry.quota_gb,
+    };
+    let from_path = |path: PathBuf| {
+        libraries
+            .iter()
+            .find(|entry| entry.path == path)
+            .map(from_entry)
+            .unwrap_or(LibraryRoot {
+                path,
+                scan_interval_seconds: default_interval,
+                priority: 0,
+                quota_gb: None,
+            })
+    };
+
+    let paths = if cli_roots.is_empty() {
+        file_root
+            .into_iter()
+            .chain(file_roots)
+            .chain(libraries.iter().map(|entry| entry.path.clone()))
+            .collect::<Vec<_>>()
+    } else {
+        cli_roots
+    };
+    let paths = if paths.is_empty() {
+        vec![PathBuf::from("./library")]
+    } else {
+        dedup_roots(paths)
+    };
+    let mut roots = paths.into_iter().map(from_path).collect::<Vec<_>>();
+    roots.sort_by_key(|root| std::cmp::Reverse(root.priority));
+    roots
+}
+
+/// Drop repeated roots while keeping the configured order (first wins on lookups).
+fn dedup_roots(roots: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut out: Vec<PathBuf> = Vec::with_capacity(roots.len());
+    for root in roots {
+        if !out.iter().any(|known| same_directory(known, &root)) {
+            out.push(root);
+        }
+    }
+    out
+}
+
+/// The first root inside another root, with that root. Such a root's files would be
+/// scanned, and listed, under both.
+fn nested_root(roots: &[PathBuf]) -> Option<(&Path, &Path)> {
+    roots.iter().find_map(|inner| {
+        roots
+            .iter()
+            .find(|outer| is_nested_in(inner, outer))
+            .map(|outer| (inner.as_path(), outer.as_path()))
+    })
+}
+
+/// Whether `inner` is a directory strictly inside `outer`, comparing canonical paths
+/// when both exist.
+fn is_nested_in(inner: &Path, outer: &Path) -> bool {
+    let canonical =
+        |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let (inner, outer) = (canonical(inner), canonical(outer));
+    inner != outer && inner.starts_with(&outer)
+}
+
+/// Whether two root paths name the same directory. Canonicalizing resolves letter case
+/// on case-insensitive filesystems (`D:\Games` vs `d:\games`) as well as symlinks.
+fn same_directory(left: &Path, right: &Path) -> bool {
+    if left == right {
+        return true;
+    }
+    match (std::fs::canonicalize(left), std::fs::canonicalize(right)) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => false,
+    }
+}
+
 fn read_file_config(path: Option<&Path>) -> Result<FileConfig, ConfigError> {
     let Some(path) = path else {
         return Ok(FileConfig::default());
@@ -261,7 +1244,114 @@ fn parse_bool_value(key: &str, raw: &str) -> Result<bool, ConfigError> {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_bool_value;
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        dedup_roots, nested_root, parse_bool_value, parse_rate, resolve_library_roots,
+        same_directory, LibraryRootEntry, UploadsConfig,
+    };
+
+    #[test]
+    fn rates_parse_decimal_and_binary_units() {
+        assert_eq!(parse_rate("40MB/s"), Some(40_000_000));
+        assert_eq!(parse_rate("1.5 MiB/s"), Some(1_572_864));
+        assert_eq!(parse_rate("512k"), Some(512_000));
+        assert_eq!(parse_rate("1000000"), Some(1_000_000));
+        for invalid in ["", "fast", "10 Mbit/s", "0MB/s", "-1MB/s"] {
+            assert_eq!(parse_rate(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn uploads_dir_stays_inside_the_library() {
+        let dir = |dir: &str| {
+            UploadsConfig {
+                dir: Some(String::from(dir)),
+            }
+            .dir()
+        };
+        assert_eq!(dir("incoming"), Some(PathBuf::from("incoming")));
+        assert_eq!(dir("uploads/new"), Some(PathBuf::from("uploads/new")));
+        for invalid in ["", "..", "../outside", "/srv/incoming", "./incoming"] {
+            assert_eq!(dir(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn library_roots_dedup_and_keep_configured_order() {
+        let roots = resolve_library_roots(
+            vec![
+                PathBuf::from("/a"),
+                PathBuf::from("/b"),
+                PathBuf::from("/a"),
+            ],
+            None,
+            Vec::new(),
+            Vec::new(),
+            30,
+        );
+        let paths = roots.iter().map(|r| r.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+        assert!(roots.iter().all(|r| r.scan_interval_seconds == 30));
+    }
+
+    #[test]
+    fn dedup_roots_keeps_first_occurrence_order() {
+        let roots = dedup_roots(vec![
+            PathBuf::from("/a"),
+            PathBuf::from("/b"),
+            PathBuf::from("/a"),
+        ]);
+        assert_eq!(roots, vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+    }
+
+    #[test]
+    fn nested_roots_are_found() {
+        let roots = vec![
+            PathBuf::from("/nas-backup"),
+            PathBuf::from("/nas/updates"),
+            PathBuf::from("/nas"),
+        ];
+        assert_eq!(
+            nested_root(&roots),
+            Some((Path::new("/nas/updates"), Path::new("/nas")))
+        );
+        assert_eq!(nested_root(&roots[..1]), None);
+        assert_eq!(
+            nested_root(&[PathBuf::from("/nas"), PathBuf::from("/nas-backup")]),
+            None
+        );
+    }
+
+    #[test]
+    fn library_entries_apply_interval_and_priority() {
+        let roots = resolve_library_roots(
+            Vec::new(),
+            Some(PathBuf::from("/cloud")),
+            Vec::new(),
+            vec![
+                LibraryRootEntry {
+                    path: PathBuf::from("/cloud"),
+                    scan_interval_seconds: Some(3600),
+                    priority: None,
+                    quota_gb: None,
+                },
+                LibraryRootEntry {
+                    path: PathBuf::from("/ssd"),
+                    scan_interval_seconds: Some(60),
+                    priority: Some(10),
+                    quota_gb: Some(500),
+                },
+            ],
+            30,
+        );
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].path, PathBuf::from("/ssd"));
+        assert_eq!(roots[0].scan_interval_seconds, 60);
+        assert_eq!(roots[0].quota_gb, Some(500));
+        assert_eq!(roots[1].path, PathBuf::from("/cloud"));
+        assert_eq!(roots[1].scan_interval_seconds, 3600);
+    }
 
     #[test]
     fn parse_bool_value_accepts_common_true_values() {
@@ -283,4 +1373,37 @@ mod tests {
     fn parse_bool_value_rejects_invalid_values() {
         assert!(parse_bool_value("K", "maybe").is_err());
     }
+
+    #[test]
+    fn equivalent_root_paths_are_the_same_directory() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("sub"))?;
+        assert!(same_directory(
+            dir.path(),
+            &dir.path().join("sub").join("..")
+        ));
+        assert!(!same_directory(dir.path(), &dir.path().join("sub")));
+        assert!(!same_directory(
+            &PathBuf::from("/missing/a"),
+            &PathBuf::from("/missing/b")
+        ));
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_roots_differing_only_by_case_are_merged() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let upper = PathBuf::from(dir.path().to_string_lossy().to_uppercase());
+        assert!(same_directory(dir.path(), &upper));
+        let roots = resolve_library_roots(
+            vec![dir.path().to_path_buf(), upper],
+            None,
+            Vec::new(),
+            Vec::new(),
+            30,
+        );
+        assert_eq!(roots.len(), 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,352 @@
+//! Container metadata: title ID, version, and content type from NSP/XCI file tables.
+//!
+//! NSP/NSZ files are PFS0 archives; XCI/XCZ files hold an HFS0 partition table whose
+//! `secure` partition carries the installable content. Only the (unencrypted) file
+//! tables are read: ticket names (`<rights id>.tik`) carry the title ID, and a
+//! `.cnmt.xml`, when present, carries ID, version, and type. Anything deeper would
+//! require console keys, so callers fall back to filename parsing for missing fields.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::archive::{is_archive, ArchiveEntry};
+use crate::catalog::{classify_title_id, ContentKind};
+use crate::split::ContentReader;
+
+const PFS0_MAGIC: &[u8; 4] = b"PFS0";
+const HFS0_MAGIC: &[u8; 4] = b"HFS0";
+const XCI_MAGIC: &[u8; 4] = b"HEAD";
+const XCI_MAGIC_OFFSET: u64 = 0x100;
+const XCI_ROOT_PARTITION_OFFSET: u64 = 0x130;
+const PFS0_ENTRY_SIZE: u64 = 0x18;
+const HFS0_ENTRY_SIZE: u64 = 0x40;
+/// Upper bounds that reject garbage headers before allocating.
+const MAX_ENTRIES: u32 = 4096;
+const MAX_STRING_TABLE: u32 = 1 << 20;
+const MAX_CNMT_XML: u64 = 1 << 20;
+
+static CNMT_ID_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)<Id>\s*(?:0x)?(?P<id>[0-9a-f]{16})\s*</Id>")
+        .unwrap_or_else(|e| panic!("cnmt id regex must be valid: {e}"))
+});
+
+static CNMT_VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)<Version>\s*(?P<version>\d+)\s*</Version>")
+        .unwrap_or_else(|e| panic!("cnmt version regex must be valid: {e}"))
+});
+
+static CNMT_TYPE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)<Type>\s*(?P<kind>\w+)\s*</Type>")
+        .unwrap_or_else(|e| panic!("cnmt type regex must be valid: {e}"))
+});
+
+/// Metadata recovered from a container's file table. Fields are `None` when the
+/// container does not expose them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerMetadata {
+    pub title_id: Option<String>,
+    pub version: Option<u32>,
+    pub kind: Option<ContentKind>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    /// Absolute offset of the entry's data in the file.
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+/// Read metadata from an NSP/NSZ/XCI/XCZ file. Returns `None` when the file is not a
+/// recognizable container or exposes nothing useful. For a zip archive, reads the title
+/// inside it when it is stored uncompressed.
+pub fn read_container_metadata(path: &Path) -> Option<ContainerMetadata> {
+    if is_archive(path) {
+        let entry = ArchiveEntry::find(path).ok()??;
+        let mut reader = entry.stored_reader(path).ok()??;
+        return read_metadata(&mut reader, Path::new(&entry.name));
+    }
+    let mut file = ContentReader::open(path).ok()?;
+    read_metadata(&mut file, path)
+}
+
+/// Parse `reader` as the container type given by the extension of `name`.
+fn read_metadata<R: Read + Seek>(reader: &mut R, name: &Path) -> Option<ContainerMetadata> {
+    let entries = content_entries(reader, name)?;
+    let metadata = metadata_from_entries(reader, &entries);
+    (metadata != ContainerMetadata::default()).then_some(metadata)
+}
+
+/// The installable files of a container: the PFS0 entries of an NSP/NSZ, or the `secure`
+/// partition of an XCI/XCZ. The container type comes from the extension of `name`.
+pub(crate) fn content_entries<R: Read + Seek>(reader: &mut R, name: &Path) -> Option<Vec<Entry>> {
+    let extension = name.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "nsp" | "nsz" => pfs0_entries(reader),
+        "xci" | "xcz" => read_xci_secure_partition(reader),
+        _ => None,
+    }
+}
+
+/// The file table of an NSP/NSZ.
+pub(crate) fn pfs0_entries<R: Read + Seek>(reader: &mut R) -> Option<Vec<Entry>> {
+    read_partition(reader, 0, PFS0_MAGIC, PFS0_ENTRY_SIZE)
+}
+
+fn metadata_from_entries<R: Read + Seek>(reader: &mut R, entries: &[Entry]) -> ContainerMetadata {
+    let mut metadata = entries
+        .iter()
+        .find(|entry| entry.name.ends_with(".cnmt.xml") && entry.size <= MAX_CNMT_XML)
+        .and_then(|entry| read_entry_string(reader, entry))
+        .map(|xml| parse_cnmt_xml(&xml))
+        .unwrap_or_default();
+
+    if metadata.title_id.is_none() {
+        metadata.title_id = entries
+            .iter()
+            .filter_map(|entry| entry.name.strip_suffix(".tik"))
+            .find_map(title_id_from_rights_id);
+    }
+    if metadata.kind.is_none() && metadata.title_id.is_some() {
+        metadata.kind = Some(classify_title_id(metadata.title_id.as_deref()));
+    }
+    metadata
+}
+
+/// A rights ID is the 16-hex-digit title ID followed by the key generation.
+fn title_id_from_rights_id(rights_id: &str) -> Option<String> {
+    if rights_id.len() != 32 || !rights_id.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(rights_id[..16].to_ascii_uppercase())
+}
+
+fn parse_cnmt_xml(xml: &str) -> ContainerMetadata {
+    let title_id = CNMT_ID_RE
+        .captures(xml)
+        .and_then(|c| c.name("id"))
+        .map(|m| m.as_str().to_ascii_uppercase());
+    let version = CNMT_VERSION_RE
+        .captures(xml)
+        .and_then(|c| c.name("version"))
+        .and_then(|m| m.as_str().parse::<u32>().ok());
+    let kind = CNMT_TYPE_RE
+        .captures(xml)
+        .and_then(|c| c.name("kind"))
+        .and_then(|m| match m.as_str().to_ascii_lowercase().as_str() {
+            "application" => Some(ContentKind::Base),
+            "patch" => Some(ContentKind::Update),
+            "addoncontent" => Some(ContentKind::Dlc),
+            _ => None,
+        });
+    ContainerMetadata {
+        title_id,
+        version,
+        kind,
+    }
+}
+
+fn read_xci_secure_partition<R: Read + Seek>(reader: &mut R) -> Option<Vec<Entry>> {
+    let mut magic = [0u8; 4];
+    reader.seek(SeekFrom::Start(XCI_MAGIC_OFFSET)).ok()?;
+    reader.read_exact(&mut magic).ok()?;
+    if &magic != XCI_MAGIC {
+        return None;
+    }
+    reader
+        .seek(SeekFrom::Start(XCI_ROOT_PARTITION_OFFSET))
+        .ok()?;
+    let root_offset = read_u64(reader)?;
+    let root = read_partition(reader, root_offset, HFS0_MAGIC, HFS0_ENTRY_SIZE)?;
+    let secure = root.iter().find(|entry| entry.name == "secure")?;
+    read_partition(reader, secure.offset, HFS0_MAGIC, HFS0_ENTRY_SIZE)
+}
+
+/// Parse a PFS0/HFS0 header at `base`. Both share the same layout apart from the
+/// magic and the per-entry size: magic, entry count, string table size, reserved,
+/// then entries (`offset: u64, size: u64, name_offset: u32, ...`) and the string table.
+fn read_partition<R: Read + Seek>(
+    reader: &mut R,
+    base: u64,
+    magic: &[u8; 4],
+    entry_size: u64,
+) -> Option<Vec<Entry>> {
+    reader.seek(SeekFrom::Start(base)).ok()?;
+    let mut header = [0u8; 16];
+    reader.read_exact(&mut header).ok()?;
+    if &header[..4] != magic {
+        return None;
+    }
+    let count = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let string_table_size = u32::from_le_bytes(header[8..12].try_into().ok()?);
+    if count > MAX_ENTRIES || string_table_size > MAX_STRING_TABLE {
+        return None;
+    }
+
+    let mut raw_entries = vec![0u8; usize::try_from(u64::from(count) * entry_size).ok()?];
+    reader.read_exact(&mut raw_entries).ok()?;
+    let mut string_table = vec![0u8; usize::try_from(string_table_size).ok()?];
+    reader.read_exact(&mut string_table).ok()?;
+
+    let data_start = base + 16 + u64::from(count) * entry_size + u64::from(string_table_size);
+    raw_entries
+        .chunks_exact(usize::try_from(entry_size).ok()?)
+        .map(|raw| {
+            let offset = u64::from_le_bytes(raw[0..8].try_into().ok()?);
+            let size = u64::from_le_bytes(raw[8..16].try_into().ok()?);
+            let name_offset =
+                usize::try_from(u32::from_le_bytes(raw[16..20].try_into().ok()?)).ok()?;
+            let name_bytes = string_table.get(name_offset..)?;
+            let end = name_bytes
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(name_bytes.len());
+            let name = std::str::from_utf8(&name_bytes[..end]).ok()?.to_string();
+            Some(Entry {
+                name,
+                offset: data_start.checked_add(offset)?,
+                size,
+            })
+        })
+        .collect()
+}
+
+fn read_entry_string<R: Read + Seek>(reader: &mut R, entry: &Entry) -> Option<String> {
+    reader.seek(SeekFrom::Start(entry.offset)).ok()?;
+    let mut buf = vec![0u8; usize::try_from(entry.size).ok()?];
+    reader.read_exact(&mut buf).ok()?;
+    String::from_utf8(buf).ok()
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use crate::catalog::ContentKind;
+
+    use super::{parse_cnmt_xml, read_container_metadata, ContainerMetadata};
+
+    /// Build a PFS0 image holding the given `(name, data)` files.
+    pub(crate) fn build_pfs0(files: &[(&str, &[u8])]) -> Vec<u8> {
+        build_partition(b"PFS0", 0x18, files)
+    }
+
+    fn build_partition(magic: &[u8; 4], entry_size: usize, files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut strings: Vec<u8> = Vec::new();
+        let mut entries = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+        for (name, contents) in files {
+            let mut entry = vec![0u8; entry_size];
+            entry[0..8].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            entry[8..16].copy_from_slice(&(contents.len() as u64).to_le_bytes());
+            entry[16..20].copy_from_slice(&(strings.len() as u32).to_le_bytes());
+            entries.extend(entry);
+            strings.extend(name.as_bytes());
+            strings.push(0);
+            data.extend(*contents);
+        }
+        let mut out = Vec::new();
+        out.extend(magic);
+        out.extend((files.len() as u32).to_le_bytes());
+        out.extend((strings.len() as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend(entries);
+        out.extend(strings);
+        out.extend(data);
+        out
+    }
+
+    #[test]
+    fn nsp_ticket_name_yields_title_id() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("renamed.nsp");
+        std::fs::write(
+            &path,
+            build_pfs0(&[
+                ("0123456789abcdef0123456789abcdef.nca", b"nca"),
+                ("0100abcd12340800000000000000000a.tik", b"tik"),
+            ]),
+        )?;
+
+        let metadata = read_container_metadata(&path);
+        assert_eq!(
+            metadata,
+            Some(ContainerMetadata {
+                title_id: Some(String::from("0100ABCD12340800")),
+                version: None,
+                kind: Some(ContentKind::Update),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nsp_cnmt_xml_yields_version_and_type() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("dlc.nsp");
+        let xml = b"<ContentMeta><Type>AddOnContent</Type><Id>0x0100abcd12341001</Id><Version>131072</Version></ContentMeta>";
+        std::fs::write(&path, build_pfs0(&[("abc.cnmt.xml", xml)]))?;
+
+        let metadata = read_container_metadata(&path);
+        assert_eq!(
+            metadata,
+            Some(ContainerMetadata {
+                title_id: Some(String::from("0100ABCD12341001")),
+                version: Some(131072),
+                kind: Some(ContentKind::Dlc),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn xci_secure_partition_is_read() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("cart.xci");
+        let secure = build_partition(
+            b"HFS0",
+            0x40,
+            &[("0100abcd12340000000000000000000a.tik", b"tik")],
+        );
+        let root = build_partition(b"HFS0", 0x40, &[("secure", &secure)]);
+        let root_offset = 0x200u64;
+        let mut image = vec![0u8; root_offset as usize];
+        image[0x100..0x104].copy_from_slice(b"HEAD");
+        image[0x130..0x138].copy_from_slice(&root_offset.to_le_bytes());
+        image.extend(root);
+        std::fs::write(&path, image)?;
+
+        let metadata = read_container_metadata(&path);
+        assert_eq!(
+            metadata.and_then(|m| m.title_id),
+            Some(String::from("0100ABCD12340000"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn garbage_files_yield_none() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("dummy.nsp");
+        std::fs::write(&path, b"dummy")?;
+        assert_eq!(read_container_metadata(&path), None);
+        Ok(())
+    }
+
+    #[test]
+    fn cnmt_xml_unknown_type_is_ignored() {
+        let parsed = parse_cnmt_xml("<Type>Delta</Type><Version>1</Version>");
+        assert_eq!(parsed.kind, None);
+        assert_eq!(parsed.version, Some(1));
+    }
+}
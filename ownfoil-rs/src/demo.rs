@@ -0,0 +1,177 @@
+//! `--demo`: a made-up library for trying the shop and clients without any real content.
+//!
+//! The files exist only in the catalog. Names, title IDs, versions, and sizes are
+//! generated from a fixed seed, so every run (and every test) sees the same library;
+//! downloads stream zeros of the listed size.
+
+use std::path::PathBuf;
+
+use crate::catalog::{classify_title_id, ContentFile};
+
+/// Root the demo files claim to live under. Never touched on disk.
+pub const ROOT: &str = "<demo>";
+
+const TITLES: u64 = 120;
+const HOMEBREW: u64 = 6;
+
+const ADJECTIVES: [&str; 12] = [
+    "Crystal", "Shadow", "Pixel", "Turbo", "Mystic", "Iron", "Lunar", "Neon", "Wild", "Silent",
+    "Golden", "Hyper",
+];
+const NOUNS: [&str; 12] = [
+    "Quest",
+    "Racer",
+    "Legends",
+    "Tactics",
+    "Island",
+    "Odyssey",
+    "Arena",
+    "Frontier",
+    "Chronicles",
+    "Party",
+    "Kingdom",
+    "Drift",
+];
+
+const MIB: u64 = 1024 * 1024;
+/// Modification times fall in the year before this (2024-01-01).
+const EPOCH: u64 = 1_704_067_200;
+const YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Every file of the demo library: base games with some updates and DLC, plus a few
+/// homebrew titles.
+pub fn files() -> Vec<ContentFile> {
+    let mut rng = Rng(0x5eed_0f0f_1234_abcd);
+    let mut files = Vec::new();
+    for index in 0..TITLES {
+        let name = title_name(index);
+        let base = 0x0100_0000_0A00_0000 + index * 0x2000;
+        let format = match rng.below(8) {
+            0 => "xci",
+            1 | 2 => "nsz",
+            _ => "nsp",
+        };
+        files.push(file(
+            &name,
+            base,
+            0,
+            format,
+            rng.size(256, 16 * 1024),
+            &mut rng,
+        ));
+        let updates = rng.below(4);
+        for update in 1..=updates {
+            let version = u32::try_from(update).unwrap_or(1) << 16;
+            let size = rng.size(20, 2 * 1024);
+            files.push(file(&name, base + 0x800, version, "nsp", size, &mut rng));
+        }
+        for dlc in 1..=rng.below(5) {
+            let size = rng.size(5, 1024);
+            files.push(file(&name, base + 0x1000 + dlc, 0, "nsp", size, &mut rng));
+        }
+    }
+    for index in 0..HOMEBREW {
+        let name = format!("{} Tools", title_name(index + TITLES));
+        let size = rng.size(1, 64);
+        files.push(file(
+            &name,
+            0x0500_0000_0B00_0000 + index * 0x2000,
+            0,
+            "nsp",
+            size,
+            &mut rng,
+        ));
+    }
+    files
+}
+
+/// A distinct two-word title for each index below 144.
+fn title_name(index: u64) -> String {
+    let adjective = ADJECTIVES[(index % 12) as usize];
+    let noun = NOUNS[((index / 12 + index) % 12) as usize];
+    format!("{adjective} {noun}")
+}
+
+fn file(
+    title: &str,
+    title_id: u64,
+    version: u32,
+    format: &str,
+    size: u64,
+    rng: &mut Rng,
+) -> ContentFile {
+    let title_id = format!("{title_id:016X}");
+    let name = format!("{title} [{title_id}][v{version}].{format}");
+    ContentFile {
+        root: PathBuf::from(ROOT),
+        relative_path: PathBuf::from(title).join(&name),
+        name,
+        size,
+        modified: Some(EPOCH - YEAR + rng.below(YEAR)),
+        kind: classify_title_id(Some(&title_id)),
+        title_id: Some(title_id),
+        version: Some(version),
+        hash: None,
+        stale: false,
+        verification: None,
+    }
+}
+
+/// xorshift64: small, seedable, and good enough for made-up sizes.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    /// A size between `min` and `max` MiB, not a whole number of them.
+    fn size(&mut self, min: u64, max: u64) -> u64 {
+        min * MIB + self.below((max - min) * MIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::catalog::{derive_base_title_id, parse_filename_metadata, ContentKind};
+
+    #[test]
+    fn files_are_stable_and_consistent_with_their_names() {
+        let files = files();
+        assert_eq!(files.len(), super::files().len());
+        assert_eq!(files[0].name, super::files()[0].name);
+
+        let paths: HashSet<_> = files.iter().map(|file| &file.relative_path).collect();
+        assert_eq!(paths.len(), files.len());
+        for file in &files {
+            let parsed = parse_filename_metadata(&file.name);
+            let title_id: Option<String> = parsed.title_id.map(|chars| chars.iter().collect());
+            assert_eq!(title_id, file.title_id);
+            assert_eq!(parsed.version, file.version);
+            if matches!(file.kind, ContentKind::Update | ContentKind::Dlc) {
+                let base = derive_base_title_id(file.kind, file.title_id.as_deref());
+                assert!(files
+                    .iter()
+                    .any(|other| other.kind == ContentKind::Base && other.title_id == base));
+            }
+        }
+        for kind in [
+            ContentKind::Base,
+            ContentKind::Update,
+            ContentKind::Dlc,
+            ContentKind::Homebrew,
+        ] {
+            assert!(files.iter().any(|file| file.kind == kind), "{kind:?}");
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Library inventory export: every catalog file with its metadata, as JSON or CSV.
+//!
+//! Served at `GET /api/library/export` and written by `ownfoil-rs export`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::{url_path, ContentFile, ContentKind};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// One exported file. Field order is the CSV column order.
+#[derive(Debug, Serialize)]
+struct ExportRow<'a> {
+    root: String,
+    path: String,
+    title_id: Option<&'a str>,
+    version: Option<u32>,
+    kind: ContentKind,
+    size: u64,
+    modified: Option<u64>,
+    hash: Option<&'a str>,
+    stale: bool,
+}
+
+const CSV_HEADER: &str = "root,path,title_id,version,kind,size,modified,hash,stale";
+
+fn export_rows(files: &[ContentFile]) -> Vec<ExportRow<'_>> {
+    files
+        .iter()
+        .map(|file| ExportRow {
+            root: file.root.display().to_string(),
+            path: url_path(&file.relative_path),
+            title_id: file.title_id.as_deref(),
+            version: file.version,
+            kind: file.kind,
+            size: file.size,
+            modified: file.modified,
+            hash: file.hash.as_deref(),
+            stale: file.stale,
+        })
+        .collect()
+}
+
+/// Render `files` in `format`.
+pub fn render(files: &[ContentFile], format: ExportFormat) -> Result<String, serde_json::Error> {
+    let rows = export_rows(files);
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&rows),
+        ExportFormat::Csv => {
+            let mut out = String::from(CSV_HEADER);
+            out.push('\n');
+            for row in rows {
+                let kind = serde_json::to_value(row.kind)?;
+                let fields = [
+                    row.root,
+                    row.path,
+                    row.title_id.unwrap_or_default().to_string(),
+                    row.version.map(|v| v.to_string()).unwrap_or_default(),
+                    kind.as_str().unwrap_or_default().to_string(),
+                    row.size.to_string(),
+                    row.modified.map(|m| m.to_string()).unwrap_or_default(),
+                    row.hash.unwrap_or_default().to_string(),
+                    row.stale.to_string(),
+                ];
+                let line = fields
+                    .iter()
+                    .map(|field| csv_field(field))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                out.push_str(&line);
+                out.push('\n');
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a delimiter, quote, or line break (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{render, ExportFormat};
+    use crate::catalog::{ContentFile, ContentKind};
+
+    fn file(path: &str) -> ContentFile {
+        ContentFile {
+            modified: Some(1_700_000_000),
+            title_id: Some(String::from("0100ABCD12340000")),
+            version: Some(0),
+            kind: ContentKind::Base,
+            ..ContentFile::fixture(path, 42)
+        }
+    }
+
+    #[test]
+    fn csv_quotes_fields_with_commas_and_quotes() -> Result<()> {
+        let csv = render(&[file("Games/Zelda, \"BotW\".nsp")], ExportFormat::Csv)?;
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("root,path,title_id,version,kind,size,modified,hash,stale")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("/library,\"Games/Zelda, \"\"BotW\"\".nsp\",0100ABCD12340000,0,base,42,1700000000,,false")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_lists_every_file() -> Result<()> {
+        let json = render(&[file("a.nsp"), file("b.nsp")], ExportFormat::Json)?;
+        let parsed: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(parsed.as_array().map(Vec::len), Some(2));
+        assert_eq!(parsed[1]["path"], "b.nsp");
+        assert_eq!(parsed[1]["kind"], "base");
+        Ok(())
+    }
+}
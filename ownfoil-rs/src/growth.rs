@@ -0,0 +1,237 @@
+//! Library growth: daily size samples and a forecast of when the library disk fills up.
+//!
+//! The catalog's total size is sampled every [`SAMPLE_INTERVAL`]; the last sample of each
+//! (UTC) day is kept, for up to [`MAX_SAMPLES`] days, in `<data_dir>/growth.json`. The
+//! growth rate is a least-squares fit over the samples of the last [`TREND_DAYS`] days.
+//! At that rate, the free space on the filesystems holding the library roots gives the
+//! days until they are full.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::library::LibrarySet;
+
+const GROWTH_FILE: &str = "growth.json";
+/// Days of samples kept.
+const MAX_SAMPLES: usize = 365;
+/// Days of samples the growth rate is fitted to.
+pub const TREND_DAYS: u64 = 30;
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(3600);
+const DAY: u64 = 86400;
+
+/// Library size at the end of a day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeSample {
+    /// Days since the Unix epoch.
+    pub day: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GrowthForecast {
+    /// Average daily change of the library size; unset until there are samples from two
+    /// days.
+    pub bytes_per_day: Option<i64>,
+    /// Free space on the filesystems holding the library roots, if known.
+    pub free_bytes: Option<u64>,
+    /// Days until the free space runs out at the current rate; unset when the library
+    /// isn't growing or the free space is unknown.
+    pub days_until_full: Option<u64>,
+    /// Samples the rate is fitted to, oldest first.
+    pub samples: Vec<SizeSample>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GrowthStore {
+    /// Oldest first, one per day.
+    inner: Arc<RwLock<Vec<SizeSample>>>,
+    store_path: PathBuf,
+}
+
+impl GrowthStore {
+    /// Load samples from `data_dir`, starting empty if the file is missing or invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(GROWTH_FILE);
+        let samples = match std::fs::read_to_string(&store_path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+                warn!(path = %store_path.display(), error = %err, "ignoring invalid growth file");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            inner: Arc::new(RwLock::new(samples)),
+            store_path,
+        }
+    }
+
+    /// Record the library size at `now` (Unix seconds), replacing the sample of the same
+    /// day.
+    pub async fn record(&self, now: u64, bytes: u64) -> std::io::Result<()> {
+        let day = now / DAY;
+        let mut samples = self.inner.write().await;
+        match samples.last_mut() {
+            Some(last) if last.day == day && last.bytes == bytes => return Ok(()),
+            Some(last) if last.day == day => last.bytes = bytes,
+            _ => samples.push(SizeSample { day, bytes }),
+        }
+        let excess = samples.len().saturating_sub(MAX_SAMPLES);
+        samples.drain(..excess);
+        self.save(&samples).await
+    }
+
+    /// Growth over the last [`TREND_DAYS`] days before `now`, and when `free_bytes` will
+    /// be used up.
+    pub async fn forecast(&self, now: u64, free_bytes: Option<u64>) -> GrowthForecast {
+        let since = (now / DAY).saturating_sub(TREND_DAYS);
+        let samples = self
+            .inner
+            .read()
+            .await
+            .iter()
+            .filter(|sample| sample.day > since)
+            .copied()
+            .collect::<Vec<_>>();
+        let rate = growth_rate(&samples);
+        let days_until_full = match (rate, free_bytes) {
+            (Some(rate), Some(free)) if rate >= 1.0 => Some((free as f64 / rate) as u64),
+            _ => None,
+        };
+        GrowthForecast {
+            bytes_per_day: rate.map(|rate| rate.round() as i64),
+            free_bytes,
+            days_until_full,
+            samples,
+        }
+    }
+
+    async fn save(&self, samples: &[SizeSample]) -> std::io::Result<()> {
+        let raw = serde_json::to_string(samples).map_err(std::io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp = self.store_path.with_extension("json.tmp");
+        tokio::fs::write(&temp, raw).await?;
+        tokio::fs::rename(&temp, &self.store_path).await
+    }
+}
+
+/// Least-squares slope of size over days, in bytes per day.
+fn growth_rate(samples: &[SizeSample]) -> Option<f64> {
+    let (first, last) = (samples.first()?, samples.last()?);
+    if first.day == last.day {
+        return None;
+    }
+    let n = samples.len() as f64;
+    let mean_day = samples
+        .iter()
+        .map(|s| (s.day - first.day) as f64)
+        .sum::<f64>()
+        / n;
+    let mean_bytes = samples.iter().map(|s| s.bytes as f64).sum::<f64>() / n;
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for sample in samples {
+        let day = (sample.day - first.day) as f64 - mean_day;
+        covariance += day * (sample.bytes as f64 - mean_bytes);
+        variance += day * day;
+    }
+    Some(covariance / variance)
+}
+
+/// Space available to the server on the filesystems holding `roots`, each counted once.
+#[cfg(unix)]
+pub fn free_space(roots: &[PathBuf]) -> Option<u64> {
+    use std::collections::HashSet;
+
+    let mut filesystems = HashSet::new();
+    let mut free = None;
+    for root in roots {
+        match rustix::fs::statvfs(root.as_path()) {
+            Ok(stat) if filesystems.insert(stat.f_fsid) => {
+                *free.get_or_insert(0u64) += stat.f_bavail.saturating_mul(stat.f_frsize);
+            }
+            Ok(_) => {}
+            Err(err) => warn!(root = %root.display(), error = %err, "failed to read free space"),
+        }
+    }
+    free
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_roots: &[PathBuf]) -> Option<u64> {
+    None
+}
+
+/// Spawns a background task that samples the library size every [`SAMPLE_INTERVAL`].
+pub fn spawn_growth_sampler(library: LibrarySet, store: GrowthStore) {
+    tokio::spawn(async move {
+        let catalog = library.catalog();
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let bytes = catalog
+                .read()
+                .await
+                .files()
+                .iter()
+                .map(|file| file.size)
+                .sum();
+            if let Err(err) = store.record(crate::jobs::unix_now(), bytes).await {
+                warn!(error = %err, "failed to save library growth sample");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{GrowthStore, SizeSample, DAY, TREND_DAYS};
+
+    const GB: u64 = 1 << 30;
+
+    #[tokio::test]
+    async fn daily_samples_forecast_when_the_disk_fills() -> Result<()> {
+        let dir = tempdir()?;
+        let store = GrowthStore::load(dir.path());
+        let start = 20_000 * DAY;
+        assert_eq!(store.forecast(start, Some(GB)).await.bytes_per_day, None);
+
+        // Later samples of the same day replace earlier ones.
+        store.record(start, 500 * GB).await?;
+        store.record(start + 3600, 100 * GB).await?;
+        for day in 1..=10 {
+            store.record(start + day * DAY, 100 * GB + day * GB).await?;
+        }
+        let forecast = GrowthStore::load(dir.path())
+            .forecast(start + 10 * DAY, Some(42 * GB))
+            .await;
+        assert_eq!(forecast.samples.len(), 11);
+        assert_eq!(
+            forecast.samples[0],
+            SizeSample {
+                day: 20_000,
+                bytes: 100 * GB
+            }
+        );
+        assert_eq!(forecast.bytes_per_day, Some(GB as i64));
+        assert_eq!(forecast.days_until_full, Some(42));
+
+        // Old samples fall out of the trend; a shrinking library never fills up.
+        let later = start + (10 + TREND_DAYS) * DAY;
+        store.record(later, 80 * GB).await?;
+        store.record(later + DAY, 70 * GB).await?;
+        let forecast = store.forecast(later + DAY, Some(42 * GB)).await;
+        assert_eq!(forecast.samples.len(), 2);
+        assert_eq!(forecast.bytes_per_day, Some(-10 * GB as i64));
+        assert_eq!(forecast.days_until_full, None);
+        Ok(())
+    }
+}
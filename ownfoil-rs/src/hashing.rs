@@ -0,0 +1,304 @@
+//! Content hashing: BLAKE3 digests of library files for duplicate detection, and SHA-256
+//! digests from the same read for clients verifying their downloads.
+//!
+//! Hashing a large library is slow, so it runs as an optional background pass and
+//! results are cached by absolute path (validated by size and modification time) and
+//! persisted to `<data_dir>/hashes.json` so restarts don't rehash everything. Files
+//! cached before SHA-256 or the modification time was recorded are hashed once more, and
+//! files without a modification time are never cached.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::split::ContentReader;
+
+const HASH_FILE: &str = "hashes.json";
+const READ_BUFFER: usize = 1 << 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    path: PathBuf,
+    size: u64,
+    /// Modification time (Unix seconds) of the file the digests were computed for.
+    #[serde(default)]
+    modified: Option<u64>,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+/// Digests of one file, as lowercase hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDigests {
+    pub blake3: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct HashCache {
+    inner: Arc<RwLock<HashMap<PathBuf, CachedHash>>>,
+    store_path: PathBuf,
+}
+
+impl HashCache {
+    /// Load the persisted cache from `data_dir`, starting empty if it is missing or invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(HASH_FILE);
+        let entries = std::fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Vec<CachedHash>>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            inner: Arc::new(RwLock::new(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.path.clone(), entry))
+                    .collect(),
+            )),
+            store_path,
+        }
+    }
+
+    /// Cached BLAKE3 hash for `path`, if it was computed for a file of the same size and
+    /// modification time.
+    pub async fn get(&self, path: &Path, size: u64, modified: Option<u64>) -> Option<String> {
+        self.digests(path, size, modified)
+            .await
+            .map(|digests| digests.blake3)
+    }
+
+    /// Cached digests for `path`, if they were computed for a file of the same size and
+    /// modification time.
+    pub async fn digests(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: Option<u64>,
+    ) -> Option<FileDigests> {
+        modified?;
+        let entries = self.inner.read().await;
+        let entry = entries
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified == modified)?;
+        Some(FileDigests {
+            blake3: entry.hash.clone(),
+            sha256: entry.sha256.clone()?,
+        })
+    }
+
+    /// Hash every `(path, size, modified)` not already cached, then persist the cache.
+    /// Returns the number of newly hashed files. Unreadable files, and files without a
+    /// modification time, are skipped.
+    pub async fn hash_missing(&self, files: Vec<(PathBuf, u64, Option<u64>)>) -> usize {
+        let mut hashed = 0;
+        for (path, size, modified) in files {
+            if modified.is_none() || self.get(&path, size, modified).await.is_some() {
+                continue;
+            }
+            let target = path.clone();
+            match tokio::task::spawn_blocking(move || digest_file(&target)).await {
+                Ok(Ok(digests)) => {
+                    debug!(path = %path.display(), hash = %digests.blake3, "file hashed");
+                    let entry = CachedHash {
+                        path: path.clone(),
+                        size,
+                        modified,
+                        hash: digests.blake3,
+                        sha256: Some(digests.sha256),
+                    };
+                    self.inner.write().await.insert(path, entry);
+                    hashed += 1;
+                }
+                Ok(Err(err)) => warn!(path = %path.display(), error = %err, "file hash failed"),
+                Err(err) => warn!(path = %path.display(), error = %err, "file hash task failed"),
+            }
+        }
+        if hashed > 0 {
+            if let Err(err) = self.save().await {
+                warn!(path = %self.store_path.display(), error = %err, "hash cache save failed");
+            }
+        }
+        hashed
+    }
+
+    /// Path, size, and modification time of every cached row.
+    pub async fn rows(&self) -> Vec<(PathBuf, u64, Option<u64>)> {
+        self.inner
+            .read()
+            .await
+            .values()
+            .map(|entry| (entry.path.clone(), entry.size, entry.modified))
+            .collect()
+    }
+
+    /// Drop the rows for `paths` and persist the cache. Returns how many were removed.
+    pub async fn forget(&self, paths: &[PathBuf]) -> std::io::Result<usize> {
+        let removed = {
+            let mut entries = self.inner.write().await;
+            paths
+                .iter()
+                .filter(|path| entries.remove(*path).is_some())
+                .count()
+        };
+        if removed > 0 {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        let entries = self
+            .inner
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let raw = serde_json::to_string(&entries).map_err(std::io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.store_path, raw).await
+    }
+}
+
+/// BLAKE3 and SHA-256 of a library file, from one read; split dumps hash as their
+/// joined parts.
+pub fn digest_file(path: &Path) -> std::io::Result<FileDigests> {
+    let mut file = ContentReader::open(path)?;
+    let mut blake3 = blake3::Hasher::new();
+    let mut sha256 = Sha256::new();
+    let mut buf = vec![0u8; READ_BUFFER];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        blake3.update(&buf[..read]);
+        sha256.update(&buf[..read]);
+    }
+    Ok(FileDigests {
+        blake3: blake3.finalize().to_hex().to_string(),
+        sha256: sha256
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+    })
+}
+
+/// BLAKE3 hash of a library file; split dumps hash as their joined parts.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = ContentReader::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; READ_BUFFER];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::HashCache;
+
+    const MODIFIED: Option<u64> = Some(1_700_000_000);
+
+    #[tokio::test]
+    async fn identical_contents_share_a_hash_and_persist() -> Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a.nsp");
+        let b = dir.path().join("b.nsp");
+        std::fs::write(&a, b"same")?;
+        std::fs::write(&b, b"same")?;
+
+        let cache = HashCache::load(dir.path());
+        assert_eq!(
+            cache
+                .hash_missing(vec![(a.clone(), 4, MODIFIED), (b.clone(), 4, MODIFIED)])
+                .await,
+            2
+        );
+        assert_eq!(
+            cache.get(&a, 4, MODIFIED).await,
+            cache.get(&b, 4, MODIFIED).await
+        );
+        assert_eq!(cache.get(&a, 5, MODIFIED).await, None);
+
+        let reloaded = HashCache::load(dir.path());
+        assert!(reloaded.get(&a, 4, MODIFIED).await.is_some());
+        assert_eq!(
+            reloaded.hash_missing(vec![(a.clone(), 4, MODIFIED)]).await,
+            0
+        );
+        assert_eq!(
+            reloaded
+                .digests(&a, 4, MODIFIED)
+                .await
+                .map(|digests| digests.sha256),
+            Some(String::from(
+                "0967115f2813a3541eaef77de9d9d5773f1c0c04314b0bbfe4ff3b3b1c55b5d5"
+            ))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_same_size_replacement_is_hashed_again() -> Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a.nsp");
+        std::fs::write(&a, b"same")?;
+        let cache = HashCache::load(dir.path());
+        cache.hash_missing(vec![(a.clone(), 4, MODIFIED)]).await;
+        let before = cache.digests(&a, 4, MODIFIED).await;
+
+        std::fs::write(&a, b"diff")?;
+        let replaced = MODIFIED.map(|seconds| seconds + 60);
+        assert_eq!(cache.digests(&a, 4, replaced).await, None);
+        assert_eq!(cache.hash_missing(vec![(a.clone(), 4, replaced)]).await, 1);
+        let after = cache.digests(&a, 4, replaced).await;
+        assert!(after.is_some());
+        assert_ne!(after, before);
+
+        // Without a modification time there is nothing to validate against.
+        assert_eq!(cache.hash_missing(vec![(a.clone(), 4, None)]).await, 0);
+        assert_eq!(cache.get(&a, 4, None).await, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hashes_cached_without_sha256_or_mtime_are_redone() -> Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a.nsp");
+        std::fs::write(&a, b"same")?;
+        let legacy = serde_json::json!([
+            { "path": a, "size": 4, "hash": "00" },
+            { "path": dir.path().join("b.nsp"), "size": 4, "hash": "00", "sha256": "11" },
+        ]);
+        std::fs::write(dir.path().join("hashes.json"), legacy.to_string())?;
+
+        let cache = HashCache::load(dir.path());
+        assert_eq!(cache.get(&a, 4, MODIFIED).await, None);
+        assert_eq!(
+            cache.get(&dir.path().join("b.nsp"), 4, MODIFIED).await,
+            None
+        );
+        assert_eq!(cache.hash_missing(vec![(a.clone(), 4, MODIFIED)]).await, 1);
+        assert!(cache.digests(&a, 4, MODIFIED).await.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Download authorization hook: an external service that approves or denies each
+//! download, for quotas, parental controls, or schedules without forking the server.
+//!
+//! Before a download starts, `hooks.authorize_download_url` is POSTed a JSON
+//! [`DownloadRequest`]. A 2xx answer allows the download; a 4xx answer denies it with
+//! `403 Forbidden`, passing on the `reason` of a JSON body like `{"reason": "..."}`.
+//! When the hook can't be reached, times out, or answers anything else, the download is
+//! denied unless `hooks.fail_open` is set.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::HooksConfig;
+
+/// What the hook is told about a download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DownloadRequest {
+    /// Authenticated user; unset in public mode.
+    pub user: Option<String>,
+    pub title_id: Option<String>,
+    /// Library-relative path of the file.
+    pub file: String,
+    pub ip: IpAddr,
+    /// The request's `Range` header, so resumed and chunked transfers can be told apart
+    /// from new downloads.
+    pub range: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Deny(Option<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct DenyBody {
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadHook {
+    url: Option<String>,
+    fail_open: bool,
+    client: reqwest::Client,
+}
+
+impl DownloadHook {
+    pub fn new(config: &HooksConfig) -> Self {
+        Self {
+            url: config.authorize_download_url.clone(),
+            fail_open: config.fail_open,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_seconds.max(1)))
+                .user_agent("ownfoil-rs/1.0 (download hook)")
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Ask the hook about `request`; every download is allowed when none is configured.
+    pub async fn authorize(&self, request: &DownloadRequest) -> Verdict {
+        let Some(url) = &self.url else {
+            return Verdict::Allow;
+        };
+        let response = match self.client.post(url).json(request).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(url = %url, error = %err, "download hook failed");
+                return self.on_failure();
+            }
+        };
+        let status = response.status();
+        if status.is_success() {
+            return Verdict::Allow;
+        }
+        if status.is_client_error() {
+            let reason = response
+                .json::<DenyBody>()
+                .await
+                .ok()
+                .and_then(|body| body.reason)
+                .map(|reason| reason.trim().to_string())
+                .filter(|reason| !reason.is_empty());
+            debug!(file = %request.file, status = %status, "download hook denied download");
+            return Verdict::Deny(reason);
+        }
+        warn!(url = %url, status = %status, "download hook answered with an error");
+        self.on_failure()
+    }
+
+    fn on_failure(&self) -> Verdict {
+        if self.fail_open {
+            Verdict::Allow
+        } else {
+            Verdict::Deny(None)
+        }
+    }
+}
@@ -0,0 +1,808 @@
+//! ACME certificates: with `[server.acme]`, the server obtains a certificate for its
+//! domain from Let's Encrypt (or another ACME CA) and renews it, without certbot or a
+//! reverse proxy.
+//!
+//! Control of the domain is proven with the `tls-alpn-01` challenge, answered by the HTTPS
+//! listener itself, so only that port (443) has to be reachable from the internet. The
+//! account key, the certificate, and its key are kept in `<data_dir>/acme`. Certificates
+//! are renewed once they are [`RENEW_AFTER`] old (Let's Encrypt's last 90 days); failed
+//! attempts are retried after [`RETRY_INTERVAL`]. HTTPS handshakes fail until the first
+//! certificate has been issued.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+use super::tls::{self, TlsError, ACME_TLS_ALPN};
+use crate::config::AcmeConfig;
+
+const ACME_DIR: &str = "acme";
+const ACCOUNT_KEY_FILE: &str = "account.pk8";
+/// Age at which a certificate is replaced.
+pub const RENEW_AFTER: Duration = Duration::from_secs(60 * 86400);
+/// How often the certificate's age is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+/// Wait after a failed attempt; keeps well inside the CA's rate limits.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+#[derive(Debug, Error)]
+pub enum AcmeError {
+    #[error("ACME request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("ACME server answered {status}: {detail}")]
+    Server { status: u16, detail: String },
+    #[error("unexpected ACME response: {0}")]
+    Protocol(&'static str),
+    #[error("{0} became {1}")]
+    Invalid(String, String),
+    #[error("{0} is still pending")]
+    Timeout(String),
+    #[error("account key: {0}")]
+    AccountKey(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Certificate(#[from] rcgen::Error),
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// The issued certificate, and the one answering a pending `tls-alpn-01` challenge.
+#[derive(Debug, Default)]
+pub struct AcmeCert {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+    challenge: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl AcmeCert {
+    fn set(slot: &RwLock<Option<Arc<CertifiedKey>>>, key: Option<CertifiedKey>) {
+        *slot.write().unwrap_or_else(|p| p.into_inner()) = key.map(Arc::new);
+    }
+}
+
+impl ResolvesServerCert for AcmeCert {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let validation = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ACME_TLS_ALPN));
+        let slot = if validation {
+            &self.challenge
+        } else {
+            &self.current
+        };
+        slot.read().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AcmeManager {
+    config: AcmeConfig,
+    dir: PathBuf,
+    provider: Arc<CryptoProvider>,
+    cert: Arc<AcmeCert>,
+    client: reqwest::Client,
+}
+
+impl AcmeManager {
+    /// Manager for `config`, presenting the certificate saved in `data_dir`, if any.
+    pub fn new(config: AcmeConfig, data_dir: &Path) -> Self {
+        let manager = Self {
+            dir: data_dir.join(ACME_DIR),
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+            cert: Arc::default(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .user_agent("ownfoil-rs/1.0 (acme)")
+                .build()
+                .unwrap_or_default(),
+            config,
+        };
+        let (cert_path, key_path) = manager.cert_paths();
+        if cert_path.exists() {
+            match tls::read_pair(&cert_path, &key_path, &manager.provider) {
+                Ok(key) => AcmeCert::set(&manager.cert.current, Some(key)),
+                Err(err) => warn!(error = %err, "ignoring saved ACME certificate"),
+            }
+        }
+        manager
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.config.domain
+    }
+
+    /// Acceptor presenting the issued certificate and answering validation handshakes.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, TlsError> {
+        tls::acceptor(
+            Arc::clone(&self.cert) as Arc<dyn ResolvesServerCert>,
+            Arc::clone(&self.provider),
+            true,
+        )
+    }
+
+    /// Whether there is no certificate yet, or it is due for renewal.
+    pub fn needs_certificate(&self) -> bool {
+        let issued = self
+            .cert
+            .current
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .is_some();
+        let age = std::fs::metadata(self.cert_paths().0)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        !issued || age.map_or(true, |age| age >= RENEW_AFTER)
+    }
+
+    /// Order a certificate from the CA, save it, and start presenting it.
+    pub async fn obtain(&self) -> Result<(), AcmeError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let account_key = self.account_key().await?;
+        let mut session =
+            Session::start(&self.client, &self.config.directory_url, account_key).await?;
+        session.register(self.config.email.as_deref()).await?;
+        let (order_url, order) = session.new_order(&self.config.domain).await?;
+        for authorization in &order.authorizations {
+            let result = self.authorize(&mut session, authorization).await;
+            AcmeCert::set(&self.cert.challenge, None);
+            result?;
+        }
+
+        let key = KeyPair::generate()?;
+        let csr =
+            CertificateParams::new(vec![self.config.domain.clone()])?.serialize_request(&key)?;
+        let certificate_url = session
+            .finalize(&order_url, &order.finalize, csr.der())
+            .await?;
+        let chain = session.download(&certificate_url).await?;
+
+        let (cert_path, key_path) = self.cert_paths();
+        write_private(&key_path, key.serialize_pem().as_bytes()).await?;
+        tokio::fs::write(&cert_path, chain).await?;
+        let key = tls::read_pair(&cert_path, &key_path, &self.provider)?;
+        AcmeCert::set(&self.cert.current, Some(key));
+        Ok(())
+    }
+
+    /// Complete the `tls-alpn-01` challenge of the authorization at `url`.
+    async fn authorize(&self, session: &mut Session<'_>, url: &str) -> Result<(), AcmeError> {
+        let authorization: Authorization = session.post(url, None).await?.json().await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == "tls-alpn-01")
+            .ok_or(AcmeError::Protocol(
+                "the CA offered no tls-alpn-01 challenge",
+            ))?;
+        let token = challenge
+            .token
+            .as_deref()
+            .ok_or(AcmeError::Protocol("challenge without a token"))?;
+        let key_authorization = format!("{token}.{}", session.thumbprint());
+        let key = challenge_cert(&self.config.domain, &key_authorization, &self.provider)?;
+        AcmeCert::set(&self.cert.challenge, Some(key));
+        session.post(&challenge.url, Some(&json!({}))).await?;
+        session.poll(url).await.map(drop)
+    }
+
+    async fn account_key(&self) -> Result<EcdsaKeyPair, AcmeError> {
+        let path = self.dir.join(ACCOUNT_KEY_FILE);
+        let rng = SystemRandom::new();
+        let pkcs8 = match tokio::fs::read(&path).await {
+            Ok(pkcs8) => pkcs8,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| AcmeError::AccountKey(String::from("generation failed")))?;
+                write_private(&path, pkcs8.as_ref()).await?;
+                pkcs8.as_ref().to_vec()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|err| AcmeError::AccountKey(err.to_string()))
+    }
+
+    fn cert_paths(&self) -> (PathBuf, PathBuf) {
+        let domain = &self.config.domain;
+        (
+            self.dir.join(format!("{domain}.crt.pem")),
+            self.dir.join(format!("{domain}.key.pem")),
+        )
+    }
+}
+
+/// Spawns a background task that obtains a certificate when there is none and renews it
+/// when it gets old.
+pub fn spawn_acme(manager: AcmeManager) {
+    tokio::spawn(async move {
+        loop {
+            let wait = if manager.needs_certificate() {
+                info!(domain = %manager.domain(), "requesting ACME certificate");
+                match manager.obtain().await {
+                    Ok(()) => {
+                        info!(domain = %manager.domain(), "ACME certificate issued");
+                        CHECK_INTERVAL
+                    }
+                    Err(err) => {
+                        warn!(domain = %manager.domain(), error = %err, "ACME certificate request failed");
+                        RETRY_INTERVAL
+                    }
+                }
+            } else {
+                CHECK_INTERVAL
+            };
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
+
+/// Self-signed certificate for `domain` carrying the `acmeIdentifier` extension that
+/// proves control of it for `tls-alpn-01` (RFC 8737).
+fn challenge_cert(
+    domain: &str,
+    key_authorization: &str,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, AcmeError> {
+    let digest = Sha256::digest(key_authorization.as_bytes());
+    let mut params = CertificateParams::new(vec![domain.to_string()])?;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&digest)];
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    // Not `from_der`: its key check parses the certificate, and webpki rejects the
+    // critical `acmeIdentifier` extension.
+    Ok(CertifiedKey::new(
+        vec![CertificateDer::from(cert.der().to_vec())],
+        provider.key_provider.load_private_key(key)?,
+    ))
+}
+
+/// Write a key readable only by the server's user.
+async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(contents).await?;
+    file.flush().await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+}
+
+/// Signed requests to the CA (RFC 8555) with one account key.
+struct Session<'a> {
+    client: &'a reqwest::Client,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    directory: Directory,
+    nonce: Option<String>,
+    /// Account URL, once registered; requests are signed with the JWK until then.
+    kid: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    async fn start(
+        client: &'a reqwest::Client,
+        directory_url: &str,
+        key: EcdsaKeyPair,
+    ) -> Result<Session<'a>, AcmeError> {
+        let directory = client
+            .get(directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(Self {
+            client,
+            key,
+            rng: SystemRandom::new(),
+            directory,
+            nonce: None,
+            kid: None,
+        })
+    }
+
+    /// Create the account, or look it up if the key is registered already.
+    async fn register(&mut self, email: Option<&str>) -> Result<(), AcmeError> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{email}")]);
+        }
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        self.kid = Some(location(&response)?);
+        Ok(())
+    }
+
+    async fn new_order(&mut self, domain: &str) -> Result<(String, Order), AcmeError> {
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": domain }] });
+        let url = self.directory.new_order.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        let order_url = location(&response)?;
+        Ok((order_url, response.json().await?))
+    }
+
+    /// Submit the CSR and wait for the certificate. Returns its URL.
+    async fn finalize(
+        &mut self,
+        order_url: &str,
+        finalize_url: &str,
+        csr: &[u8],
+    ) -> Result<String, AcmeError> {
+        let payload = json!({ "csr": URL_SAFE_NO_PAD.encode(csr) });
+        let order: Value = self
+            .post(finalize_url, Some(&payload))
+            .await?
+            .json()
+            .await?;
+        let order = match order["status"].as_str() {
+            Some("valid") => order,
+            _ => self.poll(order_url).await?,
+        };
+        let order: Order =
+            serde_json::from_value(order).map_err(|_| AcmeError::Protocol("malformed order"))?;
+        order
+            .certificate
+            .ok_or(AcmeError::Protocol("valid order without a certificate"))
+    }
+
+    /// The PEM certificate chain at `url`.
+    async fn download(&mut self, url: &str) -> Result<String, AcmeError> {
+        Ok(self.post(url, None).await?.text().await?)
+    }
+
+    /// Fetch `url` until its status is no longer `pending` or `processing`; anything but
+    /// `valid` then is an error.
+    async fn poll(&mut self, url: &str) -> Result<Value, AcmeError> {
+        for _ in 0..POLL_ATTEMPTS {
+            let value: Value = self.post(url, None).await?.json().await?;
+            match value["status"].as_str() {
+                Some("valid") => return Ok(value),
+                Some("pending" | "processing") => tokio::time::sleep(POLL_INTERVAL).await,
+                status => {
+                    let status = status.unwrap_or("unknown").to_string();
+                    return Err(AcmeError::Invalid(url.to_string(), status));
+                }
+            }
+        }
+        Err(AcmeError::Timeout(url.to_string()))
+    }
+
+    /// POST a JWS-signed `payload` to `url`, or a POST-as-GET without one. A rejected
+    /// nonce is retried once with a fresh one.
+    async fn post(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<reqwest::Response, AcmeError> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fresh_nonce().await?,
+            };
+            let body = self.sign(url, &nonce, payload)?;
+            let response = self
+                .client
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(body)
+                .send()
+                .await?;
+            self.nonce = replay_nonce(&response);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status().as_u16();
+            let problem: Value = response.json().await.unwrap_or_default();
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            let detail = problem["detail"]
+                .as_str()
+                .unwrap_or("no details")
+                .to_string();
+            return Err(AcmeError::Server { status, detail });
+        }
+    }
+
+    async fn fresh_nonce(&self) -> Result<String, AcmeError> {
+        let response = self
+            .client
+            .head(&self.directory.new_nonce)
+            .send()
+            .await?
+            .error_for_status()?;
+        replay_nonce(&response).ok_or(AcmeError::Protocol("no Replay-Nonce"))
+    }
+
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<String, AcmeError> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload.map_or_else(String::new, |payload| {
+            URL_SAFE_NO_PAD.encode(payload.to_string())
+        });
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| AcmeError::AccountKey(String::from("signing failed")))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        })
+        .to_string())
+    }
+
+    /// Public account key as a JWK: the uncompressed P-256 point split into coordinates.
+    fn coordinates(&self) -> (String, String) {
+        let point = self.key.public_key().as_ref();
+        let (x, y) = point.get(1..).unwrap_or_default().split_at(point.len() / 2);
+        (URL_SAFE_NO_PAD.encode(x), URL_SAFE_NO_PAD.encode(y))
+    }
+
+    fn jwk(&self) -> Value {
+        let (x, y) = self.coordinates();
+        json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y })
+    }
+
+    /// RFC 7638 thumbprint of the account key: the JWK's members in lexicographic order
+    /// without whitespace, hashed.
+    fn thumbprint(&self) -> String {
+        let (x, y) = self.coordinates();
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+        URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes()))
+    }
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn location(response: &reqwest::Response) -> Result<String, AcmeError> {
+    response
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .ok_or(AcmeError::Protocol("no Location header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use axum::extract::State;
+    use axum::http::{StatusCode, Uri};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use rcgen::{BasicConstraints, CertificateParams, CertificateSigningRequestParams, IsCa};
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::CryptoProvider;
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::{DigitallySignedStruct, SignatureScheme};
+    use serde_json::{json, Value};
+    use tempfile::tempdir;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsConnector;
+
+    use super::{AcmeManager, ACME_TLS_ALPN};
+    use crate::config::{AcmeConfig, ServerConfig};
+    use crate::http::serve;
+
+    const DOMAIN: &str = "shop.test";
+
+    /// Just enough of an ACME CA for one order with one `tls-alpn-01` authorization.
+    struct MockCa {
+        base: String,
+        https: SocketAddr,
+        issuer: (rcgen::Certificate, rcgen::KeyPair),
+        nonces: AtomicUsize,
+        validated: AtomicBool,
+        chain: Mutex<Option<String>>,
+    }
+
+    impl MockCa {
+        fn order(&self) -> Value {
+            let base = &self.base;
+            let issued = self
+                .chain
+                .lock()
+                .map(|chain| chain.is_some())
+                .unwrap_or(false);
+            let status = if issued {
+                "valid"
+            } else if self.validated.load(Ordering::SeqCst) {
+                "ready"
+            } else {
+                "pending"
+            };
+            json!({
+                "status": status,
+                "authorizations": [format!("{base}/authz/1")],
+                "finalize": format!("{base}/finalize/1"),
+                "certificate": issued.then(|| format!("{base}/cert/1")),
+            })
+        }
+
+        /// Connect to the server the way a CA validates `tls-alpn-01`.
+        async fn validate(&self) -> bool {
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let Ok(config) = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+                .with_safe_default_protocol_versions()
+            else {
+                return false;
+            };
+            let mut config = config
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAny(provider)))
+                .with_no_client_auth();
+            config.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+            let Ok(stream) = TcpStream::connect(self.https).await else {
+                return false;
+            };
+            let Ok(name) = ServerName::try_from(DOMAIN) else {
+                return false;
+            };
+            match TlsConnector::from(Arc::new(config))
+                .connect(name, stream)
+                .await
+            {
+                Ok(tls) => tls.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN),
+                Err(_) => false,
+            }
+        }
+
+        fn issue(&self, payload: &Value) -> Option<String> {
+            let der = URL_SAFE_NO_PAD.decode(payload["csr"].as_str()?).ok()?;
+            let csr = CertificateSigningRequestParams::from_der(&der.into()).ok()?;
+            let (issuer, issuer_key) = &self.issuer;
+            let cert = csr.signed_by(issuer, issuer_key).ok()?;
+            Some(format!("{}{}", cert.pem(), issuer.pem()))
+        }
+    }
+
+    async fn acme_endpoint(State(ca): State<Arc<MockCa>>, uri: Uri, body: String) -> Response {
+        let base = &ca.base;
+        let nonce = [(
+            "replay-nonce",
+            format!("nonce-{}", ca.nonces.fetch_add(1, Ordering::SeqCst)),
+        )];
+        let payload = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|jws| URL_SAFE_NO_PAD.decode(jws["payload"].as_str()?).ok())
+            .and_then(|raw| serde_json::from_slice::<Value>(&raw).ok())
+            .unwrap_or_default();
+        match uri.path() {
+            "/directory" => Json(json!({
+                "newNonce": format!("{base}/nonce"),
+                "newAccount": format!("{base}/account"),
+                "newOrder": format!("{base}/order"),
+            }))
+            .into_response(),
+            "/nonce" => (nonce, "").into_response(),
+            "/account" => (
+                StatusCode::CREATED,
+                nonce,
+                [("location", format!("{base}/account/1"))],
+                Json(json!({ "status": "valid" })),
+            )
+                .into_response(),
+            "/order" | "/order/1" => (
+                StatusCode::CREATED,
+                nonce,
+                [("location", format!("{base}/order/1"))],
+                Json(ca.order()),
+            )
+                .into_response(),
+            "/authz/1" => {
+                let status = if ca.validated.load(Ordering::SeqCst) {
+                    "valid"
+                } else {
+                    "pending"
+                };
+                let challenges = json!([
+                    { "type": "http-01", "url": format!("{base}/chall/0"), "token": "a" },
+                    { "type": "tls-alpn-01", "url": format!("{base}/chall/1"), "token": "b" },
+                ]);
+                (
+                    nonce,
+                    Json(json!({ "status": status, "challenges": challenges })),
+                )
+                    .into_response()
+            }
+            "/chall/1" => {
+                let valid = ca.validate().await;
+                ca.validated.store(valid, Ordering::SeqCst);
+                (nonce, Json(json!({ "status": "processing" }))).into_response()
+            }
+            "/finalize/1" => match ca.issue(&payload) {
+                Some(chain) => {
+                    if let Ok(mut slot) = ca.chain.lock() {
+                        *slot = Some(chain);
+                    }
+                    (nonce, Json(ca.order())).into_response()
+                }
+                None => (StatusCode::BAD_REQUEST, nonce, "bad CSR").into_response(),
+            },
+            "/cert/1" => {
+                let chain = ca.chain.lock().ok().and_then(|chain| chain.clone());
+                (nonce, chain.unwrap_or_default()).into_response()
+            }
+            _ => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    #[derive(Debug)]
+    struct AcceptAny(Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for AcceptAny {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        // webpki won't parse certificates with the critical `acmeIdentifier` extension.
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    #[tokio::test]
+    async fn obtains_a_certificate_through_tls_alpn_validation() -> Result<()> {
+        let data = tempdir()?;
+        let ca_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", ca_listener.local_addr()?);
+        let config = AcmeConfig {
+            domain: String::from(DOMAIN),
+            email: Some(String::from("admin@shop.test")),
+            directory_url: format!("{base}/directory"),
+        };
+        let manager = AcmeManager::new(config.clone(), data.path());
+        assert!(manager.needs_certificate());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let https = listener.local_addr()?;
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(serve(
+            listener,
+            app,
+            ServerConfig::default(),
+            Some(manager.acceptor()?),
+            std::future::pending(),
+        ));
+
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let issuer_key = rcgen::KeyPair::generate()?;
+        let ca = Arc::new(MockCa {
+            base,
+            https,
+            issuer: (params.self_signed(&issuer_key)?, issuer_key),
+            nonces: AtomicUsize::new(0),
+            validated: AtomicBool::new(false),
+            chain: Mutex::new(None),
+        });
+        let ca_app = Router::new()
+            .fallback(acme_endpoint)
+            .with_state(Arc::clone(&ca));
+        tokio::spawn(async move { axum::serve(ca_listener, ca_app).await });
+
+        manager.obtain().await?;
+        assert!(ca.validated.load(Ordering::SeqCst));
+        assert!(!manager.needs_certificate());
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+        let body = client
+            .get(format!("https://{https}/health"))
+            .send()
+            .await?
+            .text()
+            .await?;
+        assert_eq!(body, "ok");
+
+        // The certificate and account survive a restart.
+        assert!(!AcmeManager::new(config, data.path()).needs_certificate());
+        assert!(data.path().join("acme/account.pk8").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+//! Client activity for idle mode: every request except `/health` probes counts, until its
+//! response body has been sent, so a long download keeps the server awake.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+
+use crate::idle::IdleTracker;
+
+pub async fn track_activity(
+    State(idle): State<IdleTracker>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.uri().path() == "/health" {
+        return next.run(request).await;
+    }
+    let activity = idle.begin();
+    hold(next.run(request).await, activity)
+}
+
+/// `response` holding on to `held` until its body is done or dropped.
+pub fn hold<T: Send + Unpin + 'static>(response: Response, held: T) -> Response {
+    response.map(|body| Body::new(HoldingBody { body, _held: held }))
+}
+
+/// Response body that holds its request's [`Activity`](crate::idle::Activity), or a
+/// download slot, until it
+/// is done or dropped.
+struct HoldingBody<T> {
+    body: Body,
+    _held: T,
+}
+
+impl<T: Send + Unpin + 'static> http_body::Body for HoldingBody<T> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,112 @@
+//! The admin frontend (and the API docs page): the pages, stylesheets and scripts in
+//! `web/`, embedded in the binary (release builds; debug builds read `web/` from disk once, at first use).
+//!
+//! Stylesheets and scripts are served under `/admin/assets/` both by name and by a
+//! content-hashed name such as `admin.3f9c0a1b2c.js`. Pages link to the hashed names, so
+//! browsers keep those for a year and still fetch the new files after an upgrade; the
+//! pages themselves are revalidated on every load.
+
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{Html, IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+use super::etag::{is_fresh, not_modified};
+
+/// Where pages link their stylesheets and scripts, relative to their `<base>`.
+const ASSET_DIR: &str = "admin/assets/";
+
+/// Hex digits of the content hash kept in hashed names.
+const HASH_LEN: usize = 10;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "no-cache";
+
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct Web;
+
+/// A stylesheet, script or other file pages link to.
+#[derive(Debug)]
+struct Asset {
+    name: String,
+    hashed: String,
+    data: Cow<'static, [u8]>,
+    etag: HeaderValue,
+    content_type: HeaderValue,
+}
+
+static ASSETS: LazyLock<Vec<Asset>> = LazyLock::new(|| {
+    Web::iter()
+        .filter(|name| !name.ends_with(".html"))
+        .filter_map(|name| {
+            let file = Web::get(&name)?;
+            let hash: String = file
+                .metadata
+                .sha256_hash()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            let hash = &hash[..HASH_LEN];
+            let hashed = match name.rsplit_once('.') {
+                Some((stem, ext)) => format!("{stem}.{hash}.{ext}"),
+                None => format!("{name}.{hash}"),
+            };
+            let content_type = mime_guess::from_path(name.as_ref()).first_or_octet_stream();
+            Some(Asset {
+                etag: HeaderValue::from_str(&format!("\"{hash}\"")).ok()?,
+                content_type: HeaderValue::from_str(content_type.as_ref()).ok()?,
+                name: name.into_owned(),
+                hashed,
+                data: file.data,
+            })
+        })
+        .collect()
+});
+
+/// The page `name` (e.g. `admin.html`) with its `<base>` set to `base` and its asset links
+/// pointing at the hashed names.
+pub fn page(name: &str, base: &str) -> Option<Response> {
+    let file = Web::get(name)?;
+    let mut html = String::from_utf8_lossy(&file.data).replacen(
+        "<base href=\"/\">",
+        &format!("<base href=\"{base}\">"),
+        1,
+    );
+    for asset in ASSETS.iter() {
+        html = html.replace(
+            &format!("\"{ASSET_DIR}{}\"", asset.name),
+            &format!("\"{ASSET_DIR}{}\"", asset.hashed),
+        );
+    }
+    Some(([(CACHE_CONTROL, REVALIDATE)], Html(html)).into_response())
+}
+
+/// The asset named `file`, by its plain or hashed name.
+pub fn asset(file: &str, headers: &HeaderMap) -> Option<Response> {
+    let asset = ASSETS
+        .iter()
+        .find(|asset| asset.hashed == file || asset.name == file)?;
+    let cache = if asset.hashed == file {
+        IMMUTABLE
+    } else {
+        REVALIDATE
+    };
+    if is_fresh(headers, &asset.etag) {
+        return Some(not_modified(asset.etag.clone()));
+    }
+    Some(
+        (
+            [
+                (CONTENT_TYPE, asset.content_type.clone()),
+                (ETAG, asset.etag.clone()),
+                (CACHE_CONTROL, HeaderValue::from_static(cache)),
+            ],
+            asset.data.clone(),
+        )
+            .into_response(),
+    )
+}
@@ -1,29 +1,122 @@
+use std::convert::Infallible;
+
+use axum::extract::{FromRequestParts, Request, State};
 use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
 use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::CookieJar;
 use base64::prelude::*;
 use tracing::{debug, warn};
 
 use super::error::ApiError;
+use super::handlers::SESSION_COOKIE;
 use super::state::AppState;
+use crate::auth::UserAccess;
+
+/// What the request's user may see and do. Per-user shop URLs set it as a request
+/// extension, since their inner routes run with auth open. Without a known user it
+/// allows everything: auth is off, or `ensure_authorized` turns the request away.
+#[derive(Debug, Clone, Default)]
+pub struct Access(pub UserAccess);
+
+impl FromRequestParts<AppState> for Access {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(access) = parts.extensions.get::<Access>() {
+            return Ok(access.clone());
+        }
+        let user = request_user(state, &parts.headers);
+        Ok(Access(access_for(state, user.as_deref())))
+    }
+}
+
+/// Access of `user`; everything for no user.
+pub fn access_for(state: &AppState, user: Option<&str>) -> UserAccess {
+    user.and_then(|user| state.auth.load().access(user).cloned())
+        .unwrap_or_default()
+}
+
+/// User of a session, while they are still in the auth file and not disabled.
+pub fn session_user(state: &AppState, token: &str) -> Option<String> {
+    state
+        .sessions
+        .get(token)
+        .filter(|user| state.auth.load().has_user(user))
+}
+
+/// User of the request's `Authorization: Bearer` API token, while they are still in the
+/// auth file and not disabled.
+pub fn token_user(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    let token = extract_bearer_token(headers)?;
+    state
+        .api_tokens
+        .user_for(&token)
+        .filter(|user| state.auth.load().has_user(user))
+}
+
+/// User a request is signed in as: its session's, its API token's, or its Basic auth
+/// user when the password is right.
+pub fn request_user(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    let auth = state.auth.load();
+    if !auth.is_enabled() {
+        return None;
+    }
+    CookieJar::from_headers(headers)
+        .get(SESSION_COOKIE)
+        .and_then(|cookie| session_user(state, cookie.value()))
+        .or_else(|| token_user(state, headers))
+        .or_else(|| {
+            extract_basic_auth(headers)
+                .filter(|(username, password)| auth.is_authorized(username, password))
+                .map(|(username, _)| username)
+        })
+}
+
+/// Turn away signed-in users whose role isn't `admin`. Requests without valid
+/// credentials go on to the handler, which asks for them.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(user) = request_user(&state, request.headers()) {
+        if !access_for(&state, Some(&user)).is_admin() {
+            debug!(user, "admin route refused: user is not an admin");
+            return ApiError::Forbidden.into_response();
+        }
+    }
+    next.run(request).await
+}
 
 pub fn ensure_authorized(
     state: &AppState,
     headers: &HeaderMap,
     session_token: Option<&str>,
 ) -> Result<(), ApiError> {
-    if !state.auth.is_enabled() {
+    if !state.auth.load().is_enabled() {
         return Ok(());
     }
 
     if let Some(token) = session_token {
-        if state.sessions.get(token).is_some() {
+        if session_user(state, token).is_some() {
             debug!("authorized request using session");
             return Ok(());
         }
     }
 
+    if token_user(state, headers).is_some() {
+        debug!("authorized request using API token");
+        return Ok(());
+    }
+
     if let Some((username, password)) = extract_basic_auth(headers) {
-        if state.auth.is_authorized(&username, &password) {
+        if state.auth.load().is_authorized(&username, &password) {
             debug!("authorized request using basic auth");
             return Ok(());
         }
@@ -48,3 +141,17 @@ pub fn extract_basic_auth(headers: &HeaderMap) -> Option<(String, String)> {
     let (username, password) = credentials.split_once(':')?;
     Some((username.to_string(), password.to_string()))
 }
+
+/// The token of an `Authorization: Bearer <token>` header.
+pub fn extract_bearer_token(headers: &HeaderMap) -> Option<String> {
+    let raw = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())?;
+    let mut parts = raw.split_whitespace();
+    let scheme = parts.next()?;
+    let token = parts.next()?;
+    if !scheme.eq_ignore_ascii_case("bearer") || parts.next().is_some() {
+        return None;
+    }
+    Some(token.to_string())
+}
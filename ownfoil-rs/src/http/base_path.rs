@@ -0,0 +1,129 @@
+//! Public URLs: serving under a URL prefix behind a reverse proxy, e.g.
+//! `https://host/switch/`, and absolute URLs in the index.
+//!
+//! The prefix is the `X-Forwarded-Prefix` a proxy sends, or else `[server] base_path`.
+//! Requests are routed with or without the configured prefix, so the proxy may strip it or
+//! pass it on. The server-relative URLs in JSON responses (`/api/get_game/...`,
+//! `/download/...`) and redirects get the prefix prepended. With `[server] external_url`
+//! or `absolute_urls`, JSON URLs also get the public origin, so clients that need fully
+//! qualified URLs get `https://shop.example.com/api/get_game/1`. Handlers that build
+//! absolute URLs or encrypt their JSON add these themselves via [`url_prefix`],
+//! [`public_origin`], and [`link_prefix`].
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header::LOCATION;
+use axum::http::{HeaderMap, HeaderValue, Request, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+
+use super::responses::prefix_json_response;
+use super::state::AppState;
+use crate::config::normalize_base_path;
+
+/// Prefix the client reached the server under; empty when there is none.
+pub fn url_prefix(state: &AppState, headers: &HeaderMap) -> String {
+    headers
+        .get("x-forwarded-prefix")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .and_then(normalize_base_path)
+        .or_else(|| state.base_path.as_deref().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// `scheme://host` clients reach the server at: `[server] external_url`, or else what the
+/// request shows, honouring reverse-proxy headers.
+pub fn public_origin(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(external_url) = &state.external_url {
+        return external_url.to_string();
+    }
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let scheme = header("x-forwarded-proto")
+        .filter(|scheme| matches!(*scheme, "http" | "https"))
+        .unwrap_or("http");
+    let host = header("x-forwarded-host")
+        .or_else(|| header("host"))
+        .unwrap_or("localhost");
+    format!("{scheme}://{host}")
+}
+
+/// What server-relative URLs in the index get prepended: the URL prefix, after the
+/// public origin when URLs are absolute.
+pub fn link_prefix(state: &AppState, headers: &HeaderMap) -> String {
+    let prefix = url_prefix(state, headers);
+    if state.absolute_urls || state.external_url.is_some() {
+        format!("{}{prefix}", public_origin(state, headers))
+    } else {
+        prefix
+    }
+}
+
+/// Route requests under `base_path` as if it weren't there. Wraps the whole router so
+/// the rewritten path is what gets matched.
+pub fn strip_base_path(app: Router, base_path: &str) -> Router {
+    Router::new()
+        .fallback_service(app)
+        .layer(middleware::from_fn_with_state(
+            base_path.to_string(),
+            strip_request_prefix,
+        ))
+}
+
+async fn strip_request_prefix(
+    State(base_path): State<String>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let rest = match path.strip_prefix(base_path.as_str()) {
+        Some("") => Some("/"),
+        Some(rest) if rest.starts_with('/') => Some(rest),
+        _ => None,
+    };
+    if let Some(rest) = rest {
+        let uri = match request.uri().query() {
+            Some(query) => format!("{rest}?{query}"),
+            None => rest.to_string(),
+        };
+        if let Ok(uri) = uri.parse::<Uri>() {
+            *request.uri_mut() = uri;
+        }
+    }
+    next.run(request).await
+}
+
+/// Prepend the link prefix to the URLs in JSON responses, and the URL prefix to redirect
+/// targets.
+pub async fn prefix_responses(
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let prefix = url_prefix(&state, request.headers());
+    let links = link_prefix(&state, request.headers());
+    let mut response = next.run(request).await;
+    if links.is_empty() {
+        return response;
+    }
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .filter(|location| location.starts_with('/') && !location.starts_with("//"))
+        .and_then(|location| HeaderValue::from_str(&format!("{prefix}{location}")).ok());
+    if let Some(location) = location {
+        response.headers_mut().insert(LOCATION, location);
+    }
+    prefix_json_response(response, &links)
+        .await
+        .unwrap_or_else(IntoResponse::into_response)
+}
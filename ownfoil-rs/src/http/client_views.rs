@@ -0,0 +1,220 @@
+//! Per-client views of the shop index routes.
+//!
+//! Tinfoil, CyberFoil, and browsers all open `/` but want different shapes: Tinfoil its
+//! index (encrypted when `[shop] encrypt` is set), CyberFoil plain JSON, and a person a
+//! readable library page. `[shop.views]` overrides the choice per route and client.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::http::header::{ACCEPT, USER_AGENT};
+use axum::http::HeaderMap;
+
+use crate::catalog::{derive_base_title_id, ContentFile, ContentKind};
+use crate::config::{ShopView, ShopViewRule};
+use crate::remote::format_size;
+
+use super::responses::is_tinfoil;
+use super::webdav::escape;
+
+/// Who is asking for the shop index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShopClient {
+    Tinfoil,
+    CyberFoil,
+    Browser,
+    Other,
+}
+
+impl ShopClient {
+    pub fn detect(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+        };
+        if header(USER_AGENT).contains("cyberfoil") {
+            ShopClient::CyberFoil
+        } else if is_tinfoil(headers) {
+            ShopClient::Tinfoil
+        } else if header(ACCEPT).contains("text/html") {
+            ShopClient::Browser
+        } else {
+            ShopClient::Other
+        }
+    }
+}
+
+/// `[shop.views]` on top of each route's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct ShopViews(BTreeMap<String, ShopViewRule>);
+
+impl ShopViews {
+    pub fn new(rules: BTreeMap<String, ShopViewRule>) -> Self {
+        Self(rules)
+    }
+
+    /// What `client` gets from `route` (one of [`crate::config::SHOP_VIEW_ROUTES`]).
+    pub fn view(&self, route: &str, client: ShopClient) -> ShopView {
+        let rule = self.0.get(route).copied().unwrap_or_default();
+        let configured = match client {
+            ShopClient::Tinfoil => rule.tinfoil,
+            ShopClient::CyberFoil => rule.cyberfoil,
+            ShopClient::Browser => rule.browser,
+            ShopClient::Other => rule.other,
+        };
+        configured.unwrap_or(match (route, client) {
+            ("/", ShopClient::Tinfoil) => ShopView::Tinfoil,
+            ("/", ShopClient::Browser) => ShopView::Html,
+            _ => ShopView::Json,
+        })
+    }
+
+    /// Whether clients can get different views of `route`, so caches must tell them apart.
+    pub fn varies(&self, route: &str) -> bool {
+        let tinfoil = self.view(route, ShopClient::Tinfoil);
+        [
+            ShopClient::CyberFoil,
+            ShopClient::Browser,
+            ShopClient::Other,
+        ]
+        .into_iter()
+        .any(|client| self.view(route, client) != tinfoil)
+    }
+}
+
+/// HTML library page listing `files` with download links under `prefix`. `names` are
+/// TitleDB names by title ID; files without one show their file name. Checked files, or
+/// a whole title, download as one ZIP.
+pub fn library_page(
+    message: &str,
+    files: &[(usize, &ContentFile)],
+    names: &HashMap<String, String>,
+    prefix: &str,
+) -> String {
+    let mut rows: Vec<_> = files
+        .iter()
+        .map(|(file_id, file)| {
+            let name = file
+                .title_id
+                .as_ref()
+                .and_then(|title_id| names.get(title_id))
+                .unwrap_or(&file.name);
+            (name.as_str(), *file_id, *file)
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        a.0.to_lowercase()
+            .cmp(&b.0.to_lowercase())
+            .then(a.2.version.cmp(&b.2.version))
+    });
+    let total: u64 = files.iter().map(|(_, file)| file.size).sum();
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>Library</title><style>\
+         body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse}}\
+         th,td{{padding:.3em .8em;text-align:left}}tr:nth-child(even){{background:#f3f3f3}}\
+         td.size{{text-align:right}}</style></head><body>\n\
+         <h1>Library</h1>\n<p>{}</p>\n<p>{} files, {}</p>\n\
+         <form method=\"post\" action=\"{}\">\n\
+         <p><button type=\"submit\">Download selected as ZIP</button></p>\n<table>\n\
+         <tr><th></th><th>Title</th><th>Title ID</th><th>Version</th><th>Type</th>\
+         <th>Size</th><th>File</th></tr>\n",
+        escape(message),
+        files.len(),
+        format_size(total),
+        escape(&format!("{prefix}/api/download/batch")),
+    );
+    for (name, file_id, file) in rows {
+        html.push_str(&format!(
+            "<tr><td><input type=\"checkbox\" name=\"ids\" value=\"{file_id}\"></td>\
+             <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"size\">{}</td>\
+             <td><a href=\"{}\" download=\"{}\">{}</a></td></tr>\n",
+            escape(name),
+            title_cell(file),
+            file.version
+                .map_or_else(|| String::from("-"), |version| format!("v{version}")),
+            kind_label(file.kind),
+            format_size(file.size),
+            escape(&format!("{prefix}/api/get_game/{file_id}")),
+            escape(&file.name),
+            escape(&file.name),
+        ));
+    }
+    html.push_str("</table>\n</form>\n</body></html>\n");
+    html
+}
+
+/// The file's title ID, with a button zipping its base title with all updates and DLC.
+fn title_cell(file: &ContentFile) -> String {
+    let Some(title_id) = file.title_id.as_deref() else {
+        return String::from("-");
+    };
+    match derive_base_title_id(file.kind, Some(title_id)) {
+        Some(base) => format!(
+            "{} <button type=\"submit\" name=\"title_ids\" value=\"{}\" \
+             title=\"Download the title with its updates and DLC as ZIP\">ZIP</button>",
+            escape(title_id),
+            escape(&base),
+        ),
+        None => escape(title_id),
+    }
+}
+
+fn kind_label(kind: ContentKind) -> &'static str {
+    match kind {
+        ContentKind::Base => "Game",
+        ContentKind::Update => "Update",
+        ContentKind::Dlc => "DLC",
+        ContentKind::Homebrew => "Homebrew",
+        ContentKind::Unknown => "-",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn detects_clients_and_applies_overrides() {
+        let tinfoil = headers(&[("theme", "0"), ("uid", "0")]);
+        let cyberfoil = headers(&[
+            ("theme", "0"),
+            ("uid", "0"),
+            ("user-agent", "CyberFoil/1.3"),
+        ]);
+        let browser = headers(&[("accept", "text/html,application/xhtml+xml")]);
+        assert_eq!(ShopClient::detect(&tinfoil), ShopClient::Tinfoil);
+        assert_eq!(ShopClient::detect(&cyberfoil), ShopClient::CyberFoil);
+        assert_eq!(ShopClient::detect(&browser), ShopClient::Browser);
+        assert_eq!(ShopClient::detect(&HeaderMap::new()), ShopClient::Other);
+
+        let views = ShopViews::new(BTreeMap::from([(
+            String::from("/shop"),
+            ShopViewRule {
+                browser: Some(ShopView::Html),
+                ..ShopViewRule::default()
+            },
+        )]));
+        assert_eq!(views.view("/", ShopClient::Tinfoil), ShopView::Tinfoil);
+        assert_eq!(views.view("/", ShopClient::Browser), ShopView::Html);
+        assert_eq!(views.view("/", ShopClient::CyberFoil), ShopView::Json);
+        assert_eq!(views.view("/shop", ShopClient::Browser), ShopView::Html);
+        assert_eq!(views.view("/api/shop", ShopClient::Browser), ShopView::Json);
+        assert!(views.varies("/shop"));
+        assert!(!views.varies("/api/shop"));
+    }
+}
@@ -0,0 +1,46 @@
+//! Response compression for JSON.
+//!
+//! Catalog and shop indexes of large libraries run to megabytes of JSON, which compresses
+//! well; clients asking for it via `Accept-Encoding` get gzip, deflate, or zstd. Only
+//! JSON bodies are compressed. Downloads are excluded explicitly: they are already
+//! compressed containers, clients resume them with byte ranges that must address the
+//! file itself, and their length is what the client checks.
+
+use axum::http::header::{ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE};
+use axum::http::Response;
+use tower_http::compression::predicate::{Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Smallest body worth compressing.
+const MIN_SIZE: u16 = 256;
+
+/// Compress JSON responses that aren't byte-range capable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOnly;
+
+impl Predicate for JsonOnly {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: axum::body::HttpBody,
+    {
+        let headers = response.headers();
+        if headers.contains_key(ACCEPT_RANGES) || headers.contains_key(CONTENT_RANGE) {
+            return false;
+        }
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| {
+                let mime = mime.trim().to_ascii_lowercase();
+                mime == "application/json" || mime.ends_with("+json")
+            })
+    }
+}
+
+/// gzip, deflate, and zstd for JSON responses above [`MIN_SIZE`].
+pub fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .no_br()
+        .compress_when(SizeAbove::new(MIN_SIZE).and(JsonOnly))
+}
@@ -0,0 +1,168 @@
+//! Virtual directories: the shop as a browsable tree for clients that follow Tinfoil's
+//! `directories` field instead of showing one flat list.
+//!
+//! `/api/directories/` offers three views, each answering with `files` and
+//! `directories` like the shop root:
+//! - `kind/<base|update|dlc|homebrew|unknown>/`
+//! - `letter/<0-9|A…Z|other>/`, by the first character of the file name
+//! - `folder/<path>/`, mirroring the library's folders (merged across roots)
+
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::responses::{build_shop_root_files, ShopRootFile};
+use crate::catalog::{ContentFile, ContentKind};
+
+const PREFIX: &str = "/api/directories/";
+const VIEWS: [&str; 3] = ["kind", "letter", "folder"];
+const KINDS: [(ContentKind, &str); 5] = [
+    (ContentKind::Base, "base"),
+    (ContentKind::Update, "update"),
+    (ContentKind::Dlc, "dlc"),
+    (ContentKind::Homebrew, "homebrew"),
+    (ContentKind::Unknown, "unknown"),
+];
+
+/// One level of the tree.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DirectoryResponse {
+    pub success: &'static str,
+    pub files: Vec<ShopRootFile>,
+    /// URLs of the subdirectories.
+    pub directories: Vec<String>,
+}
+
+/// URLs of the top-level views.
+pub fn root_directories() -> Vec<String> {
+    VIEWS.iter().map(|view| directory_url(&[view])).collect()
+}
+
+/// The directory at `path` (e.g. `kind/dlc`) over the listed `files`, or `None` if there
+/// is no such directory.
+pub fn build_directory(path: &str, files: &[(usize, &ContentFile)]) -> Option<DirectoryResponse> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let (directories, selected) = match segments.as_slice() {
+        [] => (root_directories(), Vec::new()),
+        ["kind"] => {
+            let present: Vec<_> = KINDS
+                .iter()
+                .filter(|(kind, _)| files.iter().any(|(_, file)| file.kind == *kind))
+                .map(|(_, slug)| directory_url(&["kind", slug]))
+                .collect();
+            (present, Vec::new())
+        }
+        ["kind", slug] => {
+            let (kind, _) = KINDS.iter().find(|(_, known)| known == slug)?;
+            (Vec::new(), select(files, |file| file.kind == *kind))
+        }
+        ["letter"] => {
+            let letters: BTreeSet<String> = files
+                .iter()
+                .map(|(_, file)| letter_of(&file.name))
+                .collect();
+            let mut letters: Vec<_> = letters.into_iter().collect();
+            // `0-9` sorts first already; keep `other` last.
+            letters.sort_by_key(|letter| letter == "other");
+            let urls = letters
+                .iter()
+                .map(|letter| directory_url(&["letter", letter]))
+                .collect();
+            (urls, Vec::new())
+        }
+        ["letter", letter] => {
+            let selected = select(files, |file| letter_of(&file.name) == *letter);
+            if selected.is_empty() {
+                return None;
+            }
+            (Vec::new(), selected)
+        }
+        ["folder", folder @ ..] => {
+            let mut subfolders = BTreeSet::new();
+            let mut selected = Vec::new();
+            for &(id, file) in files {
+                let parts = folder_parts(&file.relative_path);
+                if parts.len() < folder.len() || parts.iter().zip(folder).any(|(a, b)| a != b) {
+                    continue;
+                }
+                match parts.into_iter().nth(folder.len()) {
+                    None => selected.push((id, file)),
+                    Some(next) => {
+                        subfolders.insert(next);
+                    }
+                }
+            }
+            if !folder.is_empty() && subfolders.is_empty() && selected.is_empty() {
+                return None;
+            }
+            let urls = subfolders
+                .into_iter()
+                .map(|next| {
+                    let mut path = vec!["folder"];
+                    path.extend_from_slice(folder);
+                    path.push(&next);
+                    directory_url(&path)
+                })
+                .collect();
+            sort_by_name(&mut selected);
+            (urls, selected)
+        }
+        _ => return None,
+    };
+    Some(DirectoryResponse {
+        success: "ok",
+        files: build_shop_root_files(&selected),
+        directories,
+    })
+}
+
+fn select<'a>(
+    files: &[(usize, &'a ContentFile)],
+    keep: impl Fn(&ContentFile) -> bool,
+) -> Vec<(usize, &'a ContentFile)> {
+    let mut selected: Vec<_> = files
+        .iter()
+        .filter(|(_, file)| keep(file))
+        .copied()
+        .collect();
+    sort_by_name(&mut selected);
+    selected
+}
+
+fn sort_by_name(files: &mut [(usize, &ContentFile)]) {
+    files.sort_by_key(|(_, file)| file.name.to_lowercase());
+}
+
+/// `0-9`, an uppercase ASCII letter, or `other`.
+fn letter_of(name: &str) -> String {
+    match name.chars().next() {
+        Some(ch) if ch.is_ascii_digit() => String::from("0-9"),
+        Some(ch) if ch.is_ascii_alphabetic() => ch.to_ascii_uppercase().to_string(),
+        _ => String::from("other"),
+    }
+}
+
+/// Folders of a file's relative path, without the file itself.
+pub(super) fn folder_parts(relative_path: &Path) -> Vec<String> {
+    let mut parts: Vec<String> = relative_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    parts.pop();
+    parts
+}
+
+fn directory_url(segments: &[&str]) -> String {
+    let mut url = String::from(PREFIX);
+    for segment in segments {
+        url.extend(utf8_percent_encode(segment, NON_ALPHANUMERIC));
+        url.push('/');
+    }
+    url
+}
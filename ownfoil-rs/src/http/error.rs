@@ -1,32 +1,128 @@
-use axum::http::header::WWW_AUTHENTICATE;
-use axum::http::{HeaderValue, StatusCode};
+use std::time::Duration;
+
+use axum::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
+use axum::http::{HeaderName, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use serde::Serialize;
 use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::annotations::AnnotationError;
+use crate::quota::QuotaUsage;
+use crate::search::SearchError;
+
+/// Bytes of an upload already on the server, so a client knows where to continue.
+pub const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+
+/// JSON body of every error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+}
 
 #[derive(Debug, Error)]
 pub enum ApiError {
     #[error("unauthorized")]
     Unauthorized,
+    #[error("not allowed for this account")]
+    Forbidden,
     #[error("title not found")]
     TitleNotFound,
     #[error("invalid path")]
     InvalidPath,
+    #[error("invalid title id")]
+    InvalidTitleId,
+    #[error("expected a PNG, JPEG, WebP, or SVG image")]
+    UnsupportedImage,
     #[error("not found")]
     NotFound,
     #[error("range not satisfiable")]
     InvalidRange,
+    #[error("not an NSZ file")]
+    NotNsz,
+    #[error("file has not been hashed yet")]
+    NotHashed,
+    #[error("chunk size must be from 1 MiB to 4 GiB, like \"64MB\"")]
+    InvalidChunkSize,
+    #[error("replication is not configured")]
+    ReplicationDisabled,
+    #[error("a job is already running for this title")]
+    JobInProgress,
+    #[error("settings were changed elsewhere; reload and try again")]
+    SettingsConflict,
+    #[error("{0}")]
+    InvalidImport(&'static str),
+    #[error("{0}")]
+    InvalidAnnouncement(&'static str),
+    #[error("{0}")]
+    InvalidBatch(&'static str),
+    #[error("{0}")]
+    InvalidUpload(&'static str),
+    #[error("{0}")]
+    InvalidUser(String),
+    #[error("the change would leave no enabled admin")]
+    LastAdmin,
+    #[error("upload continues at byte {0}")]
+    UploadOffset(u64),
+    #[error("another upload of this file is running")]
+    UploadInProgress,
+    #[error("a file already exists at that path")]
+    AlreadyExists,
+    #[error("the {0} is full")]
+    QuotaExceeded(QuotaUsage),
+    #[error("invalid search: {0}")]
+    InvalidSearch(#[from] SearchError),
+    #[error("invalid annotations: {0}")]
+    InvalidAnnotations(#[from] AnnotationError),
+    #[error("failed to import blocklist: {0}")]
+    BlocklistImport(String),
+    #[error("download denied{}", .0.as_deref().map(|reason| format!(": {reason}")).unwrap_or_default())]
+    DownloadDenied(Option<String>),
+    #[error("too many requests; retry in {} seconds", .0.as_secs().max(1))]
+    RateLimited(Duration),
+    #[error("too many downloads at once; at most {0} per client")]
+    TooManyDownloads(usize),
     #[error("internal server error")]
     Internal,
 }
 
+/// `Retry-After` for clients out of download slots; when one frees up isn't known.
+const DOWNLOAD_RETRY_AFTER: Duration = Duration::from_secs(10);
+
 impl ApiError {
     pub fn status(&self) -> StatusCode {
         match self {
             ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
-            ApiError::TitleNotFound | ApiError::NotFound => StatusCode::NOT_FOUND,
-            ApiError::InvalidPath => StatusCode::BAD_REQUEST,
+            ApiError::TitleNotFound
+            | ApiError::NotFound
+            | ApiError::NotNsz
+            | ApiError::NotHashed => StatusCode::NOT_FOUND,
+            ApiError::InvalidPath | ApiError::InvalidTitleId | ApiError::ReplicationDisabled => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::UnsupportedImage => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::InvalidImport(_)
+            | ApiError::InvalidAnnouncement(_)
+            | ApiError::InvalidBatch(_)
+            | ApiError::InvalidUpload(_)
+            | ApiError::InvalidUser(_)
+            | ApiError::InvalidChunkSize
+            | ApiError::InvalidSearch(_)
+            | ApiError::InvalidAnnotations(_) => StatusCode::BAD_REQUEST,
+            ApiError::JobInProgress
+            | ApiError::SettingsConflict
+            | ApiError::AlreadyExists
+            | ApiError::LastAdmin
+            | ApiError::UploadOffset(_)
+            | ApiError::UploadInProgress => StatusCode::CONFLICT,
             ApiError::InvalidRange => StatusCode::RANGE_NOT_SATISFIABLE,
+            ApiError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::BlocklistImport(_) => StatusCode::BAD_GATEWAY,
+            ApiError::DownloadDenied(_) | ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::RateLimited(_) | ApiError::TooManyDownloads(_) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -34,11 +130,22 @@ impl ApiError {
     pub fn is_unauthorized(&self) -> bool {
         matches!(self, ApiError::Unauthorized)
     }
+
+    /// How long a client should wait before trying again, if it should.
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RateLimited(wait) => Some(*wait),
+            ApiError::TooManyDownloads(_) => Some(DOWNLOAD_RETRY_AFTER),
+            _ => None,
+        }
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let body = Json(serde_json::json!({ "error": self.to_string() }));
+        let body = Json(ErrorBody {
+            error: self.to_string(),
+        });
         let mut response = (self.status(), body).into_response();
         if self.is_unauthorized() {
             response.headers_mut().insert(
@@ -46,6 +153,16 @@ impl IntoResponse for ApiError {
                 HeaderValue::from_static("Basic realm=\"ownfoil-rs\""),
             );
         }
+        if let Some(wait) = self.retry_after() {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(wait.as_secs().max(1)));
+        }
+        if let ApiError::UploadOffset(offset) = self {
+            response
+                .headers_mut()
+                .insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+        }
         response
     }
 }
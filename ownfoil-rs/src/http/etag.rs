@@ -0,0 +1,61 @@
+//! Conditional requests for the index endpoints.
+//!
+//! Tinfoil and CyberFoil fetch the whole index every time the shop is opened. Index
+//! responses carry an `ETag` hashed from the JSON body, so it changes whenever anything
+//! in the listing does (catalog, overrides, TitleDB, query parameters), and a request
+//! whose `If-None-Match` names the current tag gets `304 Not Modified` without a body.
+//! Tags are weak: the same JSON may go out compressed or encrypted.
+
+use axum::http::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use tracing::warn;
+
+use super::error::ApiError;
+
+/// Weak tag of a response body.
+pub fn etag(body: &[u8]) -> HeaderValue {
+    let hash = blake3::hash(body).to_hex();
+    HeaderValue::from_str(&format!("W/\"{}\"", &hash[..32]))
+        .unwrap_or_else(|_| HeaderValue::from_static("W/\"\""))
+}
+
+/// Whether the client's `If-None-Match` names `etag` (or is `*`).
+pub fn is_fresh(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let current = opaque(etag);
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == current)
+}
+
+pub fn not_modified(etag: HeaderValue) -> Response {
+    (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response()
+}
+
+/// `value` as JSON with its `ETag`, or `304 Not Modified` if the client has it already.
+pub fn json_response<T: Serialize>(headers: &HeaderMap, value: &T) -> Result<Response, ApiError> {
+    let body = serde_json::to_vec(value).map_err(|err| {
+        warn!(error = %err, "failed to serialize response");
+        ApiError::Internal
+    })?;
+    let etag = etag(&body);
+    if is_fresh(headers, &etag) {
+        return Ok(not_modified(etag));
+    }
+    Ok((
+        [
+            (CONTENT_TYPE, HeaderValue::from_static("application/json")),
+            (ETAG, etag),
+        ],
+        body,
+    )
+        .into_response())
+}
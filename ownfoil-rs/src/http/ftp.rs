@@ -0,0 +1,691 @@
+//! Read-only FTP, for DBI (which installs over FTP rather than Tinfoil's HTTP protocol)
+//! and FTP clients generally.
+//!
+//! The tree is the one `/dav/` serves: the library's folders with roots merged, and dedup
+//! and hidden titles applied. Logins take the shop's credentials (any name and password
+//! in public mode), and downloads go through the same checks, hook and statistics as
+//! `/download/`. Only passive mode is offered (`PASV`, `EPSV`), on `[ftp] passive_ports`
+//! when set, and `REST` resumes a download from an offset. With HTTPS set up, `AUTH TLS`
+//! secures the control connection and `PROT P` the data connections (explicit FTPS).
+//! Commands that would change the library are refused.
+
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use futures_util::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use super::auth::access_for;
+use super::handlers::{ftp_download, listed_files};
+use super::state::AppState;
+use super::webdav::{self, DavEntry, DavResource};
+use crate::config::FtpConfig;
+
+/// Longest command line read.
+const MAX_LINE: u64 = 4096;
+
+/// A control connection silent for this long is closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long a client has to open the data connection of a transfer.
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pause before answering a failed login, to slow down password guessing.
+const LOGIN_DELAY: Duration = Duration::from_secs(1);
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A control or data connection, plain or inside TLS.
+type Connection = Box<dyn Stream>;
+
+type Control = BufReader<Connection>;
+
+/// Serve FTP on `listener` until `shutdown` resolves. With `tls`, clients may switch to
+/// FTPS; sessions still open at shutdown are dropped with the process.
+pub async fn serve_ftp(
+    listener: TcpListener,
+    state: AppState,
+    config: FtpConfig,
+    tls: Option<TlsAcceptor>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let config = Arc::new(config);
+    let tls = tls.map(ftps_acceptor);
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "failed to accept FTP connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        let local = match stream.local_addr() {
+            Ok(local) => local.ip().to_canonical(),
+            Err(err) => {
+                debug!(peer = %peer, error = %err, "FTP connection closed before setup");
+                continue;
+            }
+        };
+        let session = Session {
+            state: state.clone(),
+            config: Arc::clone(&config),
+            tls: tls.clone(),
+            peer,
+            local,
+            user: None,
+            login: None,
+            cwd: String::from("/"),
+            passive: None,
+            rest: 0,
+            secure: false,
+            protect_data: false,
+        };
+        tokio::spawn(async move {
+            if let Err(err) = session.run(stream).await {
+                debug!(peer = %peer, error = %err, "FTP session ended with error");
+            }
+        });
+    }
+}
+
+/// `tls` without the HTTP ALPN protocols, which FTP clients don't offer.
+fn ftps_acceptor(tls: TlsAcceptor) -> TlsAcceptor {
+    let mut config = rustls::ServerConfig::clone(tls.config());
+    config.alpn_protocols.clear();
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// A logged-in client.
+#[derive(Debug)]
+struct Login {
+    username: String,
+    /// Sent along with each download, which is authorized like an HTTP one; `None` in
+    /// public mode.
+    password: Option<String>,
+}
+
+struct Session {
+    state: AppState,
+    config: Arc<FtpConfig>,
+    tls: Option<TlsAcceptor>,
+    peer: SocketAddr,
+    /// Address the control connection came in on.
+    local: IpAddr,
+    /// Name from `USER`, waiting for `PASS`.
+    user: Option<String>,
+    login: Option<Login>,
+    /// Working directory, `/`-separated from `/`.
+    cwd: String,
+    /// Listener of the next data connection, after `PASV` or `EPSV`.
+    passive: Option<TcpListener>,
+    /// Offset the next `RETR` starts at.
+    rest: u64,
+    /// Whether the control connection is inside TLS.
+    secure: bool,
+    /// Whether data connections are inside TLS (`PROT P`).
+    protect_data: bool,
+}
+
+impl Session {
+    async fn run(mut self, stream: TcpStream) -> io::Result<()> {
+        let mut control: Control = BufReader::new(Box::new(stream));
+        reply(&mut control, 220, "ownfoil-rs FTP ready").await?;
+        loop {
+            let mut line = String::new();
+            let mut limited = (&mut control).take(MAX_LINE);
+            match tokio::time::timeout(IDLE_TIMEOUT, limited.read_line(&mut line)).await {
+                Ok(Ok(0)) => return Ok(()),
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(err),
+                Err(_) => {
+                    reply(&mut control, 421, "Idle timeout; closing connection").await?;
+                    return Ok(());
+                }
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let command = command.to_ascii_uppercase();
+            self.state.idle.touch();
+            match command.as_str() {
+                "QUIT" => {
+                    reply(&mut control, 221, "Goodbye").await?;
+                    return Ok(());
+                }
+                "AUTH" => control = self.auth(control, arg).await?,
+                _ => self.command(&mut control, &command, arg).await?,
+            }
+        }
+    }
+
+    /// `AUTH TLS`: continue the control connection inside TLS.
+    async fn auth(&mut self, mut control: Control, arg: &str) -> io::Result<Control> {
+        let Some(acceptor) = self.tls.clone() else {
+            reply(&mut control, 502, "TLS is not set up on this server").await?;
+            return Ok(control);
+        };
+        if !matches!(arg.to_ascii_uppercase().as_str(), "TLS" | "TLS-C" | "SSL") {
+            reply(&mut control, 504, "Only AUTH TLS is supported").await?;
+            return Ok(control);
+        }
+        if self.secure {
+            reply(&mut control, 503, "Already using TLS").await?;
+            return Ok(control);
+        }
+        reply(&mut control, 234, "Starting TLS").await?;
+        let stream = acceptor.accept(control.into_inner()).await?;
+        self.secure = true;
+        // Credentials sent in the clear before don't count.
+        self.user = None;
+        self.login = None;
+        Ok(BufReader::new(Box::new(stream)))
+    }
+
+    async fn command(&mut self, control: &mut Control, command: &str, arg: &str) -> io::Result<()> {
+        match command {
+            "USER" => {
+                self.user = Some(arg.to_string());
+                self.login = None;
+                return reply(control, 331, "Password required").await;
+            }
+            "PASS" => return self.pass(control, arg).await,
+            "SYST" => return reply(control, 215, "UNIX Type: L8").await,
+            "FEAT" => return self.feat(control).await,
+            "NOOP" => return reply(control, 200, "OK").await,
+            "OPTS" | "CLNT" => return reply(control, 200, "OK").await,
+            "PBSZ" => return reply(control, 200, "PBSZ=0").await,
+            "PROT" => return self.prot(control, arg).await,
+            _ => {}
+        }
+        if self.login.is_none() {
+            return reply(control, 530, "Log in with USER and PASS").await;
+        }
+        match command {
+            "PWD" | "XPWD" => {
+                let cwd = self.cwd.replace('"', "\"\"");
+                reply(control, 257, &format!("\"{cwd}\" is the current directory")).await
+            }
+            "CWD" | "XCWD" => self.cwd(control, arg).await,
+            "CDUP" | "XCUP" => self.cwd(control, "..").await,
+            "TYPE" => match arg.to_ascii_uppercase().as_str() {
+                "I" | "L 8" | "A" | "A N" => reply(control, 200, "Type set").await,
+                _ => reply(control, 504, "Unsupported type").await,
+            },
+            "MODE" if arg.eq_ignore_ascii_case("S") => reply(control, 200, "Mode set").await,
+            "STRU" if arg.eq_ignore_ascii_case("F") => reply(control, 200, "Structure set").await,
+            "MODE" | "STRU" => reply(control, 504, "Unsupported parameter").await,
+            "PASV" => self.pasv(control).await,
+            "EPSV" => self.epsv(control, arg).await,
+            "PORT" | "EPRT" => reply(control, 502, "Active mode is not supported; use PASV").await,
+            "SIZE" => self.size(control, arg).await,
+            "MDTM" => self.mdtm(control, arg).await,
+            "MLST" => self.mlst(control, arg).await,
+            "LIST" | "NLST" | "MLSD" => self.list(control, command, arg).await,
+            "REST" => match arg.trim().parse() {
+                Ok(offset) => {
+                    self.rest = offset;
+                    reply(control, 350, &format!("Restarting at {offset}")).await
+                }
+                Err(_) => reply(control, 501, "REST needs a byte offset").await,
+            },
+            "RETR" => self.retr(control, arg).await,
+            "ABOR" => reply(control, 226, "No transfer to abort").await,
+            "ALLO" => reply(control, 202, "No storage allocation needed").await,
+            "STOR" | "STOU" | "APPE" | "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR"
+            | "RNTO" | "SITE" => reply(control, 550, "This server is read-only").await,
+            _ => reply(control, 502, "Command not implemented").await,
+        }
+    }
+
+    async fn pass(&mut self, control: &mut Control, password: &str) -> io::Result<()> {
+        let Some(username) = self.user.take() else {
+            return reply(control, 503, "Send USER first").await;
+        };
+        let auth = self.state.auth.load();
+        if !auth.is_enabled() {
+            self.login = Some(Login {
+                username,
+                password: None,
+            });
+            return reply(control, 230, "Logged in").await;
+        }
+        if auth.is_authorized(&username, password) {
+            info!(peer = %self.peer, user = %username, tls = self.secure, "FTP login");
+            self.login = Some(Login {
+                username,
+                password: Some(password.to_string()),
+            });
+            return reply(control, 230, "Logged in").await;
+        }
+        warn!(peer = %self.peer, user = %username, "FTP login failed");
+        tokio::time::sleep(LOGIN_DELAY).await;
+        reply(control, 530, "Login incorrect").await
+    }
+
+    async fn feat(&self, control: &mut Control) -> io::Result<()> {
+        let mut features = vec![
+            " EPSV",
+            " MDTM",
+            " MLST type*;size*;modify*;",
+            " PASV",
+            " REST STREAM",
+            " SIZE",
+            " UTF8",
+        ];
+        if self.tls.is_some() {
+            features.extend([" AUTH TLS", " PBSZ", " PROT"]);
+        }
+        let lines: Vec<String> = features.into_iter().map(String::from).collect();
+        multiline(control, 211, "Features:", &lines, "End").await
+    }
+
+    async fn prot(&mut self, control: &mut Control, arg: &str) -> io::Result<()> {
+        if !self.secure {
+            return reply(control, 503, "Send AUTH TLS first").await;
+        }
+        match arg.to_ascii_uppercase().as_str() {
+            "P" => {
+                self.protect_data = true;
+                reply(control, 200, "Data connections use TLS").await
+            }
+            "C" => {
+                self.protect_data = false;
+                reply(control, 200, "Data connections are plain").await
+            }
+            _ => reply(control, 504, "Unsupported protection level").await,
+        }
+    }
+
+    async fn cwd(&mut self, control: &mut Control, arg: &str) -> io::Result<()> {
+        let target = resolve(&self.cwd, arg);
+        let is_folder = self
+            .with_resource(&target, |resource| {
+                matches!(resource, Some(DavResource::Folder(..)))
+            })
+            .await;
+        if !is_folder {
+            return reply(control, 550, "No such directory").await;
+        }
+        self.cwd = target;
+        reply(control, 250, "Directory changed").await
+    }
+
+    async fn pasv(&mut self, control: &mut Control) -> io::Result<()> {
+        let IpAddr::V4(address) = self.config.passive_address.unwrap_or(self.local) else {
+            return reply(control, 425, "PASV needs IPv4; use EPSV").await;
+        };
+        let Some(port) = self.listen().await else {
+            return reply(control, 425, "No free passive port").await;
+        };
+        let [a, b, c, d] = address.octets();
+        let [high, low] = port.to_be_bytes();
+        let text = format!("Entering Passive Mode ({a},{b},{c},{d},{high},{low})");
+        reply(control, 227, &text).await
+    }
+
+    async fn epsv(&mut self, control: &mut Control, arg: &str) -> io::Result<()> {
+        if arg.eq_ignore_ascii_case("ALL") {
+            return reply(control, 200, "Only EPSV from now on").await;
+        }
+        let Some(port) = self.listen().await else {
+            return reply(control, 425, "No free passive port").await;
+        };
+        let text = format!("Entering Extended Passive Mode (|||{port}|)");
+        reply(control, 229, &text).await
+    }
+
+    /// Listen for the next data connection; its port, or `None` when none is free.
+    async fn listen(&mut self) -> Option<u16> {
+        self.passive = None;
+        let ports = self.config.passive_ports().unwrap_or(0..=0);
+        for port in ports {
+            match TcpListener::bind((self.local, port)).await {
+                Ok(listener) => {
+                    let port = listener.local_addr().ok()?.port();
+                    self.passive = Some(listener);
+                    return Some(port);
+                }
+                Err(err) => debug!(port, error = %err, "passive port unavailable"),
+            }
+        }
+        warn!(peer = %self.peer, "no free FTP passive port");
+        None
+    }
+
+    /// The data connection opened after `PASV` or `EPSV`, from the control connection's
+    /// host, inside TLS after `PROT P`.
+    async fn data_connection(&mut self) -> Option<Connection> {
+        let listener = self.passive.take()?;
+        let accept = async {
+            loop {
+                let (stream, peer) = listener.accept().await.ok()?;
+                if peer.ip().to_canonical() == self.peer.ip().to_canonical() {
+                    return Some(stream);
+                }
+                warn!(peer = %peer, client = %self.peer, "refused data connection from another host");
+            }
+        };
+        let stream = tokio::time::timeout(DATA_TIMEOUT, accept).await.ok()??;
+        if !self.protect_data {
+            return Some(Box::new(stream));
+        }
+        let acceptor = self.tls.clone()?;
+        match tokio::time::timeout(DATA_TIMEOUT, acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => Some(Box::new(stream)),
+            Ok(Err(err)) => {
+                debug!(peer = %self.peer, error = %err, "data connection TLS handshake failed");
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    async fn size(&self, control: &mut Control, arg: &str) -> io::Result<()> {
+        let size = self
+            .with_resource(&resolve(&self.cwd, arg), |resource| match resource {
+                Some(DavResource::File(file)) => Some(file.size),
+                _ => None,
+            })
+            .await;
+        match size {
+            Some(size) => reply(control, 213, &size.to_string()).await,
+            None => reply(control, 550, "No such file").await,
+        }
+    }
+
+    async fn mdtm(&self, control: &mut Control, arg: &str) -> io::Result<()> {
+        let modified = self
+            .with_resource(&resolve(&self.cwd, arg), |resource| match resource {
+                Some(DavResource::File(file)) => Some(file.modified.unwrap_or_default()),
+                _ => None,
+            })
+            .await;
+        match modified {
+            Some(modified) => reply(control, 213, &mdtm(modified)).await,
+            None => reply(control, 550, "No such file").await,
+        }
+    }
+
+    async fn mlst(&self, control: &mut Control, arg: &str) -> io::Result<()> {
+        let path = resolve(&self.cwd, arg);
+        let facts = self
+            .with_resource(&path, |resource| match resource? {
+                DavResource::Folder(..) => Some(format!(" type=dir; {path}")),
+                DavResource::File(file) => Some(format!(
+                    " type=file;size={};modify={}; {path}",
+                    file.size,
+                    mdtm(file.modified.unwrap_or_default())
+                )),
+            })
+            .await;
+        match facts {
+            Some(facts) => multiline(control, 250, "Listing", &[facts], "End").await,
+            None => reply(control, 550, "No such file or directory").await,
+        }
+    }
+
+    async fn list(&mut self, control: &mut Control, command: &str, arg: &str) -> io::Result<()> {
+        // `LIST -la` and the like: options are ignored.
+        let arg = arg
+            .split(' ')
+            .filter(|part| !part.starts_with('-'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let line = |entry: &DavEntry<'_>| match command {
+            "NLST" => entry_name(entry).to_string(),
+            "MLSD" => mlsd_line(entry),
+            _ => list_line(entry),
+        };
+        let listing = self
+            .with_resource(&resolve(&self.cwd, &arg), |resource| match resource? {
+                DavResource::Folder(_, entries) => {
+                    Some(entries.iter().map(line).collect::<Vec<_>>())
+                }
+                DavResource::File(_) if command == "MLSD" => None,
+                DavResource::File(file) => Some(vec![line(&DavEntry::File(file))]),
+            })
+            .await;
+        let Some(listing) = listing else {
+            return reply(control, 550, "No such directory").await;
+        };
+        let body: String = listing.iter().map(|line| format!("{line}\r\n")).collect();
+        self.send(control, "Here comes the listing", body.as_bytes())
+            .await
+    }
+
+    async fn retr(&mut self, control: &mut Control, arg: &str) -> io::Result<()> {
+        let offset = std::mem::take(&mut self.rest);
+        let path = resolve(&self.cwd, arg);
+        let file = self
+            .with_resource(&path, |resource| match resource {
+                Some(DavResource::File(file)) => Some((file.relative_path.clone(), file.size)),
+                _ => None,
+            })
+            .await;
+        let Some((relative, size)) = file else {
+            return reply(control, 550, "No such file").await;
+        };
+        if offset > size {
+            return reply(control, 554, "Restart offset is past the end of the file").await;
+        }
+        if offset == size {
+            return self.send(control, "Nothing left to send", &[]).await;
+        }
+        self.retr_file(control, &path, relative, offset).await
+    }
+
+    async fn retr_file(
+        &mut self,
+        control: &mut Control,
+        path: &str,
+        relative: PathBuf,
+        offset: u64,
+    ) -> io::Result<()> {
+        let login = self.login.as_ref().and_then(|login| {
+            login
+                .password
+                .clone()
+                .map(|password| (login.username.clone(), password))
+        });
+        let response =
+            match ftp_download(self.state.clone(), self.peer, login, &relative, offset).await {
+                Ok(response) if response.status().is_success() => response,
+                Ok(response) => {
+                    debug!(path, status = %response.status(), "FTP download refused");
+                    return reply(control, 550, "File unavailable").await;
+                }
+                Err(err) => {
+                    debug!(path, error = ?err, "FTP download refused");
+                    return reply(control, 550, "File unavailable").await;
+                }
+            };
+        reply(control, 150, "Opening BINARY mode data connection").await?;
+        let Some(mut data) = self.data_connection().await else {
+            return reply(control, 425, "Can't open data connection").await;
+        };
+        let _activity = self.state.idle.begin();
+        let mut body = response.into_body().into_data_stream();
+        let sent = async {
+            while let Some(chunk) = body.next().await {
+                data.write_all(&chunk.map_err(io::Error::other)?).await?;
+            }
+            data.shutdown().await
+        };
+        match sent.await {
+            Ok(()) => reply(control, 226, "Transfer complete").await,
+            Err(err) => {
+                debug!(path, error = %err, "FTP transfer aborted");
+                reply(control, 426, "Transfer aborted").await
+            }
+        }
+    }
+
+    /// Send `body` over the next data connection.
+    async fn send(&mut self, control: &mut Control, text: &str, body: &[u8]) -> io::Result<()> {
+        reply(control, 150, text).await?;
+        let Some(mut data) = self.data_connection().await else {
+            return reply(control, 425, "Can't open data connection").await;
+        };
+        let sent = async {
+            data.write_all(body).await?;
+            data.shutdown().await
+        };
+        match sent.await {
+            Ok(()) => reply(control, 226, "Transfer complete").await,
+            Err(err) => {
+                debug!(error = %err, "FTP transfer aborted");
+                reply(control, 426, "Transfer aborted").await
+            }
+        }
+    }
+
+    /// Run `f` on the resource at `path` with the catalog locked.
+    async fn with_resource<T>(
+        &self,
+        path: &str,
+        f: impl FnOnce(Option<DavResource<'_>>) -> T,
+    ) -> T {
+        let access = access_for(
+            &self.state,
+            self.login.as_ref().map(|login| login.username.as_str()),
+        );
+        let overrides = self.state.overrides.snapshot().await;
+        let catalog = self.state.catalog.read().await;
+        let files = listed_files(&catalog, self.state.dedup, &overrides, &access);
+        f(webdav::lookup(path, &files))
+    }
+}
+
+async fn reply(control: &mut Control, code: u16, text: &str) -> io::Result<()> {
+    let stream = control.get_mut();
+    stream
+        .write_all(format!("{code} {text}\r\n").as_bytes())
+        .await?;
+    stream.flush().await
+}
+
+/// A reply of several lines: `first`, each of `lines` (which start with a space), `last`.
+async fn multiline(
+    control: &mut Control,
+    code: u16,
+    first: &str,
+    lines: &[String],
+    last: &str,
+) -> io::Result<()> {
+    let mut text = format!("{code}-{first}\r\n");
+    for line in lines {
+        text.push_str(line);
+        text.push_str("\r\n");
+    }
+    text.push_str(&format!("{code} {last}\r\n"));
+    let stream = control.get_mut();
+    stream.write_all(text.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// `arg` resolved against the working directory `cwd`, as an absolute path.
+fn resolve(cwd: &str, arg: &str) -> String {
+    let mut segments: Vec<&str> = if arg.starts_with('/') {
+        Vec::new()
+    } else {
+        cwd.split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    };
+    for segment in arg.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+fn entry_name<'a>(entry: &'a DavEntry<'_>) -> &'a str {
+    match entry {
+        DavEntry::Folder(name) => name,
+        DavEntry::File(file) => &file.name,
+    }
+}
+
+/// `ls -l` style, as most clients parse `LIST`.
+fn list_line(entry: &DavEntry<'_>) -> String {
+    match entry {
+        DavEntry::Folder(name) => {
+            format!("drwxr-xr-x 1 ftp ftp {:>13} {} {name}", 0, list_date(0))
+        }
+        DavEntry::File(file) => format!(
+            "-rw-r--r-- 1 ftp ftp {:>13} {} {}",
+            file.size,
+            list_date(file.modified.unwrap_or_default()),
+            file.name
+        ),
+    }
+}
+
+fn mlsd_line(entry: &DavEntry<'_>) -> String {
+    match entry {
+        DavEntry::Folder(name) => format!("type=dir; {name}"),
+        DavEntry::File(file) => format!(
+            "type=file;size={};modify={}; {}",
+            file.size,
+            mdtm(file.modified.unwrap_or_default()),
+            file.name
+        ),
+    }
+}
+
+/// `2024-03-09T14:05:00Z` for Unix time `secs`.
+fn rfc3339(secs: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+}
+
+/// `YYYYMMDDHHMMSS` in UTC, as `MDTM` and `MLSD` give times.
+fn mdtm(secs: u64) -> String {
+    rfc3339(secs).chars().filter(char::is_ascii_digit).collect()
+}
+
+/// `Mar  9  2024`, the date column of `LIST`.
+fn list_date(secs: u64) -> String {
+    let stamp = rfc3339(secs);
+    let field = |range: std::ops::Range<usize>| stamp.get(range).unwrap_or_default();
+    let month = field(5..7).parse::<usize>().unwrap_or(1);
+    let day = field(8..10).trim_start_matches('0');
+    let month = MONTHS.get(month.saturating_sub(1)).unwrap_or(&MONTHS[0]);
+    format!("{month} {day:>2}  {}", field(0..4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_date, mdtm, resolve};
+
+    #[test]
+    fn paths_resolve_against_the_working_directory() {
+        assert_eq!(resolve("/", "Games"), "/Games");
+        assert_eq!(resolve("/Games", "../DLC/./x.nsp"), "/DLC/x.nsp");
+        assert_eq!(resolve("/Games/Updates", "/"), "/");
+        assert_eq!(resolve("/", ".."), "/");
+        assert_eq!(mdtm(1_709_993_100), "20240309140500");
+        assert_eq!(list_date(1_709_993_100), "Mar  9  2024");
+    }
+}
@@ -1,60 +1,167 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::Infallible;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::body::Body;
+use axum::extract::{Extension, FromRequest, FromRequestParts, Path, Query, State};
+use axum::http::header::{
+    ALLOW, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    RANGE, VARY,
+};
 use axum::http::request::Parts;
-use axum::http::HeaderMap;
 use axum::http::Request;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware;
 use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use axum::routing::{get, post};
+use axum::routing::{any, delete, get, post, put};
 use axum::{Json, Router};
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+#[cfg(feature = "admin-ui")]
+use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::cookie::CookieJar;
 use axum_extra::extract::Form;
+use bytes::Bytes;
 use futures_util::stream::StreamExt;
-use percent_encoding::percent_decode_str;
-use tower_governor::{
-    errors::GovernorError, governor::GovernorConfigBuilder, key_extractor::KeyExtractor,
-    GovernorLayer,
-};
-use tracing::{debug, warn};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
 
-use crate::catalog::{ContentKind, TitleVersions};
-use crate::serve_files::{sanitize_relative_path, stream_with_range_support, DownloadLogContext};
+use crate::annotations::{self, AnnotationFormat};
+use crate::announcements::{Announcement, AnnouncementDraft};
+use crate::artwork::{is_svg, normalize_title_id, Artwork};
+use crate::auth::{AuthSettings, SharedAuth, UserAccess};
+use crate::benchmark::{pick_samples, spawn_benchmarks, JOB_KIND as BENCHMARK_JOB};
+use crate::blocklist::fetch_title_ids;
+use crate::catalog::{
+    best_versions, classify_title_id, derive_base_title_id, parse_filename_metadata,
+    to_display_title_id, url_path, Catalog, ContentFile, ContentKind, FormatPreference,
+    TitleVersions,
+};
+use crate::chunks::{self, ChunkKey};
+use crate::export::ExportFormat;
+#[cfg(feature = "metrics")]
+use crate::growth::free_space;
+use crate::hooks::{DownloadRequest, Verdict};
+use crate::icon_cache::IconCacheStats;
+use crate::import::{spawn_import, ImportTarget, JOB_KIND as IMPORT_JOB};
+use crate::library::{FsckReport, RescanSummary};
+use crate::network::IpNetwork;
+use crate::overrides::{Overrides, TitleOverride};
+use crate::replication::{
+    is_partial, partial_path, spawn_replication, ReplicationSource, JOB_KIND as REPLICATION_JOB,
+};
+use crate::reports::LibraryReport;
+use crate::scanner::is_supported_content;
+use crate::search::parse_size;
+use crate::serve_files::{
+    sanitize_relative_path, set_download_cache_headers, set_download_filename, stream_nsz_as_nsp,
+    stream_with_range_support, stream_zeros_with_range_support, Download, DownloadLogContext,
+    FileServeError, StreamOptions,
+};
+use crate::slots::DownloadSlot;
+use crate::speedtest;
+use crate::trash::{Trash, TrashEntry, TrashError};
+use crate::upload::{finish, parse_content_range, partial_len, write_piece, UploadError};
+use crate::users::{UserChange, UserEditError};
+use crate::zip_stream::{StoredZip, ZipEntry};
 
-use crate::config::TitleDbConfig;
+use crate::config::{ShopView, TitleDbConfig};
+use crate::jobs::unix_now;
 
-use super::auth::ensure_authorized;
-use super::error::ApiError;
+use super::activity::{hold, track_activity};
+#[cfg(feature = "admin-ui")]
+use super::assets;
+use super::auth::{
+    access_for, ensure_authorized, extract_basic_auth, require_admin, session_user, token_user,
+    Access,
+};
+use super::base_path::{link_prefix, prefix_responses, public_origin, strip_base_path, url_prefix};
+use super::client_views::{library_page, ShopClient};
+use super::compression::compression_layer;
+use super::directories::{build_directory, DirectoryResponse};
+use super::error::{ApiError, ErrorBody, UPLOAD_OFFSET};
+use super::etag::{etag, is_fresh, json_response, not_modified};
+use super::openapi;
+use super::rate_limit::rate_limit;
+use super::redact::{sensitive_headers, RedactedMakeSpan};
+use super::shop_index::ShopIndexDocument;
+use super::webdav::{self, DavResource};
 
-const SESSION_COOKIE: &str = "ownfoil_session";
+pub(super) const SESSION_COOKIE: &str = "ownfoil_session";
 
 /// Extracts peer address from request extensions when available (e.g. from
 /// `into_make_service_with_connect_info`). Returns `None` in tests or when
 /// connection info is not set.
 struct PeerAddr(pub Option<SocketAddr>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct ClientKeyExtractor;
+/// The connection's peer address, from the request's extensions.
+pub(super) fn request_peer<T>(req: &Request<T>) -> Option<SocketAddr> {
+    req.extensions()
+        .get::<axum::extract::ConnectInfo<SocketAddr>>()
+        .map(|addr| addr.0)
+        .or_else(|| req.extensions().get::<SocketAddr>().copied())
+}
+
+/// [`client_ip`] of a request, taking the peer address from its extensions.
+pub(super) fn request_client_ip<T>(req: &Request<T>) -> IpAddr {
+    client_ip(req.headers(), request_peer(req))
+}
 
-impl KeyExtractor for ClientKeyExtractor {
-    type Key = String;
+/// The client's IP, preferring reverse-proxy headers over the connection's peer address.
+pub(super) fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> IpAddr {
+    forwarded_for_ip(headers)
+        .or_else(|| x_real_ip(headers))
+        .or_else(|| peer.map(|addr| addr.ip()))
+        .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST))
+}
 
-    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
-        let ip = forwarded_for_ip(req.headers())
-            .or_else(|| x_real_ip(req.headers()))
-            .or_else(|| {
-                req.extensions()
-                    .get::<axum::extract::ConnectInfo<SocketAddr>>()
-                    .map(|addr| addr.ip())
-            })
-            .or_else(|| req.extensions().get::<SocketAddr>().map(SocketAddr::ip))
-            .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
-        Ok(ip.to_string())
+/// The client address per-client limits count against. Behind trusted proxies that is the
+/// nearest `X-Forwarded-For` hop that isn't one of them; otherwise the peer itself, since
+/// any client can send forwarding headers. `None` when the peer is unknown or a trusted
+/// proxy forwarded an unreadable address.
+pub(super) fn client_addr(
+    trusted_proxies: &[IpNetwork],
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Option<IpAddr> {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut ip = peer?.ip();
+    if !trusted(ip) {
+        return Some(ip);
+    }
+    let values = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let hops = values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    if hops.is_empty() {
+        return Some(x_real_ip(headers).unwrap_or(ip));
     }
+    // Each proxy appends the address it saw, so the hops before the nearest untrusted one
+    // came from the client and can't be believed.
+    for hop in hops.iter().rev() {
+        ip = hop.trim().parse().ok()?;
+        if !trusted(ip) {
+            break;
+        }
+    }
+    Some(ip)
+}
+
+/// [`client_addr`] under `[rate_limit] trusted_proxies`, falling back to the peer and then
+/// to localhost like [`client_ip`].
+fn trusted_client_ip(state: &AppState, headers: &HeaderMap, peer: Option<SocketAddr>) -> IpAddr {
+    client_addr(&state.rate_limit.trusted_proxies, headers, peer)
+        .or_else(|| peer.map(|addr| addr.ip()))
+        .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST))
 }
 
 fn forwarded_for_ip(headers: &HeaderMap) -> Option<IpAddr> {
@@ -89,58 +196,51 @@ where
     }
 }
 
+#[cfg(feature = "saves")]
+use super::responses::SavesListResponse;
 use super::responses::{
-    build_catalog_response, build_shop_root_files, build_shop_sections_payload, catalog_sections,
-    map_file_error, map_shop_files, map_to_entries, static_png_response, CatalogResponse,
-    HealthResponse, SavesListResponse, SearchQuery, SearchResponse, SectionsResponse,
-    ShopRootResponse, ShopSectionsQuery, ShopSectionsResponse,
+    accepts_svg, artwork_response, build_aria2_input, build_catalog_response, build_download_stats,
+    build_duplicates_response, build_health_response, build_index_txt, build_missing_dlc_response,
+    build_shop_root_files, build_shop_sections_payload, catalog_sections, downloaded_title_ids,
+    entry_to_api, map_file_error, map_shop_files, map_to_entries, placeholder_artwork,
+    prefix_json_response, prefix_urls, sort_files, static_png_response, AnnotationImportQuery,
+    AnnotationImportResponse, AnnouncementsResponse, ApiTokensResponse, BatchDownloadRequest,
+    BenchmarkStarted, BenchmarkStartedResponse, BenchmarkStatusResponse, BlocklistImportRequest,
+    BlocklistImportResponse, BlocklistResponse, CatalogChangesResponse, CatalogQuery,
+    CatalogResponse, ChangesQuery, ChecksumResponse, ChunkEntry, ChunkMapResponse, ChunksQuery,
+    DownloadStatsResponse, DuplicatesResponse, FsckQuery, HealthResponse, HiddenResponse,
+    HideRequest, IconCachePurgedResponse, ImageQuery, ImportStartedResponse, ImportUrlRequest,
+    IndexQuery, IssueTokenRequest, IssuedTokenResponse, JobsQuery, JobsResponse,
+    LibraryTitlesResponse, MissingDlcResponse, PageQuery, ProblemsResponse,
+    ReplicationStartedResponse, ReplicationStatusResponse, SearchQuery, SearchResponse,
+    SectionsResponse, ShopSectionsQuery, ShopSectionsResponse, ShopTokenEntry, ShopTokensResponse,
+    SortQuery, SpeedTestQuery, TitleDbHealth, TitleRefreshResponse, TrashListResponse,
+    UploadResponse, UserEntry, UsersResponse, VerificationResponse,
 };
+#[cfg(feature = "metrics")]
+use super::responses::{build_library_stats, LibraryStatsResponse};
 use super::state::AppState;
+use super::tally::{tally_download, tally_stream};
 
-/// Build the Axum router with all routes, layers (rate limit, request ID, trace), and state.
+/// Build the Axum router with all routes, layers (rate limit, request ID, trace,
+/// compression), and state.
 pub fn router(state: AppState) -> Router {
-    let governor_conf = GovernorConfigBuilder::default()
-        .per_second(20)
-        .burst_size(50)
-        .key_extractor(ClientKeyExtractor)
-        .finish()
-        .map(Arc::new);
-    if governor_conf.is_none() {
-        warn!("governor config invalid; rate limiting disabled");
-    }
-    let auth_enabled = state.auth.is_enabled();
-
-    let app = Router::new()
-        .route("/", get(shop_root))
-        .route("/health", get(health))
-        .route("/api/catalog", get(catalog_all))
-        .route("/api/sections", get(sections))
-        .route("/api/sections/{section}", get(section_entries))
-        .route("/api/shop/sections", get(shop_sections))
-        .route("/api/search", get(search))
-        .route("/api/title/{title_id}/versions", get(title_versions))
-        .route("/api/download/{*path}", get(download))
-        .route("/api/get_game/{id}", get(download_by_id))
-        .route("/api/shop/icon/{title_id}", get(shop_icon))
-        .route("/api/shop/banner/{title_id}", get(shop_banner))
-        .route("/api/saves/list", get(saves_list))
-        .route("/api/titles", get(catalog_all))
-        .route("/api/index", get(catalog_all))
-        .route("/api/shop", get(shop_root))
-        .route("/shop", get(shop_root))
-        .route("/index", get(catalog_all))
-        .route("/titles", get(catalog_all))
-        .route("/download/{*path}", get(download));
+    let auth_enabled = state.auth.load().is_enabled();
+
+    let app = shop_routes();
 
     let app = if auth_enabled {
-        app.route("/admin", get(admin_ui))
-            .route("/admin/settings", get(settings_ui))
+        #[cfg(feature = "admin-ui")]
+        let app = app
             .route("/admin/login", get(login_page).post(login_post))
-            .route("/admin/logout", get(logout))
-            .route("/api/settings", get(settings_get).post(settings_post))
-            .route("/api/settings/refresh", post(settings_refresh))
-            .route("/api/settings/titledb/progress", get(titledb_progress_sse))
-            .route("/api/settings/titledb/test", get(titledb_test_connectivity))
+            .route("/admin/logout", get(logout));
+        app.route("/u/{token}", get(token_shop))
+            .route("/u/{token}/", get(token_shop))
+            .route("/u/{token}/{*rest}", any(token_shop))
+            .merge(
+                admin_routes()
+                    .route_layer(middleware::from_fn_with_state(state.clone(), require_admin)),
+            )
     } else {
         app
     };
@@ -153,123 +253,828 @@ pub fn router(state: AppState) -> Router {
         .layer(tower_http::request_id::PropagateRequestIdLayer::new(
             axum::http::header::HeaderName::from_static("x-request-id"),
         ))
-        .layer(tower_http::trace::TraceLayer::new_for_http())
-        .with_state(state);
+        .layer(tower_http::trace::TraceLayer::new_for_http().make_span_with(RedactedMakeSpan))
+        .layer(tower_http::sensitive_headers::SetSensitiveHeadersLayer::new(sensitive_headers()))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            prefix_responses,
+        ))
+        .layer(compression_layer())
+        .layer(middleware::from_fn_with_state(
+            state.idle.clone(),
+            track_activity,
+        ))
+        .with_state(state.clone());
 
-    if let Some(governor_conf) = governor_conf {
-        app.layer(GovernorLayer::new(governor_conf))
-    } else {
-        app
+    let app = rate_limit(app, state.clone());
+    match state.base_path.as_deref() {
+        Some(base_path) => strip_base_path(app, base_path),
+        None => app,
     }
 }
 
+/// Admin pages and API, only mounted when auth is on. Only `admin` users get through.
+fn admin_routes() -> Router<AppState> {
+    let app = Router::new();
+    #[cfg(feature = "admin-ui")]
+    let app = app
+        .route("/admin", get(admin_ui))
+        .route("/admin/settings", get(settings_ui))
+        .route("/admin/users", get(users_ui));
+    #[cfg(feature = "titledb")]
+    let app = app.route("/api/settings/titledb/test", get(titledb_test_connectivity));
+    #[cfg(feature = "metrics")]
+    let app = app.route("/api/library/stats", get(library_stats));
+    app.route("/api/settings", get(settings_get).post(settings_post))
+        .route("/api/settings/refresh", post(settings_refresh))
+        .route("/api/settings/events", get(settings_events_sse))
+        .route("/api/settings/titledb/progress", get(titledb_progress_sse))
+        .route("/api/title/{title_id}/refresh", post(title_refresh))
+        .route("/api/overrides", get(overrides_list))
+        .route("/api/overrides/import", post(overrides_import))
+        .route(
+            "/api/overrides/{title_id}",
+            put(override_put).delete(override_delete),
+        )
+        .route(
+            "/api/overrides/{title_id}/icon",
+            put(override_icon_put).delete(override_icon_delete),
+        )
+        .route("/api/library/rescan", post(library_rescan))
+        .route("/api/library/import-url", post(library_import_url))
+        .route("/api/upload/{filename}", get(upload_status).put(upload_put))
+        .route("/api/jobs", get(jobs_list))
+        .route("/api/library/export", get(library_export))
+        .route("/api/library/duplicates", get(library_duplicates))
+        .route("/api/stats/downloads", get(download_stats))
+        .route("/api/library/fsck", get(library_fsck).post(library_fsck))
+        .route("/api/library/problems", get(library_problems))
+        .route("/api/library/hidden", get(library_hidden))
+        .route("/api/library/hide", post(library_hide))
+        .route("/api/library/unhide", post(library_unhide))
+        .route("/api/library/verification", get(library_verification))
+        .route("/api/library/missing-dlc", get(library_missing_dlc))
+        .route(
+            "/api/library/benchmark",
+            get(benchmark_status).post(benchmark_start),
+        )
+        .route(
+            "/api/cache/icons",
+            get(icon_cache_status).delete(icon_cache_purge),
+        )
+        .route("/api/library/file/{id}", delete(library_file_delete))
+        .route("/api/library/trash", get(trash_list))
+        .route("/api/library/trash/{id}", delete(trash_purge))
+        .route("/api/library/trash/{id}/restore", post(trash_restore))
+        .route("/api/library/replication", get(replication_status))
+        .route("/api/library/replicate/{title_id}", post(replicate_title))
+        .route("/api/reports/latest", get(report_latest))
+        .route("/api/reports/latest.html", get(report_latest_html))
+        .route("/api/reports/generate", post(report_generate))
+        .route("/api/blocklist", get(blocklist_get))
+        .route("/api/blocklist/import", post(blocklist_import))
+        .route(
+            "/api/blocklist/{title_id}",
+            put(blocklist_put).delete(blocklist_delete),
+        )
+        .route("/api/announcements", post(announcement_create))
+        .route(
+            "/api/announcements/{id}",
+            put(announcement_update).delete(announcement_delete),
+        )
+        .route("/api/users", get(users_list).post(user_save))
+        .route("/api/users/{username}", delete(user_delete))
+        .route(
+            "/api/users/{username}/tokens",
+            get(api_tokens_list).post(api_token_issue),
+        )
+        .route(
+            "/api/users/{username}/tokens/{id}",
+            delete(api_token_revoke),
+        )
+        .route("/api/shop-tokens", get(shop_tokens_list))
+        .route(
+            "/api/shop-tokens/{username}",
+            post(shop_token_rotate).delete(shop_token_revoke),
+        )
+}
+
+/// Client-facing shop and download routes, also served under `/u/{token}/`.
+fn shop_routes() -> Router<AppState> {
+    let app = Router::new();
+    #[cfg(feature = "saves")]
+    let app = app.route("/api/saves/list", get(saves_list));
+    // The API docs page loads its script from here, so this is mounted without auth too.
+    #[cfg(feature = "admin-ui")]
+    let app = app
+        .route("/api/docs", get(api_docs))
+        .route("/admin/assets/{file}", get(admin_asset));
+    app.route("/", get(shop_index))
+        .route("/health", get(health))
+        .route("/api/catalog", get(catalog_all))
+        .route("/api/sections", get(sections))
+        .route("/api/sections/{section}", get(section_entries))
+        .route("/api/shop/sections", get(shop_sections))
+        .route("/api/search", get(search))
+        .route("/index.txt", get(index_txt))
+        .route("/api/catalog/aria2", get(catalog_aria2))
+        .route("/api/announcements", get(announcements_list))
+        .route("/api/directories", get(directory))
+        .route("/api/directories/", get(directory))
+        .route("/api/directories/{*path}", get(directory))
+        .route("/dav", any(dav))
+        .route("/dav/", any(dav))
+        .route("/dav/{*path}", any(dav))
+        .route("/api/catalog/changes", get(catalog_changes))
+        .route("/api/title/{title_id}/versions", get(title_versions))
+        .route("/api/library/titles", get(library_titles))
+        .route("/api/download/{*path}", get(download))
+        .route("/api/get_game/{id}", get(download_by_id))
+        .route("/api/get_game/{id}/chunks", get(download_chunks))
+        .route("/api/file/{id}/checksum", get(file_checksum))
+        .route("/api/get_game/{id}/nsp", get(download_nsp_by_id))
+        .route("/api/download/batch", post(download_batch))
+        .route("/api/shop/icon/{title_id}", get(shop_icon))
+        .route("/api/shop/banner/{title_id}", get(shop_banner))
+        .route("/api/speedtest", get(speedtest))
+        .route("/api/openapi.json", get(openapi_json))
+        .route("/api/titles", get(catalog_all))
+        .route("/api/index", get(catalog_all))
+        .route("/api/shop", get(shop_root))
+        .route("/shop", get(shop_page))
+        .route("/index", get(catalog_all))
+        .route("/titles", get(catalog_all))
+        .route("/download/{*path}", get(download))
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "server",
+    responses(
+        (status = 200, description = "OK", body = HealthResponse),
+    )
+)]
 async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
     let catalog_files = state.catalog.read().await.files().len();
-    Json(HealthResponse {
-        status: "ok",
-        catalog_files: Some(catalog_files),
-    })
+    let scans = state.library.scan_timings().await;
+    let titledb = if state.titledb.config().await.enabled {
+        let last_refresh = state.titledb.last_refresh().await;
+        Some(TitleDbHealth {
+            age_seconds: last_refresh.map(|at| at.elapsed().as_secs()),
+        })
+    } else {
+        None
+    };
+    Json(build_health_response(
+        catalog_files,
+        &scans,
+        titledb,
+        state.health,
+        unix_now(),
+    ))
 }
 
 fn ensure_admin_enabled(state: &AppState) -> Result<(), ApiError> {
-    if state.auth.is_enabled() {
+    if state.auth.load().is_enabled() {
         Ok(())
     } else {
         Err(ApiError::NotFound)
     }
 }
 
-async fn shop_root(
+/// `/`: the shop index in the view its client gets; see [`super::client_views`]. By
+/// default Tinfoil gets it encrypted when `[shop] encrypt` is set, browsers a library
+/// page, and everything else plain JSON.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "shop",
+    responses(
+        (status = 200, description = "Shop index; an encrypted `application/octet-stream` body for Tinfoil when `[shop] encrypt` is set, or an HTML library page for browsers", body = ShopIndexDocument),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn shop_index(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> Result<Response, ApiError> {
+    shop_view(state, access, jar, headers, prefix, "/").await
+}
+
+/// Answer the shop index `route` in the view `[shop.views]` picks for the client.
+async fn shop_view(
+    state: AppState,
+    access: UserAccess,
+    jar: CookieJar,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+    route: &str,
+) -> Result<Response, ApiError> {
+    let client = ShopClient::detect(&headers);
+    let view = state.shop.view(route, client);
+    let varies = state.shop.varies(route);
+    debug!(route, ?client, ?view, "shop index view");
+    let mut response = match view {
+        ShopView::Tinfoil => tinfoil_index(&state, &access, &jar, &headers, prefix).await?,
+        ShopView::Json => json_response(
+            &headers,
+            &shop_document(&state, &access, &jar, &headers).await?,
+        )?,
+        ShopView::Sections => {
+            let query = ShopSectionsQuery {
+                limit: None,
+                offset: 0,
+                section: None,
+            };
+            shop_sections(State(state), Access(access), jar, Query(query), headers).await?
+        }
+        ShopView::Html => library_html(&state, &access, &jar, &headers, prefix).await?,
+    };
+    if varies {
+        response.headers_mut().append(
+            VARY,
+            HeaderValue::from_static("accept, user-agent, theme, uid"),
+        );
+    }
+    Ok(response)
+}
+
+/// The shop index for Tinfoil, encrypted when `[shop] encrypt` is set.
+async fn tinfoil_index(
+    state: &AppState,
+    access: &UserAccess,
+    jar: &CookieJar,
+    headers: &HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> Result<Response, ApiError> {
+    let index = shop_document(state, access, jar, headers).await?;
+    let Some(encryptor) = state.index_encryptor.clone() else {
+        return json_response(headers, &index);
+    };
+    // Prefixes are added to plain JSON on the way out; this body is opaque by then.
+    let mut index = serde_json::to_value(&index).map_err(|err| {
+        warn!(error = %err, "failed to serialize shop index");
+        ApiError::Internal
+    })?;
+    let prefix = format!("{}{}", link_prefix(state, headers), shop_prefix(prefix));
+    if !prefix.is_empty() {
+        prefix_urls(&mut index, &prefix);
+    }
+    // Tag the plain index: every encryption uses a new key.
+    let plain = serde_json::to_vec(&index).map_err(|err| {
+        warn!(error = %err, "failed to serialize shop index");
+        ApiError::Internal
+    })?;
+    let tag = etag(&plain);
+    if is_fresh(headers, &tag) {
+        return Ok(not_modified(tag));
+    }
+    let body = encryptor.encrypt(&index).map_err(|err| {
+        warn!(error = %err, "shop index encryption failed");
+        ApiError::Internal
+    })?;
+    Ok((
+        [
+            (
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            ),
+            (ETAG, tag),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// The shop as an HTML page with TitleDB names and download links.
+async fn library_html(
+    state: &AppState,
+    access: &UserAccess,
+    jar: &CookieJar,
+    headers: &HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> Result<Response, ApiError> {
+    ensure_authorized(state, headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let overrides = state.overrides.snapshot().await;
+    let catalog = state.catalog.read().await;
+    let files = listed_files(&catalog, state.dedup, &overrides, access);
+    let names = state
+        .titledb
+        .names_for(
+            files
+                .iter()
+                .filter_map(|(_, file)| file.title_id.as_deref()),
+        )
+        .await;
+    let announcement = state.announcements.latest(unix_now()).await;
+    let message = state.shop.message(announcement.as_ref());
+    let prefix = format!("{}{}", link_prefix(state, headers), shop_prefix(prefix));
+    let body = library_page(&message, &files, &names, &prefix);
+    let tag = etag(body.as_bytes());
+    if is_fresh(headers, &tag) {
+        return Ok(not_modified(tag));
+    }
+    Ok(([(ETAG, tag)], Html(body)).into_response())
+}
+
+/// One level of the virtual directory tree; see [`super::directories`].
+#[utoipa::path(
+    get,
+    path = "/api/directories/{path}",
+    tag = "catalog",
+    params(("path" = String, Path, description = "Directory path, e.g. `kind/dlc`; empty for the root")),
+    responses(
+        (status = 200, description = "OK", body = DirectoryResponse),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 404, description = "No such file or title", body = ErrorBody),
+    )
+)]
+async fn directory(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    path: Option<Path<String>>,
+    headers: HeaderMap,
+) -> Result<Json<DirectoryResponse>, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let path = path.map(|Path(path)| path).unwrap_or_default();
+    let overrides = state.overrides.snapshot().await;
+    let catalog = state.catalog.read().await;
+    let listing = build_directory(
+        &path,
+        &listed_files(&catalog, state.dedup, &overrides, &access),
+    )
+    .ok_or(ApiError::NotFound)?;
+    debug!(
+        path = %path,
+        files = listing.files.len(),
+        directories = listing.directories.len(),
+        "directory requested"
+    );
+    Ok(Json(listing))
+}
+
+/// Read-only WebDAV over the library's folders; see [`webdav`].
+#[allow(clippy::too_many_arguments)]
+async fn dav(
     State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
+    peer: PeerAddr,
+    shop_user: Option<Extension<ShopUser>>,
+    prefix: Option<Extension<ShopPrefix>>,
+    method: Method,
+    path: Option<Path<String>>,
     headers: HeaderMap,
-) -> Result<Json<ShopRootResponse>, ApiError> {
+) -> Result<Response, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let allow = [(ALLOW, "OPTIONS, GET, HEAD, PROPFIND")];
+    match method.as_str() {
+        "OPTIONS" => return Ok((allow, [("dav", "1")]).into_response()),
+        "GET" | "HEAD" | "PROPFIND" => {}
+        _ => return Ok((StatusCode::METHOD_NOT_ALLOWED, allow).into_response()),
+    }
+    let path = path.map(|Path(path)| path).unwrap_or_default();
+    let overrides = state.overrides.snapshot().await;
+    let catalog = state.catalog.read().await;
+    let files = listed_files(&catalog, state.dedup, &overrides, &access);
+    let resource = webdav::lookup(&path, &files).ok_or(ApiError::NotFound)?;
+
+    let mut href = format!(
+        "{}{}/dav/",
+        url_prefix(&state, &headers),
+        shop_prefix(prefix)
+    );
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        href.push_str(&webdav::encode(segment));
+        href.push('/');
+    }
+    if method.as_str() == "PROPFIND" {
+        if matches!(resource, DavResource::File(_)) {
+            href.pop();
+        }
+        let shallow = headers
+            .get("depth")
+            .is_some_and(|depth| depth.as_bytes() == b"0");
+        debug!(path = %path, shallow, "webdav listing requested");
+        let xml = webdav::multistatus(&href, &resource, shallow);
+        return Ok((
+            StatusCode::MULTI_STATUS,
+            [(CONTENT_TYPE, "application/xml; charset=utf-8")],
+            xml,
+        )
+            .into_response());
+    }
+    match resource {
+        DavResource::Folder(_, entries) => {
+            Ok(Html(webdav::html_index(&href, &entries)).into_response())
+        }
+        DavResource::File(file) => {
+            // Served like `/download/`, which decodes the path once more.
+            let relative = file.relative_path.to_string_lossy();
+            let encoded = utf8_percent_encode(&relative, NON_ALPHANUMERIC).to_string();
+            drop(catalog);
+            download(
+                State(state),
+                Access(access),
+                jar,
+                peer,
+                shop_user,
+                Path(encoded),
+                headers,
+            )
+            .await
+        }
+    }
+}
+
+/// A library file for the FTP server, through the same checks as `/download/`: `login` is
+/// the session's account when auth is on, and a non-zero `offset` resumes a transfer.
+#[cfg(feature = "ftp")]
+pub(super) async fn ftp_download(
+    state: AppState,
+    peer: SocketAddr,
+    login: Option<(String, String)>,
+    relative_path: &std::path::Path,
+    offset: u64,
+) -> Result<Response, ApiError> {
+    use axum::http::header::AUTHORIZATION;
+    use base64::prelude::*;
+
+    let access = access_for(
+        &state,
+        login.as_ref().map(|(username, _)| username.as_str()),
+    );
+    let mut headers = HeaderMap::new();
+    if let Some((username, password)) = login {
+        let credentials = BASE64_STANDARD.encode(format!("{username}:{password}"));
+        let value = HeaderValue::from_str(&format!("Basic {credentials}"))
+            .map_err(|_| ApiError::Unauthorized)?;
+        headers.insert(AUTHORIZATION, value);
+    }
+    if offset > 0 {
+        let value =
+            HeaderValue::from_str(&format!("bytes={offset}-")).map_err(|_| ApiError::Internal)?;
+        headers.insert(RANGE, value);
+    }
+    let relative = relative_path.to_string_lossy();
+    let encoded = utf8_percent_encode(&relative, NON_ALPHANUMERIC).to_string();
+    download(
+        State(state),
+        Access(access),
+        CookieJar::new(),
+        PeerAddr(Some(peer)),
+        None,
+        Path(encoded),
+        headers,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/shop",
+    tag = "shop",
+    responses(
+        (status = 200, description = "Shop index as plain JSON, unless `[shop.views]` picks another view", body = ShopIndexDocument),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn shop_root(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> Result<Response, ApiError> {
+    shop_view(state, access, jar, headers, prefix, "/api/shop").await
+}
+
+/// `/shop`: like `/api/shop`, with its own `[shop.views]` entry.
+async fn shop_page(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> Result<Response, ApiError> {
+    shop_view(state, access, jar, headers, prefix, "/shop").await
+}
+
+async fn shop_document(
+    state: &AppState,
+    access: &UserAccess,
+    jar: &CookieJar,
+    headers: &HeaderMap,
+) -> Result<ShopIndexDocument, ApiError> {
+    ensure_authorized(state, headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let overrides = state.overrides.snapshot().await;
     let catalog = state.catalog.read().await;
-    let files = build_shop_root_files(catalog.files());
+    let files = build_shop_root_files(&listed_files(&catalog, state.dedup, &overrides, access));
+    let announcement = state.announcements.latest(unix_now()).await;
     debug!(files = files.len(), "shop root requested");
-    Ok(Json(ShopRootResponse {
-        success: "ok",
-        files,
-    }))
+    Ok(state.shop.build(files, announcement.as_ref()))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/catalog",
+    tag = "catalog",
+    params(
+        CatalogQuery,
+        PageQuery,
+    ),
+    responses(
+        (status = 200, description = "OK", body = CatalogResponse),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn catalog_all(
     State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    Query(query): Query<CatalogQuery>,
+    Query(page): Query<PageQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let overrides = if query.all {
+        Overrides::default()
+    } else {
+        state.overrides.snapshot().await
+    };
+    let catalog = state.catalog.read().await;
+    let dedup = state.dedup.filter(|_| !query.all);
+    let files = dedup_listing(catalog.files().iter().collect(), dedup, &overrides, &access);
+    let mut response = build_catalog_response(sort_files(files, query.sort), &page);
+    response.cursor = Some(state.library.cursor(catalog.generation()).await);
+    debug!(
+        entries = response.entries.len(),
+        total = response.total,
+        "catalog requested"
+    );
+    json_response(&headers, &response)
+}
+
+/// Catalog files added and removed since a cursor from `/api/catalog` or a previous call.
+/// Tracks every file, like `/api/catalog?all=true`.
+#[utoipa::path(
+    get,
+    path = "/api/catalog/changes",
+    tag = "catalog",
+    params(ChangesQuery),
+    responses(
+        (status = 200, description = "OK", body = CatalogChangesResponse),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn catalog_changes(
+    State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
+    Query(query): Query<ChangesQuery>,
     headers: HeaderMap,
-) -> Result<Json<CatalogResponse>, ApiError> {
+) -> Result<Json<CatalogChangesResponse>, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    // Holding the catalog lock keeps the change log at the same generation.
     let catalog = state.catalog.read().await;
-    let entries = map_to_entries(catalog.files());
-    debug!(entries = entries.len(), "catalog requested");
-    Ok(Json(build_catalog_response(entries)))
+    let cursor = state.library.cursor(catalog.generation()).await;
+    let delta = match &query.since {
+        Some(since) => state.library.changes_since(since).await,
+        None => None,
+    };
+    let reset = delta.is_none();
+    let delta = delta.unwrap_or_default();
+    let overrides = state.overrides.snapshot().await;
+    debug!(
+        reset,
+        added = delta.added.len(),
+        removed = delta.removed.len(),
+        "catalog changes requested"
+    );
+    Ok(Json(CatalogChangesResponse {
+        cursor,
+        reset,
+        added: delta
+            .added
+            .iter()
+            .filter(|file| !overrides.hides(file) && access.allows(file))
+            .map(entry_to_api)
+            .collect(),
+        removed: delta
+            .removed
+            .iter()
+            .filter(|file| !overrides.hides(file) && access.allows(file))
+            .map(|file| url_path(&file.relative_path))
+            .collect(),
+    }))
+}
+
+/// Files the shop index lists, with their file IDs: every file that isn't hidden, or the
+/// best copy of each title in dedup mode. IDs always refer to the full catalog.
+pub(super) fn listed_files<'a>(
+    catalog: &'a Catalog,
+    dedup: Option<FormatPreference>,
+    overrides: &Overrides,
+    access: &UserAccess,
+) -> Vec<(usize, &'a ContentFile)> {
+    let files = catalog
+        .indexed()
+        .into_iter()
+        .filter(|(_, file)| !overrides.hides(file) && access.allows(file))
+        .collect();
+    match dedup {
+        Some(prefer) => best_versions(files, prefer),
+        None => files,
+    }
+}
+
+fn dedup_listing<'a>(
+    files: Vec<&'a ContentFile>,
+    dedup: Option<FormatPreference>,
+    overrides: &Overrides,
+    access: &UserAccess,
+) -> Vec<&'a ContentFile> {
+    let files: Vec<_> = files
+        .into_iter()
+        .filter(|file| !overrides.hides(file) && access.allows(file))
+        .collect();
+    match dedup {
+        Some(prefer) => best_versions(files.into_iter().enumerate().collect(), prefer)
+            .into_iter()
+            .map(|(_, file)| file)
+            .collect(),
+        None => files,
+    }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/sections",
+    tag = "catalog",
+    responses(
+        (status = 200, description = "OK", body = SectionsResponse),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn sections(
     State(state): State<AppState>,
     jar: CookieJar,
     headers: HeaderMap,
-) -> Result<Json<SectionsResponse>, ApiError> {
+) -> Result<Response, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
     debug!("sections requested");
-    Ok(Json(SectionsResponse {
-        sections: catalog_sections(),
-    }))
+    json_response(
+        &headers,
+        &SectionsResponse {
+            sections: catalog_sections(),
+        },
+    )
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/shop/sections",
+    tag = "shop",
+    params(ShopSectionsQuery),
+    responses(
+        (status = 200, description = "OK", body = ShopSectionsResponse),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn shop_sections(
     State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
     Query(query): Query<ShopSectionsQuery>,
     headers: HeaderMap,
-) -> Result<Json<ShopSectionsResponse>, ApiError> {
+) -> Result<Response, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
     let limit = query.limit.unwrap_or(50).max(1);
 
+    let overrides = state.overrides.snapshot().await;
     let catalog = state.catalog.read().await;
-    let payload = build_shop_sections_payload(catalog.files(), limit, &state.titledb).await;
+    let indexed = listed_files(&catalog, state.dedup, &overrides, &access);
+    let mut payload = build_shop_sections_payload(
+        &indexed,
+        limit,
+        query.offset,
+        &state.titledb,
+        &state.artwork,
+        &overrides,
+        &state.downloads,
+    )
+    .await;
+    if let Some(section) = &query.section {
+        payload.sections.retain(|candidate| candidate.id == section);
+    }
     debug!(
         limit,
+        offset = query.offset,
         sections = payload.sections.len(),
         "shop sections requested"
     );
-    Ok(Json(payload))
+    json_response(&headers, &payload)
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/sections/{section}",
+    tag = "catalog",
+    params(
+        ("section" = String, Path, description = "Section ID from `/api/sections`"),
+        SortQuery,
+        PageQuery,
+    ),
+    responses(
+        (status = 200, description = "OK", body = CatalogResponse),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn section_entries(
     State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
     Path(section): Path<String>,
+    Query(query): Query<SortQuery>,
+    Query(page): Query<PageQuery>,
     headers: HeaderMap,
-) -> Result<Json<CatalogResponse>, ApiError> {
+) -> Result<Response, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
 
+    let overrides = state.overrides.snapshot().await;
     let catalog = state.catalog.read().await;
-    let entries = match section.as_str() {
-        "all" | "new" | "recommended" => map_to_entries(catalog.files()),
-        "base" | "games" => map_to_entries(catalog.files_by_kind(ContentKind::Base)),
-        "updates" | "update" => map_to_entries(catalog.files_by_kind(ContentKind::Update)),
-        "dlc" => map_to_entries(catalog.files_by_kind(ContentKind::Dlc)),
+    let files = match section.as_str() {
+        "new" => catalog.recently_added(),
+        "all" | "recommended" => catalog.files().iter().collect(),
+        "base" | "games" => catalog.files_by_kind(ContentKind::Base),
+        "updates" | "update" => catalog.files_by_kind(ContentKind::Update),
+        "dlc" => catalog.files_by_kind(ContentKind::Dlc),
+        "homebrew" | "forwarders" => catalog.files_by_kind(ContentKind::Homebrew),
         _ => Vec::new(),
     };
-    debug!(section = %section, entries = entries.len(), "section requested");
+    let response = build_catalog_response(
+        sort_files(
+            dedup_listing(files, state.dedup, &overrides, &access),
+            query.sort,
+        ),
+        &page,
+    );
+    debug!(
+        section = %section,
+        entries = response.entries.len(),
+        total = response.total,
+        "section requested"
+    );
 
-    Ok(Json(build_catalog_response(entries)))
+    json_response(&headers, &response)
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    tag = "catalog",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "OK", body = SearchResponse),
+        (status = 400, description = "Invalid search", body = ErrorBody),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn search(
     State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
     headers: HeaderMap,
     Query(params): Query<SearchQuery>,
 ) -> Result<Json<SearchResponse>, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
 
+    let query = crate::search::SearchQuery::parse(&params.q)?;
+    let overrides = state.overrides.snapshot().await;
     let catalog = state.catalog.read().await;
-    let matches = catalog.search(&params.q);
+    let names = state.titledb.names_for(catalog.title_ids()).await;
+    let matches = sort_files(
+        dedup_listing(
+            catalog.search(&query, &names),
+            state.dedup,
+            &overrides,
+            &access,
+        ),
+        params.sort,
+    );
     debug!(query = %params.q, results = matches.len(), "search requested");
-    let entries = map_to_entries(matches.iter().copied());
+    let entries = map_to_entries(matches);
 
     Ok(Json(SearchResponse {
         query: params.q,
@@ -279,16 +1084,198 @@ async fn search(
     }))
 }
 
+/// Path prefix a shop route was reached under, e.g. `/u/{token}` for per-user shop URLs.
+#[derive(Debug, Clone)]
+struct ShopPrefix(String);
+
+/// User a `/u/{token}` shop path belongs to; inner routes run with auth open.
+#[derive(Debug, Clone)]
+struct ShopUser(String);
+
+/// Newline-delimited absolute download URLs, for wget/aria2 batch downloads.
+#[utoipa::path(
+    get,
+    path = "/index.txt",
+    tag = "catalog",
+    params(IndexQuery),
+    responses(
+        (status = 200, description = "Download URLs, one per line", content_type = "text/plain"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn index_txt(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+    Query(params): Query<IndexQuery>,
+) -> Result<Response, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let selection = DownloadSelection::parse(&params)?;
+    let overrides = state.overrides.snapshot().await;
+    let catalog = state.catalog.read().await;
+    let names = state.titledb.names_for(catalog.title_ids()).await;
+    let files = selection.apply(
+        listed_files(&catalog, state.dedup, &overrides, &access),
+        &names,
+    );
+    debug!(query = %params.q, files = files.len(), "index.txt requested");
+    Ok((
+        [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+        build_index_txt(&files, &shop_base_url(&state, &headers, prefix)),
+    )
+        .into_response())
+}
+
+/// aria2 input file (`aria2c -i`) that mirrors the selected files with their library paths.
+#[utoipa::path(
+    get,
+    path = "/api/catalog/aria2",
+    tag = "catalog",
+    params(IndexQuery),
+    responses(
+        (status = 200, description = "aria2 input file", content_type = "text/plain"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn catalog_aria2(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+    Query(params): Query<IndexQuery>,
+) -> Result<Response, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let selection = DownloadSelection::parse(&params)?;
+    let overrides = state.overrides.snapshot().await;
+    let catalog = state.catalog.read().await;
+    let names = state.titledb.names_for(catalog.title_ids()).await;
+    let files = selection.apply(
+        listed_files(&catalog, state.dedup, &overrides, &access),
+        &names,
+    );
+    debug!(query = %params.q, files = files.len(), "aria2 input file requested");
+    Ok((
+        [
+            (CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"ownfoil.aria2\"",
+            ),
+        ],
+        build_aria2_input(&files, &shop_base_url(&state, &headers, prefix)),
+    )
+        .into_response())
+}
+
+/// Files picked by `?q=` (search syntax) and `?titles=` (comma-separated title IDs; a base
+/// title also selects its updates and DLC) for bulk download listings.
+struct DownloadSelection {
+    query: crate::search::SearchQuery,
+    titles: BTreeSet<String>,
+}
+
+impl DownloadSelection {
+    fn parse(params: &IndexQuery) -> Result<Self, ApiError> {
+        let titles = params
+            .titles
+            .split(',')
+            .map(str::trim)
+            .filter(|title_id| !title_id.is_empty())
+            .map(|title_id| normalize_title_id(title_id).ok_or(ApiError::InvalidTitleId))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            query: crate::search::SearchQuery::parse(&params.q)?,
+            titles,
+        })
+    }
+
+    fn apply<'a>(
+        &self,
+        files: Vec<(usize, &'a ContentFile)>,
+        names: &HashMap<String, String>,
+    ) -> Vec<&'a ContentFile> {
+        files
+            .into_iter()
+            .map(|(_, file)| file)
+            .filter(|file| self.selects_title(file))
+            .filter(|file| {
+                let name = file
+                    .title_id
+                    .as_deref()
+                    .and_then(|title_id| names.get(title_id))
+                    .map(String::as_str);
+                self.query.score(file, name).is_some()
+            })
+            .collect()
+    }
+
+    fn selects_title(&self, file: &ContentFile) -> bool {
+        if self.titles.is_empty() {
+            return true;
+        }
+        let Some(title_id) = file.title_id.as_deref().and_then(normalize_title_id) else {
+            return false;
+        };
+        derive_base_title_id(file.kind, Some(&title_id))
+            .is_some_and(|base| self.titles.contains(&base))
+            || self.titles.contains(&title_id)
+    }
+}
+
+/// Origin, base path, and any shop prefix (`/u/{token}`) that absolute download URLs
+/// start with.
+fn shop_base_url(
+    state: &AppState,
+    headers: &HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> String {
+    format!(
+        "{}{}{}",
+        public_origin(state, headers),
+        url_prefix(state, headers),
+        shop_prefix(prefix)
+    )
+}
+
+fn shop_prefix(prefix: Option<Extension<ShopPrefix>>) -> String {
+    prefix
+        .map(|Extension(ShopPrefix(prefix))| prefix)
+        .unwrap_or_default()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/title/{title_id}/versions",
+    tag = "catalog",
+    params(("title_id" = String, Path, description = "Any title ID of the title")),
+    responses(
+        (status = 200, description = "OK", body = TitleVersions),
+        (status = 400, description = "Invalid title ID", body = ErrorBody),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 404, description = "No such file or title", body = ErrorBody),
+    )
+)]
 async fn title_versions(
     State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
     Path(title_id): Path<String>,
     headers: HeaderMap,
 ) -> Result<Json<TitleVersions>, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
 
+    let overrides = state.overrides.snapshot().await;
     let catalog = state.catalog.read().await;
-    let versions = catalog.versions(&title_id).ok_or(ApiError::TitleNotFound)?;
+    let mut versions = catalog.versions(&title_id).ok_or(ApiError::TitleNotFound)?;
+    versions
+        .files
+        .retain(|file| !overrides.hides(file) && access.allows(file));
+    if versions.files.is_empty() {
+        return Err(ApiError::TitleNotFound);
+    }
     debug!(
         title_id = %versions.title_id,
         versions = versions.files.len(),
@@ -297,10 +1284,54 @@ async fn title_versions(
     Ok(Json(versions))
 }
 
+/// One aggregated record per base title, leaving out hidden titles.
+#[utoipa::path(
+    get,
+    path = "/api/library/titles",
+    tag = "catalog",
+    responses(
+        (status = 200, description = "OK", body = LibraryTitlesResponse),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn library_titles(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<LibraryTitlesResponse>, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let overrides = state.overrides.snapshot().await;
+    let mut titles = state
+        .catalog
+        .read()
+        .await
+        .titles_where(|file| access.allows(file));
+    titles.retain(|title| !overrides.is_hidden(&title.title_id));
+    debug!(titles = titles.len(), "library titles requested");
+    Ok(Json(LibraryTitlesResponse { titles }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/download/{path}",
+    tag = "downloads",
+    params(("path" = String, Path, description = "Library-relative path, as in catalog `url`s")),
+    responses(
+        (status = 200, description = "The file", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range", content_type = "application/octet-stream"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 403, description = "Download denied", body = ErrorBody),
+        (status = 404, description = "No such file or title", body = ErrorBody),
+        (status = 416, description = "Range not satisfiable", body = ErrorBody),
+    )
+)]
 async fn download(
     State(state): State<AppState>,
+    Access(access): Access,
     jar: CookieJar,
     PeerAddr(peer): PeerAddr,
+    shop_user: Option<Extension<ShopUser>>,
     Path(path): Path<String>,
     headers: HeaderMap,
 ) -> Result<Response, ApiError> {
@@ -310,6 +1341,15 @@ async fn download(
         .decode_utf8()
         .map_err(|_| ApiError::InvalidPath)?;
     let sanitized = sanitize_relative_path(&decoded).map_err(map_file_error)?;
+    if state.catalog.read().await.is_blocked_path(&sanitized) {
+        debug!(path = %sanitized.display(), "refusing download of blocked title");
+        return Err(ApiError::NotFound);
+    }
+    // Neither is in the catalog, but both are regular files under a root.
+    if trash(&state).contains(&sanitized) || is_partial(&sanitized) {
+        debug!(path = %sanitized.display(), "refusing download of trashed or partial file");
+        return Err(ApiError::NotFound);
+    }
     let title = sanitized
         .file_name()
         .and_then(|n: &std::ffi::OsStr| n.to_str())
@@ -321,10 +1361,32 @@ async fn download(
         title: title.clone(),
     });
 
-    let response = match stream_with_range_support(
-        &state.library_root,
+    let (root, sanitized) = resolve_library_root(&state, sanitized).await;
+    let file = state
+        .catalog
+        .read()
+        .await
+        .find_by_relative_path(&sanitized)
+        .cloned();
+    ensure_may_download(&access, file.as_ref())?;
+    ensure_not_hidden(&state.overrides.snapshot().await, file.as_ref())?;
+    let title_id = file.as_ref().and_then(|file| file.title_id.clone());
+    let user = download_user(&state, &jar, &headers, shop_user);
+    authorize_download(&state, &headers, user.clone(), peer, title_id, &sanitized).await?;
+    let client = user.unwrap_or_else(|| trusted_client_ip(&state, &headers, peer).to_string());
+    let slot = download_slot(&state, &client)?;
+    let options = StreamOptions {
+        read_buffer: state.read_buffer,
+        throttle: state
+            .bandwidth
+            .for_client(trusted_client_ip(&state, &headers, peer)),
+    };
+    let mut response = match stream_library_file(
+        &state,
+        &root,
         &sanitized,
         &headers,
+        options,
         log_ctx.as_ref(),
     )
     .await
@@ -335,6 +1397,19 @@ async fn download(
             return Err(map_file_error(error));
         }
     };
+    set_download_cache_headers(&mut response, state.auth.load().is_enabled());
+    match &file {
+        Some(file) => {
+            set_download_filename(&mut response, &file.name);
+            set_checksum_headers(&state, &mut response, file).await;
+        }
+        None => set_download_filename(&mut response, &title),
+    }
+    if starts_download(&headers, response.status()) {
+        state.downloads.record(&sanitized);
+    }
+    let response = tally_download(response, &state.downloads, sanitized.clone(), client);
+    let response = hold(response, slot);
     debug!(
         path = %sanitized.display(),
         status = %response.status(),
@@ -344,35 +1419,450 @@ async fn download(
     Ok(response)
 }
 
-async fn download_by_id(
-    State(state): State<AppState>,
-    jar: CookieJar,
-    PeerAddr(peer): PeerAddr,
-    Path(id): Path<usize>,
-    headers: HeaderMap,
-) -> Result<Response, ApiError> {
-    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+/// Whether a response sends a file from the start, so resumed or chunked range
+/// requests for the same download are only counted once, and revalidations not at all.
+fn starts_download(headers: &HeaderMap, status: StatusCode) -> bool {
+    match status {
+        StatusCode::OK => true,
+        StatusCode::PARTIAL_CONTENT => headers
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|range| range.trim().starts_with("bytes=0-")),
+        _ => false,
+    }
+}
+
+/// User a download request is authorized as, if any. The request is already
+/// authorized, so any credentials it carries are valid.
+fn download_user(
+    state: &AppState,
+    jar: &CookieJar,
+    headers: &HeaderMap,
+    shop_user: Option<Extension<ShopUser>>,
+) -> Option<String> {
+    shop_user
+        .map(|Extension(ShopUser(user))| user)
+        .or_else(|| {
+            jar.get(SESSION_COOKIE)
+                .and_then(|cookie| session_user(state, cookie.value()))
+        })
+        .or_else(|| token_user(state, headers))
+        .or_else(|| {
+            extract_basic_auth(headers)
+                .filter(|_| state.auth.load().is_enabled())
+                .map(|(username, _)| username)
+        })
+}
+
+/// Refuse a download `access` doesn't allow: any for view-only users, and files outside
+/// the user's kinds and titles, which they can't see either.
+fn ensure_may_download(access: &UserAccess, file: Option<&ContentFile>) -> Result<(), ApiError> {
+    if !access.can_download() {
+        debug!("download refused: account is view-only");
+        return Err(ApiError::Forbidden);
+    }
+    if access.sees_everything() || file.is_some_and(|file| access.allows(file)) {
+        Ok(())
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+/// Refuse a file hidden from the shop as if it didn't exist. Listings leave it out, but
+/// file IDs index the full catalog and paths reach it directly.
+fn ensure_not_hidden(overrides: &Overrides, file: Option<&ContentFile>) -> Result<(), ApiError> {
+    if file.is_some_and(|file| overrides.hides(file)) {
+        debug!("download refused: file is hidden");
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+/// One of `client`'s `[downloads] max_concurrent_per_client` slots, held by the response
+/// until it is sent.
+fn download_slot(state: &AppState, client: &str) -> Result<DownloadSlot, ApiError> {
+    state.download_slots.try_acquire(client).ok_or_else(|| {
+        let limit = state.download_slots.limit().unwrap_or_default();
+        debug!(client, limit, "download refused: no free download slot");
+        ApiError::TooManyDownloads(limit)
+    })
+}
+
+/// Ask the `[hooks]` download hook whether a download may go ahead.
+async fn authorize_download(
+    state: &AppState,
+    headers: &HeaderMap,
+    user: Option<String>,
+    peer: Option<SocketAddr>,
+    title_id: Option<String>,
+    relative_path: &std::path::Path,
+) -> Result<(), ApiError> {
+    let request = DownloadRequest {
+        user,
+        title_id,
+        file: url_path(relative_path),
+        ip: trusted_client_ip(state, headers, peer),
+        range: headers
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    };
+    match state.download_hook.authorize(&request).await {
+        Verdict::Allow => Ok(()),
+        Verdict::Deny(reason) => {
+            info!(
+                user = request.user.as_deref().unwrap_or("-"),
+                file = %request.file,
+                ip = %request.ip,
+                "download denied by hook"
+            );
+            Err(ApiError::DownloadDenied(reason))
+        }
+    }
+}
+
+/// Stream `relative_path` under `root`, or zeros of its listed size under `--demo`.
+async fn stream_library_file(
+    state: &AppState,
+    root: &std::path::Path,
+    relative_path: &std::path::Path,
+    headers: &HeaderMap,
+    options: StreamOptions,
+    log_context: Option<&DownloadLogContext>,
+) -> Result<Response, FileServeError> {
+    if !state.library.is_demo() {
+        return stream_with_range_support(root, relative_path, headers, options, log_context).await;
+    }
+    let (size, modified) = state
+        .catalog
+        .read()
+        .await
+        .find_by_relative_path(relative_path)
+        .map(|file| (file.size, file.modified))
+        .ok_or(FileServeError::NotFound)?;
+    stream_zeros_with_range_support(relative_path, size, modified, headers, options, log_context)
+        .await
+}
+
+/// Pick the library root and on-disk path serving a requested relative path: the
+/// catalog entry when indexed (matching case-insensitively if unambiguous), otherwise
+/// the first root where the file exists (e.g. not yet rescanned).
+async fn resolve_library_root(state: &AppState, relative_path: PathBuf) -> (PathBuf, PathBuf) {
+    if let Some(file) = state
+        .catalog
+        .read()
+        .await
+        .find_by_relative_path(&relative_path)
+    {
+        return (file.root.clone(), file.relative_path.clone());
+    }
+    for root in state.library.roots() {
+        if tokio::fs::metadata(root.join(&relative_path))
+            .await
+            .is_ok_and(|meta| meta.is_file())
+        {
+            return (root.clone(), relative_path);
+        }
+    }
+    (
+        state.library.roots().first().cloned().unwrap_or_default(),
+        relative_path,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/get_game/{id}",
+    tag = "downloads",
+    params(("id" = usize, Path, description = "`file_id` from `/api/shop/sections`")),
+    responses(
+        (status = 200, description = "The file", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range", content_type = "application/octet-stream"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 403, description = "Download denied", body = ErrorBody),
+        (status = 404, description = "No such file or title", body = ErrorBody),
+        (status = 416, description = "Range not satisfiable", body = ErrorBody),
+    )
+)]
+async fn download_by_id(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    PeerAddr(peer): PeerAddr,
+    shop_user: Option<Extension<ShopUser>>,
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    serve_by_id(&state, &access, &jar, peer, shop_user, id, &headers, false).await
+}
 
-    let (relative_path, filename) = {
+/// The file cut into `?size=` byte ranges (64 MiB by default), each with its SHA-256, for
+/// clients that fetch large files range by range and check each one. The first request
+/// for a map reads the whole file.
+#[utoipa::path(
+    get,
+    path = "/api/get_game/{id}/chunks",
+    tag = "downloads",
+    params(
+        ("id" = usize, Path, description = "`file_id` from `/api/shop/sections`"),
+        ChunksQuery,
+    ),
+    responses(
+        (status = 200, description = "The chunk map", body = ChunkMapResponse),
+        (status = 400, description = "Chunk size out of range", body = ErrorBody),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 429, description = "Too many downloads at once", body = ErrorBody),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+async fn download_chunks(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    PeerAddr(peer): PeerAddr,
+    shop_user: Option<Extension<ShopUser>>,
+    Path(id): Path<usize>,
+    Query(query): Query<ChunksQuery>,
+    headers: HeaderMap,
+) -> Result<Json<ChunkMapResponse>, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let chunk_size = match query.size.as_deref() {
+        Some(size) => parse_size(size.trim()).map_err(|_| ApiError::InvalidChunkSize)?,
+        None => chunks::DEFAULT_CHUNK_SIZE,
+    };
+    if !(chunks::MIN_CHUNK_SIZE..=chunks::MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(ApiError::InvalidChunkSize);
+    }
+    let file = {
         let catalog = state.catalog.read().await;
         let index = id.checked_sub(1).ok_or(ApiError::NotFound)?;
-        let file = catalog.files().get(index).ok_or(ApiError::NotFound)?;
-        (file.relative_path.clone(), file.name.clone())
+        catalog
+            .files()
+            .get(index)
+            .ok_or(ApiError::NotFound)?
+            .clone()
+    };
+    // Computing a map reads the file like a download does.
+    ensure_may_download(&access, Some(&file))?;
+    ensure_not_hidden(&state.overrides.snapshot().await, Some(&file))?;
+    let user = download_user(&state, &jar, &headers, shop_user);
+    let client = user.unwrap_or_else(|| trusted_client_ip(&state, &headers, peer).to_string());
+    let _slot = download_slot(&state, &client)?;
+
+    let download = if state.library.is_demo() {
+        Download::zeros(&file.relative_path, file.size, file.modified)
+    } else {
+        Download::open(&file.root, &file.relative_path)
+            .await
+            .map_err(map_file_error)?
+    };
+    let key = ChunkKey {
+        path: file.root.join(&file.relative_path),
+        size: download.len(),
+        modified: file.modified,
+        chunk_size,
+    };
+    let hashes = state
+        .chunk_maps
+        .hashes(key, || download.stream(state.read_buffer))
+        .await
+        .map_err(|err| {
+            warn!(file_id = id, error = %err, "chunk map failed");
+            ApiError::Internal
+        })?;
+    let size = download.len();
+    let chunks = hashes
+        .iter()
+        .enumerate()
+        .map(|(index, sha256)| {
+            let start = index as u64 * chunk_size;
+            ChunkEntry {
+                start,
+                end: (start + chunk_size).min(size) - 1,
+                sha256: sha256.clone(),
+            }
+        })
+        .collect();
+    Ok(Json(ChunkMapResponse {
+        file_id: id,
+        name: file.name,
+        size,
+        chunk_size,
+        chunks,
+    }))
+}
+
+/// SHA-256 and BLAKE3 of a file, from the background hashing pass (`--hash-files`), to
+/// check a download against. Downloads carry them as `X-Content-SHA256` and
+/// `X-Content-BLAKE3` as well.
+#[utoipa::path(
+    get,
+    path = "/api/file/{id}/checksum",
+    tag = "downloads",
+    params(("id" = usize, Path, description = "`file_id` from `/api/shop/sections`")),
+    responses(
+        (status = 200, description = "The file's digests", body = ChecksumResponse),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 404, description = "No such file, or not hashed yet", body = ErrorBody),
+    )
+)]
+async fn file_checksum(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+) -> Result<Json<ChecksumResponse>, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let overrides = state.overrides.snapshot().await;
+    let file = {
+        let catalog = state.catalog.read().await;
+        let index = id.checked_sub(1).ok_or(ApiError::NotFound)?;
+        catalog
+            .files()
+            .get(index)
+            .filter(|file| !overrides.hides(file) && access.allows(file))
+            .ok_or(ApiError::NotFound)?
+            .clone()
+    };
+    let digests = state
+        .library
+        .digests(&file)
+        .await
+        .ok_or(ApiError::NotHashed)?;
+    Ok(Json(ChecksumResponse {
+        file_id: id,
+        name: file.name,
+        size: file.size,
+        sha256: digests.sha256,
+        blake3: digests.blake3,
+    }))
+}
+
+const CONTENT_SHA256: HeaderName = HeaderName::from_static("x-content-sha256");
+const CONTENT_BLAKE3: HeaderName = HeaderName::from_static("x-content-blake3");
+
+/// Digests of the whole file on a download of it, once hashed.
+async fn set_checksum_headers(state: &AppState, response: &mut Response, file: &ContentFile) {
+    if !matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    ) {
+        return;
+    }
+    let Some(digests) = state.library.digests(file).await else {
+        return;
+    };
+    for (name, digest) in [
+        (CONTENT_SHA256, digests.sha256),
+        (CONTENT_BLAKE3, digests.blake3),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&digest) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+}
+
+/// An `.nsz` decompressed to NSP while it streams, for clients that can't install NSZ.
+/// Only served with `[downloads] transcode_nsz` set.
+#[utoipa::path(
+    get,
+    path = "/api/get_game/{id}/nsp",
+    tag = "downloads",
+    params(("id" = usize, Path, description = "`file_id` of an `.nsz` from `/api/shop/sections`")),
+    responses(
+        (status = 200, description = "The NSP", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range", content_type = "application/octet-stream"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 403, description = "Download denied", body = ErrorBody),
+        (status = 404, description = "No such file, not an NSZ, or transcoding is off", body = ErrorBody),
+        (status = 416, description = "Range not satisfiable", body = ErrorBody),
+    )
+)]
+async fn download_nsp_by_id(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    PeerAddr(peer): PeerAddr,
+    shop_user: Option<Extension<ShopUser>>,
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if !state.transcode_nsz {
+        return Err(ApiError::NotFound);
+    }
+    serve_by_id(&state, &access, &jar, peer, shop_user, id, &headers, true).await
+}
+
+/// Serve catalog file `id`, decompressed to NSP when `as_nsp` is set.
+#[allow(clippy::too_many_arguments)]
+async fn serve_by_id(
+    state: &AppState,
+    access: &UserAccess,
+    jar: &CookieJar,
+    peer: Option<SocketAddr>,
+    shop_user: Option<Extension<ShopUser>>,
+    id: usize,
+    headers: &HeaderMap,
+    as_nsp: bool,
+) -> Result<Response, ApiError> {
+    ensure_authorized(state, headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+
+    let file = {
+        let catalog = state.catalog.read().await;
+        let index = id.checked_sub(1).ok_or(ApiError::NotFound)?;
+        catalog
+            .files()
+            .get(index)
+            .ok_or(ApiError::NotFound)?
+            .clone()
     };
+    let (root, relative_path, filename, title_id) = (
+        file.root.clone(),
+        file.relative_path.clone(),
+        file.name.clone(),
+        file.title_id.clone(),
+    );
+    let is_nsz = relative_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("nsz"));
+    if as_nsp && !is_nsz {
+        return Err(ApiError::NotNsz);
+    }
+    ensure_may_download(access, Some(&file))?;
+    ensure_not_hidden(&state.overrides.snapshot().await, Some(&file))?;
+    let user = download_user(state, jar, headers, shop_user);
+    authorize_download(state, headers, user.clone(), peer, title_id, &relative_path).await?;
+    let client = user.unwrap_or_else(|| trusted_client_ip(state, headers, peer).to_string());
+    let slot = download_slot(state, &client)?;
 
     let log_ctx = peer.map(|ip| DownloadLogContext {
         ip,
         title: filename.clone(),
     });
 
-    let response = match stream_with_range_support(
-        &state.library_root,
-        &relative_path,
-        &headers,
-        log_ctx.as_ref(),
-    )
-    .await
-    {
+    let options = StreamOptions {
+        read_buffer: state.read_buffer,
+        throttle: state
+            .bandwidth
+            .for_client(trusted_client_ip(state, headers, peer)),
+    };
+    let streamed = if as_nsp {
+        stream_nsz_as_nsp(&root, &relative_path, headers, options, log_ctx.as_ref()).await
+    } else {
+        stream_library_file(
+            state,
+            &root,
+            &relative_path,
+            headers,
+            options,
+            log_ctx.as_ref(),
+        )
+        .await
+    };
+    let mut response = match streamed {
         Ok(r) => r,
         Err(error) => {
             warn!(
@@ -386,6 +1876,21 @@ async fn download_by_id(
         }
     };
 
+    set_download_cache_headers(&mut response, state.auth.load().is_enabled());
+    if as_nsp {
+        let stem = filename
+            .rsplit_once('.')
+            .map_or(filename.as_str(), |(stem, _)| stem);
+        set_download_filename(&mut response, &format!("{stem}.nsp"));
+    } else {
+        set_download_filename(&mut response, &filename);
+        set_checksum_headers(state, &mut response, &file).await;
+    }
+    if starts_download(headers, response.status()) {
+        state.downloads.record(&relative_path);
+    }
+    let response = tally_download(response, &state.downloads, relative_path.clone(), client);
+    let response = hold(response, slot);
     debug!(
         file_id = id,
         filename = %filename,
@@ -397,42 +1902,387 @@ async fn download_by_id(
     Ok(response)
 }
 
+/// Most files one batch download may hold.
+const MAX_BATCH_FILES: usize = 1000;
+
+/// The selected files as one uncompressed ZIP, streamed as it is built, with each file
+/// under its library path.
+#[utoipa::path(
+    post,
+    path = "/api/download/batch",
+    tag = "downloads",
+    request_body(
+        content = BatchDownloadRequest,
+        description = "JSON, or a form with repeated `ids` and `title_ids` fields",
+    ),
+    responses(
+        (status = 200, description = "The ZIP", content_type = "application/zip"),
+        (status = 400, description = "Nothing selected, or too many files", body = ErrorBody),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 403, description = "Download of a selected file denied", body = ErrorBody),
+        (status = 404, description = "No such file or title", body = ErrorBody),
+    )
+)]
+async fn download_batch(
+    State(state): State<AppState>,
+    Access(access): Access,
+    jar: CookieJar,
+    PeerAddr(peer): PeerAddr,
+    shop_user: Option<Extension<ShopUser>>,
+    headers: HeaderMap,
+    request: Request<Body>,
+) -> Result<Response, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    if !access.can_download() {
+        return Err(ApiError::Forbidden);
+    }
+    let is_json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let selection = if is_json {
+        Json::<BatchDownloadRequest>::from_request(request, &state)
+            .await
+            .map(|Json(selection)| selection)
+            .ok()
+    } else {
+        Form::<BatchDownloadRequest>::from_request(request, &state)
+            .await
+            .map(|Form(selection)| selection)
+            .ok()
+    }
+    .ok_or(ApiError::InvalidBatch(
+        "expected JSON or a form with `ids` or `title_ids`",
+    ))?;
+
+    // A single title is named after it; anything else is a generic download.
+    let filename = match (selection.ids.as_slice(), selection.title_ids.as_slice()) {
+        ([], [title_id]) => {
+            normalize_title_id(title_id).map(|title_id| format!("ownfoil-{title_id}.zip"))
+        }
+        _ => None,
+    }
+    .unwrap_or_else(|| String::from("ownfoil-download.zip"));
+    let overrides = state.overrides.snapshot().await;
+    let files = {
+        let catalog = state.catalog.read().await;
+        let mut picked = Vec::new();
+        for id in &selection.ids {
+            let index = id.checked_sub(1).ok_or(ApiError::NotFound)?;
+            let file = catalog.files().get(index).ok_or(ApiError::NotFound)?;
+            ensure_may_download(&access, Some(file))?;
+            ensure_not_hidden(&overrides, Some(file))?;
+            picked.push(index);
+        }
+        for title_id in &selection.title_ids {
+            let title_id = title_id.to_ascii_uppercase();
+            let before = picked.len();
+            picked.extend(
+                catalog
+                    .files()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, file)| {
+                        derive_base_title_id(file.kind, file.title_id.as_deref()).as_deref()
+                            == Some(title_id.as_str())
+                            && !overrides.hides(file)
+                            && access.allows(file)
+                    })
+                    .map(|(index, _)| index),
+            );
+            if picked.len() == before {
+                return Err(ApiError::TitleNotFound);
+            }
+        }
+        let mut seen = HashSet::new();
+        picked.retain(|index| seen.insert(*index));
+        picked
+            .into_iter()
+            .map(|index| catalog.files()[index].clone())
+            .collect::<Vec<_>>()
+    };
+    if files.is_empty() {
+        return Err(ApiError::InvalidBatch("select at least one file"));
+    }
+    if files.len() > MAX_BATCH_FILES {
+        return Err(ApiError::InvalidBatch("at most 1000 files per batch"));
+    }
+
+    let user = download_user(&state, &jar, &headers, shop_user);
+    for file in &files {
+        authorize_download(
+            &state,
+            &headers,
+            user.clone(),
+            peer,
+            file.title_id.clone(),
+            &file.relative_path,
+        )
+        .await?;
+    }
+    let client = user.unwrap_or_else(|| trusted_client_ip(&state, &headers, peer).to_string());
+    let slot = download_slot(&state, &client)?;
+
+    let mut downloads = Vec::with_capacity(files.len());
+    for file in &files {
+        let download = if state.library.is_demo() {
+            Download::zeros(&file.relative_path, file.size, file.modified)
+        } else {
+            Download::open(&file.root, &file.relative_path)
+                .await
+                .map_err(map_file_error)?
+        };
+        downloads.push(download);
+    }
+    let zip = StoredZip::new(
+        files
+            .iter()
+            .zip(&downloads)
+            .map(|(file, download)| ZipEntry {
+                name: url_path(&file.relative_path),
+                size: download.len(),
+                modified: download.modified(),
+            })
+            .collect(),
+    );
+    let len = zip.len();
+    for file in &files {
+        state.downloads.record(&file.relative_path);
+    }
+    debug!(files = files.len(), bytes = len, client = %client, "batch download started");
+
+    let downloads = Arc::new(downloads);
+    let paths: Arc<Vec<_>> = Arc::new(files.into_iter().map(|file| file.relative_path).collect());
+    let stats = state.downloads.clone();
+    let read_buffer = state.read_buffer;
+    let body = zip.stream(move |index| {
+        let downloads = Arc::clone(&downloads);
+        let paths = Arc::clone(&paths);
+        let stats = stats.clone();
+        let client = client.clone();
+        async move {
+            let download = &downloads[index];
+            let stream = download.stream(read_buffer).await?;
+            Ok(tally_stream(
+                stream,
+                &stats,
+                paths[index].clone(),
+                client,
+                download.len(),
+            ))
+        }
+    });
+    let body = state
+        .bandwidth
+        .for_client(trusted_client_ip(&state, &headers, peer))
+        .apply(body);
+    let response = (
+        [
+            (CONTENT_TYPE, String::from("application/zip")),
+            (CONTENT_LENGTH, len.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+            (CACHE_CONTROL, String::from("no-store")),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response();
+    Ok(hold(response, slot))
+}
+
+/// `?mb=` megabytes of random data (default 100) for measuring download throughput
+/// without the library's storage in the path.
+#[utoipa::path(
+    get,
+    path = "/api/speedtest",
+    tag = "server",
+    params(SpeedTestQuery),
+    responses(
+        (status = 200, description = "Random bytes", content_type = "application/octet-stream"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+        (status = 429, description = "A test from this client is already running", body = ErrorBody),
+    )
+)]
+async fn speedtest(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    PeerAddr(peer): PeerAddr,
+    Query(query): Query<SpeedTestQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let megabytes = query
+        .mb
+        .unwrap_or(speedtest::DEFAULT_MEGABYTES)
+        .clamp(1, speedtest::MAX_MEGABYTES);
+    let client = client_ip(&headers, peer);
+    state
+        .speedtests
+        .try_start(client)
+        .map_err(ApiError::RateLimited)?;
+    let len = megabytes * 1024 * 1024;
+    debug!(client = %client, megabytes, "speed test started");
+    Ok((
+        [
+            (CONTENT_TYPE, String::from("application/octet-stream")),
+            (CONTENT_LENGTH, len.to_string()),
+            (CACHE_CONTROL, String::from("no-store")),
+        ],
+        Body::from_stream(speedtest::random_stream(len)),
+    )
+        .into_response())
+}
+
+/// The OpenAPI document of the shop routes. Served through `/u/{token}/` as well, with that
+/// prefix in its server URL.
+async fn openapi_json(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    prefix: Option<Extension<ShopPrefix>>,
+) -> Json<utoipa::openapi::OpenApi> {
+    let server = format!("{}{}", url_prefix(&state, &headers), shop_prefix(prefix));
+    Json(openapi::document(&server))
+}
+
+/// Default edge length for SVG art rendered to PNG, matching the placeholder.
+const ICON_SIZE: u32 = 256;
+const MAX_ICON_SIZE: u32 = 1024;
+
+#[utoipa::path(
+    get,
+    path = "/api/shop/icon/{title_id}",
+    tag = "artwork",
+    params(
+        ("title_id" = String, Path),
+        ImageQuery,
+    ),
+    responses(
+        (status = 200, description = "Icon, or a placeholder", content_type = "image/*"),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn shop_icon(
     State(state): State<AppState>,
     jar: CookieJar,
     Path(title_id): Path<String>,
+    Query(query): Query<ImageQuery>,
     headers: HeaderMap,
 ) -> Result<Response, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
     let tid = title_id.trim_end_matches(".png");
+    if let Some(artwork) = state.artwork.icon_override(tid).await {
+        return Ok(artwork_response(
+            negotiate_image(&state, &headers, &query, artwork).await,
+            &headers,
+        ));
+    }
     if let Some(info) = state.titledb.lookup(tid).await {
         if let Some(url) = info.icon_url {
             if url.starts_with("http") {
+                if let Some(artwork) = state.artwork.cached(&url).await {
+                    return Ok(artwork_response(
+                        negotiate_image(&state, &headers, &query, artwork).await,
+                        &headers,
+                    ));
+                }
                 return Ok(Redirect::temporary(&url).into_response());
             }
         }
     }
-    Ok(static_png_response())
+    if let Some(artwork) = state.artwork.icon(tid).await {
+        return Ok(artwork_response(
+            negotiate_image(&state, &headers, &query, artwork).await,
+            &headers,
+        ));
+    }
+    Ok(static_png_response(
+        negotiate_image(&state, &headers, &query, placeholder_artwork()).await,
+        &headers,
+    ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/shop/banner/{title_id}",
+    tag = "artwork",
+    params(
+        ("title_id" = String, Path),
+        ImageQuery,
+    ),
+    responses(
+        (status = 200, description = "Banner, or a placeholder", content_type = "image/*"),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn shop_banner(
     State(state): State<AppState>,
     jar: CookieJar,
     Path(title_id): Path<String>,
+    Query(query): Query<ImageQuery>,
     headers: HeaderMap,
 ) -> Result<Response, ApiError> {
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
     let tid = title_id.trim_end_matches(".png");
+    if let Some(artwork) = state.artwork.banner_override(tid).await {
+        return Ok(artwork_response(
+            negotiate_image(&state, &headers, &query, artwork).await,
+            &headers,
+        ));
+    }
     if let Some(info) = state.titledb.lookup(tid).await {
         if let Some(url) = info.banner_url {
             if url.starts_with("http") {
+                if let Some(artwork) = state.artwork.cached(&url).await {
+                    return Ok(artwork_response(
+                        negotiate_image(&state, &headers, &query, artwork).await,
+                        &headers,
+                    ));
+                }
                 return Ok(Redirect::temporary(&url).into_response());
             }
         }
     }
-    Ok(static_png_response())
+    Ok(static_png_response(
+        negotiate_image(&state, &headers, &query, placeholder_artwork()).await,
+        &headers,
+    ))
+}
+
+/// Render SVG art to PNG unless the client says it accepts SVG.
+async fn negotiate_image(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: &ImageQuery,
+    artwork: Artwork,
+) -> Artwork {
+    if !is_svg(&artwork.content_type) || accepts_svg(headers) {
+        return artwork;
+    }
+    let size = query.size.unwrap_or(ICON_SIZE).clamp(16, MAX_ICON_SIZE);
+    match state.artwork.svg_to_png(&artwork.bytes, size).await {
+        Some(bytes) => Artwork {
+            bytes,
+            content_type: String::from("image/png"),
+            ..artwork
+        },
+        None => artwork,
+    }
 }
 
+#[cfg(feature = "saves")]
+#[utoipa::path(
+    get,
+    path = "/api/saves/list",
+    tag = "saves",
+    responses(
+        (status = 200, description = "OK", body = SavesListResponse),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
 async fn saves_list(
     State(state): State<AppState>,
     jar: CookieJar,
@@ -445,31 +2295,66 @@ async fn saves_list(
     }))
 }
 
+#[cfg(feature = "admin-ui")]
 #[derive(serde::Deserialize)]
 struct LoginForm {
     username: String,
     password: String,
 }
 
-async fn login_page(State(state): State<AppState>, jar: CookieJar) -> Result<Response, ApiError> {
+/// An admin page whose relative URLs resolve under the prefix the client reached us at.
+#[cfg(feature = "admin-ui")]
+fn admin_page(state: &AppState, headers: &HeaderMap, name: &str) -> Result<Response, ApiError> {
+    let base = format!("{}/", url_prefix(state, headers));
+    assets::page(name, &base).ok_or_else(|| {
+        warn!(page = name, "admin page missing from the embedded assets");
+        ApiError::Internal
+    })
+}
+
+/// A stylesheet or script of the admin and API docs pages; public, like the login page.
+#[cfg(feature = "admin-ui")]
+async fn admin_asset(Path(file): Path<String>, headers: HeaderMap) -> Result<Response, ApiError> {
+    assets::asset(&file, &headers).ok_or(ApiError::NotFound)
+}
+
+/// Swagger UI over [`openapi_json`]. Public: the document lists routes, not content.
+#[cfg(feature = "admin-ui")]
+async fn api_docs(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, ApiError> {
+    admin_page(&state, &headers, "api-docs.html")
+}
+
+#[cfg(feature = "admin-ui")]
+async fn login_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Response, ApiError> {
     ensure_admin_enabled(&state)?;
     if jar
         .get(SESSION_COOKIE)
-        .and_then(|c| state.sessions.get(c.value()))
+        .and_then(|c| session_user(&state, c.value()))
         .is_some()
     {
         return Ok(Redirect::to("/admin").into_response());
     }
-    Ok(Html(include_str!("login.html")).into_response())
+    admin_page(&state, &headers, "login.html")
 }
 
+#[cfg(feature = "admin-ui")]
 async fn login_post(
     State(state): State<AppState>,
     jar: CookieJar,
     Form(form): Form<LoginForm>,
 ) -> Result<(CookieJar, Redirect), ApiError> {
     ensure_admin_enabled(&state)?;
-    if !state.auth.is_authorized(&form.username, &form.password) {
+    let auth = state.auth.load();
+    // Only admins have anything to do here; others get the same error as a wrong password.
+    if !auth.is_authorized(&form.username, &form.password)
+        || !auth
+            .access(&form.username)
+            .is_some_and(UserAccess::is_admin)
+    {
         return Ok((jar, Redirect::to("/admin/login?error=1")));
     }
     let token = state.sessions.create(form.username);
@@ -483,18 +2368,24 @@ async fn login_post(
     Ok((jar.add(cookie), Redirect::to("/admin")))
 }
 
-async fn admin_ui(State(state): State<AppState>, jar: CookieJar) -> Result<Response, ApiError> {
+#[cfg(feature = "admin-ui")]
+async fn admin_ui(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Response, ApiError> {
     ensure_admin_enabled(&state)?;
     let session_valid = jar
         .get(SESSION_COOKIE)
-        .and_then(|c| state.sessions.get(c.value()))
+        .and_then(|c| session_user(&state, c.value()))
         .is_some();
     if !session_valid {
         return Ok(Redirect::to("/admin/login").into_response());
     }
-    Ok(Html(include_str!("admin.html")).into_response())
+    admin_page(&state, &headers, "admin.html")
 }
 
+#[cfg(feature = "admin-ui")]
 async fn logout(
     State(state): State<AppState>,
     jar: CookieJar,
@@ -509,20 +2400,43 @@ async fn logout(
     ))
 }
 
-async fn settings_ui(State(state): State<AppState>, jar: CookieJar) -> Result<Response, ApiError> {
+#[cfg(feature = "admin-ui")]
+async fn settings_ui(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Response, ApiError> {
+    ensure_admin_enabled(&state)?;
+    let session_valid = jar
+        .get(SESSION_COOKIE)
+        .and_then(|c| session_user(&state, c.value()))
+        .is_some();
+    if !session_valid {
+        return Ok(Redirect::to("/admin/login").into_response());
+    }
+    admin_page(&state, &headers, "settings.html")
+}
+
+#[cfg(feature = "admin-ui")]
+async fn users_ui(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Response, ApiError> {
     ensure_admin_enabled(&state)?;
     let session_valid = jar
         .get(SESSION_COOKIE)
-        .and_then(|c| state.sessions.get(c.value()))
+        .and_then(|c| session_user(&state, c.value()))
         .is_some();
     if !session_valid {
         return Ok(Redirect::to("/admin/login").into_response());
     }
-    Ok(Html(include_str!("settings.html")).into_response())
+    admin_page(&state, &headers, "users.html")
 }
 
 #[derive(serde::Serialize)]
 struct SettingsResponse {
+    revision: u64,
     titledb: TitleDbConfig,
     titledb_entries: usize,
     titledb_last_refresh: Option<String>,
@@ -530,6 +2444,8 @@ struct SettingsResponse {
 
 #[derive(serde::Deserialize)]
 struct SettingsPost {
+    /// Revision the client's form was loaded from; stale revisions get 409.
+    revision: u64,
     titledb: Option<TitleDbConfig>,
 }
 
@@ -548,6 +2464,7 @@ async fn settings_get(
         .await
         .map(|t| humantime::format_duration(t.elapsed()).to_string());
     Ok(Json(SettingsResponse {
+        revision: state.settings.current().await,
         titledb,
         titledb_entries: entries,
         titledb_last_refresh: last_refresh,
@@ -562,14 +2479,1606 @@ async fn settings_post(
 ) -> Result<Json<serde_json::Value>, ApiError> {
     ensure_admin_enabled(&state)?;
     ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let mut revision = state.settings.lock().await;
+    if body.revision != *revision {
+        debug!(
+            expected = body.revision,
+            current = *revision,
+            "stale settings revision rejected"
+        );
+        return Err(ApiError::SettingsConflict);
+    }
     if let Some(titledb) = body.titledb {
+        let next = *revision + 1;
         state.titledb.set_config(titledb.clone()).await;
-        if let Err(e) = super::settings::save_settings(&state.data_dir, &titledb) {
-            tracing::warn!(error = %e, "failed to save settings");
+        let data_dir = state.data_dir.clone();
+        let saved = tokio::task::spawn_blocking(move || {
+            super::settings::save_settings(&data_dir, next, &titledb)
+        })
+        .await;
+        match saved {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!(error = %e, "failed to save settings"),
+            Err(e) => tracing::warn!(error = %e, "settings save task failed"),
         }
+        *revision = next;
+        state.settings.publish(next);
         state.titledb.refresh();
     }
-    Ok(Json(serde_json::json!({ "success": true })))
+    Ok(Json(
+        serde_json::json!({ "success": true, "revision": *revision }),
+    ))
+}
+
+/// Re-read one title's files, TitleDB entry, and fallback icon without a full rescan.
+async fn title_refresh(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<TitleRefreshResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    let base_title_id = derive_base_title_id(classify_title_id(Some(&title_id)), Some(&title_id))
+        .ok_or(ApiError::InvalidTitleId)?;
+
+    let files = state
+        .library
+        .refresh_title(&base_title_id)
+        .await
+        .map_err(|err| {
+            warn!(title_id = %base_title_id, error = %err, "title refresh failed");
+            ApiError::Internal
+        })?;
+    if files.refreshed == 0 && files.removed == 0 {
+        return Err(ApiError::TitleNotFound);
+    }
+    let info = state.titledb.lookup(&base_title_id).await;
+    let icon = info.as_ref().is_some_and(|info| info.icon_url.is_some())
+        || state.artwork.refresh_icon(&base_title_id).await;
+    debug!(
+        title_id = %base_title_id,
+        refreshed = files.refreshed,
+        removed = files.removed,
+        icon,
+        "title refreshed"
+    );
+    Ok(Json(TitleRefreshResponse {
+        title_id: base_title_id,
+        files,
+        name: info.and_then(|info| info.name),
+        icon,
+    }))
+}
+
+async fn overrides_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<Overrides>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(state.overrides.snapshot().await))
+}
+
+/// Merge names, tags, and notes from a CSV or JSON export; see [`crate::annotations`].
+async fn overrides_import(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(query): Query<AnnotationImportQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<AnnotationImportResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let format = query.format.unwrap_or_else(|| {
+        let content_type = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+        AnnotationFormat::detect(content_type, &body)
+    });
+    let parsed = annotations::parse(&body, format)?;
+    let unknown_titles = {
+        let catalog = state.catalog.read().await;
+        let known: HashSet<&str> = catalog
+            .files()
+            .iter()
+            .filter_map(|file| file.title_id.as_deref())
+            .collect();
+        annotations::unknown_titles(&parsed.annotations, &known)
+    };
+    let changed = if query.dry_run {
+        0
+    } else {
+        state
+            .overrides
+            .annotate(&parsed.annotations)
+            .await
+            .map_err(|err| {
+                warn!(error = %err, "failed to save imported annotations");
+                ApiError::Internal
+            })?
+    };
+    info!(
+        rows = parsed.rows,
+        changed,
+        skipped = parsed.errors.len(),
+        dry_run = query.dry_run,
+        "annotations imported"
+    );
+    Ok(Json(AnnotationImportResponse {
+        dry_run: query.dry_run,
+        rows: parsed.rows,
+        valid: parsed.annotations.len(),
+        changed,
+        unknown_titles,
+        errors: parsed.errors,
+    }))
+}
+
+async fn override_put(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+    Json(mut entry): Json<TitleOverride>,
+) -> Result<Json<TitleOverride>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    entry.name = entry
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    state
+        .overrides
+        .set(title_id.clone(), entry.clone())
+        .await
+        .map_err(|err| {
+            warn!(title_id = %title_id, error = %err, "failed to save title override");
+            ApiError::Internal
+        })?;
+    debug!(title_id = %title_id, hidden = entry.hidden, "title override saved");
+    Ok(Json(entry))
+}
+
+async fn override_delete(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    let removed = state.overrides.remove(&title_id).await.map_err(|err| {
+        warn!(title_id = %title_id, error = %err, "failed to remove title override");
+        ApiError::Internal
+    })?;
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+/// Upload a custom icon (PNG, JPEG, WebP, or SVG request body) for a title.
+async fn override_icon_put(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if body.is_empty() {
+        return Err(ApiError::UnsupportedImage);
+    }
+    let artwork = Artwork {
+        bytes: body,
+        content_type,
+        modified: None,
+        fetched: None,
+    };
+    match state.artwork.set_icon_override(&title_id, &artwork).await {
+        Ok(()) => {
+            debug!(title_id = %title_id, "custom icon saved");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => {
+            Err(ApiError::UnsupportedImage)
+        }
+        Err(err) => {
+            warn!(title_id = %title_id, error = %err, "failed to save custom icon");
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+async fn override_icon_delete(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    let removed = state
+        .artwork
+        .remove_icon_override(&title_id)
+        .await
+        .map_err(|err| {
+            warn!(title_id = %title_id, error = %err, "failed to remove custom icon");
+            ApiError::Internal
+        })?;
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+async fn blocklist_get(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<BlocklistResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let store = state.library.blocklist().ok_or(ApiError::NotFound)?;
+    let blocklist = store.snapshot().await;
+    Ok(Json(BlocklistResponse {
+        title_ids: blocklist.title_ids().map(String::from).collect(),
+        blocked_files: state.catalog.read().await.blocked_count(),
+    }))
+}
+
+async fn blocklist_put(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    let store = state.library.blocklist().ok_or(ApiError::NotFound)?;
+    store
+        .add(BTreeSet::from([title_id.clone()]))
+        .await
+        .map_err(|err| {
+            warn!(title_id = %title_id, error = %err, "failed to save blocklist");
+            ApiError::Internal
+        })?;
+    state.library.apply_blocklist().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn blocklist_delete(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+    let store = state.library.blocklist().ok_or(ApiError::NotFound)?;
+    let removed = store.remove(&title_id).await.map_err(|err| {
+        warn!(title_id = %title_id, error = %err, "failed to save blocklist");
+        ApiError::Internal
+    })?;
+    if !removed {
+        return Err(ApiError::NotFound);
+    }
+    state.library.apply_blocklist().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fetch a list of title IDs from a URL and add them to (or replace) the blocklist.
+async fn blocklist_import(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(request): Json<BlocklistImportRequest>,
+) -> Result<Json<BlocklistImportResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let store = state.library.blocklist().ok_or(ApiError::NotFound)?;
+    let title_ids = fetch_title_ids(&request.url).await.map_err(|err| {
+        warn!(url = %request.url, error = %err, "blocklist import failed");
+        ApiError::BlocklistImport(err.to_string())
+    })?;
+    let imported = title_ids.len();
+    let saved = if request.replace {
+        store.replace(title_ids).await
+    } else {
+        store.add(title_ids).await.map(|_| ())
+    };
+    saved.map_err(|err| {
+        warn!(error = %err, "failed to save blocklist");
+        ApiError::Internal
+    })?;
+    state.library.apply_blocklist().await;
+    Ok(Json(BlocklistImportResponse {
+        imported,
+        total: store.snapshot().await.len(),
+        blocked_files: state.catalog.read().await.blocked_count(),
+    }))
+}
+
+/// Active announcements, newest first. The newest is also the shop index's message.
+#[utoipa::path(
+    get,
+    path = "/api/announcements",
+    tag = "shop",
+    responses(
+        (status = 200, description = "OK", body = AnnouncementsResponse),
+        (status = 401, description = "Credentials required", body = ErrorBody),
+    )
+)]
+async fn announcements_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<AnnouncementsResponse>, ApiError> {
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(AnnouncementsResponse {
+        announcements: state.announcements.active(unix_now()).await,
+    }))
+}
+
+async fn announcement_create(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(draft): Json<AnnouncementDraft>,
+) -> Result<(StatusCode, Json<Announcement>), ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let now = unix_now();
+    let draft = validate_announcement(draft, now)?;
+    let announcement = state
+        .announcements
+        .create(draft, now)
+        .await
+        .map_err(|err| {
+            warn!(error = %err, "failed to save announcement");
+            ApiError::Internal
+        })?;
+    info!(id = announcement.id, "announcement created");
+    Ok((StatusCode::CREATED, Json(announcement)))
+}
+
+async fn announcement_update(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Json(draft): Json<AnnouncementDraft>,
+) -> Result<Json<Announcement>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let now = unix_now();
+    let draft = validate_announcement(draft, now)?;
+    state
+        .announcements
+        .update(id, draft, now)
+        .await
+        .map_err(|err| {
+            warn!(id, error = %err, "failed to save announcement");
+            ApiError::Internal
+        })?
+        .map(Json)
+        .ok_or(ApiError::NotFound)
+}
+
+async fn announcement_delete(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let removed = state.announcements.remove(id).await.map_err(|err| {
+        warn!(id, error = %err, "failed to remove announcement");
+        ApiError::Internal
+    })?;
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+fn validate_announcement(
+    mut draft: AnnouncementDraft,
+    now: u64,
+) -> Result<AnnouncementDraft, ApiError> {
+    draft.message = draft.message.trim().to_string();
+    if draft.message.is_empty() {
+        return Err(ApiError::InvalidAnnouncement("message must not be empty"));
+    }
+    if draft.expires_at.is_some_and(|at| at <= now) {
+        return Err(ApiError::InvalidAnnouncement("expires_at is in the past"));
+    }
+    Ok(draft)
+}
+
+async fn shop_tokens_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<ShopTokensResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let auth = state.auth.load();
+    let mut users = Vec::new();
+    for username in auth.usernames() {
+        let issued = state.shop_tokens.has_token(username).await;
+        users.push(ShopTokenEntry::listed(username, issued));
+    }
+    Ok(Json(ShopTokensResponse { users }))
+}
+
+/// Issue a new shop token for a user; the previous token stops working immediately.
+async fn shop_token_rotate(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ShopTokenEntry>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    if !state.auth.load().has_user(&username) {
+        return Err(ApiError::NotFound);
+    }
+    let token = state.shop_tokens.rotate(&username).await.map_err(|err| {
+        warn!(username = %username, error = %err, "failed to save shop token");
+        ApiError::Internal
+    })?;
+    Ok(Json(ShopTokenEntry::issued(&username, &token)))
+}
+
+async fn shop_token_revoke(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let removed = state.shop_tokens.revoke(&username).await.map_err(|err| {
+        warn!(username = %username, error = %err, "failed to save shop tokens");
+        ApiError::Internal
+    })?;
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+/// Run a change to the auth file off the async runtime: password hashes are slow on
+/// purpose.
+async fn edit_users<T: Send + 'static>(
+    state: &AppState,
+    edit: impl FnOnce(&SharedAuth) -> Result<T, UserEditError> + Send + 'static,
+) -> Result<T, ApiError> {
+    let auth = state.auth.clone();
+    tokio::task::spawn_blocking(move || edit(&auth))
+        .await
+        .map_err(|err| {
+            warn!(error = %err, "user change task failed");
+            ApiError::Internal
+        })?
+        .map_err(|err| match err {
+            UserEditError::NotFound(_) => ApiError::NotFound,
+            UserEditError::LastAdmin => ApiError::LastAdmin,
+            UserEditError::InvalidUsername
+            | UserEditError::PasswordRequired
+            | UserEditError::EmptyPassword
+            | UserEditError::InvalidTitleId(_) => ApiError::InvalidUser(err.to_string()),
+            err => {
+                warn!(error = %err, "failed to change users");
+                ApiError::Internal
+            }
+        })
+}
+
+/// Users in the auth file, disabled ones included, without their passwords.
+async fn users_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<UsersResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let users = edit_users(&state, SharedAuth::file_users).await?;
+    Ok(Json(UsersResponse {
+        users: users.into_iter().map(UserEntry::from).collect(),
+    }))
+}
+
+/// Create a user, or change the fields given for an existing one.
+async fn user_save(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(change): Json<UserChange>,
+) -> Result<(StatusCode, Json<UserEntry>), ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let (user, created) = edit_users(&state, move |auth| auth.save_user(&change)).await?;
+    info!(username = %user.username, created, "user saved");
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(UserEntry::from(user))))
+}
+
+/// Remove a user from the auth file and revoke their shop and API tokens.
+async fn user_delete(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let removed = username.clone();
+    edit_users(&state, move |auth| auth.remove_user(&removed)).await?;
+    info!(username = %username, "user removed");
+    if let Err(err) = state.shop_tokens.revoke(&username).await {
+        warn!(username = %username, error = %err, "failed to revoke shop token");
+    }
+    if let Err(err) = state.api_tokens.revoke_user(&username).await {
+        warn!(username = %username, error = %err, "failed to revoke API tokens");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Longest name an API token may have.
+const MAX_TOKEN_NAME_CHARS: usize = 100;
+
+/// API tokens of a user, without the tokens themselves.
+async fn api_tokens_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ApiTokensResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(ApiTokensResponse {
+        tokens: state.api_tokens.list(&username),
+    }))
+}
+
+/// Issue an API token for a user. The response is the only place the token appears.
+async fn api_token_issue(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<IssueTokenRequest>,
+) -> Result<(StatusCode, Json<IssuedTokenResponse>), ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    if !state.auth.load().has_user(&username) {
+        return Err(ApiError::NotFound);
+    }
+    let name = request.name.trim();
+    if name.chars().count() > MAX_TOKEN_NAME_CHARS {
+        return Err(ApiError::InvalidUser(format!(
+            "token name is longer than {MAX_TOKEN_NAME_CHARS} characters"
+        )));
+    }
+    let (info, token) = state
+        .api_tokens
+        .issue(&username, name, unix_now())
+        .await
+        .map_err(|err| {
+            warn!(username = %username, error = %err, "failed to save API token");
+            ApiError::Internal
+        })?;
+    info!(username = %username, id = info.id, "API token issued");
+    Ok((
+        StatusCode::CREATED,
+        Json(IssuedTokenResponse { info, token }),
+    ))
+}
+
+async fn api_token_revoke(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path((username, id)): Path<(String, u64)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let revoked = state
+        .api_tokens
+        .revoke(&username, id)
+        .await
+        .map_err(|err| {
+            warn!(username = %username, error = %err, "failed to save API tokens");
+            ApiError::Internal
+        })?;
+    if revoked {
+        info!(username = %username, id, "API token revoked");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+/// Serve the shop routes under `/u/{token}/` for the token's owner, without Basic auth.
+/// Unknown tokens and tokens of users no longer in the auth file get a plain 404.
+async fn token_shop(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request<Body>,
+) -> Result<Response, ApiError> {
+    let token = params.get("token").map(String::as_str).unwrap_or_default();
+    let username = state
+        .shop_tokens
+        .user_for(token)
+        .await
+        .filter(|username| state.auth.load().has_user(username))
+        .ok_or(ApiError::NotFound)?;
+    let access = access_for(&state, Some(&username));
+    debug!(username = %username, "authorized request using shop token");
+
+    let prefix = format!("/u/{token}");
+    let rest = request
+        .uri()
+        .path()
+        .strip_prefix(prefix.as_str())
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or("/");
+    let uri = match request.uri().query() {
+        Some(query) => format!("{rest}?{query}"),
+        None => rest.to_string(),
+    };
+    let uri: axum::http::Uri = uri.parse().map_err(|_| ApiError::InvalidPath)?;
+
+    // Start from a clean request: the outer route's path params must not leak into the
+    // inner router's extractors. Only the peer address is carried over.
+    let (parts, body) = request.into_parts();
+    let mut request = Request::new(body);
+    *request.method_mut() = parts.method;
+    *request.uri_mut() = uri;
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers;
+    if let Some(info) = parts
+        .extensions
+        .get::<axum::extract::ConnectInfo<SocketAddr>>()
+        .cloned()
+    {
+        request.extensions_mut().insert(info);
+    }
+    request.extensions_mut().insert(ShopPrefix(prefix.clone()));
+    request.extensions_mut().insert(ShopUser(username));
+    request.extensions_mut().insert(Access(access));
+
+    // The token already identified the user, so the inner routes run with auth open.
+    let mut shop_state = state.clone();
+    shop_state.auth = SharedAuth::new(AuthSettings::from_users(Vec::new()));
+    let response = shop_routes()
+        .with_state(shop_state)
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {});
+    prefix_json_response(response, &prefix).await
+}
+
+async fn library_rescan(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<RescanSummary>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let summary = state
+        .library
+        .rescan_all_with_summary()
+        .await
+        .map_err(|err| {
+            warn!(error = %err, "manual library rescan failed");
+            ApiError::Internal
+        })?;
+    debug!(
+        files = summary.files,
+        added = summary.added,
+        removed = summary.removed,
+        changed = summary.changed,
+        "manual library rescan finished"
+    );
+    Ok(Json(summary))
+}
+
+#[derive(serde::Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Full catalog inventory as a JSON or CSV download.
+async fn library_export(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(query): Query<ExportQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let catalog = state.catalog.read().await;
+    let body = crate::export::render(catalog.files(), query.format).map_err(|err| {
+        warn!(error = %err, "library export failed");
+        ApiError::Internal
+    })?;
+    debug!(files = catalog.files().len(), format = ?query.format, "library export requested");
+    let disposition = format!(
+        "attachment; filename=\"ownfoil-library.{}\"",
+        query.format.extension()
+    );
+    Ok((
+        [
+            (CONTENT_TYPE, query.format.content_type().to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn library_duplicates(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<DuplicatesResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let catalog = state.catalog.read().await;
+    let payload = build_duplicates_response(&catalog, state.library.hashing_enabled());
+    debug!(
+        by_hash = payload.by_hash.len(),
+        by_title_version = payload.by_title_version.len(),
+        "library duplicates requested"
+    );
+    Ok(Json(payload))
+}
+
+/// Completed downloads and bytes served, by title and by client.
+async fn download_stats(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<DownloadStatsResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let totals = state.downloads.totals();
+    let catalog = state.catalog.read().await;
+    let title_ids = downloaded_title_ids(&totals, &catalog);
+    let names = state
+        .titledb
+        .names_for(title_ids.iter().map(String::as_str))
+        .await;
+    let stats = build_download_stats(totals, &catalog, &names);
+    debug!(
+        downloads = stats.downloads,
+        clients = stats.clients.len(),
+        "download stats requested"
+    );
+    Ok(Json(stats))
+}
+
+/// Library totals for the admin dashboard and monitoring.
+#[cfg(feature = "metrics")]
+async fn library_stats(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<LibraryStatsResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let scans = state.library.scan_timings().await;
+    let roots = state.library.roots().to_vec();
+    let free_bytes = tokio::task::spawn_blocking(move || free_space(&roots))
+        .await
+        .ok()
+        .flatten();
+    let growth = state.growth.forecast(unix_now(), free_bytes).await;
+    let catalog = state.catalog.read().await;
+    let names = state.titledb.names_for(catalog.title_ids()).await;
+    let quotas = state.quotas.usage(&catalog);
+    let stats = build_library_stats(&catalog, &names, scans, growth, quotas);
+    debug!(
+        titles = stats.titles,
+        files = stats.files,
+        "library stats requested"
+    );
+    Ok(Json(stats))
+}
+
+/// Check the catalog and hash cache against the filesystem. Only `POST` with
+/// `?apply=true` changes anything.
+async fn library_fsck(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    method: Method,
+    Query(query): Query<FsckQuery>,
+    headers: HeaderMap,
+) -> Result<Json<FsckReport>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let apply = query.apply && method == Method::POST;
+    let report = state.library.fsck(apply).await.map_err(|err| {
+        warn!(error = %err, "library fsck failed");
+        ApiError::Internal
+    })?;
+    debug!(
+        applied = report.applied,
+        clean = report.is_clean(),
+        missing = report.missing.len(),
+        size_mismatches = report.size_mismatches.len(),
+        unreadable = report.unreadable.len(),
+        orphaned_hashes = report.orphaned_hashes,
+        stale_hashes = report.stale_hashes,
+        "library fsck"
+    );
+    Ok(Json(report))
+}
+
+/// Titles and paths left out of the shop, from the admin API and the config file.
+async fn library_hidden(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<HiddenResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let overrides = state.overrides.snapshot().await;
+    Ok(Json(HiddenResponse {
+        admin: overrides.hidden(),
+        config: overrides.config_hidden().clone(),
+    }))
+}
+
+async fn library_hide(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(request): Json<HideRequest>,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    set_hidden(&state, request, true).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Show a title or path hidden through the admin API again. Entries hidden in the config
+/// file are not affected.
+async fn library_unhide(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(request): Json<HideRequest>,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    if set_hidden(&state, request, false).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+/// Apply a hide or unhide request. Returns whether anything changed.
+async fn set_hidden(
+    state: &AppState,
+    request: HideRequest,
+    hidden: bool,
+) -> Result<bool, ApiError> {
+    let changed = match (request.title_id, request.path) {
+        (Some(title_id), None) => {
+            let title_id = normalize_title_id(&title_id).ok_or(ApiError::InvalidTitleId)?;
+            state.overrides.set_hidden(title_id, hidden).await
+        }
+        (None, Some(path)) => {
+            let path = sanitize_relative_path(&path).map_err(map_file_error)?;
+            state.overrides.set_path_hidden(&path, hidden).await
+        }
+        _ => return Err(ApiError::InvalidPath),
+    };
+    changed.map_err(|err| {
+        warn!(error = %err, "failed to save hidden entries");
+        ApiError::Internal
+    })
+}
+
+/// Files kept out of the shop because they look like interrupted copies.
+async fn library_problems(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<ProblemsResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let payload = ProblemsResponse::new(
+        state.library.problems().await,
+        state.library.scan_config().min_file_size,
+    );
+    debug!(files = payload.files.len(), "library problems requested");
+    Ok(Json(payload))
+}
+
+/// Verification results across the library, with every file that failed.
+async fn library_verification(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<VerificationResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let mut files = state.catalog.read().await.files().to_vec();
+    files.extend(state.library.problems().await);
+    let payload = VerificationResponse::new(files, state.library.verifier());
+    debug!(
+        enabled = payload.enabled,
+        ok = payload.ok,
+        bad = payload.bad,
+        pending = payload.pending,
+        "library verification requested"
+    );
+    Ok(Json(payload))
+}
+
+async fn library_missing_dlc(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<MissingDlcResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let catalog = state.catalog.read().await;
+    let payload = build_missing_dlc_response(&catalog, &state.titledb).await;
+    debug!(
+        titles = payload.titles.len(),
+        titledb_entries = payload.titledb_entries,
+        "missing dlc requested"
+    );
+    Ok(Json(payload))
+}
+
+/// Background jobs (replication, imports), newest first, optionally of one `kind`.
+async fn jobs_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(query): Query<JobsQuery>,
+    headers: HeaderMap,
+) -> Result<Json<JobsResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(JobsResponse {
+        jobs: state.jobs.list(query.kind.as_deref()),
+    }))
+}
+
+/// Download a file from an HTTP(S) URL into the first library root as a background job.
+async fn library_import_url(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(request): Json<ImportUrlRequest>,
+) -> Result<(StatusCode, Json<ImportStartedResponse>), ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let url = reqwest::Url::parse(request.url.trim())
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or(ApiError::InvalidImport("expected an http or https URL"))?;
+    let requested = match request.path {
+        Some(path) => path,
+        None => url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|name| percent_decode_str(name).decode_utf8_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let relative_path = sanitize_relative_path(&requested).map_err(map_file_error)?;
+    if !is_supported_content(&relative_path) {
+        return Err(ApiError::InvalidImport(
+            "destination must be an .nsp, .xci, .nsz, or .xcz file",
+        ));
+    }
+    let blake3 = match request.blake3 {
+        Some(hash) if hash.len() == 64 && hash.chars().all(|ch| ch.is_ascii_hexdigit()) => {
+            Some(hash.to_ascii_lowercase())
+        }
+        Some(_) => return Err(ApiError::InvalidImport("blake3 must be 64 hex digits")),
+        None => None,
+    };
+    let root = state
+        .library
+        .roots()
+        .first()
+        .cloned()
+        .ok_or(ApiError::Internal)?;
+    if tokio::fs::try_exists(root.join(&relative_path))
+        .await
+        .unwrap_or(false)
+    {
+        return Err(ApiError::AlreadyExists);
+    }
+    let path = url_path(&relative_path);
+    if state.jobs.is_running(IMPORT_JOB, &path) {
+        return Err(ApiError::JobInProgress);
+    }
+    let name = relative_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let title_id = to_display_title_id(parse_filename_metadata(&name).title_id);
+    let kind = classify_title_id(title_id.as_deref());
+    let quota = state
+        .quotas
+        .allowance(&*state.catalog.read().await, &root, kind);
+    if let Some(quota) = quota.as_ref().filter(|quota| quota.remaining_bytes == 0) {
+        return Err(ApiError::QuotaExceeded(quota.clone()));
+    }
+
+    let job_id = spawn_import(
+        &state.jobs,
+        state.library.clone(),
+        ImportTarget {
+            url,
+            root,
+            relative_path,
+            blake3,
+            quota,
+        },
+    );
+    debug!(path = %path, job_id = %job_id, "url import started");
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ImportStartedResponse { job_id, path }),
+    ))
+}
+
+/// Where an upload of `filename` goes.
+struct UploadTarget {
+    root: PathBuf,
+    relative_path: PathBuf,
+    destination: PathBuf,
+}
+
+fn upload_target(state: &AppState, filename: &str) -> Result<UploadTarget, ApiError> {
+    let dir = state.uploads.dir().ok_or(ApiError::NotFound)?;
+    let name = sanitize_relative_path(filename).map_err(map_file_error)?;
+    if name.components().count() != 1 {
+        return Err(ApiError::InvalidUpload("expected a file name, not a path"));
+    }
+    if !is_supported_content(&name) {
+        return Err(ApiError::InvalidUpload(
+            "uploads must be .nsp, .xci, .nsz, or .xcz files",
+        ));
+    }
+    let root = state
+        .library
+        .roots()
+        .first()
+        .cloned()
+        .ok_or(ApiError::NotFound)?;
+    let relative_path = dir.join(name);
+    Ok(UploadTarget {
+        destination: root.join(&relative_path),
+        root,
+        relative_path,
+    })
+}
+
+fn upload_response(status: StatusCode, upload: UploadResponse) -> Response {
+    let offset = upload.offset;
+    let mut response = (status, Json(upload)).into_response();
+    response
+        .headers_mut()
+        .insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+    response
+}
+
+/// How much of an upload has arrived; `complete` once the file is in the library.
+async fn upload_status(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(filename): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let target = upload_target(&state, &filename)?;
+    let (offset, complete) = match tokio::fs::metadata(&target.destination).await {
+        Ok(meta) => (meta.len(), true),
+        Err(_) => (partial_len(&partial_path(&target.destination)).await, false),
+    };
+    Ok(upload_response(
+        StatusCode::OK,
+        UploadResponse {
+            path: url_path(&target.relative_path),
+            offset,
+            complete,
+        },
+    ))
+}
+
+/// Write the body into `[uploads] dir`, whole or as one `Content-Range` piece, and add the
+/// file to the library once it is complete.
+async fn upload_put(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(filename): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let target = upload_target(&state, &filename)?;
+    let path = url_path(&target.relative_path);
+    if tokio::fs::try_exists(&target.destination)
+        .await
+        .unwrap_or(false)
+    {
+        return Err(ApiError::AlreadyExists);
+    }
+    let range = match headers.get(CONTENT_RANGE) {
+        Some(value) => Some(value.to_str().ok().and_then(parse_content_range).ok_or(
+            ApiError::InvalidUpload("Content-Range must look like \"bytes 0-1048575/4194304\""),
+        )?),
+        None => None,
+    };
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    // Without a range the body is the whole file.
+    let (first, limit, total) = match range {
+        Some(range) => (
+            range.first,
+            Some(range.last.map_or(0, |last| last + 1 - range.first)),
+            range.total,
+        ),
+        None => (0, content_length, content_length),
+    };
+
+    let name = target
+        .relative_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let title_id = to_display_title_id(parse_filename_metadata(&name).title_id);
+    let kind = classify_title_id(title_id.as_deref());
+    let quota = state
+        .quotas
+        .allowance(&*state.catalog.read().await, &target.root, kind);
+    let over_quota = |size: u64| {
+        quota
+            .as_ref()
+            .filter(|quota| size > quota.remaining_bytes)
+            .map(|quota| ApiError::QuotaExceeded(quota.clone()))
+    };
+    // While the size isn't known yet, only a budget that is used up already refuses it.
+    if let Some(err) = over_quota(total.unwrap_or(1)) {
+        return Err(err);
+    }
+
+    let _guard = state
+        .uploads
+        .begin(&target.relative_path)
+        .ok_or(ApiError::UploadInProgress)?;
+    // Checked again under the guard: the file may have appeared since the check above.
+    if tokio::fs::try_exists(&target.destination)
+        .await
+        .unwrap_or(false)
+    {
+        return Err(ApiError::AlreadyExists);
+    }
+    let partial = partial_path(&target.destination);
+    let offset = partial_len(&partial).await;
+    if first != 0 && first != offset {
+        return Err(ApiError::UploadOffset(offset));
+    }
+    // Stop writing once the budget is spent, not after the whole body is on disk.
+    let budget = quota
+        .as_ref()
+        .map(|quota| quota.remaining_bytes.saturating_sub(first));
+    let quota_bound = budget.is_some_and(|budget| !limit.is_some_and(|limit| limit <= budget));
+    let written = write_piece(
+        &partial,
+        first,
+        body.into_data_stream(),
+        if quota_bound { budget } else { limit },
+    )
+    .await;
+    let exceeded = quota
+        .as_ref()
+        .filter(|_| quota_bound && matches!(written, Err(UploadError::TooLong)));
+    if let Some(quota) = exceeded {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(ApiError::QuotaExceeded(quota.clone()));
+    }
+    let size = written.map_err(|err| match err {
+        UploadError::Io { .. } => {
+            warn!(path = %path, error = %err, "failed to write upload");
+            ApiError::Internal
+        }
+        UploadError::Body(_) => {
+            debug!(path = %path, error = %err, "upload interrupted");
+            ApiError::InvalidUpload("upload interrupted; continue from its offset")
+        }
+        UploadError::TooLong => ApiError::InvalidUpload("body is longer than its Content-Range"),
+    })?;
+
+    if range.is_some() && total != Some(size) {
+        debug!(path = %path, offset = size, total, "upload piece received");
+        return Ok(upload_response(
+            StatusCode::ACCEPTED,
+            UploadResponse {
+                path,
+                offset: size,
+                complete: false,
+            },
+        ));
+    }
+    if let Some(err) = over_quota(size) {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(err);
+    }
+    finish(&partial, &target.destination)
+        .await
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::AlreadyExists => ApiError::AlreadyExists,
+            _ => {
+                warn!(path = %path, error = %err, "failed to move upload into place");
+                ApiError::Internal
+            }
+        })?;
+    info!(path = %path, size, "upload finished");
+    if let Err(err) = state.library.rescan(&target.root).await {
+        warn!(root = %target.root.display(), error = %err, "rescan after upload failed");
+    }
+    Ok(upload_response(
+        StatusCode::CREATED,
+        UploadResponse {
+            path,
+            offset: size,
+            complete: true,
+        },
+    ))
+}
+
+async fn replication_status(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<ReplicationStatusResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(ReplicationStatusResponse {
+        enabled: state.mirror_root.is_some(),
+        jobs: state.jobs.list(Some(REPLICATION_JOB)),
+    }))
+}
+
+/// Move a catalog file into its root's trash and drop it from the catalog.
+async fn library_file_delete(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+) -> Result<Json<TrashEntry>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let (root, relative_path, size) = {
+        let catalog = state.catalog.read().await;
+        let index = id.checked_sub(1).ok_or(ApiError::NotFound)?;
+        let file = catalog.files().get(index).ok_or(ApiError::NotFound)?;
+        (file.root.clone(), file.relative_path.clone(), file.size)
+    };
+    let entry = trash(&state)
+        .delete(&root, &relative_path, size)
+        .await
+        .map_err(map_trash_error)?;
+    info!(path = %relative_path.display(), trash_id = %entry.id, "library file moved to trash");
+    rescan_after_trash(&state, &root).await;
+    Ok(Json(entry))
+}
+
+async fn trash_list(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<TrashListResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(TrashListResponse {
+        entries: trash(&state).list(state.library.roots()).await,
+    }))
+}
+
+/// Move a trashed file back to where it was and add it to the catalog again.
+async fn trash_restore(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<TrashEntry>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let entry = trash(&state)
+        .restore(state.library.roots(), &id)
+        .await
+        .map_err(map_trash_error)?;
+    info!(path = %entry.relative_path.display(), trash_id = %entry.id, "library file restored from trash");
+    rescan_after_trash(&state, &entry.root).await;
+    Ok(Json(entry))
+}
+
+/// Permanently delete a trashed file.
+async fn trash_purge(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let entry = trash(&state)
+        .purge(state.library.roots(), &id)
+        .await
+        .map_err(map_trash_error)?;
+    info!(path = %entry.relative_path.display(), trash_id = %entry.id, "trashed file purged");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn trash(state: &AppState) -> Trash {
+    Trash::new(state.library.scan_config().trash_dir)
+}
+
+async fn rescan_after_trash(state: &AppState, root: &std::path::Path) {
+    if let Err(err) = state.library.rescan(root).await {
+        warn!(root = %root.display(), error = %err, "rescan after trash change failed");
+    }
+}
+
+fn map_trash_error(error: TrashError) -> ApiError {
+    match error {
+        TrashError::NotFound => ApiError::NotFound,
+        TrashError::Exists(_) => ApiError::AlreadyExists,
+        TrashError::Io { .. } | TrashError::Entry { .. } => {
+            warn!(error = %error, "trash operation failed");
+            ApiError::Internal
+        }
+    }
+}
+
+/// Size of the fetched icon cache.
+async fn icon_cache_status(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<IconCacheStats>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(state.artwork.icon_cache().stats()))
+}
+
+/// Delete every cached icon; they are fetched again on demand.
+async fn icon_cache_purge(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<IconCachePurgedResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let removed = state.artwork.icon_cache().purge().await.map_err(|err| {
+        warn!(error = %err, "icon cache purge failed");
+        ApiError::Internal
+    })?;
+    info!(
+        entries = removed.entries,
+        bytes = removed.bytes,
+        "icon cache purged"
+    );
+    Ok(Json(IconCachePurgedResponse {
+        removed: removed.entries,
+        freed_bytes: removed.bytes,
+    }))
+}
+
+async fn benchmark_status(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<BenchmarkStatusResponse>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    Ok(Json(BenchmarkStatusResponse {
+        jobs: state.jobs.list(Some(BENCHMARK_JOB)),
+    }))
+}
+
+/// Measure sequential read throughput of every library root, one job per root.
+async fn benchmark_start(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<BenchmarkStartedResponse>), ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let roots = state.library.roots().to_vec();
+    if roots.iter().any(|root| {
+        state
+            .jobs
+            .is_running(BENCHMARK_JOB, &root.display().to_string())
+    }) {
+        return Err(ApiError::JobInProgress);
+    }
+
+    let sampled: Vec<_> = {
+        let catalog = state.catalog.read().await;
+        roots
+            .into_iter()
+            .map(|root| {
+                let samples = pick_samples(catalog.files(), &root);
+                (root, samples)
+            })
+            .filter(|(_, samples)| !samples.is_empty())
+            .collect()
+    };
+    let started: Vec<_> = sampled
+        .iter()
+        .map(|(root, samples)| (root.display().to_string(), samples.len()))
+        .collect();
+    let ids = spawn_benchmarks(&state.jobs, sampled);
+    let jobs: Vec<_> = ids
+        .into_iter()
+        .zip(started)
+        .map(|(job_id, (root, files))| BenchmarkStarted {
+            job_id,
+            root,
+            files,
+        })
+        .collect();
+    debug!(roots = jobs.len(), "storage benchmark started");
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(BenchmarkStartedResponse { jobs }),
+    ))
+}
+
+/// Copy a title's base, update, and DLC files to the mirror root as a background job.
+async fn replicate_title(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(title_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<ReplicationStartedResponse>), ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let mirror_root = state
+        .mirror_root
+        .clone()
+        .ok_or(ApiError::ReplicationDisabled)?;
+    let title_id = title_id.to_ascii_uppercase();
+    if state.jobs.is_running(REPLICATION_JOB, &title_id) {
+        return Err(ApiError::JobInProgress);
+    }
+
+    let files = state
+        .catalog
+        .read()
+        .await
+        .files_for_base_title(&title_id)
+        .into_iter()
+        .filter(|file| !file.stale)
+        .map(|file| ReplicationSource {
+            source: file.root.join(&file.relative_path),
+            relative_path: file.relative_path.clone(),
+            size: file.size,
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err(ApiError::TitleNotFound);
+    }
+
+    let count = files.len();
+    let job_id = spawn_replication(&state.jobs, mirror_root, title_id.clone(), files);
+    debug!(title_id = %title_id, files = count, job_id = %job_id, "title replication started");
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ReplicationStartedResponse {
+            job_id,
+            title_id,
+            files: count,
+        }),
+    ))
+}
+
+async fn report_latest(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<LibraryReport>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    state
+        .reports
+        .latest()
+        .await
+        .map(Json)
+        .ok_or(ApiError::NotFound)
+}
+
+async fn report_latest_html(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Html<String>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    state
+        .reports
+        .latest_html()
+        .await
+        .map(Html)
+        .ok_or(ApiError::NotFound)
+}
+
+/// Generate a report now instead of waiting for the schedule.
+async fn report_generate(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Json<LibraryReport>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    state.reports.generate().await.map(Json).map_err(|err| {
+        warn!(error = %err, "library report generation failed");
+        ApiError::Internal
+    })
+}
+
+/// Streams `{"revision": n}` whenever settings are saved, so open admin pages can reload.
+async fn settings_events_sse(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    headers: HeaderMap,
+) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, Infallible>> + Send>, ApiError> {
+    ensure_admin_enabled(&state)?;
+    ensure_authorized(&state, &headers, jar.get(SESSION_COOKIE).map(|c| c.value()))?;
+    let rx = state.settings.subscribe();
+    let stream = tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(|r| async move {
+        let revision = r.ok()?;
+        Some(Ok(Event::default().event("settings").data(
+            serde_json::json!({ "revision": revision }).to_string(),
+        )))
+    });
+    Ok(Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("ping"),
+    ))
 }
 
 async fn titledb_progress_sse(
@@ -595,6 +4104,7 @@ async fn titledb_progress_sse(
     ))
 }
 
+#[cfg(feature = "titledb")]
 async fn titledb_test_connectivity(
     State(state): State<AppState>,
     jar: CookieJar,
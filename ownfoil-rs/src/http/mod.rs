@@ -3,15 +3,43 @@
 //! Exposes CyberFoil-compatible endpoints (`/`, `/api/shop/sections`, etc.),
 //! admin UI, and settings API.
 
+mod acme;
+mod activity;
+#[cfg(feature = "admin-ui")]
+mod assets;
 mod auth;
+mod base_path;
+mod client_views;
+mod compression;
+mod directories;
 mod error;
+mod etag;
+#[cfg(feature = "ftp")]
+mod ftp;
 mod handlers;
+mod openapi;
+mod rate_limit;
+mod redact;
 mod responses;
+mod server;
 mod settings;
+mod shop_index;
 mod state;
+mod tally;
+mod tls;
+mod webdav;
 
 #[cfg(test)]
 mod tests;
 
+pub use acme::{spawn_acme, AcmeManager};
+#[cfg(feature = "ftp")]
+pub use ftp::serve_ftp;
 pub use handlers::router;
+#[cfg(unix)]
+pub use server::bind_unix;
+pub use server::serve;
+pub use settings::SettingsRevision;
+pub use shop_index::ShopIndex;
 pub use state::{AppState, SessionStore};
+pub use tls::{redirect_router, spawn_cert_reloader, ReloadingCert};
@@ -0,0 +1,109 @@
+//! OpenAPI description of the client-facing routes, served at `/api/openapi.json` and
+//! browsable with Swagger UI at `/api/docs`.
+//!
+//! Only the canonical path of each route is listed; aliases such as `/shop` and
+//! `/download/` behave like the paths they mirror. Admin routes are left out.
+
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::{OpenApi as Document, Server};
+use utoipa::{Modify, OpenApi};
+
+use super::handlers;
+use super::responses::CatalogSort;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        handlers::shop_index,
+        handlers::shop_root,
+        handlers::health,
+        handlers::catalog_all,
+        handlers::catalog_changes,
+        handlers::sections,
+        handlers::section_entries,
+        handlers::shop_sections,
+        handlers::search,
+        handlers::index_txt,
+        handlers::catalog_aria2,
+        handlers::announcements_list,
+        handlers::directory,
+        handlers::title_versions,
+        handlers::library_titles,
+        handlers::download,
+        handlers::download_by_id,
+        handlers::download_nsp_by_id,
+        handlers::download_chunks,
+        handlers::file_checksum,
+        handlers::download_batch,
+        handlers::shop_icon,
+        handlers::shop_banner,
+        handlers::speedtest,
+    ),
+    // Query parameters don't register the schemas they refer to.
+    components(schemas(CatalogSort)),
+    modifiers(&BasicAuth),
+    // Credentials are only needed when `[auth]` has users.
+    security((), ("basic" = [])),
+    tags(
+        (name = "shop", description = "Shop index and sections for Tinfoil and other clients"),
+        (name = "catalog", description = "Library listings"),
+        (name = "downloads", description = "Content files, with `Range` support"),
+        (name = "artwork", description = "Title icons and banners"),
+        (name = "server", description = "Health and diagnostics"),
+    )
+)]
+struct ApiDoc;
+
+#[cfg(feature = "saves")]
+#[derive(OpenApi)]
+#[openapi(
+    paths(handlers::saves_list),
+    tags((name = "saves", description = "Save backups"))
+)]
+struct SavesDoc;
+
+struct BasicAuth;
+
+impl Modify for BasicAuth {
+    fn modify(&self, openapi: &mut Document) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "basic",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+            );
+    }
+}
+
+/// The document, with `server` (the base path the request came through, if any) as the
+/// server URL.
+pub fn document(server: &str) -> Document {
+    let mut document = ApiDoc::openapi();
+    #[cfg(feature = "saves")]
+    document.merge(SavesDoc::openapi());
+    if !server.is_empty() {
+        document.servers = Some(vec![Server::new(server)]);
+    }
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_listed_schema_reference_resolves() -> anyhow::Result<()> {
+        let document = serde_json::to_value(document(""))?;
+        let text = serde_json::to_string(&document)?;
+        let schemas = &document["components"]["schemas"];
+        for reference in text.split("\"$ref\":\"#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap_or_default();
+            assert!(
+                schemas.get(name).is_some(),
+                "{name} is referenced but missing"
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+//! Per-IP rate limits and their exemptions.
+//!
+//! Every client is throttled per IP by two limiters: one for downloads, which installers
+//! fetch in many ranged requests, and one for everything else (the index, artwork, and
+//! the JSON API), so a running install can't starve the shop of requests and the other
+//! way round. A throttled request gets `429` with `Retry-After` and a JSON error.
+//!
+//! Requests matching `[rate_limit]` are never throttled: clients whose address falls in
+//! an exempt network (typically the LAN the Switch is on), requests authenticated as an
+//! exempt user by Basic auth, an admin session, or a `/u/{token}/` shop token, and
+//! authenticated requests from an exempt-when-authenticated network. Failed logins are
+//! never exempt, so guessing passwords stays throttled. Exempt networks are matched
+//! against the connection's peer address; `X-Forwarded-For` / `X-Real-IP` are only
+//! believed when that peer is one of `trusted_proxies`, since any client can send them.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, Request, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use axum_extra::extract::cookie::CookieJar;
+use tower::ServiceExt;
+use tower_governor::errors::GovernorError;
+use tower_governor::governor::GovernorConfigBuilder;
+use tower_governor::key_extractor::KeyExtractor;
+use tower_governor::GovernorLayer;
+use tracing::debug;
+
+use crate::network::IpNetwork;
+
+use super::auth::{extract_basic_auth, session_user, token_user};
+use super::error::ApiError;
+use super::handlers::{client_addr, request_client_ip, request_peer, SESSION_COOKIE};
+use super::state::AppState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ClientKeyExtractor;
+
+impl KeyExtractor for ClientKeyExtractor {
+    type Key = String;
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        Ok(request_client_ip(req).to_string())
+    }
+}
+
+/// The limit a request counts against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RateClass {
+    Requests,
+    Downloads,
+}
+
+impl RateClass {
+    fn of(path: &str) -> Self {
+        // Per-user shop URLs serve the same routes under `/u/{token}`.
+        let path = match path.strip_prefix("/u/") {
+            Some(rest) => rest.find('/').map_or("", |slash| &rest[slash..]),
+            None => path,
+        };
+        let downloads = ["/api/download/", "/download/", "/api/get_game/", "/dav/"];
+        if downloads.iter().any(|prefix| path.starts_with(prefix)) {
+            RateClass::Downloads
+        } else {
+            RateClass::Requests
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Limits {
+    state: AppState,
+    /// The same routes behind each class's governor; `None` when the class is unlimited.
+    requests: Option<Router>,
+    downloads: Option<Router>,
+}
+
+/// Put `app` behind the limits of `[rate_limit]`, serving exempt requests from it
+/// directly.
+pub fn rate_limit(app: Router, state: AppState) -> Router {
+    let rules = &state.rate_limit;
+    let requests = limited(&app, rules.requests_per_second, rules.burst);
+    let downloads = limited(
+        &app,
+        rules.download_requests_per_second,
+        rules.download_burst,
+    );
+    if requests.is_none() && downloads.is_none() {
+        return app;
+    }
+    // Wrap the whole router, so requests reach `limited` before any route matched.
+    Router::new()
+        .fallback_service(app)
+        .layer(middleware::from_fn_with_state(
+            Limits {
+                state,
+                requests,
+                downloads,
+            },
+            route_request,
+        ))
+}
+
+fn limited(app: &Router, per_second: u32, burst: u32) -> Option<Router> {
+    if per_second == 0 {
+        return None;
+    }
+    let config = GovernorConfigBuilder::default()
+        .period(Duration::from_secs(1) / per_second)
+        .burst_size(burst.max(1))
+        .key_extractor(ClientKeyExtractor)
+        .finish()?;
+    let layer = GovernorLayer::new(Arc::new(config)).error_handler(too_many_requests);
+    Some(app.clone().layer(layer))
+}
+
+/// `429` with `Retry-After` and the usual JSON error body.
+fn too_many_requests(error: GovernorError) -> Response {
+    match error {
+        GovernorError::TooManyRequests { wait_time, .. } => {
+            debug!(wait_seconds = wait_time, "request rate limited");
+            ApiError::RateLimited(Duration::from_secs(wait_time)).into_response()
+        }
+        other => other.into_response().map(Body::from),
+    }
+}
+
+async fn route_request(
+    State(limits): State<Limits>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let limited = match RateClass::of(request.uri().path()) {
+        RateClass::Requests => limits.requests,
+        RateClass::Downloads => limits.downloads,
+    };
+    let Some(limited) = limited else {
+        return next.run(request).await;
+    };
+    let rules = &limits.state.rate_limit;
+    if rules.has_exemptions() {
+        let ip = client_addr(
+            &rules.trusted_proxies,
+            request.headers(),
+            request_peer(&request),
+        );
+        if is_exempt(&limits.state, ip, request.headers(), request.uri()).await {
+            return next.run(request).await;
+        }
+    }
+    match limited.oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+async fn is_exempt(state: &AppState, ip: Option<IpAddr>, headers: &HeaderMap, uri: &Uri) -> bool {
+    let rules = &state.rate_limit;
+    let in_any =
+        |networks: &[IpNetwork]| ip.is_some_and(|ip| networks.iter().any(|net| net.contains(ip)));
+    if in_any(&rules.exempt_networks) {
+        debug!(?ip, "rate limit exempt network");
+        return true;
+    }
+    let trusted_network = in_any(&rules.exempt_authenticated_networks);
+    if !trusted_network && rules.exempt_users.is_empty() {
+        return false;
+    }
+    match authenticated_user(state, headers, uri).await {
+        Some(user) if trusted_network => {
+            debug!(?ip, username = %user, "rate limit exempt authenticated client");
+            true
+        }
+        Some(user) if rules.exempt_users.contains(&user) => {
+            debug!(username = %user, "rate limit exempt user");
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The user a request authenticates as, if its credentials are valid.
+async fn authenticated_user(state: &AppState, headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let auth = state.auth.load();
+    if !auth.is_enabled() {
+        return None;
+    }
+    let session_user = CookieJar::from_headers(headers)
+        .get(SESSION_COOKIE)
+        .and_then(|cookie| session_user(state, cookie.value()));
+    let user = match session_user.or_else(|| token_user(state, headers)) {
+        Some(user) => Some(user),
+        None => match extract_basic_auth(headers) {
+            Some((username, password)) if auth.is_authorized(&username, &password) => {
+                Some(username)
+            }
+            _ => {
+                let token = uri.path().strip_prefix("/u/")?.split('/').next()?;
+                state.shop_tokens.user_for(token).await
+            }
+        },
+    };
+    user.filter(|user| auth.has_user(user))
+}
@@ -0,0 +1,121 @@
+//! Keeps credentials out of logs.
+//!
+//! Request spans record the URI with secret-looking query parameters (`token`, `key`,
+//! `signature`, ...) and the token of `/u/{token}/` shop paths masked, and never record
+//! headers. Credential headers are also marked
+//! sensitive so any `Debug` output of them prints `Sensitive` instead of the value.
+
+use axum::http::header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE};
+use axum::http::{HeaderName, Request, Uri};
+use tower_http::trace::MakeSpan;
+use tracing::Span;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Query parameter names whose values are never logged (compared case-insensitively).
+const SECRET_PARAMS: [&str; 12] = [
+    "token",
+    "access_token",
+    "auth",
+    "key",
+    "api_key",
+    "apikey",
+    "password",
+    "pass",
+    "secret",
+    "signature",
+    "sig",
+    "session",
+];
+
+/// Headers carrying credentials.
+pub fn sensitive_headers() -> [HeaderName; 4] {
+    [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE]
+}
+
+fn is_secret_param(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_PARAMS.contains(&name.as_str())
+        || name.ends_with("_token")
+        || name.ends_with("_secret")
+        || name.ends_with("_signature")
+}
+
+/// `uri` as path and query, with shop tokens and the values of secret query parameters
+/// replaced.
+pub fn redact_uri(uri: &Uri) -> String {
+    let path = redact_path(uri.path());
+    let Some(query) = uri.query() else {
+        return path;
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_secret_param(name) => format!("{name}={REDACTED}"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{path}?{query}")
+}
+
+fn redact_path(path: &str) -> String {
+    match path.strip_prefix("/u/") {
+        Some(rest) => match rest.split_once('/') {
+            Some((_, tail)) => format!("/u/{REDACTED}/{tail}"),
+            None => format!("/u/{REDACTED}"),
+        },
+        None => path.to_string(),
+    }
+}
+
+/// Request span for `TraceLayer`: method, redacted URI, and HTTP version only.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RedactedMakeSpan;
+
+impl<B> MakeSpan<B> for RedactedMakeSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        tracing::debug_span!(
+            "request",
+            method = %request.method(),
+            uri = %redact_uri(request.uri()),
+            version = ?request.version(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use super::redact_uri;
+
+    #[test]
+    fn secret_query_values_are_masked() {
+        let uri: Uri = "/api/get_game/3?token=abc&sort=added&Upload_Token=x&sig=zz"
+            .parse()
+            .unwrap_or_default();
+        assert_eq!(
+            redact_uri(&uri),
+            "/api/get_game/3?token=[REDACTED]&sort=added&Upload_Token=[REDACTED]&sig=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn uris_without_secrets_are_unchanged() {
+        let uri: Uri = "/api/search?q=zelda".parse().unwrap_or_default();
+        assert_eq!(redact_uri(&uri), "/api/search?q=zelda");
+        let uri: Uri = "/api/catalog".parse().unwrap_or_default();
+        assert_eq!(redact_uri(&uri), "/api/catalog");
+    }
+
+    #[test]
+    fn shop_tokens_in_paths_are_masked() {
+        let uri: Uri = "/u/0123abcd/api/get_game/3?sort=added"
+            .parse()
+            .unwrap_or_default();
+        assert_eq!(redact_uri(&uri), "/u/[REDACTED]/api/get_game/3?sort=added");
+        let uri: Uri = "/u/0123abcd".parse().unwrap_or_default();
+        assert_eq!(redact_uri(&uri), "/u/[REDACTED]");
+    }
+}
@@ -1,15 +1,36 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::catalog::{ContentFile, ContentKind};
+use crate::annotations::{AnnotationFormat, RowError};
+use crate::announcements::Announcement;
+use crate::api_tokens::ApiToken;
+use crate::artwork::{Artwork, ArtworkProvider};
+use crate::auth::{AuthUser, Role};
+use crate::catalog::{
+    by_recency, derive_base_title_id, url_path, Catalog, ContentFile, ContentKind, TitleSummary,
+};
+use crate::config::HealthConfig;
+#[cfg(feature = "metrics")]
+use crate::growth::GrowthForecast;
+use crate::jobs::JobInfo;
+use crate::library::{ScanTiming, TitleRefresh};
+use crate::overrides::{HiddenEntries, Overrides};
+#[cfg(feature = "metrics")]
+use crate::quota::QuotaUsage;
 use crate::serve_files::FileServeError;
+use crate::stats::{DownloadStats, Totals};
 use crate::titledb::{TitleDb, TitleInfo};
+use crate::trash::TrashEntry;
+use crate::verify::{Verification, Verifier};
 
+use super::directories::root_directories;
 use super::error::ApiError;
 
-const PATH_SEGMENT_ENCODE_SET: &AsciiSet = &CONTROLS
+pub(super) const PATH_SEGMENT_ENCODE_SET: &AsciiSet = &CONTROLS
     .add(b' ')
     .add(b'"')
     .add(b'#')
@@ -18,14 +39,23 @@ const PATH_SEGMENT_ENCODE_SET: &AsciiSet = &CONTROLS
     .add(b'{')
     .add(b'}');
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct HealthResponse {
+    /// `ok`, `degraded` (a freshness threshold was exceeded), or `error` (a scan failed).
     pub status: &'static str,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub catalog_files: Option<usize>,
+    /// Age of the stalest root's latest scan.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_scan_age_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub titledb_age_seconds: Option<u64>,
+    /// Why the status isn't `ok`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ApiEntry {
     pub id: String,
     pub name: String,
@@ -41,48 +71,79 @@ pub struct ApiEntry {
     #[serde(rename = "type")]
     pub content_type: ContentKind,
     pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
     pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CatalogResponse {
+    /// Entries in the whole listing, not just the current page.
     pub total: usize,
     pub success: &'static str,
     pub files: Vec<ShopFile>,
+    /// URLs of the virtual directory views (by kind, letter, and folder).
     pub directories: Vec<String>,
     pub entries: Vec<ApiEntry>,
     pub sections: Vec<SectionInfo>,
+    /// Present only when the request asked for a page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
+    /// Pass to `/api/catalog/changes?since=` to poll for changes after this listing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// `/api/catalog/changes` payload. Apply `removed` before `added`: a changed file is in both.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CatalogChangesResponse {
+    pub cursor: String,
+    /// The cursor is unknown or too old; refetch `/api/catalog?all=true` and start over.
+    pub reset: bool,
+    pub added: Vec<ApiEntry>,
+    /// Entry IDs (library-relative paths) that are gone.
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangesQuery {
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PageInfo {
+    /// 1-based page number.
+    pub page: usize,
+    pub per_page: usize,
+    pub pages: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SectionsResponse {
     pub sections: Vec<SectionInfo>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct SectionInfo {
     pub id: &'static str,
     pub label: &'static str,
 }
 
-#[derive(Debug, Serialize)]
-pub struct ShopRootResponse {
-    pub success: &'static str,
-    pub files: Vec<ShopRootFile>,
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ShopRootFile {
     pub url: String,
     pub size: u64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ShopSectionsResponse {
     pub sections: Vec<ShopSection>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ShopSection {
     pub id: &'static str,
     pub title: &'static str,
@@ -93,7 +154,7 @@ pub struct ShopSection {
     pub truncated: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ShopSectionItem {
     pub name: String,
     pub title_name: String,
@@ -110,9 +171,11 @@ pub struct ShopSectionItem {
     pub file_id: usize,
     pub filename: String,
     pub download_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SearchResponse {
     pub query: String,
     pub success: &'static str,
@@ -120,7 +183,7 @@ pub struct SearchResponse {
     pub entries: Vec<ApiEntry>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ShopFile {
     pub id: String,
     pub url: String,
@@ -139,23 +202,200 @@ pub struct ShopFile {
     pub content_type: ContentKind,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchQuery {
     pub q: String,
+    pub sort: Option<CatalogSort>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Optional `?sort=` for catalog listings. Without it, catalog order is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogSort {
+    /// Most recently added (modified) first.
+    Added,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SortQuery {
+    pub sort: Option<CatalogSort>,
+}
+
+/// `/api/catalog` query: `?all=true` bypasses dedup and hidden titles and lists every file.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CatalogQuery {
+    pub sort: Option<CatalogSort>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// `/index.txt` and `/api/catalog/aria2` query: `?q=` takes the `/api/search` syntax and
+/// `?titles=` a comma-separated list of title IDs to narrow the list.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IndexQuery {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub titles: String,
+}
+
+/// `?page=&per_page=` for catalog listings. Without either, the whole listing is returned,
+/// which is what Tinfoil expects.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+impl PageQuery {
+    const DEFAULT_PER_PAGE: usize = 100;
+    const MAX_PER_PAGE: usize = 1000;
+
+    /// Cut `items` down to the requested page. Pages past the end are empty.
+    pub fn apply<T>(&self, items: Vec<T>) -> (Vec<T>, Option<PageInfo>) {
+        if self.page.is_none() && self.per_page.is_none() {
+            return (items, None);
+        }
+        let page = self.page.unwrap_or(1).max(1);
+        let per_page = self
+            .per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .clamp(1, Self::MAX_PER_PAGE);
+        let info = PageInfo {
+            page,
+            per_page,
+            pages: items.len().div_ceil(per_page),
+        };
+        let items = items
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+        (items, Some(info))
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ShopSectionsQuery {
     pub limit: Option<usize>,
+    /// Skip this many items of the `all` section, for fetching it in pages.
+    #[serde(default)]
+    pub offset: usize,
+    /// Return only this section.
+    pub section: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AnnotationImportQuery {
+    /// `csv` or `json`; taken from `Content-Type` or guessed when unset.
+    pub format: Option<AnnotationFormat>,
+    /// Validate and report without saving.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Validation report of an annotation import.
 #[derive(Debug, Serialize)]
+pub struct AnnotationImportResponse {
+    pub dry_run: bool,
+    /// Non-empty rows read.
+    pub rows: usize,
+    /// Rows that passed validation.
+    pub valid: usize,
+    /// Titles whose overrides changed (0 on a dry run).
+    pub changed: usize,
+    /// Valid title IDs with no file in the library; their annotations are kept anyway.
+    pub unknown_titles: Vec<String>,
+    /// Rows that were skipped, and why.
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsckQuery {
+    /// Fix what the check finds (`POST` only).
+    #[serde(default)]
+    pub apply: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SpeedTestQuery {
+    /// Megabytes to send.
+    pub mb: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChunksQuery {
+    /// Bytes per chunk, like `64MB` (binary units), from 1 MiB to 4 GiB; 64 MiB when unset.
+    pub size: Option<String>,
+}
+
+/// A file's chunk map.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChunkMapResponse {
+    pub file_id: usize,
+    pub name: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub chunks: Vec<ChunkEntry>,
+}
+
+/// One chunk, ready for a `Range: bytes=start-end` request.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChunkEntry {
+    pub start: u64,
+    /// Last byte, inclusive.
+    pub end: u64,
+    /// SHA-256 of the chunk, lowercase hex.
+    pub sha256: String,
+}
+
+/// Digests of a library file, for verifying a download.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChecksumResponse {
+    pub file_id: usize,
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the file, lowercase hex.
+    pub sha256: String,
+    /// BLAKE3 of the file, lowercase hex.
+    pub blake3: String,
+}
+
+/// Files to download as one ZIP, as JSON or as a form with repeated fields.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchDownloadRequest {
+    /// `file_id`s from `/api/shop/sections`.
+    #[serde(default)]
+    pub ids: Vec<usize>,
+    /// Base title IDs; each adds every file of the title: base, updates, and DLC.
+    #[serde(default)]
+    pub title_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImageQuery {
+    /// Edge length in pixels when SVG art is rendered to PNG.
+    pub size: Option<u32>,
+}
+
+#[cfg(feature = "saves")]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SavesListResponse {
     pub success: bool,
     pub saves: Vec<SavedItem>,
 }
 
-#[derive(Debug, Serialize)]
+#[cfg(feature = "saves")]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SavedItem {
     pub name: String,
     pub title_id: String,
@@ -167,6 +407,432 @@ pub struct SavedItem {
     pub size: u64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct JobsResponse {
+    pub jobs: Vec<JobInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobsQuery {
+    pub kind: Option<String>,
+}
+
+/// Body of `POST /api/library/import-url`.
+#[derive(Debug, Deserialize)]
+pub struct ImportUrlRequest {
+    pub url: String,
+    /// Destination relative to the first library root; defaults to the URL's file name.
+    pub path: Option<String>,
+    /// Expected BLAKE3 hash of the file, hex encoded.
+    pub blake3: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportStartedResponse {
+    pub job_id: String,
+    pub path: String,
+}
+
+/// State of an upload to `/api/upload/{filename}`.
+#[derive(Debug, Serialize)]
+pub struct UploadResponse {
+    /// Where the file goes, relative to its library root.
+    pub path: String,
+    /// Bytes received so far; the next piece starts here.
+    pub offset: u64,
+    /// Whether the file is complete and in the library.
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplicationStatusResponse {
+    pub enabled: bool,
+    pub jobs: Vec<JobInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplicationStartedResponse {
+    pub job_id: String,
+    pub title_id: String,
+    pub files: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrashListResponse {
+    pub entries: Vec<TrashEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IconCachePurgedResponse {
+    pub removed: usize,
+    pub freed_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkStatusResponse {
+    pub jobs: Vec<JobInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkStartedResponse {
+    pub jobs: Vec<BenchmarkStarted>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkStarted {
+    pub job_id: String,
+    pub root: String,
+    pub files: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleRefreshResponse {
+    pub title_id: String,
+    #[serde(flatten)]
+    pub files: TitleRefresh,
+    /// Name from TitleDB, if it knows the title.
+    pub name: Option<String>,
+    /// Whether an icon is available (override, TitleDB, or fallback artwork).
+    pub icon: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LibraryTitlesResponse {
+    pub titles: Vec<TitleSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProblemsResponse {
+    /// Configured `[scan] min_file_size`; empty files are always quarantined.
+    pub min_file_size: u64,
+    pub files: Vec<ProblemFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProblemFile {
+    #[serde(flatten)]
+    pub file: ContentFile,
+    /// `empty`, `too_small`, or `failed_verification`.
+    pub reason: &'static str,
+}
+
+impl ProblemsResponse {
+    pub fn new(files: Vec<ContentFile>, min_file_size: u64) -> Self {
+        Self {
+            min_file_size,
+            files: files
+                .into_iter()
+                .map(|file| ProblemFile {
+                    reason: if file.size == 0 {
+                        "empty"
+                    } else if file.size < min_file_size {
+                        "too_small"
+                    } else {
+                        "failed_verification"
+                    },
+                    file,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HiddenResponse {
+    /// Hidden through the admin API.
+    pub admin: HiddenEntries,
+    /// Hidden in the config file; edit the config to change these.
+    pub config: HiddenEntries,
+}
+
+/// Body of `POST /api/library/hide` and `/unhide`: a title ID or a relative path.
+#[derive(Debug, Deserialize)]
+pub struct HideRequest {
+    pub title_id: Option<String>,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlocklistResponse {
+    pub title_ids: Vec<String>,
+    /// Library files currently withheld because of the list.
+    pub blocked_files: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlocklistImportRequest {
+    pub url: String,
+    /// Replace the current list instead of adding to it.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlocklistImportResponse {
+    /// Title IDs found in the imported list.
+    pub imported: usize,
+    pub total: usize,
+    pub blocked_files: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AnnouncementsResponse {
+    /// Newest first.
+    pub announcements: Vec<Announcement>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShopTokensResponse {
+    pub users: Vec<ShopTokenEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShopTokenEntry {
+    pub username: String,
+    /// Whether the user has a shop URL.
+    pub issued: bool,
+    /// Shop path to give the client, e.g. `/u/<token>/`. Only in the response that issues
+    /// the token, since the server keeps just a digest of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl ShopTokenEntry {
+    pub fn listed(username: &str, issued: bool) -> Self {
+        Self {
+            username: username.to_string(),
+            issued,
+            path: None,
+        }
+    }
+
+    pub fn issued(username: &str, token: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            issued: true,
+            path: Some(format!("/u/{token}/")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsersResponse {
+    pub users: Vec<UserEntry>,
+}
+
+/// A user in the auth file, without their password.
+#[derive(Debug, Serialize)]
+pub struct UserEntry {
+    pub username: String,
+    pub role: Role,
+    pub kinds: Vec<ContentKind>,
+    pub titles: Vec<String>,
+    pub disabled: bool,
+    /// Whether the password is stored as a hash rather than in plaintext.
+    pub password_hashed: bool,
+}
+
+impl From<AuthUser> for UserEntry {
+    fn from(user: AuthUser) -> Self {
+        Self {
+            password_hashed: user.password_hash.is_some(),
+            username: user.username,
+            role: user.access.role,
+            kinds: user.access.kinds,
+            titles: user.access.titles.into_iter().collect(),
+            disabled: user.disabled,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiTokensResponse {
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct IssueTokenRequest {
+    /// What the token is for, e.g. `backup script`.
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssuedTokenResponse {
+    #[serde(flatten)]
+    pub info: ApiToken,
+    /// Send as `Authorization: Bearer <token>`. Not shown again.
+    pub token: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerificationResponse {
+    pub enabled: bool,
+    /// Whether `prod.keys` was loaded, so NCA headers are checked too.
+    pub keys: bool,
+    pub exclude_bad: bool,
+    pub ok: usize,
+    pub bad: usize,
+    pub unverifiable: usize,
+    /// Files the background pass hasn't reached yet.
+    pub pending: usize,
+    pub bad_files: Vec<ContentFile>,
+}
+
+impl VerificationResponse {
+    /// Tally `files` (catalog and quarantined alike) by verification result.
+    pub fn new(files: Vec<ContentFile>, verifier: Option<&Verifier>) -> Self {
+        let Some(verifier) = verifier else {
+            return Self::default();
+        };
+        let mut response = Self {
+            enabled: true,
+            keys: verifier.has_keys(),
+            exclude_bad: verifier.exclude_bad,
+            ..Self::default()
+        };
+        for file in files {
+            match &file.verification {
+                Some(Verification::Ok { .. }) => response.ok += 1,
+                Some(Verification::Unverifiable { .. }) => response.unverifiable += 1,
+                Some(Verification::Bad { .. }) => {
+                    response.bad += 1;
+                    response.bad_files.push(file);
+                }
+                None => response.pending += 1,
+            }
+        }
+        response
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingDlcResponse {
+    /// Number of TitleDB entries loaded; 0 means the report cannot be complete.
+    pub titledb_entries: usize,
+    pub titles: Vec<MissingDlcTitle>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingDlcTitle {
+    pub title_id: String,
+    pub name: Option<String>,
+    pub missing: Vec<MissingDlc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingDlc {
+    pub title_id: String,
+    pub name: Option<String>,
+}
+
+/// Titles listed in `largest` by [`build_library_stats`].
+#[cfg(feature = "metrics")]
+const LARGEST_TITLES: usize = 10;
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Serialize)]
+pub struct LibraryStatsResponse {
+    /// Base titles with at least one file (base, update, or DLC).
+    pub titles: usize,
+    pub files: usize,
+    pub total_bytes: u64,
+    pub by_kind: KindCounts,
+    /// Base titles with the most bytes across all their files, largest first.
+    pub largest: Vec<LargestTitle>,
+    /// Extra copies of the same title ID and version, beyond the first.
+    pub duplicate_files: usize,
+    /// Files with no title ID in their header, name, or path.
+    pub untitled_files: usize,
+    /// Latest scan of each library root.
+    pub scans: Vec<ScanTiming>,
+    /// Size trend and when the library disk fills up at that rate.
+    pub growth: GrowthForecast,
+    /// Configured storage budgets and how much of each is used.
+    pub quotas: Vec<QuotaUsage>,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default, Serialize)]
+pub struct KindCounts {
+    pub base: usize,
+    pub update: usize,
+    pub dlc: usize,
+    pub homebrew: usize,
+    pub unknown: usize,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Serialize)]
+pub struct LargestTitle {
+    pub title_id: String,
+    pub name: Option<String>,
+    pub total_size: u64,
+    pub file_count: usize,
+}
+
+/// Titles listed in `top_titles` by [`build_download_stats`].
+const TOP_DOWNLOADED_TITLES: usize = 20;
+
+#[derive(Debug, Serialize)]
+pub struct DownloadStatsResponse {
+    /// Completed downloads of all files.
+    pub downloads: u64,
+    /// Bytes served, including partial and aborted downloads.
+    pub bytes: u64,
+    /// Most downloaded titles, most first; updates and DLC count towards their base title.
+    pub top_titles: Vec<TitleDownloads>,
+    /// Every client by user name, or IP address when anonymous, most bytes first.
+    pub clients: Vec<ClientDownloads>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleDownloads {
+    /// Unset for files without a title ID, or no longer in the library.
+    pub title_id: Option<String>,
+    pub name: String,
+    pub downloads: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientDownloads {
+    pub client: String,
+    pub downloads: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicatesResponse {
+    pub hashing_enabled: bool,
+    pub by_hash: Vec<HashDuplicateGroup>,
+    pub by_title_version: Vec<TitleVersionDuplicateGroup>,
+    /// Files whose paths differ only by letter case.
+    pub case_collisions: Vec<Vec<DuplicateFile>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HashDuplicateGroup {
+    pub hash: String,
+    pub files: Vec<DuplicateFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleVersionDuplicateGroup {
+    pub title_id: String,
+    pub version: u32,
+    pub files: Vec<DuplicateFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateFile {
+    pub file_id: usize,
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub url: String,
+}
+
 impl From<&ApiEntry> for ShopFile {
     fn from(entry: &ApiEntry) -> Self {
         ShopFile {
@@ -189,20 +855,58 @@ pub fn map_to_entries<'a>(files: impl IntoIterator<Item = &'a ContentFile>) -> V
     files.into_iter().map(entry_to_api).collect()
 }
 
+/// Apply the requested `?sort=` order to a file listing.
+pub fn sort_files(files: Vec<&ContentFile>, sort: Option<CatalogSort>) -> Vec<&ContentFile> {
+    match sort {
+        Some(CatalogSort::Added) => by_recency(files),
+        None => files,
+    }
+}
+
 pub fn map_shop_files(entries: &[ApiEntry]) -> Vec<ShopFile> {
     entries.iter().map(ShopFile::from).collect()
 }
 
-pub fn entry_to_api(file: &ContentFile) -> ApiEntry {
-    let rel = file.relative_path.to_string_lossy();
-    let encoded_segments = rel
+/// Percent-encoded `/download/...` path for `file`.
+pub fn download_url(file: &ContentFile) -> String {
+    let encoded_segments = url_path(&file.relative_path)
         .split('/')
         .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string())
         .collect::<Vec<_>>()
         .join("/");
+    format!("/download/{encoded_segments}")
+}
+
+/// `/index.txt` body: one absolute download URL per line. `base` is the scheme, host,
+/// and any path prefix, without a trailing slash.
+pub fn build_index_txt(files: &[&ContentFile], base: &str) -> String {
+    files
+        .iter()
+        .map(|file| format!("{base}{}\n", download_url(file)))
+        .collect()
+}
+
+/// aria2 input file body: each download URL with an `out=` of its library path, so
+/// `aria2c -i` recreates the folder layout. aria2 cannot check BLAKE3, so known hashes are
+/// written as `# blake3 <hash>  <path>` comments that `grep '^# blake3' | cut -c10- | b3sum -c`
+/// understands.
+pub fn build_aria2_input(files: &[&ContentFile], base: &str) -> String {
+    let mut out = String::new();
+    for file in files {
+        let path = url_path(&file.relative_path);
+        if let Some(hash) = &file.hash {
+            out.push_str(&format!("# blake3 {hash}  {path}\n"));
+        }
+        out.push_str(&format!("{base}{}\n  out={path}\n", download_url(file)));
+    }
+    out
+}
+
+pub fn entry_to_api(file: &ContentFile) -> ApiEntry {
+    let rel = url_path(&file.relative_path);
 
     ApiEntry {
-        id: rel.to_string(),
+        id: rel.clone(),
         name: file.name.clone(),
         title_id: file.title_id.clone(),
         titleid: file.title_id.clone(),
@@ -212,58 +916,360 @@ pub fn entry_to_api(file: &ContentFile) -> ApiEntry {
         kind: file.kind,
         content_type: file.kind,
         size: file.size,
-        url: format!("/download/{encoded_segments}"),
+        modified: file.modified,
+        url: download_url(file),
+        verification: file.verification.clone(),
     }
 }
 
-pub fn build_catalog_response(entries: Vec<ApiEntry>) -> CatalogResponse {
+/// Catalog payload for `files`, limited to the page `query` asks for.
+pub fn build_catalog_response(files: Vec<&ContentFile>, query: &PageQuery) -> CatalogResponse {
+    let total = files.len();
+    let (files, page) = query.apply(files);
+    let entries = map_to_entries(files);
     CatalogResponse {
         success: "ok",
-        total: entries.len(),
+        total,
         files: map_shop_files(&entries),
-        directories: Vec::new(),
+        directories: root_directories(),
         entries,
         sections: catalog_sections(),
+        page,
+        cursor: None,
     }
 }
 
-pub fn build_shop_root_files(files: &[ContentFile]) -> Vec<ShopRootFile> {
-    files
+pub fn build_duplicates_response(catalog: &Catalog, hashing_enabled: bool) -> DuplicatesResponse {
+    let files = catalog.files();
+    let to_files = |ids: &[usize]| -> Vec<DuplicateFile> {
+        ids.iter()
+            .filter_map(|id| files.get(id - 1).map(|file| (*id, file)))
+            .map(|(file_id, file)| DuplicateFile {
+                file_id,
+                name: file.name.clone(),
+                path: url_path(&file.relative_path),
+                size: file.size,
+                url: shop_game_url(file_id, &file.name),
+            })
+            .collect()
+    };
+
+    let by_hash = catalog
+        .duplicates_by_hash()
+        .iter()
+        .filter_map(|ids| {
+            let hash = files.get(*ids.first()? - 1)?.hash.clone()?;
+            Some(HashDuplicateGroup {
+                hash,
+                files: to_files(ids),
+            })
+        })
+        .collect();
+    let by_title_version = catalog
+        .duplicates_by_title_version()
+        .iter()
+        .filter_map(|ids| {
+            let first = files.get(*ids.first()? - 1)?;
+            Some(TitleVersionDuplicateGroup {
+                title_id: first.title_id.clone()?,
+                version: first.version.unwrap_or(0),
+                files: to_files(ids),
+            })
+        })
+        .collect();
+
+    let case_collisions = catalog
+        .case_collisions()
+        .iter()
+        .map(|ids| to_files(ids))
+        .collect();
+
+    DuplicatesResponse {
+        hashing_enabled,
+        by_hash,
+        by_title_version,
+        case_collisions,
+    }
+}
+
+/// Health of the server at Unix time `now`: `error` when a root's latest scan failed,
+/// `degraded` when a scan or TitleDB is older than the `[health]` thresholds.
+pub fn build_health_response(
+    catalog_files: usize,
+    scans: &[ScanTiming],
+    titledb: Option<TitleDbHealth>,
+    limits: HealthConfig,
+    now: u64,
+) -> HealthResponse {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    for scan in scans.iter().filter(|scan| scan.failed) {
+        errors.push(format!("scan of {} failed", scan.root.display()));
+    }
+
+    let last_scan_age_seconds = scans
+        .iter()
+        .map(|scan| now.saturating_sub(scan.finished_at))
+        .max();
+    if let (Some(age), Some(max)) = (last_scan_age_seconds, limits.max_scan_age_seconds) {
+        if age > max {
+            warnings.push(format!("last scan is {age}s old (max {max}s)"));
+        }
+    }
+
+    let titledb_age_seconds = titledb.and_then(|titledb| titledb.age_seconds);
+    if let (Some(titledb), Some(max)) = (titledb, limits.max_titledb_age_seconds) {
+        match titledb.age_seconds {
+            Some(age) if age > max => {
+                warnings.push(format!("titledb is {age}s old (max {max}s)"));
+            }
+            Some(_) => {}
+            None => warnings.push(String::from("titledb has not been loaded")),
+        }
+    }
+
+    let status = if !errors.is_empty() {
+        "error"
+    } else if !warnings.is_empty() {
+        "degraded"
+    } else {
+        "ok"
+    };
+    errors.extend(warnings);
+    HealthResponse {
+        status,
+        catalog_files: Some(catalog_files),
+        last_scan_age_seconds,
+        titledb_age_seconds,
+        issues: errors,
+    }
+}
+
+/// TitleDB freshness, for an enabled TitleDB.
+#[derive(Debug, Clone, Copy)]
+pub struct TitleDbHealth {
+    /// Seconds since the last refresh; `None` if it never loaded.
+    pub age_seconds: Option<u64>,
+}
+
+#[cfg(feature = "metrics")]
+pub fn build_library_stats(
+    catalog: &Catalog,
+    names: &HashMap<String, String>,
+    scans: Vec<ScanTiming>,
+    growth: GrowthForecast,
+    quotas: Vec<QuotaUsage>,
+) -> LibraryStatsResponse {
+    let files = catalog.files();
+    let mut by_kind = KindCounts::default();
+    for file in files {
+        let count = match file.kind {
+            ContentKind::Base => &mut by_kind.base,
+            ContentKind::Update => &mut by_kind.update,
+            ContentKind::Dlc => &mut by_kind.dlc,
+            ContentKind::Homebrew => &mut by_kind.homebrew,
+            ContentKind::Unknown => &mut by_kind.unknown,
+        };
+        *count += 1;
+    }
+
+    let mut titles = catalog.titles();
+    titles.sort_by_key(|title| std::cmp::Reverse(title.total_size));
+    let largest = titles
         .iter()
-        .enumerate()
-        .map(|(index, file)| ShopRootFile {
-            url: shop_game_url(index + 1, &file.name),
+        .take(LARGEST_TITLES)
+        .map(|title| LargestTitle {
+            name: names.get(&title.title_id).cloned(),
+            title_id: title.title_id.clone(),
+            total_size: title.total_size,
+            file_count: title.file_count,
+        })
+        .collect();
+
+    LibraryStatsResponse {
+        titles: titles.len(),
+        files: files.len(),
+        total_bytes: files.iter().map(|file| file.size).sum(),
+        by_kind,
+        largest,
+        duplicate_files: catalog
+            .duplicates_by_title_version()
+            .iter()
+            .map(|group| group.len().saturating_sub(1))
+            .sum(),
+        untitled_files: files.iter().filter(|file| file.title_id.is_none()).count(),
+        scans,
+        growth,
+        quotas,
+    }
+}
+
+/// Base title IDs of the files in download `totals`, for looking up their names.
+pub fn downloaded_title_ids(totals: &Totals, catalog: &Catalog) -> Vec<String> {
+    totals
+        .files
+        .keys()
+        .filter_map(|path| catalog.find_by_relative_path(path))
+        .filter_map(|file| derive_base_title_id(file.kind, file.title_id.as_deref()))
+        .collect()
+}
+
+/// Download totals by title and client. `names` are TitleDB names by base title ID.
+pub fn build_download_stats(
+    totals: Totals,
+    catalog: &Catalog,
+    names: &HashMap<String, String>,
+) -> DownloadStatsResponse {
+    // Titles group by ID; files without one stand alone.
+    let mut titles: HashMap<String, TitleDownloads> = HashMap::new();
+    for (path, tally) in &totals.files {
+        let file = catalog.find_by_relative_path(path);
+        let title_id =
+            file.and_then(|file| derive_base_title_id(file.kind, file.title_id.as_deref()));
+        let name = title_id
+            .as_ref()
+            .and_then(|title_id| names.get(title_id).cloned())
+            .or_else(|| file.map(|file| file.name.clone()))
+            .unwrap_or_else(|| path.display().to_string());
+        let entry = titles
+            .entry(title_id.clone().unwrap_or_else(|| name.clone()))
+            .or_insert_with(|| TitleDownloads {
+                title_id,
+                name,
+                downloads: 0,
+                bytes: 0,
+            });
+        entry.downloads += tally.downloads;
+        entry.bytes = entry.bytes.saturating_add(tally.bytes);
+    }
+    let mut top_titles: Vec<_> = titles.into_values().collect();
+    top_titles.sort_by(|a, b| {
+        b.downloads
+            .cmp(&a.downloads)
+            .then(b.bytes.cmp(&a.bytes))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    top_titles.truncate(TOP_DOWNLOADED_TITLES);
+
+    let mut clients: Vec<_> = totals
+        .clients
+        .into_iter()
+        .map(|(client, tally)| ClientDownloads {
+            client,
+            downloads: tally.downloads,
+            bytes: tally.bytes,
+        })
+        .collect();
+    clients.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.client.cmp(&b.client)));
+
+    DownloadStatsResponse {
+        downloads: totals.files.values().map(|tally| tally.downloads).sum(),
+        bytes: totals.files.values().map(|tally| tally.bytes).sum(),
+        top_titles,
+        clients,
+    }
+}
+
+/// DLC known to TitleDB for base titles in the library but not present on disk.
+pub async fn build_missing_dlc_response(
+    catalog: &Catalog,
+    titledb: &TitleDb,
+) -> MissingDlcResponse {
+    let files = catalog.files();
+    let present = files
+        .iter()
+        .filter_map(|file| file.title_id.as_deref())
+        .map(str::to_ascii_uppercase)
+        .collect::<HashSet<_>>();
+    let bases = files
+        .iter()
+        .filter_map(|file| derive_base_title_id(file.kind, file.title_id.as_deref()))
+        .collect::<HashSet<_>>();
+
+    let mut titles = Vec::new();
+    for (title_id, dlc) in titledb.dlc_for(&bases).await {
+        let missing = dlc
+            .into_iter()
+            .filter(|(id, _)| !present.contains(id))
+            .map(|(title_id, name)| MissingDlc { title_id, name })
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            continue;
+        }
+        let name = titledb.lookup(&title_id).await.and_then(|info| info.name);
+        titles.push(MissingDlcTitle {
+            title_id,
+            name,
+            missing,
+        });
+    }
+
+    MissingDlcResponse {
+        titledb_entries: titledb.entry_count().await,
+        titles,
+    }
+}
+
+pub fn build_shop_root_files(indexed: &[(usize, &ContentFile)]) -> Vec<ShopRootFile> {
+    indexed
+        .iter()
+        .map(|(file_id, file)| ShopRootFile {
+            url: shop_game_url(*file_id, &file.name),
             size: file.size,
         })
         .collect()
 }
 
 pub async fn build_shop_sections_payload(
-    files: &[ContentFile],
+    indexed: &[(usize, &ContentFile)],
     limit: usize,
+    offset: usize,
     titledb: &TitleDb,
+    artwork: &ArtworkProvider,
+    overrides: &Overrides,
+    downloads: &DownloadStats,
 ) -> ShopSectionsResponse {
-    let indexed: Vec<_> = files.iter().enumerate().map(|(i, f)| (i + 1, f)).collect();
-
-    let title_map = resolve_title_map(&indexed, titledb).await;
+    let title_map = resolve_title_map(indexed, titledb, artwork).await;
+    let paths: HashMap<usize, &Path> = indexed
+        .iter()
+        .map(|(file_id, file)| (*file_id, file.relative_path.as_path()))
+        .collect();
 
-    let base_items = collect_base_items(&indexed, &title_map);
-    let update_items_full =
-        collect_latest_by_key(&indexed, ContentKind::Update, &title_map, |item| {
+    let mut base_items = collect_base_items(indexed, &title_map);
+    let mut update_items_full =
+        collect_latest_by_key(indexed, ContentKind::Update, &title_map, |item| {
             item.title_id.clone().unwrap_or_else(|| item.app_id.clone())
         });
-    let dlc_items_full = collect_latest_by_key(&indexed, ContentKind::Dlc, &title_map, |item| {
+    let mut dlc_items_full = collect_latest_by_key(indexed, ContentKind::Dlc, &title_map, |item| {
         item.app_id.clone()
     });
+    let mut homebrew_items_full =
+        collect_latest_by_key(indexed, ContentKind::Homebrew, &title_map, |item| {
+            item.app_id.clone()
+        });
+    for item in base_items
+        .iter_mut()
+        .chain(update_items_full.iter_mut())
+        .chain(dlc_items_full.iter_mut())
+        .chain(homebrew_items_full.iter_mut())
+    {
+        if let Some(name) = overrides.name(&item.app_id) {
+            item.name = name.to_string();
+            item.title_name = name.to_string();
+        }
+        if let Some(path) = paths.get(&item.file_id) {
+            item.download_count = downloads.download_count(path);
+        }
+    }
 
     let mut all_items: Vec<_> = base_items
         .iter()
         .chain(update_items_full.iter())
         .chain(dlc_items_full.iter())
+        .chain(homebrew_items_full.iter())
         .cloned()
         .collect();
-    all_items.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-    let all_total = all_items.len();
+    all_items.sort_by_key(|item| item.name.to_lowercase());
 
     let new_items = base_items.iter().take(limit).cloned().collect::<Vec<_>>();
     let new_items = if new_items.is_empty() {
@@ -293,34 +1299,38 @@ pub async fn build_shop_sections_payload(
                 total: None,
                 truncated: None,
             },
-            ShopSection {
-                id: "updates",
-                title: "Updates",
-                items: update_items_full.iter().take(limit).cloned().collect(),
-                total: None,
-                truncated: None,
-            },
-            ShopSection {
-                id: "dlc",
-                title: "DLC",
-                items: dlc_items_full.iter().take(limit).cloned().collect(),
-                total: None,
-                truncated: None,
-            },
-            ShopSection {
-                id: "all",
-                title: "All",
-                items: all_items,
-                total: Some(all_total),
-                truncated: Some(false),
-            },
+            paged_section("updates", "Updates", &update_items_full, 0, limit),
+            paged_section("dlc", "DLC", &dlc_items_full, 0, limit),
+            paged_section("homebrew", "Homebrew", &homebrew_items_full, 0, limit),
+            paged_section("all", "All", &all_items, offset, limit),
         ],
     }
 }
 
+/// Up to `limit` of `items` starting at `offset`, with the full count.
+fn paged_section(
+    id: &'static str,
+    title: &'static str,
+    items: &[ShopSectionItem],
+    offset: usize,
+    limit: usize,
+) -> ShopSection {
+    let page: Vec<_> = items.iter().skip(offset).take(limit).cloned().collect();
+    ShopSection {
+        id,
+        title,
+        total: Some(items.len()),
+        truncated: Some(offset.saturating_add(page.len()) < items.len()),
+        items: page,
+    }
+}
+
+/// TitleDB info per base title. Titles with a local icon override drop the TitleDB icon
+/// URL so items link to the local icon endpoint instead.
 async fn resolve_title_map(
     indexed: &[(usize, &ContentFile)],
     titledb: &TitleDb,
+    artwork: &ArtworkProvider,
 ) -> HashMap<String, TitleInfo> {
     let ids: Vec<String> = indexed
         .iter()
@@ -329,10 +1339,17 @@ async fn resolve_title_map(
         .into_iter()
         .collect();
 
-    let results = futures_util::future::join_all(ids.iter().map(|id| titledb.lookup(id))).await;
+    let results = futures_util::future::join_all(ids.iter().map(|id| async move {
+        let mut info = titledb.lookup(id).await?;
+        if artwork.has_icon_override(id).await {
+            info.icon_url = None;
+        }
+        Some(info)
+    }))
+    .await;
 
     ids.into_iter()
-        .zip(results.into_iter())
+        .zip(results)
         .filter_map(|(id, info)| info.map(|i| (id, i)))
         .collect()
 }
@@ -346,7 +1363,7 @@ fn collect_base_items(
         .filter(|(_, file)| matches!(file.kind, ContentKind::Base | ContentKind::Unknown))
         .map(|(idx, file)| to_shop_section_item(*idx, file, title_map))
         .collect();
-    items.sort_by(|a, b| b.file_id.cmp(&a.file_id));
+    items.sort_by_key(|item| std::cmp::Reverse((item.modified, item.file_id)));
     items
 }
 
@@ -414,43 +1431,18 @@ fn to_shop_section_item(
         file_id,
         filename: file.name.clone(),
         download_count: 0,
+        modified: file.modified,
     }
 }
 
 fn app_type_for_kind(kind: ContentKind) -> &'static str {
     match kind {
-        ContentKind::Base | ContentKind::Unknown => "BASE",
+        ContentKind::Base | ContentKind::Homebrew | ContentKind::Unknown => "BASE",
         ContentKind::Update => "UPDATE",
         ContentKind::Dlc => "DLC",
     }
 }
 
-fn derive_base_title_id(kind: ContentKind, title_id: Option<&str>) -> Option<String> {
-    let raw = title_id?;
-    if raw.len() != 16 || !raw.chars().all(|ch| ch.is_ascii_hexdigit()) {
-        return None;
-    }
-
-    let normalized = raw.to_ascii_uppercase();
-    match kind {
-        ContentKind::Base | ContentKind::Unknown => Some(normalized),
-        ContentKind::Update => {
-            let mut chars = normalized.chars().collect::<Vec<_>>();
-            let len = chars.len();
-            chars[len - 3] = '0';
-            chars[len - 2] = '0';
-            chars[len - 1] = '0';
-            Some(chars.into_iter().collect::<String>())
-        }
-        ContentKind::Dlc => {
-            let high = &normalized[..13];
-            let high_value = u64::from_str_radix(high, 16).ok()?;
-            let base_high = high_value.checked_sub(1)?;
-            Some(format!("{base_high:013X}000"))
-        }
-    }
-}
-
 fn parse_version_number(raw: &str) -> u64 {
     raw.parse::<u64>().unwrap_or(0)
 }
@@ -481,6 +1473,10 @@ pub fn catalog_sections() -> Vec<SectionInfo> {
             id: "dlc",
             label: "DLC",
         },
+        SectionInfo {
+            id: "homebrew",
+            label: "Homebrew",
+        },
         SectionInfo {
             id: "all",
             label: "All",
@@ -499,15 +1495,45 @@ const PLACEHOLDER_SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width=
   </g>
 </svg>"##;
 
-pub fn static_png_response() -> axum::response::Response {
-    use axum::body::Body;
-    use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+/// The placeholder shown for titles without artwork.
+pub fn placeholder_artwork() -> Artwork {
+    Artwork {
+        bytes: bytes::Bytes::from_static(PLACEHOLDER_SVG.as_bytes()),
+        content_type: String::from("image/svg+xml"),
+        modified: None,
+        fetched: None,
+    }
+}
+
+/// Whether the client lists SVG in its `Accept` header. Clients that don't (Tinfoil,
+/// CyberFoil, most non-browser HTTP clients) get SVG art rendered to PNG.
+pub fn accepts_svg(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get_all(axum::http::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| media.split(';').next().map(str::trim) == Some("image/svg+xml"))
+}
+
+/// Whether the request comes from Tinfoil, which sends its `Theme` and `Uid` headers with
+/// every request. CyberFoil can send them too but names itself in `User-Agent`.
+pub fn is_tinfoil(headers: &axum::http::HeaderMap) -> bool {
+    let cyberfoil = headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|agent| agent.to_ascii_lowercase().contains("cyberfoil"));
+    headers.contains_key("theme") && headers.contains_key("uid") && !cyberfoil
+}
+
+pub fn static_png_response(
+    artwork: Artwork,
+    headers: &axum::http::HeaderMap,
+) -> axum::response::Response {
+    use axum::http::header::CACHE_CONTROL;
     use axum::http::HeaderValue;
 
-    let mut response = axum::response::Response::new(Body::from(PLACEHOLDER_SVG));
-    response
-        .headers_mut()
-        .insert(CONTENT_TYPE, HeaderValue::from_static("image/svg+xml"));
+    let mut response = artwork_response(artwork, headers);
     response.headers_mut().insert(
         CACHE_CONTROL,
         HeaderValue::from_static("public, max-age=604800, immutable"),
@@ -515,11 +1541,112 @@ pub fn static_png_response() -> axum::response::Response {
     response
 }
 
+/// Serve artwork bytes with their own content type, answering `If-Modified-Since` with
+/// `304 Not Modified`. Art copied from a remote provider reports how long it has been in
+/// the artwork cache as `Age`, so downstream caches don't keep it past a day in total.
+pub fn artwork_response(
+    artwork: Artwork,
+    headers: &axum::http::HeaderMap,
+) -> axum::response::Response {
+    use axum::body::Body;
+    use axum::http::header::{AGE, CACHE_CONTROL, CONTENT_TYPE, LAST_MODIFIED, VARY};
+    use axum::http::HeaderValue;
+
+    use crate::serve_files::{is_not_modified, last_modified_value, not_modified_response};
+
+    let age = artwork.age();
+    let mut response = match artwork.modified {
+        Some(modified) if is_not_modified(headers, modified) => not_modified_response(modified),
+        _ => axum::response::Response::new(Body::from(artwork.bytes)),
+    };
+    let response_headers = response.headers_mut();
+    if let Ok(content_type) = HeaderValue::from_str(&artwork.content_type) {
+        response_headers.insert(CONTENT_TYPE, content_type);
+    }
+    if let Some(modified) = artwork.modified {
+        response_headers.insert(LAST_MODIFIED, last_modified_value(modified));
+    }
+    if let Some(age) = age {
+        response_headers.insert(AGE, HeaderValue::from(age.as_secs()));
+    }
+    if let Ok(cache_control) = HeaderValue::from_str(&format!(
+        "public, max-age={}",
+        crate::artwork::CACHE_TTL.as_secs()
+    )) {
+        response_headers.insert(CACHE_CONTROL, cache_control);
+    }
+    // The same URL serves SVG or PNG depending on `Accept`.
+    response_headers.insert(VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+/// Rewrite the server-relative URLs (`/download/...`, `/api/...`, `/u/...`) in a JSON
+/// response to start with `prefix`, so clients browsing a token path keep using it. Other responses,
+/// such as file downloads, pass through untouched.
+pub async fn prefix_json_response(
+    response: axum::response::Response,
+    prefix: &str,
+) -> Result<axum::response::Response, ApiError> {
+    use axum::body::Body;
+    use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return Ok(response);
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|_| ApiError::Internal)?;
+    let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+        return Ok(axum::response::Response::from_parts(
+            parts,
+            Body::from(bytes),
+        ));
+    };
+    prefix_urls(&mut value, prefix);
+    let bytes = serde_json::to_vec(&value).map_err(|_| ApiError::Internal)?;
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(axum::response::Response::from_parts(
+        parts,
+        Body::from(bytes),
+    ))
+}
+
+/// Prepend `prefix` to the server-relative URLs anywhere in `value`.
+pub fn prefix_urls(value: &mut serde_json::Value, prefix: &str) {
+    match value {
+        serde_json::Value::String(text)
+            if ["/download/", "/api/", "/u/"]
+                .iter()
+                .any(|start| text.starts_with(start)) =>
+        {
+            text.insert_str(0, prefix);
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                prefix_urls(item, prefix);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for field in fields.values_mut() {
+                prefix_urls(field, prefix);
+            }
+        }
+        _ => {}
+    }
+}
+
 pub fn map_file_error(error: FileServeError) -> ApiError {
     match error {
         FileServeError::InvalidPath => ApiError::InvalidPath,
         FileServeError::NotFound => ApiError::NotFound,
         FileServeError::InvalidRange => ApiError::InvalidRange,
+        FileServeError::NotNsz => ApiError::NotNsz,
         FileServeError::Io(_) | FileServeError::HeaderValue(_) => ApiError::Internal,
     }
 }
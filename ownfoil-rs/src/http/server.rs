@@ -0,0 +1,464 @@
+//! Listener: accepts TCP or Unix socket connections and serves the router over HTTP/1.1,
+//! TCP ones optionally inside TLS.
+//!
+//! Used instead of `axum::serve` so connections can be guarded before any handler runs.
+//! The server and each client IP may hold a limited number of open connections; extra
+//! ones are closed as soon as they are accepted. A connection that doesn't finish its
+//! TLS handshake or sending its request headers in time is closed too, as is one that
+//! moves no bytes for the idle timeout, so slow-loris clients and stalled downloads can't
+//! pin sockets and tasks. Unix socket clients are local reverse proxies: they appear as
+//! `127.0.0.1` and only count towards the server-wide limit.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderValue, Request};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::time::Sleep;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use super::tls::ACME_TLS_ALPN;
+use crate::config::ServerConfig;
+
+/// Open connections, server-wide and per client IP.
+#[derive(Debug, Clone)]
+struct ConnectionCounter {
+    limit: usize,
+    per_ip_limit: usize,
+    open: Arc<Mutex<OpenConnections>>,
+}
+
+#[derive(Debug, Default)]
+struct OpenConnections {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// One open connection; frees its slot when dropped.
+#[derive(Debug)]
+struct ConnectionSlot {
+    ip: Option<IpAddr>,
+    open: Arc<Mutex<OpenConnections>>,
+}
+
+impl ConnectionCounter {
+    fn new(limit: usize, per_ip_limit: usize) -> Self {
+        Self {
+            limit,
+            per_ip_limit,
+            open: Arc::default(),
+        }
+    }
+
+    /// Take a slot for a client at `ip` (`None` for local clients, which aren't limited
+    /// per IP), or `None` when the server or that IP is at its limit.
+    fn acquire(&self, ip: Option<IpAddr>) -> Option<ConnectionSlot> {
+        let mut open = self.open.lock().unwrap_or_else(|p| p.into_inner());
+        if open.total >= self.limit {
+            return None;
+        }
+        if let Some(ip) = ip {
+            let count = open.per_ip.entry(ip).or_default();
+            if *count >= self.per_ip_limit {
+                return None;
+            }
+            *count += 1;
+        }
+        open.total += 1;
+        Some(ConnectionSlot {
+            ip,
+            open: Arc::clone(&self.open),
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap_or_else(|p| p.into_inner());
+        open.total = open.total.saturating_sub(1);
+        if let Some(ip) = self.ip {
+            if let Some(count) = open.per_ip.get_mut(&ip) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    open.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// A connection that fails once no bytes have moved either way for `timeout`: a client
+/// idling between keep-alive requests, stalled mid-upload, or no longer reading its
+/// download.
+struct IdleTimeout<T> {
+    io: T,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<T> IdleTimeout<T> {
+    fn new(io: T, timeout: Duration) -> Self {
+        Self {
+            io,
+            timeout,
+            deadline: Box::pin(tokio::time::sleep(timeout)),
+        }
+    }
+
+    /// Push the deadline back after progress, or fail a stalled operation once it passes.
+    fn check<R>(&mut self, cx: &mut Context<'_>, poll: Poll<io::Result<R>>) -> Poll<io::Result<R>> {
+        if poll.is_ready() {
+            self.deadline
+                .as_mut()
+                .reset(tokio::time::Instant::now() + self.timeout);
+            return poll;
+        }
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection idle",
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for IdleTimeout<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.io).poll_read(cx, buf);
+        this.check(cx, poll)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.io).poll_write(cx, buf);
+        this.check(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.io).poll_write_vectored(cx, bufs);
+        this.check(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.io).poll_flush(cx);
+        this.check(cx, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// A socket [`serve`] accepts connections on.
+pub trait Listener: Send + 'static {
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    /// Whether peers are remote clients, limited per IP.
+    const REMOTE: bool;
+
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Io, SocketAddr)>> + Send;
+}
+
+impl Listener for TcpListener {
+    type Io = tokio::net::TcpStream;
+    const REMOTE: bool = true;
+
+    async fn accept(&self) -> io::Result<(Self::Io, SocketAddr)> {
+        TcpListener::accept(self).await
+    }
+}
+
+#[cfg(unix)]
+impl Listener for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+    const REMOTE: bool = false;
+
+    async fn accept(&self) -> io::Result<(Self::Io, SocketAddr)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((stream, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))))
+    }
+}
+
+/// Listen on the Unix socket `path`, replacing a socket left behind by an earlier run.
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    tokio::net::UnixListener::bind(path)
+}
+
+/// Serve `app` on `listener` until `shutdown` resolves, then wait for open connections
+/// to finish. With `tls`, connections are HTTPS and requests without an
+/// `X-Forwarded-Proto` get `https`. Handlers see the peer address as
+/// `ConnectInfo<SocketAddr>`.
+pub async fn serve<L: Listener>(
+    listener: L,
+    app: Router,
+    config: ServerConfig,
+    tls: Option<TlsAcceptor>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let counter = ConnectionCounter::new(
+        config.max_connections.max(1),
+        config.max_connections_per_ip.max(1),
+    );
+    let graceful = GracefulShutdown::new();
+    let header_timeout = Duration::from_secs(config.header_read_timeout_seconds.max(1));
+    let idle_timeout = Duration::from_secs(config.idle_timeout_seconds.max(1));
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_timeout);
+
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Usually out of file descriptors; back off instead of spinning.
+                    warn!(error = %err, "failed to accept connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        let Some(slot) = counter.acquire(L::REMOTE.then(|| peer.ip())) else {
+            debug!(peer = %peer, "connection limit reached; closing connection");
+            continue;
+        };
+        let stream = IdleTimeout::new(stream, idle_timeout);
+
+        let https = tls.is_some();
+        let service = app
+            .clone()
+            .map_request(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                if https {
+                    request
+                        .headers_mut()
+                        .entry("x-forwarded-proto")
+                        .or_insert(HeaderValue::from_static("https"));
+                }
+                request.map(Body::new)
+            });
+        let service = TowerToHyperService::new(service);
+        let Some(acceptor) = tls.clone() else {
+            let connection =
+                graceful.watch(builder.serve_connection(TokioIo::new(stream), service));
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    debug!(peer = %peer, error = %err, "connection closed with error");
+                }
+                drop(slot);
+            });
+            continue;
+        };
+        let (watcher, builder) = (graceful.watcher(), builder.clone());
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(header_timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    debug!(peer = %peer, error = %err, "TLS handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    debug!(peer = %peer, "TLS handshake timed out");
+                    return;
+                }
+            };
+            if stream.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN) {
+                debug!(peer = %peer, "answered ACME TLS-ALPN validation");
+                return;
+            }
+            let connection = watcher.watch(builder.serve_connection(TokioIo::new(stream), service));
+            if let Err(err) = connection.await {
+                debug!(peer = %peer, error = %err, "connection closed with error");
+            }
+            drop(slot);
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use axum::routing::get;
+    use axum::Router;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{serve, ConnectionCounter};
+    use crate::config::ServerConfig;
+
+    #[test]
+    fn slots_are_limited_per_ip_and_freed_on_drop() {
+        let counter = ConnectionCounter::new(4, 2);
+        let client = Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)));
+        let other = Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 21)));
+
+        let first = counter.acquire(client);
+        let second = counter.acquire(client);
+        assert!(first.is_some() && second.is_some());
+        assert!(counter.acquire(client).is_none());
+        let third = counter.acquire(other);
+        assert!(third.is_some());
+
+        drop(first);
+        let fourth = counter.acquire(client);
+        assert!(fourth.is_some());
+
+        // Local clients skip the per-IP limit but not the server-wide one.
+        let local = counter.acquire(None);
+        assert!(local.is_some());
+        assert!(counter.acquire(None).is_none());
+        assert!(counter.acquire(other).is_none());
+        drop(local);
+        assert!(counter.acquire(None).is_some());
+    }
+
+    #[tokio::test]
+    async fn closes_connections_that_stall_before_finishing_headers() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let config = ServerConfig {
+            max_connections_per_ip: 1,
+            header_read_timeout_seconds: 1,
+            ..ServerConfig::default()
+        };
+        tokio::spawn(serve(listener, app, config, None, std::future::pending()));
+
+        let mut slow = TcpStream::connect(addr).await?;
+        slow.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n")
+            .await?;
+
+        // The slow connection holds the only slot for this IP.
+        let mut extra = TcpStream::connect(addr).await?;
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), extra.read_to_end(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0))));
+
+        let read = tokio::time::timeout(Duration::from_secs(5), slow.read_to_end(&mut buf)).await;
+        assert!(read.is_ok(), "stalled connection was not closed");
+
+        // With the slot free again, a complete request is answered.
+        let mut client = TcpStream::connect(addr).await?;
+        client
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_string(&mut response))
+            .await??;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn closes_connections_that_stop_moving_bytes() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let config = ServerConfig {
+            idle_timeout_seconds: 1,
+            ..ServerConfig::default()
+        };
+        tokio::spawn(serve(listener, app, config, None, std::future::pending()));
+
+        // A keep-alive connection is answered, then closed once it sits idle.
+        let mut client = TcpStream::connect(addr).await?;
+        client
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut buf)).await;
+        assert!(read.is_ok(), "idle connection was not closed");
+        assert!(String::from_utf8_lossy(&buf).starts_with("HTTP/1.1 200"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_unix_socket_clients_without_per_ip_limits() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ownfoil.sock");
+        // A socket left behind by an earlier run is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&path)?);
+        let listener = super::bind_unix(&path)?;
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let config = ServerConfig {
+            max_connections_per_ip: 1,
+            ..ServerConfig::default()
+        };
+        tokio::spawn(serve(listener, app, config, None, std::future::pending()));
+
+        let _idle = tokio::net::UnixStream::connect(&path).await?;
+        let mut client = tokio::net::UnixStream::connect(&path).await?;
+        client
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_string(&mut response))
+            .await??;
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        std::fs::write(dir.path().join("file"), "")?;
+        assert!(super::bind_unix(&dir.path().join("file")).is_err());
+        Ok(())
+    }
+}
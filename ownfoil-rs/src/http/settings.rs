@@ -1,19 +1,236 @@
-use std::path::Path;
+//! Runtime settings persisted to `<data_dir>/settings.toml`.
+//!
+//! Writes go to a temp file that is renamed over the original, under a lock file so
+//! concurrent saves (two admin tabs, two processes sharing a data dir) can't interleave.
+//! The previous versions are kept as `settings.toml.bak`, `settings.toml.bak.1`, ...
+//!
+//! Every save bumps a revision number; saves based on an older revision are rejected so
+//! one admin tab can't silently overwrite another's changes.
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
 
 use crate::config::TitleDbConfig;
 
-pub fn save_settings(data_dir: &Path, titledb: &TitleDbConfig) -> std::io::Result<()> {
+const SETTINGS_FILE: &str = "settings.toml";
+/// Number of previous settings files kept next to `settings.toml`.
+const BACKUPS: usize = 3;
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// A lock file older than this is assumed to be left over from a crashed process.
+const STALE_LOCK: Duration = Duration::from_secs(30);
+
+/// Serializes saves within this process; the lock file covers other processes.
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Current settings revision plus a channel announcing each new one.
+#[derive(Debug, Clone)]
+pub struct SettingsRevision {
+    current: Arc<tokio::sync::Mutex<u64>>,
+    events: broadcast::Sender<u64>,
+}
+
+impl SettingsRevision {
+    pub fn new(revision: u64) -> Self {
+        let (events, _) = broadcast::channel(16);
+        Self {
+            current: Arc::new(tokio::sync::Mutex::new(revision)),
+            events,
+        }
+    }
+
+    /// Start from the revision stored in `<data_dir>/settings.toml` (0 if none).
+    pub fn load(data_dir: &Path) -> Self {
+        let revision = std::fs::read_to_string(data_dir.join(SETTINGS_FILE))
+            .ok()
+            .and_then(|raw| toml::from_str::<toml::Value>(&raw).ok())
+            .and_then(|value| value.get("revision")?.as_integer())
+            .and_then(|revision| u64::try_from(revision).ok())
+            .unwrap_or(0);
+        Self::new(revision)
+    }
+
+    pub async fn current(&self) -> u64 {
+        *self.current.lock().await
+    }
+
+    /// Hold the revision while a change is applied, so concurrent saves are serialized.
+    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, u64> {
+        self.current.lock().await
+    }
+
+    /// Tell subscribers that settings moved to `revision`.
+    pub fn publish(&self, revision: u64) {
+        let _ = self.events.send(revision);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.events.subscribe()
+    }
+}
+
+pub fn save_settings(
+    data_dir: &Path,
+    revision: u64,
+    titledb: &TitleDbConfig,
+) -> std::io::Result<()> {
     let settings_dir = data_dir;
     std::fs::create_dir_all(settings_dir)?;
-    let path = settings_dir.join("settings.toml");
+    let path = settings_dir.join(SETTINGS_FILE);
     let content = toml::to_string_pretty(&RuntimeSettings {
+        revision,
         titledb: titledb.clone(),
     })
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-    std::fs::write(path, content)
+    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+    let _guard = SAVE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _lock = LockFile::acquire(&sibling(&path, "lock"))?;
+
+    let temp = sibling(&path, "tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    if path.exists() {
+        rotate_backups(&path)?;
+    }
+    std::fs::rename(&temp, &path)
+}
+
+/// Shift `settings.toml.bak` -> `.bak.1` -> ... and copy the current file to `.bak`.
+fn rotate_backups(path: &Path) -> std::io::Result<()> {
+    let backup = |n: usize| match n {
+        0 => sibling(path, "bak"),
+        n => sibling(path, &format!("bak.{n}")),
+    };
+    for n in (1..BACKUPS).rev() {
+        let from = backup(n - 1);
+        if from.exists() {
+            std::fs::rename(&from, backup(n))?;
+        }
+    }
+    std::fs::copy(path, backup(0)).map(|_| ())
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Advisory lock held by creating a file exclusively; removed on drop.
+struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    fn acquire(path: &Path) -> std::io::Result<Self> {
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => {
+                    return Ok(Self {
+                        path: path.to_path_buf(),
+                    })
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .is_some_and(|age| age > STALE_LOCK);
+                    if stale {
+                        tracing::warn!(path = %path.display(), "removing stale settings lock");
+                        let _ = std::fs::remove_file(path);
+                        continue;
+                    }
+                    if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(std::io::Error::new(
+                            ErrorKind::WouldBlock,
+                            format!("settings are locked by {}", path.display()),
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(25));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
 }
 
 #[derive(serde::Serialize)]
 struct RuntimeSettings {
+    revision: u64,
     titledb: TitleDbConfig,
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{save_settings, SettingsRevision};
+    use crate::config::TitleDbConfig;
+
+    fn config(region: &str) -> TitleDbConfig {
+        TitleDbConfig {
+            region: String::from(region),
+            ..TitleDbConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn saves_keep_rotating_backups() -> Result<()> {
+        let dir = tempdir()?;
+        for (revision, region) in ["US", "GB", "JP", "DE", "FR"].into_iter().enumerate() {
+            save_settings(dir.path(), revision as u64 + 1, &config(region))?;
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name));
+
+        assert!(read("settings.toml")?.contains("\"FR\""));
+        assert!(read("settings.toml.bak")?.contains("\"DE\""));
+        assert!(read("settings.toml.bak.1")?.contains("\"JP\""));
+        assert!(read("settings.toml.bak.2")?.contains("\"GB\""));
+        assert!(!dir.path().join("settings.toml.bak.3").exists());
+        assert!(!dir.path().join("settings.toml.lock").exists());
+        assert!(!dir.path().join("settings.toml.tmp").exists());
+        assert_eq!(SettingsRevision::load(dir.path()).current().await, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_saves_leave_a_valid_file() -> Result<()> {
+        let dir = tempdir()?;
+        let handles: Vec<_> = ["US", "GB", "JP", "DE"]
+            .into_iter()
+            .map(|region| {
+                let path = dir.path().to_path_buf();
+                std::thread::spawn(move || save_settings(&path, 1, &config(region)))
+            })
+            .collect();
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("save thread panicked"))??;
+        }
+
+        let raw = std::fs::read_to_string(dir.path().join("settings.toml"))?;
+        let parsed: toml::Value = toml::from_str(&raw)?;
+        assert!(parsed.get("titledb").is_some());
+        Ok(())
+    }
+}
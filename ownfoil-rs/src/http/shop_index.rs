@@ -0,0 +1,139 @@
+//! Shop index: the JSON document Tinfoil and CyberFoil load from the shop root.
+//!
+//! Besides `files`, Tinfoil reads shop-wide directives from it: the welcome message in
+//! `success`, a referrer and headers for its requests, a minimum client version, theme
+//! restrictions, and a client certificate. They come from `[shop]` and are assembled once
+//! at startup; each request only adds the file list.
+
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::client_views::{ShopClient, ShopViews};
+use super::responses::ShopRootFile;
+use crate::announcements::Announcement;
+use crate::config::{ShopConfig, ShopView};
+
+#[derive(Debug, Error)]
+pub enum ShopIndexError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+/// The shop root sent to clients: `success`, `files`, and any configured directives.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ShopIndexDocument {
+    pub success: String,
+    pub files: Vec<ShopRootFile>,
+    #[serde(flatten)]
+    pub directives: Map<String, Value>,
+}
+
+/// Builds shop index documents.
+#[derive(Debug, Clone)]
+pub struct ShopIndex {
+    success: String,
+    directives: Map<String, Value>,
+    views: ShopViews,
+}
+
+impl Default for ShopIndex {
+    fn default() -> Self {
+        Self {
+            success: String::from("ok"),
+            directives: Map::new(),
+            views: ShopViews::default(),
+        }
+    }
+}
+
+impl ShopIndex {
+    /// Assemble the directives configured in `[shop]`, reading the client certificate
+    /// files if set.
+    pub fn from_config(config: &ShopConfig) -> Result<Self, ShopIndexError> {
+        let mut index = Self {
+            views: ShopViews::new(config.views.clone()),
+            ..Self::default()
+        };
+        if let Some(motd) = &config.motd {
+            index = index.success(motd);
+        }
+        index = index
+            .directive("referrer", config.referrer.clone())
+            .directive("headers", non_empty(&config.headers))
+            .directive("googleApiKey", config.google_api_key.clone())
+            .directive("minVersion", config.min_version)
+            .directive("themeBlackList", non_empty(&config.theme_blacklist))
+            .directive("themeWhiteList", non_empty(&config.theme_whitelist))
+            .directive("themeError", config.theme_error.clone());
+        if let Some(path) = &config.client_cert_file {
+            index = index.directive("clientCertPub", Some(read_pem(path)?));
+        }
+        if let Some(path) = &config.client_key_file {
+            index = index.directive("clientCertKey", Some(read_pem(path)?));
+        }
+        Ok(index)
+    }
+
+    /// Set the welcome message.
+    pub fn success(mut self, message: impl Into<String>) -> Self {
+        self.success = message.into();
+        self
+    }
+
+    /// Add a top-level field; `None` leaves it out.
+    pub fn directive(mut self, key: &str, value: Option<impl Into<Value>>) -> Self {
+        if let Some(value) = value {
+            self.directives.insert(key.to_string(), value.into());
+        }
+        self
+    }
+
+    /// What `client` gets from the shop index `route`.
+    pub fn view(&self, route: &str, client: ShopClient) -> ShopView {
+        self.views.view(route, client)
+    }
+
+    /// Whether `route` answers clients differently.
+    pub fn varies(&self, route: &str) -> bool {
+        self.views.varies(route)
+    }
+
+    /// The welcome message, or the announcement that takes its place.
+    pub fn message(&self, announcement: Option<&Announcement>) -> String {
+        match announcement {
+            Some(announcement) => announcement.message.clone(),
+            None => self.success.clone(),
+        }
+    }
+
+    /// The document listing `files`, with [`Self::message`] as `success`.
+    pub fn build(
+        &self,
+        files: Vec<ShopRootFile>,
+        announcement: Option<&Announcement>,
+    ) -> ShopIndexDocument {
+        ShopIndexDocument {
+            success: self.message(announcement),
+            files,
+            directives: self.directives.clone(),
+        }
+    }
+}
+
+fn non_empty(values: &[String]) -> Option<Vec<String>> {
+    (!values.is_empty()).then(|| values.to_vec())
+}
+
+fn read_pem(path: &Path) -> Result<String, ShopIndexError> {
+    std::fs::read_to_string(path).map_err(|source| ShopIndexError::Read {
+        path: path.display().to_string(),
+        source,
+    })
+}
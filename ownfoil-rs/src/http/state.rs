@@ -5,12 +5,37 @@ use std::time::{Duration, Instant};
 use dashmap::DashMap;
 use tokio::sync::{broadcast, RwLock};
 
-use crate::auth::AuthSettings;
-use crate::catalog::Catalog;
+use super::settings::SettingsRevision;
+use super::shop_index::ShopIndex;
+use crate::announcements::AnnouncementStore;
+use crate::api_tokens::ApiTokenStore;
+use crate::artwork::ArtworkProvider;
+use crate::auth::SharedAuth;
+use crate::bandwidth::Bandwidth;
+use crate::catalog::{Catalog, FormatPreference};
+use crate::chunks::ChunkMaps;
+use crate::config::{HealthConfig, RateLimitConfig};
+#[cfg(feature = "metrics")]
+use crate::growth::GrowthStore;
+use crate::hooks::DownloadHook;
+use crate::idle::IdleTracker;
+use crate::index::IndexEncryptor;
+use crate::jobs::JobManager;
+use crate::library::LibrarySet;
+use crate::overrides::OverrideStore;
+use crate::quota::Quotas;
+use crate::reports::Reporter;
+use crate::shop_tokens::ShopTokenStore;
+use crate::slots::DownloadSlots;
+use crate::speedtest::SpeedTestLimiter;
+use crate::stats::DownloadStats;
 use crate::titledb::TitleDb;
+use crate::upload::Uploads;
 
-/// Session token -> (username, expires_at). Sessions expire after 24 hours.
+/// Session token -> (username, expires_at). Sessions expire after 24 hours. Only the
+/// admin pages log in, so without the `admin-ui` feature the store stays empty.
 #[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "admin-ui"), allow(dead_code))]
 pub struct SessionStore {
     inner: Arc<DashMap<String, (String, Instant)>>,
     ttl: Duration,
@@ -24,6 +49,7 @@ impl SessionStore {
         }
     }
 
+    #[cfg_attr(not(feature = "admin-ui"), allow(dead_code))]
     pub fn create(&self, username: String) -> String {
         let token = uuid::Uuid::new_v4().to_string();
         let expires = Instant::now() + self.ttl;
@@ -42,6 +68,7 @@ impl SessionStore {
         }
     }
 
+    #[cfg_attr(not(feature = "admin-ui"), allow(dead_code))]
     pub fn remove(&self, token: &str) {
         self.inner.remove(token);
     }
@@ -50,11 +77,69 @@ impl SessionStore {
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub catalog: Arc<RwLock<Catalog>>,
-    pub library_root: PathBuf,
-    pub auth: Arc<AuthSettings>,
+    pub library: LibrarySet,
+    pub jobs: JobManager,
+    /// Secondary root for per-title replication, if configured.
+    pub mirror_root: Option<PathBuf>,
+    /// Shop listings show only the best copy per title when set.
+    pub dedup: Option<FormatPreference>,
+    pub downloads: DownloadStats,
+    /// `[downloads]` bandwidth limits.
+    pub bandwidth: Bandwidth,
+    /// `[downloads] read_buffer_kib`, in bytes.
+    pub read_buffer: usize,
+    /// `[downloads]` concurrent downloads per client.
+    pub download_slots: DownloadSlots,
+    /// `[downloads] transcode_nsz`: serve NSZs decompressed to NSP.
+    pub transcode_nsz: bool,
+    /// `[uploads]`: where uploads go, and the ones running.
+    pub uploads: Uploads,
+    pub reports: Reporter,
+    /// Credentials; reloaded in place when the auth file changes.
+    pub auth: SharedAuth,
+    #[cfg_attr(not(feature = "admin-ui"), allow(dead_code))]
     pub insecure_admin_cookie: bool,
     pub sessions: SessionStore,
     pub titledb: TitleDb,
+    /// Fallback icons for titles missing from TitleDB.
+    pub artwork: ArtworkProvider,
+    /// Admin-set display names and hidden titles.
+    pub overrides: OverrideStore,
+    /// Per-user tokens for the `/u/{token}/` shop paths.
+    pub shop_tokens: ShopTokenStore,
+    /// Bearer tokens for scripts, per user.
+    pub api_tokens: ApiTokenStore,
+    /// Admin messages shown in the shop.
+    pub announcements: AnnouncementStore,
+    /// Daily library size samples, for the growth forecast.
+    #[cfg(feature = "metrics")]
+    pub growth: GrowthStore,
+    /// Storage budgets checked by imports.
+    pub quotas: Arc<Quotas>,
+    /// Client activity, for idle mode.
+    pub idle: IdleTracker,
     pub data_dir: PathBuf,
+    /// Runtime settings revision, for optimistic concurrency and change events.
+    pub settings: SettingsRevision,
     pub titledb_progress_tx: broadcast::Sender<String>,
+    /// Per-client cooldown for `/api/speedtest`.
+    pub speedtests: SpeedTestLimiter,
+    /// Chunk maps computed for `/api/get_game/{id}/chunks`.
+    pub chunk_maps: ChunkMaps,
+    /// Freshness thresholds for `/health`.
+    pub health: HealthConfig,
+    /// `[server] base_path`, normalized: the prefix a reverse proxy serves the shop under.
+    pub base_path: Option<Arc<str>>,
+    /// `[server] external_url`: the origin clients reach the server at, if fixed.
+    pub external_url: Option<Arc<str>>,
+    /// Put absolute URLs in the index, even without `external_url`.
+    pub absolute_urls: bool,
+    /// Clients exempt from the request rate limit.
+    pub rate_limit: Arc<RateLimitConfig>,
+    /// Consulted before each download when `[hooks] authorize_download_url` is set.
+    pub download_hook: DownloadHook,
+    /// Builds the shop root with the `[shop]` directives.
+    pub shop: Arc<ShopIndex>,
+    /// Encrypts the index Tinfoil gets at `/` when `[shop] encrypt` is set.
+    pub index_encryptor: Option<Arc<IndexEncryptor>>,
 }
@@ -0,0 +1,160 @@
+//! Download tallies: a download response counts the bytes it sends and, once sent or
+//! dropped, adds them to [`DownloadStats`]. It counts as a completed download when it
+//! delivered the end of the file in full, so a chunked or resumed install is counted
+//! once, by its last part. Files sent inside a batch ZIP are tallied the same way, each
+//! completed once all of it has been sent.
+
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_RANGE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use bytes::Bytes;
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use http_body::{Frame, SizeHint};
+
+use crate::stats::DownloadStats;
+
+/// Tally `response`, a download of `relative_path` by `client`, into `stats`.
+pub fn tally_download(
+    response: Response,
+    stats: &DownloadStats,
+    relative_path: PathBuf,
+    client: String,
+) -> Response {
+    let reaches_end = match response.status() {
+        StatusCode::OK => true,
+        StatusCode::PARTIAL_CONTENT => range_reaches_end(response.headers()),
+        _ => return response,
+    };
+    let expected = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let served = Served {
+        stats: stats.clone(),
+        relative_path,
+        client,
+        sent: 0,
+        expected: expected.filter(|_| reaches_end),
+    };
+    response.map(|body| Body::new(TalliedBody { body, served }))
+}
+
+/// Tally `stream`, all `size` bytes of `relative_path` sent to `client` as part of a
+/// larger response.
+pub fn tally_stream(
+    stream: BoxStream<'static, io::Result<Bytes>>,
+    stats: &DownloadStats,
+    relative_path: PathBuf,
+    client: String,
+    size: u64,
+) -> BoxStream<'static, io::Result<Bytes>> {
+    let mut served = Served {
+        stats: stats.clone(),
+        relative_path,
+        client,
+        sent: 0,
+        expected: Some(size),
+    };
+    stream
+        .inspect_ok(move |chunk| {
+            // Capture all of `served`, not just its count, so it is dropped with the stream.
+            let served = &mut served;
+            served.sent += chunk.len() as u64;
+        })
+        .boxed()
+}
+
+/// Whether a single-range `Content-Range: bytes first-last/size` ends at the last byte.
+fn range_reaches_end(headers: &HeaderMap) -> bool {
+    let Some(range) = headers
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes "))
+    else {
+        return false;
+    };
+    let Some((span, size)) = range.split_once('/') else {
+        return false;
+    };
+    let last = span
+        .split_once('-')
+        .and_then(|(_, last)| last.parse::<u64>().ok());
+    matches!((last, size.parse::<u64>()), (Some(last), Ok(size)) if last + 1 == size)
+}
+
+/// Bytes of one file sent so far, added to the stats when dropped.
+struct Served {
+    stats: DownloadStats,
+    relative_path: PathBuf,
+    client: String,
+    sent: u64,
+    /// Length that completes the download; unset when this response can't.
+    expected: Option<u64>,
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        let completed = self.expected == Some(self.sent);
+        self.stats
+            .served(&self.relative_path, &self.client, self.sent, completed);
+    }
+}
+
+/// Response body that tallies the bytes sent when it is done or dropped.
+struct TalliedBody {
+    body: Body,
+    served: Served,
+}
+
+impl http_body::Body for TalliedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.served.sent += data.len() as u64;
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn only_ranges_through_the_last_byte_reach_the_end() {
+        let reaches = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_RANGE, HeaderValue::from_static(value));
+            range_reaches_end(&headers)
+        };
+        assert!(reaches("bytes 500-999/1000"));
+        assert!(!reaches("bytes 0-499/1000"));
+        assert!(!reaches("bytes */1000"));
+        assert!(!range_reaches_end(&HeaderMap::new()));
+    }
+}
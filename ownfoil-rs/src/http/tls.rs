@@ -0,0 +1,276 @@
+//! HTTPS: serving the shop over TLS directly, without a reverse proxy (Tinfoil accepts
+//! `https://` shops).
+//!
+//! The certificate chain and private key are PEM files. They are checked every
+//! [`RELOAD_INTERVAL`] and reloaded when either changes, so a renewed certificate (e.g.
+//! from certbot) is picked up without a restart; open connections keep the old one. A
+//! pair that fails to load is logged and the previous one kept. A second, plain-HTTP
+//! listener can redirect every request to HTTPS (see [`redirect_router`]).
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::http::header::HOST;
+use axum::http::{HeaderMap, Uri};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+/// How often the certificate and key files are checked for changes.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// ALPN protocol of ACME `tls-alpn-01` validation handshakes, which carry no HTTP.
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: rustls::pki_types::pem::Error,
+    },
+    #[error("no certificates in {0}")]
+    NoCertificates(String),
+    #[error("invalid certificate or key: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Certificate and key loaded from PEM files, reloaded when the files change.
+#[derive(Debug)]
+pub struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    /// Modification times of the certificate and key files when they were loaded.
+    stamp: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl ReloadingCert {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, TlsError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let stamp = stamp(cert_path, key_path);
+        let key = Arc::new(read_pair(cert_path, key_path, &provider)?);
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            provider,
+            current: RwLock::new(Loaded { key, stamp }),
+        })
+    }
+
+    /// Load the files again if either changed since the last load. Returns whether a new
+    /// pair is in use; on error the previous one stays.
+    pub fn reload_if_changed(&self) -> Result<bool, TlsError> {
+        let stamp = stamp(&self.cert_path, &self.key_path);
+        if self.read().stamp == stamp {
+            return Ok(false);
+        }
+        let key = Arc::new(read_pair(&self.cert_path, &self.key_path, &self.provider)?);
+        *self.current.write().unwrap_or_else(|p| p.into_inner()) = Loaded { key, stamp };
+        Ok(true)
+    }
+
+    /// Acceptor for TLS connections that always presents the current pair.
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor, TlsError> {
+        acceptor(Arc::<Self>::clone(self), Arc::clone(&self.provider), false)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Loaded> {
+        self.current.read().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.read().key))
+    }
+}
+
+/// Acceptor for HTTP/1.1 over TLS with certificates from `resolver`, also offering
+/// [`ACME_TLS_ALPN`] when `acme` is set.
+pub fn acceptor(
+    resolver: Arc<dyn ResolvesServerCert>,
+    provider: Arc<CryptoProvider>,
+    acme: bool,
+) -> Result<TlsAcceptor, TlsError> {
+    let mut config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    if acme {
+        config.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+    }
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub(super) fn read_pair(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, TlsError> {
+    let read_error = |path: &Path| {
+        let path = path.display().to_string();
+        move |source| TlsError::Read { path, source }
+    };
+    let chain = CertificateDer::pem_file_iter(cert_path)
+        .map_err(read_error(cert_path))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error(cert_path))?;
+    if chain.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.display().to_string()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(read_error(key_path))?;
+    Ok(CertifiedKey::from_der(chain, key, provider)?)
+}
+
+fn stamp(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    (modified(cert_path), modified(key_path))
+}
+
+/// Spawns a background task that reloads `cert` every [`RELOAD_INTERVAL`] if its files
+/// changed.
+pub fn spawn_cert_reloader(cert: Arc<ReloadingCert>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(RELOAD_INTERVAL);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match cert.reload_if_changed() {
+                Ok(true) => info!(path = %cert.cert_path.display(), "TLS certificate reloaded"),
+                Ok(false) => {}
+                Err(err) => warn!(
+                    error = %err,
+                    "TLS certificate reload failed; keeping the previous certificate"
+                ),
+            }
+        }
+    });
+}
+
+/// Router that redirects every request to the same URL over HTTPS on `https_port`.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        redirect_to_https(&headers, &uri, https_port)
+    })
+}
+
+fn redirect_to_https(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<axum::http::uri::Authority>().ok())
+        .map_or_else(
+            || String::from("localhost"),
+            |authority| authority.host().to_string(),
+        );
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Redirect::permanent(&format!("https://{host}{port}{path}")).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use axum::routing::get;
+    use axum::Router;
+    use tempfile::tempdir;
+    use tokio::net::TcpListener;
+
+    use super::{redirect_router, ReloadingCert};
+    use crate::config::ServerConfig;
+    use crate::http::serve;
+
+    fn self_signed(dir: &std::path::Path, name: &str) -> Result<()> {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from(name)])?;
+        std::fs::write(dir.join("cert.pem"), cert.cert.pem())?;
+        std::fs::write(dir.join("key.pem"), cert.key_pair.serialize_pem())?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_https_and_reloads_a_renewed_certificate() -> Result<()> {
+        let dir = tempdir()?;
+        self_signed(dir.path(), "first.test")?;
+        let cert = Arc::new(ReloadingCert::load(
+            &dir.path().join("cert.pem"),
+            &dir.path().join("key.pem"),
+        )?);
+        assert!(!cert.reload_if_changed()?);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(serve(
+            listener,
+            app,
+            ServerConfig::default(),
+            Some(cert.acceptor()?),
+            std::future::pending(),
+        ));
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+        let body = client
+            .get(format!("https://{addr}/health"))
+            .send()
+            .await?
+            .text()
+            .await?;
+        assert_eq!(body, "ok");
+        assert!(reqwest::get(format!("http://{addr}/health")).await.is_err());
+
+        // A broken pair is rejected and the old one kept.
+        std::fs::write(dir.path().join("key.pem"), "not a key")?;
+        assert!(cert.reload_if_changed().is_err());
+        self_signed(dir.path(), "second.test")?;
+        assert!(cert.reload_if_changed()?);
+        let body = client
+            .get(format!("https://{addr}/health"))
+            .send()
+            .await?
+            .text()
+            .await?;
+        assert_eq!(body, "ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plain_http_redirects_to_https() -> Result<()> {
+        let server = axum_test::TestServer::new(redirect_router(8443))?;
+        let response = server
+            .get("/api/shop/sections?limit=5")
+            .add_header("Host", "shop.example.com:8080")
+            .await;
+        assert_eq!(response.status_code(), 308);
+        assert_eq!(
+            response.header("location"),
+            "https://shop.example.com:8443/api/shop/sections?limit=5"
+        );
+
+        let server = axum_test::TestServer::new(redirect_router(443))?;
+        let response = server.get("/").add_header("Host", "shop.example.com").await;
+        assert_eq!(response.header("location"), "https://shop.example.com/");
+        Ok(())
+    }
+}
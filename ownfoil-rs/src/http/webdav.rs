@@ -0,0 +1,149 @@
+//! Read-only WebDAV: the library's folders (merged across roots, like
+//! `/api/directories/folder/`) under `/dav/`, for DBI's network install and desktop file
+//! managers.
+//!
+//! `PROPFIND` answers with the resource and, unless `Depth: 0`, its children; deeper
+//! listings are never sent, so `Depth: infinity` is answered like `Depth: 1`. Requested
+//! properties are ignored and the same small set is always returned. `GET` on a folder
+//! gives an HTML index; files are served like `/download/`.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use percent_encoding::utf8_percent_encode;
+
+use super::directories::folder_parts;
+use super::responses::PATH_SEGMENT_ENCODE_SET;
+use crate::catalog::ContentFile;
+
+/// What a `/dav/` path names.
+#[derive(Debug)]
+pub enum DavResource<'a> {
+    /// A folder, empty-named for the root, and its children.
+    Folder(String, Vec<DavEntry<'a>>),
+    File(&'a ContentFile),
+}
+
+/// A folder's child.
+#[derive(Debug)]
+pub enum DavEntry<'a> {
+    Folder(String),
+    File(&'a ContentFile),
+}
+
+/// The resource at `path` (folders separated by `/`) among the listed `files`.
+pub fn lookup<'a>(path: &str, files: &[(usize, &'a ContentFile)]) -> Option<DavResource<'a>> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut folders = Vec::new();
+    let mut children = Vec::new();
+    for &(_, file) in files {
+        let mut parts = folder_parts(&file.relative_path);
+        parts.push(file.name.clone());
+        if parts.len() < segments.len() || parts.iter().zip(&segments).any(|(a, b)| a != b) {
+            continue;
+        }
+        match parts.len() - segments.len() {
+            0 => return Some(DavResource::File(file)),
+            1 => children.push(file),
+            _ => folders.push(parts.swap_remove(segments.len())),
+        }
+    }
+    if !segments.is_empty() && folders.is_empty() && children.is_empty() {
+        return None;
+    }
+    folders.sort();
+    folders.dedup();
+    children.sort_by_key(|file| file.name.to_lowercase());
+    let mut entries: Vec<_> = folders.into_iter().map(DavEntry::Folder).collect();
+    entries.extend(children.into_iter().map(DavEntry::File));
+    let name = segments
+        .last()
+        .map_or(String::new(), |name| name.to_string());
+    Some(DavResource::Folder(name, entries))
+}
+
+/// `207 Multi-Status` body for `resource` at `href`, with its children unless `shallow`.
+pub fn multistatus(href: &str, resource: &DavResource<'_>, shallow: bool) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<D:multistatus xmlns:D=\"DAV:\">\n");
+    match resource {
+        DavResource::File(file) => push_file(&mut xml, href, file),
+        DavResource::Folder(name, entries) => {
+            push_folder(&mut xml, href, name);
+            if !shallow {
+                for entry in entries {
+                    match entry {
+                        DavEntry::Folder(name) => {
+                            push_folder(&mut xml, &format!("{href}{}/", encode(name)), name)
+                        }
+                        DavEntry::File(file) => {
+                            push_file(&mut xml, &format!("{href}{}", encode(&file.name)), file)
+                        }
+                    }
+                }
+            }
+        }
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+/// HTML index of a folder's `entries`, with links relative to the folder.
+pub fn html_index(href: &str, entries: &[DavEntry<'_>]) -> String {
+    let title = escape(&percent_encoding::percent_decode_str(href).decode_utf8_lossy());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title></head>\
+         <body>\n<h1>{title}</h1>\n<ul>\n<li><a href=\"../\">../</a></li>\n"
+    );
+    for entry in entries {
+        let (link, name) = match entry {
+            DavEntry::Folder(name) => (format!("{}/", encode(name)), format!("{name}/")),
+            DavEntry::File(file) => (encode(&file.name), file.name.clone()),
+        };
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape(&link),
+            escape(&name)
+        ));
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    html
+}
+
+fn push_folder(xml: &mut String, href: &str, name: &str) {
+    xml.push_str(&format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype><D:collection/></D:resourcetype>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape(href),
+        escape(name)
+    ));
+}
+
+fn push_file(xml: &mut String, href: &str, file: &ContentFile) {
+    let modified = file.modified.map_or(String::new(), |secs| {
+        let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs));
+        format!("<D:getlastmodified>{date}</D:getlastmodified>")
+    });
+    xml.push_str(&format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname><D:resourcetype/>\
+         <D:getcontentlength>{}</D:getcontentlength>\
+         <D:getcontenttype>application/octet-stream</D:getcontenttype>{modified}\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape(href),
+        escape(&file.name),
+        file.size
+    ));
+}
+
+/// One percent-encoded path segment.
+pub fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+pub fn escape(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
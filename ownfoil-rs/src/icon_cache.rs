@@ -0,0 +1,332 @@
+//! Icon cache: remote artwork on disk, content-addressed by source URL and bounded in size.
+//!
+//! Each image is stored as `<blake3(url)>.<ext>` under the cache directory, so a changed
+//! fallback URL template never serves stale art under the old name. The file's
+//! modification time is when it was fetched. Use is tracked in memory; once the cache
+//! grows past its byte budget, the least recently used images are deleted. Access order
+//! starts from fetch times after a restart.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::artwork::{extension_for, Artwork};
+
+/// Default budget when `[artwork] cache_max_mb` is unset.
+pub const DEFAULT_MAX_BYTES: u64 = 256 << 20;
+
+#[derive(Debug)]
+struct CacheEntry {
+    extension: &'static str,
+    size: u64,
+    /// Position in access order; higher is more recent.
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    bytes: u64,
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+}
+
+/// Size of the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IconCacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct IconCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Arc<Mutex<CacheIndex>>,
+}
+
+impl IconCache {
+    /// Open the cache in `dir`, indexing images already there. Files not named by a URL
+    /// hash (left by older versions) are removed.
+    pub fn open(dir: PathBuf, max_bytes: u64) -> Self {
+        let mut found = Vec::new();
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                match cache_key(&path) {
+                    Some((key, extension)) if metadata.is_file() => {
+                        found.push((metadata.modified().ok(), key, extension, metadata.len()));
+                    }
+                    _ if metadata.is_file() => {
+                        if let Err(err) = std::fs::remove_file(&path) {
+                            warn!(path = %path.display(), error = %err, "icon cache cleanup failed");
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // Oldest fetch first, so it is the first to go.
+        found.sort_by_key(|(modified, ..)| *modified);
+        let mut index = CacheIndex::default();
+        for (_, key, extension, size) in found {
+            let last_used = index.tick();
+            index.bytes += size;
+            index.entries.insert(
+                key,
+                CacheEntry {
+                    extension,
+                    size,
+                    last_used,
+                },
+            );
+        }
+        debug!(
+            entries = index.entries.len(),
+            bytes = index.bytes,
+            "icon cache loaded"
+        );
+        let cache = Self {
+            dir,
+            max_bytes,
+            index: Arc::new(Mutex::new(index)),
+        };
+        cache.evict();
+        cache
+    }
+
+    /// Cached image fetched from `url`, with `fetched` set to when it was stored.
+    pub async fn get(&self, url: &str) -> Option<Artwork> {
+        let key = url_key(url);
+        let path = {
+            let mut index = self.lock();
+            let last_used = index.tick();
+            let entry = index.entries.get_mut(&key)?;
+            entry.last_used = last_used;
+            self.path(&key, entry.extension)
+        };
+        let read = async {
+            let bytes = tokio::fs::read(&path).await?;
+            let modified = tokio::fs::metadata(&path).await?.modified().ok();
+            io::Result::Ok((bytes, modified))
+        };
+        match read.await {
+            Ok((bytes, modified)) => Some(Artwork {
+                bytes: Bytes::from(bytes),
+                content_type: mime_guess::from_path(&path)
+                    .first_or_octet_stream()
+                    .to_string(),
+                modified,
+                fetched: modified,
+            }),
+            Err(err) => {
+                debug!(path = %path.display(), error = %err, "icon cache entry unreadable");
+                self.lock().remove(&key);
+                None
+            }
+        }
+    }
+
+    /// Store `artwork` fetched from `url`, then evict the least recently used images
+    /// while over budget. Images of unsupported types are not cached.
+    pub async fn insert(&self, url: &str, artwork: &Artwork) {
+        let Some(extension) = extension_for(&artwork.content_type) else {
+            return;
+        };
+        let key = url_key(url);
+        // A refetch may come back with a different format.
+        self.remove(url).await;
+        let path = self.path(&key, extension);
+        let result = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&path, &artwork.bytes).await
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(path = %path.display(), error = %err, "icon cache write failed");
+            return;
+        }
+        {
+            let mut index = self.lock();
+            let last_used = index.tick();
+            let size = artwork.bytes.len() as u64;
+            index.bytes += size;
+            index.entries.insert(
+                key,
+                CacheEntry {
+                    extension,
+                    size,
+                    last_used,
+                },
+            );
+        }
+        self.evict();
+    }
+
+    /// Forget the image fetched from `url`.
+    pub async fn remove(&self, url: &str) {
+        let key = url_key(url);
+        let Some(entry) = self.lock().remove(&key) else {
+            return;
+        };
+        let path = self.path(&key, entry.extension);
+        if let Err(err) = tokio::fs::remove_file(&path).await {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!(path = %path.display(), error = %err, "icon cache delete failed");
+            }
+        }
+    }
+
+    pub fn stats(&self) -> IconCacheStats {
+        let index = self.lock();
+        IconCacheStats {
+            entries: index.entries.len(),
+            bytes: index.bytes,
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    /// Delete every cached image. Returns what was removed.
+    pub async fn purge(&self) -> io::Result<IconCacheStats> {
+        let removed = {
+            let mut index = self.lock();
+            let removed = std::mem::take(&mut *index);
+            index.clock = removed.clock;
+            removed
+        };
+        for (key, entry) in &removed.entries {
+            let path = self.path(key, entry.extension);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(IconCacheStats {
+            entries: removed.entries.len(),
+            bytes: removed.bytes,
+            max_bytes: self.max_bytes,
+        })
+    }
+
+    fn evict(&self) {
+        let mut evicted = Vec::new();
+        {
+            let mut index = self.lock();
+            while index.bytes > self.max_bytes {
+                let Some(key) = index
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone())
+                else {
+                    break;
+                };
+                if let Some(entry) = index.remove(&key) {
+                    evicted.push(self.path(&key, entry.extension));
+                }
+            }
+        }
+        for path in evicted {
+            debug!(path = %path.display(), "icon evicted from cache");
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!(path = %path.display(), error = %err, "icon cache eviction failed");
+            }
+        }
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{key}.{extension}"))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheIndex> {
+        self.index.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+fn url_key(url: &str) -> String {
+    blake3::hash(url.as_bytes()).to_hex().to_string()
+}
+
+/// The key and extension of a cache file name, if it is one.
+fn cache_key(path: &Path) -> Option<(String, &'static str)> {
+    let stem = path.file_stem()?.to_str()?;
+    let extension = match path.extension()?.to_str()? {
+        "png" => "png",
+        "jpg" => "jpg",
+        "webp" => "webp",
+        "svg" => "svg",
+        _ => return None,
+    };
+    (stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| (stem.to_string(), extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use super::IconCache;
+    use crate::artwork::Artwork;
+
+    fn png(len: usize) -> Artwork {
+        Artwork {
+            bytes: Bytes::from(vec![0u8; len]),
+            content_type: String::from("image/png"),
+            modified: Some(SystemTime::now()),
+            fetched: Some(SystemTime::now()),
+        }
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_icons_are_evicted_over_budget() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("0100ABCD12340000.png"), b"legacy")?;
+        let cache = IconCache::open(dir.path().to_path_buf(), 250);
+        assert!(!dir.path().join("0100ABCD12340000.png").exists());
+
+        cache.insert("https://icons/a.png", &png(100)).await;
+        cache.insert("https://icons/b.png", &png(100)).await;
+        assert!(cache.get("https://icons/a.png").await.is_some());
+        cache.insert("https://icons/c.png", &png(100)).await;
+
+        // `b` was used least recently.
+        assert!(cache.get("https://icons/b.png").await.is_none());
+        assert!(cache.get("https://icons/a.png").await.is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (2, 200));
+
+        let reopened = IconCache::open(dir.path().to_path_buf(), 250);
+        assert_eq!(reopened.stats().bytes, 200);
+        let purged = reopened.purge().await?;
+        assert_eq!(purged.entries, 2);
+        assert_eq!(reopened.stats().entries, 0);
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Idle mode, for power-conscious home servers: after `[idle] after` without client
+//! requests, and with no response still streaming, periodic library scans run
+//! `scan_multiplier` times less often and the TitleDB refresh and the hashing and
+//! verification passes pause, so disks can spin down. A pass already running stops at its
+//! next batch. The next request ends idle mode; work that came due meanwhile runs right
+//! away and the normal cadence resumes.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::info;
+
+/// Scan interval stretch while idle when `[idle] scan_multiplier` is unset.
+pub const DEFAULT_SCAN_MULTIPLIER: u32 = 10;
+
+/// How a periodic task runs while idle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdlePace {
+    /// Its interval is this many times longer.
+    Stretch(u32),
+    /// It doesn't run until a client is back.
+    Pause,
+}
+
+/// Tracks client activity; never idle unless built with a quiet period.
+#[derive(Debug, Clone, Default)]
+pub struct IdleTracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    after: Option<Duration>,
+    last_activity: Mutex<Instant>,
+    /// Requests whose responses are still being sent.
+    active: AtomicUsize,
+    /// Whether idle mode has been logged as entered.
+    idle: AtomicBool,
+    wake: Notify,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            after: None,
+            last_activity: Mutex::new(Instant::now()),
+            active: AtomicUsize::new(0),
+            idle: AtomicBool::new(false),
+            wake: Notify::new(),
+        }
+    }
+}
+
+impl IdleTracker {
+    /// Tracker that goes idle after `after` without activity; never when `None`.
+    pub fn new(after: Option<Duration>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                after,
+                ..Inner::default()
+            }),
+        }
+    }
+
+    /// Record client activity, ending idle mode.
+    pub fn touch(&self) {
+        *self.last_activity() = Instant::now();
+        if self.inner.idle.swap(false, Ordering::SeqCst) {
+            info!("client activity; leaving idle mode");
+            self.inner.wake.notify_waiters();
+        }
+    }
+
+    /// Count a request as activity until the returned guard is dropped.
+    pub fn begin(&self) -> Activity {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        self.touch();
+        Activity(self.clone())
+    }
+
+    pub fn is_idle(&self) -> bool {
+        let idle = self
+            .idle_at()
+            .is_some_and(|idle_at| idle_at <= Instant::now());
+        if idle && !self.inner.idle.swap(true, Ordering::SeqCst) {
+            info!("no client activity; entering idle mode");
+        }
+        idle
+    }
+
+    /// When idle mode begins if nothing happens until then.
+    fn idle_at(&self) -> Option<Instant> {
+        let after = self.inner.after?;
+        if self.inner.active.load(Ordering::SeqCst) > 0 {
+            return None;
+        }
+        Some(*self.last_activity() + after)
+    }
+
+    /// Wait until `period` after `since`, or as `pace` says while idle. Returns as soon as
+    /// activity brings a stretched or paused wait back under `period`.
+    pub async fn wait(&self, since: Instant, period: Duration, pace: IdlePace) {
+        loop {
+            let woken = self.inner.wake.notified();
+            let deadline = match (self.is_idle(), pace) {
+                (false, _) => Some(since + period),
+                (true, IdlePace::Stretch(factor)) => Some(since + period.saturating_mul(factor)),
+                (true, IdlePace::Pause) => None,
+            };
+            let Some(deadline) = deadline else {
+                woken.await;
+                continue;
+            };
+            // Idle mode may begin before the deadline and push it back.
+            let wake_at = match self.idle_at() {
+                Some(idle_at) if idle_at > Instant::now() => deadline.min(idle_at),
+                _ => deadline,
+            };
+            tokio::select! {
+                () = tokio::time::sleep_until(wake_at) => {
+                    if wake_at == deadline {
+                        return;
+                    }
+                }
+                () = woken => {}
+            }
+        }
+    }
+
+    fn last_activity(&self) -> std::sync::MutexGuard<'_, Instant> {
+        self.inner
+            .last_activity
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// A request in flight; see [`IdleTracker::begin`].
+#[derive(Debug)]
+pub struct Activity(IdleTracker);
+
+impl Drop for Activity {
+    fn drop(&mut self) {
+        *self.0.last_activity() = Instant::now();
+        self.0.inner.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use tokio::time::Instant;
+
+    use super::{IdlePace, IdleTracker};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_stretches_and_pauses_periodic_work() -> Result<()> {
+        let idle = IdleTracker::new(Some(60 * SECOND));
+        assert!(!idle.is_idle());
+        let start = Instant::now();
+        idle.wait(start, 10 * SECOND, IdlePace::Pause).await;
+        assert_eq!(start.elapsed(), 10 * SECOND);
+
+        // Idle mode begins mid-wait and stretches it.
+        let start = Instant::now();
+        idle.wait(start, 100 * SECOND, IdlePace::Stretch(3)).await;
+        assert_eq!(start.elapsed(), 300 * SECOND);
+        assert!(idle.is_idle());
+
+        // Paused work resumes on the next request.
+        let waiter = {
+            let idle = idle.clone();
+            tokio::spawn(async move {
+                let start = Instant::now();
+                idle.wait(start, 10 * SECOND, IdlePace::Pause).await;
+                start.elapsed()
+            })
+        };
+        tokio::time::sleep(3600 * SECOND).await;
+        assert!(!waiter.is_finished());
+        let request = idle.begin();
+        assert!(!idle.is_idle());
+        assert_eq!(waiter.await?, 3600 * SECOND);
+
+        // A response still streaming keeps the server awake.
+        tokio::time::advance(120 * SECOND).await;
+        assert!(!idle.is_idle());
+        drop(request);
+        tokio::time::advance(61 * SECOND).await;
+        assert!(idle.is_idle());
+
+        assert!(!IdleTracker::default().is_idle());
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+//! URL import: downloads a file from an HTTP(S) URL straight into the library.
+//!
+//! Each import runs as a job in the [`JobManager`]. The body is written to a `.part` file
+//! next to the destination; when the connection drops, the download resumes from the
+//! bytes already on disk with a `Range` request, including on a later import of the same
+//! file. The result is checked against the announced size and, when given, the expected
+//! BLAKE3 hash (as listed in another server's catalog) before it is renamed into place and
+//! its library root is rescanned. A file bigger than what is left of its storage quota is
+//! abandoned as soon as that is known.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::header::RANGE;
+use reqwest::{StatusCode, Url};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::catalog::url_path;
+use crate::hashing::hash_file;
+use crate::jobs::{JobHandle, JobManager};
+use crate::library::LibrarySet;
+use crate::quota::QuotaUsage;
+use crate::remote::format_size;
+use crate::replication::partial_path;
+
+/// Job kind used for URL imports.
+pub const JOB_KIND: &str = "import";
+
+/// Requests per import before giving up on a flaky connection.
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Where to fetch a file from and where it goes in the library.
+#[derive(Debug, Clone)]
+pub struct ImportTarget {
+    pub url: Url,
+    pub root: PathBuf,
+    pub relative_path: PathBuf,
+    /// Expected BLAKE3 hash, lowercase hex.
+    pub blake3: Option<String>,
+    /// Tightest storage quota the file counts against, as of the start of the import.
+    pub quota: Option<QuotaUsage>,
+}
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server answered {0}")]
+    Status(StatusCode),
+    #[error("failed to write {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("download stopped at {got} of {expected} bytes")]
+    Incomplete { expected: u64, got: u64 },
+    #[error("hash mismatch: expected {expected}, got {actual}")]
+    HashMismatch { expected: String, actual: String },
+    #[error("{} would exceed the {quota}", format_size(*.size))]
+    OverQuota { size: u64, quota: QuotaUsage },
+    #[error("import task failed: {0}")]
+    Task(String),
+}
+
+impl ImportError {
+    /// Failures a resumed request may get past.
+    fn is_retryable(&self) -> bool {
+        matches!(self, ImportError::Http(_) | ImportError::Incomplete { .. })
+    }
+}
+
+/// Start an import job and return its job ID.
+pub fn spawn_import(jobs: &JobManager, library: LibrarySet, target: ImportTarget) -> String {
+    let job = jobs.start(JOB_KIND, url_path(&target.relative_path), 0);
+    let id = job.id().to_string();
+    tokio::spawn(async move {
+        match import(&target, &job).await {
+            Ok(size) => {
+                info!(
+                    url = %target.url,
+                    path = %target.relative_path.display(),
+                    size,
+                    "import finished"
+                );
+                if let Err(err) = library.rescan(&target.root).await {
+                    warn!(root = %target.root.display(), error = %err, "rescan after import failed");
+                }
+                job.complete(None);
+            }
+            Err(err) => {
+                warn!(url = %target.url, error = %err, "import failed");
+                job.fail(err.to_string());
+            }
+        }
+    });
+    id
+}
+
+/// Download `target` into its library root, reporting bytes to `job`. Returns the size
+/// of the imported file.
+pub async fn import(target: &ImportTarget, job: &JobHandle) -> Result<u64, ImportError> {
+    let destination = target.root.join(&target.relative_path);
+    let partial = partial_path(&destination);
+    if let Some(parent) = destination.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(io_err(parent))?;
+    }
+
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(30))
+        .read_timeout(Duration::from_secs(60))
+        .user_agent("ownfoil-rs/1.0 (library import)")
+        .build()?;
+    let mut attempt = 1;
+    let size = loop {
+        match download(&client, target, &partial, job).await {
+            Ok(size) => break size,
+            Err(err @ ImportError::OverQuota { .. }) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(err);
+            }
+            Err(err) if err.is_retryable() && attempt < ATTEMPTS => {
+                warn!(url = %target.url, attempt, error = %err, "import interrupted; resuming");
+                attempt += 1;
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    if let Some(expected) = &target.blake3 {
+        let path = partial.clone();
+        let actual = tokio::task::spawn_blocking(move || hash_file(&path))
+            .await
+            .map_err(|err| ImportError::Task(err.to_string()))?
+            .map_err(io_err(&partial))?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            // Resuming would only append to the same bad bytes.
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(ImportError::HashMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    tokio::fs::rename(&partial, &destination)
+        .await
+        .map_err(io_err(&destination))?;
+    Ok(size)
+}
+
+/// One request, continuing `partial` from where it ends when the server supports ranges.
+/// Returns the size of the complete file.
+async fn download(
+    client: &reqwest::Client,
+    target: &ImportTarget,
+    partial: &Path,
+    job: &JobHandle,
+) -> Result<u64, ImportError> {
+    let over_quota = |size: u64| {
+        target
+            .quota
+            .as_ref()
+            .filter(|quota| size > quota.remaining_bytes)
+            .map(|quota| ImportError::OverQuota {
+                size,
+                quota: quota.clone(),
+            })
+    };
+    let offset = tokio::fs::metadata(partial)
+        .await
+        .map(|meta| meta.len())
+        .unwrap_or(0);
+    let mut request = client.get(target.url.clone());
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    let mut response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(ImportError::Status(status));
+    }
+
+    // A plain 200 means the server ignored the range; start over.
+    let start = if status == StatusCode::PARTIAL_CONTENT {
+        offset
+    } else {
+        0
+    };
+    let expected = response.content_length().map(|len| start + len);
+    if let Some(err) = expected.and_then(over_quota) {
+        return Err(err);
+    }
+    job.set_progress(start);
+    job.set_total(expected.unwrap_or(0));
+
+    let mut file = if start > 0 {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(partial)
+            .await
+    } else {
+        tokio::fs::File::create(partial).await
+    }
+    .map_err(io_err(partial))?;
+    let mut written = start;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await.map_err(io_err(partial))?;
+        written += chunk.len() as u64;
+        job.add_progress(chunk.len() as u64);
+        if let Some(err) = over_quota(written) {
+            return Err(err);
+        }
+    }
+    file.sync_all().await.map_err(io_err(partial))?;
+
+    match expected {
+        Some(expected) if written != expected => Err(ImportError::Incomplete {
+            expected,
+            got: written,
+        }),
+        _ => Ok(written),
+    }
+}
+
+fn io_err(path: &Path) -> impl FnOnce(std::io::Error) -> ImportError {
+    let path = path.display().to_string();
+    move |source| ImportError::Io { path, source }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use tempfile::tempdir;
+    use tokio::net::TcpListener;
+
+    use super::{import, ImportError, ImportTarget, JOB_KIND};
+    use crate::hashing::hash_file;
+    use crate::jobs::JobManager;
+    use crate::quota::{QuotaScope, QuotaUsage};
+
+    const PAYLOAD: &[u8] = b"0123456789abcdef";
+
+    /// Serves `PAYLOAD`, honouring `Range: bytes=N-`.
+    async fn serve_payload(headers: HeaderMap) -> impl IntoResponse {
+        let offset = headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("bytes="))
+            .and_then(|value| value.trim_end_matches('-').parse::<usize>().ok());
+        match offset {
+            Some(offset) => (StatusCode::PARTIAL_CONTENT, PAYLOAD[offset..].to_vec()),
+            None => (StatusCode::OK, PAYLOAD.to_vec()),
+        }
+    }
+
+    async fn start_server() -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new().route("/game.nsp", get(serve_payload));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(format!("http://{addr}/game.nsp"))
+    }
+
+    #[tokio::test]
+    async fn resumes_a_partial_download_and_checks_the_hash() -> Result<()> {
+        let url = start_server().await?;
+        let library = tempdir()?;
+        std::fs::create_dir(library.path().join("Imports"))?;
+        std::fs::write(library.path().join("Imports/game.nsp.part"), &PAYLOAD[..6])?;
+        let expected = {
+            let reference = library.path().join("reference");
+            std::fs::write(&reference, PAYLOAD)?;
+            hash_file(&reference)?
+        };
+
+        let jobs = JobManager::new();
+        let job = jobs.start(JOB_KIND, "Imports/game.nsp", 0);
+        let target = ImportTarget {
+            url: url.parse()?,
+            root: library.path().to_path_buf(),
+            relative_path: "Imports/game.nsp".into(),
+            blake3: Some(expected),
+            quota: None,
+        };
+        assert_eq!(import(&target, &job).await?, PAYLOAD.len() as u64);
+        assert_eq!(
+            std::fs::read(library.path().join("Imports/game.nsp"))?,
+            PAYLOAD
+        );
+        assert!(!library.path().join("Imports/game.nsp.part").exists());
+        assert_eq!(jobs.list(Some(JOB_KIND))[0].total_bytes, 16);
+
+        let bad = ImportTarget {
+            relative_path: "Imports/other.nsp".into(),
+            blake3: Some("00".repeat(32)),
+            ..target
+        };
+        let err = import(&bad, &job).await;
+        assert!(matches!(err, Err(ImportError::HashMismatch { .. })));
+        assert!(!library.path().join("Imports/other.nsp").exists());
+
+        let over_quota = ImportTarget {
+            blake3: None,
+            quota: Some(QuotaUsage {
+                scope: QuotaScope::Kind,
+                name: String::from("base"),
+                limit_bytes: 100,
+                used_bytes: 90,
+                remaining_bytes: 10,
+            }),
+            ..bad
+        };
+        let err = import(&over_quota, &job).await;
+        assert!(matches!(err, Err(ImportError::OverQuota { size: 16, .. })));
+        assert!(!library.path().join("Imports/other.nsp.part").exists());
+        Ok(())
+    }
+}
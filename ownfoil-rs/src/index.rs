@@ -0,0 +1,149 @@
+//! Encrypted shop index: the `TINFOIL` container Tinfoil reads in place of plain JSON.
+//!
+//! The JSON index is compressed with zstd and encrypted with a random AES-128 key (ECB,
+//! zero padded); the AES key itself is encrypted with RSA-OAEP (SHA-256) under Tinfoil's
+//! public key, so only Tinfoil clients can open the index. The layout is the magic
+//! `TINFOIL`, a flag byte (`0xFD`: zstd + encrypted), the encrypted key, the compressed
+//! length as a little-endian `u64`, then the ciphertext.
+//!
+//! The public key isn't bundled; `[shop] public_key_file` points at a PEM copy of it.
+
+use std::path::Path;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::rand_core::{OsRng, RngCore};
+use rsa::{Oaep, RsaPublicKey};
+use serde::Serialize;
+use sha2::Sha256;
+use thiserror::Error;
+
+const MAGIC: &[u8; 7] = b"TINFOIL";
+/// zstd compressed (`0x0D`) and encrypted (`0xF0`).
+const FLAGS: u8 = 0xFD;
+const BLOCK: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("failed to read public key {path}: {source}")]
+    ReadKey {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("{path} is not a PEM encoded RSA public key")]
+    InvalidKey { path: String },
+    #[error("failed to serialize shop index: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("failed to compress shop index: {0}")]
+    Compress(std::io::Error),
+    #[error("failed to encrypt shop index key: {0}")]
+    Encrypt(#[from] rsa::Error),
+}
+
+/// Encrypts shop indexes for Tinfoil.
+#[derive(Debug, Clone)]
+pub struct IndexEncryptor {
+    key: RsaPublicKey,
+}
+
+impl IndexEncryptor {
+    pub fn new(key: RsaPublicKey) -> Self {
+        Self { key }
+    }
+
+    /// Load the public key from a PEM file (`BEGIN PUBLIC KEY` or `BEGIN RSA PUBLIC KEY`).
+    pub fn load(path: &Path) -> Result<Self, IndexError> {
+        let pem = std::fs::read_to_string(path).map_err(|source| IndexError::ReadKey {
+            path: path.display().to_string(),
+            source,
+        })?;
+        RsaPublicKey::from_public_key_pem(pem.trim())
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem.trim()))
+            .map(Self::new)
+            .map_err(|_| IndexError::InvalidKey {
+                path: path.display().to_string(),
+            })
+    }
+
+    /// Serialize `index` as JSON and wrap it in an encrypted `TINFOIL` container.
+    pub fn encrypt(&self, index: &impl Serialize) -> Result<Vec<u8>, IndexError> {
+        let json = serde_json::to_vec(index)?;
+        let mut body = zstd::bulk::compress(&json, 0).map_err(IndexError::Compress)?;
+        let compressed_len = body.len() as u64;
+
+        let mut aes_key = [0u8; BLOCK];
+        OsRng.fill_bytes(&mut aes_key);
+        let session_key = self
+            .key
+            .encrypt(&mut OsRng, Oaep::new::<Sha256>(), &aes_key)?;
+
+        // Always pad, by a whole block when already aligned, as Tinfoil's own tools do.
+        body.resize(body.len() + BLOCK - body.len() % BLOCK, 0);
+        let cipher = Aes128::new(GenericArray::from_slice(&aes_key));
+        for block in body.chunks_exact_mut(BLOCK) {
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        }
+
+        let mut out = Vec::with_capacity(MAGIC.len() + 1 + session_key.len() + 8 + body.len());
+        out.extend_from_slice(MAGIC);
+        out.push(FLAGS);
+        out.extend_from_slice(&session_key);
+        out.extend_from_slice(&compressed_len.to_le_bytes());
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockDecrypt, KeyInit};
+    use aes::Aes128;
+    use anyhow::Result;
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    use rsa::rand_core::OsRng;
+    use rsa::{Oaep, RsaPrivateKey};
+    use sha2::Sha256;
+    use tempfile::tempdir;
+
+    use super::IndexEncryptor;
+
+    #[test]
+    fn encrypted_index_opens_with_the_private_key() -> Result<()> {
+        let private = RsaPrivateKey::new(&mut OsRng, 1024)?;
+        let dir = tempdir()?;
+        let pem_path = dir.path().join("public.pem");
+        std::fs::write(
+            &pem_path,
+            private.to_public_key().to_public_key_pem(LineEnding::LF)?,
+        )?;
+        let encryptor = IndexEncryptor::load(&pem_path)?;
+
+        let index =
+            serde_json::json!({ "files": [{ "url": "/api/get_game/1#Game.nsp", "size": 4 }] });
+        let data = encryptor.encrypt(&index)?;
+        assert_eq!(&data[..8], b"TINFOIL\xFD");
+
+        let (session_key, rest) = data[8..].split_at(128);
+        let (len, body) = rest.split_at(8);
+        let len = usize::try_from(u64::from_le_bytes(len.try_into()?))?;
+        assert_eq!(body.len() % 16, 0);
+        assert!(body.len() > len);
+
+        let aes_key = private.decrypt(Oaep::new::<Sha256>(), session_key)?;
+        let cipher = Aes128::new(GenericArray::from_slice(&aes_key));
+        let mut body = body.to_vec();
+        for block in body.chunks_exact_mut(16) {
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+        let json = zstd::bulk::decompress(&body[..len], 1 << 20)?;
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&json)?, index);
+
+        std::fs::write(&pem_path, "not a key")?;
+        assert!(IndexEncryptor::load(&pem_path).is_err());
+        Ok(())
+    }
+}
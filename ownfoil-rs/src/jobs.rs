@@ -0,0 +1,209 @@
+//! Job manager: tracks long-running background operations (e.g. replication, imports).
+//!
+//! Jobs are kept in memory with their status and byte progress so the admin API can
+//! report on them. Only the most recent finished jobs are retained.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+/// Finished jobs beyond this count are pruned, oldest first.
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Snapshot of a job for API responses.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: &'static str,
+    /// What the job operates on (e.g. a title ID).
+    pub target: String,
+    pub status: JobStatus,
+    pub done_bytes: u64,
+    pub total_bytes: u64,
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug)]
+struct JobEntry {
+    info: JobInfo,
+    done_bytes: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JobManager {
+    jobs: Arc<DashMap<String, JobEntry>>,
+}
+
+/// Handle held by the task doing the work; reports progress and the final outcome.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    id: String,
+    done_bytes: Arc<AtomicU64>,
+    manager: JobManager,
+}
+
+impl JobManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a running job and return its handle.
+    pub fn start(
+        &self,
+        kind: &'static str,
+        target: impl Into<String>,
+        total_bytes: u64,
+    ) -> JobHandle {
+        let id = uuid::Uuid::new_v4().to_string();
+        let done_bytes = Arc::new(AtomicU64::new(0));
+        self.jobs.insert(
+            id.clone(),
+            JobEntry {
+                info: JobInfo {
+                    id: id.clone(),
+                    kind,
+                    target: target.into(),
+                    status: JobStatus::Running,
+                    done_bytes: 0,
+                    total_bytes,
+                    started_at: unix_now(),
+                    finished_at: None,
+                    message: None,
+                },
+                done_bytes: Arc::clone(&done_bytes),
+            },
+        );
+        JobHandle {
+            id,
+            done_bytes,
+            manager: self.clone(),
+        }
+    }
+
+    /// All jobs of `kind` (or every job when `None`), newest first.
+    pub fn list(&self, kind: Option<&str>) -> Vec<JobInfo> {
+        let mut jobs = self
+            .jobs
+            .iter()
+            .filter(|entry| kind.map_or(true, |kind| entry.info.kind == kind))
+            .map(|entry| snapshot(&entry))
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.started_at));
+        jobs
+    }
+
+    /// Whether a job of `kind` is currently running for `target`.
+    pub fn is_running(&self, kind: &str, target: &str) -> bool {
+        self.jobs.iter().any(|entry| {
+            entry.info.kind == kind
+                && entry.info.target == target
+                && entry.info.status == JobStatus::Running
+        })
+    }
+
+    fn finish(&self, id: &str, status: JobStatus, message: Option<String>) {
+        if let Some(mut entry) = self.jobs.get_mut(id) {
+            entry.info.status = status;
+            entry.info.finished_at = Some(unix_now());
+            entry.info.message = message;
+        }
+        self.prune();
+    }
+
+    fn prune(&self) {
+        let mut finished = self
+            .jobs
+            .iter()
+            .filter_map(|entry| entry.info.finished_at.map(|at| (at, entry.key().clone())))
+            .collect::<Vec<_>>();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        let excess = finished.len() - MAX_FINISHED_JOBS;
+        for (_, id) in finished.into_iter().take(excess) {
+            self.jobs.remove(&id);
+        }
+    }
+}
+
+impl JobHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn add_progress(&self, bytes: u64) {
+        self.done_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Reset progress, e.g. when a download resumes from bytes already on disk.
+    pub fn set_progress(&self, bytes: u64) {
+        self.done_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Set the expected size once it is known.
+    pub fn set_total(&self, total_bytes: u64) {
+        if let Some(mut entry) = self.manager.jobs.get_mut(&self.id) {
+            entry.info.total_bytes = total_bytes;
+        }
+    }
+
+    pub fn complete(self, message: Option<String>) {
+        self.manager.finish(&self.id, JobStatus::Completed, message);
+    }
+
+    pub fn fail(self, message: String) {
+        self.manager
+            .finish(&self.id, JobStatus::Failed, Some(message));
+    }
+}
+
+fn snapshot(entry: &JobEntry) -> JobInfo {
+    let mut info = entry.info.clone();
+    info.done_bytes = entry.done_bytes.load(Ordering::Relaxed);
+    info
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JobManager, JobStatus};
+
+    #[test]
+    fn jobs_report_progress_and_outcome() {
+        let jobs = JobManager::new();
+        let handle = jobs.start("replicate", "0100ABCD12340000", 10);
+        handle.add_progress(4);
+        assert!(jobs.is_running("replicate", "0100ABCD12340000"));
+        assert_eq!(jobs.list(Some("replicate"))[0].done_bytes, 4);
+
+        handle.fail(String::from("disk full"));
+        let job = &jobs.list(Some("replicate"))[0];
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.message.as_deref(), Some("disk full"));
+        assert!(!jobs.is_running("replicate", "0100ABCD12340000"));
+        assert_eq!(jobs.list(Some("other")).len(), 0);
+        assert_eq!(jobs.list(None).len(), 1);
+    }
+}
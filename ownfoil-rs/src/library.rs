@@ -0,0 +1,917 @@
+//! Library set: per-root scan results merged into the shared catalog.
+//!
+//! Each library root is scanned independently (on its own interval or on filesystem
+//! events) and its latest result is kept in a slot. Every update rebuilds the catalog
+//! from all slots in priority order, so a slow root never blocks a fast one.
+//!
+//! When a root (or part of it) is temporarily unreadable, its previously known files
+//! stay in the catalog marked `stale` instead of disappearing.
+//!
+//! Empty or truncated files (see [`is_truncated`]), and with `[verify] exclude_bad` files
+//! that failed verification, are kept out of the catalog and listed as problems instead,
+//! so clients never download them.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex, RwLock};
+
+use crate::blocklist::{Blocklist, BlocklistStore};
+use crate::catalog::{derive_base_title_id, Catalog, ContentFile};
+use crate::changes::{ChangeLog, Delta};
+use crate::config::ScanConfig;
+use crate::demo;
+use crate::hashing::{FileDigests, HashCache};
+use crate::idle::IdleTracker;
+use crate::metadata_cache::MetadataCache;
+use crate::scanner::{is_truncated, scan_file, scan_library, ScanError};
+use crate::verify::Verifier;
+
+/// Files hashed or verified between idle checks (and cache saves) in a pass.
+const PASS_BATCH: usize = 16;
+
+/// How a rescan changed the catalog.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RescanSummary {
+    /// Files in the catalog after the rescan.
+    pub files: usize,
+    pub added: usize,
+    pub removed: usize,
+    /// Files still present whose size or modification time changed.
+    pub changed: usize,
+}
+
+/// How a single-title refresh changed the catalog.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TitleRefresh {
+    /// Files of the title re-read from disk.
+    pub refreshed: usize,
+    /// Files of the title that no longer exist.
+    pub removed: usize,
+}
+
+/// What a consistency check found, and fixed when `applied` is set.
+///
+/// Applying drops missing files from the catalog, re-reads files whose size changed, and
+/// removes hash cache rows that no catalog file can use.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct FsckReport {
+    pub applied: bool,
+    /// Catalog files that no longer exist.
+    pub missing: Vec<PathBuf>,
+    pub size_mismatches: Vec<SizeMismatch>,
+    /// Catalog files that couldn't be checked; left as they are.
+    pub unreadable: Vec<PathBuf>,
+    /// Hash cache rows for paths the catalog doesn't hold.
+    pub orphaned_hashes: usize,
+    /// Hash cache rows computed for a different file size.
+    pub stale_hashes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeMismatch {
+    pub relative_path: PathBuf,
+    pub catalog: u64,
+    pub disk: u64,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.size_mismatches.is_empty()
+            && self.orphaned_hashes == 0
+            && self.stale_hashes == 0
+    }
+}
+
+/// How long the latest scan of a root took.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanTiming {
+    pub root: PathBuf,
+    /// Unix seconds when the scan finished.
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    /// The scan failed and the root's previous files were kept as stale.
+    pub failed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LibrarySet {
+    catalog: Arc<RwLock<Catalog>>,
+    /// Roots in priority order (highest first).
+    roots: Arc<[PathBuf]>,
+    slots: Arc<Mutex<HashMap<PathBuf, Vec<ContentFile>>>>,
+    /// Files left out of the catalog as empty or truncated.
+    problems: Arc<RwLock<Vec<ContentFile>>>,
+    hashes: Option<HashCache>,
+    metadata: Option<MetadataCache>,
+    verifier: Option<Verifier>,
+    blocklist: Option<BlocklistStore>,
+    changes: Arc<RwLock<ChangeLog>>,
+    /// Announces the generation of each catalog build that changed something.
+    rebuilds: broadcast::Sender<u64>,
+    scan_timings: Arc<RwLock<HashMap<PathBuf, ScanTiming>>>,
+    scan: ScanConfig,
+    /// Hashing and verification passes stop while the server is idle.
+    idle: IdleTracker,
+    /// `--demo`: the files are made up, so scans and refreshes leave them as they are.
+    demo: bool,
+}
+
+impl LibrarySet {
+    /// Create an empty set for `roots`, which must already be in priority order.
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self::with_catalog(roots, Catalog::from_files(Vec::new()))
+    }
+
+    /// Create a set whose catalog starts as `catalog` until the first rescan.
+    pub fn with_catalog(roots: Vec<PathBuf>, catalog: Catalog) -> Self {
+        Self {
+            catalog: Arc::new(RwLock::new(catalog)),
+            roots: roots.into(),
+            slots: Arc::new(Mutex::new(HashMap::new())),
+            problems: Arc::new(RwLock::new(Vec::new())),
+            hashes: None,
+            metadata: None,
+            verifier: None,
+            blocklist: None,
+            changes: Arc::new(RwLock::new(ChangeLog::default())),
+            rebuilds: broadcast::channel(16).0,
+            scan_timings: Arc::new(RwLock::new(HashMap::new())),
+            scan: ScanConfig::default(),
+            idle: IdleTracker::default(),
+            demo: false,
+        }
+    }
+
+    /// A set serving the made-up `files` of `--demo` from [`demo::ROOT`] instead of
+    /// scanning anything.
+    pub fn demo(files: Vec<ContentFile>) -> Self {
+        let root = PathBuf::from(demo::ROOT);
+        Self {
+            slots: Arc::new(Mutex::new(HashMap::from([(root.clone(), files)]))),
+            demo: true,
+            ..Self::new(vec![root])
+        }
+    }
+
+    pub fn is_demo(&self) -> bool {
+        self.demo
+    }
+
+    /// Use `scan` for worker count and I/O throttling on every rescan.
+    pub fn with_scan_config(mut self, scan: ScanConfig) -> Self {
+        self.scan = scan;
+        self
+    }
+
+    /// Stop hashing and verification passes between batches while `idle` is idle.
+    pub fn with_idle(mut self, idle: IdleTracker) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Annotate catalog entries with content hashes from `cache`.
+    pub fn with_hashes(mut self, cache: HashCache) -> Self {
+        self.hashes = Some(cache);
+        self
+    }
+
+    /// Reuse parsed metadata of unchanged files from `cache` on every rescan.
+    pub fn with_metadata_cache(mut self, cache: MetadataCache) -> Self {
+        self.metadata = Some(cache);
+        self
+    }
+
+    /// Withhold titles on `store`'s blocklist from the catalog.
+    pub fn with_blocklist(mut self, store: BlocklistStore) -> Self {
+        self.blocklist = Some(store);
+        self
+    }
+
+    pub fn blocklist(&self) -> Option<&BlocklistStore> {
+        self.blocklist.as_ref()
+    }
+
+    /// Rebuild the catalog from the last scan after the blocklist changed. Returns the
+    /// number of files in the catalog.
+    pub async fn apply_blocklist(&self) -> usize {
+        self.rebuild().await
+    }
+
+    /// Cursor for the catalog build with `generation`, for `/api/catalog/changes`.
+    pub async fn cursor(&self, generation: u64) -> String {
+        self.changes.read().await.cursor(generation)
+    }
+
+    /// Files added and removed since `cursor`, or `None` when the client must refetch the
+    /// full catalog.
+    pub async fn changes_since(&self, cursor: &str) -> Option<Delta> {
+        self.changes.read().await.since(cursor)
+    }
+
+    /// Generations of catalog builds that changed something, as they happen.
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.rebuilds.subscribe()
+    }
+
+    pub fn scan_config(&self) -> ScanConfig {
+        self.scan.clone()
+    }
+
+    /// Files quarantined as empty or truncated by the last rebuild, in catalog order.
+    pub async fn problems(&self) -> Vec<ContentFile> {
+        self.problems.read().await.clone()
+    }
+
+    pub fn hashing_enabled(&self) -> bool {
+        self.hashes.is_some()
+    }
+
+    /// Digests of `file`, once the hashing pass has covered it.
+    pub async fn digests(&self, file: &ContentFile) -> Option<FileDigests> {
+        let path = file.root.join(&file.relative_path);
+        self.hashes
+            .as_ref()?
+            .digests(&path, file.size, file.modified)
+            .await
+    }
+
+    /// Annotate catalog entries with integrity checks from `verifier`.
+    pub fn with_verifier(mut self, verifier: Verifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    pub fn verifier(&self) -> Option<&Verifier> {
+        self.verifier.as_ref()
+    }
+
+    /// Verify every file without a cached result, then re-annotate the catalog. Covers
+    /// files already set aside as problems so bad dumps stay flagged. Stops early when the
+    /// server goes idle. Returns the number of newly verified files (0 when verification
+    /// is disabled).
+    pub async fn verify_pass(&self) -> usize {
+        let Some(verifier) = &self.verifier else {
+            return 0;
+        };
+        let mut files = self.catalog.read().await.files().to_vec();
+        files.extend(self.problems().await);
+        let targets = files
+            .iter()
+            .filter(|file| file.verification.is_none() && !file.stale && file.size > 0)
+            .map(|file| {
+                let path = file.root.join(&file.relative_path);
+                (path, file.size, file.modified)
+            })
+            .collect::<Vec<_>>();
+        let mut verified = 0;
+        for batch in targets.chunks(PASS_BATCH) {
+            if self.idle.is_idle() {
+                break;
+            }
+            verified += verifier.verify_missing(batch.to_vec()).await;
+        }
+        if verified > 0 {
+            self.rebuild().await;
+        }
+        verified
+    }
+
+    /// Hash every catalog file not yet in the hash cache, then re-annotate the catalog.
+    /// Stops early when the server goes idle. Returns the number of newly hashed files (0
+    /// when hashing is disabled).
+    pub async fn hash_pass(&self) -> usize {
+        let Some(hashes) = &self.hashes else {
+            return 0;
+        };
+        let targets = self
+            .catalog
+            .read()
+            .await
+            .files()
+            .iter()
+            .filter(|file| file.hash.is_none() && !file.stale)
+            .map(|file| {
+                let path = file.root.join(&file.relative_path);
+                (path, file.size, file.modified)
+            })
+            .collect::<Vec<_>>();
+        let mut hashed = 0;
+        for batch in targets.chunks(PASS_BATCH) {
+            if self.idle.is_idle() {
+                break;
+            }
+            hashed += hashes.hash_missing(batch.to_vec()).await;
+        }
+        if hashed > 0 {
+            self.rebuild().await;
+        }
+        hashed
+    }
+
+    pub fn catalog(&self) -> Arc<RwLock<Catalog>> {
+        Arc::clone(&self.catalog)
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// The configured root containing `path`, if any. Roots are never nested.
+    pub fn root_for(&self, path: &Path) -> Option<&PathBuf> {
+        self.roots.iter().find(|root| path.starts_with(root))
+    }
+
+    /// Latest scan timing of each root that has been scanned, in priority order.
+    pub async fn scan_timings(&self) -> Vec<ScanTiming> {
+        let timings = self.scan_timings.read().await;
+        self.roots
+            .iter()
+            .filter_map(|root| timings.get(root).cloned())
+            .collect()
+    }
+
+    /// Rescan a single root and merge it into the catalog. Returns the number of files
+    /// found under that root. On error the root's previous files are kept as stale.
+    pub async fn rescan(&self, root: &Path) -> Result<usize, ScanError> {
+        let result = self.scan_into_slot(root).await;
+        self.rebuild().await;
+        result
+    }
+
+    /// Rescan every root in priority order. Returns the total number of indexed files.
+    pub async fn rescan_all(&self) -> Result<usize, ScanError> {
+        for root in self.roots.iter() {
+            if let Err(err) = self.scan_into_slot(root).await {
+                self.rebuild().await;
+                return Err(err);
+            }
+        }
+        Ok(self.rebuild().await)
+    }
+
+    /// Rescan every root and report how many files were added, removed, or changed.
+    pub async fn rescan_all_with_summary(&self) -> Result<RescanSummary, ScanError> {
+        let before = self.snapshot().await;
+        let files = self.rescan_all().await?;
+        let after = self.snapshot().await;
+
+        let mut summary = RescanSummary {
+            files,
+            ..RescanSummary::default()
+        };
+        for (path, fingerprint) in &after {
+            match before.get(path) {
+                None => summary.added += 1,
+                Some(previous) if previous != fingerprint => summary.changed += 1,
+                Some(_) => {}
+            }
+        }
+        summary.removed = before
+            .keys()
+            .filter(|path| !after.contains_key(*path))
+            .count();
+        Ok(summary)
+    }
+
+    /// Re-read every file (base, updates, DLC) of `base_title_id` in place, without
+    /// walking the library roots.
+    pub async fn refresh_title(&self, base_title_id: &str) -> Result<TitleRefresh, ScanError> {
+        let mut refresh = TitleRefresh::default();
+        if self.demo {
+            return Ok(refresh);
+        }
+        let mut failure = None;
+        {
+            let mut slots = self.slots.lock().await;
+            for (root, files) in slots.iter_mut() {
+                let mut kept = Vec::with_capacity(files.len());
+                for file in files.drain(..) {
+                    if derive_base_title_id(file.kind, file.title_id.as_deref()).as_deref()
+                        != Some(base_title_id)
+                    {
+                        kept.push(file);
+                        continue;
+                    }
+                    match scan_file(root, &file.relative_path).await {
+                        Ok(Some(fresh)) => {
+                            kept.push(fresh);
+                            refresh.refreshed += 1;
+                        }
+                        Ok(None) => refresh.removed += 1,
+                        // Keep the unreadable file as is; report the first error.
+                        Err(err) => {
+                            kept.push(file);
+                            failure.get_or_insert(err);
+                        }
+                    }
+                }
+                *files = kept;
+            }
+        }
+        self.rebuild().await;
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(refresh),
+        }
+    }
+
+    /// Cross-check the catalog and hash cache against the filesystem. With `apply`, fix
+    /// what was found. Files under a root that can't be read right now, and files
+    /// already marked stale, are left for the next rescan.
+    pub async fn fsck(&self, apply: bool) -> std::io::Result<FsckReport> {
+        let mut report = FsckReport {
+            applied: apply,
+            ..FsckReport::default()
+        };
+        // Size and modification time of every file that exists, as it is on disk.
+        let mut on_disk = HashMap::new();
+        {
+            let mut slots = self.slots.lock().await;
+            for (root, files) in slots.iter_mut() {
+                let readable = tokio::fs::metadata(root)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_dir());
+                let mut kept = Vec::with_capacity(files.len());
+                for file in files.drain(..) {
+                    let path = root.join(&file.relative_path);
+                    if !readable || file.stale {
+                        on_disk.insert(path, (file.size, file.modified));
+                        kept.push(file);
+                        continue;
+                    }
+                    match scan_file(root, &file.relative_path).await {
+                        Ok(None) => {
+                            report.missing.push(file.relative_path.clone());
+                            if !apply {
+                                kept.push(file);
+                            }
+                        }
+                        Ok(Some(fresh)) => {
+                            on_disk.insert(path, (fresh.size, fresh.modified));
+                            if fresh.size == file.size {
+                                kept.push(file);
+                                continue;
+                            }
+                            report.size_mismatches.push(SizeMismatch {
+                                relative_path: file.relative_path.clone(),
+                                catalog: file.size,
+                                disk: fresh.size,
+                            });
+                            kept.push(if apply { fresh } else { file });
+                        }
+                        Err(_) => {
+                            report.unreadable.push(file.relative_path.clone());
+                            on_disk.insert(path, (file.size, file.modified));
+                            kept.push(file);
+                        }
+                    }
+                }
+                *files = kept;
+            }
+        }
+
+        if let Some(hashes) = &self.hashes {
+            let mut unusable = Vec::new();
+            for (path, size, modified) in hashes.rows().await {
+                match on_disk.get(&path) {
+                    None => report.orphaned_hashes += 1,
+                    Some(current) if *current != (size, modified) => report.stale_hashes += 1,
+                    Some(_) => continue,
+                }
+                unusable.push(path);
+            }
+            if apply {
+                hashes.forget(&unusable).await?;
+            }
+        }
+        if apply {
+            self.rebuild().await;
+        }
+        Ok(report)
+    }
+
+    /// Size and mtime of every catalog file, keyed by absolute path.
+    async fn snapshot(&self) -> HashMap<PathBuf, (u64, Option<u64>)> {
+        self.catalog
+            .read()
+            .await
+            .files()
+            .iter()
+            .map(|file| {
+                (
+                    file.root.join(&file.relative_path),
+                    (file.size, file.modified),
+                )
+            })
+            .collect()
+    }
+
+    /// Scan `root` and store the result in its slot, carrying over previous files
+    /// under unreadable subtrees (or the whole root on error) as stale.
+    async fn scan_into_slot(&self, root: &Path) -> Result<usize, ScanError> {
+        if self.demo {
+            return Ok(self.slots.lock().await.get(root).map_or(0, Vec::len));
+        }
+        let started = Instant::now();
+        let outcome = scan_library(root, self.scan.clone(), self.metadata.clone()).await;
+        let timing = ScanTiming {
+            root: root.to_path_buf(),
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            failed: outcome.is_err(),
+        };
+        self.scan_timings
+            .write()
+            .await
+            .insert(root.to_path_buf(), timing);
+        let mut slots = self.slots.lock().await;
+        let previous = slots.remove(root).unwrap_or_default();
+        match outcome {
+            Ok(outcome) => {
+                let mut files = outcome.files;
+                let count = files.len();
+                let carried = previous
+                    .into_iter()
+                    .filter(|file| {
+                        outcome
+                            .unavailable
+                            .iter()
+                            .any(|subtree| file.relative_path.starts_with(subtree))
+                            && !files
+                                .iter()
+                                .any(|fresh| fresh.relative_path == file.relative_path)
+                    })
+                    .map(mark_stale)
+                    .collect::<Vec<_>>();
+                files.extend(carried);
+                slots.insert(root.to_path_buf(), files);
+                Ok(count)
+            }
+            Err(err) => {
+                slots.insert(
+                    root.to_path_buf(),
+                    previous.into_iter().map(mark_stale).collect(),
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// Merge all slots into a fresh catalog, setting truncated files aside and withholding
+    /// blocked titles. Returns the number of files in the catalog.
+    async fn rebuild(&self) -> usize {
+        let slots = self.slots.lock().await;
+        let mut files = self
+            .roots
+            .iter()
+            .filter_map(|root| slots.get(root))
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        for file in &mut files {
+            let path = file.root.join(&file.relative_path);
+            if let Some(hashes) = &self.hashes {
+                file.hash = hashes.get(&path, file.size, file.modified).await;
+            }
+            if let Some(verifier) = &self.verifier {
+                file.verification = verifier.get(&path, file.size, file.modified).await;
+            }
+        }
+        let exclude_bad = self
+            .verifier
+            .as_ref()
+            .is_some_and(|verifier| verifier.exclude_bad);
+        let (problems, files): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| {
+            let failed = file.verification.as_ref().is_some_and(|v| v.is_bad());
+            is_truncated(file.size, &self.scan) || (exclude_bad && failed)
+        });
+        let blocklist = match &self.blocklist {
+            Some(store) => store.snapshot().await,
+            None => Blocklist::default(),
+        };
+        *self.problems.write().await = problems
+            .into_iter()
+            .filter(|file| !blocklist.blocks(file))
+            .collect();
+        let catalog = Catalog::with_blocklist(files, blocklist);
+        let count = catalog.files().len();
+        let mut current = self.catalog.write().await;
+        let generation = self
+            .changes
+            .write()
+            .await
+            .record(current.files(), catalog.files());
+        let changed = generation != current.generation();
+        *current = catalog.with_generation(generation);
+        if changed {
+            let _ = self.rebuilds.send(generation);
+        }
+        count
+    }
+}
+
+fn mark_stale(mut file: ContentFile) -> ContentFile {
+    file.stale = true;
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tokio::fs;
+
+    use super::{FsckReport, LibrarySet, RescanSummary, SizeMismatch, TitleRefresh};
+    use crate::catalog::ContentKind;
+    use crate::config::ScanConfig;
+    use crate::hashing::HashCache;
+    use crate::verify::Verifier;
+
+    #[tokio::test]
+    async fn rescan_all_merges_roots_in_priority_order() -> Result<()> {
+        let fast = tempdir()?;
+        let slow = tempdir()?;
+        fs::write(fast.path().join("game.nsp"), b"fast").await?;
+        fs::write(slow.path().join("game.nsp"), b"slow").await?;
+
+        let set = LibrarySet::new(vec![fast.path().to_path_buf(), slow.path().to_path_buf()]);
+        assert_eq!(set.rescan_all().await?, 2);
+
+        let catalog = set.catalog();
+        let catalog = catalog.read().await;
+        let file = catalog.find_by_relative_path(Path::new("game.nsp"));
+        assert_eq!(file.map(|f| f.root.as_path()), Some(fast.path()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rescan_all_merges_roots_and_tracks_origin() -> Result<()> {
+        let first = tempdir()?;
+        let second = tempdir()?;
+        fs::write(first.path().join("game_[0100ABCD12340000].nsp"), b"a").await?;
+        fs::write(second.path().join("update_[0100ABCD12340800].nsp"), b"b").await?;
+
+        let set = LibrarySet::new(vec![
+            first.path().to_path_buf(),
+            second.path().to_path_buf(),
+        ]);
+        assert_eq!(set.rescan_all().await?, 2);
+        let catalog = set.catalog();
+        let catalog = catalog.read().await;
+        let files = catalog.files();
+        assert_eq!(files[0].root, first.path());
+        assert_eq!(files[1].root, second.path());
+        assert_eq!(files[1].kind, ContentKind::Update);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rescan_summary_counts_added_removed_and_changed() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("keep.nsp"), b"keep").await?;
+        fs::write(dir.path().join("gone.nsp"), b"gone").await?;
+        fs::write(dir.path().join("grow.nsp"), b"grow").await?;
+
+        let set = LibrarySet::new(vec![dir.path().to_path_buf()]);
+        set.rescan_all().await?;
+        fs::remove_file(dir.path().join("gone.nsp")).await?;
+        fs::write(dir.path().join("grow.nsp"), b"grown bigger").await?;
+        fs::write(dir.path().join("new.nsp"), b"new").await?;
+
+        let summary = set.rescan_all_with_summary().await?;
+        assert_eq!(
+            summary,
+            RescanSummary {
+                files: 3,
+                added: 1,
+                removed: 1,
+                changed: 1,
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_title_rereads_only_that_title() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("Game [0100ABCD12340000][v0].nsp"), b"base").await?;
+        fs::write(
+            dir.path().join("Game [0100ABCD12340800][v65536].nsp"),
+            b"update",
+        )
+        .await?;
+        fs::write(
+            dir.path().join("Other [0100FFFF12340000][v0].nsp"),
+            b"other",
+        )
+        .await?;
+
+        let set = LibrarySet::new(vec![dir.path().to_path_buf()]);
+        set.rescan_all().await?;
+        fs::remove_file(dir.path().join("Game [0100ABCD12340800][v65536].nsp")).await?;
+        fs::write(
+            dir.path().join("Game [0100ABCD12340000][v0].nsp"),
+            b"base, fixed",
+        )
+        .await?;
+        fs::remove_file(dir.path().join("Other [0100FFFF12340000][v0].nsp")).await?;
+
+        let refresh = set.refresh_title("0100ABCD12340000").await?;
+        assert_eq!(
+            refresh,
+            TitleRefresh {
+                refreshed: 1,
+                removed: 1,
+            }
+        );
+        let catalog = set.catalog();
+        let catalog = catalog.read().await;
+        // Other titles are untouched until the next full rescan.
+        assert_eq!(catalog.files().len(), 2);
+        let base = catalog.find_by_relative_path(Path::new("Game [0100ABCD12340000][v0].nsp"));
+        assert_eq!(base.map(|file| file.size), Some(11));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rescan_single_root_keeps_other_roots() -> Result<()> {
+        let first = tempdir()?;
+        let second = tempdir()?;
+        fs::write(first.path().join("a.nsp"), b"a").await?;
+        fs::write(second.path().join("b.nsp"), b"b").await?;
+
+        let set = LibrarySet::new(vec![
+            first.path().to_path_buf(),
+            second.path().to_path_buf(),
+        ]);
+        set.rescan_all().await?;
+        fs::write(second.path().join("c.nsp"), b"c").await?;
+
+        assert_eq!(set.rescan(second.path()).await?, 2);
+        assert_eq!(set.catalog().read().await.files().len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreadable_root_keeps_previous_files_as_stale() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().join("mount");
+        fs::create_dir(&root).await?;
+        fs::write(root.join("a.nsp"), b"a").await?;
+
+        let set = LibrarySet::new(vec![root.clone()]);
+        set.rescan_all().await?;
+        fs::remove_dir_all(&root).await?;
+
+        assert!(set.rescan(&root).await.is_err());
+        let catalog = set.catalog();
+        let catalog = catalog.read().await;
+        assert_eq!(catalog.files().len(), 1);
+        assert!(catalog.files()[0].stale);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hash_pass_annotates_catalog_entries() -> Result<()> {
+        let dir = tempdir()?;
+        let data = tempdir()?;
+        fs::write(dir.path().join("a.nsp"), b"same").await?;
+        fs::write(dir.path().join("b.nsp"), b"same").await?;
+
+        let set = LibrarySet::new(vec![dir.path().to_path_buf()])
+            .with_hashes(HashCache::load(data.path()));
+        set.rescan_all().await?;
+        assert_eq!(set.hash_pass().await, 2);
+
+        let catalog = set.catalog();
+        let catalog = catalog.read().await;
+        assert!(catalog.files().iter().all(|file| file.hash.is_some()));
+        assert_eq!(catalog.duplicates_by_hash(), vec![vec![1, 2]]);
+        Ok(())
+    }
+
+    #[test]
+    fn root_for_finds_the_containing_root() {
+        let set = LibrarySet::new(vec![PathBuf::from("/nas"), PathBuf::from("/usb")]);
+        assert_eq!(
+            set.root_for(Path::new("/usb/updates/x.nsp")),
+            Some(&PathBuf::from("/usb"))
+        );
+        assert_eq!(
+            set.root_for(Path::new("/nas/games/x.nsp")),
+            Some(&PathBuf::from("/nas"))
+        );
+        assert_eq!(set.root_for(Path::new("/nas-backup/x.nsp")), None);
+        assert_eq!(set.root_for(Path::new("/other/x.nsp")), None);
+    }
+
+    #[tokio::test]
+    async fn fsck_reports_then_repairs_drift() -> Result<()> {
+        let dir = tempdir()?;
+        let data = tempdir()?;
+        fs::write(dir.path().join("keep.nsp"), b"keep").await?;
+        fs::write(dir.path().join("gone.nsp"), b"gone").await?;
+        fs::write(dir.path().join("grow.nsp"), b"grow").await?;
+        let set = LibrarySet::new(vec![dir.path().to_path_buf()])
+            .with_hashes(HashCache::load(data.path()));
+        set.rescan_all().await?;
+        assert_eq!(set.hash_pass().await, 3);
+
+        fs::remove_file(dir.path().join("gone.nsp")).await?;
+        fs::write(dir.path().join("grow.nsp"), b"grown").await?;
+        let expected = FsckReport {
+            applied: false,
+            missing: vec![PathBuf::from("gone.nsp")],
+            size_mismatches: vec![SizeMismatch {
+                relative_path: PathBuf::from("grow.nsp"),
+                catalog: 4,
+                disk: 5,
+            }],
+            unreadable: Vec::new(),
+            orphaned_hashes: 1,
+            stale_hashes: 1,
+        };
+        assert_eq!(set.fsck(false).await?, expected);
+        assert_eq!(set.catalog().read().await.files().len(), 3);
+
+        let applied = set.fsck(true).await?;
+        assert_eq!(
+            applied,
+            FsckReport {
+                applied: true,
+                ..expected
+            }
+        );
+        let catalog = set.catalog();
+        let catalog = catalog.read().await;
+        assert_eq!(catalog.files().len(), 2);
+        assert_eq!(
+            catalog
+                .find_by_relative_path(Path::new("grow.nsp"))
+                .map(|file| file.size),
+            Some(5)
+        );
+        drop(catalog);
+        assert!(set.fsck(false).await?.is_clean());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn truncated_files_are_kept_out_of_the_catalog() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("good.nsp"), b"complete").await?;
+        fs::write(dir.path().join("empty.nsp"), b"").await?;
+        fs::write(dir.path().join("short.nsp"), b"abc").await?;
+        let set = LibrarySet::new(vec![dir.path().to_path_buf()]).with_scan_config(ScanConfig {
+            min_file_size: 4,
+            ..ScanConfig::default()
+        });
+
+        assert_eq!(set.rescan_all().await?, 1);
+        let problems = set
+            .problems()
+            .await
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<_>>();
+        assert_eq!(problems, vec!["empty.nsp", "short.nsp"]);
+
+        fs::write(dir.path().join("short.nsp"), b"abcdef").await?;
+        assert_eq!(set.rescan_all().await?, 2);
+        assert_eq!(set.problems().await.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bad_dumps_can_be_excluded_after_verification() -> Result<()> {
+        let dir = tempdir()?;
+        let data = tempdir()?;
+        fs::write(dir.path().join("garbage.nsp"), b"not a container").await?;
+        let set = LibrarySet::new(vec![dir.path().to_path_buf()]).with_verifier(Verifier::load(
+            data.path(),
+            None,
+            true,
+        ));
+        assert_eq!(set.rescan_all().await?, 1);
+
+        assert_eq!(set.verify_pass().await, 1);
+        assert!(set.catalog().read().await.files().is_empty());
+        let problems = set.problems().await;
+        assert!(problems[0]
+            .verification
+            .as_ref()
+            .is_some_and(|result| result.is_bad()));
+        assert_eq!(set.verify_pass().await, 0);
+        Ok(())
+    }
+}
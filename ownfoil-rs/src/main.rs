@@ -8,6 +8,7 @@
 //! ## Architecture
 //!
 //! - **Catalog**: In-memory index of `.nsp`, `.xci`, `.nsz`, `.xcz` files, refreshed on interval
+//!   and/or on filesystem events
 //! - **TitleDB**: Optional game metadata (icons, banners) from [blawar/titledb](https://github.com/blawar/titledb)
 //! - **Auth**: TOML-based credentials with constant-time password comparison
 //! - **HTTP**: Axum router with rate limiting, request IDs, and graceful shutdown
@@ -15,39 +16,147 @@
 #![forbid(unsafe_code)]
 #![deny(clippy::unwrap_used, clippy::expect_used)]
 
+mod annotations;
+mod announcements;
+mod api_tokens;
+mod archive;
+mod artwork;
 mod auth;
+mod bandwidth;
+mod benchmark;
+mod blocklist;
 mod catalog;
+mod changes;
+mod chunks;
 mod config;
+mod container;
+mod demo;
+mod export;
+#[cfg(feature = "metrics")]
+mod growth;
+mod hashing;
+mod hooks;
 mod http;
+mod icon_cache;
+mod idle;
+mod import;
+mod index;
+mod jobs;
+mod library;
+mod metadata_cache;
+mod network;
+mod nsz;
+mod overrides;
+mod prewarm;
+mod quota;
+mod remote;
+mod replication;
+mod reports;
 mod scanner;
+mod search;
+mod selftest;
 mod serve_files;
+mod shop_tokens;
+mod slots;
+mod speedtest;
+mod split;
+mod stats;
 mod titledb;
+mod trash;
+mod upgrade;
+mod upload;
+mod users;
+mod verify;
+mod watcher;
+mod zip_stream;
 
-use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
-use axum::serve;
 use clap::Parser;
-use tokio::net::TcpListener;
-use tokio::sync::RwLock;
 use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
 
-use crate::auth::load_auth;
-use crate::catalog::Catalog;
-use crate::config::{AppConfig, Cli};
-use crate::http::{router, AppState, SessionStore};
-use crate::scanner::scan_library;
+use crate::announcements::AnnouncementStore;
+use crate::api_tokens::ApiTokenStore;
+use crate::artwork::ArtworkProvider;
+use crate::auth::{hash_password_entry, load_auth, spawn_auth_watcher, SharedAuth};
+use crate::bandwidth::Bandwidth;
+use crate::blocklist::BlocklistStore;
+use crate::catalog::set_filename_rules;
+use crate::chunks::ChunkMaps;
+use crate::config::{AppConfig, Cli, Command};
+use crate::export::ExportFormat;
+#[cfg(feature = "metrics")]
+use crate::growth::{spawn_growth_sampler, GrowthStore};
+use crate::hashing::HashCache;
+use crate::hooks::DownloadHook;
+#[cfg(unix)]
+use crate::http::bind_unix;
+#[cfg(feature = "ftp")]
+use crate::http::serve_ftp;
+use crate::http::{
+    redirect_router, router, serve, spawn_acme, spawn_cert_reloader, AcmeManager, AppState,
+    ReloadingCert, SessionStore, SettingsRevision, ShopIndex,
+};
+use crate::idle::{IdlePace, IdleTracker, DEFAULT_SCAN_MULTIPLIER};
+use crate::index::IndexEncryptor;
+use crate::jobs::JobManager;
+use crate::library::LibrarySet;
+use crate::metadata_cache::MetadataCache;
+use crate::network::BindAddr;
+use crate::overrides::{HiddenEntries, OverrideStore};
+use crate::prewarm::spawn_artwork_prewarm;
+use crate::quota::Quotas;
+use crate::reports::{spawn_report_scheduler, Reporter};
+use crate::shop_tokens::ShopTokenStore;
+use crate::slots::DownloadSlots;
+use crate::speedtest::SpeedTestLimiter;
+use crate::stats::{spawn_stats_saver, DownloadStats};
 use crate::titledb::TitleDb;
+use crate::upload::Uploads;
+use crate::verify::{Keys, Verifier};
+use crate::watcher::spawn_library_watcher;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    init_logging().context("failed to initialize logging")?;
+fn main() -> anyhow::Result<()> {
+    // Before the runtime's threads exist, as it changes the environment.
+    let handoff = upgrade::Handoff::from_env();
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to start the runtime")?
+        .block_on(run(handoff))
+}
 
+async fn run(handoff: upgrade::Handoff) -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let command = cli.command.clone();
+    // Keep stdout clean for commands that print data.
+    init_logging(command.is_some()).context("failed to initialize logging")?;
+
+    if let Some(Command::Remote(args)) = command {
+        // Needs no library or auth file, only the server's address and credentials.
+        return remote::run(args).await.context("remote command failed");
+    }
+    if let Some(Command::Selftest(args)) = command {
+        return selftest::run(args).await.context("selftest failed");
+    }
+    if let Some(Command::HashPassword(args)) = command {
+        let entry = hash_password_entry(&args).context("failed to hash password")?;
+        println!("{entry}");
+        return Ok(());
+    }
     let config = AppConfig::from_cli(cli).context("failed to load configuration")?;
+    set_filename_rules(
+        config
+            .scan
+            .filename_rules()
+            .context("invalid filename rules")?,
+    );
+    if let Some(Command::Export { format, output }) = command {
+        return run_export(&config, format, output.as_deref()).await;
+    }
     let auth = if config.public_shop {
         if config.auth_file.is_some() {
             info!("public shop mode enabled; auth file is ignored");
@@ -60,38 +169,131 @@ async fn main() -> anyhow::Result<()> {
             .unwrap_or_else(|| unreachable!("validated by config"));
         load_auth(Some(auth_path)).context("failed to load auth credentials file")?
     };
+    let binds = config
+        .binds
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
     info!(
-        bind = %config.bind,
-        root = %config.library_root.display(),
+        binds = %binds,
+        roots = ?config.library_roots,
         public_shop = config.public_shop,
         insecure_admin_cookie = config.insecure_admin_cookie,
         auth_enabled = auth.is_enabled(),
         auth_user_count = auth.user_count(),
         auth_file = ?config.auth_file.as_ref().map(|path| path.display().to_string()),
         scan_interval_seconds = config.scan_interval_seconds,
+        scan_mode = ?config.scan_mode,
         "configuration loaded"
     );
 
-    let initial_files = scan_library(&config.library_root).await.with_context(|| {
-        format!(
-            "failed to scan library root {}",
-            config.library_root.display()
+    let idle = IdleTracker::new(config.idle.after());
+    let library = if config.demo {
+        info!("demo mode: serving a made-up library; downloads are zeros");
+        LibrarySet::demo(demo::files())
+    } else {
+        LibrarySet::new(
+            config
+                .library_roots
+                .iter()
+                .map(|root| root.path.clone())
+                .collect(),
         )
-    })?;
+    }
+    .with_scan_config(config.scan.clone())
+    .with_idle(idle.clone())
+    .with_metadata_cache(MetadataCache::load(&config.data_dir, &config.scan))
+    .with_blocklist(BlocklistStore::load(&config.data_dir));
+    // Demo files have no bytes to hash or verify.
+    let library = if config.hash_files && !config.demo {
+        library.with_hashes(HashCache::load(&config.data_dir))
+    } else {
+        library
+    };
+    let library = if config.verify.enabled && !config.demo {
+        let keys = config
+            .verify
+            .keys_file
+            .as_deref()
+            .map(Keys::load)
+            .transpose()
+            .context("failed to load verification keys")?;
+        library.with_verifier(Verifier::load(
+            &config.data_dir,
+            keys,
+            config.verify.exclude_bad,
+        ))
+    } else {
+        library
+    };
+    let index_encryptor = if config.shop.encrypt {
+        let path = config
+            .shop
+            .public_key_file
+            .as_deref()
+            .context("shop.encrypt requires shop.public_key_file")?;
+        let encryptor = IndexEncryptor::load(path).context("failed to load shop public key")?;
+        Some(Arc::new(encryptor))
+    } else {
+        None
+    };
+    let shop = ShopIndex::from_config(&config.shop).context("failed to load shop settings")?;
+    let initial_files = library
+        .rescan_all()
+        .await
+        .context("failed to scan library roots")?;
 
     info!(
-        files = initial_files.len(),
-        root = %config.library_root.display(),
+        files = initial_files,
+        roots = library.roots().len(),
         "library scan complete"
     );
 
-    let catalog = Arc::new(RwLock::new(Catalog::from_files(initial_files)));
+    let mut poll = config.scan_mode.polls();
+    if config.scan_mode.watches() && !config.demo {
+        if let Err(err) = spawn_library_watcher(library.clone()) {
+            tracing::warn!(
+                error = %err,
+                "library watcher unavailable; falling back to interval polling"
+            );
+            poll = true;
+        }
+    }
+    if poll {
+        let pace = IdlePace::Stretch(
+            config
+                .idle
+                .scan_multiplier
+                .unwrap_or(DEFAULT_SCAN_MULTIPLIER)
+                .max(1),
+        );
+        for root in &config.library_roots {
+            spawn_background_scanner(
+                library.clone(),
+                root.path.clone(),
+                Duration::from_secs(root.scan_interval_seconds),
+                idle.clone(),
+                pace,
+            );
+        }
+    }
 
-    spawn_background_scanner(
-        Arc::clone(&catalog),
-        config.library_root.clone(),
-        Duration::from_secs(config.scan_interval_seconds),
-    );
+    if library.hashing_enabled() {
+        spawn_hash_pass(
+            library.clone(),
+            Duration::from_secs(config.scan_interval_seconds.max(60)),
+            idle.clone(),
+        );
+    }
+
+    if library.verifier().is_some() {
+        spawn_verify_pass(
+            library.clone(),
+            Duration::from_secs(config.scan_interval_seconds.max(60)),
+            idle.clone(),
+        );
+    }
 
     let (titledb_progress_tx, _) = tokio::sync::broadcast::channel::<String>(16);
     let titledb = TitleDb::with_progress(
@@ -99,34 +301,164 @@ async fn main() -> anyhow::Result<()> {
         config.data_dir.clone(),
         Some(titledb_progress_tx.clone()),
     );
-    let refresh_interval = config.titledb.refresh_interval.as_str();
-    spawn_titledb_refresh(titledb.clone(), refresh_interval);
-    if config.titledb.enabled {
-        info!(
-            refresh_interval = %refresh_interval,
-            "titledb background refresh scheduled"
-        );
+    #[cfg(feature = "titledb")]
+    {
+        let refresh_interval = config.titledb.refresh_interval.as_str();
+        spawn_titledb_refresh(titledb.clone(), refresh_interval, idle.clone());
+        if config.titledb.enabled {
+            info!(
+                refresh_interval = %refresh_interval,
+                "titledb background refresh scheduled"
+            );
+        }
+    }
+
+    // Demo downloads aren't worth keeping.
+    let downloads = if config.demo {
+        DownloadStats::new()
+    } else {
+        DownloadStats::load(&config.data_dir)
+    };
+    spawn_stats_saver(downloads.clone());
+    let reports = Reporter::new(
+        library.catalog(),
+        downloads.clone(),
+        &config.data_dir,
+        config.reports.clone(),
+    );
+    spawn_report_scheduler(reports.clone());
+
+    #[cfg(feature = "metrics")]
+    let growth = GrowthStore::load(&config.data_dir);
+    #[cfg(feature = "metrics")]
+    spawn_growth_sampler(library.clone(), growth.clone());
+
+    let prewarm_concurrency = config
+        .artwork
+        .prewarm_concurrency
+        .unwrap_or(prewarm::DEFAULT_CONCURRENCY);
+    let artwork = ArtworkProvider::new(config.artwork, &config.data_dir);
+    spawn_artwork_prewarm(
+        library.clone(),
+        titledb.clone(),
+        artwork.clone(),
+        prewarm_concurrency,
+    );
+
+    let mut auth = SharedAuth::new(auth);
+    if auth.load().is_enabled() {
+        if let Some(path) = &config.auth_file {
+            auth = auth.with_file(path);
+            if let Err(err) = spawn_auth_watcher(path.clone(), auth.clone()) {
+                tracing::warn!(error = %err, "auth file watcher unavailable; restart to apply changes");
+            }
+        }
     }
 
+    let acme = config
+        .server
+        .acme
+        .clone()
+        .map(|acme| AcmeManager::new(acme, &config.data_dir));
+
     let state = AppState {
-        catalog,
-        library_root: config.library_root,
-        auth: Arc::new(auth),
+        catalog: library.catalog(),
+        library,
+        jobs: JobManager::new(),
+        mirror_root: config.mirror_root,
+        dedup: config.dedup,
+        downloads: downloads.clone(),
+        bandwidth: Bandwidth::new(&config.downloads),
+        read_buffer: config.downloads.read_buffer(),
+        download_slots: DownloadSlots::new(config.downloads.max_concurrent_per_client),
+        transcode_nsz: config.downloads.transcode_nsz,
+        uploads: Uploads::new(config.uploads.dir()),
+        reports,
+        auth,
         insecure_admin_cookie: config.insecure_admin_cookie,
         sessions: SessionStore::new(24),
         titledb,
-        data_dir: config.data_dir,
+        artwork,
+        overrides: OverrideStore::load(
+            &config.data_dir,
+            HiddenEntries::from_config(&config.hidden),
+        ),
+        shop_tokens: ShopTokenStore::load(&config.data_dir),
+        api_tokens: ApiTokenStore::load(&config.data_dir),
+        announcements: AnnouncementStore::load(&config.data_dir),
+        #[cfg(feature = "metrics")]
+        growth,
+        quotas: Arc::new(Quotas::new(&config.quotas, &config.library_roots)),
+        idle,
+        settings: SettingsRevision::load(&config.data_dir),
+        data_dir: config.data_dir.clone(),
         titledb_progress_tx,
+        speedtests: SpeedTestLimiter::default(),
+        chunk_maps: ChunkMaps::default(),
+        health: config.health,
+        base_path: config.server.base_path().map(Arc::from),
+        external_url: config.server.external_url().map(Arc::from),
+        absolute_urls: config.server.absolute_urls,
+        rate_limit: Arc::new(config.rate_limit),
+        download_hook: DownloadHook::new(&config.hooks),
+        shop: Arc::new(shop),
+        index_encryptor,
     };
 
+    #[cfg(feature = "ftp")]
+    let ftp_state = state.clone();
     let app = router(state);
-    let listener = TcpListener::bind(config.bind)
-        .await
-        .with_context(|| format!("failed to bind {}", config.bind))?;
+    let tls = match (&config.server.tls_cert, &config.server.tls_key) {
+        _ if acme.is_some() => acme
+            .as_ref()
+            .map(AcmeManager::acceptor)
+            .transpose()
+            .context("failed to set up TLS")?,
+        (Some(cert), Some(key)) => {
+            let cert =
+                Arc::new(ReloadingCert::load(cert, key).context("failed to load TLS certificate")?);
+            spawn_cert_reloader(Arc::clone(&cert));
+            Some(cert.acceptor().context("failed to set up TLS")?)
+        }
+        _ => None,
+    };
+    let mut inherited =
+        upgrade::inherited_listener(&handoff).context("failed to take over socket")?;
+    // With several addresses, `[::]` must leave IPv4 to a separate `0.0.0.0`.
+    let v6_only = config.binds.len() > 1;
+    let mut listeners = Vec::new();
+    #[cfg(unix)]
+    let mut unix_listeners = Vec::new();
+    for bind in &config.binds {
+        match bind {
+            BindAddr::Tcp(addr) => {
+                let listener = match inherited.take() {
+                    Some(listener) => {
+                        info!("took over listening socket from previous process");
+                        Ok(listener)
+                    }
+                    None if listeners.is_empty() => upgrade::bind(*addr, v6_only),
+                    None => upgrade::bind_shared(*addr, v6_only),
+                };
+                let listener = listener.with_context(|| format!("failed to bind {addr}"))?;
+                listeners.push(listener);
+            }
+            #[cfg(unix)]
+            BindAddr::Unix(path) => {
+                unix_listeners
+                    .push(bind_unix(path).with_context(|| format!("failed to bind {bind}"))?);
+            }
+            #[cfg(not(unix))]
+            BindAddr::Unix(_) => {
+                anyhow::bail!("failed to bind {bind}: Unix sockets need a Unix system")
+            }
+        }
+    }
 
-    if config.bind.ip().is_loopback() {
+    let tcp_binds = || config.binds.iter().filter_map(BindAddr::tcp);
+    if tcp_binds().next().is_some() && tcp_binds().all(|addr| addr.ip().is_loopback()) {
         tracing::warn!(
-            bind = %config.bind,
+            binds = %binds,
             "binding to loopback; use --bind 0.0.0.0:8465 for LAN access"
         );
     }
@@ -136,74 +468,198 @@ async fn main() -> anyhow::Result<()> {
         );
     }
 
+    if let Some(addr) = config.server.http_redirect_bind {
+        let redirect =
+            upgrade::bind_shared(addr, false).with_context(|| format!("failed to bind {addr}"))?;
+        let https_port = tcp_binds().next().map_or(443, |addr| addr.port());
+        tokio::spawn(serve(
+            redirect,
+            redirect_router(https_port),
+            config.server.clone(),
+            None,
+            std::future::pending(),
+        ));
+        info!(bind = %addr, "redirecting HTTP to HTTPS");
+    }
+
+    if let Some(acme) = acme {
+        // After binding: validation connects to the listener.
+        spawn_acme(acme);
+    }
+
     let shutdown = tokio::signal::ctrl_c();
-    info!(bind = %config.bind, "ownfoil-rs listening");
-
-    serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(async {
-        let _ = shutdown.await;
-        info!("shutting down gracefully");
-    })
-    .await
-    .context("server exited with error")
+    // Upgrades hand over the first TCP listener; the others are bound again.
+    let handed_over = listeners
+        .first()
+        .map(|listener| upgrade::handed_over(listener, &config.data_dir))
+        .transpose()
+        .context("failed to watch for upgrades")?;
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let stopped = move || {
+        let mut stopped = stopped.clone();
+        async move {
+            let _ = stopped.wait_for(|stop| *stop).await;
+        }
+    };
+    let mut servers = tokio::task::JoinSet::new();
+    for listener in listeners {
+        let server = serve(
+            listener,
+            app.clone(),
+            config.server.clone(),
+            tls.clone(),
+            stopped(),
+        );
+        servers.spawn(server);
+    }
+    #[cfg(unix)]
+    for listener in unix_listeners {
+        // Behind a reverse proxy, which terminates TLS.
+        servers.spawn(serve(
+            listener,
+            app.clone(),
+            config.server.clone(),
+            None,
+            stopped(),
+        ));
+    }
+    if let Some(addr) = config.ftp.bind {
+        #[cfg(feature = "ftp")]
+        {
+            let listener = upgrade::bind_shared(addr, false)
+                .with_context(|| format!("failed to bind {addr}"))?;
+            servers.spawn(serve_ftp(
+                listener,
+                ftp_state,
+                config.ftp.clone(),
+                tls.clone(),
+                stopped(),
+            ));
+            info!(bind = %addr, ftps = tls.is_some(), "serving FTP");
+        }
+        #[cfg(not(feature = "ftp"))]
+        tracing::warn!(bind = %addr, "[ftp] is set, but this build has no FTP server");
+    }
+    info!(binds = %binds, https = tls.is_some(), "ownfoil-rs listening");
+    upgrade::announce_ready(&handoff);
+
+    tokio::select! {
+        _ = shutdown => info!("shutting down gracefully"),
+        () = async {
+            match handed_over {
+                Some(handed_over) => handed_over.await,
+                None => std::future::pending().await,
+            }
+        } => {}
+    }
+    let _ = stop.send(true);
+    while servers.join_next().await.is_some() {}
+    if let Err(err) = downloads.save().await {
+        tracing::warn!(error = %err, "failed to save download stats");
+    }
+    Ok(())
 }
 
 /// Initialize tracing subscriber with `RUST_LOG` env filter (default: `info`).
-fn init_logging() -> anyhow::Result<()> {
+fn init_logging(to_stderr: bool) -> anyhow::Result<()> {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
-    tracing_subscriber::fmt()
+    let builder = tracing_subscriber::fmt()
         .with_env_filter(filter)
         .with_target(false)
-        .compact()
-        .init();
+        .compact();
+    if to_stderr {
+        builder.with_writer(std::io::stderr).init();
+    } else {
+        builder.init();
+    }
 
     Ok(())
 }
 
+/// `ownfoil-rs export`: scan every library root once and write the inventory.
+async fn run_export(
+    config: &AppConfig,
+    format: ExportFormat,
+    output: Option<&std::path::Path>,
+) -> anyhow::Result<()> {
+    let library = if config.demo {
+        LibrarySet::demo(demo::files())
+    } else {
+        LibrarySet::new(
+            config
+                .library_roots
+                .iter()
+                .map(|root| root.path.clone())
+                .collect(),
+        )
+    }
+    .with_scan_config(config.scan.clone())
+    .with_metadata_cache(MetadataCache::load(&config.data_dir, &config.scan))
+    .with_blocklist(BlocklistStore::load(&config.data_dir));
+    // Include hashes already cached by the server; nothing new is hashed here.
+    let library = if config.hash_files {
+        library.with_hashes(HashCache::load(&config.data_dir))
+    } else {
+        library
+    };
+    library
+        .rescan_all()
+        .await
+        .context("failed to scan library roots")?;
+
+    let catalog = library.catalog();
+    let catalog = catalog.read().await;
+    let rendered =
+        export::render(catalog.files(), format).context("failed to render library export")?;
+    match output {
+        Some(path) => {
+            tokio::fs::write(path, rendered)
+                .await
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            info!(path = %path.display(), files = catalog.files().len(), "library exported");
+        }
+        None => print!("{rendered}"),
+    }
+    Ok(())
+}
+
 /// Spawns a background task that refreshes TitleDB at the given interval (e.g. `24h`).
-/// Runs one refresh immediately, then on a ticker.
-fn spawn_titledb_refresh(titledb: TitleDb, interval_str: &str) {
+/// Runs one refresh immediately, then every interval; paused while idle.
+#[cfg(feature = "titledb")]
+fn spawn_titledb_refresh(titledb: TitleDb, interval_str: &str, idle: IdleTracker) {
     let interval = humantime::parse_duration(interval_str).unwrap_or(Duration::from_secs(86400));
     tokio::spawn(async move {
-        titledb.refresh();
-        let mut ticker = tokio::time::interval(interval);
         loop {
-            ticker.tick().await;
+            let started = tokio::time::Instant::now();
             titledb.refresh();
+            idle.wait(started, interval, IdlePace::Pause).await;
         }
     });
 }
 
-/// Spawns a background task that rescans the library root at the given interval.
-/// Updates the shared catalog in place. Logs errors but does not panic.
+/// Spawns a background task that rescans one library root at the given interval, paced
+/// by `pace` while idle. Merges the result into the shared catalog. Logs errors but does
+/// not panic.
 fn spawn_background_scanner(
-    catalog: Arc<RwLock<Catalog>>,
+    library: LibrarySet,
     root: std::path::PathBuf,
     interval: Duration,
+    idle: IdleTracker,
+    pace: IdlePace,
 ) {
     tokio::spawn(async move {
-        let mut ticker = tokio::time::interval(interval);
-
         loop {
-            ticker.tick().await;
-
-            let root = root.clone();
-            let catalog = Arc::clone(&catalog);
-            let handle = tokio::spawn(async move {
-                let files = scan_library(&root).await?;
-                let count = files.len();
-                let mut guard = catalog.write().await;
-                *guard = Catalog::from_files(files);
-                Ok::<_, crate::scanner::ScanError>(count)
-            });
+            let started = tokio::time::Instant::now();
+            let library = library.clone();
+            let scan_root = root.clone();
+            let handle = tokio::spawn(async move { library.rescan(&scan_root).await });
 
             match handle.await {
-                Ok(Ok(count)) => info!(files = count, "catalog refreshed"),
-                Ok(Err(err)) => error!(error = %err, "catalog refresh failed"),
+                Ok(Ok(count)) => info!(root = %root.display(), files = count, "catalog refreshed"),
+                Ok(Err(err)) => {
+                    error!(root = %root.display(), error = %err, "catalog refresh failed")
+                }
                 Err(join_err) => {
                     if join_err.is_panic() {
                         error!(
@@ -213,6 +669,37 @@ fn spawn_background_scanner(
                     }
                 }
             }
+            idle.wait(started, interval, pace).await;
+        }
+    });
+}
+
+/// Spawns a background task that verifies not-yet-verified library files at the given
+/// interval; paused while idle.
+fn spawn_verify_pass(library: LibrarySet, interval: Duration, idle: IdleTracker) {
+    tokio::spawn(async move {
+        loop {
+            let started = tokio::time::Instant::now();
+            let verified = library.verify_pass().await;
+            if verified > 0 {
+                info!(files = verified, "library verification pass complete");
+            }
+            idle.wait(started, interval, IdlePace::Pause).await;
+        }
+    });
+}
+
+/// Spawns a background task that hashes not-yet-hashed library files at the given
+/// interval, for duplicate detection; paused while idle.
+fn spawn_hash_pass(library: LibrarySet, interval: Duration, idle: IdleTracker) {
+    tokio::spawn(async move {
+        loop {
+            let started = tokio::time::Instant::now();
+            let hashed = library.hash_pass().await;
+            if hashed > 0 {
+                info!(files = hashed, "library hashing pass complete");
+            }
+            idle.wait(started, interval, IdlePace::Pause).await;
         }
     });
 }
@@ -0,0 +1,234 @@
+//! Metadata cache: the parsed title ID, version, and kind of each library file, kept
+//! across restarts.
+//!
+//! Scanning opens every file to read its container header and matches its path against
+//! the filename patterns; on a large library behind a network share that is most of a
+//! scan's cost, yet most files never change. Results are cached by absolute path,
+//! validated by size and modification time, and persisted to
+//! `<data_dir>/metadata.json`, so only new or touched files are parsed again. The cache
+//! records the filename patterns and server version it was built with and starts over
+//! when either changes.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::catalog::{ContentFile, ContentKind};
+use crate::config::ScanConfig;
+
+const METADATA_FILE: &str = "metadata.json";
+
+/// What parsing a file produced; everything else in a [`ContentFile`] comes from the walk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParsedMetadata {
+    pub name: String,
+    pub title_id: Option<String>,
+    pub version: Option<u32>,
+    pub kind: ContentKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedMetadata {
+    path: PathBuf,
+    size: u64,
+    /// Modification time as (seconds, nanoseconds) since the Unix epoch.
+    modified: (u64, u32),
+    #[serde(flatten)]
+    parsed: ParsedMetadata,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredCache {
+    fingerprint: String,
+    entries: Vec<CachedMetadata>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetadataCache {
+    inner: Arc<RwLock<HashMap<PathBuf, CachedMetadata>>>,
+    /// Set when entries changed since the last save.
+    dirty: Arc<AtomicBool>,
+    fingerprint: String,
+    store_path: PathBuf,
+}
+
+impl MetadataCache {
+    /// Load the persisted cache from `data_dir`, starting empty if it is missing, invalid,
+    /// or was built with other filename patterns.
+    pub fn load(data_dir: &Path, scan: &ScanConfig) -> Self {
+        let store_path = data_dir.join(METADATA_FILE);
+        let fingerprint = format!(
+            "{}|{}|{}",
+            env!("CARGO_PKG_VERSION"),
+            scan.title_regex.as_deref().unwrap_or_default(),
+            scan.version_regex.as_deref().unwrap_or_default()
+        );
+        let entries = std::fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<StoredCache>(&raw).ok())
+            .filter(|stored| stored.fingerprint == fingerprint)
+            .map(|stored| stored.entries)
+            .unwrap_or_default();
+        debug!(entries = entries.len(), "metadata cache loaded");
+        Self {
+            inner: Arc::new(RwLock::new(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.path.clone(), entry))
+                    .collect(),
+            )),
+            dirty: Arc::new(AtomicBool::new(false)),
+            fingerprint,
+            store_path,
+        }
+    }
+
+    /// Cached metadata for `path`, if it was parsed from a file of the same size and
+    /// modification time. Files without a modification time are never cached.
+    pub fn get(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+    ) -> Option<ParsedMetadata> {
+        let modified = timestamp(modified?)?;
+        self.inner
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified == modified)
+            .map(|entry| entry.parsed.clone())
+    }
+
+    /// Remember what parsing `file` (found at `path`) produced.
+    pub fn insert(&self, path: &Path, file: &ContentFile, modified: Option<SystemTime>) {
+        let Some(modified) = modified.and_then(timestamp) else {
+            return;
+        };
+        let entry = CachedMetadata {
+            path: path.to_path_buf(),
+            size: file.size,
+            modified,
+            parsed: ParsedMetadata {
+                name: file.name.clone(),
+                title_id: file.title_id.clone(),
+                version: file.version,
+                kind: file.kind,
+            },
+        };
+        self.inner
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .insert(entry.path.clone(), entry);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Forget entries under `root` that are not in `seen`, e.g. files deleted since the
+    /// last scan.
+    pub fn prune(&self, root: &Path, seen: &HashSet<PathBuf>) {
+        let mut entries = self.inner.write().unwrap_or_else(|p| p.into_inner());
+        let before = entries.len();
+        entries.retain(|path, _| !path.starts_with(root) || seen.contains(path));
+        if entries.len() != before {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Persist the cache if anything changed since the last save.
+    pub async fn save(&self) {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let stored = StoredCache {
+            fingerprint: self.fingerprint.clone(),
+            entries: self
+                .inner
+                .read()
+                .unwrap_or_else(|p| p.into_inner())
+                .values()
+                .cloned()
+                .collect(),
+        };
+        let result = async {
+            let raw = serde_json::to_string(&stored).map_err(std::io::Error::other)?;
+            if let Some(parent) = self.store_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&self.store_path, raw).await
+        }
+        .await;
+        if let Err(err) = result {
+            self.dirty.store(true, Ordering::Relaxed);
+            warn!(path = %self.store_path.display(), error = %err, "metadata cache save failed");
+        }
+    }
+}
+
+fn timestamp(time: SystemTime) -> Option<(u64, u32)> {
+    let elapsed = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((elapsed.as_secs(), elapsed.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::MetadataCache;
+    use crate::catalog::{ContentFile, ContentKind};
+    use crate::config::ScanConfig;
+
+    fn file(size: u64) -> ContentFile {
+        ContentFile {
+            title_id: Some(String::from("0100ABCD12340000")),
+            version: Some(0),
+            kind: ContentKind::Base,
+            ..ContentFile::fixture("game.nsp", size)
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_are_validated_by_size_and_mtime_and_persist() -> Result<()> {
+        let dir = tempdir()?;
+        let path = PathBuf::from("/library/game.nsp");
+        let modified = Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123));
+        let touched = modified.map(|time| time + Duration::from_millis(1));
+
+        let cache = MetadataCache::load(dir.path(), &ScanConfig::default());
+        cache.insert(&path, &file(4), modified);
+        assert!(cache.get(&path, 4, modified).is_some());
+        assert!(cache.get(&path, 5, modified).is_none());
+        assert!(cache.get(&path, 4, touched).is_none());
+        assert!(cache.get(&path, 4, None).is_none());
+        cache.save().await;
+
+        let reloaded = MetadataCache::load(dir.path(), &ScanConfig::default());
+        let parsed = reloaded.get(&path, 4, modified);
+        assert_eq!(
+            parsed.and_then(|p| p.title_id).as_deref(),
+            Some("0100ABCD12340000")
+        );
+
+        // Other filename patterns would parse differently: start over.
+        let custom = ScanConfig {
+            version_regex: Some(String::from(r"-r(?P<version>\d+)")),
+            ..ScanConfig::default()
+        };
+        assert!(MetadataCache::load(dir.path(), &custom)
+            .get(&path, 4, modified)
+            .is_none());
+
+        reloaded.prune(&PathBuf::from("/library"), &HashSet::new());
+        assert!(reloaded.get(&path, 4, modified).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! IP networks in CIDR notation (`192.168.1.0/24`, `fd00::/8`) and listen addresses
+//! (`0.0.0.0:8465`, `unix:/run/ownfoil.sock`), as used in the config.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid network {0:?}; expected an address or CIDR like 192.168.1.0/24")]
+pub struct NetworkParseError(String);
+
+/// An address range; a bare address is a network of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Whether `ip` is in the network. IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), as
+    /// seen on dual-stack listeners, match IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(net), u32::from(ip), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches<T>(net: T, ip: T, prefix_len: u8) -> bool
+where
+    T: Copy + Eq + std::ops::BitXor<Output = T> + std::ops::Shr<u32, Output = T> + From<u8>,
+{
+    let bits = std::mem::size_of::<T>() as u32 * 8;
+    let host_bits = bits - u32::from(prefix_len);
+    // Shifting by the full width overflows; a /0 matches everything.
+    host_bits == bits || (net ^ ip) >> host_bits == T::from(0)
+}
+
+impl FromStr for IpNetwork {
+    type Err = NetworkParseError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let invalid = || NetworkParseError(raw.to_string());
+        let (addr, prefix_len) = match raw.trim().split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (raw.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix_len > max {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = NetworkParseError;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        raw.parse()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid bind address {0:?}; expected an address like 0.0.0.0:8465 or unix:/path")]
+pub struct BindParseError(String);
+
+/// Where the server listens: a TCP address or, with a `unix:` prefix, a Unix socket path.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum BindAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl BindAddr {
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(addr) => Some(*addr),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+impl FromStr for BindAddr {
+    type Err = BindParseError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let raw = raw.trim();
+        match raw.strip_prefix("unix:") {
+            Some(path) if !path.is_empty() => Ok(Self::Unix(PathBuf::from(path))),
+            Some(_) => Err(BindParseError(raw.to_string())),
+            None => raw
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| BindParseError(raw.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for BindAddr {
+    type Error = BindParseError;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        raw.parse()
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+
+    use super::{BindAddr, IpNetwork};
+
+    #[test]
+    fn networks_match_addresses_by_prefix() -> Result<()> {
+        let lan: IpNetwork = "192.168.1.0/24".parse()?;
+        assert!(lan.contains("192.168.1.42".parse()?));
+        assert!(lan.contains("::ffff:192.168.1.42".parse()?));
+        assert!(!lan.contains("192.168.2.1".parse()?));
+        assert!(!lan.contains("fd00::1".parse()?));
+
+        let host: IpNetwork = "10.0.0.5".parse()?;
+        assert_eq!(host.to_string(), "10.0.0.5/32");
+        assert!(host.contains("10.0.0.5".parse()?));
+        assert!(!host.contains("10.0.0.6".parse()?));
+
+        let ula: IpNetwork = "fd00::/8".parse()?;
+        assert!(ula.contains("fd12:3456::1".parse()?));
+        assert!(!ula.contains("fe80::1".parse()?));
+
+        let any: IpNetwork = "0.0.0.0/0".parse()?;
+        assert!(any.contains(IpAddr::from([203, 0, 113, 9])));
+
+        for invalid in ["192.168.1.0/33", "lan", "10.0.0.0/x", "::/129"] {
+            assert!(invalid.parse::<IpNetwork>().is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+    #[test]
+    fn bind_addresses_are_tcp_or_unix_sockets() -> Result<()> {
+        let v6: BindAddr = "[::]:8465".parse()?;
+        assert_eq!(v6, BindAddr::Tcp(SocketAddr::from(([0u16; 8], 8465))));
+        assert_eq!(v6.to_string(), "[::]:8465");
+
+        let unix: BindAddr = "unix:/run/ownfoil.sock".parse()?;
+        assert_eq!(unix, BindAddr::Unix(PathBuf::from("/run/ownfoil.sock")));
+        assert_eq!(unix.to_string(), "unix:/run/ownfoil.sock");
+        assert_eq!(unix.tcp(), None);
+
+        for invalid in ["unix:", "0.0.0.0", "localhost:8465"] {
+            assert!(invalid.parse::<BindAddr>().is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,655 @@
+//! NSZ to NSP decompression, for clients that can't install NSZ.
+//!
+//! An NSZ is an NSP whose NCAs are stored as NCZs: the first 0x4000 bytes of the NCA as
+//! they are, a table of its sections with their AES-CTR keys, and the rest of the NCA
+//! decrypted and zstd-compressed, either as one solid stream or as independently
+//! compressed blocks (`NCZBLOCK`). Decompressing and re-encrypting each section gives
+//! back the original NCA byte for byte. The section tables hold every NCA's size, so the
+//! NSP's layout and length are known before anything is decompressed.
+//!
+//! A range is produced from the start of the first NCA it touches: block-compressed NCZs
+//! seek to the block holding it, while a solid stream is decompressed from its start.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::container::{pfs0_entries, Entry};
+use crate::split::ContentReader;
+
+/// The NCA bytes an NCZ stores uncompressed, ahead of its section table.
+const NCA_HEADER_SIZE: u64 = 0x4000;
+const SECTION_MAGIC: &[u8; 8] = b"NCZSECTN";
+const BLOCK_MAGIC: &[u8; 8] = b"NCZBLOCK";
+const SECTION_ENTRY_SIZE: usize = 0x40;
+/// Section crypto types re-encrypted with AES-CTR: plain CTR and BKTR (patch) sections.
+const CTR_CRYPTO_TYPES: [u64; 2] = [3, 4];
+/// Upper bounds that reject garbage headers before allocating.
+const MAX_SECTIONS: u64 = 64;
+const MAX_BLOCKS: u32 = 1 << 22;
+/// Chunks buffered between the decompressing thread and the response.
+const CHANNEL_CHUNKS: usize = 4;
+
+/// The NSP an NSZ decompresses to: its PFS0 header, then each entry in turn, NCZs as
+/// their NCAs and everything else copied as is.
+#[derive(Debug)]
+pub struct NspLayout {
+    header: Vec<u8>,
+    parts: Vec<Part>,
+    len: u64,
+}
+
+#[derive(Debug)]
+enum Part {
+    /// Copied from `offset` in the NSZ.
+    Stored {
+        offset: u64,
+        len: u64,
+    },
+    Ncz(Ncz),
+}
+
+impl Part {
+    fn len(&self) -> u64 {
+        match self {
+            Part::Stored { len, .. } => *len,
+            Part::Ncz(ncz) => ncz.nca_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Ncz {
+    /// Where the NCZ starts in the NSZ.
+    offset: u64,
+    nca_size: u64,
+    sections: Vec<Section>,
+    /// Where the compressed data starts in the NSZ.
+    body: u64,
+    /// Block table; a solid zstd stream when unset.
+    blocks: Option<Blocks>,
+}
+
+#[derive(Debug)]
+struct Section {
+    /// NCA offset of the section.
+    offset: u64,
+    size: u64,
+    /// AES-CTR key and counter; unencrypted when unset.
+    ctr: Option<([u8; 16], [u8; 16])>,
+}
+
+impl Section {
+    fn end(&self) -> u64 {
+        self.offset.saturating_add(self.size)
+    }
+
+    /// Encrypt `data`, found at NCA offset `offset`, with the section's keystream. The
+    /// counter is the upper half of the section's, then the offset in 16-byte blocks.
+    fn encrypt(&self, offset: u64, data: &mut [u8]) {
+        let Some((key, counter)) = &self.ctr else {
+            return;
+        };
+        let cipher = Aes128::new(GenericArray::from_slice(key));
+        let first = offset / 16;
+        let skip = (offset % 16) as usize;
+        let mut keystream: Vec<_> = (0..(skip + data.len()).div_ceil(16) as u64)
+            .map(|index| {
+                let mut block = [0u8; 16];
+                block[..8].copy_from_slice(&counter[..8]);
+                block[8..].copy_from_slice(&(first + index).to_be_bytes());
+                GenericArray::from(block)
+            })
+            .collect();
+        cipher.encrypt_blocks(&mut keystream);
+        for (byte, key) in data.iter_mut().zip(keystream.iter().flatten().skip(skip)) {
+            *byte ^= key;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Blocks {
+    /// Decompressed size of every block but the last.
+    size: u64,
+    /// Decompressed size of all blocks.
+    total: u64,
+    /// NSZ offset and compressed size of each block.
+    compressed: Vec<(u64, u64)>,
+}
+
+impl Blocks {
+    fn decompressed_len(&self, index: usize) -> u64 {
+        (self.total - index as u64 * self.size).min(self.size)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read_array(reader).map(u64::from_le_bytes)
+}
+
+impl NspLayout {
+    /// Lay out the NSP that `reader`, an NSZ, decompresses to. Fails with
+    /// [`io::ErrorKind::InvalidData`] when it isn't one.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+        let entries = pfs0_entries(reader).ok_or_else(|| invalid("not a PFS0 container"))?;
+        if !entries.iter().any(|entry| entry.name.ends_with(".ncz")) {
+            return Err(invalid("no NCZ entries"));
+        }
+        let mut files = Vec::with_capacity(entries.len());
+        let mut parts = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let part = match entry.name.strip_suffix(".ncz") {
+                Some(stem) => {
+                    files.push(format!("{stem}.nca"));
+                    Part::Ncz(read_ncz(reader, &entry)?)
+                }
+                None => {
+                    files.push(entry.name);
+                    Part::Stored {
+                        offset: entry.offset,
+                        len: entry.size,
+                    }
+                }
+            };
+            parts.push(part);
+        }
+        let sizes: Vec<_> = parts.iter().map(Part::len).collect();
+        let header = pfs0_header(&files, &sizes);
+        let len = sizes
+            .iter()
+            .try_fold(header.len() as u64, |len, size| len.checked_add(*size))
+            .ok_or_else(|| invalid("NCA sizes overflow"))?;
+        Ok(Self { header, parts, len })
+    }
+
+    /// Size of the NSP.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Write `len` bytes of the NSP from `start` to `sink`, at most `chunk` at a time,
+    /// reading the NSZ from `reader`.
+    pub fn write_range<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        start: u64,
+        len: u64,
+        chunk: usize,
+        sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let end = start.saturating_add(len).min(self.len);
+        let header_len = self.header.len() as u64;
+        if start < header_len {
+            sink(&self.header[start as usize..end.min(header_len) as usize])?;
+        }
+        let mut position = header_len;
+        for part in &self.parts {
+            let part_end = position + part.len();
+            if position >= end {
+                break;
+            }
+            if part_end > start {
+                let from = start.saturating_sub(position);
+                let to = (end - position).min(part.len());
+                match part {
+                    Part::Stored { offset, .. } => {
+                        copy(reader, offset + from, to - from, chunk, sink)?
+                    }
+                    Part::Ncz(ncz) => ncz.write(reader, from, to, chunk, sink)?,
+                }
+            }
+            position = part_end;
+        }
+        Ok(())
+    }
+
+    /// Stream `len` bytes of the NSP at `path` from `start`, decompressed on a blocking
+    /// thread `chunk` bytes at a time once the stream is first polled.
+    pub fn stream_range(
+        self: &Arc<Self>,
+        path: PathBuf,
+        start: u64,
+        len: u64,
+        chunk: usize,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let layout = Arc::clone(self);
+        stream::once(async move {
+            let (tx, rx) = mpsc::channel(CHANNEL_CHUNKS);
+            tokio::task::spawn_blocking(move || {
+                let result = ContentReader::open(&path).and_then(|mut reader| {
+                    layout.write_range(&mut reader, start, len, chunk, &mut |data| {
+                        tx.blocking_send(Ok(Bytes::copy_from_slice(data)))
+                            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+                    })
+                });
+                match result {
+                    // The response was dropped.
+                    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+                    Err(err) => {
+                        let _ = tx.blocking_send(Err(err));
+                    }
+                    Ok(()) => {}
+                }
+            });
+            ReceiverStream::new(rx)
+        })
+        .flatten()
+    }
+}
+
+/// PFS0 header listing `files` back to back, padded to 16 bytes.
+fn pfs0_header(files: &[String], sizes: &[u64]) -> Vec<u8> {
+    let mut entries = Vec::with_capacity(files.len() * 0x18);
+    let mut strings = Vec::new();
+    let mut offset = 0u64;
+    for (name, size) in files.iter().zip(sizes) {
+        entries.extend(offset.to_le_bytes());
+        entries.extend(size.to_le_bytes());
+        entries.extend((strings.len() as u32).to_le_bytes());
+        entries.extend(0u32.to_le_bytes());
+        strings.extend(name.as_bytes());
+        strings.push(0);
+        offset += size;
+    }
+    let unpadded = 0x10 + entries.len() + strings.len();
+    strings.resize(
+        strings.len() + unpadded.next_multiple_of(0x10) - unpadded,
+        0,
+    );
+
+    let mut header = Vec::with_capacity(0x10 + entries.len() + strings.len());
+    header.extend(b"PFS0");
+    header.extend((files.len() as u32).to_le_bytes());
+    header.extend((strings.len() as u32).to_le_bytes());
+    header.extend(0u32.to_le_bytes());
+    header.extend(entries);
+    header.extend(strings);
+    header
+}
+
+/// Parse the NCZ header of `entry`: its section table and, if block-compressed, the
+/// block table.
+fn read_ncz<R: Read + Seek>(reader: &mut R, entry: &Entry) -> io::Result<Ncz> {
+    reader.seek(SeekFrom::Start(entry.offset + NCA_HEADER_SIZE))?;
+    if &read_array::<_, 8>(reader)? != SECTION_MAGIC {
+        return Err(invalid("NCZ without a section table"));
+    }
+    let count = read_u64(reader)?;
+    if count == 0 || count > MAX_SECTIONS {
+        return Err(invalid("bad NCZ section count"));
+    }
+    let mut sections = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let raw: [u8; SECTION_ENTRY_SIZE] = read_array(reader)?;
+        let field = |at: usize| u64::from_le_bytes(raw[at..at + 8].try_into().unwrap_or_default());
+        let mut key = [0u8; 16];
+        let mut counter = [0u8; 16];
+        key.copy_from_slice(&raw[0x20..0x30]);
+        counter.copy_from_slice(&raw[0x30..0x40]);
+        sections.push(Section {
+            offset: field(0),
+            size: field(8),
+            ctr: CTR_CRYPTO_TYPES
+                .contains(&field(0x10))
+                .then_some((key, counter)),
+        });
+    }
+    if sections
+        .iter()
+        .any(|section| section.offset < NCA_HEADER_SIZE)
+    {
+        return Err(invalid("NCZ section overlaps the NCA header"));
+    }
+    let nca_size = sections
+        .iter()
+        .map(Section::end)
+        .max()
+        .unwrap_or_default()
+        .max(NCA_HEADER_SIZE);
+
+    let mut body = entry.offset + NCA_HEADER_SIZE + 16 + count * SECTION_ENTRY_SIZE as u64;
+    let blocks = if &read_array::<_, 8>(reader)? == BLOCK_MAGIC {
+        // version, type, unused, block size exponent, block count, decompressed size
+        let [_, _, _, exponent] = read_array::<_, 4>(reader)?;
+        let count = u32::from_le_bytes(read_array(reader)?);
+        let total = read_u64(reader)?;
+        if !(14..=32).contains(&exponent) || count > MAX_BLOCKS {
+            return Err(invalid("bad NCZ block header"));
+        }
+        let size = 1u64 << exponent;
+        if total > count as u64 * size || total != nca_size - NCA_HEADER_SIZE {
+            return Err(invalid("NCZ block table doesn't match its sections"));
+        }
+        body += 8 + 16 + 4 * count as u64;
+        let mut compressed = Vec::with_capacity(count as usize);
+        let mut offset = body;
+        for _ in 0..count {
+            let len = u64::from(u32::from_le_bytes(read_array(reader)?));
+            compressed.push((offset, len));
+            offset += len;
+        }
+        Some(Blocks {
+            size,
+            total,
+            compressed,
+        })
+    } else {
+        None
+    };
+    Ok(Ncz {
+        offset: entry.offset,
+        nca_size,
+        sections,
+        body,
+        blocks,
+    })
+}
+
+impl Ncz {
+    /// Write bytes `from..to` of the NCA to `sink`.
+    fn write<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        from: u64,
+        to: u64,
+        chunk: usize,
+        sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut position = from;
+        if position < NCA_HEADER_SIZE {
+            let header_end = to.min(NCA_HEADER_SIZE);
+            copy(
+                reader,
+                self.offset + position,
+                header_end - position,
+                chunk,
+                sink,
+            )?;
+            position = header_end;
+        }
+        if position >= to {
+            return Ok(());
+        }
+        let mut body = self.decompressed(reader, position - NCA_HEADER_SIZE)?;
+        let mut buf = vec![0u8; chunk];
+        while position < to {
+            // Keep each chunk within one section, so it is encrypted with one key.
+            let boundary = self
+                .sections
+                .iter()
+                .flat_map(|section| [section.offset, section.end()])
+                .filter(|boundary| *boundary > position)
+                .min()
+                .unwrap_or(to);
+            let len = (chunk as u64).min(to - position).min(boundary - position) as usize;
+            let data = &mut buf[..len];
+            body.read_exact(data)?;
+            if let Some(section) = self
+                .sections
+                .iter()
+                .find(|section| (section.offset..section.end()).contains(&position))
+            {
+                section.encrypt(position, data);
+            }
+            sink(data)?;
+            position += len as u64;
+        }
+        Ok(())
+    }
+
+    /// The decompressed NCA body from `skip` bytes in.
+    fn decompressed<'r, R: Read + Seek>(
+        &'r self,
+        reader: &'r mut R,
+        skip: u64,
+    ) -> io::Result<Box<dyn Read + 'r>> {
+        match &self.blocks {
+            Some(blocks) => {
+                let index = (skip / blocks.size) as usize;
+                let mut blocks = BlockReader {
+                    reader,
+                    blocks,
+                    next: index,
+                    block: Vec::new(),
+                    position: 0,
+                };
+                blocks.load()?;
+                blocks.position = (skip % blocks.blocks.size) as usize;
+                Ok(Box::new(blocks))
+            }
+            None => {
+                reader.seek(SeekFrom::Start(self.body))?;
+                let mut decoder = zstd::stream::read::Decoder::new(reader)?.single_frame();
+                let skipped = io::copy(&mut (&mut decoder).take(skip), &mut io::sink())?;
+                if skipped < skip {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(Box::new(decoder))
+            }
+        }
+    }
+}
+
+/// Reads the blocks of a block-compressed NCZ as one stream.
+struct BlockReader<'r, R> {
+    reader: &'r mut R,
+    blocks: &'r Blocks,
+    /// Block loaded next.
+    next: usize,
+    block: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read + Seek> BlockReader<'_, R> {
+    /// Load the next block. Blocks that didn't shrink are stored uncompressed.
+    fn load(&mut self) -> io::Result<()> {
+        let (offset, compressed) = *self
+            .blocks
+            .compressed
+            .get(self.next)
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        let len = self.blocks.decompressed_len(self.next);
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut raw = vec![0u8; compressed.min(len) as usize];
+        self.reader.read_exact(&mut raw)?;
+        self.block = if compressed < len {
+            let block = zstd::bulk::decompress(&raw, len as usize)?;
+            if block.len() as u64 != len {
+                return Err(invalid("NCZ block decompressed to the wrong size"));
+            }
+            block
+        } else {
+            raw
+        };
+        self.next += 1;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for BlockReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.block.len() {
+            if self.next == self.blocks.compressed.len() {
+                return Ok(0);
+            }
+            self.load()?;
+        }
+        let len = buf.len().min(self.block.len() - self.position);
+        buf[..len].copy_from_slice(&self.block[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Copy `len` bytes from `offset` in `reader` to `sink`.
+fn copy<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    len: u64,
+    chunk: usize,
+    sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; chunk.min(len as usize)];
+    let mut left = len;
+    while left > 0 {
+        let data = &mut buf[..(left as usize).min(chunk)];
+        reader.read_exact(data)?;
+        sink(data)?;
+        left -= data.len() as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::container::content_entries;
+    use crate::container::tests::build_pfs0;
+
+    const KEY: [u8; 16] = [7; 16];
+    const COUNTER: [u8; 16] = [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    /// An NCA of a header, an unencrypted section, and an AES-CTR one; its body is
+    /// partly compressible and partly not, so blocks are both compressed and stored.
+    fn nca() -> (Vec<u8>, Vec<u8>, Vec<Section>) {
+        let header: Vec<u8> = (0..NCA_HEADER_SIZE).map(|i| (i % 251) as u8).collect();
+        let mut plain = vec![0x11; 0x3000];
+        plain.extend((0..0x9123u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8));
+        let sections = vec![
+            Section {
+                offset: NCA_HEADER_SIZE,
+                size: 0x3000,
+                ctr: None,
+            },
+            Section {
+                offset: NCA_HEADER_SIZE + 0x3000,
+                size: 0x9123,
+                ctr: Some((KEY, COUNTER)),
+            },
+        ];
+        let mut nca = header.clone();
+        nca.extend(&plain);
+        let (_, encrypted) = nca.split_at_mut((NCA_HEADER_SIZE + 0x3000) as usize);
+        sections[1].encrypt(NCA_HEADER_SIZE + 0x3000, encrypted);
+        (nca, plain, sections)
+    }
+
+    /// The NCZ of the NCA, solid or in 16 KiB blocks.
+    pub(crate) fn ncz(block_compressed: bool) -> (Vec<u8>, Vec<u8>) {
+        let (nca, plain, sections) = nca();
+        let mut ncz = nca[..NCA_HEADER_SIZE as usize].to_vec();
+        ncz.extend(SECTION_MAGIC);
+        ncz.extend((sections.len() as u64).to_le_bytes());
+        for section in &sections {
+            ncz.extend(section.offset.to_le_bytes());
+            ncz.extend(section.size.to_le_bytes());
+            ncz.extend(if section.ctr.is_some() { 3u64 } else { 1 }.to_le_bytes());
+            ncz.extend([0; 8]);
+            ncz.extend(KEY);
+            ncz.extend(COUNTER);
+        }
+        if block_compressed {
+            let blocks: Vec<_> = plain
+                .chunks(1 << 14)
+                .map(|block| {
+                    let compressed = zstd::bulk::compress(block, 3).unwrap_or_default();
+                    if compressed.len() < block.len() {
+                        compressed
+                    } else {
+                        block.to_vec()
+                    }
+                })
+                .collect();
+            ncz.extend(BLOCK_MAGIC);
+            ncz.extend([2, 1, 0, 14]);
+            ncz.extend((blocks.len() as u32).to_le_bytes());
+            ncz.extend((plain.len() as u64).to_le_bytes());
+            for block in &blocks {
+                ncz.extend((block.len() as u32).to_le_bytes());
+            }
+            ncz.extend(blocks.concat());
+        } else {
+            ncz.extend(zstd::bulk::compress(&plain, 3).unwrap_or_default());
+        }
+        (ncz, nca)
+    }
+
+    fn write_all(layout: &NspLayout, nsz: &[u8], start: u64, len: u64) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        layout.write_range(&mut Cursor::new(nsz), start, len, 0x1000, &mut |data| {
+            out.extend(data);
+            Ok(())
+        })?;
+        Ok(out)
+    }
+
+    #[test]
+    fn nsz_decompresses_to_the_original_nsp() -> Result<()> {
+        for block_compressed in [false, true] {
+            let (ncz, nca) = ncz(block_compressed);
+            let nsz = build_pfs0(&[("title.tik", b"ticket"), ("0123.ncz", &ncz)]);
+            let layout = NspLayout::read(&mut Cursor::new(&nsz))?;
+
+            let nsp = write_all(&layout, &nsz, 0, layout.len())?;
+            assert_eq!(nsp.len() as u64, layout.len());
+            let mut reader = Cursor::new(&nsp);
+            let entries = content_entries(&mut reader, Path::new("title.nsp"))
+                .ok_or_else(|| anyhow::anyhow!("not a PFS0"))?;
+            let files: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    let start = entry.offset as usize;
+                    (
+                        entry.name.as_str(),
+                        &nsp[start..start + entry.size as usize],
+                    )
+                })
+                .collect();
+            assert_eq!(
+                files,
+                [("title.tik", &b"ticket"[..]), ("0123.nca", &nca[..])]
+            );
+
+            // Ranges start anywhere, including mid-block and mid-AES-block.
+            for (start, len) in [(0, 10), (5, 0x4100), (0x4099, 0x5001), (0x7fff, 1 << 20)] {
+                let end = (start + len).min(nsp.len() as u64);
+                assert_eq!(
+                    write_all(&layout, &nsz, start, len)?,
+                    &nsp[start as usize..end as usize],
+                    "range {start}+{len} of block_compressed={block_compressed}"
+                );
+            }
+        }
+
+        let nsp = build_pfs0(&[("a.nca", b"x")]);
+        let read = NspLayout::read(&mut Cursor::new(&nsp));
+        assert!(matches!(read, Err(err) if err.kind() == io::ErrorKind::InvalidData));
+        Ok(())
+    }
+}
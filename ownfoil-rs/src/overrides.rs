@@ -0,0 +1,374 @@
+//! Per-title overrides set by the admin: a custom display name, a `hidden` flag, and
+//! tags and notes (usually imported from another library manager).
+//!
+//! Persisted to `<data_dir>/overrides.json`, keyed by uppercase title ID. Hidden titles
+//! are left out of every shop listing but stay on disk and downloadable by direct URL.
+//! Custom icons are stored with the other artwork overrides in `<data_dir>/artwork`.
+//!
+//! Single files can be hidden by relative path as well; those are persisted to
+//! `<data_dir>/hidden_paths.json`. Title IDs and paths listed under `hidden` in the
+//! config file are hidden too, but can only be changed there.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::annotations::Annotation;
+use crate::artwork::normalize_title_id;
+use crate::catalog::{derive_base_title_id, path_key, url_path, ContentFile};
+
+const OVERRIDES_FILE: &str = "overrides.json";
+const HIDDEN_PATHS_FILE: &str = "hidden_paths.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TitleOverride {
+    /// Name shown in the shop instead of the file name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Leave the title (and its updates and DLC) out of shop listings.
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+impl TitleOverride {
+    fn is_empty(&self) -> bool {
+        self == &TitleOverride::default()
+    }
+}
+
+/// Title IDs and relative paths hidden from the shop.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HiddenEntries {
+    pub title_ids: BTreeSet<String>,
+    /// Relative paths with `/` separators, as written.
+    pub paths: BTreeSet<String>,
+}
+
+impl HiddenEntries {
+    /// Sort config entries into title IDs and relative paths.
+    pub fn from_config(entries: &[String]) -> Self {
+        let mut hidden = Self::default();
+        for entry in entries.iter().map(|entry| entry.trim()) {
+            match normalize_title_id(entry) {
+                Some(title_id) => {
+                    hidden.title_ids.insert(title_id);
+                }
+                None if !entry.is_empty() => {
+                    hidden
+                        .paths
+                        .insert(url_path(Path::new(entry.trim_start_matches('/'))));
+                }
+                None => {}
+            }
+        }
+        hidden
+    }
+
+    fn hides_path(&self, key: &str) -> bool {
+        self.paths.iter().any(|path| path.to_lowercase() == key)
+    }
+}
+
+/// Point-in-time copy of every override, for filtering a listing.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct Overrides {
+    titles: BTreeMap<String, TitleOverride>,
+    /// Paths hidden through the admin API.
+    #[serde(skip)]
+    paths: BTreeSet<String>,
+    /// Entries hidden in the config file.
+    #[serde(skip)]
+    config: HiddenEntries,
+}
+
+impl Overrides {
+    /// Whether `file` is hidden by path, or belongs to a hidden title either directly or
+    /// through its base game.
+    pub fn hides(&self, file: &ContentFile) -> bool {
+        file.title_id
+            .as_deref()
+            .is_some_and(|id| self.is_hidden(id))
+            || derive_base_title_id(file.kind, file.title_id.as_deref())
+                .is_some_and(|base| self.is_hidden(&base))
+            || self.hides_path(&file.relative_path)
+    }
+
+    /// Whether exactly `title_id` is marked hidden.
+    pub fn is_hidden(&self, title_id: &str) -> bool {
+        self.titles.get(title_id).is_some_and(|entry| entry.hidden)
+            || self.config.title_ids.contains(title_id)
+    }
+
+    fn hides_path(&self, relative_path: &Path) -> bool {
+        if self.paths.is_empty() && self.config.paths.is_empty() {
+            return false;
+        }
+        let key = path_key(relative_path);
+        self.paths.iter().any(|path| path.to_lowercase() == key) || self.config.hides_path(&key)
+    }
+
+    /// Custom display name for exactly `title_id`.
+    pub fn name(&self, title_id: &str) -> Option<&str> {
+        self.titles.get(title_id)?.name.as_deref()
+    }
+
+    /// Titles and paths hidden through the admin API.
+    pub fn hidden(&self) -> HiddenEntries {
+        HiddenEntries {
+            title_ids: self
+                .titles
+                .iter()
+                .filter(|(_, entry)| entry.hidden)
+                .map(|(title_id, _)| title_id.clone())
+                .collect(),
+            paths: self.paths.clone(),
+        }
+    }
+
+    /// Titles and paths hidden in the config file.
+    pub fn config_hidden(&self) -> &HiddenEntries {
+        &self.config
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OverrideStore {
+    inner: Arc<RwLock<Overrides>>,
+    store_path: PathBuf,
+    paths_path: PathBuf,
+}
+
+impl OverrideStore {
+    /// Load overrides from `data_dir`, starting empty if the files are missing or invalid.
+    /// `config` entries are hidden on top of them.
+    pub fn load(data_dir: &Path, config: HiddenEntries) -> Self {
+        let store_path = data_dir.join(OVERRIDES_FILE);
+        let paths_path = data_dir.join(HIDDEN_PATHS_FILE);
+        let titles = read_json(&store_path).unwrap_or_default();
+        let paths = read_json(&paths_path).unwrap_or_default();
+        Self {
+            inner: Arc::new(RwLock::new(Overrides {
+                titles,
+                paths,
+                config,
+            })),
+            store_path,
+            paths_path,
+        }
+    }
+
+    pub async fn snapshot(&self) -> Overrides {
+        self.inner.read().await.clone()
+    }
+
+    /// Replace the override for `title_id`; an empty override removes it.
+    pub async fn set(&self, title_id: String, entry: TitleOverride) -> std::io::Result<()> {
+        let mut overrides = self.inner.write().await;
+        if entry.is_empty() {
+            overrides.titles.remove(&title_id);
+        } else {
+            overrides.titles.insert(title_id, entry);
+        }
+        self.save(&overrides).await
+    }
+
+    /// Set or clear the `hidden` flag of `title_id`, keeping its custom name. Returns
+    /// whether anything changed.
+    pub async fn set_hidden(&self, title_id: String, hidden: bool) -> std::io::Result<bool> {
+        let mut overrides = self.inner.write().await;
+        let mut entry = overrides.titles.get(&title_id).cloned().unwrap_or_default();
+        if entry.hidden == hidden {
+            return Ok(false);
+        }
+        entry.hidden = hidden;
+        if entry.is_empty() {
+            overrides.titles.remove(&title_id);
+        } else {
+            overrides.titles.insert(title_id, entry);
+        }
+        self.save(&overrides).await.map(|()| true)
+    }
+
+    /// Hide or show the file at `relative_path` (matched case-insensitively). Returns
+    /// whether anything changed.
+    pub async fn set_path_hidden(
+        &self,
+        relative_path: &Path,
+        hidden: bool,
+    ) -> std::io::Result<bool> {
+        let mut overrides = self.inner.write().await;
+        let key = path_key(relative_path);
+        let existing = overrides
+            .paths
+            .iter()
+            .find(|path| path.to_lowercase() == key)
+            .cloned();
+        match (existing, hidden) {
+            (None, true) => {
+                overrides.paths.insert(url_path(relative_path));
+            }
+            (Some(path), false) => {
+                overrides.paths.remove(&path);
+            }
+            _ => return Ok(false),
+        }
+        write_json(&self.paths_path, &overrides.paths)
+            .await
+            .map(|()| true)
+    }
+
+    /// Merge imported annotations: names and notes replace the current ones, tags are
+    /// added to them. Returns how many titles changed.
+    pub async fn annotate(&self, annotations: &[Annotation]) -> std::io::Result<usize> {
+        let mut overrides = self.inner.write().await;
+        let mut changed = 0;
+        for annotation in annotations {
+            let entry = overrides
+                .titles
+                .entry(annotation.title_id.clone())
+                .or_default();
+            let before = entry.clone();
+            if let Some(name) = &annotation.name {
+                entry.name = Some(name.clone());
+            }
+            if let Some(notes) = &annotation.notes {
+                entry.notes = Some(notes.clone());
+            }
+            entry.tags.extend(annotation.tags.iter().cloned());
+            if *entry != before {
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.save(&overrides).await?;
+        }
+        Ok(changed)
+    }
+
+    /// Drop the override for `title_id`. Returns whether one existed.
+    pub async fn remove(&self, title_id: &str) -> std::io::Result<bool> {
+        let mut overrides = self.inner.write().await;
+        if overrides.titles.remove(title_id).is_none() {
+            return Ok(false);
+        }
+        self.save(&overrides).await.map(|()| true)
+    }
+
+    async fn save(&self, overrides: &Overrides) -> std::io::Result<()> {
+        write_json(&self.store_path, overrides).await
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let raw = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&raw)
+        .map_err(
+            |err| warn!(path = %path.display(), error = %err, "ignoring invalid overrides file"),
+        )
+        .ok()
+}
+
+async fn write_json<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    let raw = serde_json::to_string_pretty(value).map_err(std::io::Error::other)?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let temp = path.with_extension("json.tmp");
+    tokio::fs::write(&temp, raw).await?;
+    tokio::fs::rename(&temp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{HiddenEntries, OverrideStore, TitleOverride};
+    use crate::catalog::{ContentFile, ContentKind};
+
+    fn file(title_id: &str, kind: ContentKind) -> ContentFile {
+        ContentFile {
+            title_id: Some(String::from(title_id)),
+            version: Some(0),
+            kind,
+            ..ContentFile::fixture(&format!("{title_id}.nsp"), 1)
+        }
+    }
+
+    #[tokio::test]
+    async fn hidden_base_hides_its_updates_and_survives_reload() -> Result<()> {
+        let dir = tempdir()?;
+        let store = OverrideStore::load(dir.path(), HiddenEntries::default());
+        store
+            .set(
+                String::from("0100ABCD12340000"),
+                TitleOverride {
+                    name: Some(String::from("My Game")),
+                    hidden: true,
+                    ..TitleOverride::default()
+                },
+            )
+            .await?;
+
+        let overrides = OverrideStore::load(dir.path(), HiddenEntries::default())
+            .snapshot()
+            .await;
+        assert!(overrides.hides(&file("0100ABCD12340000", ContentKind::Base)));
+        assert!(overrides.hides(&file("0100ABCD12340800", ContentKind::Update)));
+        assert!(!overrides.hides(&file("0100FFFF12340000", ContentKind::Base)));
+        assert_eq!(overrides.name("0100ABCD12340000"), Some("My Game"));
+
+        store
+            .set(String::from("0100ABCD12340000"), TitleOverride::default())
+            .await?;
+        assert!(!store.remove("0100ABCD12340000").await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paths_hide_case_insensitively_and_config_entries_apply() -> Result<()> {
+        let dir = tempdir()?;
+        let config = HiddenEntries::from_config(&[
+            String::from("0100bbbb00000000"),
+            String::from("/Extras/Demo.nsp"),
+        ]);
+        let store = OverrideStore::load(dir.path(), config.clone());
+        assert!(
+            store
+                .set_path_hidden(&PathBuf::from("Games/Secret.nsp"), true)
+                .await?
+        );
+
+        let overrides = OverrideStore::load(dir.path(), config).snapshot().await;
+        let mut secret = file("0100CCCC00000000", ContentKind::Base);
+        secret.relative_path = PathBuf::from("games/secret.NSP");
+        let mut demo = file("0100DDDD00000000", ContentKind::Base);
+        demo.relative_path = PathBuf::from("Extras/Demo.nsp");
+        assert!(overrides.hides(&secret));
+        assert!(overrides.hides(&demo));
+        assert!(overrides.hides(&file("0100BBBB00000800", ContentKind::Update)));
+        assert_eq!(
+            overrides.hidden().paths.into_iter().collect::<Vec<_>>(),
+            vec!["Games/Secret.nsp"]
+        );
+
+        assert!(
+            store
+                .set_path_hidden(&PathBuf::from("GAMES/SECRET.nsp"), false)
+                .await?
+        );
+        assert!(!store.snapshot().await.hides(&secret));
+        Ok(())
+    }
+}
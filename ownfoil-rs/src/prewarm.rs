@@ -0,0 +1,212 @@
+//! Artwork pre-warming: when a scan adds titles, their icons and banners are fetched into
+//! the artwork cache in the background, so the first shop browse afterwards is served
+//! locally instead of sending every new tile to a cold TitleDB CDN.
+//!
+//! Titles with an override are skipped; titles TitleDB has no icon for get their fallback
+//! icon looked up (see [`ArtworkProvider::icon`]). Titles already present when the server
+//! starts aren't pre-warmed; their art is cached as it is requested.
+
+use std::collections::BTreeSet;
+
+use futures_util::stream::{self, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+
+use crate::artwork::ArtworkProvider;
+use crate::catalog::{derive_base_title_id, Catalog};
+use crate::library::LibrarySet;
+use crate::titledb::TitleDb;
+
+/// Artwork fetched in parallel when `[artwork] prewarm_concurrency` is unset.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Pre-warm the artwork of the base titles each catalog rebuild adds, `concurrency`
+/// images at a time. Does nothing when `concurrency` is 0.
+pub fn spawn_artwork_prewarm(
+    library: LibrarySet,
+    titledb: TitleDb,
+    artwork: ArtworkProvider,
+    concurrency: usize,
+) {
+    if concurrency == 0 {
+        return;
+    }
+    let mut rebuilds = library.subscribe();
+    let catalog = library.catalog();
+    // Taken now, so titles of a scan that finishes before the task starts count as new.
+    let known = catalog
+        .try_read()
+        .ok()
+        .map(|catalog| base_title_ids(&catalog));
+    tokio::spawn(async move {
+        let mut known = match known {
+            Some(known) => known,
+            None => base_title_ids(&*catalog.read().await),
+        };
+        loop {
+            // Missed rebuilds don't matter: the catalog is compared as a whole.
+            if let Err(RecvError::Closed) = rebuilds.recv().await {
+                return;
+            }
+            let current = base_title_ids(&*catalog.read().await);
+            let added = current.difference(&known).cloned().collect::<Vec<_>>();
+            known = current;
+            if added.is_empty() {
+                continue;
+            }
+            let cached = prewarm(&titledb, &artwork, &added, concurrency).await;
+            info!(
+                titles = added.len(),
+                images = cached,
+                "artwork pre-warmed for new titles"
+            );
+        }
+    });
+}
+
+/// Fetch icons and banners for `title_ids`. Returns how many images are cached now.
+pub async fn prewarm(
+    titledb: &TitleDb,
+    artwork: &ArtworkProvider,
+    title_ids: &[String],
+    concurrency: usize,
+) -> usize {
+    // Owned futures: borrowing ones trip up `Send` inference in the spawned task.
+    stream::iter(title_ids.to_vec())
+        .map(|title_id| {
+            let (titledb, artwork) = (titledb.clone(), artwork.clone());
+            async move { prewarm_title(&titledb, &artwork, &title_id).await }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .fold(0, |total, cached| async move { total + cached })
+        .await
+}
+
+async fn prewarm_title(titledb: &TitleDb, artwork: &ArtworkProvider, title_id: &str) -> usize {
+    let info = titledb.lookup(title_id).await;
+    let remote = |url: Option<&String>| url.filter(|url| url.starts_with("http")).cloned();
+    let icon_url = remote(info.as_ref().and_then(|info| info.icon_url.as_ref()));
+    let banner_url = remote(info.as_ref().and_then(|info| info.banner_url.as_ref()));
+
+    let mut cached = 0;
+    if !artwork.has_icon_override(title_id).await {
+        let icon = match &icon_url {
+            Some(url) => artwork.prefetch(url).await,
+            None => artwork.icon(title_id).await.is_some(),
+        };
+        cached += usize::from(icon);
+    }
+    if let Some(url) = &banner_url {
+        if artwork.banner_override(title_id).await.is_none() {
+            cached += usize::from(artwork.prefetch(url).await);
+        }
+    }
+    cached
+}
+
+fn base_title_ids(catalog: &Catalog) -> BTreeSet<String> {
+    catalog
+        .files()
+        .iter()
+        .filter_map(|file| derive_base_title_id(file.kind, file.title_id.as_deref()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use axum::http::header::CONTENT_TYPE;
+    use axum::routing::get;
+    use axum::Router;
+    use tempfile::tempdir;
+
+    use super::spawn_artwork_prewarm;
+    use crate::artwork::ArtworkProvider;
+    use crate::config::{ArtworkConfig, TitleDbConfig};
+    use crate::library::LibrarySet;
+    use crate::titledb::{TitleDb, TitleInfo};
+
+    #[tokio::test]
+    async fn titles_added_by_a_scan_get_their_artwork_cached() -> Result<()> {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        let app = Router::new().route(
+            "/{image}",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { ([(CONTENT_TYPE, "image/jpeg")], "jpeg-bytes") }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let library_dir = tempdir()?;
+        let data = tempdir()?;
+        tokio::fs::write(
+            library_dir.path().join("Old [0100AAAA00000000][v0].nsp"),
+            b"old",
+        )
+        .await?;
+        let library = LibrarySet::new(vec![library_dir.path().to_path_buf()]);
+        library.rescan_all().await?;
+
+        let titledb = TitleDb::new(
+            TitleDbConfig {
+                enabled: false,
+                ..Default::default()
+            },
+            data.path().to_path_buf(),
+        );
+        let art = |title_id: &str| TitleInfo {
+            icon_url: Some(format!("http://{addr}/{title_id}.icon.jpg")),
+            banner_url: Some(format!("http://{addr}/{title_id}.banner.jpg")),
+            name: None,
+        };
+        titledb
+            .insert("0100AAAA00000000", art("0100AAAA00000000"))
+            .await;
+        titledb
+            .insert("0100BBBB00000000", art("0100BBBB00000000"))
+            .await;
+        let artwork = ArtworkProvider::new(ArtworkConfig::default(), data.path());
+        spawn_artwork_prewarm(library.clone(), titledb, artwork.clone(), 2);
+
+        // An update for a known title and a new base title.
+        tokio::fs::write(
+            library_dir
+                .path()
+                .join("Old [0100AAAA00000800][v65536].nsp"),
+            b"update",
+        )
+        .await?;
+        tokio::fs::write(
+            library_dir.path().join("New [0100BBBB00000000][v0].nsp"),
+            b"new",
+        )
+        .await?;
+        library.rescan_all().await?;
+
+        let banner = format!("http://{addr}/0100BBBB00000000.banner.jpg");
+        let icon = format!("http://{addr}/0100BBBB00000000.icon.jpg");
+        // Both are fetched at once, and may finish in either order.
+        for _ in 0..100 {
+            if artwork.cached(&banner).await.is_some() && artwork.cached(&icon).await.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(artwork.cached(&icon).await.is_some());
+        assert!(artwork.cached(&banner).await.is_some());
+        assert!(artwork
+            .cached(&format!("http://{addr}/0100AAAA00000000.icon.jpg"))
+            .await
+            .is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
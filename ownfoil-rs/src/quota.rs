@@ -0,0 +1,217 @@
+//! Storage quotas: budgets per content kind (`[quotas]`) and per library root (`quota_gb`
+//! on a `[[libraries]]` entry).
+//!
+//! Usage is the total size of the catalog's files of that kind or under that root. A URL
+//! import is refused when a budget that applies to the new file is already used up, and
+//! stopped when its size turns out to be more than what is left (see
+//! [`Quotas::allowance`]). Library stats list every budget with its usage.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::catalog::{Catalog, ContentKind};
+use crate::config::{LibraryRoot, QuotaConfig};
+use crate::remote::format_size;
+
+const GIB: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaScope {
+    Kind,
+    Root,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaUsage {
+    pub scope: QuotaScope,
+    /// Content kind (`update`, `dlc`, ...) or library root path.
+    pub name: String,
+    pub limit_bytes: u64,
+    pub used_bytes: u64,
+    pub remaining_bytes: u64,
+}
+
+impl fmt::Display for QuotaUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scope = match self.scope {
+            QuotaScope::Kind => "content",
+            QuotaScope::Root => "library root",
+        };
+        write!(
+            f,
+            "{} {scope} quota ({} of {} used)",
+            self.name,
+            format_size(self.used_bytes),
+            format_size(self.limit_bytes)
+        )
+    }
+}
+
+/// Configured budgets, in bytes.
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+    kinds: Vec<(ContentKind, u64)>,
+    roots: Vec<(PathBuf, u64)>,
+}
+
+impl Quotas {
+    pub fn new(config: &QuotaConfig, roots: &[LibraryRoot]) -> Self {
+        let kinds = [
+            (ContentKind::Base, config.base_gb),
+            (ContentKind::Update, config.update_gb),
+            (ContentKind::Dlc, config.dlc_gb),
+            (ContentKind::Homebrew, config.homebrew_gb),
+            (ContentKind::Unknown, config.unknown_gb),
+        ];
+        Self {
+            kinds: kinds
+                .into_iter()
+                .filter_map(|(kind, gb)| Some((kind, gb?.saturating_mul(GIB))))
+                .collect(),
+            roots: roots
+                .iter()
+                .filter_map(|root| Some((root.path.clone(), root.quota_gb?.saturating_mul(GIB))))
+                .collect(),
+        }
+    }
+
+    /// Every budget with its usage in `catalog`, kinds first.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub fn usage(&self, catalog: &Catalog) -> Vec<QuotaUsage> {
+        let kinds = self
+            .kinds
+            .iter()
+            .map(|(kind, limit)| kind_usage(catalog, *kind, *limit));
+        let roots = self
+            .roots
+            .iter()
+            .map(|(root, limit)| root_usage(catalog, root, *limit));
+        kinds.chain(roots).collect()
+    }
+
+    /// The budget with the least room left among those a new file of `kind` under `root`
+    /// counts against; `None` when neither has one.
+    pub fn allowance(
+        &self,
+        catalog: &Catalog,
+        root: &Path,
+        kind: ContentKind,
+    ) -> Option<QuotaUsage> {
+        let kinds = self
+            .kinds
+            .iter()
+            .filter(|(quota_kind, _)| *quota_kind == kind)
+            .map(|(kind, limit)| kind_usage(catalog, *kind, *limit));
+        let roots = self
+            .roots
+            .iter()
+            .filter(|(quota_root, _)| quota_root == root)
+            .map(|(root, limit)| root_usage(catalog, root, *limit));
+        kinds.chain(roots).min_by_key(|usage| usage.remaining_bytes)
+    }
+}
+
+fn kind_usage(catalog: &Catalog, kind: ContentKind, limit: u64) -> QuotaUsage {
+    let used = catalog
+        .files()
+        .iter()
+        .filter(|file| file.kind == kind)
+        .map(|file| file.size)
+        .sum();
+    usage(QuotaScope::Kind, kind_name(kind), limit, used)
+}
+
+fn root_usage(catalog: &Catalog, root: &Path, limit: u64) -> QuotaUsage {
+    let used = catalog
+        .files()
+        .iter()
+        .filter(|file| file.root == root)
+        .map(|file| file.size)
+        .sum();
+    usage(QuotaScope::Root, &root.display().to_string(), limit, used)
+}
+
+fn usage(scope: QuotaScope, name: &str, limit_bytes: u64, used_bytes: u64) -> QuotaUsage {
+    QuotaUsage {
+        scope,
+        name: name.to_string(),
+        limit_bytes,
+        used_bytes,
+        remaining_bytes: limit_bytes.saturating_sub(used_bytes),
+    }
+}
+
+fn kind_name(kind: ContentKind) -> &'static str {
+    match kind {
+        ContentKind::Base => "base",
+        ContentKind::Update => "update",
+        ContentKind::Dlc => "dlc",
+        ContentKind::Homebrew => "homebrew",
+        ContentKind::Unknown => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{QuotaScope, Quotas, GIB};
+    use crate::catalog::{Catalog, ContentFile, ContentKind};
+    use crate::config::{LibraryRoot, QuotaConfig};
+
+    fn file(root: &str, name: &str, kind: ContentKind, size: u64) -> ContentFile {
+        ContentFile {
+            root: PathBuf::from(root),
+            kind,
+            ..ContentFile::fixture(name, size)
+        }
+    }
+
+    #[test]
+    fn allowance_is_the_tightest_applicable_budget() {
+        let roots = [
+            LibraryRoot {
+                path: PathBuf::from("/ssd"),
+                scan_interval_seconds: 30,
+                priority: 0,
+                quota_gb: Some(10),
+            },
+            LibraryRoot {
+                path: PathBuf::from("/hdd"),
+                scan_interval_seconds: 30,
+                priority: 0,
+                quota_gb: None,
+            },
+        ];
+        let config = QuotaConfig {
+            update_gb: Some(3),
+            ..Default::default()
+        };
+        let quotas = Quotas::new(&config, &roots);
+        let catalog = Catalog::from_files(vec![
+            file("/ssd", "a.nsp", ContentKind::Base, 6 * GIB),
+            file("/ssd", "a-update.nsp", ContentKind::Update, GIB),
+            file("/hdd", "b-update.nsp", ContentKind::Update, GIB),
+        ]);
+
+        let usage = quotas.usage(&catalog);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].name, "update");
+        assert_eq!(usage[0].used_bytes, 2 * GIB);
+        assert_eq!(usage[1].scope, QuotaScope::Root);
+        assert_eq!(usage[1].remaining_bytes, 3 * GIB);
+
+        let ssd = PathBuf::from("/ssd");
+        let base = quotas.allowance(&catalog, &ssd, ContentKind::Base);
+        assert_eq!(base.map(|usage| usage.remaining_bytes), Some(3 * GIB));
+        let update = quotas.allowance(&catalog, &ssd, ContentKind::Update);
+        assert_eq!(update.map(|usage| usage.scope), Some(QuotaScope::Kind));
+        assert_eq!(
+            quotas.allowance(&catalog, &PathBuf::from("/hdd"), ContentKind::Dlc),
+            None
+        );
+    }
+}
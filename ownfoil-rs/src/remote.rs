@@ -0,0 +1,408 @@
+//! `ownfoil-rs remote`: a small client for a running server's admin API.
+//!
+//! Lets headless admins list the catalog, trigger scans, manage per-user shop tokens,
+//! watch jobs, and tail server events without hand-written `curl` calls. Output is a
+//! plain-text table by default, or the server's JSON with `--json`.
+
+use std::time::Duration;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{Method, StatusCode, Url};
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct RemoteArgs {
+    /// Server base URL.
+    #[arg(
+        long,
+        env = "OWNFOIL_URL",
+        default_value = "http://127.0.0.1:8465",
+        value_name = "URL"
+    )]
+    pub url: Url,
+    #[arg(long, short = 'u', env = "OWNFOIL_USER", value_name = "NAME")]
+    pub user: Option<String>,
+    #[arg(
+        long,
+        env = "OWNFOIL_PASSWORD",
+        hide_env_values = true,
+        value_name = "PASSWORD"
+    )]
+    pub password: Option<String>,
+    /// API token, sent as `Authorization: Bearer` instead of the user and password.
+    #[arg(
+        long,
+        env = "OWNFOIL_TOKEN",
+        hide_env_values = true,
+        value_name = "TOKEN"
+    )]
+    pub token: Option<String>,
+    /// Print the server's JSON instead of a table.
+    #[arg(long, global = true)]
+    pub json: bool,
+    #[command(subcommand)]
+    pub action: RemoteAction,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum RemoteAction {
+    /// List catalog files, optionally filtered with the `/api/search` query syntax.
+    Catalog {
+        #[arg(long, short = 's', value_name = "QUERY")]
+        search: Option<String>,
+    },
+    /// Rescan every library root and print what changed.
+    Scan,
+    /// List background jobs (replication, imports).
+    Jobs {
+        #[arg(long, value_name = "KIND")]
+        kind: Option<String>,
+    },
+    /// Manage per-user shop URLs.
+    Users {
+        #[command(subcommand)]
+        action: UsersAction,
+    },
+    /// Follow server events until interrupted.
+    Events {
+        #[arg(long, value_enum, default_value = "settings")]
+        stream: EventStream,
+    },
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum UsersAction {
+    /// List users and their shop paths.
+    List,
+    /// Issue a new shop token for a user, invalidating the old one.
+    Rotate { username: String },
+    /// Revoke a user's shop token.
+    Revoke { username: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EventStream {
+    /// Settings revisions saved from any admin session.
+    Settings,
+    /// TitleDB refresh progress.
+    Titledb,
+}
+
+impl EventStream {
+    fn path(self) -> &'static str {
+        match self {
+            EventStream::Settings => "/api/settings/events",
+            EventStream::Titledb => "/api/settings/titledb/progress",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RemoteError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("invalid URL: {0}")]
+    Url(String),
+    #[error("server answered {status}: {message}")]
+    Status { status: StatusCode, message: String },
+    #[error("server sent an unexpected response: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+struct RemoteClient {
+    base: Url,
+    http: reqwest::Client,
+    credentials: Option<(String, Option<String>)>,
+    token: Option<String>,
+}
+
+impl RemoteClient {
+    fn new(args: &RemoteArgs) -> Result<Self, RemoteError> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .user_agent(concat!(
+                "ownfoil-rs/",
+                env!("CARGO_PKG_VERSION"),
+                " (remote)"
+            ))
+            .build()?;
+        Ok(Self {
+            base: args.url.clone(),
+            http,
+            credentials: args.user.clone().map(|user| (user, args.password.clone())),
+            token: args.token.clone(),
+        })
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<reqwest::Response, RemoteError> {
+        let url = self
+            .base
+            .join(path)
+            .map_err(|err| RemoteError::Url(err.to_string()))?;
+        let mut request = self.http.request(method, url).query(query);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        } else if let Some((user, password)) = &self.credentials {
+            request = request.basic_auth(user, password.as_ref());
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|value| value["error"].as_str().map(String::from))
+            .unwrap_or(body);
+        Err(RemoteError::Status { status, message })
+    }
+
+    async fn json(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Value, RemoteError> {
+        let response = self.send(method, path, query).await?;
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+}
+
+/// Run one remote command, printing its result to stdout.
+pub async fn run(args: RemoteArgs) -> Result<(), RemoteError> {
+    let client = RemoteClient::new(&args)?;
+    let (value, rendered) = match &args.action {
+        RemoteAction::Catalog { search } => {
+            let value = match search {
+                Some(query) => {
+                    client
+                        .json(Method::GET, "/api/search", &[("q", query.as_str())])
+                        .await?
+                }
+                None => client.json(Method::GET, "/api/catalog", &[]).await?,
+            };
+            let rendered = render_catalog(&value);
+            (value, rendered)
+        }
+        RemoteAction::Scan => {
+            let value = client
+                .json(Method::POST, "/api/library/rescan", &[])
+                .await?;
+            let rendered = format!(
+                "{} files ({} added, {} removed, {} changed)\n",
+                value["files"], value["added"], value["removed"], value["changed"]
+            );
+            (value, rendered)
+        }
+        RemoteAction::Jobs { kind } => {
+            let query: Vec<_> = kind.iter().map(|kind| ("kind", kind.as_str())).collect();
+            let value = client.json(Method::GET, "/api/jobs", &query).await?;
+            let rendered = render_jobs(&value);
+            (value, rendered)
+        }
+        RemoteAction::Users { action } => {
+            let (method, path) = match action {
+                UsersAction::List => (Method::GET, String::from("/api/shop-tokens")),
+                UsersAction::Rotate { username } => (Method::POST, user_path(username)),
+                UsersAction::Revoke { username } => (Method::DELETE, user_path(username)),
+            };
+            let value = client.json(method, &path, &[]).await?;
+            let rendered = match action {
+                UsersAction::List => render_users(&value, &args.url),
+                UsersAction::Rotate { .. } => {
+                    render_users(&serde_json::json!({ "users": [value.clone()] }), &args.url)
+                }
+                UsersAction::Revoke { username } => format!("revoked shop token for {username}\n"),
+            };
+            (value, rendered)
+        }
+        RemoteAction::Events { stream } => return tail_events(&client, *stream).await,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else {
+        print!("{rendered}");
+    }
+    Ok(())
+}
+
+fn user_path(username: &str) -> String {
+    format!(
+        "/api/shop-tokens/{}",
+        utf8_percent_encode(username, NON_ALPHANUMERIC)
+    )
+}
+
+/// Print each server-sent event as one line until the server closes the stream.
+async fn tail_events(client: &RemoteClient, stream: EventStream) -> Result<(), RemoteError> {
+    let mut response = client.send(Method::GET, stream.path(), &[]).await?;
+    let mut parser = EventParser::default();
+    while let Some(chunk) = response.chunk().await? {
+        for line in parser.push(&chunk) {
+            println!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// Incremental `text/event-stream` parser that yields `event: data` lines.
+#[derive(Debug, Default)]
+struct EventParser {
+    buffer: String,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl EventParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=end).collect();
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    let data = self.data.join("\n");
+                    events.push(match self.event.take() {
+                        Some(event) => format!("{event}: {data}"),
+                        None => data,
+                    });
+                }
+                self.event = None;
+                self.data.clear();
+            } else if let Some(event) = line.strip_prefix("event:") {
+                self.event = Some(event.trim_start().to_string());
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data
+                    .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // Comments (`: ping`), ids, and retry hints are ignored.
+        }
+        events
+    }
+}
+
+fn render_catalog(value: &Value) -> String {
+    let entries = value["entries"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&format!(
+            "{:<16}  {:>8}  {:<7}  {:>10}  {}\n",
+            entry["title_id"].as_str().unwrap_or("-"),
+            entry["version"]
+                .as_u64()
+                .map_or_else(|| String::from("-"), |version| format!("v{version}")),
+            entry["kind"].as_str().unwrap_or("-"),
+            format_size(entry["size"].as_u64().unwrap_or(0)),
+            entry["name"].as_str().unwrap_or_default(),
+        ));
+    }
+    out.push_str(&format!("{} files\n", entries.len()));
+    out
+}
+
+fn render_jobs(value: &Value) -> String {
+    let jobs = value["jobs"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    let mut out = String::new();
+    for job in jobs {
+        let done = job["done_bytes"].as_u64().unwrap_or(0);
+        let total = job["total_bytes"].as_u64().unwrap_or(0);
+        let progress = done
+            .saturating_mul(100)
+            .checked_div(total)
+            .map_or_else(|| String::from("   -"), |percent| format!("{percent:>3}%"));
+        out.push_str(&format!(
+            "{}  {:<9}  {:<9}  {progress}  {}{}\n",
+            job["id"].as_str().unwrap_or_default(),
+            job["kind"].as_str().unwrap_or_default(),
+            job["status"].as_str().unwrap_or_default(),
+            job["target"].as_str().unwrap_or_default(),
+            job["message"]
+                .as_str()
+                .map(|message| format!(" ({message})"))
+                .unwrap_or_default(),
+        ));
+    }
+    if jobs.is_empty() {
+        out.push_str("no jobs\n");
+    }
+    out
+}
+
+fn render_users(value: &Value, base: &Url) -> String {
+    let users = value["users"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    let mut out = String::new();
+    for user in users {
+        let shop = user["path"]
+            .as_str()
+            .and_then(|path| base.join(path).ok())
+            .map_or_else(|| String::from("(no shop token)"), String::from);
+        out.push_str(&format!(
+            "{:<20}  {shop}\n",
+            user["username"].as_str().unwrap_or_default()
+        ));
+    }
+    out
+}
+
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for candidate in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = candidate;
+    }
+    format!("{size:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{format_size, render_catalog, EventParser};
+
+    #[test]
+    fn parses_events_split_across_chunks() {
+        let mut parser = EventParser::default();
+        assert!(parser.push(b"event: settings\ndata: {\"revi").is_empty());
+        assert_eq!(
+            parser.push(b"sion\":3}\n\n: ping\n\ndata: line one\ndata: line two\n\n"),
+            vec!["settings: {\"revision\":3}", "line one\nline two"]
+        );
+    }
+
+    #[test]
+    fn renders_catalog_rows() {
+        let value = json!({ "entries": [{
+            "title_id": "0100ABCD12340000",
+            "version": 0,
+            "kind": "base",
+            "size": 6_u64 << 30,
+            "name": "Game.nsp",
+        }]});
+        let rendered = render_catalog(&value);
+        assert!(rendered.starts_with("0100ABCD12340000        v0  base        6.0 GiB  Game.nsp\n"));
+        assert!(rendered.ends_with("1 files\n"));
+        assert_eq!(format_size(512), "512 B");
+    }
+}
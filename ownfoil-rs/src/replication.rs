@@ -0,0 +1,220 @@
+//! Replication: copies a title's files to a secondary root (e.g. an external drive).
+//!
+//! Each request runs as a job in the [`JobManager`]. Files are copied to a `.part`
+//! file, verified against the source by BLAKE3 hash, then renamed into place. Files
+//! already present in the mirror with matching contents are skipped.
+
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::hashing::hash_file;
+use crate::jobs::{JobHandle, JobManager};
+use crate::split::ContentReader;
+
+/// Job kind used for replication jobs.
+pub const JOB_KIND: &str = "replicate";
+
+const COPY_BUFFER: usize = 1 << 20;
+
+/// A library file to replicate, keeping its path relative to the library root.
+#[derive(Debug, Clone)]
+pub struct ReplicationSource {
+    pub source: PathBuf,
+    pub relative_path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicationSummary {
+    pub copied: usize,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplicationError {
+    #[error("failed to replicate {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("verification failed for {path}: copy does not match source")]
+    Mismatch { path: String },
+    #[error("replication task failed: {0}")]
+    Task(String),
+}
+
+/// Start a replication job for `title_id` and return its job ID.
+pub fn spawn_replication(
+    jobs: &JobManager,
+    mirror_root: PathBuf,
+    title_id: String,
+    files: Vec<ReplicationSource>,
+) -> String {
+    let total = files.iter().map(|file| file.size).sum();
+    let job = jobs.start(JOB_KIND, title_id.clone(), total);
+    let id = job.id().to_string();
+    tokio::spawn(async move {
+        match replicate_files(&mirror_root, files, &job).await {
+            Ok(summary) => {
+                info!(
+                    title_id = %title_id,
+                    copied = summary.copied,
+                    skipped = summary.skipped,
+                    "title replicated"
+                );
+                job.complete(Some(format!(
+                    "{} copied, {} already present",
+                    summary.copied, summary.skipped
+                )));
+            }
+            Err(err) => {
+                warn!(title_id = %title_id, error = %err, "title replication failed");
+                job.fail(err.to_string());
+            }
+        }
+    });
+    id
+}
+
+/// Copy and verify every file under `mirror_root`, reporting bytes to `job`.
+pub async fn replicate_files(
+    mirror_root: &Path,
+    files: Vec<ReplicationSource>,
+    job: &JobHandle,
+) -> Result<ReplicationSummary, ReplicationError> {
+    let mut summary = ReplicationSummary::default();
+    for file in files {
+        let destination = mirror_root.join(&file.relative_path);
+        let job = job.clone();
+        let copied = tokio::task::spawn_blocking(move || {
+            replicate_file(&file.source, &destination, file.size, &job)
+        })
+        .await
+        .map_err(|err| ReplicationError::Task(err.to_string()))??;
+        if copied {
+            summary.copied += 1;
+        } else {
+            summary.skipped += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Replicate one file. Returns `false` when an identical copy was already present.
+fn replicate_file(
+    source: &Path,
+    destination: &Path,
+    size: u64,
+    job: &JobHandle,
+) -> Result<bool, ReplicationError> {
+    let io_err = |path: &Path| {
+        let path = path.display().to_string();
+        move |source| ReplicationError::Io { path, source }
+    };
+
+    if std::fs::metadata(destination).is_ok_and(|meta| meta.len() == size)
+        && hash_file(source).map_err(io_err(source))?
+            == hash_file(destination).map_err(io_err(destination))?
+    {
+        job.add_progress(size);
+        return Ok(false);
+    }
+
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent).map_err(io_err(parent))?;
+    }
+    let partial = partial_path(destination);
+    let source_hash = copy_hashing(source, &partial, job).map_err(io_err(&partial))?;
+    let copy_hash = hash_file(&partial).map_err(io_err(&partial))?;
+    if source_hash != copy_hash {
+        let _ = std::fs::remove_file(&partial);
+        return Err(ReplicationError::Mismatch {
+            path: destination.display().to_string(),
+        });
+    }
+    std::fs::rename(&partial, destination).map_err(io_err(destination))?;
+    Ok(true)
+}
+
+/// Copy `source` to `destination`, returning the BLAKE3 hash of the bytes read.
+/// Split dumps are joined into a single file.
+fn copy_hashing(source: &Path, destination: &Path, job: &JobHandle) -> std::io::Result<String> {
+    let mut input = ContentReader::open(source)?;
+    let mut output = std::fs::File::create(destination)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; COPY_BUFFER];
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        output.write_all(&buf[..read])?;
+        job.add_progress(read as u64);
+    }
+    output.sync_all()?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// `<destination>.part`, where a file is written before it is renamed into place.
+pub(crate) fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(".part");
+    destination.with_file_name(name)
+}
+
+/// Whether `path` is a [`partial_path`], still being written.
+pub(crate) fn is_partial(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("part"))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{replicate_files, ReplicationSource, ReplicationSummary, JOB_KIND};
+    use crate::jobs::JobManager;
+
+    #[tokio::test]
+    async fn copies_verified_files_and_skips_existing_copies() -> Result<()> {
+        let library = tempdir()?;
+        let mirror = tempdir()?;
+        std::fs::create_dir(library.path().join("Game"))?;
+        let source = library.path().join("Game").join("game.nsp");
+        std::fs::write(&source, b"payload")?;
+        let files = vec![ReplicationSource {
+            source,
+            relative_path: "Game/game.nsp".into(),
+            size: 7,
+        }];
+
+        let jobs = JobManager::new();
+        let job = jobs.start(JOB_KIND, "0100ABCD12340000", 7);
+        let summary = replicate_files(mirror.path(), files.clone(), &job).await?;
+        assert_eq!(
+            summary,
+            ReplicationSummary {
+                copied: 1,
+                skipped: 0
+            }
+        );
+        assert_eq!(
+            std::fs::read(mirror.path().join("Game").join("game.nsp"))?,
+            b"payload"
+        );
+        assert!(!mirror.path().join("Game").join("game.nsp.part").exists());
+
+        let summary = replicate_files(mirror.path(), files, &job).await?;
+        assert_eq!(summary.skipped, 1);
+        Ok(())
+    }
+}
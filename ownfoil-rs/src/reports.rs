@@ -0,0 +1,434 @@
+//! Library reports: periodic summaries of new titles, storage growth, top downloads,
+//! and problems found.
+//!
+//! Each report is written to `<data_dir>/reports` as JSON and HTML, compared against the
+//! previous report for growth figures, and optionally POSTed to configured webhooks.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::catalog::{Catalog, ContentKind};
+use crate::config::{ReportSchedule, ReportsConfig};
+use crate::jobs::unix_now;
+use crate::stats::DownloadStats;
+
+const REPORTS_DIR: &str = "reports";
+const TOP_DOWNLOADS: usize = 10;
+/// How often the scheduler wakes up to check whether a report is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryReport {
+    pub generated_at: u64,
+    pub period_start: u64,
+    pub total_files: usize,
+    pub total_bytes: u64,
+    /// Change since the previous report (0 for the first report).
+    pub files_growth: i64,
+    pub bytes_growth: i64,
+    pub new_titles: Vec<ReportTitle>,
+    pub top_downloads: Vec<ReportDownload>,
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportTitle {
+    pub name: String,
+    pub title_id: Option<String>,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportDownload {
+    pub name: String,
+    pub downloads: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reporter {
+    catalog: Arc<RwLock<Catalog>>,
+    downloads: DownloadStats,
+    dir: PathBuf,
+    config: ReportsConfig,
+}
+
+impl Reporter {
+    pub fn new(
+        catalog: Arc<RwLock<Catalog>>,
+        downloads: DownloadStats,
+        data_dir: &Path,
+        config: ReportsConfig,
+    ) -> Self {
+        Self {
+            catalog,
+            downloads,
+            dir: data_dir.join(REPORTS_DIR),
+            config,
+        }
+    }
+
+    /// Build a report for the period since the previous one, store it, and deliver it
+    /// to the configured webhooks.
+    pub async fn generate(&self) -> std::io::Result<LibraryReport> {
+        let previous = self.latest().await;
+        let now = unix_now();
+        let period_start = previous.as_ref().map_or_else(
+            || now.saturating_sub(self.period().as_secs()),
+            |report| report.generated_at,
+        );
+        let report = {
+            let catalog = self.catalog.read().await;
+            build_report(
+                &catalog,
+                previous.as_ref(),
+                self.downloads.take(),
+                period_start,
+                now,
+            )
+        };
+        self.save(&report).await?;
+        info!(
+            files = report.total_files,
+            new_titles = report.new_titles.len(),
+            problems = report.problems.len(),
+            "library report generated"
+        );
+        self.deliver(&report).await;
+        Ok(report)
+    }
+
+    /// Most recent stored report, if any.
+    pub async fn latest(&self) -> Option<LibraryReport> {
+        let stamp = self.latest_stamp().await?;
+        let raw = tokio::fs::read_to_string(self.dir.join(format!("report-{stamp}.json")))
+            .await
+            .ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    /// HTML rendering of the most recent stored report, if any.
+    pub async fn latest_html(&self) -> Option<String> {
+        let stamp = self.latest_stamp().await?;
+        tokio::fs::read_to_string(self.dir.join(format!("report-{stamp}.html")))
+            .await
+            .ok()
+    }
+
+    fn period(&self) -> Duration {
+        self.config
+            .schedule
+            .unwrap_or(ReportSchedule::Weekly)
+            .period()
+    }
+
+    async fn latest_stamp(&self) -> Option<u64> {
+        let mut entries = tokio::fs::read_dir(&self.dir).await.ok()?;
+        let mut latest = None;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let stamp = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("report-"))
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|stamp| stamp.parse::<u64>().ok());
+            latest = latest.max(stamp);
+        }
+        latest
+    }
+
+    async fn save(&self, report: &LibraryReport) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let stem = format!("report-{}", report.generated_at);
+        let json = serde_json::to_string_pretty(report).map_err(std::io::Error::other)?;
+        tokio::fs::write(self.dir.join(format!("{stem}.json")), json).await?;
+        tokio::fs::write(self.dir.join(format!("{stem}.html")), render_html(report)).await
+    }
+
+    async fn deliver(&self, report: &LibraryReport) {
+        if self.config.webhooks.is_empty() {
+            return;
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .user_agent("ownfoil-rs/1.0 (library reports)")
+            .build()
+            .unwrap_or_default();
+        for url in &self.config.webhooks {
+            match client.post(url).json(report).send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    warn!(url = %url, status = %response.status(), "report webhook rejected")
+                }
+                Err(err) => warn!(url = %url, error = %err, "report webhook failed"),
+            }
+        }
+    }
+}
+
+/// Generate a report whenever the configured period has elapsed since the last one.
+pub fn spawn_report_scheduler(reporter: Reporter) {
+    let Some(schedule) = reporter.config.schedule else {
+        return;
+    };
+    info!(schedule = ?schedule, "library reports scheduled");
+    tokio::spawn(async move {
+        let started_at = unix_now();
+        loop {
+            let last = reporter
+                .latest()
+                .await
+                .map_or(started_at, |report| report.generated_at);
+            let due = last + schedule.period().as_secs();
+            let now = unix_now();
+            if now < due {
+                tokio::time::sleep(Duration::from_secs(due - now).min(CHECK_INTERVAL)).await;
+                continue;
+            }
+            if let Err(err) = reporter.generate().await {
+                warn!(error = %err, "library report failed");
+                tokio::time::sleep(CHECK_INTERVAL).await;
+            }
+        }
+    });
+}
+
+/// Summarize `catalog` for the period starting at `period_start`.
+pub fn build_report(
+    catalog: &Catalog,
+    previous: Option<&LibraryReport>,
+    downloads: Vec<(PathBuf, u64)>,
+    period_start: u64,
+    now: u64,
+) -> LibraryReport {
+    let files = catalog.files();
+    let total_files = files.len();
+    let total_bytes = files.iter().map(|file| file.size).sum::<u64>();
+
+    let new_titles = catalog
+        .recently_added()
+        .into_iter()
+        .filter(|file| matches!(file.kind, ContentKind::Base | ContentKind::Unknown))
+        .filter(|file| {
+            file.modified
+                .is_some_and(|modified| modified >= period_start)
+        })
+        .map(|file| ReportTitle {
+            name: file.name.clone(),
+            title_id: file.title_id.clone(),
+            size: file.size,
+        })
+        .collect();
+
+    let top_downloads = downloads
+        .into_iter()
+        .take(TOP_DOWNLOADS)
+        .map(|(path, downloads)| ReportDownload {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+            downloads,
+        })
+        .collect();
+
+    let mut problems = Vec::new();
+    let stale = files.iter().filter(|file| file.stale).count();
+    if stale > 0 {
+        problems.push(format!(
+            "{stale} file(s) on unavailable storage, kept from an earlier scan"
+        ));
+    }
+    let duplicates = catalog.duplicates_by_title_version().len();
+    if duplicates > 0 {
+        problems.push(format!(
+            "{duplicates} title version(s) present more than once"
+        ));
+    }
+    let collisions = catalog.case_collisions().len();
+    if collisions > 0 {
+        problems.push(format!(
+            "{collisions} path(s) differing only by letter case"
+        ));
+    }
+    let unidentified = files.iter().filter(|file| file.title_id.is_none()).count();
+    if unidentified > 0 {
+        problems.push(format!("{unidentified} file(s) without a title ID"));
+    }
+
+    LibraryReport {
+        generated_at: now,
+        period_start,
+        total_files,
+        total_bytes,
+        files_growth: previous.map_or(0, |prev| total_files as i64 - prev.total_files as i64),
+        bytes_growth: previous.map_or(0, |prev| total_bytes as i64 - prev.total_bytes as i64),
+        new_titles,
+        top_downloads,
+        problems,
+    }
+}
+
+pub fn render_html(report: &LibraryReport) -> String {
+    let mut html = String::from(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Library report</title></head><body>\n",
+    );
+    let _ = writeln!(
+        html,
+        "<h1>Library report</h1>\n<p>{} &ndash; {}</p>",
+        format_time(report.period_start),
+        format_time(report.generated_at)
+    );
+    let _ = writeln!(
+        html,
+        "<h2>Storage</h2>\n<p>{} files, {} bytes ({:+} files, {:+} bytes since the last report)</p>",
+        report.total_files, report.total_bytes, report.files_growth, report.bytes_growth
+    );
+
+    html.push_str("<h2>New titles</h2>\n");
+    if report.new_titles.is_empty() {
+        html.push_str("<p>None</p>\n");
+    } else {
+        html.push_str("<ul>\n");
+        for title in &report.new_titles {
+            let _ = writeln!(
+                html,
+                "<li>{} ({})</li>",
+                escape_html(&title.name),
+                escape_html(title.title_id.as_deref().unwrap_or("unknown"))
+            );
+        }
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str("<h2>Top downloads</h2>\n");
+    if report.top_downloads.is_empty() {
+        html.push_str("<p>None</p>\n");
+    } else {
+        html.push_str("<ol>\n");
+        for download in &report.top_downloads {
+            let _ = writeln!(
+                html,
+                "<li>{} &times; {}</li>",
+                escape_html(&download.name),
+                download.downloads
+            );
+        }
+        html.push_str("</ol>\n");
+    }
+
+    html.push_str("<h2>Problems</h2>\n");
+    if report.problems.is_empty() {
+        html.push_str("<p>None found</p>\n");
+    } else {
+        html.push_str("<ul>\n");
+        for problem in &report.problems {
+            let _ = writeln!(html, "<li>{}</li>", escape_html(problem));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn format_time(unix: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(unix)).to_string()
+}
+
+fn escape_html(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tokio::sync::RwLock;
+
+    use super::{build_report, render_html, Reporter};
+    use crate::catalog::{Catalog, ContentFile, ContentKind};
+    use crate::config::ReportsConfig;
+    use crate::stats::DownloadStats;
+
+    fn file(name: &str, modified: u64, title_id: Option<&str>) -> ContentFile {
+        ContentFile {
+            modified: Some(modified),
+            title_id: title_id.map(String::from),
+            version: Some(0),
+            kind: ContentKind::Base,
+            ..ContentFile::fixture(name, 10)
+        }
+    }
+
+    #[test]
+    fn report_lists_new_titles_downloads_and_problems() {
+        let catalog = Catalog::from_files(vec![
+            file("old.nsp", 100, Some("0100ABCD12340000")),
+            file("new <1>.nsp", 500, Some("0100ABCD12350000")),
+            file("mystery.nsp", 50, None),
+        ]);
+        let report = build_report(
+            &catalog,
+            None,
+            vec![(PathBuf::from("dir/old.nsp"), 3)],
+            400,
+            600,
+        );
+
+        assert_eq!(report.total_bytes, 30);
+        assert_eq!(report.new_titles.len(), 1);
+        assert_eq!(report.new_titles[0].name, "new <1>.nsp");
+        assert_eq!(report.top_downloads[0].name, "old.nsp");
+        assert_eq!(
+            report.problems,
+            vec![String::from("1 file(s) without a title ID")]
+        );
+        assert!(render_html(&report).contains("new &lt;1&gt;.nsp"));
+    }
+
+    #[tokio::test]
+    async fn reports_are_stored_and_compared_with_the_previous_one() -> Result<()> {
+        let data = tempdir()?;
+        let catalog = Arc::new(RwLock::new(Catalog::from_files(vec![file(
+            "a.nsp",
+            0,
+            Some("0100ABCD12340000"),
+        )])));
+        let reporter = Reporter::new(
+            Arc::clone(&catalog),
+            DownloadStats::new(),
+            data.path(),
+            ReportsConfig::default(),
+        );
+
+        let first = reporter.generate().await?;
+        assert_eq!(first.files_growth, 0);
+        assert!(reporter.latest_html().await.is_some());
+
+        *catalog.write().await = Catalog::from_files(vec![
+            file("a.nsp", 0, Some("0100ABCD12340000")),
+            file("b.nsp", 0, Some("0100ABCD12350000")),
+        ]);
+        // Reports are keyed by second; make sure the second one sorts after the first.
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let second = reporter.generate().await?;
+        assert_eq!(second.files_growth, 1);
+        assert_eq!(second.bytes_growth, 10);
+        assert_eq!(
+            reporter.latest().await.map(|report| report.generated_at),
+            Some(second.generated_at)
+        );
+        Ok(())
+    }
+}
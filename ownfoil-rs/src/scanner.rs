@@ -1,18 +1,31 @@
 //! Library scanner: recursively walks a directory for `.nsp`, `.xci`, `.nsz`, `.xcz` files.
 //!
-//! Runs in a blocking task to avoid blocking the async runtime. Parses title ID and
-//! version from filenames (e.g. `[0100D2F00D5C0000][v0]`).
+//! Runs in a blocking task to avoid blocking the async runtime; directories are listed by
+//! a small pool of worker threads. Reads title ID, version, and type from the container's
+//! file table when possible, falling back to filenames (e.g. `[0100D2F00D5C0000][v0]`)
+//! for anything the container does not expose. Split dumps (`Game.nsp/00`, `01`, ...)
+//! are indexed as a single file. Symlinks are skipped unless `scan.follow_symlinks` is set.
 
-use std::ffi::OsStr;
-use std::path::Path;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use thiserror::Error;
-use tracing::info;
-use walkdir::WalkDir;
+use tracing::{debug, info, warn};
 
+use crate::archive::{is_archive, ArchiveEntry};
 use crate::catalog::{
-    classify_title_id, parse_filename_metadata, to_display_title_id, ContentFile,
+    classify_title_id, is_homebrew_title_id, parse_filename_metadata, path_key,
+    to_display_title_id, ContentFile,
 };
+use crate::config::ScanConfig;
+use crate::container::read_container_metadata;
+use crate::metadata_cache::MetadataCache;
+use crate::split::SplitParts;
 
 #[derive(Debug, Error)]
 pub enum ScanError {
@@ -32,76 +45,176 @@ pub enum ScanError {
     NormalizePath { path: String },
 }
 
+/// Retries for subtrees that fail with transient I/O errors (e.g. SMB/NFS hiccups).
+const TRANSIENT_RETRIES: u32 = 3;
+/// First retry delay; doubles on each attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Result of scanning one library root.
+#[derive(Debug, Default)]
+pub struct ScanOutcome {
+    pub files: Vec<ContentFile>,
+    /// Subtrees (relative to the root) that kept failing with transient I/O errors.
+    /// Their previously known files should be kept and marked stale, not dropped.
+    pub unavailable: Vec<PathBuf>,
+}
+
 /// Recursively scan the library root for supported content files.
 ///
-/// Returns [`ContentFile`] entries with parsed title IDs.
-/// Runs in `spawn_blocking` to avoid blocking the async runtime.
-pub async fn scan_library(root: &Path) -> Result<Vec<ContentFile>, ScanError> {
+/// Returns [`ContentFile`] entries with parsed title IDs, plus any subtrees that could
+/// not be read after retrying with exponential backoff. Directories are walked by
+/// `config.concurrency` workers, optionally throttled to `config.files_per_second`.
+/// Unchanged files' metadata comes from `cache` when given, which is updated with what
+/// was parsed and saved afterwards. Runs in `spawn_blocking` to avoid blocking the async
+/// runtime.
+pub async fn scan_library(
+    root: &Path,
+    config: ScanConfig,
+    cache: Option<MetadataCache>,
+) -> Result<ScanOutcome, ScanError> {
     let root_path = root.to_path_buf();
     let path_display = root_path.display().to_string();
-    tokio::task::spawn_blocking(move || scan_library_sync(&root_path))
-        .await
-        .map_err(|e| ScanError::Walk {
-            path: path_display,
-            source: std::io::Error::other(e.to_string()),
-        })?
+    let walk_cache = cache.clone();
+    let outcome = tokio::task::spawn_blocking(move || {
+        scan_library_sync(&root_path, config, walk_cache.as_ref())
+    })
+    .await
+    .map_err(|e| ScanError::Walk {
+        path: path_display,
+        source: std::io::Error::other(e.to_string()),
+    })?;
+    if let Some(cache) = cache {
+        cache.save().await;
+    }
+    outcome
+}
+
+/// Whether a file of `size` bytes is probably an interrupted copy: empty, or under the
+/// configured `min_file_size`. Such files are kept out of the catalog.
+pub fn is_truncated(size: u64, config: &ScanConfig) -> bool {
+    size == 0 || size < config.min_file_size
 }
 
-fn scan_library_sync(root: &Path) -> Result<Vec<ContentFile>, ScanError> {
+/// Re-read a single file under `root`. Returns `None` if it no longer exists or is no
+/// longer supported content.
+pub async fn scan_file(
+    root: &Path,
+    relative_path: &Path,
+) -> Result<Option<ContentFile>, ScanError> {
+    let root = root.to_path_buf();
+    let path = root.join(relative_path);
+    let path_display = path.display().to_string();
+    tokio::task::spawn_blocking(move || {
+        if !is_supported_content(&path) && !is_archive(&path) {
+            return Ok(None);
+        }
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() && is_archive(&path) => {
+                match ArchiveEntry::find(&path) {
+                    Ok(Some(entry)) => content_file_from_path(
+                        &root,
+                        &path,
+                        Some(entry.name),
+                        entry.size,
+                        metadata.modified().ok(),
+                    )
+                    .map(Some),
+                    Ok(None) => Ok(None),
+                    Err(source) => Err(ScanError::Metadata {
+                        path: path.display().to_string(),
+                        source,
+                    }),
+                }
+            }
+            Ok(metadata) if metadata.is_file() => {
+                content_file_from_path(&root, &path, None, metadata.len(), metadata.modified().ok())
+                    .map(Some)
+            }
+            Ok(metadata) if metadata.is_dir() => match SplitParts::detect(&path) {
+                Ok(Some(parts)) => {
+                    content_file_from_path(&root, &path, None, parts.len(), parts.modified())
+                        .map(Some)
+                }
+                Ok(None) => Ok(None),
+                Err(source) => Err(ScanError::Metadata {
+                    path: path.display().to_string(),
+                    source,
+                }),
+            },
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(ScanError::Metadata {
+                path: path.display().to_string(),
+                source,
+            }),
+        }
+    })
+    .await
+    .map_err(|e| ScanError::Walk {
+        path: path_display,
+        source: std::io::Error::other(e.to_string()),
+    })?
+}
+
+fn scan_library_sync(
+    root: &Path,
+    config: ScanConfig,
+    cache: Option<&MetadataCache>,
+) -> Result<ScanOutcome, ScanError> {
     let started_at = std::time::Instant::now();
     if !root.exists() {
         return Err(ScanError::MissingRoot(root.display().to_string()));
     }
 
-    let mut out = Vec::new();
-
-    for entry in WalkDir::new(root)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if !entry.file_type().is_file() {
-            continue;
-        }
+    let throttle = config.files_per_second.map(Throttle::new);
+    let walk = |starts: Vec<PathBuf>| {
+        Walker::new(root, starts, throttle.as_ref(), cache, &config).run(config.concurrency.max(1))
+    };
+    let (mut out, mut pending) = walk(vec![root.to_path_buf()])?;
 
-        if !is_supported_content(path) {
-            continue;
+    let mut delay = RETRY_BASE_DELAY;
+    for attempt in 1..=TRANSIENT_RETRIES {
+        if pending.is_empty() {
+            break;
         }
+        warn!(
+            root = %root.display(),
+            subtrees = pending.len(),
+            attempt,
+            delay_ms = delay.as_millis(),
+            "transient i/o errors during scan; retrying"
+        );
+        std::thread::sleep(delay);
+        delay = delay.saturating_mul(2);
+        let (files, still_failing) = walk(pending)?;
+        out.extend(files);
+        pending = still_failing;
+    }
 
-        let metadata = std::fs::metadata(path).map_err(|source| ScanError::Metadata {
-            path: path.display().to_string(),
-            source,
-        })?;
+    // Workers finish in any order; keep results stable for dedup and logging.
+    out.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    dedupe_paths(&mut out);
 
-        let relative_path = path
-            .strip_prefix(root)
-            .map(Path::to_path_buf)
-            .map_err(|_| ScanError::NormalizePath {
-                path: path.display().to_string(),
-            })?;
+    if let Some(cache) = cache {
+        // Subtrees that couldn't be read keep their entries for the next scan.
+        let seen = out
+            .iter()
+            .map(|file| root.join(&file.relative_path))
+            .chain(pending.iter().cloned())
+            .collect();
+        cache.prune(root, &seen);
+    }
 
-        let name = relative_path
-            .file_name()
-            .and_then(OsStr::to_str)
-            .map(String::from)
-            .unwrap_or_else(|| relative_path.display().to_string());
-
-        let parsed_name = parse_filename_metadata(&name);
-        let rel = relative_path.to_string_lossy();
-        let parsed_path = parse_filename_metadata(&rel);
-
-        let title_id = to_display_title_id(parsed_name.title_id.or(parsed_path.title_id));
-        let kind = classify_title_id(title_id.as_deref());
-
-        out.push(ContentFile {
-            relative_path,
-            name,
-            size: metadata.len(),
-            title_id,
-            version: parsed_name.version.or(parsed_path.version),
-            kind,
-        });
+    let unavailable = pending
+        .iter()
+        .filter_map(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect::<Vec<_>>();
+    if !unavailable.is_empty() {
+        warn!(
+            root = %root.display(),
+            subtrees = ?unavailable,
+            "subtrees unavailable after retries; keeping previous entries as stale"
+        );
     }
 
     let with_title_id = out.iter().filter(|file| file.title_id.is_some()).count();
@@ -109,11 +222,544 @@ fn scan_library_sync(root: &Path) -> Result<Vec<ContentFile>, ScanError> {
         root = %root.display(),
         files = out.len(),
         with_title_id,
+        concurrency = config.concurrency,
         elapsed_ms = started_at.elapsed().as_millis(),
         "library scan finished"
     );
 
-    Ok(out)
+    Ok(ScanOutcome {
+        files: out,
+        unavailable,
+    })
+}
+
+/// Spaces file inspections evenly so a scan stays under a files-per-second budget.
+#[derive(Debug)]
+struct Throttle {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(files_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / files_per_second.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn wait(&self) {
+        let now = Instant::now();
+        let slot = {
+            let mut next = self.next.lock().unwrap_or_else(|p| p.into_inner());
+            let slot = (*next).max(now);
+            *next = slot + self.interval;
+            slot
+        };
+        std::thread::sleep(slot.saturating_duration_since(now));
+    }
+}
+
+/// Directories waiting to be listed, shared by the scan workers.
+#[derive(Debug, Default)]
+struct WalkQueue {
+    dirs: Vec<PathBuf>,
+    /// Workers currently listing a directory (and so possibly adding more).
+    busy: usize,
+    aborted: bool,
+}
+
+/// What one worker found: content files and paths that failed transiently.
+#[derive(Debug, Default)]
+struct WalkResult {
+    files: Vec<ContentFile>,
+    failed: Vec<PathBuf>,
+}
+
+/// Walks directories under `root` with a pool of worker threads.
+struct Walker<'a> {
+    root: &'a Path,
+    starts: Vec<PathBuf>,
+    throttle: Option<&'a Throttle>,
+    cache: Option<&'a MetadataCache>,
+    /// Also index single-title `.zip` archives.
+    archives: bool,
+    /// The root's trash directory, which is skipped.
+    trash: PathBuf,
+    /// File names that mark a directory (and everything below it) as not to be scanned.
+    ignore_markers: Vec<OsString>,
+    follow_symlinks: bool,
+    /// Directories already queued, by identity rather than path; only tracked when
+    /// following symlinks.
+    visited: Mutex<HashSet<DirId>>,
+    queue: Mutex<WalkQueue>,
+    ready: Condvar,
+}
+
+impl<'a> Walker<'a> {
+    fn new(
+        root: &'a Path,
+        starts: Vec<PathBuf>,
+        throttle: Option<&'a Throttle>,
+        cache: Option<&'a MetadataCache>,
+        config: &ScanConfig,
+    ) -> Self {
+        Self {
+            root,
+            starts,
+            throttle,
+            cache,
+            archives: config.archives,
+            trash: root.join(&config.trash_dir),
+            ignore_markers: config.ignore_markers.iter().map(OsString::from).collect(),
+            follow_symlinks: config.follow_symlinks,
+            visited: Mutex::new(HashSet::new()),
+            queue: Mutex::new(WalkQueue::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Walk every start path (a directory or a single file). Returns content files and
+    /// paths that failed with transient I/O errors.
+    fn run(mut self, workers: usize) -> Result<(Vec<ContentFile>, Vec<PathBuf>), ScanError> {
+        let mut seed = WalkResult::default();
+        for start in std::mem::take(&mut self.starts) {
+            match std::fs::metadata(&start) {
+                Ok(meta) if meta.is_dir() => {
+                    if (start == self.root || !self.inspect_split(&start, &mut seed)?)
+                        && self.first_visit(&start)
+                    {
+                        self.lock().dirs.push(start);
+                    }
+                }
+                Ok(_) => self.inspect_file(&start, &mut seed)?,
+                Err(err) if is_transient(&err) => seed.failed.push(start),
+                Err(err) => {
+                    debug!(path = %start.display(), error = %err, "skipping unreadable path")
+                }
+            }
+        }
+
+        let results = std::thread::scope(|scope| {
+            let handles = (0..workers)
+                .map(|_| scope.spawn(|| self.work()))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or_else(|_| {
+                        Err(ScanError::Walk {
+                            path: self.root.display().to_string(),
+                            source: std::io::Error::other("scan worker panicked"),
+                        })
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut files = seed.files;
+        let mut failed = seed.failed;
+        for result in results {
+            let result = result?;
+            files.extend(result.files);
+            failed.extend(result.failed);
+        }
+        Ok((files, failed))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, WalkQueue> {
+        self.queue.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn work(&self) -> Result<WalkResult, ScanError> {
+        let mut result = WalkResult::default();
+        while let Some(dir) = self.next_dir() {
+            let listed = self.list_dir(&dir, &mut result);
+            let mut queue = self.lock();
+            queue.busy -= 1;
+            if let Err(err) = listed {
+                queue.aborted = true;
+                self.ready.notify_all();
+                return Err(err);
+            }
+            if queue.busy == 0 && queue.dirs.is_empty() {
+                self.ready.notify_all();
+            }
+        }
+        Ok(result)
+    }
+
+    /// Next directory to list, or `None` once the queue is drained and no worker can
+    /// add more.
+    fn next_dir(&self) -> Option<PathBuf> {
+        let mut queue = self.lock();
+        loop {
+            if queue.aborted {
+                return None;
+            }
+            if let Some(dir) = queue.dirs.pop() {
+                queue.busy += 1;
+                return Some(dir);
+            }
+            if queue.busy == 0 {
+                return None;
+            }
+            queue = self.ready.wait(queue).unwrap_or_else(|p| p.into_inner());
+        }
+    }
+
+    fn list_dir(&self, dir: &Path, result: &mut WalkResult) -> Result<(), ScanError> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                if is_transient(&err) {
+                    result.failed.push(dir.to_path_buf());
+                } else {
+                    debug!(path = %dir.display(), error = %err, "skipping unreadable path");
+                }
+                return Ok(());
+            }
+        };
+
+        // Read the whole listing first: a marker file anywhere in it skips the directory.
+        let mut listed = Vec::new();
+        for entry in entries {
+            match entry {
+                Ok(entry) => listed.push(entry),
+                Err(err) => {
+                    // The listing broke off; retry the whole directory (repeats are deduped).
+                    if is_transient(&err) {
+                        result.failed.push(dir.to_path_buf());
+                    } else {
+                        debug!(path = %dir.display(), error = %err, "skipping unreadable path");
+                    }
+                    break;
+                }
+            }
+        }
+        if let Some(marker) = listed
+            .iter()
+            .find(|entry| self.ignore_markers.contains(&entry.file_name()))
+        {
+            debug!(
+                path = %dir.display(),
+                marker = ?marker.file_name(),
+                "skipping directory with ignore marker"
+            );
+            return Ok(());
+        }
+
+        let mut subdirs = Vec::new();
+        for entry in listed {
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(err) if is_transient(&err) => {
+                    result.failed.push(path);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let file_type = if file_type.is_symlink() && self.follow_symlinks {
+                match std::fs::metadata(&path) {
+                    Ok(metadata) => metadata.file_type(),
+                    Err(err) if is_transient(&err) => {
+                        result.failed.push(path);
+                        continue;
+                    }
+                    Err(err) => {
+                        debug!(path = %path.display(), error = %err, "skipping broken symlink");
+                        continue;
+                    }
+                }
+            } else {
+                file_type
+            };
+            if file_type.is_dir() {
+                if path == self.trash {
+                    continue;
+                }
+                if !self.inspect_split(&path, result)? && self.first_visit(&path) {
+                    subdirs.push(path);
+                }
+            } else if file_type.is_file()
+                && (is_supported_content(&path) || (self.archives && is_archive(&path)))
+            {
+                self.inspect_file(&path, result)?;
+            }
+        }
+
+        if !subdirs.is_empty() {
+            self.lock().dirs.extend(subdirs);
+            self.ready.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Whether `dir` has not been queued yet under any path. Without symlinks every
+    /// directory is reached exactly once, so this only tracks anything when following them.
+    fn first_visit(&self, dir: &Path) -> bool {
+        if !self.follow_symlinks {
+            return true;
+        }
+        match dir_identity(dir) {
+            Ok(id) => {
+                let first = self
+                    .visited
+                    .lock()
+                    .unwrap_or_else(|p| p.into_inner())
+                    .insert(id);
+                if !first {
+                    debug!(path = %dir.display(), "directory already walked; skipping symlink loop or alias");
+                }
+                first
+            }
+            // Listing it will fail the same way and is handled there.
+            Err(_) => true,
+        }
+    }
+
+    fn inspect_file(&self, path: &Path, result: &mut WalkResult) -> Result<(), ScanError> {
+        if let Some(throttle) = self.throttle {
+            throttle.wait();
+        }
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(source) if is_transient(&source) => {
+                result.failed.push(path.to_path_buf());
+                return Ok(());
+            }
+            Err(source) if source.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(source) => {
+                return Err(ScanError::Metadata {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        };
+        if is_archive(path) {
+            return self.inspect_archive(path, &metadata, result);
+        }
+        result
+            .files
+            .push(self.content_file(path, metadata.len(), metadata.modified().ok())?);
+        Ok(())
+    }
+
+    /// Index a zip archive under the name and size of the single title inside it.
+    fn inspect_archive(
+        &self,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+        result: &mut WalkResult,
+    ) -> Result<(), ScanError> {
+        match ArchiveEntry::find(path) {
+            Ok(Some(entry)) => {
+                result.files.push(content_file_from_path(
+                    self.root,
+                    path,
+                    Some(entry.name),
+                    entry.size,
+                    metadata.modified().ok(),
+                )?);
+            }
+            Ok(None) => {
+                debug!(path = %path.display(), "archive does not hold a single title; skipped")
+            }
+            Err(err) if is_transient(&err) => result.failed.push(path.to_path_buf()),
+            Err(err) => debug!(path = %path.display(), error = %err, "unreadable archive skipped"),
+        }
+        Ok(())
+    }
+
+    /// Index `dir` as one file if it is a split dump. Returns `false` for an ordinary
+    /// directory, which should be walked instead.
+    fn inspect_split(&self, dir: &Path, result: &mut WalkResult) -> Result<bool, ScanError> {
+        if !is_supported_content(dir) {
+            return Ok(false);
+        }
+        let parts = match SplitParts::detect(dir) {
+            Ok(Some(parts)) => parts,
+            Ok(None) => return Ok(false),
+            Err(err) if is_transient(&err) => {
+                result.failed.push(dir.to_path_buf());
+                return Ok(true);
+            }
+            Err(err) => {
+                debug!(path = %dir.display(), error = %err, "skipping unreadable path");
+                return Ok(true);
+            }
+        };
+        if let Some(throttle) = self.throttle {
+            throttle.wait();
+        }
+        result
+            .files
+            .push(self.content_file(dir, parts.len(), parts.modified())?);
+        Ok(true)
+    }
+
+    /// Catalog entry for `path`, taken from the metadata cache when the file is unchanged.
+    fn content_file(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+    ) -> Result<ContentFile, ScanError> {
+        let Some(cache) = self.cache else {
+            return content_file_from_path(self.root, path, None, size, modified);
+        };
+        if let Some(parsed) = cache.get(path, size, modified) {
+            return Ok(ContentFile {
+                root: self.root.to_path_buf(),
+                relative_path: relative_to(self.root, path)?,
+                name: parsed.name,
+                size,
+                modified: unix_seconds(modified),
+                title_id: parsed.title_id,
+                version: parsed.version,
+                kind: parsed.kind,
+                hash: None,
+                stale: false,
+                verification: None,
+            });
+        }
+        let file = content_file_from_path(self.root, path, None, size, modified)?;
+        cache.insert(path, &file, modified);
+        Ok(file)
+    }
+}
+
+/// A directory's identity regardless of the path (or symlink) it was reached through.
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(unix)]
+fn dir_identity(dir: &Path) -> std::io::Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(dir)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_identity(dir: &Path) -> std::io::Result<DirId> {
+    std::fs::canonicalize(dir)
+}
+
+/// Errors worth retrying: interrupted or timed-out calls, and the `EIO` / `ESTALE` an
+/// SMB or NFS mount returns while it reconnects. Anything else, like "gone" or "not
+/// allowed", won't heal on its own within a scan.
+fn is_transient(error: &std::io::Error) -> bool {
+    if matches!(
+        error.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::StaleNetworkFileHandle
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use rustix::io::Errno;
+        error.raw_os_error() == Some(Errno::IO.raw_os_error())
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+/// Drop repeated entries: a directory that failed mid-listing may have yielded some files
+/// before the retry, and case-insensitive shares (SMB, Windows) can list the same file
+/// under paths differing only by case. Same-size case variants are treated as one file;
+/// different contents are kept and reported as a collision.
+fn dedupe_paths(files: &mut Vec<ContentFile>) {
+    let mut seen: HashMap<String, (PathBuf, u64)> = HashMap::new();
+    files.retain(|file| match seen.entry(path_key(&file.relative_path)) {
+        Entry::Vacant(slot) => {
+            slot.insert((file.relative_path.clone(), file.size));
+            true
+        }
+        Entry::Occupied(slot) => {
+            let (path, size) = slot.get();
+            if *path == file.relative_path || *size == file.size {
+                debug!(path = %file.relative_path.display(), "duplicate listing skipped");
+                return false;
+            }
+            warn!(
+                path = %file.relative_path.display(),
+                other = %path.display(),
+                "paths differ only by case; downloads may be ambiguous on case-insensitive filesystems"
+            );
+            true
+        }
+    });
+}
+
+/// Build the catalog entry for `path`. `name` overrides the displayed file name (the
+/// title inside an archive); title ID and version are parsed from it.
+fn content_file_from_path(
+    root: &Path,
+    path: &Path,
+    name: Option<String>,
+    size: u64,
+    modified: Option<SystemTime>,
+) -> Result<ContentFile, ScanError> {
+    let relative_path = relative_to(root, path)?;
+
+    let name = name.unwrap_or_else(|| {
+        relative_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .map(String::from)
+            .unwrap_or_else(|| relative_path.display().to_string())
+    });
+
+    let header = read_container_metadata(path).unwrap_or_default();
+    let parsed_name = parse_filename_metadata(&name);
+    let rel = relative_path.to_string_lossy();
+    let parsed_path = parse_filename_metadata(&rel);
+
+    let title_id = header
+        .title_id
+        .or_else(|| to_display_title_id(parsed_name.title_id.or(parsed_path.title_id)));
+    // Forwarders carry an "application" CNMT, so the title ID range decides first.
+    let kind = match header.kind {
+        Some(kind) if !title_id.as_deref().is_some_and(is_homebrew_title_id) => kind,
+        _ => classify_title_id(title_id.as_deref()),
+    };
+
+    Ok(ContentFile {
+        root: root.to_path_buf(),
+        relative_path,
+        name,
+        size,
+        modified: unix_seconds(modified),
+        title_id,
+        version: header
+            .version
+            .or(parsed_name.version)
+            .or(parsed_path.version),
+        kind,
+        hash: None,
+        stale: false,
+        verification: None,
+    })
+}
+
+fn relative_to(root: &Path, path: &Path) -> Result<PathBuf, ScanError> {
+    path.strip_prefix(root)
+        .map(Path::to_path_buf)
+        .map_err(|_| ScanError::NormalizePath {
+            path: path.display().to_string(),
+        })
+}
+
+fn unix_seconds(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_secs())
 }
 
 pub fn is_supported_content(path: &Path) -> bool {
@@ -130,15 +776,44 @@ pub fn is_supported_content(path: &Path) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use anyhow::Result;
     use tempfile::tempdir;
     use tokio::fs;
+    use zip::CompressionMethod;
+
+    use crate::archive::tests::write_zip;
+    use crate::catalog::{ContentFile, ContentKind};
+    use crate::config::ScanConfig;
+    use crate::container::tests::build_pfs0;
+    use crate::metadata_cache::MetadataCache;
+
+    use super::{dedupe_paths, is_supported_content, is_transient, scan_library};
 
-    use crate::catalog::ContentKind;
+    #[test]
+    fn transient_errors_exclude_missing_and_denied() {
+        use std::io::{Error, ErrorKind};
 
-    use super::{is_supported_content, scan_library};
+        assert!(is_transient(&Error::from(ErrorKind::TimedOut)));
+        assert!(is_transient(&Error::from(ErrorKind::Interrupted)));
+        assert!(!is_transient(&Error::from(ErrorKind::NotFound)));
+        assert!(!is_transient(&Error::from(ErrorKind::PermissionDenied)));
+        assert!(!is_transient(&Error::from(ErrorKind::NotADirectory)));
+        assert!(!is_transient(&Error::other("corrupt archive")));
+        #[cfg(unix)]
+        {
+            use rustix::io::Errno;
+            for errno in [Errno::IO, Errno::STALE] {
+                assert!(is_transient(&Error::from_raw_os_error(
+                    errno.raw_os_error()
+                )));
+            }
+            assert!(!is_transient(&Error::from_raw_os_error(
+                Errno::NOENT.raw_os_error()
+            )));
+        }
+    }
 
     #[test]
     fn supported_extensions() {
@@ -161,7 +836,9 @@ mod tests {
         }
         fs::write(&nested, b"dummy").await?;
 
-        let files = scan_library(dir.path()).await?;
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
         assert_eq!(files.len(), 1);
         let file = &files[0];
         assert_eq!(file.title_id.as_deref(), Some("0100ABCD12341001"));
@@ -169,6 +846,85 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn scan_library_skips_the_trash_directory() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join(".trash/1f2e/Games")).await?;
+        fs::write(dir.path().join(".trash/1f2e/Games/old.nsp"), b"dummy").await?;
+        fs::create_dir(dir.path().join("Games")).await?;
+        fs::write(dir.path().join("Games/.trash.nsp"), b"dummy").await?;
+
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].relative_path, PathBuf::from("Games/.trash.nsp"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_library_skips_directories_with_an_ignore_marker() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("WIP/Nested")).await?;
+        fs::write(dir.path().join("WIP/.nomedia"), b"").await?;
+        fs::write(dir.path().join("WIP/half.nsp"), b"dummy").await?;
+        fs::write(dir.path().join("WIP/Nested/deeper.nsp"), b"dummy").await?;
+        fs::create_dir(dir.path().join("Custom")).await?;
+        fs::write(dir.path().join("Custom/skip-me"), b"").await?;
+        fs::write(dir.path().join("Custom/game.nsp"), b"dummy").await?;
+        fs::write(dir.path().join("kept.nsp"), b"dummy").await?;
+
+        let names = |files: Vec<ContentFile>| -> Vec<PathBuf> {
+            files.into_iter().map(|file| file.relative_path).collect()
+        };
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert_eq!(
+            names(files),
+            vec![PathBuf::from("Custom/game.nsp"), PathBuf::from("kept.nsp")]
+        );
+
+        let config = ScanConfig {
+            ignore_markers: vec![String::from("skip-me")],
+            ..ScanConfig::default()
+        };
+        let files = scan_library(dir.path(), config, None).await?.files;
+        assert_eq!(names(files).len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_library_reuses_cached_metadata_of_unchanged_files() -> Result<()> {
+        let dir = tempdir()?;
+        let data = tempdir()?;
+        let path = dir.path().join("Game [0100ABCD12340000][v0].nsp");
+        fs::write(&path, b"dummy").await?;
+        let cache = MetadataCache::load(data.path(), &ScanConfig::default());
+
+        let files = scan_library(dir.path(), ScanConfig::default(), Some(cache.clone()))
+            .await?
+            .files;
+        assert_eq!(files[0].title_id.as_deref(), Some("0100ABCD12340000"));
+
+        // A cache hit is used as is, so a planted entry shows up without reparsing.
+        let mut planted = files[0].clone();
+        planted.title_id = Some(String::from("0100FFFF00000000"));
+        let modified = std::fs::metadata(&path)?.modified().ok();
+        cache.insert(&path, &planted, modified);
+        let files = scan_library(dir.path(), ScanConfig::default(), Some(cache.clone()))
+            .await?
+            .files;
+        assert_eq!(files[0].title_id.as_deref(), Some("0100FFFF00000000"));
+
+        fs::write(&path, b"changed contents").await?;
+        let files = scan_library(dir.path(), ScanConfig::default(), Some(cache))
+            .await?
+            .files;
+        assert_eq!(files[0].title_id.as_deref(), Some("0100ABCD12340000"));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn scan_library_parses_title_id_from_parent_directory_path() -> Result<()> {
         let dir = tempdir()?;
@@ -182,10 +938,182 @@ mod tests {
         }
         fs::write(&nested, b"dummy").await?;
 
-        let files = scan_library(dir.path()).await?;
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
         assert_eq!(files.len(), 1);
         assert_eq!(files[0].title_id.as_deref(), Some("0100ABCD12340000"));
         assert_eq!(files[0].kind, ContentKind::Base);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn scan_library_prefers_container_metadata_over_filename() -> Result<()> {
+        let dir = tempdir()?;
+        let xml = b"<Type>Patch</Type><Id>0x0100abcd12340800</Id><Version>65536</Version>";
+        fs::write(
+            dir.path().join("My Game [0100FFFF00000000][v0].nsp"),
+            build_pfs0(&[("meta.cnmt.xml", xml)]),
+        )
+        .await?;
+
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].title_id.as_deref(), Some("0100ABCD12340800"));
+        assert_eq!(files[0].version, Some(65536));
+        assert_eq!(files[0].kind, ContentKind::Update);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_library_indexes_split_dumps_as_one_file() -> Result<()> {
+        let dir = tempdir()?;
+        let xml = b"<Type>Application</Type><Id>0x0100abcd12340000</Id><Version>0</Version>";
+        let image = build_pfs0(&[("meta.cnmt.xml", xml)]);
+        let (first, second) = image.split_at(image.len() / 2);
+        let split = dir.path().join("Big Game.nsp");
+        fs::create_dir(&split).await?;
+        fs::write(split.join("00"), first).await?;
+        fs::write(split.join("01"), second).await?;
+
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].relative_path, Path::new("Big Game.nsp"));
+        assert_eq!(files[0].size, image.len() as u64);
+        assert_eq!(files[0].title_id.as_deref(), Some("0100ABCD12340000"));
+        assert_eq!(files[0].kind, ContentKind::Base);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_library_indexes_single_title_archives_when_enabled() -> Result<()> {
+        let dir = tempdir()?;
+        let xml = b"<Type>Application</Type><Id>0x0100abcd12340000</Id><Version>0</Version>";
+        let image = build_pfs0(&[("meta.cnmt.xml", xml)]);
+        write_zip(
+            &dir.path().join("Game.zip"),
+            &[("Game.nsp", &image)],
+            CompressionMethod::Stored,
+        )?;
+
+        let plain = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert!(plain.is_empty());
+
+        let config = ScanConfig {
+            archives: true,
+            ..ScanConfig::default()
+        };
+        let files = scan_library(dir.path(), config, None).await?.files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].relative_path, Path::new("Game.zip"));
+        assert_eq!(files[0].name, "Game.nsp");
+        assert_eq!(files[0].size, image.len() as u64);
+        assert_eq!(files[0].title_id.as_deref(), Some("0100ABCD12340000"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_library_classifies_forwarders_as_homebrew() -> Result<()> {
+        let dir = tempdir()?;
+        let xml = b"<Type>Application</Type><Id>0x05001234abcd0000</Id><Version>0</Version>";
+        fs::write(
+            dir.path().join("Forwarder.nsp"),
+            build_pfs0(&[("meta.cnmt.xml", xml)]),
+        )
+        .await?;
+
+        let files = scan_library(dir.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].kind, ContentKind::Homebrew);
+        Ok(())
+    }
+
+    #[test]
+    fn case_variants_of_the_same_file_are_listed_once() {
+        let file = |path: &str, size: u64| ContentFile {
+            root: std::env::temp_dir(),
+            ..ContentFile::fixture(path, size)
+        };
+        let mut files = vec![
+            file("Games/Game.nsp", 10),
+            file("games/GAME.nsp", 10),
+            file("Games/Game.nsp", 10),
+            file("Other.nsp", 5),
+            file("other.nsp", 6),
+        ];
+        dedupe_paths(&mut files);
+        let paths = files
+            .iter()
+            .map(|file| file.relative_path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["Games/Game.nsp", "Other.nsp", "other.nsp"]);
+    }
+
+    #[tokio::test]
+    async fn parallel_scan_finds_every_file_and_honours_rate_limit() -> Result<()> {
+        let dir = tempdir()?;
+        for n in 0..6 {
+            let nested = dir.path().join(format!("dir{n}")).join("sub");
+            fs::create_dir_all(&nested).await?;
+            fs::write(nested.join(format!("game{n}.nsp")), b"dummy").await?;
+        }
+
+        let started = std::time::Instant::now();
+        let config = ScanConfig {
+            concurrency: 3,
+            files_per_second: Some(50),
+            ..ScanConfig::default()
+        };
+        let files = scan_library(dir.path(), config, None).await?.files;
+        assert_eq!(files.len(), 6);
+        assert!(files
+            .windows(2)
+            .all(|pair| pair[0].relative_path < pair[1].relative_path));
+        // Six files at 50/s: the last one waits at least five 20ms slots.
+        assert!(started.elapsed() >= std::time::Duration::from_millis(100));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinked_directories_are_followed_once_when_enabled() -> Result<()> {
+        let farm = tempdir()?;
+        let store = tempdir()?;
+        fs::write(
+            store.path().join("Game [0100AAAA00000000][v0].nsp"),
+            b"dummy",
+        )
+        .await?;
+        std::os::unix::fs::symlink(store.path(), farm.path().join("linked"))?;
+        std::os::unix::fs::symlink(farm.path(), farm.path().join("loop"))?;
+        std::os::unix::fs::symlink(farm.path().join("missing"), farm.path().join("broken"))?;
+
+        let files = scan_library(farm.path(), ScanConfig::default(), None)
+            .await?
+            .files;
+        assert!(files.is_empty());
+
+        let config = ScanConfig {
+            follow_symlinks: true,
+            ..ScanConfig::default()
+        };
+        let files = scan_library(farm.path(), config, None).await?.files;
+        let paths = files
+            .iter()
+            .map(|file| file.relative_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("linked/Game [0100AAAA00000000][v0].nsp")]
+        );
+        Ok(())
+    }
 }
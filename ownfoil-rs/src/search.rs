@@ -0,0 +1,412 @@
+//! Catalog search: query parsing, filters, fuzzy matching, and relevance ranking.
+//!
+//! A query is a list of whitespace-separated tokens. `key:value` and `key<op>value`
+//! tokens are filters; everything else is a search term. A file matches when it passes
+//! every filter and every term matches its file name, TitleDB name, or title ID.
+//!
+//! | Filter | Example | Meaning |
+//! |---|---|---|
+//! | `kind:` | `kind:update` | `base`/`game`, `update`, `dlc`, `homebrew`, `unknown` |
+//! | `tid:` | `tid:0100ABCD` | title ID starts with the given hex digits |
+//! | `ext:` | `ext:nsz` | file extension |
+//! | `size` | `size>4GB`, `size<=500MB` | `<`, `<=`, `>`, `>=`, `=`; binary units (`K`, `M`, `G`, `T`) |
+//! | `ver` | `ver>=65536`, `ver:0` | version number, same operators as `size` |
+//!
+//! Terms match exact words best, then word prefixes, then substrings, then near misses
+//! (one typo, two for long words), then acronym-style subsequences (`botw`). Results are
+//! ranked by the summed score of their terms; ties keep catalog order.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::catalog::{ContentFile, ContentKind};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SearchError {
+    #[error("unknown kind `{0}`")]
+    UnknownKind(String),
+    #[error("invalid title ID prefix `{0}`")]
+    InvalidTitleId(String),
+    #[error("invalid size `{0}`")]
+    InvalidSize(String),
+    #[error("invalid version `{0}`")]
+    InvalidVersion(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn holds(self, value: u64, bound: u64) -> bool {
+        let ordering = value.cmp(&bound);
+        match self {
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+            Comparison::Greater => ordering == Ordering::Greater,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    Kind(ContentKind),
+    /// Uppercase hex prefix.
+    TitleId(String),
+    /// Lowercase extension without the dot.
+    Extension(String),
+    Size(Comparison, u64),
+    Version(Comparison, u64),
+}
+
+impl Filter {
+    fn matches(&self, file: &ContentFile) -> bool {
+        match self {
+            Filter::Kind(kind) => file.kind == *kind,
+            Filter::TitleId(prefix) => file
+                .title_id
+                .as_deref()
+                .is_some_and(|title_id| title_id.to_ascii_uppercase().starts_with(prefix)),
+            Filter::Extension(ext) => file
+                .relative_path
+                .extension()
+                .and_then(|candidate| candidate.to_str())
+                .is_some_and(|candidate| candidate.eq_ignore_ascii_case(ext)),
+            Filter::Size(comparison, bound) => comparison.holds(file.size, *bound),
+            Filter::Version(comparison, bound) => file
+                .version
+                .is_some_and(|version| comparison.holds(u64::from(version), *bound)),
+        }
+    }
+}
+
+/// A parsed search query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Lowercase search terms.
+    terms: Vec<String>,
+    filters: Vec<Filter>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, SearchError> {
+        let mut parsed = Self::default();
+        for token in query.split_whitespace() {
+            match parse_filter(token)? {
+                Some(filter) => parsed.filters.push(filter),
+                None => parsed.terms.push(token.to_lowercase()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Relevance of `file`, or `None` when it does not match. `title_name` is the
+    /// TitleDB name for the file's title, if known.
+    pub fn score(&self, file: &ContentFile, title_name: Option<&str>) -> Option<u32> {
+        if !self.filters.iter().all(|filter| filter.matches(file)) {
+            return None;
+        }
+        let name = file.name.to_lowercase();
+        let title_name = title_name.map(str::to_lowercase);
+        let title_id = file.title_id.as_deref().map(str::to_ascii_lowercase);
+        self.terms.iter().try_fold(0, |total, term| {
+            let by_id = title_id
+                .as_deref()
+                .filter(|title_id| title_id.contains(term.as_str()))
+                .map(|_| 90);
+            let best = [Some(name.as_str()), title_name.as_deref()]
+                .into_iter()
+                .flatten()
+                .filter_map(|haystack| term_score(term, haystack))
+                .chain(by_id)
+                .max()?;
+            Some(total + best)
+        })
+    }
+}
+
+/// `Some` for filter tokens, `None` for plain search terms.
+fn parse_filter(token: &str) -> Result<Option<Filter>, SearchError> {
+    if let Some((key, value)) = token.split_once(':') {
+        let filter = match key.to_ascii_lowercase().as_str() {
+            "kind" | "type" => Filter::Kind(parse_kind(value)?),
+            "tid" | "title_id" | "titleid" => {
+                if value.is_empty()
+                    || value.len() > 16
+                    || !value.chars().all(|ch| ch.is_ascii_hexdigit())
+                {
+                    return Err(SearchError::InvalidTitleId(value.to_string()));
+                }
+                Filter::TitleId(value.to_ascii_uppercase())
+            }
+            "ext" | "format" => {
+                Filter::Extension(value.trim_start_matches('.').to_ascii_lowercase())
+            }
+            "size" => Filter::Size(Comparison::Equal, parse_size(value)?),
+            "ver" | "version" => Filter::Version(Comparison::Equal, parse_version(value)?),
+            _ => return Ok(None),
+        };
+        return Ok(Some(filter));
+    }
+
+    let Some(split) = token.find(['<', '>', '=']) else {
+        return Ok(None);
+    };
+    let (key, rest) = token.split_at(split);
+    let (comparison, value) = if let Some(value) = rest.strip_prefix("<=") {
+        (Comparison::LessOrEqual, value)
+    } else if let Some(value) = rest.strip_prefix(">=") {
+        (Comparison::GreaterOrEqual, value)
+    } else if let Some(value) = rest.strip_prefix('<') {
+        (Comparison::Less, value)
+    } else if let Some(value) = rest.strip_prefix('>') {
+        (Comparison::Greater, value)
+    } else {
+        (Comparison::Equal, &rest[1..])
+    };
+    let filter = match key.to_ascii_lowercase().as_str() {
+        "size" => Filter::Size(comparison, parse_size(value)?),
+        "ver" | "version" => Filter::Version(comparison, parse_version(value)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(filter))
+}
+
+fn parse_kind(value: &str) -> Result<ContentKind, SearchError> {
+    match value.to_ascii_lowercase().as_str() {
+        "base" | "game" | "games" => Ok(ContentKind::Base),
+        "update" | "updates" | "upd" => Ok(ContentKind::Update),
+        "dlc" => Ok(ContentKind::Dlc),
+        "homebrew" | "forwarder" | "forwarders" => Ok(ContentKind::Homebrew),
+        "unknown" => Ok(ContentKind::Unknown),
+        _ => Err(SearchError::UnknownKind(value.to_string())),
+    }
+}
+
+/// Parse `4GB`, `4.5G`, `500MiB`, or a plain byte count. Units are binary.
+pub(crate) fn parse_size(value: &str) -> Result<u64, SearchError> {
+    let invalid = || SearchError::InvalidSize(value.to_string());
+    let split = value
+        .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    let bytes = number * multiplier as f64;
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes as u64)
+}
+
+fn parse_version(value: &str) -> Result<u64, SearchError> {
+    value
+        .trim_start_matches(['v', 'V'])
+        .parse()
+        .map_err(|_| SearchError::InvalidVersion(value.to_string()))
+}
+
+/// How well lowercase `term` matches lowercase `haystack`.
+fn term_score(term: &str, haystack: &str) -> Option<u32> {
+    let words: Vec<&str> = haystack
+        .split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.contains(&term) {
+        return Some(100);
+    }
+    if words.iter().any(|word| word.starts_with(term)) {
+        return Some(80);
+    }
+    if haystack.contains(term) {
+        return Some(60);
+    }
+    let term_len = term.chars().count();
+    let max_typos = match term_len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    if max_typos > 0
+        && words
+            .iter()
+            .any(|word| edit_distance(term, word, max_typos) <= max_typos)
+    {
+        return Some(40);
+    }
+    if term_len >= 3 && is_acronym(term, &words) {
+        return Some(20);
+    }
+    None
+}
+
+/// Levenshtein distance between `left` and `right`, or `limit + 1` once it is known
+/// to exceed `limit`.
+fn edit_distance(left: &str, right: &str, limit: usize) -> usize {
+    let left: Vec<char> = left.chars().collect();
+    let right: Vec<char> = right.chars().collect();
+    if left.len().abs_diff(right.len()) > limit {
+        return limit + 1;
+    }
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+    for (i, left_ch) in left.iter().enumerate() {
+        let mut current = vec![i + 1; right.len() + 1];
+        for (j, right_ch) in right.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left_ch != right_ch);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().all(|&distance| distance > limit) {
+            return limit + 1;
+        }
+        previous = current;
+    }
+    previous[right.len()]
+}
+
+/// Whether `term` can be spelled by taking, in order, leading letters of consecutive
+/// words, starting at any word (`botw` for "breath of the wild").
+fn is_acronym(term: &str, words: &[&str]) -> bool {
+    (0..words.len()).any(|start| {
+        let mut remaining = term.chars().peekable();
+        for word in &words[start..] {
+            for ch in word.chars() {
+                if remaining.peek() == Some(&ch) {
+                    remaining.next();
+                } else {
+                    break;
+                }
+            }
+            if remaining.peek().is_none() {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// Rank `files` against `query`, best match first. `names` maps title IDs to TitleDB names.
+pub fn rank<'a>(
+    files: impl IntoIterator<Item = &'a ContentFile>,
+    query: &SearchQuery,
+    names: &HashMap<String, String>,
+) -> Vec<&'a ContentFile> {
+    let mut scored: Vec<(u32, &ContentFile)> = files
+        .into_iter()
+        .filter_map(|file| {
+            let title_name = file
+                .title_id
+                .as_deref()
+                .and_then(|title_id| names.get(title_id))
+                .map(String::as_str);
+            query.score(file, title_name).map(|score| (score, file))
+        })
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, file)| file).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::{parse_size, rank, SearchError, SearchQuery};
+    use crate::catalog::{ContentFile, ContentKind};
+
+    fn file(name: &str, title_id: &str, kind: ContentKind, size: u64) -> ContentFile {
+        ContentFile {
+            root: PathBuf::new(),
+            title_id: Some(title_id.to_string()),
+            version: Some(0),
+            kind,
+            ..ContentFile::fixture(name, size)
+        }
+    }
+
+    #[test]
+    fn parses_sizes_with_binary_units() {
+        assert_eq!(parse_size("4GB"), Ok(4 << 30));
+        assert_eq!(parse_size("1.5k"), Ok(1536));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(
+            parse_size("4XB"),
+            Err(SearchError::InvalidSize(String::from("4XB")))
+        );
+        assert!(SearchQuery::parse("kind:patch").is_err());
+    }
+
+    #[test]
+    fn filters_and_ranks_by_relevance() -> Result<(), SearchError> {
+        let files = [
+            file(
+                "Zelda Tears [0100AAAA00000000].nsp",
+                "0100AAAA00000000",
+                ContentKind::Base,
+                16 << 30,
+            ),
+            file(
+                "Zelda Tears [0100AAAA00000800].nsp",
+                "0100AAAA00000800",
+                ContentKind::Update,
+                1 << 30,
+            ),
+            file(
+                "Zeldas Adventure [0100BBBB00000000].nsp",
+                "0100BBBB00000000",
+                ContentKind::Base,
+                1 << 30,
+            ),
+            file(
+                "[0100CCCC00000000].nsp",
+                "0100CCCC00000000",
+                ContentKind::Base,
+                6 << 30,
+            ),
+        ];
+        let names = HashMap::from([(
+            String::from("0100CCCC00000000"),
+            String::from("The Legend of Zelda: Breath of the Wild"),
+        )]);
+        let names_of = |query: &str| -> Result<Vec<String>, SearchError> {
+            let query = SearchQuery::parse(query)?;
+            Ok(rank(&files, &query, &names)
+                .into_iter()
+                .map(|file| file.title_id.clone().unwrap_or_default())
+                .collect())
+        };
+
+        // Exact word hits (including the TitleDB name) outrank the prefix hit.
+        assert_eq!(
+            names_of("zelda")?,
+            vec![
+                "0100AAAA00000000",
+                "0100AAAA00000800",
+                "0100CCCC00000000",
+                "0100BBBB00000000"
+            ]
+        );
+        assert_eq!(names_of("zelda kind:update")?, vec!["0100AAAA00000800"]);
+        assert_eq!(names_of("size>4GB tid:0100aaaa")?, vec!["0100AAAA00000000"]);
+        // Typos and acronyms still find something.
+        assert_eq!(names_of("adventrue")?, vec!["0100BBBB00000000"]);
+        assert_eq!(names_of("botw")?, vec!["0100CCCC00000000"]);
+        assert!(names_of("metroid")?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,496 @@
+//! `ownfoil-rs selftest`: replay what Tinfoil, CyberFoil, and DBI send against a running
+//! server and check the answers.
+//!
+//! The sequences follow captured client sessions: the index fetch and its revalidation,
+//! an icon fetch, and installs that start, abort, resume, and fetch fixed-size chunks,
+//! then probe past the end. Only a small window at the start and end of the smallest
+//! listed file is read, so a run is cheap even against a large library.
+
+use std::time::Duration;
+
+use reqwest::header::{
+    ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, IF_RANGE,
+    LOCATION, RANGE, USER_AGENT,
+};
+use reqwest::{redirect, StatusCode, Url};
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct SelftestArgs {
+    /// Shop URL, as entered in the client.
+    #[arg(
+        long,
+        env = "OWNFOIL_URL",
+        default_value = "http://127.0.0.1:8465",
+        value_name = "URL"
+    )]
+    pub url: Url,
+    #[arg(long, short = 'u', env = "OWNFOIL_USER", value_name = "NAME")]
+    pub user: Option<String>,
+    #[arg(
+        long,
+        env = "OWNFOIL_PASSWORD",
+        hide_env_values = true,
+        value_name = "PASSWORD"
+    )]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum SelftestError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("{failed} of {total} checks failed")]
+    Failed { failed: usize, total: usize },
+}
+
+/// Headers Tinfoil sends with every request; `theme` and `uid` are how servers tell it
+/// apart from browsers.
+const TINFOIL: &[(&str, &str)] = &[
+    (
+        "Theme",
+        "4a2d5b1c0e0f7a3b9c6d8e1f2a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b",
+    ),
+    (
+        "Uid",
+        "1b0c9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0c",
+    ),
+    ("Version", "18.0"),
+    ("Revision", "0"),
+    ("Language", "en"),
+    ("Hauth", "0"),
+    ("Uauth", "0"),
+];
+const CYBERFOIL_AGENT: &str = "CyberFoil/1.3.0";
+const DBI_AGENT: &str = "DBI/658";
+
+/// Bytes read from the start of the install target; DBI fetches it in thirds.
+const WINDOW: u64 = 96 * 1024;
+/// Bytes a suffix range asks for.
+const TAIL: u64 = 16;
+
+/// The result of one check: what was seen, or why it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub check: &'static str,
+    pub result: Result<String, String>,
+}
+
+/// A file from the shop index, the one installs are replayed against.
+#[derive(Debug, Clone)]
+struct Target {
+    url: Url,
+    size: u64,
+}
+
+struct Client {
+    base: Url,
+    http: reqwest::Client,
+    credentials: Option<(String, Option<String>)>,
+}
+
+impl Client {
+    async fn get(&self, url: &Url, headers: &[(&str, &str)]) -> Result<reqwest::Response, String> {
+        let mut request = self.http.get(url.clone());
+        if let Some((user, password)) = &self.credentials {
+            request = request.basic_auth(user, password.as_ref());
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().await.map_err(|err| err.to_string())
+    }
+
+    /// `path` under the shop URL, keeping a prefix such as `/u/<token>/`.
+    fn join(&self, path: &str) -> Result<Url, String> {
+        self.base
+            .join(path)
+            .map_err(|err| format!("bad URL {path:?}: {err}"))
+    }
+
+    /// A URL from a server response; those already carry any prefix.
+    fn resolve(&self, url: &str) -> Result<Url, String> {
+        self.base
+            .join(url)
+            .map_err(|err| format!("bad URL {url:?}: {err}"))
+    }
+}
+
+/// Run every check against the server at `args.url`, printing one line per check.
+pub async fn run(args: SelftestArgs) -> Result<(), SelftestError> {
+    let credentials = args.user.map(|user| (user, args.password));
+    let outcomes = run_checks(args.url, credentials).await?;
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok(detail) => println!("ok    {:<20}  {detail}", outcome.check),
+            Err(reason) => println!("FAIL  {:<20}  {reason}", outcome.check),
+        }
+    }
+    if failed > 0 {
+        return Err(SelftestError::Failed {
+            failed,
+            total: outcomes.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Replay the client sequences against the shop at `base`.
+pub async fn run_checks(
+    mut base: Url,
+    credentials: Option<(String, Option<String>)>,
+) -> Result<Vec<Outcome>, SelftestError> {
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    let http = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(60))
+        // Icons may redirect to TitleDB; the redirect itself is the answer.
+        .redirect(redirect::Policy::none())
+        .build()?;
+    let client = Client {
+        base,
+        http,
+        credentials,
+    };
+
+    let mut outcomes = Vec::new();
+    let index = tinfoil_index(&client).await;
+    let (files, etag) = index.as_ref().cloned().unwrap_or_default();
+    outcomes.push(Outcome {
+        check: "tinfoil index",
+        result: index.map(|(files, _)| format!("{} files", files.len())),
+    });
+    outcomes.push(Outcome {
+        check: "index revalidation",
+        result: revalidate_index(&client, etag.as_deref()).await,
+    });
+    outcomes.push(Outcome {
+        check: "cyberfoil index",
+        result: cyberfoil_index(&client).await,
+    });
+    outcomes.push(Outcome {
+        check: "icon",
+        result: icon(&client).await,
+    });
+
+    let Some(target) = files
+        .into_iter()
+        .filter(|file| file.size > 0)
+        .min_by_key(|file| file.size)
+    else {
+        outcomes.push(Outcome {
+            check: "install",
+            result: Ok(String::from("skipped: the index lists no files")),
+        });
+        return Ok(outcomes);
+    };
+    let head = install_start(&client, &target).await;
+    let (window, etag) = head.as_ref().cloned().unwrap_or_default();
+    outcomes.push(Outcome {
+        check: "install start",
+        result: head.map(|(window, _)| format!("{} bytes of {}", window.len(), target.url)),
+    });
+    if window.is_empty() {
+        return Ok(outcomes);
+    }
+    outcomes.push(Outcome {
+        check: "aborted install",
+        result: aborted_install(&client, &target, &window).await,
+    });
+    outcomes.push(Outcome {
+        check: "resumed install",
+        result: resumed_install(&client, &target, &window, etag.as_deref()).await,
+    });
+    outcomes.push(Outcome {
+        check: "dbi chunked install",
+        result: chunked_install(&client, &target, &window).await,
+    });
+    outcomes.push(Outcome {
+        check: "suffix range",
+        result: suffix_range(&client, &target).await,
+    });
+    outcomes.push(Outcome {
+        check: "range past end",
+        result: range_past_end(&client, &target).await,
+    });
+    Ok(outcomes)
+}
+
+/// `GET /` as Tinfoil: the files it lists and the index's `ETag`. An encrypted index
+/// can't be read here, so the file list then comes from the plain `shop` copy.
+async fn tinfoil_index(client: &Client) -> Result<(Vec<Target>, Option<String>), String> {
+    let response = client.get(&client.base, TINFOIL).await?;
+    expect_status(&response, StatusCode::OK)?;
+    let etag = header(&response, ETAG).map(String::from);
+    let body = response.bytes().await.map_err(|err| err.to_string())?;
+    let body = if body.starts_with(b"TINFOIL") {
+        let plain = client.get(&client.join("shop")?, TINFOIL).await?;
+        expect_status(&plain, StatusCode::OK)?;
+        plain.bytes().await.map_err(|err| err.to_string())?
+    } else {
+        body
+    };
+    let index: Value =
+        serde_json::from_slice(&body).map_err(|err| format!("index is not JSON: {err}"))?;
+    let files = index["files"]
+        .as_array()
+        .ok_or("index has no `files` array")?
+        .iter()
+        .map(|file| {
+            let url = file["url"].as_str().ok_or("file without a `url`")?;
+            let size = file["size"].as_u64().ok_or("file without a `size`")?;
+            Ok(Target {
+                url: client.resolve(url)?,
+                size,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok((files, etag))
+}
+
+async fn revalidate_index(client: &Client, etag: Option<&str>) -> Result<String, String> {
+    let Some(etag) = etag else {
+        return Ok(String::from("skipped: the index has no ETag"));
+    };
+    let mut headers = TINFOIL.to_vec();
+    headers.push((IF_NONE_MATCH.as_str(), etag));
+    let response = client.get(&client.base, &headers).await?;
+    expect_status(&response, StatusCode::NOT_MODIFIED)?;
+    Ok(format!("304 for {etag}"))
+}
+
+/// CyberFoil sends Tinfoil's headers too, but always gets plain JSON.
+async fn cyberfoil_index(client: &Client) -> Result<String, String> {
+    let mut headers = TINFOIL.to_vec();
+    headers.push((USER_AGENT.as_str(), CYBERFOIL_AGENT));
+    let response = client.get(&client.base, &headers).await?;
+    expect_status(&response, StatusCode::OK)?;
+    let index: Value = response
+        .json()
+        .await
+        .map_err(|err| format!("index is not JSON: {err}"))?;
+    let files = index["files"]
+        .as_array()
+        .ok_or("index has no `files` array")?;
+    Ok(format!("{} files", files.len()))
+}
+
+/// Fetch the icon of the first title in the CyberFoil sections.
+async fn icon(client: &Client) -> Result<String, String> {
+    let headers = [(USER_AGENT.as_str(), CYBERFOIL_AGENT)];
+    let response = client
+        .get(&client.join("api/shop/sections")?, &headers)
+        .await?;
+    expect_status(&response, StatusCode::OK)?;
+    let sections: Value = response.json().await.map_err(|err| err.to_string())?;
+    let icon_url = sections["sections"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|section| section["items"].as_array().into_iter().flatten())
+        .find_map(|item| item["icon_url"].as_str().filter(|url| !url.is_empty()));
+    let Some(icon_url) = icon_url else {
+        return Ok(String::from("skipped: no title has an icon"));
+    };
+    let url = client.resolve(icon_url)?;
+    let response = client.get(&url, &headers).await?;
+    let status = response.status();
+    if status.is_redirection() {
+        let location = header(&response, LOCATION).ok_or("redirect without a Location")?;
+        return Ok(format!("{status} to {location}"));
+    }
+    expect_status(&response, StatusCode::OK)?;
+    let content_type = header(&response, CONTENT_TYPE)
+        .unwrap_or_default()
+        .to_string();
+    if !content_type.starts_with("image/") {
+        return Err(format!("icon served as {content_type:?}"));
+    }
+    Ok(format!("{content_type} from {url}"))
+}
+
+/// Tinfoil starts an install by asking for the first bytes: the window and the `ETag`.
+async fn install_start(
+    client: &Client,
+    target: &Target,
+) -> Result<(Vec<u8>, Option<String>), String> {
+    let len = WINDOW.min(target.size);
+    let range = format!("bytes=0-{}", len - 1);
+    let response = client
+        .get(&target.url, &with_tinfoil(RANGE.as_str(), &range))
+        .await?;
+    expect_status(&response, StatusCode::PARTIAL_CONTENT)?;
+    if header(&response, ACCEPT_RANGES) != Some("bytes") {
+        return Err(String::from("no `Accept-Ranges: bytes`"));
+    }
+    expect_content_range(&response, &format!("bytes 0-{}/{}", len - 1, target.size))?;
+    let etag = header(&response, ETAG).map(String::from);
+    let body = read_prefix(response, len).await?;
+    if body.len() as u64 != len {
+        return Err(format!("sent {} bytes for a {len}-byte range", body.len()));
+    }
+    Ok((body, etag))
+}
+
+/// A full download dropped after the window, as when an install is cancelled.
+async fn aborted_install(
+    client: &Client,
+    target: &Target,
+    window: &[u8],
+) -> Result<String, String> {
+    let response = client.get(&target.url, TINFOIL).await?;
+    expect_status(&response, StatusCode::OK)?;
+    let length = header(&response, CONTENT_LENGTH).and_then(|value| value.parse::<u64>().ok());
+    if length != Some(target.size) {
+        return Err(format!(
+            "Content-Length {length:?}, index says {}",
+            target.size
+        ));
+    }
+    let body = read_prefix(response, window.len() as u64).await?;
+    expect_same(&body, window)?;
+    Ok(format!("dropped after {} bytes", body.len()))
+}
+
+/// Resume halfway through the window with an open-ended range guarded by `If-Range`.
+async fn resumed_install(
+    client: &Client,
+    target: &Target,
+    window: &[u8],
+    etag: Option<&str>,
+) -> Result<String, String> {
+    let offset = window.len() / 2;
+    let range = format!("bytes={offset}-");
+    let mut headers = with_tinfoil(RANGE.as_str(), &range);
+    if let Some(etag) = etag {
+        headers.push((IF_RANGE.as_str(), etag));
+    }
+    let response = client.get(&target.url, &headers).await?;
+    expect_status(&response, StatusCode::PARTIAL_CONTENT)?;
+    expect_content_range(
+        &response,
+        &format!("bytes {offset}-{}/{}", target.size - 1, target.size),
+    )?;
+    let body = read_prefix(response, (window.len() - offset) as u64).await?;
+    expect_same(&body, &window[offset..])?;
+    Ok(format!("resumed at byte {offset}"))
+}
+
+/// DBI fetches fixed-size chunks one after another.
+async fn chunked_install(
+    client: &Client,
+    target: &Target,
+    window: &[u8],
+) -> Result<String, String> {
+    let chunk = window.len().div_ceil(3);
+    let mut body = Vec::with_capacity(window.len());
+    for start in (0..window.len()).step_by(chunk) {
+        let end = (start + chunk).min(window.len()) - 1;
+        let range = format!("bytes={start}-{end}");
+        let headers = [
+            (USER_AGENT.as_str(), DBI_AGENT),
+            (RANGE.as_str(), range.as_str()),
+        ];
+        let response = client.get(&target.url, &headers).await?;
+        expect_status(&response, StatusCode::PARTIAL_CONTENT)?;
+        expect_content_range(&response, &format!("bytes {start}-{end}/{}", target.size))?;
+        body.extend(read_prefix(response, (end - start + 1) as u64).await?);
+    }
+    expect_same(&body, window)?;
+    Ok(format!(
+        "{} chunks of {chunk} bytes",
+        window.len().div_ceil(chunk)
+    ))
+}
+
+/// Installers read the end of a file for its footer first.
+async fn suffix_range(client: &Client, target: &Target) -> Result<String, String> {
+    let len = TAIL.min(target.size);
+    let range = format!("bytes=-{len}");
+    let response = client
+        .get(&target.url, &with_tinfoil(RANGE.as_str(), &range))
+        .await?;
+    expect_status(&response, StatusCode::PARTIAL_CONTENT)?;
+    let start = target.size - len;
+    expect_content_range(
+        &response,
+        &format!("bytes {start}-{}/{}", target.size - 1, target.size),
+    )?;
+    let body = response.bytes().await.map_err(|err| err.to_string())?;
+    if body.len() as u64 != len {
+        return Err(format!("sent {} bytes for the last {len}", body.len()));
+    }
+    Ok(format!("last {len} bytes"))
+}
+
+/// A resume from a stale, larger copy of the file asks for bytes that don't exist.
+async fn range_past_end(client: &Client, target: &Target) -> Result<String, String> {
+    let range = format!("bytes={}-", target.size);
+    let response = client
+        .get(&target.url, &with_tinfoil(RANGE.as_str(), &range))
+        .await?;
+    expect_status(&response, StatusCode::RANGE_NOT_SATISFIABLE)?;
+    expect_content_range(&response, &format!("bytes */{}", target.size))?;
+    Ok(String::from("416"))
+}
+
+fn with_tinfoil<'a>(name: &'a str, value: &'a str) -> Vec<(&'a str, &'a str)> {
+    let mut headers = TINFOIL.to_vec();
+    headers.push((name, value));
+    headers
+}
+
+fn header(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<&str> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn expect_status(response: &reqwest::Response, expected: StatusCode) -> Result<(), String> {
+    if response.status() == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {expected}, got {}", response.status()))
+    }
+}
+
+fn expect_content_range(response: &reqwest::Response, expected: &str) -> Result<(), String> {
+    match header(response, CONTENT_RANGE) {
+        Some(actual) if actual == expected => Ok(()),
+        actual => Err(format!("Content-Range {actual:?}, expected {expected:?}")),
+    }
+}
+
+fn expect_same(actual: &[u8], expected: &[u8]) -> Result<(), String> {
+    match actual.iter().zip(expected).position(|(a, b)| a != b) {
+        Some(offset) => Err(format!("bytes differ at offset {offset}")),
+        None if actual.len() != expected.len() => Err(format!(
+            "sent {} bytes, expected {}",
+            actual.len(),
+            expected.len()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Read at most `limit` bytes of the body, then drop the connection's response.
+async fn read_prefix(mut response: reqwest::Response, limit: u64) -> Result<Vec<u8>, String> {
+    let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+    let mut body = Vec::new();
+    while body.len() < limit {
+        match response.chunk().await.map_err(|err| err.to_string())? {
+            Some(chunk) => body.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    body.truncate(limit);
+    Ok(body)
+}
@@ -0,0 +1,160 @@
+//! Per-user shop tokens for clients that cannot send Basic auth.
+//!
+//! Each user can be issued a random token that is embedded in the shop URL
+//! (`/u/{token}/`), so the client needs no `Authorization` header. Only a SHA-256 digest
+//! of each token is persisted, to `<data_dir>/shop_tokens.json` keyed by username; the
+//! token itself is shown once, when issued. Tokens stop working as soon as the user is
+//! removed from the auth file, rotated, or revoked.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+const TOKENS_FILE: &str = "shop_tokens.json";
+/// Length of a hex SHA-256 digest. Files written before tokens were hashed hold the
+/// 32-character tokens themselves.
+const DIGEST_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ShopTokenStore {
+    /// Username -> hex SHA-256 of their token.
+    inner: Arc<RwLock<BTreeMap<String, String>>>,
+    store_path: PathBuf,
+}
+
+impl ShopTokenStore {
+    /// Load tokens from `data_dir`, starting empty if the file is missing or invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(TOKENS_FILE);
+        let mut tokens: BTreeMap<String, String> = match std::fs::read_to_string(&store_path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+                warn!(path = %store_path.display(), error = %err, "ignoring invalid shop tokens file");
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        let mut plaintext = false;
+        for token in tokens.values_mut() {
+            if token.len() != DIGEST_LEN {
+                *token = digest(token);
+                plaintext = true;
+            }
+        }
+        if plaintext {
+            // Keep the URLs already handed out working, but stop storing them.
+            let saved = serde_json::to_string_pretty(&tokens)
+                .map_err(std::io::Error::other)
+                .and_then(|raw| std::fs::write(&store_path, raw));
+            if let Err(err) = saved {
+                warn!(path = %store_path.display(), error = %err, "failed to hash stored shop tokens");
+            }
+        }
+        Self {
+            inner: Arc::new(RwLock::new(tokens)),
+            store_path,
+        }
+    }
+
+    /// Whether `username` has a token.
+    pub async fn has_token(&self, username: &str) -> bool {
+        self.inner.read().await.contains_key(username)
+    }
+
+    /// The user a token was issued to, if it is still current.
+    pub async fn user_for(&self, token: &str) -> Option<String> {
+        let digest = digest(token);
+        let tokens = self.inner.read().await;
+        tokens
+            .iter()
+            .find(|(_, known)| bool::from(known.as_bytes().ct_eq(digest.as_bytes())))
+            .map(|(username, _)| username.clone())
+    }
+
+    /// Issue a fresh token for `username`, invalidating any previous one. Returns the
+    /// token, which isn't kept.
+    pub async fn rotate(&self, username: &str) -> std::io::Result<String> {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let mut tokens = self.inner.write().await;
+        tokens.insert(username.to_string(), digest(&token));
+        self.save(&tokens).await?;
+        Ok(token)
+    }
+
+    /// Drop the token for `username`. Returns whether one existed.
+    pub async fn revoke(&self, username: &str) -> std::io::Result<bool> {
+        let mut tokens = self.inner.write().await;
+        if tokens.remove(username).is_none() {
+            return Ok(false);
+        }
+        self.save(&tokens).await.map(|()| true)
+    }
+
+    async fn save(&self, tokens: &BTreeMap<String, String>) -> std::io::Result<()> {
+        let raw = serde_json::to_string_pretty(tokens).map_err(std::io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp = self.store_path.with_extension("json.tmp");
+        tokio::fs::write(&temp, raw).await?;
+        tokio::fs::rename(&temp, &self.store_path).await
+    }
+}
+
+fn digest(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::ShopTokenStore;
+
+    #[tokio::test]
+    async fn rotation_replaces_the_previous_token_and_survives_reload() -> Result<()> {
+        let dir = tempdir()?;
+        let store = ShopTokenStore::load(dir.path());
+        let first = store.rotate("alice").await?;
+        let second = store.rotate("alice").await?;
+        assert_ne!(first, second);
+
+        let raw = std::fs::read_to_string(dir.path().join("shop_tokens.json"))?;
+        assert!(!raw.contains(&second));
+
+        let reloaded = ShopTokenStore::load(dir.path());
+        assert!(reloaded.has_token("alice").await);
+        assert_eq!(reloaded.user_for(&second).await.as_deref(), Some("alice"));
+        assert_eq!(reloaded.user_for(&first).await, None);
+
+        assert!(reloaded.revoke("alice").await?);
+        assert!(!reloaded.revoke("alice").await?);
+        assert_eq!(reloaded.user_for(&second).await, None);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn plaintext_tokens_are_hashed_on_load_and_keep_working() -> Result<()> {
+        let dir = tempdir()?;
+        let token = "3f0c9a1e5b7d4c2a8e6f0b1d3c5a7e9f";
+        std::fs::write(
+            dir.path().join("shop_tokens.json"),
+            format!(r#"{{"alice": "{token}"}}"#),
+        )?;
+
+        let store = ShopTokenStore::load(dir.path());
+        assert_eq!(store.user_for(token).await.as_deref(), Some("alice"));
+        let raw = std::fs::read_to_string(dir.path().join("shop_tokens.json"))?;
+        assert!(!raw.contains(token));
+        let reloaded = ShopTokenStore::load(dir.path());
+        assert_eq!(reloaded.user_for(token).await.as_deref(), Some("alice"));
+        Ok(())
+    }
+}
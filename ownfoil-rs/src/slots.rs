@@ -0,0 +1,76 @@
+//! Concurrent download slots: `[downloads] max_concurrent_per_client` caps how many files
+//! each client (its user, or its IP when anonymous) downloads at once, so parallel
+//! installs don't keep a spinning disk seeking between files.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone, Default)]
+pub struct DownloadSlots {
+    /// Downloads per client; unlimited when unset.
+    limit: Option<usize>,
+    /// Slots of clients with downloads running.
+    clients: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+/// A running download's slot, freed when dropped.
+#[derive(Debug)]
+pub struct DownloadSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl DownloadSlots {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit: limit.filter(|limit| *limit > 0),
+            clients: Arc::default(),
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// A slot for a download by `client`, or `None` while all of its slots are taken.
+    pub fn try_acquire(&self, client: &str) -> Option<DownloadSlot> {
+        let Some(limit) = self.limit else {
+            return Some(DownloadSlot { _permit: None });
+        };
+        let mut clients = self.clients.lock().unwrap_or_else(|p| p.into_inner());
+        // Permits hold their semaphore, so only idle clients' are dropped.
+        clients.retain(|_, slots| Arc::strong_count(slots) > 1);
+        let slots = clients
+            .entry(client.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)));
+        Arc::clone(slots)
+            .try_acquire_owned()
+            .ok()
+            .map(|permit| DownloadSlot {
+                _permit: Some(permit),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DownloadSlots;
+
+    #[test]
+    fn each_client_gets_its_own_slots() {
+        let slots = DownloadSlots::new(Some(2));
+        let first = slots.try_acquire("switch");
+        let second = slots.try_acquire("switch");
+        assert!(first.is_some() && second.is_some());
+        assert!(slots.try_acquire("switch").is_none());
+        assert!(slots.try_acquire("192.168.1.20").is_some());
+
+        drop(first);
+        assert!(slots.try_acquire("switch").is_some());
+
+        let unlimited = DownloadSlots::new(None);
+        let held: Vec<_> = (0..10).map(|_| unlimited.try_acquire("switch")).collect();
+        assert!(held.iter().all(Option::is_some));
+    }
+}
@@ -0,0 +1,91 @@
+//! Speed test: streams incompressible data so clients can measure throughput.
+//!
+//! The payload comes straight from memory, so a slow result points at the network or the
+//! console rather than the library's storage. Bytes are the extendable output of a
+//! BLAKE3 hash with a random key, which no proxy or transport can compress. Each client
+//! may start one test per [`COOLDOWN`].
+
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use futures_util::stream::{self, Stream};
+
+/// Size of a test when the client doesn't ask for one.
+pub const DEFAULT_MEGABYTES: u64 = 100;
+/// Largest test a client may ask for.
+pub const MAX_MEGABYTES: u64 = 2048;
+/// Minimum time between two tests from the same client.
+pub const COOLDOWN: Duration = Duration::from_secs(10);
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// When each client last started a test.
+#[derive(Debug, Clone, Default)]
+pub struct SpeedTestLimiter {
+    started: Arc<DashMap<IpAddr, Instant>>,
+}
+
+impl SpeedTestLimiter {
+    /// Record a test start for `client`, or return how long it must wait first.
+    pub fn try_start(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.started
+            .retain(|_, started| now.duration_since(*started) < COOLDOWN);
+        if let Some(started) = self.started.get(&client) {
+            return Err(COOLDOWN.saturating_sub(now.duration_since(*started)));
+        }
+        self.started.insert(client, now);
+        Ok(())
+    }
+}
+
+/// `len` bytes of pseudorandom data in 64 KiB chunks.
+pub fn random_stream(len: u64) -> impl Stream<Item = Result<Bytes, io::Error>> + Send {
+    let key = *blake3::hash(uuid::Uuid::new_v4().as_bytes()).as_bytes();
+    let output = blake3::Hasher::new_keyed(&key).finalize_xof();
+    stream::unfold((output, len), |(mut output, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let size = remaining.min(CHUNK_SIZE as u64) as usize;
+        let mut chunk = vec![0; size];
+        output.fill(&mut chunk);
+        Some((Ok(Bytes::from(chunk)), (output, remaining - size as u64)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use futures_util::StreamExt;
+
+    use super::{random_stream, SpeedTestLimiter};
+
+    #[tokio::test]
+    async fn streams_exactly_the_requested_length_of_distinct_data() {
+        let chunks: Vec<_> = random_stream(150_000).collect().await;
+        let data: Vec<u8> = chunks
+            .into_iter()
+            .flatten()
+            .flat_map(|chunk| chunk.to_vec())
+            .collect();
+        assert_eq!(data.len(), 150_000);
+        assert_ne!(data[..1024], data[65_536..65_536 + 1024]);
+    }
+
+    #[test]
+    fn clients_wait_out_the_cooldown_independently() {
+        let limiter = SpeedTestLimiter::default();
+        let console = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 30));
+        assert!(limiter.try_start(console).is_ok());
+        assert!(limiter.try_start(console).is_err());
+        assert!(limiter
+            .try_start(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 31)))
+            .is_ok());
+    }
+}
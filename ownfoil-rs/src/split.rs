@@ -0,0 +1,278 @@
+//! Split dumps: FAT32 can't hold files over 4 GiB, so large NSP/XCI dumps often come as a
+//! directory named like the file (`Game.nsp/`) holding the parts `00`, `01`, `02`, ...
+//!
+//! Such a directory is treated as one logical file: its size is the sum of the parts and
+//! reads (including ranged downloads) run across part boundaries.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::scanner::is_supported_content;
+
+/// Parts of a split dump, in order, with their sizes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitParts {
+    parts: Vec<(PathBuf, u64)>,
+    modified: Option<SystemTime>,
+}
+
+impl SplitParts {
+    /// The parts of `dir` if it is a split dump: a directory with a content extension
+    /// (`.nsp`, `.xci`, ...) holding `00` and consecutively numbered parts after it.
+    /// Anything else in the directory is ignored.
+    pub fn detect(dir: &Path) -> io::Result<Option<Self>> {
+        if !is_supported_content(dir) {
+            return Ok(None);
+        }
+        let mut numbered = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let Some(index) = entry.file_name().to_str().and_then(part_index) else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                numbered.push((index, entry.path(), metadata));
+            }
+        }
+        numbered.sort_by_key(|(index, _, _)| *index);
+        if numbered.is_empty()
+            || numbered
+                .iter()
+                .enumerate()
+                .any(|(expected, (index, _, _))| *index != expected)
+        {
+            return Ok(None);
+        }
+
+        let modified = numbered
+            .iter()
+            .filter_map(|(_, _, metadata)| metadata.modified().ok())
+            .max();
+        Ok(Some(Self {
+            parts: numbered
+                .into_iter()
+                .map(|(_, path, metadata)| (path, metadata.len()))
+                .collect(),
+            modified,
+        }))
+    }
+
+    /// Total size of all parts.
+    pub fn len(&self) -> u64 {
+        self.parts.iter().map(|(_, size)| size).sum()
+    }
+
+    /// Latest modification time of any part.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// `(part, offset, length)` for each part overlapping `len` bytes from `start`.
+    fn segments(&self, start: u64, len: u64) -> Vec<(PathBuf, u64, u64)> {
+        let end = start.saturating_add(len);
+        let mut part_start = 0;
+        let mut out = Vec::new();
+        for (path, size) in &self.parts {
+            let part_end = part_start + size;
+            if part_end > start && part_start < end {
+                let offset = start.saturating_sub(part_start);
+                let take = end.min(part_end) - (part_start + offset);
+                out.push((path.clone(), offset, take));
+            }
+            part_start = part_end;
+        }
+        out
+    }
+
+    /// Stream `len` bytes starting at `start`, opening parts as they are reached and
+    /// reading `read_buffer` bytes at a time.
+    pub fn stream_range(
+        &self,
+        start: u64,
+        len: u64,
+        read_buffer: usize,
+    ) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        stream::iter(self.segments(start, len))
+            .then(move |(path, offset, take)| async move {
+                let mut file = tokio::fs::File::open(&path).await?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                Ok::<_, io::Error>(ReaderStream::with_capacity(file.take(take), read_buffer))
+            })
+            .try_flatten()
+    }
+
+    /// Blocking reader over the joined parts.
+    pub fn reader(&self) -> SplitReader {
+        SplitReader {
+            parts: self.parts.clone(),
+            len: self.len(),
+            position: 0,
+            open: None,
+        }
+    }
+}
+
+/// `00`..`99` to 0..99.
+fn part_index(name: &str) -> Option<usize> {
+    (name.len() == 2 && name.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| name.parse().ok())
+        .flatten()
+}
+
+/// Reads a split dump as one contiguous file.
+#[derive(Debug)]
+pub struct SplitReader {
+    parts: Vec<(PathBuf, u64)>,
+    len: u64,
+    position: u64,
+    /// Index of the part currently open.
+    open: Option<(usize, File)>,
+}
+
+impl Read for SplitReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut part_start = 0;
+        for (index, (path, size)) in self.parts.iter().enumerate() {
+            let part_end = part_start + size;
+            if self.position < part_end {
+                let offset = self.position - part_start;
+                if self.open.as_ref().map(|(open, _)| *open) != Some(index) {
+                    self.open = Some((index, File::open(path)?));
+                }
+                let Some((_, file)) = self.open.as_mut() else {
+                    return Ok(0);
+                };
+                file.seek(SeekFrom::Start(offset))?;
+                let want = buf
+                    .len()
+                    .min(usize::try_from(size - offset).unwrap_or(usize::MAX));
+                let read = file.read(&mut buf[..want])?;
+                self.position += read as u64;
+                return Ok(read);
+            }
+            part_start = part_end;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for SplitReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before start of split file",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// A library file opened for reading: a plain file or a split dump.
+#[derive(Debug)]
+pub enum ContentReader {
+    File(File),
+    Split(SplitReader),
+}
+
+impl ContentReader {
+    /// Open `path`, reading it as a split dump when it is a split directory.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if path.is_dir() {
+            return match SplitParts::detect(path)? {
+                Some(parts) => Ok(Self::Split(parts.reader())),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is a directory, not a split dump", path.display()),
+                )),
+            };
+        }
+        File::open(path).map(Self::File)
+    }
+}
+
+impl Read for ContentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Split(split) => split.read(buf),
+        }
+    }
+}
+
+impl Seek for ContentReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Split(split) => split.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+
+    use anyhow::Result;
+    use futures_util::TryStreamExt;
+    use tempfile::tempdir;
+
+    use super::SplitParts;
+
+    #[tokio::test]
+    async fn parts_read_as_one_file() -> Result<()> {
+        let dir = tempdir()?;
+        let split = dir.path().join("Game.nsp");
+        std::fs::create_dir(&split)?;
+        std::fs::write(split.join("00"), b"hello ")?;
+        std::fs::write(split.join("01"), b"split ")?;
+        std::fs::write(split.join("02"), b"world")?;
+        std::fs::write(split.join(".DS_Store"), b"ignored")?;
+
+        let parts = SplitParts::detect(&split)?.ok_or_else(|| anyhow::anyhow!("not split"))?;
+        assert_eq!(parts.len(), 17);
+
+        let mut joined = String::new();
+        parts.reader().read_to_string(&mut joined)?;
+        assert_eq!(joined, "hello split world");
+
+        let mut reader = parts.reader();
+        reader.seek(SeekFrom::Start(4))?;
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"o sp");
+
+        let chunks: Vec<_> = parts.stream_range(4, 9, 4).try_collect().await?;
+        assert_eq!(chunks.concat(), b"o split w");
+        Ok(())
+    }
+
+    #[test]
+    fn gaps_and_plain_directories_are_not_split_dumps() -> Result<()> {
+        let dir = tempdir()?;
+        let gap = dir.path().join("Gap.nsp");
+        std::fs::create_dir(&gap)?;
+        std::fs::write(gap.join("00"), b"a")?;
+        std::fs::write(gap.join("02"), b"c")?;
+        assert_eq!(SplitParts::detect(&gap)?, None);
+
+        let plain = dir.path().join("Games");
+        std::fs::create_dir(&plain)?;
+        std::fs::write(plain.join("00"), b"a")?;
+        assert_eq!(SplitParts::detect(&plain)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+//! Download statistics: per-file download counts since the last library report, and
+//! running totals of completed downloads and bytes served, per file and per client.
+//!
+//! The totals survive restarts in `<data_dir>/downloads.json`, written every
+//! [`SAVE_INTERVAL`] while they change and on shutdown.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const STATS_FILE: &str = "downloads.json";
+/// How often changed totals are written to disk.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Completed downloads and bytes served.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    pub downloads: u64,
+    pub bytes: u64,
+}
+
+impl Tally {
+    fn add(&mut self, bytes: u64, completed: bool) {
+        self.downloads += u64::from(completed);
+        self.bytes = self.bytes.saturating_add(bytes);
+    }
+}
+
+/// Running totals since the statistics were first kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Totals {
+    /// By library-relative path.
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, Tally>,
+    /// By user name, or IP address for anonymous clients.
+    #[serde(default)]
+    pub clients: BTreeMap<String, Tally>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DownloadStats {
+    counts: Arc<DashMap<PathBuf, u64>>,
+    totals: Arc<Mutex<Totals>>,
+    /// Totals changed since they were last saved.
+    dirty: Arc<AtomicBool>,
+    /// Where totals are kept; only in memory when unset.
+    store_path: Option<PathBuf>,
+}
+
+impl DownloadStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load totals from `data_dir`, starting empty if the file is missing or invalid.
+    pub fn load(data_dir: &Path) -> Self {
+        let store_path = data_dir.join(STATS_FILE);
+        let totals = match std::fs::read_to_string(&store_path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+                warn!(path = %store_path.display(), error = %err, "ignoring invalid download stats");
+                Totals::default()
+            }),
+            Err(_) => Totals::default(),
+        };
+        Self {
+            totals: Arc::new(Mutex::new(totals)),
+            store_path: Some(store_path),
+            ..Self::default()
+        }
+    }
+
+    /// Count a download of `relative_path`.
+    pub fn record(&self, relative_path: &Path) {
+        *self.counts.entry(relative_path.to_path_buf()).or_default() += 1;
+    }
+
+    /// Add `bytes` of `relative_path` sent to `client`, and a completed download when the
+    /// response delivered the end of the file.
+    pub fn served(&self, relative_path: &Path, client: &str, bytes: u64, completed: bool) {
+        if bytes == 0 && !completed {
+            return;
+        }
+        let mut totals = self.totals.lock().unwrap_or_else(|p| p.into_inner());
+        totals
+            .files
+            .entry(relative_path.to_path_buf())
+            .or_default()
+            .add(bytes, completed);
+        totals
+            .clients
+            .entry(client.to_string())
+            .or_default()
+            .add(bytes, completed);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Completed downloads of `relative_path`.
+    pub fn download_count(&self, relative_path: &Path) -> u64 {
+        let totals = self.totals.lock().unwrap_or_else(|p| p.into_inner());
+        totals
+            .files
+            .get(relative_path)
+            .map_or(0, |tally| tally.downloads)
+    }
+
+    pub fn totals(&self) -> Totals {
+        self.totals
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+
+    /// Return the counts gathered so far, highest first, and start a new period.
+    pub fn take(&self) -> Vec<(PathBuf, u64)> {
+        let keys = self
+            .counts
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        let mut counts = keys
+            .into_iter()
+            .filter_map(|key| self.counts.remove(&key))
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+    /// Write the totals if they changed since the last save.
+    pub async fn save(&self) -> std::io::Result<()> {
+        let Some(store_path) = &self.store_path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let raw = serde_json::to_string(&self.totals()).map_err(std::io::Error::other)?;
+        let result = async {
+            if let Some(parent) = store_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let temp = store_path.with_extension("json.tmp");
+            tokio::fs::write(&temp, raw).await?;
+            tokio::fs::rename(&temp, store_path).await
+        }
+        .await;
+        if result.is_err() {
+            // Try again next time.
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+/// Save changed totals every [`SAVE_INTERVAL`].
+pub fn spawn_stats_saver(stats: DownloadStats) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SAVE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = stats.save().await {
+                warn!(error = %err, "failed to save download stats");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::Result;
+
+    use super::{DownloadStats, Tally};
+
+    #[test]
+    fn take_returns_sorted_counts_and_resets() {
+        let stats = DownloadStats::new();
+        stats.record(Path::new("a.nsp"));
+        stats.record(Path::new("b.nsp"));
+        stats.record(Path::new("b.nsp"));
+
+        assert_eq!(
+            stats.take(),
+            vec![(PathBuf::from("b.nsp"), 2), (PathBuf::from("a.nsp"), 1)]
+        );
+        assert!(stats.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn totals_are_kept_per_file_and_client_across_restarts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let stats = DownloadStats::load(dir.path());
+        // A download resumed once: the first part doesn't complete it.
+        stats.served(Path::new("a.nsp"), "switch", 600, false);
+        stats.served(Path::new("a.nsp"), "switch", 400, true);
+        stats.served(Path::new("a.nsp"), "192.168.1.20", 1000, true);
+        stats.served(Path::new("b.nsp"), "switch", 0, false);
+        stats.save().await?;
+
+        let stats = DownloadStats::load(dir.path());
+        assert_eq!(stats.download_count(Path::new("a.nsp")), 2);
+        assert_eq!(stats.download_count(Path::new("b.nsp")), 0);
+        let totals = stats.totals();
+        assert_eq!(totals.files.len(), 1);
+        assert_eq!(
+            totals.clients["switch"],
+            Tally {
+                downloads: 1,
+                bytes: 1000
+            }
+        );
+        assert_eq!(totals.clients["192.168.1.20"].downloads, 1);
+        Ok(())
+    }
+}
@@ -1,121 +1,12 @@
-//! TitleDB integration: fetch game metadata (icon/banner URLs) from multiple sources.
-//! Fetches concurrently from all sources and merges results redundantly.
+//! Fetching TitleDB: downloads and parses the sources, merges them, and keeps a cache in
+//! the data dir for when the network is unavailable.
 
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
 
 use tokio::sync::{broadcast, RwLock};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, info, warn};
 
-use crate::config::TitleDbConfig;
-
-/// Per-title metadata from TitleDB.
-///
-/// Used to enrich shop section items with icon/banner URLs and display names.
-#[derive(Debug, Clone)]
-pub struct TitleInfo {
-    /// CDN URL for the game icon (e.g. Nintendo eShop).
-    pub icon_url: Option<String>,
-    /// CDN URL for the banner image.
-    pub banner_url: Option<String>,
-    /// Localized game name.
-    pub name: Option<String>,
-}
-
-/// Lazy-loaded TitleDB cache. Loads from disk on first access, refreshes in background.
-#[derive(Debug, Clone)]
-pub struct TitleDb {
-    inner: Arc<RwLock<TitleDbInner>>,
-}
-
-#[derive(Debug)]
-struct TitleDbInner {
-    map: HashMap<String, TitleInfo>,
-    config: TitleDbConfig,
-    data_dir: PathBuf,
-    last_refresh: Option<std::time::Instant>,
-    progress_tx: Option<broadcast::Sender<String>>,
-}
-
-impl TitleDb {
-    #[allow(dead_code)]
-    pub fn new(config: TitleDbConfig, data_dir: PathBuf) -> Self {
-        Self::with_progress(config, data_dir, None)
-    }
-
-    /// Create a TitleDB instance with optional progress broadcast channel.
-    ///
-    /// Progress messages are sent during refresh (e.g. for SSE in the admin UI).
-    pub fn with_progress(
-        config: TitleDbConfig,
-        data_dir: PathBuf,
-        progress_tx: Option<broadcast::Sender<String>>,
-    ) -> Self {
-        info!(
-            enabled = config.enabled,
-            region = %config.region,
-            language = %config.language,
-            refresh_interval = %config.refresh_interval,
-            data_dir = %data_dir.display(),
-            "titledb initialized"
-        );
-        Self {
-            inner: Arc::new(RwLock::new(TitleDbInner {
-                map: HashMap::new(),
-                config,
-                data_dir,
-                last_refresh: None,
-                progress_tx,
-            })),
-        }
-    }
-
-    #[allow(dead_code)]
-    pub async fn progress_subscribe(&self) -> Option<broadcast::Receiver<String>> {
-        self.inner
-            .read()
-            .await
-            .progress_tx
-            .as_ref()
-            .map(|tx| tx.subscribe())
-    }
-
-    /// Look up icon and banner URLs for a title ID (16-char hex, uppercase).
-    pub async fn lookup(&self, title_id: &str) -> Option<TitleInfo> {
-        let normalized = title_id.to_uppercase();
-        let guard = self.inner.read().await;
-        guard.map.get(&normalized).cloned()
-    }
-
-    /// Trigger a refresh. Returns immediately; refresh runs in background.
-    /// Fetch runs without holding the lock so lookups remain fast during refresh.
-    pub fn refresh(&self) {
-        debug!("titledb refresh triggered");
-        let inner = Arc::clone(&self.inner);
-        tokio::spawn(async move {
-            if let Err(e) = do_refresh_without_lock(&inner).await {
-                error!(error = %e, "titledb refresh failed");
-            }
-        });
-    }
-
-    pub async fn config(&self) -> TitleDbConfig {
-        self.inner.read().await.config.clone()
-    }
-
-    pub async fn set_config(&self, config: TitleDbConfig) {
-        self.inner.write().await.config = config;
-    }
-
-    pub async fn last_refresh(&self) -> Option<std::time::Instant> {
-        self.inner.read().await.last_refresh
-    }
-
-    pub async fn entry_count(&self) -> usize {
-        self.inner.read().await.map.len()
-    }
-}
+use super::{TitleDbInner, TitleInfo};
 
 fn send_progress(tx: &Option<broadcast::Sender<String>>, msg: &str) {
     if let Some(tx) = tx {
@@ -124,7 +15,9 @@ fn send_progress(tx: &Option<broadcast::Sender<String>>, msg: &str) {
 }
 
 /// Fetch and merge TitleDB data without holding the lock, then apply in a short write.
-async fn do_refresh_without_lock(inner: &RwLock<TitleDbInner>) -> Result<(), TitleDbError> {
+pub(super) async fn do_refresh_without_lock(
+    inner: &RwLock<TitleDbInner>,
+) -> Result<(), TitleDbError> {
     let (enabled, region, lang, url_override, data_dir, progress_tx) = {
         let guard = inner.read().await;
         if !guard.config.enabled {
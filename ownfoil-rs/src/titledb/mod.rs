@@ -0,0 +1,240 @@
+//! TitleDB integration: fetch game metadata (icon/banner URLs) from multiple sources.
+//! Fetches concurrently from all sources and merges results redundantly. Without the
+//! `titledb` feature nothing is fetched and lookups find nothing.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, RwLock};
+#[cfg(feature = "titledb")]
+use tracing::error;
+use tracing::{debug, info};
+
+use crate::catalog::{classify_title_id, derive_base_title_id, ContentKind};
+use crate::config::TitleDbConfig;
+
+#[cfg(feature = "titledb")]
+mod fetch;
+
+/// Per-title metadata from TitleDB.
+///
+/// Used to enrich shop section items with icon/banner URLs and display names.
+#[derive(Debug, Clone)]
+pub struct TitleInfo {
+    /// CDN URL for the game icon (e.g. Nintendo eShop).
+    pub icon_url: Option<String>,
+    /// CDN URL for the banner image.
+    pub banner_url: Option<String>,
+    /// Localized game name.
+    pub name: Option<String>,
+}
+
+/// Lazy-loaded TitleDB cache. Loads from disk on first access, refreshes in background.
+#[derive(Debug, Clone)]
+pub struct TitleDb {
+    inner: Arc<RwLock<TitleDbInner>>,
+}
+
+#[derive(Debug)]
+struct TitleDbInner {
+    map: HashMap<String, TitleInfo>,
+    config: TitleDbConfig,
+    #[cfg_attr(not(feature = "titledb"), allow(dead_code))]
+    data_dir: PathBuf,
+    last_refresh: Option<std::time::Instant>,
+    progress_tx: Option<broadcast::Sender<String>>,
+}
+
+impl TitleDb {
+    #[allow(dead_code)]
+    pub fn new(config: TitleDbConfig, data_dir: PathBuf) -> Self {
+        Self::with_progress(config, data_dir, None)
+    }
+
+    /// Create a TitleDB instance with optional progress broadcast channel.
+    ///
+    /// Progress messages are sent during refresh (e.g. for SSE in the admin UI).
+    pub fn with_progress(
+        config: TitleDbConfig,
+        data_dir: PathBuf,
+        progress_tx: Option<broadcast::Sender<String>>,
+    ) -> Self {
+        info!(
+            enabled = config.enabled,
+            region = %config.region,
+            language = %config.language,
+            refresh_interval = %config.refresh_interval,
+            data_dir = %data_dir.display(),
+            "titledb initialized"
+        );
+        Self {
+            inner: Arc::new(RwLock::new(TitleDbInner {
+                map: HashMap::new(),
+                config,
+                data_dir,
+                last_refresh: None,
+                progress_tx,
+            })),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn progress_subscribe(&self) -> Option<broadcast::Receiver<String>> {
+        self.inner
+            .read()
+            .await
+            .progress_tx
+            .as_ref()
+            .map(|tx| tx.subscribe())
+    }
+
+    /// Look up icon and banner URLs for a title ID (16-char hex, uppercase).
+    pub async fn lookup(&self, title_id: &str) -> Option<TitleInfo> {
+        let normalized = title_id.to_uppercase();
+        let guard = self.inner.read().await;
+        guard.map.get(&normalized).cloned()
+    }
+
+    /// Trigger a refresh. Returns immediately; refresh runs in background.
+    /// Fetch runs without holding the lock so lookups remain fast during refresh.
+    #[cfg(feature = "titledb")]
+    pub fn refresh(&self) {
+        debug!("titledb refresh triggered");
+        let inner = Arc::clone(&self.inner);
+        tokio::spawn(async move {
+            if let Err(e) = fetch::do_refresh_without_lock(&inner).await {
+                error!(error = %e, "titledb refresh failed");
+            }
+        });
+    }
+
+    /// Built without the `titledb` feature: there is nothing to fetch.
+    #[cfg(not(feature = "titledb"))]
+    pub fn refresh(&self) {
+        debug!("titledb refresh skipped (built without the titledb feature)");
+    }
+
+    pub async fn config(&self) -> TitleDbConfig {
+        self.inner.read().await.config.clone()
+    }
+
+    pub async fn set_config(&self, config: TitleDbConfig) {
+        self.inner.write().await.config = config;
+    }
+
+    pub async fn last_refresh(&self) -> Option<std::time::Instant> {
+        self.inner.read().await.last_refresh
+    }
+
+    pub async fn entry_count(&self) -> usize {
+        self.inner.read().await.map.len()
+    }
+
+    /// DLC title IDs known to TitleDB for each of `base_title_ids`, with their names.
+    /// DLC are matched to base titles by title ID (the base ID's high bits plus one).
+    pub async fn dlc_for(
+        &self,
+        base_title_ids: &HashSet<String>,
+    ) -> BTreeMap<String, Vec<(String, Option<String>)>> {
+        let guard = self.inner.read().await;
+        group_dlc_by_base(
+            guard
+                .map
+                .iter()
+                .map(|(id, info)| (id.as_str(), info.name.as_deref())),
+            base_title_ids,
+        )
+    }
+
+    /// TitleDB names for `title_ids`, falling back to the base title's name for updates
+    /// and DLC TitleDB has no entry for.
+    pub async fn names_for<'a>(
+        &self,
+        title_ids: impl IntoIterator<Item = &'a str>,
+    ) -> HashMap<String, String> {
+        let guard = self.inner.read().await;
+        title_ids
+            .into_iter()
+            .filter_map(|title_id| {
+                let normalized = title_id.to_uppercase();
+                let name = guard
+                    .map
+                    .get(&normalized)
+                    .and_then(|info| info.name.clone())
+                    .or_else(|| {
+                        let kind = classify_title_id(Some(&normalized));
+                        derive_base_title_id(kind, Some(&normalized))
+                            .and_then(|base| guard.map.get(&base))
+                            .and_then(|info| info.name.clone())
+                    })?;
+                Some((title_id.to_string(), name))
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    pub async fn insert(&self, title_id: &str, info: TitleInfo) {
+        self.inner
+            .write()
+            .await
+            .map
+            .insert(title_id.to_uppercase(), info);
+    }
+}
+
+fn group_dlc_by_base<'a>(
+    entries: impl Iterator<Item = (&'a str, Option<&'a str>)>,
+    base_title_ids: &HashSet<String>,
+) -> BTreeMap<String, Vec<(String, Option<String>)>> {
+    let mut out: BTreeMap<String, Vec<(String, Option<String>)>> = BTreeMap::new();
+    for (id, name) in entries {
+        if classify_title_id(Some(id)) != ContentKind::Dlc {
+            continue;
+        }
+        let Some(base) = derive_base_title_id(ContentKind::Dlc, Some(id)) else {
+            continue;
+        };
+        if base_title_ids.contains(&base) {
+            out.entry(base)
+                .or_default()
+                .push((id.to_string(), name.map(String::from)));
+        }
+    }
+    for dlc in out.values_mut() {
+        dlc.sort();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::group_dlc_by_base;
+
+    #[test]
+    fn dlc_are_grouped_under_owned_base_titles() {
+        let entries = [
+            ("0100ABCD12340000", Some("Game")),
+            ("0100ABCD12341001", Some("Game DLC 1")),
+            ("0100ABCD12341002", None),
+            ("0100ABCD12340800", Some("Game Update")),
+            ("0100FFFF00001001", Some("Other DLC")),
+        ];
+        let owned = HashSet::from([String::from("0100ABCD12340000")]);
+        let grouped = group_dlc_by_base(entries.into_iter(), &owned);
+
+        assert_eq!(grouped.len(), 1);
+        assert_eq!(
+            grouped.get("0100ABCD12340000"),
+            Some(&vec![
+                (
+                    String::from("0100ABCD12341001"),
+                    Some(String::from("Game DLC 1"))
+                ),
+                (String::from("0100ABCD12341002"), None),
+            ])
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Trash: library files deleted through the admin API are moved aside, never erased.
+//!
+//! A deleted file (or split dump directory) goes to
+//! `<root>/<trash dir>/<id>/<relative path>`, next to an `entry.json` recording where it
+//! came from. From there it can be restored to the same place or purged for good. The
+//! trash directory is named by `[scan] trash_dir` and is never scanned.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::jobs::unix_now;
+
+const ENTRY_FILE: &str = "entry.json";
+
+/// A file in the trash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    pub root: PathBuf,
+    /// Where the file was, relative to `root`; it is restored there.
+    pub relative_path: PathBuf,
+    pub size: u64,
+    /// Unix seconds when the file was deleted.
+    pub deleted_at: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum TrashError {
+    #[error("trash entry not found")]
+    NotFound,
+    #[error("a file already exists at {0}")]
+    Exists(String),
+    #[error("trash i/o failed for {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("unreadable trash entry {path}: {source}")]
+    Entry {
+        path: String,
+        source: serde_json::Error,
+    },
+}
+
+fn io_err(path: &Path) -> impl FnOnce(std::io::Error) -> TrashError {
+    let path = path.display().to_string();
+    move |source| TrashError::Io { path, source }
+}
+
+/// The trash directories of a set of library roots.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    /// Name of the trash directory inside each root.
+    name: String,
+}
+
+impl Trash {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+
+    /// Trash directory of `root`.
+    pub fn dir(&self, root: &Path) -> PathBuf {
+        root.join(&self.name)
+    }
+
+    /// Whether `relative_path`, relative to a root, lies inside its trash directory.
+    pub fn contains(&self, relative_path: &Path) -> bool {
+        relative_path
+            .components()
+            .next()
+            .and_then(|first| first.as_os_str().to_str())
+            .is_some_and(|first| first.eq_ignore_ascii_case(&self.name))
+    }
+
+    /// Move `relative_path` under `root` into the trash.
+    pub async fn delete(
+        &self,
+        root: &Path,
+        relative_path: &Path,
+        size: u64,
+    ) -> Result<TrashEntry, TrashError> {
+        let source = root.join(relative_path);
+        tokio::fs::symlink_metadata(&source)
+            .await
+            .map_err(|err| match err.kind() {
+                ErrorKind::NotFound => TrashError::NotFound,
+                _ => io_err(&source)(err),
+            })?;
+
+        let entry = TrashEntry {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            root: root.to_path_buf(),
+            relative_path: relative_path.to_path_buf(),
+            size,
+            deleted_at: unix_now(),
+        };
+        let item = self.dir(root).join(&entry.id);
+        let destination = item.join(relative_path);
+        if let Some(parent) = destination.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(io_err(parent))?;
+        }
+        write_entry(&item, &entry).await?;
+        if let Err(err) = tokio::fs::rename(&source, &destination).await {
+            let _ = tokio::fs::remove_dir_all(&item).await;
+            return Err(io_err(&source)(err));
+        }
+        Ok(entry)
+    }
+
+    /// Everything in the trash of `roots`, newest first. Unreadable entries are skipped.
+    pub async fn list(&self, roots: &[PathBuf]) -> Vec<TrashEntry> {
+        let mut entries = Vec::new();
+        for root in roots {
+            let Ok(mut items) = tokio::fs::read_dir(self.dir(root)).await else {
+                continue;
+            };
+            while let Ok(Some(item)) = items.next_entry().await {
+                if let Ok(entry) = read_entry(&item.path()).await {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+        entries
+    }
+
+    /// Find the entry `id` in the trash of one of `roots`.
+    pub async fn find(&self, roots: &[PathBuf], id: &str) -> Result<TrashEntry, TrashError> {
+        // IDs are generated as hex; anything else could escape the trash directory.
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(TrashError::NotFound);
+        }
+        for root in roots {
+            let item = self.dir(root).join(id);
+            match read_entry(&item).await {
+                Ok(entry) => return Ok(entry),
+                Err(TrashError::Io { source, .. }) if source.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(TrashError::NotFound)
+    }
+
+    /// Move the entry `id` back to where it was deleted from.
+    pub async fn restore(&self, roots: &[PathBuf], id: &str) -> Result<TrashEntry, TrashError> {
+        let entry = self.find(roots, id).await?;
+        let item = self.dir(&entry.root).join(&entry.id);
+        let destination = entry.root.join(&entry.relative_path);
+        if tokio::fs::symlink_metadata(&destination).await.is_ok() {
+            return Err(TrashError::Exists(
+                entry.relative_path.display().to_string(),
+            ));
+        }
+        if let Some(parent) = destination.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(io_err(parent))?;
+        }
+        let trashed = item.join(&entry.relative_path);
+        tokio::fs::rename(&trashed, &destination)
+            .await
+            .map_err(io_err(&trashed))?;
+        tokio::fs::remove_dir_all(&item)
+            .await
+            .map_err(io_err(&item))?;
+        Ok(entry)
+    }
+
+    /// Permanently delete the entry `id`.
+    pub async fn purge(&self, roots: &[PathBuf], id: &str) -> Result<TrashEntry, TrashError> {
+        let entry = self.find(roots, id).await?;
+        let item = self.dir(&entry.root).join(&entry.id);
+        tokio::fs::remove_dir_all(&item)
+            .await
+            .map_err(io_err(&item))?;
+        Ok(entry)
+    }
+}
+
+async fn write_entry(item: &Path, entry: &TrashEntry) -> Result<(), TrashError> {
+    let path = item.join(ENTRY_FILE);
+    let json = serde_json::to_vec_pretty(entry).map_err(|source| TrashError::Entry {
+        path: path.display().to_string(),
+        source,
+    })?;
+    tokio::fs::write(&path, json).await.map_err(io_err(&path))
+}
+
+async fn read_entry(item: &Path) -> Result<TrashEntry, TrashError> {
+    let path = item.join(ENTRY_FILE);
+    let raw = tokio::fs::read(&path).await.map_err(io_err(&path))?;
+    serde_json::from_slice(&raw).map_err(|source| TrashError::Entry {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tokio::fs;
+
+    use super::{Trash, TrashError};
+
+    #[tokio::test]
+    async fn deleted_files_can_be_restored_or_purged() -> Result<()> {
+        let library = tempdir()?;
+        let roots = vec![library.path().to_path_buf()];
+        fs::create_dir(library.path().join("Updates")).await?;
+        fs::write(library.path().join("Updates/old.nsp"), b"old").await?;
+        fs::write(library.path().join("other.nsp"), b"other").await?;
+        let trash = Trash::new(".trash");
+
+        let entry = trash
+            .delete(library.path(), "Updates/old.nsp".as_ref(), 3)
+            .await?;
+        assert!(!library.path().join("Updates/old.nsp").exists());
+        assert_eq!(trash.list(&roots).await, vec![entry.clone()]);
+
+        // Something new took its place: restoring must not overwrite it.
+        fs::write(library.path().join("Updates/old.nsp"), b"new").await?;
+        assert!(matches!(
+            trash.restore(&roots, &entry.id).await,
+            Err(TrashError::Exists(_))
+        ));
+        fs::remove_file(library.path().join("Updates/old.nsp")).await?;
+        trash.restore(&roots, &entry.id).await?;
+        assert_eq!(
+            fs::read(library.path().join("Updates/old.nsp")).await?,
+            b"old"
+        );
+        assert!(trash.list(&roots).await.is_empty());
+
+        let entry = trash
+            .delete(library.path(), "other.nsp".as_ref(), 5)
+            .await?;
+        trash.purge(&roots, &entry.id).await?;
+        assert!(trash.list(&roots).await.is_empty());
+        assert!(!library.path().join("other.nsp").exists());
+        assert!(matches!(
+            trash.purge(&roots, "../..").await,
+            Err(TrashError::NotFound)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Zero-downtime upgrades: on `SIGUSR2` the server starts its binary again (usually just
+//! replaced by a newer version) with the same arguments and hands it the listening
+//! socket. Once the new process is serving, the old one stops accepting connections and
+//! exits after the open ones, including long downloads, have finished.
+//!
+//! The socket is passed as the new process's standard input, which needs no unsafe code;
+//! `OWNFOIL_INHERIT_LISTENER` tells it to take the socket from there. The new process
+//! creates the file named by `OWNFOIL_UPGRADE_READY`, in the data directory, once it is
+//! listening. If it exits, or isn't ready within [`READY_TIMEOUT`], the upgrade is
+//! abandoned and the old process keeps serving. Unix only.
+//!
+//! Both variables are read, and cleared, by [`Handoff::from_env`] before the runtime
+//! starts any threads.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpSocket};
+use tracing::warn;
+
+const INHERIT_ENV: &str = "OWNFOIL_INHERIT_LISTENER";
+const READY_ENV: &str = "OWNFOIL_UPGRADE_READY";
+/// How long the new process may take to load its catalog and start serving.
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What the previous process passed on, if this is an upgrade.
+#[derive(Debug, Default)]
+pub struct Handoff {
+    inherit: bool,
+    ready: Option<PathBuf>,
+}
+
+impl Handoff {
+    /// Take the upgrade variables out of the environment, so that nothing this process
+    /// starts inherits them. Call before any other thread exists.
+    pub fn from_env() -> Self {
+        let handoff = Self {
+            inherit: std::env::var_os(INHERIT_ENV).is_some(),
+            ready: std::env::var_os(READY_ENV).map(PathBuf::from),
+        };
+        std::env::remove_var(INHERIT_ENV);
+        std::env::remove_var(READY_ENV);
+        handoff
+    }
+}
+
+/// The listening socket handed over by the previous process, if this is an upgrade.
+#[cfg(unix)]
+pub fn inherited_listener(handoff: &Handoff) -> io::Result<Option<TcpListener>> {
+    use std::os::fd::AsFd;
+
+    if !handoff.inherit {
+        return Ok(None);
+    }
+    let socket = io::stdin().as_fd().try_clone_to_owned()?;
+    let listener = std::net::TcpListener::from(socket);
+    // Fails unless standard input really is a socket.
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener).map(Some)
+}
+
+#[cfg(not(unix))]
+pub fn inherited_listener(_handoff: &Handoff) -> io::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+/// Bind the listener that is handed over on upgrades. With `v6_only`, an IPv6 address
+/// doesn't also take IPv4 connections, so `[::]` and `0.0.0.0` can be bound side by side.
+pub fn bind(addr: SocketAddr, v6_only: bool) -> io::Result<TcpListener> {
+    bind_socket(addr, v6_only, false)
+}
+
+/// Bind a secondary listener that isn't handed over, allowing the address to be shared
+/// so a new process can bind it while the old one is still finishing.
+pub fn bind_shared(addr: SocketAddr, v6_only: bool) -> io::Result<TcpListener> {
+    bind_socket(addr, v6_only, true)
+}
+
+fn bind_socket(addr: SocketAddr, v6_only: bool, shared: bool) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    if shared {
+        socket.set_reuseport(true)?;
+    }
+    #[cfg(unix)]
+    if v6_only && addr.is_ipv6() {
+        rustix::net::sockopt::set_ipv6_v6only(&socket, true)?;
+    }
+    #[cfg(not(unix))]
+    let _ = (v6_only, shared);
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+/// Tell the previous process, if any, that this one is serving.
+pub fn announce_ready(handoff: &Handoff) {
+    let Some(path) = &handoff.ready else {
+        return;
+    };
+    if let Err(err) = std::fs::write(path, std::process::id().to_string()) {
+        warn!(path = %path.display(), error = %err, "failed to signal upgrade readiness");
+    }
+}
+
+/// Resolves once a `SIGUSR2` upgrade has handed `listener` to a new process that is
+/// serving; failed upgrades are logged and waited out. The new process signals readiness
+/// through a file in `data_dir`.
+#[cfg(unix)]
+pub fn handed_over(
+    listener: &TcpListener,
+    data_dir: &Path,
+) -> io::Result<impl Future<Output = ()>> {
+    use std::os::fd::AsFd;
+    use tokio::signal::unix::{signal, SignalKind};
+    use tracing::info;
+
+    let socket = listener.as_fd().try_clone_to_owned()?;
+    let mut signals = signal(SignalKind::user_defined2())?;
+    let ready = data_dir.join(format!("upgrade-{}.ready", std::process::id()));
+    Ok(async move {
+        while signals.recv().await.is_some() {
+            info!("upgrade requested; starting new process");
+            match start_successor(&socket, &ready).await {
+                Ok(pid) => {
+                    info!(pid, "new process is serving; finishing open connections");
+                    return;
+                }
+                Err(err) => warn!(error = %err, "upgrade failed; still serving"),
+            }
+        }
+        std::future::pending().await
+    })
+}
+
+#[cfg(not(unix))]
+pub fn handed_over(
+    _listener: &TcpListener,
+    _data_dir: &Path,
+) -> io::Result<impl Future<Output = ()>> {
+    Ok(std::future::pending())
+}
+
+/// Start this binary again on `socket` and wait until it is serving. Returns its pid.
+#[cfg(unix)]
+async fn start_successor(socket: &std::os::fd::OwnedFd, ready: &Path) -> io::Result<u32> {
+    use std::process::{Command, Stdio};
+    use std::time::Instant;
+
+    let _ = std::fs::remove_file(ready);
+    let mut args = std::env::args_os();
+    // argv[0] rather than current_exe(): on Linux the latter names the replaced binary.
+    let program = match args.next() {
+        Some(program) => std::path::PathBuf::from(program),
+        None => std::env::current_exe()?,
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::from(socket.try_clone()?))
+        .env(INHERIT_ENV, "1")
+        .env(READY_ENV, ready)
+        .spawn()?;
+
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        if ready.exists() {
+            let _ = std::fs::remove_file(ready);
+            return Ok(child.id());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!("new process exited ({status})")));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "new process did not start serving in time",
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
@@ -0,0 +1,263 @@
+//! Uploads: `PUT /api/upload/{filename}` writes new content into `[uploads] dir`, a folder
+//! inside the first library root.
+//!
+//! The body goes to a `.part` file next to the destination. An upload may be sent in
+//! pieces with `Content-Range: bytes first-last/total`, each continuing where the `.part`
+//! file ends, so a dropped connection only costs the piece in flight; `GET` on the same
+//! URL tells where to continue. Once the file has all its bytes it is moved into place,
+//! never over a file that appeared there in the meantime, and its library root is
+//! rescanned.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures_util::stream::{Stream, StreamExt};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, Default)]
+pub struct Uploads {
+    /// Folder inside the first library root; uploads are off when unset.
+    dir: Option<PathBuf>,
+    /// Destinations with an upload request running.
+    active: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+/// A running upload request, which keeps others to the same file out until dropped.
+#[derive(Debug)]
+pub struct UploadGuard {
+    active: Arc<Mutex<HashSet<PathBuf>>>,
+    relative_path: PathBuf,
+}
+
+/// What a `Content-Range` header says about the bytes of one piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    pub first: u64,
+    /// Last byte, inclusive; unset for `bytes */total`, which sends none.
+    pub last: Option<u64>,
+    /// Size of the whole file; unset for `/*` while it isn't known yet.
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum UploadError {
+    #[error("failed to write {path}: {source}")]
+    Io { path: String, source: io::Error },
+    #[error("upload interrupted: {0}")]
+    Body(String),
+    #[error("body has more bytes than its range or the file")]
+    TooLong,
+}
+
+impl Uploads {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            active: Arc::default(),
+        }
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Claim `relative_path` for one request; `None` while another one is writing it.
+    pub fn begin(&self, relative_path: &Path) -> Option<UploadGuard> {
+        let mut active = self.active.lock().unwrap_or_else(|p| p.into_inner());
+        active
+            .insert(relative_path.to_path_buf())
+            .then(|| UploadGuard {
+                active: Arc::clone(&self.active),
+                relative_path: relative_path.to_path_buf(),
+            })
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap_or_else(|p| p.into_inner());
+        active.remove(&self.relative_path);
+    }
+}
+
+/// Parse `bytes first-last/total`, `bytes first-last/*`, or `bytes */total`.
+pub fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (span, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse::<u64>().ok()?),
+    };
+    if span.trim() == "*" {
+        return total.map(|total| ContentRange {
+            first: total,
+            last: None,
+            total: Some(total),
+        });
+    }
+    let (first, last) = span.split_once('-')?;
+    let first: u64 = first.trim().parse().ok()?;
+    let last: u64 = last.trim().parse().ok()?;
+    if last < first || total.is_some_and(|total| last >= total) {
+        return None;
+    }
+    Some(ContentRange {
+        first,
+        last: Some(last),
+        total,
+    })
+}
+
+/// Bytes already in `partial`, 0 when it doesn't exist.
+pub async fn partial_len(partial: &Path) -> u64 {
+    tokio::fs::metadata(partial)
+        .await
+        .map(|meta| meta.len())
+        .unwrap_or(0)
+}
+
+/// Write `body` to `partial` from byte `first`, starting the file over when `first` is 0,
+/// taking at most `limit` bytes. Returns the length of `partial` afterwards; what arrived
+/// before an error is kept for the next piece.
+pub async fn write_piece<S, E>(
+    partial: &Path,
+    first: u64,
+    body: S,
+    limit: Option<u64>,
+) -> Result<u64, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let io_err = |source| UploadError::Io {
+        path: partial.display().to_string(),
+        source,
+    };
+    if let Some(parent) = partial.parent() {
+        tokio::fs::create_dir_all(parent).await.map_err(io_err)?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(first == 0)
+        .append(first > 0)
+        .open(partial)
+        .await
+        .map_err(io_err)?;
+    let mut written = 0u64;
+    let mut body = std::pin::pin!(body);
+    let result = loop {
+        let chunk = match body.next().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(err)) => break Err(UploadError::Body(err.to_string())),
+            None => break Ok(()),
+        };
+        written += chunk.len() as u64;
+        if limit.is_some_and(|limit| written > limit) {
+            break Err(UploadError::TooLong);
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            break Err(io_err(err));
+        }
+    };
+    file.flush().await.map_err(io_err)?;
+    result.map(|()| first + written)
+}
+
+/// Move a finished upload from `partial` to `destination`, failing with
+/// [`io::ErrorKind::AlreadyExists`] instead of replacing a file that is already there,
+/// such as one an import wrote while the upload ran.
+pub async fn finish(partial: &Path, destination: &Path) -> io::Result<()> {
+    match tokio::fs::hard_link(partial, destination).await {
+        Ok(()) => tokio::fs::remove_file(partial).await,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+        // Filesystems without hard links (FAT, some SMB shares) only get the check.
+        Err(_) if !tokio::fs::try_exists(destination).await? => {
+            tokio::fs::rename(partial, destination).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures_util::stream;
+
+    use super::*;
+
+    #[test]
+    fn content_ranges_parse_with_known_or_unknown_totals() {
+        let range = |first, last, total| ContentRange { first, last, total };
+        assert_eq!(
+            parse_content_range("bytes 0-99/1000"),
+            Some(range(0, Some(99), Some(1000)))
+        );
+        assert_eq!(
+            parse_content_range("bytes 100-199/*"),
+            Some(range(100, Some(199), None))
+        );
+        assert_eq!(
+            parse_content_range("bytes */1000"),
+            Some(range(1000, None, Some(1000)))
+        );
+        assert_eq!(parse_content_range("bytes 900-1000/1000"), None);
+        assert_eq!(parse_content_range("bytes 5-4/10"), None);
+        assert_eq!(parse_content_range("bytes */*"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[tokio::test]
+    async fn pieces_append_and_keep_what_arrived_before_an_error() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let partial = dir.path().join("incoming").join("game.nsp.part");
+        let body = |chunks: &[&'static [u8]]| {
+            stream::iter(
+                chunks
+                    .iter()
+                    .map(|chunk| Ok::<_, Infallible>(Bytes::from_static(chunk)))
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(write_piece(&partial, 0, body(&[b"0123"]), None).await?, 4);
+        assert_eq!(
+            write_piece(&partial, 4, body(&[b"45", b"67"]), Some(4)).await?,
+            8
+        );
+        let too_long = write_piece(&partial, 8, body(&[b"89", b"ab"]), Some(3)).await;
+        assert!(matches!(too_long, Err(UploadError::TooLong)));
+        assert_eq!(tokio::fs::read(&partial).await?, b"0123456789");
+
+        let interrupted = stream::iter(vec![Ok(Bytes::from_static(b"xy")), Err("reset")]);
+        let result = write_piece(&partial, 0, interrupted, None).await;
+        assert!(matches!(result, Err(UploadError::Body(_))));
+        assert_eq!(partial_len(&partial).await, 2);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn finishing_never_replaces_an_existing_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let partial = dir.path().join("game.nsp.part");
+        let destination = dir.path().join("game.nsp");
+        tokio::fs::write(&partial, b"upload").await?;
+        tokio::fs::write(&destination, b"import").await?;
+
+        let err = finish(&partial, &destination).await.err();
+        assert_eq!(
+            err.map(|err| err.kind()),
+            Some(io::ErrorKind::AlreadyExists)
+        );
+        assert_eq!(tokio::fs::read(&destination).await?, b"import");
+
+        tokio::fs::remove_file(&destination).await?;
+        finish(&partial, &destination).await?;
+        assert_eq!(tokio::fs::read(&destination).await?, b"upload");
+        assert!(!partial.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+//! Changes to the auth file from the user management API (`/api/users`).
+//!
+//! Edits go through `toml_edit`, so comments, ordering, and the entries an edit doesn't
+//! touch stay as they were. Passwords set here are stored as Argon2 hashes. The edited
+//! file is checked like a reload before it replaces the old one, and an edit that would
+//! leave no enabled admin is refused. The new users take effect at once; the auth file
+//! watcher then sees nothing left to change.
+//!
+//! The flat `username`/`password` pair can only be admin, so changing its role, content
+//! filters, or `disabled` moves it into a `[[users]]` entry first.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+use thiserror::Error;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::artwork::normalize_title_id;
+use crate::auth::{
+    hash_password, parse_users, read_users, AuthFileError, AuthSettings, AuthUser, HashAlgorithm,
+    HashPasswordError, Role, SharedAuth,
+};
+use crate::catalog::ContentKind;
+
+/// A user to create, or the fields to change on an existing one. Fields left out keep
+/// their value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserChange {
+    pub username: String,
+    pub password: Option<String>,
+    pub role: Option<Role>,
+    /// An empty list allows every kind.
+    pub kinds: Option<Vec<ContentKind>>,
+    /// An empty list allows every title.
+    pub titles: Option<Vec<String>>,
+    pub disabled: Option<bool>,
+}
+
+impl UserChange {
+    /// Whether the change leaves the user an enabled admin without filters.
+    fn keeps_flat(&self) -> bool {
+        self.role.map_or(true, |role| role == Role::Admin)
+            && self.kinds.as_ref().map_or(true, Vec::is_empty)
+            && self.titles.as_ref().map_or(true, Vec::is_empty)
+            && self.disabled != Some(true)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum UserEditError {
+    #[error("users are not loaded from an auth file")]
+    NoFile,
+    #[error(transparent)]
+    File(#[from] AuthFileError),
+    #[error("invalid auth file {path}: {source}")]
+    Toml {
+        path: String,
+        source: toml_edit::TomlError,
+    },
+    #[error("users in {path} are not a list of tables")]
+    UsersLayout { path: String },
+    #[error("username must not be empty or contain ':'")]
+    InvalidUsername,
+    #[error("a new user needs a password")]
+    PasswordRequired,
+    #[error("password must not be empty")]
+    EmptyPassword,
+    #[error("{0:?} is not a title ID")]
+    InvalidTitleId(String),
+    #[error("no user named {0:?}")]
+    NotFound(String),
+    #[error("the change would leave no enabled admin")]
+    LastAdmin,
+    #[error(transparent)]
+    Hash(#[from] HashPasswordError),
+    #[error("failed to write auth file {path}: {source}")]
+    Write { path: String, source: io::Error },
+}
+
+impl SharedAuth {
+    /// Users in the auth file, disabled ones included.
+    pub fn file_users(&self) -> Result<Vec<AuthUser>, UserEditError> {
+        let path = self.file().ok_or(UserEditError::NoFile)?;
+        Ok(read_users(path)?)
+    }
+
+    /// Create `change.username`, or apply `change` to it. Returns the user as saved and
+    /// whether it was created.
+    pub fn save_user(&self, change: &UserChange) -> Result<(AuthUser, bool), UserEditError> {
+        let username = change.username.trim();
+        if username.is_empty() || username.contains(':') {
+            return Err(UserEditError::InvalidUsername);
+        }
+        let titles = change
+            .titles
+            .as_ref()
+            .map(|titles| {
+                titles
+                    .iter()
+                    .map(|title| {
+                        normalize_title_id(title)
+                            .ok_or_else(|| UserEditError::InvalidTitleId(title.clone()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        // Hashed before the file is locked: it takes a while on purpose.
+        let hash = match change.password.as_deref().map(str::trim) {
+            Some("") => return Err(UserEditError::EmptyPassword),
+            Some(password) => Some(hash_password(password, HashAlgorithm::Argon2)?),
+            None => None,
+        };
+
+        let mut created = false;
+        let users = self.edit_file(|doc, path| {
+            let flat = flat_username(doc) == Some(username);
+            let index = last_entry(users_mut(doc, path)?, username);
+            if index.is_none() && flat && change.keeps_flat() {
+                if let Some(hash) = &hash {
+                    set_password(doc.as_table_mut(), hash);
+                }
+                return Ok(());
+            }
+            let entry = match index {
+                Some(index) => index,
+                None if flat => {
+                    let mut entry = Table::new();
+                    for key in ["username", "password", "password_hash"] {
+                        if let Some(item) = doc.remove(key) {
+                            entry.insert(key, item);
+                        }
+                    }
+                    push_entry(users_mut(doc, path)?, entry)
+                }
+                None => {
+                    if hash.is_none() {
+                        return Err(UserEditError::PasswordRequired);
+                    }
+                    created = true;
+                    let mut entry = Table::new();
+                    entry.insert("username", value(username));
+                    push_entry(users_mut(doc, path)?, entry)
+                }
+            };
+            let Some(entry) = users_mut(doc, path)?.get_mut(entry) else {
+                return Ok(());
+            };
+            if let Some(hash) = &hash {
+                set_password(entry, hash);
+            }
+            if let Some(role) = change.role {
+                set_or_remove(entry, "role", role != Role::Admin, || {
+                    value(role_name(role))
+                });
+            }
+            if let Some(kinds) = &change.kinds {
+                set_or_remove(entry, "kinds", !kinds.is_empty(), || {
+                    value(kinds.iter().map(|kind| kind_name(*kind)).collect::<Array>())
+                });
+            }
+            if let Some(titles) = &titles {
+                set_or_remove(entry, "titles", !titles.is_empty(), || {
+                    value(titles.iter().map(String::as_str).collect::<Array>())
+                });
+            }
+            if let Some(disabled) = change.disabled {
+                set_or_remove(entry, "disabled", disabled, || value(true));
+            }
+            Ok(())
+        })?;
+
+        let user = users
+            .into_iter()
+            .rev()
+            .find(|user| user.username.trim() == username)
+            .ok_or_else(|| UserEditError::NotFound(username.to_string()))?;
+        Ok((user, created))
+    }
+
+    /// Delete every entry for `username`.
+    pub fn remove_user(&self, username: &str) -> Result<(), UserEditError> {
+        let username = username.trim();
+        self.edit_file(|doc, path| {
+            let flat = flat_username(doc) == Some(username);
+            if flat {
+                for key in ["username", "password", "password_hash"] {
+                    doc.remove(key);
+                }
+            }
+            let users = users_mut(doc, path)?;
+            let before = users.len();
+            users.retain(|entry| entry_username(entry) != Some(username));
+            if !flat && users.len() == before {
+                return Err(UserEditError::NotFound(username.to_string()));
+            }
+            Ok(())
+        })
+        .map(drop)
+    }
+
+    /// Apply `edit` to the auth file, check the result, write it, and swap in its users.
+    /// Returns the users as written.
+    fn edit_file(
+        &self,
+        edit: impl FnOnce(&mut DocumentMut, &Path) -> Result<(), UserEditError>,
+    ) -> Result<Vec<AuthUser>, UserEditError> {
+        let path = self.file().ok_or(UserEditError::NoFile)?;
+        let _edits = self.edits.lock().unwrap_or_else(|p| p.into_inner());
+        let raw = fs::read_to_string(path).map_err(|source| AuthFileError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        // A file that doesn't load is left for its owner to fix by hand.
+        parse_users(path, &raw)?;
+        let mut doc: DocumentMut = raw.parse().map_err(|source| UserEditError::Toml {
+            path: path.display().to_string(),
+            source,
+        })?;
+        edit(&mut doc, path)?;
+        if doc
+            .get("users")
+            .and_then(Item::as_array_of_tables)
+            .is_some_and(ArrayOfTables::is_empty)
+        {
+            doc.remove("users");
+        }
+
+        let raw = doc.to_string();
+        let users = parse_users(path, &raw)?;
+        let next = AuthSettings::from_users(users.clone());
+        if !next.has_admin() {
+            return Err(UserEditError::LastAdmin);
+        }
+        replace_file(path, &raw).map_err(|source| UserEditError::Write {
+            path: path.display().to_string(),
+            source,
+        })?;
+        self.store(next);
+        Ok(users)
+    }
+}
+
+fn flat_username(doc: &DocumentMut) -> Option<&str> {
+    doc.get("username").and_then(Item::as_str).map(str::trim)
+}
+
+fn entry_username(entry: &Table) -> Option<&str> {
+    entry.get("username").and_then(Item::as_str).map(str::trim)
+}
+
+/// Position of the entry for `username` that is in effect: the last one.
+fn last_entry(users: &ArrayOfTables, username: &str) -> Option<usize> {
+    users
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry_username(entry) == Some(username))
+        .map(|(index, _)| index)
+        .last()
+}
+
+fn push_entry(users: &mut ArrayOfTables, entry: Table) -> usize {
+    users.push(entry);
+    users.len() - 1
+}
+
+/// The `[[users]]` entries, creating them if missing and converting an inline
+/// `users = [...]` list.
+fn users_mut<'a>(
+    doc: &'a mut DocumentMut,
+    path: &Path,
+) -> Result<&'a mut ArrayOfTables, UserEditError> {
+    let item = doc
+        .entry("users")
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()));
+    if !item.is_array_of_tables() {
+        *item = std::mem::take(item)
+            .into_array_of_tables()
+            .map(Item::ArrayOfTables)
+            .map_err(|_| UserEditError::UsersLayout {
+                path: path.display().to_string(),
+            })?;
+    }
+    item.as_array_of_tables_mut()
+        .ok_or_else(|| UserEditError::UsersLayout {
+            path: path.display().to_string(),
+        })
+}
+
+/// Store `hash` as the password, dropping any plaintext one.
+fn set_password(entry: &mut Table, hash: &str) {
+    entry.remove("password");
+    entry.insert("password_hash", value(hash));
+}
+
+/// Set `key` when `set`, and leave it out (its default) otherwise.
+fn set_or_remove(entry: &mut Table, key: &str, set: bool, item: impl FnOnce() -> Item) {
+    if set {
+        entry.insert(key, item());
+    } else {
+        entry.remove(key);
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Admin => "admin",
+        Role::Download => "download",
+        Role::ViewOnly => "view_only",
+    }
+}
+
+fn kind_name(kind: ContentKind) -> &'static str {
+    match kind {
+        ContentKind::Base => "base",
+        ContentKind::Update => "update",
+        ContentKind::Dlc => "dlc",
+        ContentKind::Homebrew => "homebrew",
+        ContentKind::Unknown => "unknown",
+    }
+}
+
+/// Write `raw` to a temporary file next to `path` and rename it over `path`, keeping the
+/// old file's permissions. A file that can't be renamed over, like one bind-mounted into a
+/// container, is overwritten in place instead.
+fn replace_file(path: &Path, raw: &str) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut temp_name = path
+        .file_name()
+        .unwrap_or(OsStr::new("auth"))
+        .to_os_string();
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+    let written = (|| {
+        let mut file = File::create(&temp)?;
+        if let Ok(meta) = fs::metadata(&path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.write_all(raw.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+        fs::write(&path, raw)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::expect_used)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::auth::load_auth;
+
+    fn shared(path: &Path) -> Result<SharedAuth> {
+        Ok(SharedAuth::new(load_auth(Some(path))?).with_file(path))
+    }
+
+    #[test]
+    fn saving_users_edits_the_file_and_keeps_comments() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("auth.toml");
+        fs::write(
+            &path,
+            "# the owner\nusername = \"admin\"\npassword = \"secret\"\n\n# family\n[[users]]\nusername = \"kid\"\npassword = \"pw\"\n",
+        )?;
+        let auth = shared(&path)?;
+
+        let (user, created) = auth.save_user(&UserChange {
+            username: "friend".into(),
+            password: Some("hunter2".into()),
+            role: Some(Role::Download),
+            titles: Some(vec!["0100000000010800".into()]),
+            ..UserChange::default()
+        })?;
+        assert!(created);
+        assert_eq!(user.access.role, Role::Download);
+        assert!(auth.load().is_authorized("friend", "hunter2"));
+
+        let (kid, created) = auth.save_user(&UserChange {
+            username: "kid".into(),
+            disabled: Some(true),
+            ..UserChange::default()
+        })?;
+        assert!(!created);
+        assert!(kid.disabled);
+        assert!(!auth.load().has_user("kid"));
+
+        let raw = fs::read_to_string(&path)?;
+        assert!(raw.contains("# the owner") && raw.contains("# family"));
+        assert!(raw.contains("titles = [\"0100000000010800\"]"), "{raw}");
+        assert!(!raw.contains("hunter2"));
+        // The file still loads on its own, as the watcher would read it.
+        let reloaded = load_auth(Some(&path))?;
+        assert!(reloaded.is_authorized("friend", "hunter2"));
+        assert!(reloaded.is_authorized("admin", "secret"));
+
+        let listed = auth.file_users()?;
+        let names: Vec<_> = listed.iter().map(|user| user.username.as_str()).collect();
+        assert_eq!(names, ["admin", "friend", "kid"]);
+        Ok(())
+    }
+
+    #[test]
+    fn flat_user_moves_into_users_when_restricted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("auth.toml");
+        fs::write(
+            &path,
+            "username = \"admin\"\npassword = \"secret\"\n\n[[users]]\nusername = \"root\"\npassword = \"pw\"\n",
+        )?;
+        let auth = shared(&path)?;
+
+        auth.save_user(&UserChange {
+            username: "admin".into(),
+            password: Some("new".into()),
+            ..UserChange::default()
+        })?;
+        let raw = fs::read_to_string(&path)?;
+        assert!(
+            raw.starts_with("username = \"admin\"\npassword_hash = "),
+            "{raw}"
+        );
+
+        auth.save_user(&UserChange {
+            username: "admin".into(),
+            role: Some(Role::ViewOnly),
+            ..UserChange::default()
+        })?;
+        let reloaded = load_auth(Some(&path))?;
+        assert_eq!(
+            reloaded.access("admin").map(|access| access.role),
+            Some(Role::ViewOnly)
+        );
+        assert!(reloaded.is_authorized("admin", "new"));
+        Ok(())
+    }
+
+    #[test]
+    fn edits_that_lock_admins_out_are_refused() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("auth.toml");
+        let original = "[[users]]\nusername = \"admin\"\npassword = \"secret\"\n\n[[users]]\nusername = \"friend\"\npassword = \"pw\"\nrole = \"download\"\n";
+        fs::write(&path, original)?;
+        let auth = shared(&path)?;
+
+        assert!(matches!(
+            auth.remove_user("admin"),
+            Err(UserEditError::LastAdmin)
+        ));
+        assert!(matches!(
+            auth.save_user(&UserChange {
+                username: "admin".into(),
+                disabled: Some(true),
+                ..UserChange::default()
+            }),
+            Err(UserEditError::LastAdmin)
+        ));
+        assert!(matches!(
+            auth.save_user(&UserChange {
+                username: "new".into(),
+                ..UserChange::default()
+            }),
+            Err(UserEditError::PasswordRequired)
+        ));
+        assert!(matches!(
+            auth.remove_user("nobody"),
+            Err(UserEditError::NotFound(_))
+        ));
+        assert_eq!(fs::read_to_string(&path)?, original);
+
+        auth.remove_user("friend")?;
+        assert!(!auth.load().has_user("friend"));
+        assert!(!fs::read_to_string(&path)?.contains("friend"));
+        Ok(())
+    }
+}
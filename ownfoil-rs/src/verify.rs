@@ -0,0 +1,526 @@
+//! Integrity verification of NSP/XCI dumps.
+//!
+//! Opt-in via `[verify] enabled = true`. A background pass checks each file's container
+//! structure (every entry must lie inside the file) and the SHA-256 of each NCA against
+//! its file name, which for installable content is the first half of that hash. With a
+//! `prod.keys` file, NCA headers are also decrypted and their magic and size checked.
+//! Compressed NCAs (`.ncz`) can't be hashed without decompressing them and are skipped.
+//!
+//! Results are cached by absolute path (validated by size and modification time) in
+//! `<data_dir>/verification.json`, like content hashes. Files cached before the
+//! modification time was recorded are verified once more.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::archive::{is_archive, ArchiveEntry};
+use crate::container::{content_entries, Entry};
+use crate::split::ContentReader;
+
+const VERIFY_FILE: &str = "verification.json";
+const READ_BUFFER: usize = 1 << 20;
+/// NCA headers are encrypted in 0x200-byte sectors; the magic sits in the second one.
+const NCA_SECTOR: usize = 0x200;
+const NCA_HEADER_CHECKED: usize = 2 * NCA_SECTOR;
+const NCA_MAGIC_OFFSET: usize = 0x200;
+const NCA_SIZE_OFFSET: usize = 0x208;
+const NCA_MAGICS: [&[u8; 4]; 3] = [b"NCA3", b"NCA2", b"NCA0"];
+
+/// Outcome of verifying one file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Verification {
+    /// Structure and every checkable NCA are intact.
+    Ok {
+        ncas: usize,
+        /// Whether NCA headers were decrypted with `prod.keys`.
+        headers: bool,
+    },
+    /// The dump is damaged or incomplete.
+    Bad { reason: String },
+    /// Nothing could be checked (e.g. only compressed NCAs).
+    Unverifiable { reason: String },
+}
+
+impl Verification {
+    pub fn is_bad(&self) -> bool {
+        matches!(self, Verification::Bad { .. })
+    }
+
+    fn bad(reason: impl Into<String>) -> Self {
+        Verification::Bad {
+            reason: reason.into(),
+        }
+    }
+
+    fn unverifiable(reason: impl Into<String>) -> Self {
+        Verification::Unverifiable {
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Console keys read from a `prod.keys` file. Only the NCA header key is used.
+#[derive(Clone)]
+pub struct Keys {
+    header_key: [u8; 32],
+}
+
+impl std::fmt::Debug for Keys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Keys { .. }")
+    }
+}
+
+impl Keys {
+    /// Load `header_key` from a `prod.keys` file (`name = hex` lines).
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no valid header_key in {}", path.display()),
+            )
+        })
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        let value = raw.lines().find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            (name.trim() == "header_key").then(|| value.trim())
+        })?;
+        Some(Self {
+            header_key: decode_hex(value)?.try_into().ok()?,
+        })
+    }
+}
+
+/// Verify the file at `path`: a plain or split NSP/XCI, or a single-title zip archive.
+pub fn verify_file(path: &Path, keys: Option<&Keys>) -> io::Result<Verification> {
+    if is_archive(path) {
+        let Some(entry) = ArchiveEntry::find(path)? else {
+            return Ok(Verification::unverifiable("not a single-title archive"));
+        };
+        let Some(mut reader) = entry.stored_reader(path)? else {
+            return Ok(Verification::unverifiable("compressed archive entry"));
+        };
+        return verify_container(&mut reader, Path::new(&entry.name), keys);
+    }
+    let mut reader = ContentReader::open(path)?;
+    verify_container(&mut reader, path, keys)
+}
+
+fn verify_container<R: Read + Seek>(
+    reader: &mut R,
+    name: &Path,
+    keys: Option<&Keys>,
+) -> io::Result<Verification> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let Some(entries) = content_entries(reader, name) else {
+        return Ok(Verification::bad("not a valid NSP/XCI container"));
+    };
+    if let Some(entry) = entries.iter().find(|entry| {
+        entry
+            .offset
+            .checked_add(entry.size)
+            .map_or(true, |end| end > len)
+    }) {
+        return Ok(Verification::bad(format!(
+            "{} extends past the end of the file",
+            entry.name
+        )));
+    }
+
+    let ncas = entries
+        .iter()
+        .filter(|entry| entry.name.to_ascii_lowercase().ends_with(".nca"))
+        .collect::<Vec<_>>();
+    if ncas.is_empty() {
+        return Ok(Verification::unverifiable("no uncompressed NCAs to check"));
+    }
+    for entry in &ncas {
+        if let Some(keys) = keys {
+            if let Some(problem) = check_nca_header(reader, entry, keys)? {
+                return Ok(Verification::bad(problem));
+            }
+        }
+        if let Some(expected) = expected_hash_prefix(&entry.name) {
+            if hash_prefix(reader, entry)? != expected {
+                return Ok(Verification::bad(format!("{} hash mismatch", entry.name)));
+            }
+        }
+    }
+    Ok(Verification::Ok {
+        ncas: ncas.len(),
+        headers: keys.is_some(),
+    })
+}
+
+/// Installable NCAs are named after the first 16 bytes of their SHA-256.
+fn expected_hash_prefix(name: &str) -> Option<[u8; 16]> {
+    let stem = name.split('.').next()?;
+    if stem.len() != 32 {
+        return None;
+    }
+    decode_hex(stem)?.try_into().ok()
+}
+
+fn hash_prefix<R: Read + Seek>(reader: &mut R, entry: &Entry) -> io::Result<[u8; 16]> {
+    reader.seek(SeekFrom::Start(entry.offset))?;
+    let mut hasher = Sha256::new();
+    let mut remaining = entry.size;
+    let mut buf = vec![0u8; READ_BUFFER];
+    while remaining > 0 {
+        let want = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        reader.read_exact(&mut buf[..want])?;
+        hasher.update(&buf[..want]);
+        remaining -= want as u64;
+    }
+    let digest = hasher.finalize();
+    let mut prefix = [0u8; 16];
+    prefix.copy_from_slice(&digest[..16]);
+    Ok(prefix)
+}
+
+/// Decrypt the start of an NCA header and check its magic and recorded size.
+fn check_nca_header<R: Read + Seek>(
+    reader: &mut R,
+    entry: &Entry,
+    keys: &Keys,
+) -> io::Result<Option<String>> {
+    if entry.size < NCA_HEADER_CHECKED as u64 {
+        return Ok(Some(format!("{} is too small to be an NCA", entry.name)));
+    }
+    let mut header = [0u8; NCA_HEADER_CHECKED];
+    reader.seek(SeekFrom::Start(entry.offset))?;
+    reader.read_exact(&mut header)?;
+    xts(&mut header, &keys.header_key, 0, false);
+    if !NCA_MAGICS
+        .iter()
+        .any(|magic| header[NCA_MAGIC_OFFSET..NCA_MAGIC_OFFSET + 4] == magic[..])
+    {
+        return Ok(Some(format!("{} header is corrupt", entry.name)));
+    }
+    let mut size = [0u8; 8];
+    size.copy_from_slice(&header[NCA_SIZE_OFFSET..NCA_SIZE_OFFSET + 8]);
+    if u64::from_le_bytes(size) != entry.size {
+        return Ok(Some(format!("{} is truncated", entry.name)));
+    }
+    Ok(None)
+}
+
+/// AES-128-XTS over 0x200-byte sectors, with Nintendo's big-endian sector tweak.
+fn xts(data: &mut [u8], key: &[u8; 32], first_sector: u64, encrypt: bool) {
+    let data_cipher = Aes128::new(GenericArray::from_slice(&key[..16]));
+    let tweak_cipher = Aes128::new(GenericArray::from_slice(&key[16..]));
+    for (index, sector) in data.chunks_mut(NCA_SECTOR).enumerate() {
+        let mut tweak = [0u8; 16];
+        tweak[8..].copy_from_slice(&(first_sector + index as u64).to_be_bytes());
+        let mut tweak = GenericArray::from(tweak);
+        tweak_cipher.encrypt_block(&mut tweak);
+        for block in sector.chunks_exact_mut(16) {
+            block
+                .iter_mut()
+                .zip(tweak.iter())
+                .for_each(|(b, t)| *b ^= t);
+            let block_array = GenericArray::from_mut_slice(block);
+            if encrypt {
+                data_cipher.encrypt_block(block_array);
+            } else {
+                data_cipher.decrypt_block(block_array);
+            }
+            block
+                .iter_mut()
+                .zip(tweak.iter())
+                .for_each(|(b, t)| *b ^= t);
+            // Multiply the tweak by x in GF(2^128).
+            let mut carry = 0;
+            for byte in tweak.iter_mut() {
+                let next = *byte >> 7;
+                *byte = (*byte << 1) | carry;
+                carry = next;
+            }
+            if carry == 1 {
+                tweak[0] ^= 0x87;
+            }
+        }
+    }
+}
+
+fn decode_hex(raw: &str) -> Option<Vec<u8>> {
+    if raw.len() % 2 != 0 {
+        return None;
+    }
+    (0..raw.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(raw.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedVerification {
+    path: PathBuf,
+    size: u64,
+    /// Modification time (Unix seconds) of the file that was verified.
+    #[serde(default)]
+    modified: Option<u64>,
+    #[serde(flatten)]
+    result: Verification,
+}
+
+/// Verification results, cached by path, size, and modification time and persisted in the data dir.
+#[derive(Debug, Clone)]
+pub struct Verifier {
+    inner: Arc<RwLock<HashMap<PathBuf, CachedVerification>>>,
+    store_path: PathBuf,
+    keys: Option<Arc<Keys>>,
+    /// Leave files that fail verification out of the catalog.
+    pub exclude_bad: bool,
+}
+
+impl Verifier {
+    /// Load cached results from `data_dir`, starting empty if they are missing or invalid.
+    pub fn load(data_dir: &Path, keys: Option<Keys>, exclude_bad: bool) -> Self {
+        let store_path = data_dir.join(VERIFY_FILE);
+        let entries = std::fs::read_to_string(&store_path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Vec<CachedVerification>>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            inner: Arc::new(RwLock::new(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.path.clone(), entry))
+                    .collect(),
+            )),
+            store_path,
+            keys: keys.map(Arc::new),
+            exclude_bad,
+        }
+    }
+
+    pub fn has_keys(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Cached result for `path`, if it was verified at the same size and modification
+    /// time.
+    pub async fn get(&self, path: &Path, size: u64, modified: Option<u64>) -> Option<Verification> {
+        modified?;
+        self.inner
+            .read()
+            .await
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified == modified)
+            .map(|entry| entry.result.clone())
+    }
+
+    /// Verify every `(path, size, modified)` without a cached result, then persist the
+    /// cache. Returns the number of newly verified files. Unreadable files, and files
+    /// without a modification time, are skipped.
+    pub async fn verify_missing(&self, files: Vec<(PathBuf, u64, Option<u64>)>) -> usize {
+        let mut verified = 0;
+        for (path, size, modified) in files {
+            if modified.is_none() || self.get(&path, size, modified).await.is_some() {
+                continue;
+            }
+            let target = path.clone();
+            let keys = self.keys.clone();
+            match tokio::task::spawn_blocking(move || verify_file(&target, keys.as_deref())).await {
+                Ok(Ok(result)) => {
+                    if result.is_bad() {
+                        warn!(path = %path.display(), result = ?result, "file failed verification");
+                    } else {
+                        debug!(path = %path.display(), result = ?result, "file verified");
+                    }
+                    let entry = CachedVerification {
+                        path: path.clone(),
+                        size,
+                        modified,
+                        result,
+                    };
+                    self.inner.write().await.insert(path, entry);
+                    verified += 1;
+                }
+                Ok(Err(err)) => {
+                    warn!(path = %path.display(), error = %err, "file verification failed")
+                }
+                Err(err) => {
+                    warn!(path = %path.display(), error = %err, "file verification task failed")
+                }
+            }
+        }
+        if verified > 0 {
+            if let Err(err) = self.save().await {
+                warn!(path = %self.store_path.display(), error = %err, "verification cache save failed");
+            }
+        }
+        verified
+    }
+
+    async fn save(&self) -> io::Result<()> {
+        let entries = self
+            .inner
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let raw = serde_json::to_string(&entries).map_err(io::Error::other)?;
+        if let Some(parent) = self.store_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.store_path, raw).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use sha2::{Digest, Sha256};
+    use tempfile::tempdir;
+
+    use super::{verify_file, xts, Keys, Verification, Verifier, NCA_MAGIC_OFFSET};
+    use crate::container::tests::build_pfs0;
+
+    const MODIFIED: Option<u64> = Some(1_700_000_000);
+    const KEY_HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// An NCA with a valid encrypted header, named after its own hash.
+    fn nca(keys: &Keys) -> (String, Vec<u8>) {
+        let mut data = vec![0u8; 0x600];
+        data[NCA_MAGIC_OFFSET..NCA_MAGIC_OFFSET + 4].copy_from_slice(b"NCA3");
+        let len = data.len() as u64;
+        data[0x208..0x210].copy_from_slice(&len.to_le_bytes());
+        xts(&mut data[..0x400], &keys.header_key, 0, true);
+        let digest = Sha256::digest(&data);
+        let name = digest[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        (format!("{name}.nca"), data)
+    }
+
+    #[test]
+    fn intact_dumps_pass_and_damaged_ones_fail() -> Result<()> {
+        let dir = tempdir()?;
+        let keys = Keys::parse(&format!("header_key = {KEY_HEX}\n"))
+            .ok_or_else(|| anyhow::anyhow!("keys"))?;
+        let (name, data) = nca(&keys);
+
+        let good = dir.path().join("good.nsp");
+        std::fs::write(&good, build_pfs0(&[(&name, &data)]))?;
+        assert_eq!(
+            verify_file(&good, Some(&keys))?,
+            Verification::Ok {
+                ncas: 1,
+                headers: true
+            }
+        );
+
+        let mut flipped = data.clone();
+        flipped[0x500] ^= 1;
+        let corrupt = dir.path().join("corrupt.nsp");
+        std::fs::write(&corrupt, build_pfs0(&[(&name, &flipped)]))?;
+        assert!(verify_file(&corrupt, None)?.is_bad());
+
+        let mut image = build_pfs0(&[(&name, &data)]);
+        image.truncate(image.len() - 16);
+        let truncated = dir.path().join("truncated.nsp");
+        std::fs::write(&truncated, image)?;
+        assert!(verify_file(&truncated, None)?.is_bad());
+
+        let wrong_keys = Keys::parse(&format!("header_key = {}\n", "11".repeat(32)))
+            .ok_or_else(|| anyhow::anyhow!("keys"))?;
+        assert!(verify_file(&good, Some(&wrong_keys))?.is_bad());
+
+        let compressed = dir.path().join("game.nsz");
+        std::fs::write(&compressed, build_pfs0(&[("a.ncz", b"x")]))?;
+        assert!(matches!(
+            verify_file(&compressed, None)?,
+            Verification::Unverifiable { .. }
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn results_are_cached_and_persisted() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("garbage.nsp");
+        std::fs::write(&path, b"garbage")?;
+
+        let verifier = Verifier::load(dir.path(), None, false);
+        assert_eq!(
+            verifier
+                .verify_missing(vec![(path.clone(), 7, MODIFIED)])
+                .await,
+            1
+        );
+        let reloaded = Verifier::load(dir.path(), None, false);
+        assert!(reloaded
+            .get(&path, 7, MODIFIED)
+            .await
+            .is_some_and(|result| result.is_bad()));
+        assert_eq!(reloaded.verify_missing(vec![(path, 7, MODIFIED)]).await, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_same_size_replacement_is_verified_again() -> Result<()> {
+        let dir = tempdir()?;
+        let keys = Keys::parse(&format!("header_key = {KEY_HEX}\n"))
+            .ok_or_else(|| anyhow::anyhow!("keys"))?;
+        let (name, data) = nca(&keys);
+        let path = dir.path().join("game.nsp");
+        let intact = build_pfs0(&[(&name, &data)]);
+        std::fs::write(&path, &intact)?;
+        let size = intact.len() as u64;
+
+        let verifier = Verifier::load(dir.path(), None, false);
+        verifier
+            .verify_missing(vec![(path.clone(), size, MODIFIED)])
+            .await;
+        assert!(verifier
+            .get(&path, size, MODIFIED)
+            .await
+            .is_some_and(|result| !result.is_bad()));
+
+        let mut flipped = data.clone();
+        flipped[0x500] ^= 1;
+        std::fs::write(&path, build_pfs0(&[(&name, &flipped)]))?;
+        let replaced = MODIFIED.map(|seconds| seconds + 60);
+        assert_eq!(verifier.get(&path, size, replaced).await, None);
+        assert_eq!(
+            verifier
+                .verify_missing(vec![(path.clone(), size, replaced)])
+                .await,
+            1
+        );
+        assert!(verifier
+            .get(&path, size, replaced)
+            .await
+            .is_some_and(|result| result.is_bad()));
+
+        // Without a modification time there is nothing to validate against.
+        assert_eq!(
+            verifier
+                .verify_missing(vec![(path.clone(), size, None)])
+                .await,
+            0
+        );
+        assert_eq!(verifier.get(&path, size, None).await, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+//! Filesystem watcher: rescans the library shortly after content files change.
+//!
+//! Uses the platform's native notification backend via `notify`. Bursts of events
+//! (e.g. a large NSP being copied in) are debounced into a single rescan.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::event::EventKind;
+use notify::{Event, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::archive::is_archive;
+use crate::library::LibrarySet;
+use crate::scanner::is_supported_content;
+
+/// Quiet period after the last relevant event before a rescan is triggered.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watch every library root recursively and rescan the affected roots on relevant changes.
+///
+/// Returns an error if the native watcher cannot be created (e.g. inotify limits),
+/// so the caller can fall back to polling.
+pub fn spawn_library_watcher(library: LibrarySet) -> Result<(), notify::Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) if is_relevant(&event) => {
+            let _ = tx.send(event.paths);
+        }
+        Ok(_) => {}
+        Err(err) => warn!(error = %err, "library watcher error"),
+    })?;
+    for root in library.roots() {
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        info!(root = %root.display(), "library watcher started");
+    }
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs.
+        let _watcher = watcher;
+        while let Some(paths) = rx.recv().await {
+            let mut changed = BTreeSet::new();
+            collect_roots(&library, &paths, &mut changed);
+            while let Ok(Some(paths)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                collect_roots(&library, &paths, &mut changed);
+            }
+            for root in changed {
+                debug!(root = %root.display(), "library change detected");
+                match library.rescan(&root).await {
+                    Ok(count) => info!(
+                        root = %root.display(),
+                        files = count,
+                        "catalog refreshed after filesystem change"
+                    ),
+                    Err(err) => error!(
+                        root = %root.display(),
+                        error = %err,
+                        "catalog refresh after filesystem change failed"
+                    ),
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn collect_roots(library: &LibrarySet, paths: &[PathBuf], out: &mut BTreeSet<PathBuf>) {
+    out.extend(
+        paths
+            .iter()
+            .filter_map(|path| library.root_for(path))
+            .cloned(),
+    );
+}
+
+/// Whether an event may change the catalog: content files, or directories
+/// (extensionless paths) being created, moved, or removed.
+fn is_relevant(event: &Event) -> bool {
+    if matches!(event.kind, EventKind::Access(_) | EventKind::Other) {
+        return false;
+    }
+    event
+        .paths
+        .iter()
+        .any(|path| is_supported_content(path) || is_archive(path) || is_directory_like(path))
+}
+
+fn is_directory_like(path: &Path) -> bool {
+    path.extension().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use notify::event::{AccessKind, CreateKind, EventKind, RemoveKind};
+    use notify::Event;
+
+    use super::is_relevant;
+
+    #[test]
+    fn content_and_directory_events_are_relevant() {
+        let created = Event::new(EventKind::Create(CreateKind::File))
+            .add_path(PathBuf::from("/library/game.nsp"));
+        assert!(is_relevant(&created));
+
+        let removed_dir = Event::new(EventKind::Remove(RemoveKind::Folder))
+            .add_path(PathBuf::from("/library/Some Game"));
+        assert!(is_relevant(&removed_dir));
+    }
+
+    #[test]
+    fn unrelated_and_access_events_are_ignored() {
+        let text = Event::new(EventKind::Create(CreateKind::File))
+            .add_path(PathBuf::from("/library/readme.txt"));
+        assert!(!is_relevant(&text));
+
+        let access = Event::new(EventKind::Access(AccessKind::Any))
+            .add_path(PathBuf::from("/library/game.nsp"));
+        assert!(!is_relevant(&access));
+    }
+}
@@ -0,0 +1,420 @@
+//! Store-mode (uncompressed) ZIP archives, streamed as they are written.
+//!
+//! NSPs and XCIs don't compress, so files are stored as they are and the archive's size
+//! follows from the file sizes before anything is read; responses carry
+//! `Content-Length`. Each file's CRC-32 is computed while it streams and sent after it in
+//! a data descriptor. Files of 4 GiB or more, or starting past 4 GiB, get ZIP64 records.
+
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use crc32fast::Hasher;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const END: u32 = 0x0605_4b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+/// Sizes and CRC follow the data; names are UTF-8.
+const FLAGS: u16 = 0x0808;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Marks a field whose value is in the ZIP64 record.
+const OVERFLOW: u32 = u32::MAX;
+
+/// A file in the archive.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    /// Path in the archive, with `/` separators.
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+struct Placed {
+    entry: ZipEntry,
+    /// Offset of the local header.
+    offset: u64,
+}
+
+/// Layout of an archive of [`ZipEntry`]s, in order.
+#[derive(Debug)]
+pub struct StoredZip {
+    entries: Vec<Placed>,
+    /// Offset of the central directory.
+    central: u64,
+    len: u64,
+}
+
+impl StoredZip {
+    pub fn new(entries: Vec<ZipEntry>) -> Self {
+        let mut offset = 0;
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|entry| {
+                let placed = Placed { entry, offset };
+                offset += placed.local_header().len() as u64
+                    + placed.entry.size
+                    + placed.descriptor(0).len() as u64;
+                placed
+            })
+            .collect();
+        let central_len: u64 = entries
+            .iter()
+            .map(|placed| placed.central_header(0).len() as u64)
+            .sum();
+        let mut zip = Self {
+            entries,
+            central: offset,
+            len: 0,
+        };
+        zip.len = offset + central_len + zip.end(central_len).len() as u64;
+        zip
+    }
+
+    /// Size of the archive.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Stream the archive, reading entry `index` from the stream `open(index)` resolves
+    /// to once the archive reaches it. Fails if an entry's stream isn't as long as its
+    /// size, e.g. because the file changed.
+    pub fn stream<F, Fut>(self, open: F) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static
+    where
+        F: Fn(usize) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<BoxStream<'static, io::Result<Bytes>>>> + Send + 'static,
+    {
+        let zip = Arc::new(self);
+        let crcs = Arc::new(Mutex::new(vec![0u32; zip.entries.len()]));
+        let open = Arc::new(open);
+
+        let files = {
+            let zip = Arc::clone(&zip);
+            let crcs = Arc::clone(&crcs);
+            stream::iter(0..zip.entries.len()).flat_map(move |index| {
+                let header = Bytes::from(zip.entries[index].local_header());
+                let progress = Arc::new(Mutex::new((Hasher::new(), 0u64)));
+                let data = {
+                    let open = Arc::clone(&open);
+                    let progress = Arc::clone(&progress);
+                    stream::once(async move { open(index).await })
+                        .try_flatten()
+                        .inspect_ok(move |chunk| {
+                            let mut progress = progress.lock().unwrap_or_else(|p| p.into_inner());
+                            progress.0.update(chunk);
+                            progress.1 += chunk.len() as u64;
+                        })
+                };
+                let descriptor = {
+                    let zip = Arc::clone(&zip);
+                    let crcs = Arc::clone(&crcs);
+                    stream::once(async move {
+                        let (hasher, sent) =
+                            progress.lock().unwrap_or_else(|p| p.into_inner()).clone();
+                        let placed = &zip.entries[index];
+                        if sent != placed.entry.size {
+                            return Err(io::Error::other(format!(
+                                "{} changed size while it was zipped",
+                                placed.entry.name
+                            )));
+                        }
+                        let crc = hasher.finalize();
+                        crcs.lock().unwrap_or_else(|p| p.into_inner())[index] = crc;
+                        Ok(Bytes::from(placed.descriptor(crc)))
+                    })
+                };
+                stream::once(async move { Ok(header) })
+                    .chain(data)
+                    .chain(descriptor)
+                    .boxed()
+            })
+        };
+        let directory = stream::once(async move {
+            let crcs = crcs.lock().unwrap_or_else(|p| p.into_inner()).clone();
+            let mut out: Vec<u8> = zip
+                .entries
+                .iter()
+                .zip(crcs)
+                .flat_map(|(placed, crc)| placed.central_header(crc))
+                .collect();
+            let central_len = out.len() as u64;
+            out.extend(zip.end(central_len));
+            Ok(Bytes::from(out))
+        });
+        files.chain(directory)
+    }
+
+    /// The end of central directory record, preceded by the ZIP64 one and its locator
+    /// when any count, size, or offset doesn't fit.
+    fn end(&self, central_len: u64) -> Vec<u8> {
+        let count = self.entries.len() as u64;
+        let zip64 = self.entries.iter().any(Placed::zip64)
+            || count >= u64::from(u16::MAX)
+            || central_len >= u64::from(OVERFLOW)
+            || self.central >= u64::from(OVERFLOW);
+        let mut out = Vec::new();
+        if zip64 {
+            let zip64_end = self.central + central_len;
+            put32(&mut out, ZIP64_END);
+            put64(&mut out, 44);
+            put16(&mut out, VERSION_ZIP64);
+            put16(&mut out, VERSION_ZIP64);
+            put32(&mut out, 0);
+            put32(&mut out, 0);
+            put64(&mut out, count);
+            put64(&mut out, count);
+            put64(&mut out, central_len);
+            put64(&mut out, self.central);
+            put32(&mut out, ZIP64_LOCATOR);
+            put32(&mut out, 0);
+            put64(&mut out, zip64_end);
+            put32(&mut out, 1);
+        }
+        let count = count.min(u64::from(u16::MAX)) as u16;
+        put32(&mut out, END);
+        put16(&mut out, 0);
+        put16(&mut out, 0);
+        put16(&mut out, count);
+        put16(&mut out, count);
+        put32(&mut out, capped(central_len));
+        put32(&mut out, capped(self.central));
+        put16(&mut out, 0);
+        out
+    }
+}
+
+impl Placed {
+    fn zip64(&self) -> bool {
+        self.entry.size >= u64::from(OVERFLOW) || self.offset >= u64::from(OVERFLOW)
+    }
+
+    fn version(&self) -> u16 {
+        if self.zip64() {
+            VERSION_ZIP64
+        } else {
+            VERSION
+        }
+    }
+
+    /// Local header. The CRC comes later, in the data descriptor, but the sizes are
+    /// known, so streaming readers can find the end of the data.
+    fn local_header(&self) -> Vec<u8> {
+        let (time, date) = dos_date_time(self.entry.modified);
+        let name = self.entry.name.as_bytes();
+        let size = self.entry.size;
+        let mut out = Vec::with_capacity(30 + name.len() + 20);
+        put32(&mut out, LOCAL_HEADER);
+        put16(&mut out, self.version());
+        put16(&mut out, FLAGS);
+        put16(&mut out, 0);
+        put16(&mut out, time);
+        put16(&mut out, date);
+        put32(&mut out, 0);
+        let sizes = if self.zip64() { OVERFLOW } else { size as u32 };
+        put32(&mut out, sizes);
+        put32(&mut out, sizes);
+        put16(&mut out, name.len() as u16);
+        put16(&mut out, if self.zip64() { 20 } else { 0 });
+        out.extend(name);
+        if self.zip64() {
+            put16(&mut out, ZIP64_EXTRA);
+            put16(&mut out, 16);
+            put64(&mut out, size);
+            put64(&mut out, size);
+        }
+        out
+    }
+
+    fn descriptor(&self, crc: u32) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24);
+        put32(&mut out, DATA_DESCRIPTOR);
+        put32(&mut out, crc);
+        if self.zip64() {
+            put64(&mut out, self.entry.size);
+            put64(&mut out, self.entry.size);
+        } else {
+            put32(&mut out, self.entry.size as u32);
+            put32(&mut out, self.entry.size as u32);
+        }
+        out
+    }
+
+    fn central_header(&self, crc: u32) -> Vec<u8> {
+        let (time, date) = dos_date_time(self.entry.modified);
+        let name = self.entry.name.as_bytes();
+        let size = self.entry.size;
+        let zip64 = self.zip64();
+        let mut out = Vec::with_capacity(46 + name.len() + 28);
+        put32(&mut out, CENTRAL_HEADER);
+        put16(&mut out, VERSION_ZIP64);
+        put16(&mut out, self.version());
+        put16(&mut out, FLAGS);
+        put16(&mut out, 0);
+        put16(&mut out, time);
+        put16(&mut out, date);
+        put32(&mut out, crc);
+        let sizes = if zip64 { OVERFLOW } else { size as u32 };
+        put32(&mut out, sizes);
+        put32(&mut out, sizes);
+        put16(&mut out, name.len() as u16);
+        put16(&mut out, if zip64 { 28 } else { 0 });
+        // Comment length, disk, internal and external attributes.
+        put16(&mut out, 0);
+        put16(&mut out, 0);
+        put16(&mut out, 0);
+        put32(&mut out, 0);
+        put32(&mut out, if zip64 { OVERFLOW } else { self.offset as u32 });
+        out.extend(name);
+        if zip64 {
+            put16(&mut out, ZIP64_EXTRA);
+            put16(&mut out, 24);
+            put64(&mut out, size);
+            put64(&mut out, size);
+            put64(&mut out, self.offset);
+        }
+        out
+    }
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend(value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_le_bytes());
+}
+
+fn put64(out: &mut Vec<u8>, value: u64) {
+    out.extend(value.to_le_bytes());
+}
+
+fn capped(value: u64) -> u32 {
+    u32::try_from(value).unwrap_or(OVERFLOW)
+}
+
+/// MS-DOS time and date of `modified`, in UTC; 1980-01-01 when unknown or earlier.
+fn dos_date_time(modified: Option<SystemTime>) -> (u16, u16) {
+    const EPOCH: (u16, u16) = (0, 0x21);
+    let Some(secs) = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_secs())
+    else {
+        return EPOCH;
+    };
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    if !(1980..=2107).contains(&year) {
+        return EPOCH;
+    }
+    let of_day = secs % 86_400;
+    let time = ((of_day / 3600) << 11) | ((of_day % 3600 / 60) << 5) | ((of_day % 60) / 2);
+    let date = (((year - 1980) as u64) << 9) | (u64::from(month) << 5) | u64::from(day);
+    (time as u16, date as u16)
+}
+
+/// Year, month, and day of `days` since 1970-01-01 (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use futures_util::TryStreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn archives_read_back_with_their_files() -> Result<()> {
+        let files: Vec<(&str, Vec<u8>)> = vec![
+            ("Games/Zelda [0100000000010000][v0].nsp", vec![7; 300_000]),
+            ("Games/Zelda [0100000000010800][v196608].nsp", Vec::new()),
+            ("DLC/Pokémon.nsp", b"dlc".to_vec()),
+        ];
+        // 2021-03-04 05:06:08 UTC
+        let modified = UNIX_EPOCH + Duration::from_secs(1_614_834_368);
+        let zip = StoredZip::new(
+            files
+                .iter()
+                .map(|(name, data)| ZipEntry {
+                    name: name.to_string(),
+                    size: data.len() as u64,
+                    modified: Some(modified),
+                })
+                .collect(),
+        );
+        let len = zip.len();
+        let contents: Vec<_> = files.iter().map(|(_, data)| data.clone()).collect();
+        let archive: Vec<Bytes> = zip
+            .stream(move |index| {
+                let chunks: Vec<_> = contents[index]
+                    .chunks(65_536)
+                    .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                    .collect();
+                async move { Ok(stream::iter(chunks).boxed()) }
+            })
+            .try_collect()
+            .await?;
+        let archive = archive.concat();
+        assert_eq!(archive.len() as u64, len);
+
+        let mut read = zip::ZipArchive::new(Cursor::new(archive))?;
+        assert_eq!(read.len(), files.len());
+        for (index, (name, data)) in files.iter().enumerate() {
+            let mut file = read.by_index(index)?;
+            assert_eq!(file.name(), *name);
+            assert_eq!(file.compression(), zip::CompressionMethod::Stored);
+            let stamp = file
+                .last_modified()
+                .ok_or_else(|| anyhow::anyhow!("no date"))?;
+            assert_eq!(
+                (
+                    stamp.year(),
+                    stamp.month(),
+                    stamp.day(),
+                    stamp.hour(),
+                    stamp.minute()
+                ),
+                (2021, 3, 4, 5, 6)
+            );
+            let mut read_back = Vec::new();
+            // Reading to the end checks the CRC.
+            file.read_to_end(&mut read_back)?;
+            assert_eq!(&read_back, data);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn files_that_changed_size_fail_the_stream() {
+        let zip = StoredZip::new(vec![ZipEntry {
+            name: String::from("a.nsp"),
+            size: 10,
+            modified: None,
+        }]);
+        let result: io::Result<Vec<Bytes>> = zip
+            .stream(|_| async { Ok(stream::iter([Ok(Bytes::from_static(b"short"))]).boxed()) })
+            .try_collect()
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,357 @@
+const sections = { new: [], recommended: [], updates: [], dlc: [], homebrew: [], all: [] };
+let data = null;
+
+function truncateTitleName(name) {
+  if (!name) return name;
+  return name
+    .replace(/\s*\[[0-9A-Fa-f]{16}\](?:\s*\[v\d+\])?\s*/gi, ' ')
+    .replace(/\s*\.(nsp|xci|nsz|xcz|nca|nspd|xcid)$/i, '')
+    .replace(/\s+/g, ' ')
+    .trim() || name;
+}
+
+function formatSize(bytes) {
+  if (bytes < 1024) return bytes + ' B';
+  if (bytes < 1024 * 1024) return (bytes / 1024).toFixed(1) + ' KB';
+  if (bytes < 1024 * 1024 * 1024) return (bytes / (1024 * 1024)).toFixed(1) + ' MB';
+  return (bytes / (1024 * 1024 * 1024)).toFixed(1) + ' GB';
+}
+
+function renderCard(item) {
+  const typeClass = item.app_type === 'BASE' ? 'success' : item.app_type === 'UPDATE' ? 'warning' : 'secondary';
+  const iconSrc = item.icon_url ? (item.icon_url.startsWith('http') ? item.icon_url : (window.location.origin + item.icon_url)) : '';
+  const coverHtml = iconSrc
+    ? `<div style="aspect-ratio: 1; background: var(--bg-2); border-radius: 4px; margin-bottom: 0.75rem; overflow: hidden; position: relative;">
+        <div style="position: absolute; inset: 0; display: flex; align-items: center; justify-content: center; font-size: 2rem; opacity: 0.4;">📦</div>
+        <img src="${escapeHtml(iconSrc)}" alt="" style="position: relative; z-index: 1; width: 100%; height: 100%; object-fit: cover;" loading="lazy" onerror="this.style.display='none'">
+      </div>`
+    : `<div style="aspect-ratio: 1; background: var(--bg-2); border-radius: 4px; margin-bottom: 0.75rem; display: flex; align-items: center; justify-content: center; font-size: 2rem; opacity: 0.4;">📦</div>`;
+  return `
+    <article class="card card-item">
+      ${coverHtml}
+      <h4 class="card-title"><span class="card-title-inner">${escapeHtml(truncateTitleName(item.title_name || item.name))} &nbsp;&nbsp;&nbsp;&nbsp;&nbsp; ${escapeHtml(truncateTitleName(item.title_name || item.name))}</span></h4>
+      <span class="badge badge-${typeClass}">${item.app_type}</span>
+      <div class="meta">${item.title_id || '—'} · ${formatSize(item.size)}</div>
+      <button type="button" class="small" data-variant="danger" data-file-id="${item.file_id}" data-filename="${escapeHtml(item.filename)}" title="Move this file to the trash">Delete</button>
+    </article>
+  `;
+}
+
+function escapeHtml(s) {
+  const div = document.createElement('div');
+  div.textContent = s;
+  return div.innerHTML;
+}
+
+function renderSection(sectionId, items) {
+  const grid = document.getElementById('grid-' + sectionId);
+  if (!items.length) {
+    grid.innerHTML = '<div class="empty-state">No items in this section.</div>';
+    return;
+  }
+  grid.innerHTML = items.map(renderCard).join('');
+}
+
+function showTab(sectionId) {
+  document.querySelectorAll('[role="tab"]').forEach(t => {
+    t.setAttribute('aria-selected', t.dataset.section === sectionId);
+  });
+  document.querySelectorAll('[role="tabpanel"]').forEach(p => {
+    p.setAttribute('aria-hidden', p.id !== 'panel-' + sectionId);
+  });
+}
+
+document.getElementById('rescan-btn').addEventListener('click', (e) => {
+  const btn = e.currentTarget;
+  btn.disabled = true;
+  btn.textContent = 'Scanning…';
+  fetch('api/library/rescan', { method: 'POST', credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(res => {
+      if (res.added || res.removed || res.changed) {
+        window.location.reload();
+      } else {
+        btn.textContent = 'Up to date';
+      }
+    })
+    .catch(() => { btn.textContent = 'Rescan failed'; })
+    .finally(() => {
+      btn.disabled = false;
+      setTimeout(() => { btn.textContent = 'Rescan'; }, 3000);
+    });
+});
+
+let missingDlcLoaded = false;
+
+function loadMissingDlc() {
+  if (missingDlcLoaded) return;
+  missingDlcLoaded = true;
+  const target = document.getElementById('missing-dlc');
+  fetch('api/library/missing-dlc', { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(res => {
+      if (!res.titledb_entries) {
+        target.innerHTML = '<div class="empty-state">TitleDB is not loaded yet; missing DLC cannot be determined.</div>';
+        return;
+      }
+      if (!res.titles.length) {
+        target.innerHTML = '<div class="empty-state">No missing DLC for games in your library.</div>';
+        return;
+      }
+      target.innerHTML = res.titles.map(t => `
+        <article class="card" style="margin-bottom: 1rem;">
+          <h4 style="margin: 0 0 0.5rem;">${escapeHtml(t.name || t.title_id)} <small style="opacity: 0.7;">${t.title_id}</small></h4>
+          <ul style="margin: 0;">
+            ${t.missing.map(d => `<li>${escapeHtml(d.name || 'Unknown DLC')} <small style="opacity: 0.7;">${d.title_id}</small></li>`).join('')}
+          </ul>
+        </article>
+      `).join('');
+    })
+    .catch(() => {
+      missingDlcLoaded = false;
+      target.innerHTML = '<div class="empty-state">Failed to load missing DLC.</div>';
+    });
+}
+
+let problemsLoaded = false;
+
+function problemRow(file, reason) {
+  return `<li>${escapeHtml(file.relative_path)} <small style="opacity: 0.7;">${formatSize(file.size)} · ${escapeHtml(reason)}</small></li>`;
+}
+
+function loadProblems() {
+  if (problemsLoaded) return;
+  problemsLoaded = true;
+  const target = document.getElementById('problems');
+  const get = url => fetch(url, { credentials: 'include' }).then(r => {
+    if (!r.ok) throw new Error(r.status);
+    return r.json();
+  });
+  Promise.all([get('api/library/problems'), get('api/library/verification')])
+    .then(([problems, verification]) => {
+      let html = '';
+      if (verification.enabled) {
+        html += `<p>Verification${verification.keys ? ' (with NCA headers)' : ''}: ${verification.ok} ok, ${verification.bad} bad, ${verification.unverifiable} unverifiable, ${verification.pending} pending.</p>`;
+      }
+      const quarantined = new Set(problems.files.map(f => f.relative_path));
+      const listed = verification.bad_files.filter(f => !quarantined.has(f.relative_path));
+      if (!problems.files.length && !listed.length) {
+        target.innerHTML = html + '<div class="empty-state">No problem files found.</div>';
+        return;
+      }
+      if (problems.files.length) {
+        html += `<article class="card" style="margin-bottom: 1rem;"><h4 style="margin: 0 0 0.5rem;">Hidden from the shop</h4><ul style="margin: 0;">
+          ${problems.files.map(f => problemRow(f, f.verification && f.verification.reason ? f.verification.reason : f.reason)).join('')}
+        </ul></article>`;
+      }
+      if (listed.length) {
+        html += `<article class="card" style="margin-bottom: 1rem;"><h4 style="margin: 0 0 0.5rem;">Failed verification</h4><ul style="margin: 0;">
+          ${listed.map(f => problemRow(f, f.verification.reason)).join('')}
+        </ul></article>`;
+      }
+      target.innerHTML = html;
+    })
+    .catch(() => {
+      problemsLoaded = false;
+      target.innerHTML = '<div class="empty-state">Failed to load problem files.</div>';
+    });
+}
+
+// Deleting moves the file to the library's trash; file IDs shift, so reload.
+document.getElementById('content').addEventListener('click', (e) => {
+  const btn = e.target.closest('[data-file-id]');
+  if (!btn || !confirm('Move ' + btn.dataset.filename + ' to the trash?')) return;
+  btn.disabled = true;
+  fetch('api/library/file/' + btn.dataset.fileId, { method: 'DELETE', credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      window.location.reload();
+    })
+    .catch(() => {
+      btn.disabled = false;
+      btn.textContent = 'Delete failed';
+    });
+});
+
+function loadTrash() {
+  const target = document.getElementById('trash');
+  fetch('api/library/trash', { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(res => {
+      if (!res.entries.length) {
+        target.innerHTML = '<div class="empty-state">The trash is empty.</div>';
+        return;
+      }
+      target.innerHTML = `<ul style="margin: 0;">${res.entries.map(t => `
+        <li style="margin-bottom: 0.5rem;">${escapeHtml(t.relative_path)} <small style="opacity: 0.7;">${formatSize(t.size)} · deleted ${new Date(t.deleted_at * 1000).toLocaleString()}</small>
+          <button type="button" class="small" data-variant="secondary" data-trash-id="${t.id}" data-action="restore">Restore</button>
+          <button type="button" class="small" data-variant="danger" data-trash-id="${t.id}" data-action="purge">Delete forever</button>
+        </li>`).join('')}</ul>`;
+    })
+    .catch(() => {
+      target.innerHTML = '<div class="empty-state">Failed to load the trash.</div>';
+    });
+}
+
+document.getElementById('trash').addEventListener('click', (e) => {
+  const btn = e.target.closest('[data-trash-id]');
+  if (!btn) return;
+  const purge = btn.dataset.action === 'purge';
+  if (purge && !confirm('Delete this file permanently?')) return;
+  btn.disabled = true;
+  const url = 'api/library/trash/' + btn.dataset.trashId + (purge ? '' : '/restore');
+  fetch(url, { method: purge ? 'DELETE' : 'POST', credentials: 'include' })
+    .then(r => {
+      if (r.status === 409) alert('A file already exists where this one would be restored.');
+      else if (!r.ok) throw new Error(r.status);
+    })
+    .catch(() => {})
+    .finally(loadTrash);
+});
+
+let benchmarkTimer = null;
+
+// Latest benchmark per library folder; polls while any is still running.
+function loadBenchmarks() {
+  const target = document.getElementById('benchmarks');
+  fetch('api/library/benchmark', { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(res => {
+      const latest = new Map();
+      res.jobs.forEach(j => {
+        const seen = latest.get(j.target);
+        if (!seen || j.started_at >= seen.started_at) latest.set(j.target, j);
+      });
+      const jobs = [...latest.values()];
+      if (!jobs.length) {
+        target.innerHTML = '<div class="empty-state">No benchmark has run yet.</div>';
+      } else {
+        target.innerHTML = `<ul style="margin: 0;">${jobs.map(j => {
+          const detail = j.status === 'running'
+            ? `running · ${formatSize(j.done_bytes)} of ${formatSize(j.total_bytes)}`
+            : (j.message || j.status);
+          return `<li>${escapeHtml(j.target)} <small style="opacity: 0.7;">${escapeHtml(detail)}</small></li>`;
+        }).join('')}</ul>`;
+      }
+      const running = jobs.some(j => j.status === 'running');
+      document.getElementById('benchmark-btn').disabled = running;
+      clearTimeout(benchmarkTimer);
+      if (running) benchmarkTimer = setTimeout(loadBenchmarks, 1000);
+    })
+    .catch(() => {
+      target.innerHTML = '<div class="empty-state">Failed to load benchmarks.</div>';
+    });
+}
+
+document.getElementById('benchmark-btn').addEventListener('click', (e) => {
+  const btn = e.currentTarget;
+  btn.disabled = true;
+  fetch('api/library/benchmark', { method: 'POST', credentials: 'include' })
+    .then(r => {
+      if (!r.ok && r.status !== 409) throw new Error(r.status);
+    })
+    .catch(() => {})
+    .finally(loadBenchmarks);
+});
+
+function loadIconCache() {
+  const target = document.getElementById('icon-cache');
+  fetch('api/cache/icons', { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(res => {
+      target.textContent = `${res.entries} icons · ${formatSize(res.bytes)} of ${formatSize(res.max_bytes)}`;
+    })
+    .catch(() => {
+      target.textContent = 'Failed to load the icon cache.';
+    });
+}
+
+document.getElementById('icon-cache-purge-btn').addEventListener('click', (e) => {
+  if (!confirm('Delete all cached icons? They are downloaded again when needed.')) return;
+  const btn = e.currentTarget;
+  btn.disabled = true;
+  fetch('api/cache/icons', { method: 'DELETE', credentials: 'include' })
+    .catch(() => {})
+    .finally(() => {
+      btn.disabled = false;
+      loadIconCache();
+    });
+});
+
+document.querySelectorAll('[role="tab"]').forEach(btn => {
+  btn.addEventListener('click', () => {
+    showTab(btn.dataset.section);
+    if (btn.dataset.section === 'missing-dlc') loadMissingDlc();
+    if (btn.dataset.section === 'problems') loadProblems();
+    if (btn.dataset.section === 'storage') {
+      loadBenchmarks();
+      loadIconCache();
+    }
+    if (btn.dataset.section === 'trash') loadTrash();
+  });
+});
+
+const evtSrc = new EventSource('api/settings/titledb/progress', { withCredentials: true });
+evtSrc.onmessage = (e) => console.log(e.data);
+evtSrc.onerror = () => evtSrc.close();
+
+// The All section is paged; keep fetching until every item is shown.
+function loadMoreAll() {
+  const offset = sections.all.length;
+  fetch('api/shop/sections?section=all&limit=100&offset=' + offset, { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(res => {
+      const all = res.sections[0];
+      if (!all || !all.items.length) return;
+      sections.all = sections.all.concat(all.items);
+      renderSection('all', sections.all);
+      if (all.truncated) loadMoreAll();
+    })
+    .catch(() => {});
+}
+
+fetch('api/shop/sections?limit=100', { credentials: 'include' })
+  .then(r => {
+    if (!r.ok) throw new Error(r.status);
+    return r.json();
+  })
+  .then(res => {
+    data = res;
+    res.sections.forEach(s => {
+      sections[s.id] = s.items || [];
+    });
+    document.getElementById('loading').style.display = 'none';
+    document.getElementById('content').style.display = 'block';
+    ['new', 'recommended', 'updates', 'dlc', 'homebrew', 'all'].forEach(id => renderSection(id, sections[id]));
+    const all = res.sections.find(s => s.id === 'all');
+    if (all && all.truncated) loadMoreAll();
+  })
+  .catch(() => {
+    document.getElementById('loading').style.display = 'none';
+    document.getElementById('error').style.display = 'block';
+  });
+
+document.getElementById('theme-toggle').addEventListener('click', () => {
+  const body = document.body;
+  const isDark = body.getAttribute('data-theme') === 'dark';
+  body.setAttribute('data-theme', isDark ? 'light' : 'dark');
+  document.getElementById('theme-toggle').textContent = isDark ? '🌙' : '☀️';
+});
@@ -0,0 +1,6 @@
+// Resolved against <base>, so the document comes from the same base path as this page.
+SwaggerUIBundle({
+  url: new URL('api/openapi.json', document.baseURI).href,
+  dom_id: '#swagger-ui',
+  deepLinking: true,
+});
@@ -0,0 +1,3 @@
+if (new URLSearchParams(location.search).get('error') === '1') {
+  document.getElementById('error-msg').style.display = 'block';
+}
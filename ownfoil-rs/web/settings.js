@@ -0,0 +1,99 @@
+const evtSrc = new EventSource('api/settings/titledb/progress', { withCredentials: true });
+evtSrc.onmessage = (e) => console.log(e.data);
+evtSrc.onerror = () => evtSrc.close();
+
+const form = document.getElementById('settings-form');
+const msg = document.getElementById('msg');
+const statusEl = document.getElementById('titledb-status');
+
+function showMsg(text, variant) {
+  msg.textContent = text;
+  msg.setAttribute('data-variant', variant || 'info');
+  msg.style.display = 'block';
+  setTimeout(() => { msg.style.display = 'none'; }, 4000);
+}
+
+let revision = null;
+
+function loadSettings() {
+  return fetch('api/settings', { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(data => {
+      revision = data.revision;
+      const t = data.titledb;
+      document.getElementById('titledb-enabled').checked = t.enabled;
+      document.getElementById('titledb-region').value = t.region || 'US';
+      document.getElementById('titledb-language').value = t.language || 'en';
+      document.getElementById('titledb-refresh').value = t.refresh_interval || '24h';
+      document.getElementById('titledb-url').value = t.url_override || '';
+      statusEl.textContent = `${data.titledb_entries} entries loaded${data.titledb_last_refresh ? ', last refresh: ' + data.titledb_last_refresh + ' ago' : ''}`;
+    })
+    .catch(() => showMsg('Failed to load settings', 'danger'));
+}
+
+loadSettings();
+
+const settingsEvents = new EventSource('api/settings/events', { withCredentials: true });
+settingsEvents.addEventListener('settings', (e) => {
+  const next = JSON.parse(e.data).revision;
+  if (next !== revision) {
+    loadSettings().then(() => showMsg('Settings were changed in another session and have been reloaded', 'info'));
+  }
+});
+
+form.addEventListener('submit', (e) => {
+  e.preventDefault();
+  const payload = {
+    revision,
+    titledb: {
+      enabled: document.getElementById('titledb-enabled').checked,
+      region: document.getElementById('titledb-region').value.trim() || 'US',
+      language: document.getElementById('titledb-language').value.trim() || 'en',
+      refresh_interval: document.getElementById('titledb-refresh').value.trim() || '24h',
+      url_override: document.getElementById('titledb-url').value.trim() || null
+    }
+  };
+  if (!payload.titledb.url_override) delete payload.titledb.url_override;
+
+  fetch('api/settings', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    credentials: 'include',
+    body: JSON.stringify(payload)
+  })
+    .then(r => {
+      if (r.status === 409) {
+        return loadSettings().then(() => showMsg('Settings were changed elsewhere; reloaded, please review and save again', 'warning'));
+      }
+      if (!r.ok) throw new Error(r.status);
+      return r.json().then(data => {
+        revision = data.revision;
+        showMsg('Settings saved', 'success');
+      });
+    })
+    .catch(() => showMsg('Failed to save', 'danger'));
+});
+
+document.getElementById('refresh-btn').addEventListener('click', () => {
+  fetch('api/settings/refresh', { method: 'POST', credentials: 'include' })
+    .then(r => r.ok ? showMsg('Refresh started', 'success') : Promise.reject())
+    .catch(() => showMsg('Refresh failed', 'danger'));
+});
+
+document.getElementById('test-btn').addEventListener('click', () => {
+  fetch('api/settings/titledb/test', { credentials: 'include' })
+    .then(r => r.json())
+    .then(data => {
+      console.table(data.results);
+      console.log('Hint:', data.hint);
+      const ok = data.results.filter(r => r.ok).length;
+      showMsg(`${ok}/${data.results.length} sources reachable (see console)`, ok > 0 ? 'success' : 'danger');
+    })
+    .catch(e => {
+      console.error('Connectivity test failed:', e);
+      showMsg('Test failed', 'danger');
+    });
+});
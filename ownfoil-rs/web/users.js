@@ -0,0 +1,138 @@
+const form = document.getElementById('user-form');
+const msg = document.getElementById('msg');
+const tbody = document.getElementById('users');
+const formTitle = document.getElementById('form-title');
+const passwordHint = document.getElementById('password-hint');
+const nameInput = document.getElementById('user-name');
+const passwordInput = document.getElementById('user-password');
+const roleSelect = document.getElementById('user-role');
+const titlesInput = document.getElementById('user-titles');
+const disabledBox = document.getElementById('user-disabled');
+
+function showMsg(text, variant) {
+  msg.textContent = text;
+  msg.setAttribute('data-variant', variant || 'info');
+  msg.style.display = 'block';
+  setTimeout(() => { msg.style.display = 'none'; }, 4000);
+}
+
+function errorText(r) {
+  return r.json().then(body => body.error, () => `HTTP ${r.status}`);
+}
+
+const ROLES = { admin: 'Admin', download: 'Download', view_only: 'View only' };
+
+function describeContent(user) {
+  const parts = [];
+  if (user.kinds.length) parts.push(user.kinds.join(', '));
+  if (user.titles.length) parts.push(`${user.titles.length} title${user.titles.length === 1 ? '' : 's'}`);
+  return parts.length ? parts.join('; ') : 'Everything';
+}
+
+function button(label, variant, onClick) {
+  const btn = document.createElement('button');
+  btn.type = 'button';
+  btn.textContent = label;
+  btn.setAttribute('data-variant', variant);
+  btn.style.marginLeft = '0.25rem';
+  btn.addEventListener('click', onClick);
+  return btn;
+}
+
+function loadUsers() {
+  return fetch('api/users', { credentials: 'include' })
+    .then(r => {
+      if (!r.ok) throw new Error(r.status);
+      return r.json();
+    })
+    .then(data => {
+      tbody.replaceChildren();
+      for (const user of data.users) {
+        const row = document.createElement('tr');
+        const name = document.createElement('td');
+        name.textContent = user.username + (user.disabled ? ' (disabled)' : '');
+        const role = document.createElement('td');
+        role.textContent = ROLES[user.role] || user.role;
+        const content = document.createElement('td');
+        content.textContent = describeContent(user);
+        const actions = document.createElement('td');
+        actions.style.textAlign = 'right';
+        actions.append(
+          button('Edit', 'secondary', () => editUser(user)),
+          button(user.disabled ? 'Enable' : 'Disable', 'secondary', () =>
+            saveUser({ username: user.username, disabled: !user.disabled })),
+          button('Delete', 'danger', () => deleteUser(user.username))
+        );
+        row.append(name, role, content, actions);
+        tbody.append(row);
+      }
+    })
+    .catch(() => showMsg('Failed to load users', 'danger'));
+}
+
+function editUser(user) {
+  formTitle.textContent = `Edit ${user.username}`;
+  passwordHint.textContent = 'Leave empty to keep the current password';
+  nameInput.value = user.username;
+  nameInput.readOnly = true;
+  passwordInput.value = '';
+  roleSelect.value = user.role;
+  for (const box of form.querySelectorAll('input[name="kinds"]')) {
+    box.checked = user.kinds.includes(box.value);
+  }
+  titlesInput.value = user.titles.join('\n');
+  disabledBox.checked = user.disabled;
+}
+
+function resetForm() {
+  form.reset();
+  nameInput.readOnly = false;
+  formTitle.textContent = 'Add user';
+  passwordHint.textContent = 'Stored as an Argon2 hash';
+}
+
+function saveUser(payload) {
+  return fetch('api/users', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    credentials: 'include',
+    body: JSON.stringify(payload)
+  })
+    .then(r => {
+      if (!r.ok) return errorText(r).then(text => Promise.reject(new Error(text)));
+      return r.json().then(user => {
+        showMsg(r.status === 201 ? `Added ${user.username}` : `Saved ${user.username}`, 'success');
+        return loadUsers();
+      });
+    })
+    .catch(e => showMsg(`Failed to save: ${e.message}`, 'danger'));
+}
+
+function deleteUser(username) {
+  if (!confirm(`Delete ${username}? Their shop URL stops working too.`)) return;
+  fetch(`api/users/${encodeURIComponent(username)}`, { method: 'DELETE', credentials: 'include' })
+    .then(r => {
+      if (!r.ok) return errorText(r).then(text => Promise.reject(new Error(text)));
+      showMsg(`Deleted ${username}`, 'success');
+      if (nameInput.value === username) resetForm();
+      return loadUsers();
+    })
+    .catch(e => showMsg(`Failed to delete: ${e.message}`, 'danger'));
+}
+
+form.addEventListener('submit', (e) => {
+  e.preventDefault();
+  const payload = {
+    username: nameInput.value.trim(),
+    role: roleSelect.value,
+    kinds: [...form.querySelectorAll('input[name="kinds"]:checked')].map(box => box.value),
+    titles: titlesInput.value.split(/[\s,]+/).filter(Boolean),
+    disabled: disabledBox.checked
+  };
+  if (passwordInput.value) payload.password = passwordInput.value;
+  saveUser(payload).then(() => { if (!nameInput.readOnly) resetForm(); });
+});
+
+document.getElementById('reset-btn').addEventListener('click', resetForm);
+
+loadUsers();